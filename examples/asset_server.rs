@@ -0,0 +1,353 @@
+/*!
+Game asset loader with a pool of loader threads, an LRU cache and hot-reload.
+
+The asset server shares one `FileReader` between its loader threads, it reads with positioned IO and only needs `&self`.
+Decrypted assets are kept in a least recently used cache bounded by their total size.
+When the PAKS file is edited, eg. by `pakscmd` while the game is running, the server reopens it and drops the cached assets.
+*/
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::SystemTime;
+use std::{fs, io, thread};
+
+use paks::Metrics;
+
+type Asset = Arc<[u8]>;
+
+// Least recently used cache of decrypted assets bounded by their total size in bytes
+struct LruCache {
+	capacity: usize,
+	size: usize,
+	assets: HashMap<Vec<u8>, Asset>,
+	// Paths ordered from least to most recently used
+	order: VecDeque<Vec<u8>>,
+}
+
+impl LruCache {
+	fn new(capacity: usize) -> LruCache {
+		LruCache { capacity, size: 0, assets: HashMap::new(), order: VecDeque::new() }
+	}
+
+	fn get(&mut self, path: &[u8]) -> Option<Asset> {
+		let asset = self.assets.get(path)?.clone();
+		self.touch(path);
+		Some(asset)
+	}
+
+	fn insert(&mut self, path: &[u8], asset: Asset) {
+		// Assets larger than the cache are never cached
+		if asset.len() > self.capacity {
+			return;
+		}
+		self.size += asset.len();
+		match self.assets.insert(path.to_vec(), asset) {
+			Some(old) => {
+				self.size -= old.len();
+				self.touch(path);
+			},
+			None => self.order.push_back(path.to_vec()),
+		}
+		while self.size > self.capacity {
+			let oldest = match self.order.pop_front() {
+				Some(oldest) => oldest,
+				None => break,
+			};
+			if let Some(asset) = self.assets.remove(&oldest) {
+				self.size -= asset.len();
+			}
+		}
+	}
+
+	fn touch(&mut self, path: &[u8]) {
+		if let Some(index) = self.order.iter().position(|p| p == path) {
+			let path = self.order.remove(index).unwrap();
+			self.order.push_back(path);
+		}
+	}
+
+	fn clear(&mut self) {
+		self.size = 0;
+		self.assets.clear();
+		self.order.clear();
+	}
+}
+
+// Identifies the version of the PAKS file on disk, every edit appends to the PAKS file
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct Stamp {
+	modified: Option<SystemTime>,
+	len: u64,
+}
+
+impl Stamp {
+	fn of(path: &Path) -> io::Result<Stamp> {
+		let metadata = fs::metadata(path)?;
+		Ok(Stamp { modified: metadata.modified().ok(), len: metadata.len() })
+	}
+}
+
+// The reader and the cache are swapped together under one lock,
+// a load which raced with a reload must not cache assets from the previous PAKS file
+struct State {
+	reader: Arc<paks::FileReader>,
+	stamp: Stamp,
+	epoch: u64,
+	cache: LruCache,
+}
+
+struct Shared {
+	path: PathBuf,
+	key: paks::Key,
+	counters: Arc<paks::Counters>,
+	state: Mutex<State>,
+}
+
+impl Shared {
+	fn open_reader(&self) -> io::Result<Arc<paks::FileReader>> {
+		let mut reader = paks::FileReader::open(&self.path, &self.key)?;
+		reader.set_metrics(self.counters.clone());
+		Ok(Arc::new(reader))
+	}
+
+	fn load(&self, path: &[u8]) -> io::Result<Asset> {
+		let (reader, epoch) = {
+			let mut state = self.state.lock().unwrap();
+			if let Some(asset) = state.cache.get(path) {
+				self.counters.cache_hit();
+				return Ok(asset);
+			}
+			(state.reader.clone(), state.epoch)
+		};
+
+		// Decrypt without holding the lock so the loader threads read in parallel
+		let asset: Asset = reader.read(path, &self.key)?.into();
+
+		let mut state = self.state.lock().unwrap();
+		if state.epoch == epoch {
+			state.cache.insert(path, asset.clone());
+		}
+		Ok(asset)
+	}
+
+	fn reload_if_changed(&self) -> io::Result<bool> {
+		let stamp = Stamp::of(&self.path)?;
+		if self.state.lock().unwrap().stamp == stamp {
+			return Ok(false);
+		}
+
+		// Open the new version before swapping so a failed reload keeps serving the previous one
+		let reader = self.open_reader()?;
+		let mut state = self.state.lock().unwrap();
+		state.reader = reader;
+		state.stamp = stamp;
+		state.epoch += 1;
+		state.cache.clear();
+		Ok(true)
+	}
+}
+
+type Reply = mpsc::Sender<io::Result<Asset>>;
+
+struct AssetServer {
+	shared: Arc<Shared>,
+	requests: Option<mpsc::Sender<(Vec<u8>, Reply)>>,
+	workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl AssetServer {
+	fn open(path: &Path, key: &paks::Key, workers: usize, cache_capacity: usize) -> io::Result<AssetServer> {
+		let stamp = Stamp::of(path)?;
+		let mut reader = paks::FileReader::open(path, key)?;
+		let counters = Arc::new(paks::Counters::new());
+		reader.set_metrics(counters.clone());
+
+		let shared = Arc::new(Shared {
+			path: path.to_path_buf(),
+			key: *key,
+			counters,
+			state: Mutex::new(State { reader: Arc::new(reader), stamp, epoch: 0, cache: LruCache::new(cache_capacity) }),
+		});
+
+		// The loader threads take turns receiving requests from the shared queue
+		let (requests, queue) = mpsc::channel::<(Vec<u8>, Reply)>();
+		let queue = Arc::new(Mutex::new(queue));
+		let workers = (0..workers).map(|_| {
+			let shared = shared.clone();
+			let queue = queue.clone();
+			thread::spawn(move || loop {
+				let request = queue.lock().unwrap().recv();
+				let (path, reply) = match request {
+					Ok(request) => request,
+					Err(_) => break,
+				};
+				let _ = reply.send(shared.load(&path));
+			})
+		}).collect();
+
+		Ok(AssetServer { shared, requests: Some(requests), workers })
+	}
+
+	// Loads the asset on a loader thread, the result is sent to the returned receiver
+	fn request(&self, path: &[u8]) -> mpsc::Receiver<io::Result<Asset>> {
+		let (reply, receiver) = mpsc::channel();
+		if let Some(requests) = &self.requests {
+			let _ = requests.send((path.to_vec(), reply));
+		}
+		receiver
+	}
+
+	// Loads the asset on the calling thread
+	fn load(&self, path: &[u8]) -> io::Result<Asset> {
+		self.shared.load(path)
+	}
+
+	// Call periodically, eg. once per frame in development builds
+	fn reload_if_changed(&self) -> io::Result<bool> {
+		self.shared.reload_if_changed()
+	}
+
+	fn counters(&self) -> &paks::Counters {
+		&self.shared.counters
+	}
+}
+
+impl Drop for AssetServer {
+	fn drop(&mut self) {
+		// Closing the queue stops the loader threads
+		self.requests = None;
+		for worker in self.workers.drain(..) {
+			let _ = worker.join();
+		}
+	}
+}
+
+const ASSETS: &[(&str, &[u8])] = &[
+	("textures/wall.png", &[0xA1; 3000]),
+	("textures/floor.png", &[0xB2; 5000]),
+	("shaders/basic.glsl", b"void main() { gl_FragColor = vec4(1.0); }"),
+	("levels/e1m1.map", &[0xC3; 12000]),
+];
+
+fn main() -> io::Result<()> {
+	let ref key = [13, 42];
+	let path = std::env::temp_dir().join("paks_asset_server_example.paks");
+
+	// Create the PAKS file with the assets of the game
+	paks::FileEditor::create_empty(&path, key)?;
+	let mut edit = paks::FileEditor::open(&path, key)?;
+	for &(asset_path, data) in ASSETS {
+		edit.create_file(asset_path.as_bytes(), data, key)?;
+	}
+	edit.finish(key)?;
+
+	// Four loader threads with room for 16 KiB of decrypted assets
+	let server = AssetServer::open(&path, key, 4, 16 * 1024)?;
+
+	// Request every asset at once, the loader threads decrypt them in parallel
+	let pending: Vec<_> = ASSETS.iter().map(|&(asset_path, _)| (asset_path, server.request(asset_path.as_bytes()))).collect();
+	for (asset_path, receiver) in pending {
+		let asset = receiver.recv().unwrap()?;
+		println!("loaded {} ({} bytes)", asset_path, asset.len());
+	}
+
+	// The assets do not all fit in the cache, the least recently used ones were evicted
+	// Loading the textures and the shader every frame is served from the cache once they are loaded
+	for _frame in 0..3 {
+		for &(asset_path, data) in &ASSETS[..3] {
+			assert_eq!(&*server.load(asset_path.as_bytes())?, data);
+		}
+	}
+	println!("cache hits: {}, sections read: {}", server.counters().cache_hits(), server.counters().sections_read());
+
+	// Nothing changed on disk
+	assert!(!server.reload_if_changed()?);
+
+	// Edit the shader while the game is running
+	let shader: &[u8] = b"void main() { gl_FragColor = vec4(0.5); }";
+	let mut edit = paks::FileEditor::open(&path, key)?;
+	edit.create_file(b"shaders/basic.glsl", shader, key)?;
+	edit.finish(key)?;
+
+	// The server picks up the edit and serves the new shader
+	assert!(server.reload_if_changed()?);
+	assert_eq!(&*server.load(b"shaders/basic.glsl")?, shader);
+	println!("reloaded shaders/basic.glsl");
+
+	drop(server);
+	fs::remove_file(&path)
+}
+
+#[test]
+fn test_lru_cache() {
+	let asset = |byte, len| -> Asset { vec![byte; len].into() };
+	let mut cache = LruCache::new(10);
+
+	// The least recently used asset is evicted first
+	cache.insert(b"a", asset(1, 4));
+	cache.insert(b"b", asset(2, 4));
+	assert!(cache.get(b"a").is_some());
+	cache.insert(b"c", asset(3, 4));
+	assert!(cache.get(b"b").is_none());
+	assert_eq!(cache.size, 8);
+
+	// Replacing an asset accounts for the size of the previous one
+	cache.insert(b"a", asset(4, 2));
+	assert_eq!(cache.size, 6);
+	assert_eq!(&*cache.get(b"a").unwrap(), &[4, 4]);
+	assert_eq!(cache.order, [b"c".to_vec(), b"a".to_vec()]);
+
+	// Assets larger than the cache are not cached and do not evict anything
+	cache.insert(b"d", asset(5, 11));
+	assert!(cache.get(b"d").is_none());
+	assert_eq!(cache.size, 6);
+
+	cache.clear();
+	assert!(cache.get(b"a").is_none());
+	assert_eq!(cache.size, 0);
+}
+
+#[test]
+fn test_asset_server() {
+	let ref key = [13, 42];
+	let path = std::env::temp_dir().join("paks_asset_server_test.paks");
+	paks::FileEditor::create_empty(&path, key).unwrap();
+	let mut edit = paks::FileEditor::open(&path, key).unwrap();
+	for &(asset_path, data) in ASSETS {
+		edit.create_file(asset_path.as_bytes(), data, key).unwrap();
+	}
+	edit.finish(key).unwrap();
+
+	// Room for every asset so the loader threads finishing out of order do not evict any
+	let server = AssetServer::open(&path, key, 2, 64 * 1024).unwrap();
+
+	// The loader threads answer every request, missing assets are errors
+	let pending: Vec<_> = ASSETS.iter().map(|&(asset_path, data)| (data, server.request(asset_path.as_bytes()))).collect();
+	for (data, receiver) in pending {
+		assert_eq!(&*receiver.recv().unwrap().unwrap(), data);
+	}
+	assert!(server.request(b"missing").recv().unwrap().is_err());
+
+	// Loading a cached asset does not read the PAKS file
+	let sections_read = server.counters().sections_read();
+	let cache_hits = server.counters().cache_hits();
+	assert_eq!(&*server.load(b"shaders/basic.glsl").unwrap(), ASSETS[2].1);
+	assert_eq!(server.counters().sections_read(), sections_read);
+	assert_eq!(server.counters().cache_hits(), cache_hits + 1);
+
+	// An edit is picked up once and drops the cached assets
+	assert!(!server.reload_if_changed().unwrap());
+	let mut edit = paks::FileEditor::open(&path, key).unwrap();
+	edit.create_file(b"shaders/basic.glsl", b"edited", key).unwrap();
+	edit.finish(key).unwrap();
+	assert!(server.reload_if_changed().unwrap());
+	assert!(!server.reload_if_changed().unwrap());
+	assert_eq!(server.shared.state.lock().unwrap().cache.size, 0);
+	assert_eq!(&*server.load(b"shaders/basic.glsl").unwrap(), b"edited");
+
+	// A failed reload keeps serving the previous version
+	fs::remove_file(&path).unwrap();
+	assert!(server.reload_if_changed().is_err());
+	assert_eq!(&*server.load(b"levels/e1m1.map").unwrap(), ASSETS[3].1);
+}
@@ -10,11 +10,11 @@ fn main() {
 
 	// Let's create a file `foo` under a directory `sub`.
 	// If a file already exists by this name it will be overwritten.
-	edit.create_file(b"sub/foo", DATA, key);
+	edit.create_file(b"sub/foo", DATA, key).unwrap();
 
 	// When done the editor object can be finalized and returns the encrypted PAKS file as a `Vec<Block>`.
 	// It also returns the unencrypted directory for final inspection if desired.
-	let (paks, dir) = edit.finish(key);
+	let (paks, dir) = edit.finish(key).unwrap();
 
 	// Print the directory.
 	print!("The directory:\n\n```\n{}```\n\n", dir.display());
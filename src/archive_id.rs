@@ -0,0 +1,144 @@
+use super::*;
+
+/// Path of the archive id in the PAKS file.
+///
+/// The header has no room for the archive id, it is stored as a regular file with its contents inline, see [`Descriptor::FLAG_INLINE`].
+/// Inline contents are encrypted and authenticated together with the directory, the archive id cannot be changed without the key.
+///
+/// The contents are the archive id followed by the generation as `u32`, see [`Directory::archive_generation`].
+pub const ARCHIVE_ID_PATH: &[u8] = b".archive-id";
+
+/// Random identity of a PAKS archive.
+///
+/// Generated once when a new PAKS archive is first finished, see [`MemoryEditor::new`] and [`FileEditor::create_new`], and kept by every edit afterwards.
+/// Keystores, patches and overlays may check the archive id to verify they are applied to the intended PAKS archive instead of relying on its path.
+///
+/// Formatted and parsed as 32 hexadecimal digits like [`parse_key`].
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash)]
+pub struct ArchiveId(pub Block);
+
+impl ArchiveId {
+	// The archive id is stored as the first block of the inline contents
+	#[inline]
+	pub(crate) fn from_desc(desc: &Descriptor) -> Option<ArchiveId> {
+		dataview::DataView::from(desc.inline_data()?).try_read::<Block>(0).map(ArchiveId)
+	}
+}
+
+// The generation follows the archive id
+pub(crate) fn generation(desc: &Descriptor) -> Option<u32> {
+	let data = desc.inline_data()?;
+	dataview::DataView::from(data).try_read::<u32>(BLOCK_SIZE)
+}
+
+pub(crate) fn set_record(desc: &mut Descriptor, id: ArchiveId, generation: u32) {
+	let mut data = [0u8; BLOCK_SIZE + 4];
+	data[..BLOCK_SIZE].copy_from_slice(dataview::bytes(&id.0));
+	data[BLOCK_SIZE..].copy_from_slice(&generation.to_ne_bytes());
+	desc.content_type = 1;
+	desc.set_inline_data(&data);
+}
+
+impl fmt::Display for ArchiveId {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:016x}{:016x}", self.0[1], self.0[0])
+	}
+}
+
+impl fmt::Debug for ArchiveId {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "ArchiveId({})", self)
+	}
+}
+
+impl str::FromStr for ArchiveId {
+	type Err = num::ParseIntError;
+
+	#[inline]
+	fn from_str(s: &str) -> Result<ArchiveId, num::ParseIntError> {
+		parse_key(s).map(ArchiveId)
+	}
+}
+
+#[test]
+fn test_archive_id() {
+	let id = ArchiveId([0x0123456789abcdef, 0xfedcba9876543210]);
+	assert_eq!(id.to_string(), "fedcba98765432100123456789abcdef");
+	assert_eq!(id.to_string().parse(), Ok(id));
+	assert!("xyz".parse::<ArchiveId>().is_err());
+
+	let mut dir = Directory::new();
+	assert_eq!(dir.archive_id(), None);
+	dir.set_archive_id(id);
+	assert_eq!(dir.archive_id(), Some(id));
+	assert!(dir.find_file(ARCHIVE_ID_PATH).unwrap().is_inline());
+
+	// The archive id is taken from the nonce source and kept by the edits
+	let ref key = [57, 58];
+	let mut edit = MemoryEditor::new();
+	edit.set_nonce_source(std::sync::Arc::new(SeededNonces::new(5)));
+	let id = edit.generate_archive_id().unwrap();
+	assert_eq!(SeededNonces::new(5).nonce().map(ArchiveId), Ok(id));
+	let (blocks, _) = edit.finish(key).unwrap();
+	let mut edit = MemoryEditor::from_blocks(blocks, key).unwrap();
+	edit.create_file(b"a", b"data", key).unwrap();
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.archive_id(), Some(id));
+
+	// Every finish increments the generation
+	assert_eq!(reader.archive_generation(), Some(2));
+	let (_, directory) = MemoryEditor::from(reader).finish(key).unwrap();
+	assert_eq!(directory.archive_generation(), Some(3));
+
+	// New PAKS files get their archive id when finished
+	let mut edit = MemoryEditor::new();
+	edit.set_nonce_source(std::sync::Arc::new(SeededNonces::new(6)));
+	let (_, directory) = edit.finish(key).unwrap();
+	assert_eq!(SeededNonces::new(6).nonce().map(ArchiveId), Ok(directory.archive_id().unwrap()));
+	assert_eq!(directory.archive_generation(), Some(1));
+}
+
+#[test]
+fn test_archive_id_strip_names() {
+	let ref key = [59, 60];
+	let mut edit = MemoryEditor::new();
+	let id = edit.generate_archive_id().unwrap();
+	edit.create_file(b"a", b"data", key).unwrap();
+	edit.strip_names();
+	let (blocks, _) = edit.finish(key).unwrap();
+
+	// Stripping the names keeps the archive id and the generation
+	let mut edit = MemoryEditor::from_blocks(blocks, key).unwrap();
+	assert!(edit.find_by_hash(path_hash(b"a")).unwrap().is_name_stripped());
+	assert_eq!((edit.archive_id(), edit.archive_generation()), (Some(id), Some(1)));
+	edit.strip_names();
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!((reader.archive_id(), reader.archive_generation()), (Some(id), Some(2)));
+}
+
+#[test]
+fn test_archive_id_digest() {
+	let ref key = [61, 62];
+	let mut edit = MemoryEditor::new();
+	edit.generate_archive_id().unwrap();
+	edit.create_file(b"a", b"data", key).unwrap();
+	let (blocks, directory) = edit.finish(key).unwrap();
+	let digest = directory.digest();
+
+	// Finishing without changes bumps the generation but keeps the digest
+	let mut edit = MemoryEditor::from_blocks(blocks, key).unwrap();
+	edit.gc();
+	let (blocks, directory) = edit.finish(key).unwrap();
+	assert_eq!(directory.archive_generation(), Some(2));
+	assert_eq!(directory.digest(), digest);
+	let (_, directory) = MemoryEditor::from_blocks(blocks.clone(), key).unwrap().finish(key).unwrap();
+	assert_eq!(directory.archive_generation(), Some(3));
+	assert_eq!(directory.digest(), digest);
+
+	// The archive id itself is covered
+	let mut edit = MemoryEditor::from_blocks(blocks, key).unwrap();
+	edit.set_archive_id(ArchiveId([1, 2]));
+	assert_ne!(edit.digest(), digest);
+}
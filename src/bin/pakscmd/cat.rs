@@ -0,0 +1,212 @@
+/*!
+Implements the `cat` command.
+*/
+
+use std::fmt::Write as _;
+use super::*;
+
+pub const HELP_CAT: &str = "\
+NAME
+    pakscmd-cat - Reads files from the PAKS archive and writes to stdout.
+
+SYNOPSIS
+    pakscmd [..] cat [--hex|--auto] [PATH]..
+
+DESCRIPTION
+    Reads files from the PAKS archive and writes to stdout.
+    Each file is read in the order specified and written to stdout one after another.
+    If an error happens it is printed and continues to write the rest of the files.
+
+    With --hex the files are written as a hexdump in the format of `hexdump -C`.
+    With --auto JSON files are pretty-printed, text files are written as-is and any other files are hexdumped.
+    Text files are valid UTF-8 without control characters other than whitespace,
+    JSON files are detected by their content type or text starting with `{` or `[`.
+
+ARGUMENTS
+    --hex    Write a hexdump of the files.
+    --auto   Pick the output format from the contents of each file.
+    PATH     Path to the file in the PAKS archive to output.
+";
+
+pub fn cat(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let hex = args.has("--hex");
+	let auto = args.has("--auto");
+
+	let reader = match paks::FileReader::open(file, key) {
+		Ok(reader) => reader,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	for &path in &args.positional {
+		match reader.find_file(path.as_bytes()) {
+			Some(file_desc) => {
+				match reader.read_data(&file_desc, key) {
+					Ok(data) => {
+						let format = if hex {
+							Format::Hex
+						}
+						else if auto {
+							detect(file_desc, &data)
+						}
+						else {
+							Format::Raw
+						};
+						let result = match format {
+							Format::Raw => io::stdout().write_all(&data),
+							Format::Hex => io::stdout().write_all(hexdump(&data, 0).as_bytes()),
+							Format::Json => match str::from_utf8(&data).ok().and_then(pretty_json) {
+								Some(text) => io::stdout().write_all(text.as_bytes()),
+								None => io::stdout().write_all(&data),
+							},
+						};
+						if let Err(err) = result {
+							error!(WritingStdout, path, err);
+						}
+					},
+					Err(err) => error!(Reading, path, err),
+				}
+			},
+			None => error!(FileNotFound, path),
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Format {
+	Raw,
+	Hex,
+	Json,
+}
+
+// Picks the output format from the content type and the contents of the file
+fn detect(desc: &paks::Descriptor, data: &[u8]) -> Format {
+	let text = match str::from_utf8(data) {
+		Ok(text) if text.chars().all(|chr| !chr.is_control() || matches!(chr, '\n' | '\r' | '\t')) => text,
+		_ => return Format::Hex,
+	};
+	if desc.content_type == paks::Descriptor::CONTENT_TYPE_JSON || matches!(text.trim_start().as_bytes().first(), Some(b'{' | b'[')) {
+		Format::Json
+	}
+	else {
+		Format::Raw
+	}
+}
+
+// Formats the data like `hexdump -C`, the offset followed by sixteen bytes in hex and as printable ASCII
+// The offsets start at the byte offset of the data
+pub fn hexdump(data: &[u8], offset: u64) -> String {
+	let mut out = String::with_capacity((data.len() / 16 + 2) * 80);
+	for (i, line) in data.chunks(16).enumerate() {
+		let _ = write!(out, "{:08x} ", offset + i as u64 * 16);
+		for j in 0..16 {
+			if j % 8 == 0 {
+				out.push(' ');
+			}
+			match line.get(j) {
+				Some(byte) => { let _ = write!(out, "{:02x} ", byte); },
+				None => out.push_str("   "),
+			}
+		}
+		out.push_str(" |");
+		out.extend(line.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+		out.push_str("|\n");
+	}
+	let _ = writeln!(out, "{:08x}", offset + data.len() as u64);
+	out
+}
+
+// Reindents the JSON text with four spaces per level, the text is otherwise not validated
+// Returns None if the strings are unterminated or the brackets are unbalanced
+fn pretty_json(text: &str) -> Option<String> {
+	let mut out = String::with_capacity(text.len() * 2);
+	let mut stack = Vec::new();
+	let mut chars = text.chars().peekable();
+	while let Some(chr) = chars.next() {
+		match chr {
+			'"' => {
+				out.push(chr);
+				loop {
+					let chr = chars.next()?;
+					out.push(chr);
+					match chr {
+						'\\' => out.push(chars.next()?),
+						'"' => break,
+						_ => (),
+					}
+				}
+			},
+			'{' | '[' => {
+				let close = if chr == '{' { '}' } else { ']' };
+				out.push(chr);
+				// Keep empty objects and arrays on a single line
+				while chars.next_if(|chr| chr.is_whitespace()).is_some() {}
+				if chars.next_if_eq(&close).is_some() {
+					out.push(close);
+					continue;
+				}
+				stack.push(close);
+				newline(&mut out, stack.len());
+			},
+			'}' | ']' => {
+				if stack.pop() != Some(chr) {
+					return None;
+				}
+				newline(&mut out, stack.len());
+				out.push(chr);
+			},
+			',' => {
+				out.push(chr);
+				newline(&mut out, stack.len());
+			},
+			':' => out.push_str(": "),
+			chr if chr.is_whitespace() => (),
+			chr => out.push(chr),
+		}
+	}
+	if !stack.is_empty() {
+		return None;
+	}
+	out.push('\n');
+	Some(out)
+}
+
+fn newline(out: &mut String, depth: usize) {
+	out.push('\n');
+	for _ in 0..depth {
+		out.push_str("    ");
+	}
+}
+
+#[test]
+fn test_cat_formats() {
+	assert_eq!(hexdump(b"Hello, world!\n\x00\xff", 0), "\
+00000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 ff  |Hello, world!...|
+00000010
+");
+	assert_eq!(hexdump(b"ab", 0x20), "00000020  61 62                                             |ab|\n00000022\n");
+	assert_eq!(hexdump(b"", 0), "00000000\n");
+
+	assert_eq!(pretty_json(r#"{"a":[1, 2],"b":{},"c":"x,\"{"}"#).unwrap(), "\
+{
+    \"a\": [
+        1,
+        2
+    ],
+    \"b\": {},
+    \"c\": \"x,\\\"{\"
+}
+");
+	assert_eq!(pretty_json("[1}"), None);
+	assert_eq!(pretty_json("{\"a"), None);
+
+	let desc = paks::Descriptor::new(b"file", 1, 0);
+	assert_eq!(detect(&desc, b" [1]"), Format::Json);
+	assert_eq!(detect(&desc, b"plain\ttext\r\n"), Format::Raw);
+	assert_eq!(detect(&desc, b"\x00\x01"), Format::Hex);
+	assert_eq!(detect(&paks::Descriptor::new(b"file", paks::Descriptor::CONTENT_TYPE_JSON, 0), b"1"), Format::Json);
+}
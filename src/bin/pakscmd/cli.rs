@@ -0,0 +1,703 @@
+/*!
+Declarative table of pakscmd's commands.
+
+The command table drives the argument parsing, the dispatch, the help and the shell completions.
+
+Flags may appear anywhere between the positional arguments.
+Flags with a value accept both `--flag value` and `--flag=value`, short flags may be combined as in `-au`.
+Flags with an optional value, written as `[VALUE]`, only accept `--flag=value`.
+All arguments after `--` are positional arguments.
+*/
+
+use std::fmt;
+use super::*;
+use super::cat::{HELP_CAT, cat};
+use super::copy::{HELP_COPY, copy};
+use super::completions::{HELP_COMPLETIONS, completions};
+use super::graft::{HELP_GRAFT, graft};
+use super::grep::{HELP_GREP, grep};
+use super::inspect::{HELP_INSPECT, inspect};
+use super::patch::{HELP_PATCH, patch};
+use super::run::{HELP_RUN, run};
+use super::session::{HELP_ABORT, HELP_BEGIN, HELP_COMMIT, abort, begin, commit};
+use super::stats::{HELP_STATS, stats};
+
+/// Command-line flag.
+pub struct Flag {
+	/// Name of the flag including the leading dashes.
+	pub name: &'static str,
+	/// Name of the value if the flag expects a value.
+	///
+	/// The name of an optional value is enclosed in brackets.
+	pub value: Option<&'static str>,
+	/// The possible values, if known.
+	pub values: &'static [&'static str],
+	/// Short description.
+	pub about: &'static str,
+}
+
+/// Kind of the positional arguments.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Args {
+	/// No arguments or paths in the PAKS archive.
+	None,
+	/// Paths on the file system.
+	Files,
+	/// Names of commands.
+	Commands,
+	/// Names of shells.
+	Shells,
+}
+
+/// Entry point of a command.
+#[derive(Copy, Clone)]
+pub enum Run {
+	/// Invoked as `pakscmd <COMMAND> [..]`.
+	Standalone(fn(&Matches)),
+	/// Invoked as `pakscmd <PAKFILE> <KEY> <COMMAND> [..]`, or `pakscmd <PAKFILE> <COMMAND> [..]` with a keystore.
+	Archive(fn(&str, &str, &Matches)),
+}
+
+/// Command-line command.
+pub struct Command {
+	/// Name of the command.
+	pub name: &'static str,
+	/// Short description.
+	pub about: &'static str,
+	/// Full help text.
+	pub help: &'static str,
+	/// Flags accepted by the command.
+	pub flags: &'static [Flag],
+	/// Kind of the positional arguments.
+	pub args: Args,
+	/// Entry point of the command.
+	pub run: Run,
+	/// Hidden from the help and the shell completions.
+	pub hidden: bool,
+}
+
+impl Command {
+	/// Returns if the command is invoked without a PAKS archive.
+	pub fn is_standalone(&self) -> bool {
+		matches!(self.run, Run::Standalone(_))
+	}
+}
+
+/// Flags accepted by every command.
+pub static GLOBAL_FLAGS: &[Flag] = &[
+	Flag { name: "--lang", value: Some("LANG"), values: &["en", "machine"], about: "Language of the messages" },
+	Flag { name: "--keystore", value: Some("FILE"), values: &[], about: "Looks up the keys of the PAKS archives in the keystore" },
+	Flag { name: "--help", value: None, values: &[], about: "Displays help information" },
+];
+
+/// All the commands.
+pub static COMMANDS: &[Command] = &[
+	Command {
+		name: "help",
+		about: "Displays help information.",
+		help: HELP_HELP,
+		flags: &[],
+		args: Args::Commands,
+		run: Run::Standalone(help),
+		hidden: false,
+	},
+	Command {
+		name: "identify",
+		about: "Identifies PAKS archives without the encryption key.",
+		help: HELP_IDENTIFY,
+		flags: &[],
+		args: Args::Files,
+		run: Run::Standalone(identify),
+		hidden: false,
+	},
+	Command {
+		name: "completions",
+		about: "Generates shell completion scripts.",
+		help: HELP_COMPLETIONS,
+		flags: &[],
+		args: Args::Shells,
+		run: Run::Standalone(completions),
+		hidden: false,
+	},
+	Command {
+		name: "new",
+		about: "Creates a new empty PAKS archive.",
+		help: HELP_NEW,
+		flags: &[
+			Flag { name: "--stealth", value: None, values: &[], about: "Omit the plaintext magic signature" },
+			Flag { name: "--block-size", value: Some("BYTES"), values: &[], about: "Align the files to blocks of BYTES bytes" },
+		],
+		args: Args::None,
+		run: Run::Archive(new),
+		hidden: false,
+	},
+	Command {
+		name: "verify-key",
+		about: "Checks the encryption key of the PAKS archive.",
+		help: HELP_VERIFY_KEY,
+		flags: &[],
+		args: Args::None,
+		run: Run::Archive(verify_key),
+		hidden: false,
+	},
+	Command {
+		name: "key",
+		about: "Manages the key slots of the PAKS archive.",
+		help: HELP_KEY,
+		flags: &[
+			Flag { name: "--age-recipient", value: Some("PUBKEY"), values: &[], about: "Wrap the key for the age recipient" },
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(key),
+		hidden: false,
+	},
+	Command {
+		name: "tree",
+		about: "Displays the directory of the PAKS archive.",
+		help: HELP_TREE,
+		flags: &[
+			Flag { name: "-a", value: None, values: &[], about: "Display using ASCII art" },
+			Flag { name: "-u", value: None, values: &[], about: "Display using UNICODE art" },
+			Flag { name: "--style", value: Some("STYLE"), values: &["ascii", "unicode", "compact", "markdown"], about: "Display using the art style" },
+			Flag { name: "--names", value: Some("NAMEFILE"), values: &[], about: "Name table of the stripped PAKS archive" },
+			Flag { name: "--types", value: None, values: &[], about: "Show the names of the content types" },
+		],
+		args: Args::None,
+		run: Run::Archive(tree),
+		hidden: false,
+	},
+	Command {
+		name: "ls",
+		about: "Lists the directory of the PAKS archive.",
+		help: HELP_LS,
+		flags: &[
+			Flag { name: "-R", value: None, values: &[], about: "List subdirectories recursively" },
+			Flag { name: "--types", value: None, values: &[], about: "Show the names of the content types" },
+		],
+		args: Args::None,
+		run: Run::Archive(ls),
+		hidden: false,
+	},
+	Command {
+		name: "add",
+		about: "Adds a file to the PAKS archive.",
+		help: HELP_ADD,
+		flags: &[
+			Flag { name: "--plaintext", value: None, values: &[], about: "Store the file unencrypted" },
+			Flag { name: "--delta", value: None, values: &[], about: "Store only the differences against the replaced file" },
+			Flag { name: "--sniff", value: None, values: &[], about: "Assign the content type by inspecting the contents" },
+			Flag { name: "--dry-run", value: None, values: &[], about: "Print the changes without writing" },
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(add),
+		hidden: false,
+	},
+	Command {
+		name: "copy",
+		about: "Copies files to the PAKS archive.",
+		help: HELP_COPY,
+		flags: &[
+			Flag { name: "--follow-symlinks", value: None, values: &[], about: "Follow symbolic links" },
+			Flag { name: "--no-follow", value: None, values: &[], about: "Skip symbolic links" },
+			Flag { name: "--exclude", value: Some("PATTERN"), values: &[], about: "Exclude files matching the pattern" },
+			Flag { name: "--map", value: Some("SRC=DEST"), values: &[], about: "Remap the path prefix SRC to DEST" },
+			Flag { name: "--transform", value: Some("EXT=NAME"), values: &[], about: "Transform files with the extension EXT" },
+			Flag { name: "--incremental", value: None, values: &[], about: "Skip unchanged files using the cache" },
+			Flag { name: "--jobs", value: Some("N"), values: &[], about: "Number of worker threads" },
+			Flag { name: "--bundle", value: Some("BYTES"), values: &[], about: "Bundle files of at most BYTES bytes" },
+			Flag { name: "--mtime", value: None, values: &[], about: "Record the modification times of the files" },
+			Flag { name: "--sniff", value: None, values: &[], about: "Assign the content types by inspecting the contents" },
+			Flag { name: "--strict", value: None, values: &[], about: "Fail if a file is copied to a path which is already taken" },
+			Flag { name: "--dry-run", value: None, values: &[], about: "Print the changes without writing" },
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::Files,
+		run: Run::Archive(copy),
+		hidden: false,
+	},
+	Command {
+		name: "graft",
+		about: "Copies a subtree from another PAKS archive.",
+		help: HELP_GRAFT,
+		flags: &[
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::Files,
+		run: Run::Archive(graft),
+		hidden: false,
+	},
+	Command {
+		name: "patch",
+		about: "Creates and applies patch files.",
+		help: HELP_PATCH,
+		flags: &[
+			Flag { name: "--verify", value: None, values: &[], about: "Check the whole PAKS archive after applying" },
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::Files,
+		run: Run::Archive(patch),
+		hidden: false,
+	},
+	Command {
+		name: "pack",
+		about: "Creates the PAKS archive from a manifest.",
+		help: HELP_PACK,
+		flags: &[
+			Flag { name: "--manifest", value: Some("MANIFEST"), values: &[], about: "Path to the manifest file" },
+			Flag { name: "--key-id", value: Some("ID=KEY"), values: &[], about: "Defines the encryption key for the key id" },
+			Flag { name: "--transform", value: Some("EXT=NAME"), values: &[], about: "Transform files with the extension EXT" },
+			Flag { name: "--incremental", value: None, values: &[], about: "Reuse unchanged files from the existing PAKS archive" },
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(pack),
+		hidden: false,
+	},
+	Command {
+		name: "run",
+		about: "Runs a script of edits to the PAKS archive.",
+		help: HELP_RUN,
+		flags: &[
+			Flag { name: "--dry-run", value: None, values: &[], about: "Print the changes without writing" },
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::Files,
+		run: Run::Archive(run),
+		hidden: false,
+	},
+	Command {
+		name: "begin",
+		about: "Opens a session staging the edits to the PAKS archive.",
+		help: HELP_BEGIN,
+		flags: &[],
+		args: Args::None,
+		run: Run::Archive(begin),
+		hidden: false,
+	},
+	Command {
+		name: "commit",
+		about: "Applies the edits staged in the session.",
+		help: HELP_COMMIT,
+		flags: &[
+			Flag { name: "--dry-run", value: None, values: &[], about: "Print the changes without writing" },
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(commit),
+		hidden: false,
+	},
+	Command {
+		name: "abort",
+		about: "Discards the edits staged in the session.",
+		help: HELP_ABORT,
+		flags: &[],
+		args: Args::None,
+		run: Run::Archive(abort),
+		hidden: false,
+	},
+	Command {
+		name: "link",
+		about: "Links the file from alternative paths.",
+		help: HELP_LINK,
+		flags: &[
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(link),
+		hidden: false,
+	},
+	Command {
+		name: "cat",
+		about: "Reads files from the PAKS archive and writes to stdout.",
+		help: HELP_CAT,
+		flags: &[
+			Flag { name: "--hex", value: None, values: &[], about: "Write a hexdump of the files" },
+			Flag { name: "--auto", value: None, values: &[], about: "Pretty-print JSON, write text as-is and hexdump binaries" },
+		],
+		args: Args::None,
+		run: Run::Archive(cat),
+		hidden: false,
+	},
+	Command {
+		name: "grep",
+		about: "Searches files in the PAKS archive for a pattern.",
+		help: HELP_GREP,
+		flags: &[
+			Flag { name: "-E", value: None, values: &[], about: "The pattern is a regular expression" },
+			Flag { name: "--hex", value: None, values: &[], about: "The pattern is a sequence of bytes encoded in hex" },
+			Flag { name: "-i", value: None, values: &[], about: "Ignore ASCII case distinctions" },
+		],
+		args: Args::None,
+		run: Run::Archive(grep),
+		hidden: false,
+	},
+	Command {
+		name: "hash",
+		about: "Prints the SHA-256 digest of files in the PAKS archive.",
+		help: HELP_HASH,
+		flags: &[
+			Flag { name: "--all", value: None, values: &[], about: "Hash every file in the PAKS archive" },
+		],
+		args: Args::None,
+		run: Run::Archive(hash),
+		hidden: false,
+	},
+	Command {
+		name: "preload",
+		about: "Manages the preload list of the PAKS archive.",
+		help: HELP_PRELOAD,
+		flags: &[
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(preload),
+		hidden: false,
+	},
+	Command {
+		name: "types",
+		about: "Manages the names of the content types.",
+		help: HELP_TYPES,
+		flags: &[
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(types),
+		hidden: false,
+	},
+	Command {
+		name: "extract",
+		about: "Extracts files from the PAKS archive to a directory.",
+		help: HELP_EXTRACT,
+		flags: &[],
+		args: Args::Files,
+		run: Run::Archive(extract),
+		hidden: false,
+	},
+	Command {
+		name: "rm",
+		about: "Removes paths from the PAKS archive.",
+		help: HELP_RM,
+		flags: &[
+			Flag { name: "--dry-run", value: None, values: &[], about: "Print the changes without writing" },
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(rm),
+		hidden: false,
+	},
+	Command {
+		name: "undelete",
+		about: "Restores removed files in the PAKS archive.",
+		help: HELP_UNDELETE,
+		flags: &[
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(undelete),
+		hidden: false,
+	},
+	Command {
+		name: "restore-backup",
+		about: "Restores the PAKS archive from its backup.",
+		help: HELP_RESTORE_BACKUP,
+		flags: &[],
+		args: Args::None,
+		run: Run::Archive(restore_backup),
+		hidden: false,
+	},
+	Command {
+		name: "mv",
+		about: "Moves files in the PAKS archive.",
+		help: HELP_MV,
+		flags: &[
+			Flag { name: "--dry-run", value: None, values: &[], about: "Print the changes without writing" },
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(mv),
+		hidden: false,
+	},
+	Command {
+		name: "fsck",
+		about: "File system consistency check.",
+		help: HELP_FSCK,
+		flags: &[
+			Flag { name: "--portable", value: None, values: &[], about: "Check the paths can be extracted on all operating systems" },
+		],
+		args: Args::None,
+		run: Run::Archive(fsck),
+		hidden: false,
+	},
+	Command {
+		name: "gc",
+		about: "Collects garbage left behind by removed files.",
+		help: HELP_GC,
+		flags: &[
+			Flag { name: "--dry-run", value: None, values: &[], about: "Print the changes without writing" },
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(gc),
+		hidden: false,
+	},
+	Command {
+		name: "optimize",
+		about: "Places the files in access order.",
+		help: HELP_OPTIMIZE,
+		flags: &[
+			Flag { name: "--order", value: Some("ORDER"), values: &[], about: "Path to the access order" },
+			Flag { name: "--block-size", value: Some("BYTES"), values: &[], about: "Align the files to blocks of BYTES bytes" },
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(optimize),
+		hidden: false,
+	},
+	Command {
+		name: "strip",
+		about: "Replaces the names with the hash of their path.",
+		help: HELP_STRIP,
+		flags: &[
+			Flag { name: "--names", value: Some("NAMEFILE"), values: &[], about: "Name table of the stripped PAKS archive" },
+		],
+		args: Args::Files,
+		run: Run::Archive(strip),
+		hidden: false,
+	},
+	Command {
+		name: "selfextract",
+		about: "Creates a self-extracting executable.",
+		help: HELP_SELFEXTRACT,
+		flags: &[
+			Flag { name: "--stub", value: Some("STUB"), values: &[], about: "Path to the stub executable" },
+		],
+		args: Args::Files,
+		run: Run::Archive(selfextract),
+		hidden: false,
+	},
+	Command {
+		name: "upgrade",
+		about: "Rewrites the PAKS archive in the newest file format.",
+		help: HELP_UPGRADE,
+		flags: &[
+			Flag { name: "--backup", value: Some("[SUFFIX]"), values: &[], about: "Back up the PAKS archive before editing" },
+		],
+		args: Args::None,
+		run: Run::Archive(upgrade),
+		hidden: false,
+	},
+	Command {
+		name: "inspect",
+		about: "Shows the layout of the PAKS archive.",
+		help: HELP_INSPECT,
+		flags: &[
+			Flag { name: "--raw", value: Some("RANGE"), values: &[], about: "Hexdump the encrypted bytes of the blocks START..END" },
+		],
+		args: Args::None,
+		run: Run::Archive(inspect),
+		hidden: false,
+	},
+	Command {
+		name: "stats",
+		about: "Shows size statistics of the PAKS archive.",
+		help: HELP_STATS,
+		flags: &[
+			Flag { name: "--json", value: None, values: &[], about: "Write the statistics as JSON" },
+			Flag { name: "--top", value: Some("N"), values: &[], about: "Number of largest files to show" },
+		],
+		args: Args::None,
+		run: Run::Archive(stats),
+		hidden: false,
+	},
+];
+
+/// Finds the command by name.
+pub fn find(name: &str) -> Option<&'static Command> {
+	COMMANDS.iter().find(|cmd| cmd.name == name)
+}
+
+/// Iterates over the commands which are not hidden.
+pub fn visible() -> impl Iterator<Item = &'static Command> {
+	COMMANDS.iter().filter(|cmd| !cmd.hidden)
+}
+
+/// Finds the flag by name, including the leading dashes.
+///
+/// Flags with the same name accepted by different commands have the same meaning.
+pub fn find_flag(name: &str) -> Option<&'static Flag> {
+	GLOBAL_FLAGS.iter().chain(COMMANDS.iter().flat_map(|cmd| cmd.flags)).find(|flag| flag.name == name)
+}
+
+//----------------------------------------------------------------
+
+/// Syntax error in the command-line arguments.
+#[derive(Copy, Clone, Debug)]
+pub enum ParseError<'a> {
+	/// The flag does not exist.
+	Unknown(&'a str),
+	/// The flag expects a value.
+	MissingValue(&'static Flag),
+	/// The flag does not take a value.
+	UnexpectedValue(&'static Flag),
+}
+
+impl ParseError<'_> {
+	/// Prints the error.
+	pub fn emit(&self) {
+		match *self {
+			ParseError::Unknown(arg) => error!(UnknownArgument, arg),
+			ParseError::MissingValue(flag) => error!(OptionExpects, flag.name, flag.value.unwrap_or("")),
+			ParseError::UnexpectedValue(flag) => error!(OptionNoValue, flag.name),
+		}
+	}
+}
+
+impl Flag {
+	/// Returns if the flag consumes the next argument as its value.
+	pub fn takes_value(&self) -> bool {
+		self.value.is_some_and(|value| !value.starts_with('['))
+	}
+}
+
+impl fmt::Debug for Flag {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(self.name)
+	}
+}
+
+/// Parsed command-line arguments.
+pub struct Matches<'a> {
+	/// The positional arguments.
+	pub positional: Vec<&'a str>,
+	/// The flags and their values in the order given.
+	pub flags: Vec<(&'static Flag, Option<&'a str>)>,
+	/// The first syntax error.
+	pub error: Option<ParseError<'a>>,
+}
+
+impl<'a> Matches<'a> {
+	/// Returns if the flag was given.
+	pub fn has(&self, name: &str) -> bool {
+		self.flags.iter().any(|(flag, _)| flag.name == name)
+	}
+
+	/// Returns the value of the last occurrence of the flag.
+	pub fn value(&self, name: &str) -> Option<&'a str> {
+		self.values(name).last()
+	}
+
+	/// Returns the values of every occurrence of the flag.
+	pub fn values<'s>(&'s self, name: &'s str) -> impl Iterator<Item = &'a str> + 's {
+		self.flags.iter().filter(move |(flag, _)| flag.name == name).filter_map(|&(_, value)| value)
+	}
+
+	fn fail(&mut self, error: ParseError<'a>) {
+		if self.error.is_none() {
+			self.error = Some(error);
+		}
+	}
+
+	fn take(&mut self, flag: &'static Flag, value: Option<&'a str>, args: &mut dyn Iterator<Item = &'a str>) {
+		if flag.value.is_some() && !flag.takes_value() {
+			self.flags.push((flag, value));
+		}
+		else if flag.value.is_some() {
+			match value.or_else(|| args.next()) {
+				Some(value) => self.flags.push((flag, Some(value))),
+				None => self.fail(ParseError::MissingValue(flag)),
+			}
+		}
+		else if value.is_some() {
+			self.fail(ParseError::UnexpectedValue(flag));
+		}
+		else {
+			self.flags.push((flag, None));
+		}
+	}
+}
+
+/// Parses the command-line arguments.
+///
+/// Parsing continues after a syntax error so the `--lang` flag can be applied before the error is printed.
+pub fn parse<'a>(args: &[&'a str]) -> Matches<'a> {
+	let mut matches = Matches {
+		positional: Vec::new(),
+		flags: Vec::new(),
+		error: None,
+	};
+
+	let mut args = args.iter().cloned();
+	while let Some(arg) = args.next() {
+		if arg == "--" {
+			matches.positional.extend(&mut args);
+		}
+		else if arg.starts_with("--") {
+			let (name, value) = match arg.split_once('=') {
+				Some((name, value)) => (name, Some(value)),
+				None => (arg, None),
+			};
+			match find_flag(name) {
+				Some(flag) => matches.take(flag, value, &mut args),
+				None => matches.fail(ParseError::Unknown(name)),
+			}
+		}
+		else if arg.len() > 1 && arg.starts_with('-') {
+			// Short flags may be combined, the rest of the argument is the value of a short flag expecting a value
+			for (i, chr) in arg.char_indices().skip(1) {
+				let end = i + chr.len_utf8();
+				let flag = match find_flag(&format!("-{}", chr)) {
+					Some(flag) => flag,
+					None => {
+						matches.fail(ParseError::Unknown(arg));
+						break;
+					},
+				};
+				if flag.takes_value() {
+					let rest = &arg[end..];
+					matches.take(flag, if rest.is_empty() { None } else { Some(rest) }, &mut args);
+					break;
+				}
+				matches.flags.push((flag, None));
+			}
+		}
+		else {
+			matches.positional.push(arg);
+		}
+	}
+
+	matches
+}
+
+#[test]
+fn test_parse() {
+	// Flags may appear anywhere, with their value in the next argument or after `=`
+	let matches = parse(&["file.paks", "--lang", "machine", "2a", "copy", "--jobs=4", "--exclude", "*.tmp", "dest", "--exclude=*.bak"]);
+	assert_eq!(matches.positional, ["file.paks", "2a", "copy", "dest"]);
+	assert!(matches.error.is_none());
+	assert_eq!(matches.value("--lang"), Some("machine"));
+	assert_eq!(matches.value("--jobs"), Some("4"));
+	assert_eq!(matches.values("--exclude").collect::<Vec<_>>(), ["*.tmp", "*.bak"]);
+	assert!(!matches.has("--help"));
+
+	// Optional values are only accepted after `=`, everything after `--` is positional
+	let matches = parse(&["--backup", "x", "--help", "--", "--backup=.old", "-a"]);
+	assert_eq!(matches.positional, ["x", "--backup=.old", "-a"]);
+	assert!(matches.has("--backup") && matches.has("--help"));
+	assert_eq!(matches.value("--backup"), None);
+	assert_eq!(parse(&["--backup=.old"]).value("--backup"), Some(".old"));
+
+	// Short flags may be combined
+	let matches = parse(&["-ai", "-"]);
+	assert!(matches.has("-a") && matches.has("-i"));
+	assert_eq!(matches.positional, ["-"]);
+
+	// Parsing continues after the first error
+	let matches = parse(&["--bogus", "--help=yes", "-az", "--lang", "en", "--jobs"]);
+	assert!(matches!(matches.error, Some(ParseError::Unknown("--bogus"))));
+	assert_eq!(matches.value("--lang"), Some("en"));
+	assert!(matches!(parse(&["--help=yes"]).error, Some(ParseError::UnexpectedValue(flag)) if flag.name == "--help"));
+	assert!(matches!(parse(&["-az"]).error, Some(ParseError::Unknown("-az"))));
+	assert!(matches!(parse(&["x", "--jobs"]).error, Some(ParseError::MissingValue(flag)) if flag.name == "--jobs"));
+}
@@ -0,0 +1,429 @@
+/*!
+Implements the `completions` command.
+
+The completion scripts are generated from the command table in the `cli` module.
+*/
+
+use std::fmt::Write as _;
+use super::*;
+use super::cli::{Args, Command, Flag};
+
+pub const HELP_COMPLETIONS: &str = "\
+NAME
+    pakscmd-completions - Generates shell completion scripts.
+
+SYNOPSIS
+    pakscmd completions <SHELL>
+
+DESCRIPTION
+    Generates a completion script for the shell and writes it to stdout.
+    The script completes the commands, their flags and the values of flags where known.
+
+    bash:        pakscmd completions bash > /etc/bash_completion.d/pakscmd
+    zsh:         pakscmd completions zsh > \"${fpath[1]}/_pakscmd\"
+    fish:        pakscmd completions fish > ~/.config/fish/completions/pakscmd.fish
+    powershell:  pakscmd completions powershell >> $PROFILE
+
+ARGUMENTS
+    SHELL  One of bash, zsh, fish or powershell.
+";
+
+/// Shells supported by the `completions` command.
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+pub fn completions(args: &cli::Matches) {
+	let script = match args.positional[..] {
+		["bash"] => bash(),
+		["zsh"] => zsh(),
+		["fish"] => fish(),
+		["powershell"] => powershell(),
+		[shell] => return error!(UnknownShell, shell),
+		_ => return error!(InvalidSyntaxCmd, "completions"),
+	};
+	print!("{}", script);
+}
+
+//----------------------------------------------------------------
+
+fn names(standalone: bool) -> Vec<&'static str> {
+	cli::visible().filter(|cmd| cmd.is_standalone() == standalone).map(|cmd| cmd.name).collect()
+}
+
+// All the flags, the same flag may be accepted by multiple commands
+fn all_flags() -> Vec<&'static Flag> {
+	let mut flags: Vec<&'static Flag> = Vec::new();
+	for flag in cli::GLOBAL_FLAGS.iter().chain(cli::visible().flat_map(|cmd| cmd.flags)) {
+		if !flags.iter().any(|other| other.name == flag.name) {
+			flags.push(flag);
+		}
+	}
+	flags
+}
+
+// Flags which consume the next argument as their value
+fn value_flags() -> Vec<&'static str> {
+	all_flags().into_iter().filter(|flag| flag.takes_value()).map(|flag| flag.name).collect()
+}
+
+fn flag_names(flags: &[Flag]) -> Vec<&'static str> {
+	flags.iter().map(|flag| flag.name).collect()
+}
+
+// Candidates for the positional arguments of the command
+fn positionals(cmd: &Command) -> Vec<&'static str> {
+	match cmd.args {
+		Args::None | Args::Files => Vec::new(),
+		Args::Commands => cli::visible().map(|cmd| cmd.name).collect(),
+		Args::Shells => SHELLS.to_vec(),
+	}
+}
+
+//----------------------------------------------------------------
+
+fn bash() -> String {
+	let mut s = String::new();
+	let value_flags = value_flags();
+
+	s.push_str("_pakscmd() {\n");
+	s.push_str("\tlocal cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+	s.push_str("\tCOMPREPLY=()\n\n");
+
+	s.push_str("\tcase \"$prev\" in\n");
+	for flag in all_flags() {
+		if flag.takes_value() {
+			if flag.values.is_empty() {
+				let _ = writeln!(s, "\t\t{}) return;;", flag.name);
+			}
+			else {
+				let _ = writeln!(s, "\t\t{}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return;;", flag.name, flag.values.join(" "));
+			}
+		}
+	}
+	s.push_str("\tesac\n\n");
+
+	s.push_str("\tlocal i cmd pos=()\n");
+	s.push_str("\tfor ((i = 1; i < COMP_CWORD; i++)); do\n");
+	s.push_str("\t\tcase \"${COMP_WORDS[i]}\" in\n");
+	let _ = writeln!(s, "\t\t\t{}) ((i++));;", value_flags.join("|"));
+	s.push_str("\t\t\t-*) ;;\n");
+	s.push_str("\t\t\t*) pos+=(\"${COMP_WORDS[i]}\");;\n");
+	s.push_str("\t\tesac\n");
+	s.push_str("\tdone\n\n");
+
+	s.push_str("\tif [[ ${#pos[@]} -eq 0 ]]; then\n");
+	s.push_str("\t\tif [[ $cur == -* ]]; then\n");
+	let _ = writeln!(s, "\t\t\tCOMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", flag_names(cli::GLOBAL_FLAGS).join(" "));
+	s.push_str("\t\telse\n");
+	let _ = writeln!(s, "\t\t\tCOMPREPLY=($(compgen -W \"{}\" -- \"$cur\") $(compgen -f -- \"$cur\"))", names(true).join(" "));
+	s.push_str("\t\tfi\n");
+	s.push_str("\t\treturn\n");
+	s.push_str("\tfi\n\n");
+
+	s.push_str("\tcmd=\"${pos[0]}\"\n");
+	s.push_str("\tcase \"$cmd\" in\n");
+	let _ = writeln!(s, "\t\t{}) ;;", names(true).join("|"));
+	s.push_str("\t\t*)\n");
+	s.push_str("\t\t\tcase ${#pos[@]} in\n");
+	s.push_str("\t\t\t\t1) return;;\n");
+	let _ = writeln!(s, "\t\t\t\t2) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return;;", names(false).join(" "));
+	s.push_str("\t\t\tesac\n");
+	s.push_str("\t\t\tcmd=\"${pos[2]}\";;\n");
+	s.push_str("\tesac\n\n");
+
+	s.push_str("\tcase \"$cmd\" in\n");
+	for cmd in cli::visible() {
+		let flags = flag_names(cmd.flags);
+		let positionals = positionals(cmd);
+		if flags.is_empty() && positionals.is_empty() {
+			continue;
+		}
+		let _ = writeln!(s, "\t\t{})", cmd.name);
+		s.push_str("\t\t\tif [[ $cur == -* ]]; then\n");
+		let _ = writeln!(s, "\t\t\t\tCOMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", flags.join(" "));
+		s.push_str("\t\t\telse\n");
+		let _ = writeln!(s, "\t\t\t\tCOMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", positionals.join(" "));
+		s.push_str("\t\t\tfi;;\n");
+	}
+	s.push_str("\tesac\n");
+	s.push_str("}\n\n");
+
+	s.push_str("complete -o default -F _pakscmd pakscmd\n");
+	s
+}
+
+//----------------------------------------------------------------
+
+fn zsh() -> String {
+	let mut s = String::new();
+	let value_flags = value_flags();
+
+	s.push_str("#compdef pakscmd\n\n");
+	s.push_str("_pakscmd() {\n");
+	s.push_str("\tlocal cur=\"${words[CURRENT]}\" prev=\"${words[CURRENT-1]}\"\n\n");
+
+	s.push_str("\tcase \"$prev\" in\n");
+	for flag in all_flags() {
+		if flag.takes_value() {
+			if flag.values.is_empty() {
+				let _ = writeln!(s, "\t\t{}) _files; return;;", flag.name);
+			}
+			else {
+				let _ = writeln!(s, "\t\t{}) compadd -- {}; return;;", flag.name, flag.values.join(" "));
+			}
+		}
+	}
+	s.push_str("\tesac\n\n");
+
+	s.push_str("\tlocal i cmd\n");
+	s.push_str("\tlocal -a pos\n");
+	s.push_str("\tfor ((i = 2; i < CURRENT; i++)); do\n");
+	s.push_str("\t\tcase \"${words[i]}\" in\n");
+	let _ = writeln!(s, "\t\t\t{}) ((i++));;", value_flags.join("|"));
+	s.push_str("\t\t\t-*) ;;\n");
+	s.push_str("\t\t\t*) pos+=(\"${words[i]}\");;\n");
+	s.push_str("\t\tesac\n");
+	s.push_str("\tdone\n\n");
+
+	s.push_str("\tif (( ${#pos} == 0 )); then\n");
+	s.push_str("\t\tif [[ $cur == -* ]]; then\n");
+	let _ = writeln!(s, "\t\t\tcompadd -- {}", flag_names(cli::GLOBAL_FLAGS).join(" "));
+	s.push_str("\t\telse\n");
+	let _ = writeln!(s, "\t\t\tcompadd -- {}", names(true).join(" "));
+	s.push_str("\t\t\t_files\n");
+	s.push_str("\t\tfi\n");
+	s.push_str("\t\treturn\n");
+	s.push_str("\tfi\n\n");
+
+	s.push_str("\tcmd=\"${pos[1]}\"\n");
+	s.push_str("\tcase \"$cmd\" in\n");
+	let _ = writeln!(s, "\t\t{}) ;;", names(true).join("|"));
+	s.push_str("\t\t*)\n");
+	s.push_str("\t\t\tcase ${#pos} in\n");
+	s.push_str("\t\t\t\t1) return;;\n");
+	let _ = writeln!(s, "\t\t\t\t2) compadd -- {}; return;;", names(false).join(" "));
+	s.push_str("\t\t\tesac\n");
+	s.push_str("\t\t\tcmd=\"${pos[3]}\";;\n");
+	s.push_str("\tesac\n\n");
+
+	s.push_str("\tcase \"$cmd\" in\n");
+	for cmd in cli::visible() {
+		let flags = flag_names(cmd.flags);
+		let positionals = match cmd.args {
+			Args::None => String::new(),
+			Args::Files => String::from("_files"),
+			_ => format!("compadd -- {}", positionals(cmd).join(" ")),
+		};
+		if flags.is_empty() && positionals.is_empty() {
+			continue;
+		}
+		let _ = writeln!(s, "\t\t{})", cmd.name);
+		s.push_str("\t\t\tif [[ $cur == -* ]]; then\n");
+		let _ = writeln!(s, "\t\t\t\tcompadd -- {}", flags.join(" "));
+		if !positionals.is_empty() {
+			s.push_str("\t\t\telse\n");
+			let _ = writeln!(s, "\t\t\t\t{}", positionals);
+		}
+		s.push_str("\t\t\tfi;;\n");
+	}
+	s.push_str("\tesac\n");
+	s.push_str("}\n\n");
+
+	s.push_str("if [ \"$funcstack[1]\" = \"_pakscmd\" ]; then\n");
+	s.push_str("\t_pakscmd \"$@\"\n");
+	s.push_str("else\n");
+	s.push_str("\tcompdef _pakscmd pakscmd\n");
+	s.push_str("fi\n");
+	s
+}
+
+//----------------------------------------------------------------
+
+fn fish() -> String {
+	let mut s = String::new();
+
+	s.push_str("function __pakscmd_pos\n");
+	s.push_str("\tset -l tokens (commandline -opc)\n");
+	s.push_str("\tset -e tokens[1]\n");
+	s.push_str("\tset -l skip 0\n");
+	s.push_str("\tfor token in $tokens\n");
+	s.push_str("\t\tif test $skip -eq 1\n");
+	s.push_str("\t\t\tset skip 0\n");
+	let _ = writeln!(s, "\t\telse if contains -- $token {}", value_flags().join(" "));
+	s.push_str("\t\t\tset skip 1\n");
+	s.push_str("\t\telse if not string match -q -- '-*' $token\n");
+	s.push_str("\t\t\techo $token\n");
+	s.push_str("\t\tend\n");
+	s.push_str("\tend\n");
+	s.push_str("end\n\n");
+
+	s.push_str("function __pakscmd_state\n");
+	s.push_str("\tset -l pos (__pakscmd_pos)\n");
+	s.push_str("\tif test (count $pos) -eq 0\n");
+	s.push_str("\t\techo :start\n");
+	let _ = writeln!(s, "\telse if contains -- $pos[1] {}", names(true).join(" "));
+	s.push_str("\t\techo $pos[1]\n");
+	s.push_str("\telse if test (count $pos) -eq 1\n");
+	s.push_str("\t\techo :key\n");
+	s.push_str("\telse if test (count $pos) -eq 2\n");
+	s.push_str("\t\techo :command\n");
+	s.push_str("\telse\n");
+	s.push_str("\t\techo $pos[3]\n");
+	s.push_str("\tend\n");
+	s.push_str("end\n\n");
+
+	s.push_str("function __pakscmd_is\n");
+	s.push_str("\ttest (__pakscmd_state) = $argv[1]\n");
+	s.push_str("end\n\n");
+
+	for flag in cli::GLOBAL_FLAGS {
+		fish_flag(&mut s, ":start", flag);
+	}
+	for cmd in cli::visible().filter(|cmd| cmd.is_standalone()) {
+		let _ = writeln!(s, "complete -c pakscmd -n '__pakscmd_is :start' -a {} -d '{}'", cmd.name, fish_escape(cmd.about));
+	}
+	s.push_str("complete -c pakscmd -n '__pakscmd_is :key' -f\n");
+	for cmd in cli::visible().filter(|cmd| !cmd.is_standalone()) {
+		let _ = writeln!(s, "complete -c pakscmd -n '__pakscmd_is :command' -f -a {} -d '{}'", cmd.name, fish_escape(cmd.about));
+	}
+	for cmd in cli::visible() {
+		for flag in cmd.flags {
+			fish_flag(&mut s, cmd.name, flag);
+		}
+		match cmd.args {
+			Args::None => {
+				let _ = writeln!(s, "complete -c pakscmd -n '__pakscmd_is {}' -f", cmd.name);
+			},
+			Args::Files => (),
+			Args::Commands | Args::Shells => {
+				let _ = writeln!(s, "complete -c pakscmd -n '__pakscmd_is {}' -f -a '{}'", cmd.name, positionals(cmd).join(" "));
+			},
+		}
+	}
+	s
+}
+
+fn fish_flag(s: &mut String, state: &str, flag: &Flag) {
+	let _ = write!(s, "complete -c pakscmd -n '__pakscmd_is {}'", state);
+	match flag.name.strip_prefix("--") {
+		Some(long) => { let _ = write!(s, " -l {}", long); },
+		None => { let _ = write!(s, " -s {}", flag.name.trim_start_matches('-')); },
+	}
+	if flag.takes_value() {
+		if flag.values.is_empty() {
+			s.push_str(" -r");
+		}
+		else {
+			let _ = write!(s, " -x -a '{}'", flag.values.join(" "));
+		}
+	}
+	let _ = writeln!(s, " -d '{}'", fish_escape(flag.about));
+}
+
+fn fish_escape(text: &str) -> String {
+	text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+//----------------------------------------------------------------
+
+fn powershell() -> String {
+	let mut s = String::new();
+
+	s.push_str("Register-ArgumentCompleter -Native -CommandName pakscmd -ScriptBlock {\n");
+	s.push_str("\tparam($wordToComplete, $commandAst, $cursorPosition)\n\n");
+
+	let _ = writeln!(s, "\t$valueFlags = {}", ps_array(&value_flags()));
+	s.push_str("\t$values = @{\n");
+	for flag in all_flags() {
+		if !flag.values.is_empty() {
+			let _ = writeln!(s, "\t\t'{}' = {}", flag.name, ps_array(flag.values));
+		}
+	}
+	s.push_str("\t}\n");
+	s.push_str("\t$flags = @{\n");
+	for cmd in cli::visible() {
+		if !cmd.flags.is_empty() {
+			let _ = writeln!(s, "\t\t'{}' = {}", cmd.name, ps_array(&flag_names(cmd.flags)));
+		}
+	}
+	s.push_str("\t}\n");
+	s.push_str("\t$positionals = @{\n");
+	for cmd in cli::visible() {
+		let positionals = positionals(cmd);
+		if !positionals.is_empty() {
+			let _ = writeln!(s, "\t\t'{}' = {}", cmd.name, ps_array(&positionals));
+		}
+	}
+	s.push_str("\t}\n");
+	let _ = writeln!(s, "\t$globalFlags = {}", ps_array(&flag_names(cli::GLOBAL_FLAGS)));
+	let _ = writeln!(s, "\t$standalone = {}", ps_array(&names(true)));
+	let _ = writeln!(s, "\t$archive = {}\n", ps_array(&names(false)));
+
+	s.push_str("\t$tokens = @($commandAst.CommandElements | Select-Object -Skip 1 | Where-Object { $_.Extent.EndOffset -lt $cursorPosition } | ForEach-Object { $_.ToString() })\n");
+	s.push_str("\t$pos = @()\n");
+	s.push_str("\t$skip = $false\n");
+	s.push_str("\tforeach ($token in $tokens) {\n");
+	s.push_str("\t\tif ($skip) { $skip = $false }\n");
+	s.push_str("\t\telseif ($valueFlags -contains $token) { $skip = $true }\n");
+	s.push_str("\t\telseif (-not $token.StartsWith('-')) { $pos += $token }\n");
+	s.push_str("\t}\n\n");
+
+	s.push_str("\t$candidates = @()\n");
+	s.push_str("\tif ($tokens.Count -gt 0 -and $valueFlags -contains $tokens[-1]) {\n");
+	s.push_str("\t\t$candidates = $values[$tokens[-1]]\n");
+	s.push_str("\t}\n");
+	s.push_str("\telse {\n");
+	s.push_str("\t\t$cmd = $null\n");
+	s.push_str("\t\tif ($pos.Count -eq 0) {\n");
+	s.push_str("\t\t\tif ($wordToComplete.StartsWith('-')) { $candidates = $globalFlags } else { $candidates = $standalone }\n");
+	s.push_str("\t\t}\n");
+	s.push_str("\t\telseif ($standalone -contains $pos[0]) { $cmd = $pos[0] }\n");
+	s.push_str("\t\telseif ($pos.Count -eq 2) { $candidates = $archive }\n");
+	s.push_str("\t\telseif ($pos.Count -gt 2) { $cmd = $pos[2] }\n");
+	s.push_str("\t\tif ($cmd) {\n");
+	s.push_str("\t\t\tif ($wordToComplete.StartsWith('-')) { $candidates = $flags[$cmd] } else { $candidates = $positionals[$cmd] }\n");
+	s.push_str("\t\t}\n");
+	s.push_str("\t}\n\n");
+
+	s.push_str("\t@($candidates) | Where-Object { $_ -and $_ -like \"$wordToComplete*\" } | ForEach-Object {\n");
+	s.push_str("\t\t[System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)\n");
+	s.push_str("\t}\n");
+	s.push_str("}\n");
+	s
+}
+
+fn ps_array(words: &[&str]) -> String {
+	let words: Vec<_> = words.iter().map(|word| format!("'{}'", word)).collect();
+	format!("@({})", words.join(", "))
+}
+
+#[test]
+fn test_completions() {
+	// Every script completes every command and flag, fish names the flags without their dashes
+	for (shell, script) in SHELLS.iter().zip([bash(), zsh(), fish(), powershell()]) {
+		for cmd in cli::visible() {
+			assert!(script.contains(cmd.name), "{} {}", shell, cmd.name);
+		}
+		for flag in all_flags() {
+			let name = match (*shell, flag.name.strip_prefix("--")) {
+				("fish", Some(long)) => format!("-l {}", long),
+				("fish", None) => format!("-s {}", &flag.name[1..]),
+				_ => flag.name.to_string(),
+			};
+			assert!(script.contains(&name), "{} {}", shell, flag.name);
+		}
+		for shell in SHELLS {
+			assert!(script.contains(shell));
+		}
+	}
+
+	// The command names are unique and flags accepted by multiple commands take the same value
+	let mut names: Vec<_> = cli::COMMANDS.iter().map(|cmd| cmd.name).collect();
+	names.sort_unstable();
+	names.dedup();
+	assert_eq!(names.len(), cli::COMMANDS.len());
+	for flag in cli::GLOBAL_FLAGS.iter().chain(cli::COMMANDS.iter().flat_map(|cmd| cmd.flags)) {
+		assert_eq!(cli::find_flag(flag.name).unwrap().value, flag.value, "{}", flag.name);
+	}
+	assert!(!value_flags().contains(&"--backup"));
+	assert!(value_flags().contains(&"--lang"));
+}
@@ -0,0 +1,824 @@
+/*!
+Implements the `copy` command.
+*/
+
+use std::{fs, path, sync::mpsc, sync::Mutex, thread};
+use std::collections::HashMap;
+use super::*;
+
+pub const HELP_COPY: &str = "\
+NAME
+    pakscmd-copy - Copies files to the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] copy [--follow-symlinks|--no-follow] [--exclude PATTERN].. [--map SRC=DEST].. [--transform EXT=NAME].. [--incremental] [--jobs N] [--bundle BYTES] [--mtime] [--sniff] [--strict] [--dry-run] <PATH> [FILE]..
+
+DESCRIPTION
+    Copies files to the PAKS archive.
+    Directories are copied recursively.
+
+    Files matching a gitignore-style PATTERN are not copied.
+    Patterns are also read from `.paksignore` files in the copied directories,
+    these patterns are relative to the directory containing the `.paksignore` file.
+
+    The in-archive layout can be changed by remapping path prefixes.
+    Paths relative to the copied directory starting with SRC are moved under DEST instead.
+    When multiple rules match, the rule with the longest SRC prefix is applied.
+
+    Files with the extension EXT are passed through the builtin transform NAME before being added.
+    Use `*` as the EXT to transform all files. The builtin transforms are:
+
+        minify-json  Removes insignificant whitespace from JSON.
+
+    Symbolic links are followed by default.
+    A symbolic link to a file which has already been copied is added as a link to the existing file.
+    A symbolic link to one of its own parent directories is skipped to avoid copying forever.
+    Symbolic links given directly as FILE arguments are always followed.
+
+    With --incremental a cache of the copied files is kept next to the PAKS archive in PAKFILE.cache.
+    Files which are unchanged since they were last copied are skipped.
+
+    Files are read, transformed and encrypted by N worker threads, by default one per CPU.
+
+    With --bundle files of at most BYTES bytes are bundled together into shared sections.
+    This saves space and speeds up reading folders of many tiny files.
+
+    With --mtime the modification time of every source file is recorded in the PAKS archive.
+    The recorded times are restored by extract, round-tripping an asset tree keeps its timestamps.
+
+    With --sniff the content types are assigned by inspecting the contents, see `pakscmd help add`.
+
+    When a file is copied to a path which is already taken, by another copied file or by an entry in the PAKS archive, it replaces the previous file.
+    These collisions are reported after copying, files copied again from the same source with --incremental are not collisions.
+    With --strict no path is replaced, the collisions are errors and the PAKS archive is left unchanged.
+
+    With --dry-run the files which would be copied and the resulting size of the PAKS archive are printed without writing.
+    The sizes are of the source files before any transforms, bundling is not simulated.
+
+ARGUMENTS
+    --follow-symlinks  Follow symbolic links (default).
+    --no-follow        Skip symbolic links.
+    --exclude PATTERN  Exclude files matching the pattern, may be repeated.
+    --map SRC=DEST     Remap the path prefix SRC to DEST, may be repeated.
+    --transform EXT=NAME
+                       Transform files with the extension EXT, may be repeated.
+    --incremental      Skip unchanged files using the cache.
+    --jobs N           Number of worker threads.
+    --bundle BYTES     Bundle files of at most BYTES bytes.
+    --mtime            Record the modification times of the files.
+    --sniff            Assign the content types by inspecting the contents.
+    --strict           Fail if a file is copied to a path which is already taken.
+    --dry-run          Print the changes without writing.
+    PATH               The destination directory in the PAKS archive.
+    FILE               Files or directories on the file system to copy.
+";
+
+pub fn copy(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let mut follow_symlinks = true;
+	let mut excludes = Vec::new();
+	let mut mapper = paks::pack::PrefixMapper::new();
+	let mut transforms = paks::pack::Transforms::new();
+	let mut jobs = None;
+	let mut bundle = None;
+	for &(flag, value) in &args.flags {
+		let value = value.unwrap_or("");
+		match flag.name {
+			"--follow-symlinks" => follow_symlinks = true,
+			"--no-follow" => follow_symlinks = false,
+			"--exclude" => excludes.extend(ignore::Pattern::parse(value)),
+			"--map" if mapper.add_rule(value).is_none() => return error!(OptionInvalid, "--map", "SRC=DEST", value),
+			"--transform" if transforms.add_rule(value).is_none() => return error!(OptionInvalid, "--transform", "EXT=NAME", value),
+			"--jobs" => match value.parse::<usize>() {
+				Ok(n) if n > 0 => jobs = Some(n),
+				_ => return error!(OptionInvalid, "--jobs", "N", value),
+			},
+			"--bundle" => match value.parse::<usize>() {
+				Ok(n) => bundle = Some(n),
+				_ => return error!(OptionInvalid, "--bundle", "BYTES", value),
+			},
+			_ => (),
+		}
+	}
+	let incremental = args.has("--incremental");
+	let mtime = args.has("--mtime");
+	let sniffer = if args.has("--sniff") { Some(paks::pack::Sniffer::builtin()) } else { None };
+	let strict = args.has("--strict");
+	let dry_run = args.has("--dry-run");
+
+	let args = &args.positional;
+	if args.len() < 1 {
+		return error!(Expecting, "<PATH> [FILE]..");
+	}
+	else if args.len() == 1 {
+		return;
+	}
+	let base_path = args[0];
+
+	let mut edit = match dryrun::open(file, key, dry_run) {
+		Some(edit) => edit,
+		None => return,
+	};
+
+	let cache_path = paks::pack::PackCache::sidecar_path(file);
+	let cache = if incremental {
+		match paks::pack::PackCache::load(&cache_path) {
+			Ok(cache) => Some(cache),
+			Err(err) => return error!(Reading, cache_path.display(), err),
+		}
+	}
+	else {
+		None
+	};
+
+	let jobs = jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+
+	// The workers read, transform and encrypt the files
+	// Only the main thread writes to the PAKS archive
+	let (job_tx, job_rx) = mpsc::sync_channel(jobs * 2);
+	let (result_tx, result_rx) = mpsc::channel();
+	let job_rx = Mutex::new(job_rx);
+
+	let (cache, collisions, sniffed) = thread::scope(|scope| {
+		for _ in 0..jobs {
+			let job_rx = &job_rx;
+			let result_tx = result_tx.clone();
+			let transforms = &transforms;
+			let sniffer = sniffer.as_ref();
+			scope.spawn(move || copy_worker(job_rx, result_tx, key, transforms, sniffer, bundle));
+		}
+		drop(result_tx);
+
+		let mut state = CopyState {
+			edit: &mut edit,
+			follow_symlinks,
+			mtime,
+			dry_run,
+			ancestors: Vec::new(),
+			files: HashMap::new(),
+			links: Vec::new(),
+			queued: HashMap::new(),
+			strict,
+			collisions: Vec::new(),
+			ignores: Vec::new(),
+			base_path,
+			mapper: &mapper,
+			transforms: &transforms,
+			cache,
+			job_tx: Some(job_tx),
+			result_rx,
+			key,
+			bundle: Vec::new(),
+			bundle_len: 0,
+			sniffed: Vec::new(),
+		};
+
+		let mut dest_path = String::new();
+
+		for src_path in &args[1..] {
+			// Deep asset trees exceed the path length limit on Windows
+			let src_path = longpath::long_path(path::Path::new(src_path));
+			let src_path = src_path.as_path();
+
+			// Command-line excludes are relative to the copied directory
+			let base = if src_path.is_dir() { src_path } else { src_path.parent().unwrap_or(src_path) };
+			state.ignores.push(ignore::Patterns::new(base, excludes.clone()));
+
+			let dest_len = dest_path.len();
+			copy_rec(&mut state, src_path, &mut dest_path, true);
+			dest_path.truncate(dest_len);
+
+			state.ignores.clear();
+		}
+
+		// Wait for the workers to finish
+		state.job_tx = None;
+		while let Ok(result) = state.result_rx.recv() {
+			copy_result(&mut state, result);
+		}
+		copy_bundle(&mut state);
+
+		// Link duplicate files after all the files have been written
+		for (archive_path, existing) in std::mem::take(&mut state.links) {
+			match state.edit.find_file(existing.as_bytes()) {
+				Some(&desc) => {
+					if let Err(err) = state.edit.create_link(archive_path.as_bytes(), &desc) {
+						error!(Linking, archive_path, err);
+					}
+					else if state.dry_run {
+						info!(DryRunLink, archive_path, existing);
+					}
+				},
+				None => error!(LinkNotCopied, archive_path, existing),
+			}
+		}
+
+		(state.cache.take(), std::mem::take(&mut state.collisions), std::mem::take(&mut state.sniffed))
+	});
+
+	// Report the files copied to a path which was already taken
+	for (archive_path, first, src_path) in &collisions {
+		match (first, strict) {
+			(Some(first), false) => warning!(CopyCollision, archive_path, first.display(), src_path.display()),
+			(Some(first), true) => error!(CopyCollisionStrict, archive_path, first.display(), src_path.display()),
+			(None, false) => warning!(CopyExists, archive_path, src_path.display()),
+			(None, true) => error!(CopyExistsStrict, archive_path, src_path.display()),
+		}
+	}
+	// Without finishing the PAKS archive the changes are discarded
+	if strict && !collisions.is_empty() {
+		return;
+	}
+
+	if dry_run {
+		return dryrun::report(file, &edit);
+	}
+
+	if let Some(sniffer) = &sniffer {
+		if let Err(err) = name_content_types(&mut edit, sniffer, &sniffed, key) {
+			return error!(Writing, String::from_utf8_lossy(paks::CONTENT_TYPES_PATH), err);
+		}
+	}
+
+	if let Err(err) = edit.finish(key) {
+		return error!(Writing, file, err);
+	}
+
+	if let Some(cache) = cache {
+		if let Err(err) = cache.save(&cache_path) {
+			error!(Writing, cache_path.display(), err);
+		}
+	}
+}
+
+struct CopyState<'a> {
+	edit: &'a mut paks::FileEditor,
+	follow_symlinks: bool,
+	// Record the modification times of the source files
+	mtime: bool,
+	// Allocate the sections of the files without reading or writing them
+	dry_run: bool,
+	// Canonical paths of the directories currently being copied to detect symlink cycles
+	ancestors: Vec<path::PathBuf>,
+	// Canonical paths of the files already copied and their destination path
+	files: HashMap<path::PathBuf, String>,
+	// Links to create to the destination path of files already copied
+	links: Vec<(String, String)>,
+	// Source path of the last file queued for every destination path, files replaced before they are written are dropped
+	queued: HashMap<String, path::PathBuf>,
+	// Never replace a path which is already taken, like CreateMode::CreateNew
+	strict: bool,
+	// Destination paths which were already taken, the previous source path or None for an entry in the PAKS archive, and the source path copied
+	collisions: Vec<(String, Option<path::PathBuf>, path::PathBuf)>,
+	// Exclude patterns from the command-line and the `.paksignore` files of the directories being copied
+	ignores: Vec<ignore::Patterns>,
+	base_path: &'a str,
+	mapper: &'a dyn paks::pack::PathMapper,
+	transforms: &'a paks::pack::Transforms,
+	// Cache of the previously copied files if copying incrementally
+	cache: Option<paks::pack::PackCache>,
+	// Queue of files for the workers, closed when all files are queued
+	job_tx: Option<mpsc::SyncSender<CopyJob>>,
+	result_rx: mpsc::Receiver<CopyResult>,
+	key: &'a paks::Key,
+	// Tiny files waiting to be bundled into a shared section
+	bundle: Vec<(CopyJob, Option<paks::hash::Digest>, Vec<u8>)>,
+	bundle_len: usize,
+	// Content types assigned by sniffing the files
+	sniffed: Vec<u32>,
+}
+
+impl<'a> CopyState<'a> {
+	// Maps the path relative to the copied directory to its path in the PAKS archive
+	fn archive_path(&self, rel_path: &str) -> Option<String> {
+		let rel_path = self.mapper.map_path(rel_path.as_bytes())?;
+		let rel_path = String::from_utf8(rel_path).ok()?;
+		let mut path = String::from(self.base_path);
+		if !path.is_empty() && !path.ends_with("/") && !rel_path.is_empty() {
+			path.push('/');
+		}
+		path.push_str(&rel_path);
+		Some(path)
+	}
+}
+
+fn copy_rec(state: &mut CopyState, src_path: &path::Path, dest_path: &mut String, root: bool) {
+	if dest_path.len() > 0 && !dest_path.ends_with("/") {
+		dest_path.push('/');
+	}
+
+	let is_dir = src_path.is_dir();
+	if !root && ignore::is_excluded(&state.ignores, src_path, is_dir) {
+		return;
+	}
+
+	// Apply the symlink policy
+	let is_symlink = match fs::symlink_metadata(src_path) {
+		Ok(meta) => meta.file_type().is_symlink(),
+		Err(err) => {
+			error!(Reading, src_path.display(), err);
+			return;
+		},
+	};
+	if is_symlink && !state.follow_symlinks && !root {
+		warning!(SkipSymlink, src_path.display());
+		return;
+	}
+
+	// Resolve the real path to detect duplicates and cycles
+	let real_path = match fs::canonicalize(src_path) {
+		Ok(real_path) => real_path,
+		Err(err) => {
+			error!(Resolving, src_path.display(), err);
+			return;
+		},
+	};
+
+	if src_path.is_file() {
+		// Never copy the ignore files themselves
+		if src_path.file_name() == Some(ignore::IGNORE_FILE.as_ref()) {
+			return;
+		}
+
+		// Extract the file name
+		let file_name = match src_path.file_name().and_then(|s| s.to_str()) {
+			Some(file_name) => file_name,
+			None => {
+				error!(InvalidFileName, src_path.display());
+				return;
+			},
+		};
+
+		// Construct destination path
+		dest_path.push_str(file_name);
+		let archive_path = match state.archive_path(dest_path) {
+			Some(archive_path) => archive_path,
+			None => return,
+		};
+
+		// Check if the path is already taken like CreateMode::CreateNew, copying again from the same source is not a collision
+		let recopy = state.cache.as_ref().and_then(|cache| cache.get(&archive_path)).is_some_and(|cached| cached.src == real_path);
+		let taken = match state.queued.get(&archive_path) {
+			Some(first) => Some(Some(first.clone())),
+			None if !recopy && state.edit.find_desc(archive_path.as_bytes()).is_some() => Some(None),
+			None => None,
+		};
+		if let Some(first) = taken {
+			state.collisions.push((archive_path.clone(), first, src_path.to_path_buf()));
+			if state.strict {
+				return;
+			}
+		}
+		state.queued.insert(archive_path.clone(), src_path.to_path_buf());
+
+		// Link to the existing file if this file has already been copied
+		if let Some(existing) = state.files.get(&real_path) {
+			state.links.push((archive_path, existing.clone()));
+			return;
+		}
+		state.files.insert(real_path.clone(), archive_path.clone());
+
+		// Check the cache if the file in the PAKS archive is still up to date
+		let stamp = paks::pack::SourceStamp::of(src_path).unwrap_or_default();
+		let transforms = state.transforms.names(&archive_path, 1);
+		let cached = state.cache.as_ref()
+			.and_then(|cache| cache.get(&archive_path))
+			.filter(|cached| cached.src == real_path && cached.transforms == transforms)
+			.filter(|cached| state.edit.find_file(archive_path.as_bytes()).is_some_and(|desc| cached.matches(desc)))
+			.cloned();
+		if cached.as_ref().is_some_and(|cached| cached.stamp == stamp) {
+			return copy_mtime(state, &archive_path, &stamp);
+		}
+
+		let job = CopyJob {
+			src_path: src_path.to_path_buf(),
+			real_path,
+			archive_path,
+			stamp,
+			transforms,
+			cached,
+			digest: state.cache.is_some(),
+			content_type: 1,
+		};
+		if state.dry_run {
+			return copy_dry_run(state, job);
+		}
+		copy_queue(state, job);
+	}
+	else if is_dir {
+		// Symbolic link pointing to one of its parent directories
+		if state.ancestors.contains(&real_path) {
+			warning!(SkipSymlinkCycle, src_path.display());
+			return;
+		}
+
+		if !root {
+			// Extract the directory name
+			let dir_name = match src_path.file_name().and_then(|s| s.to_str()) {
+				Some(dir_name) => dir_name,
+				None => {
+					error!(InvalidDirName, src_path.display());
+					return;
+				},
+			};
+
+			// Create the directory in the PAKS archive
+			dest_path.push_str(dir_name);
+			if let Some(archive_path) = state.archive_path(dest_path) {
+				if let Err(err) = state.edit.create_dir(archive_path.as_bytes()) {
+					error!(Creating, archive_path, err);
+					return;
+				}
+			}
+		}
+
+		// Recurse into the directory
+		let read_dir = match fs::read_dir(src_path) {
+			Ok(read_dir) => read_dir,
+			Err(err) => {
+				error!(Reading, src_path.display(), err);
+				return;
+			},
+		};
+
+		// Load the ignore file for this directory
+		let ignore_file = match ignore::Patterns::load(src_path) {
+			Ok(ignore_file) => ignore_file,
+			Err(err) => {
+				error!(Reading, src_path.join(ignore::IGNORE_FILE).display(), err);
+				None
+			},
+		};
+		let has_ignore_file = ignore_file.is_some();
+		state.ignores.extend(ignore_file);
+		state.ancestors.push(real_path);
+
+		for entry in read_dir {
+			let entry = match entry {
+				Ok(entry) => entry,
+				Err(err) => {
+					error!(Reading, src_path.display(), err);
+					continue;
+				},
+			};
+
+			let dest_len = dest_path.len();
+			copy_rec(state, &entry.path(), dest_path, false);
+			dest_path.truncate(dest_len);
+		}
+
+		state.ancestors.pop();
+		if has_ignore_file {
+			state.ignores.pop();
+		}
+	}
+	else {
+		warning!(SkipNotFile, src_path.display());
+	}
+}
+
+struct CopyJob {
+	src_path: path::PathBuf,
+	real_path: path::PathBuf,
+	archive_path: String,
+	stamp: paks::pack::SourceStamp,
+	// Names of the transforms applied to the file
+	transforms: String,
+	// Cache entry of the file if it exists in the PAKS archive
+	cached: Option<paks::pack::CacheEntry>,
+	// Whether to compute the digest for the cache
+	digest: bool,
+	// Content type of the file, assigned by the worker when sniffing
+	content_type: u32,
+}
+
+enum CopyResult {
+	// The file contents are unchanged, only update the cache
+	Unchanged(CopyJob, paks::pack::CacheEntry),
+	Encrypted(CopyJob, Option<paks::hash::Digest>, paks::pack::EncryptedData),
+	// The file is small enough to be bundled, its contents are encrypted by the main thread
+	Bundled(CopyJob, Option<paks::hash::Digest>, Vec<u8>),
+	Error(String),
+}
+
+// Size in bytes at which a bundle of tiny files is written
+const BUNDLE_SIZE: usize = 0x10000;
+
+// Allocates the section of the file as if it were copied
+fn copy_dry_run(state: &mut CopyState, job: CopyJob) {
+	let content_size = match fs::metadata(&job.src_path) {
+		Ok(metadata) => metadata.len(),
+		Err(err) => return error!(Reading, job.src_path.display(), err),
+	};
+	match state.edit.edit_file(job.archive_path.as_bytes()) {
+		Ok(mut edit_file) => {
+			edit_file.set_content(1, content_size as u32).allocate_data();
+			info!(DryRunCopy, job.src_path.display(), job.archive_path, content_size);
+		},
+		Err(err) => error!(Creating, job.archive_path, err),
+	}
+}
+
+// Queues the file for the workers
+fn copy_queue(state: &mut CopyState, mut job: CopyJob) {
+	// Write the finished files to keep memory usage bounded
+	while let Ok(result) = state.result_rx.try_recv() {
+		copy_result(state, result);
+	}
+
+	let job_tx = match &state.job_tx {
+		Some(job_tx) => job_tx.clone(),
+		None => return,
+	};
+	loop {
+		job = match job_tx.try_send(job) {
+			Ok(()) => return,
+			Err(mpsc::TrySendError::Full(job)) => job,
+			Err(mpsc::TrySendError::Disconnected(_)) => return,
+		};
+		// The queue is full, wait for a worker to finish a file
+		match state.result_rx.recv() {
+			Ok(result) => copy_result(state, result),
+			Err(_) => return,
+		}
+	}
+}
+
+fn copy_worker(job_rx: &Mutex<mpsc::Receiver<CopyJob>>, result_tx: mpsc::Sender<CopyResult>, key: &paks::Key, transforms: &paks::pack::Transforms, sniffer: Option<&paks::pack::Sniffer>, bundle: Option<usize>) {
+	loop {
+		let mut job = match job_rx.lock().unwrap().recv() {
+			Ok(job) => job,
+			Err(_) => return,
+		};
+
+		// Read the file contents
+		let data = match fs::read(&job.src_path) {
+			Ok(data) => data,
+			Err(err) => {
+				let _ = result_tx.send(CopyResult::Error(msg::render(msg::Level::Error, msg::Msg::Reading, &[&job.src_path.display(), &err])));
+				continue;
+			},
+		};
+
+		// The file was touched but its contents are unchanged
+		let digest = if job.digest { Some(paks::hash::sha256(&data)) } else { None };
+		if let Some(cached) = &job.cached {
+			if Some(cached.digest) == digest {
+				let entry = paks::pack::CacheEntry { stamp: job.stamp, ..cached.clone() };
+				let _ = result_tx.send(CopyResult::Unchanged(job, entry));
+				continue;
+			}
+		}
+
+		// Sniff the contents before they are transformed
+		if let Some(rule) = sniffer.and_then(|sniffer| sniffer.sniff(&data)) {
+			job.content_type = rule.content_type;
+		}
+
+		let data = match transforms.apply(&job.archive_path, 1, data) {
+			Ok(data) => data,
+			Err(err) => {
+				let _ = result_tx.send(CopyResult::Error(msg::render(msg::Level::Error, msg::Msg::Transforming, &[&job.src_path.display(), &err])));
+				continue;
+			},
+		};
+
+		if bundle.is_some_and(|bundle| data.len() <= bundle) {
+			let _ = result_tx.send(CopyResult::Bundled(job, digest, data));
+			continue;
+		}
+
+		let encrypted = match paks::pack::EncryptedData::encrypt(&data, key) {
+			Ok(encrypted) => encrypted,
+			Err(err) => {
+				let _ = result_tx.send(CopyResult::Error(msg::render(msg::Level::Error, msg::Msg::Creating, &[&job.archive_path, &err])));
+				continue;
+			},
+		};
+		let _ = result_tx.send(CopyResult::Encrypted(job, digest, encrypted));
+	}
+}
+
+// Writes the finished file to the PAKS archive
+fn copy_result(state: &mut CopyState, result: CopyResult) {
+	let job = match &result {
+		CopyResult::Unchanged(job, _) | CopyResult::Encrypted(job, _, _) | CopyResult::Bundled(job, _, _) => job,
+		CopyResult::Error(message) => return eprintln!("{}", message),
+	};
+	// Nothing is written once --strict fails
+	if state.strict && !state.collisions.is_empty() || copy_replaced(state, job) {
+		return;
+	}
+
+	let (job, digest, encrypted) = match result {
+		CopyResult::Unchanged(job, entry) => {
+			copy_mtime(state, &job.archive_path, &job.stamp);
+			if let Some(cache) = &mut state.cache {
+				cache.insert(job.archive_path, entry);
+			}
+			return;
+		},
+		CopyResult::Encrypted(job, digest, encrypted) => (job, digest, encrypted),
+		CopyResult::Bundled(job, digest, data) => {
+			// Check the path now so a single invalid path does not fail the whole bundle
+			if let Err(err) = paks::validate_path(job.archive_path.as_bytes()) {
+				return error!(Creating, job.archive_path, err);
+			}
+			state.bundle_len += data.len();
+			state.bundle.push((job, digest, data));
+			if state.bundle_len >= BUNDLE_SIZE {
+				copy_bundle(state);
+			}
+			return;
+		},
+		CopyResult::Error(_) => return,
+	};
+
+	let desc = match state.edit.edit_file(job.archive_path.as_bytes()) {
+		Ok(mut edit_file) => {
+			edit_file.set_content(job.content_type, encrypted.content_size).allocate_data();
+			match edit_file.write_encrypted(&encrypted.blocks, &encrypted.section) {
+				Ok(edit_file) => *edit_file.descriptor(),
+				Err(err) => return error!(Creating, job.archive_path, err),
+			}
+		},
+		Err(err) => return error!(Creating, job.archive_path, err),
+	};
+	copy_sniffed(state, &job);
+	copy_mtime(state, &job.archive_path, &job.stamp);
+	copy_cache(state, job, digest, &desc);
+}
+
+// Writes the tiny files waiting to be bundled to the PAKS archive
+fn copy_bundle(state: &mut CopyState) {
+	let mut bundle = std::mem::take(&mut state.bundle);
+	state.bundle_len = 0;
+	bundle.retain(|(job, _, _)| !copy_replaced(state, job));
+	if bundle.is_empty() {
+		return;
+	}
+
+	let files: Vec<_> = bundle.iter().map(|(job, _, data)| (job.archive_path.as_bytes(), &data[..])).collect();
+	if let Err(err) = state.edit.create_bundle(&files, state.key) {
+		for (job, _, _) in &bundle {
+			error!(Creating, job.archive_path, err);
+		}
+		return;
+	}
+
+	for (job, digest, _) in bundle {
+		copy_sniffed(state, &job);
+		copy_mtime(state, &job.archive_path, &job.stamp);
+		if let Some(&desc) = state.edit.find_file(job.archive_path.as_bytes()) {
+			copy_cache(state, job, digest, &desc);
+		}
+	}
+}
+
+// Whether a later file was copied to the same path, the last file copied is kept
+fn copy_replaced(state: &CopyState, job: &CopyJob) -> bool {
+	state.queued.get(&job.archive_path).is_some_and(|src_path| *src_path != job.src_path)
+}
+
+// Sets the sniffed content type of the file and remembers it to be named
+fn copy_sniffed(state: &mut CopyState, job: &CopyJob) {
+	if job.content_type == 1 {
+		return;
+	}
+	if let Some(id) = state.edit.find_id(job.archive_path.as_bytes()) {
+		if let Some(desc) = state.edit.resolve_mut(id) {
+			desc.content_type = job.content_type;
+		}
+	}
+	if !state.sniffed.contains(&job.content_type) {
+		state.sniffed.push(job.content_type);
+	}
+}
+
+// Records the modification time of the source file in the descriptor
+fn copy_mtime(state: &mut CopyState, archive_path: &str, stamp: &paks::pack::SourceStamp) {
+	if !state.mtime {
+		return;
+	}
+	let mtime = match u32::try_from(stamp.mtime / 1_000_000_000) {
+		Ok(secs) if secs > 0 => paks::Mtime(secs),
+		_ => return,
+	};
+	if let Some(id) = state.edit.find_id(archive_path.as_bytes()) {
+		if let Some(desc) = state.edit.resolve_mut(id) {
+			desc.set_extension(&mtime);
+		}
+	}
+}
+
+// Remembers the copied file in the cache
+fn copy_cache(state: &mut CopyState, job: CopyJob, digest: Option<paks::hash::Digest>, desc: &paks::Descriptor) {
+	if let (Some(cache), Some(digest)) = (&mut state.cache, digest) {
+		cache.insert(job.archive_path, paks::pack::CacheEntry {
+			src: job.real_path,
+			stamp: job.stamp,
+			digest,
+			transforms: job.transforms,
+			content_type: desc.content_type,
+			key_id: None,
+			nonce: desc.section.nonce,
+			mac: desc.section.mac,
+		});
+	}
+}
+
+#[test]
+fn test_copy_collisions() {
+	let dir = TestDir::new("copy_collisions");
+	dir.write_files(&[("one/x.txt", "one x"), ("one/old.txt", "one old"), ("two/x.txt", "two x"), ("two/y.txt", "two y")]);
+	let (ref one, ref two) = (dir.path("one"), dir.path("two"));
+	let ref key = paks::parse_key("2a").unwrap();
+	let ref file = dir.archive("archive.paks", key, &[("dest/old.txt", "archived")]);
+
+	// With --strict a path which is already taken leaves the PAKS archive unchanged
+	let before = fs::read(file).unwrap();
+	copy(file, "2a", &cli::parse(&["--strict", "dest", one]));
+	assert!(fs::read(file).unwrap() == before);
+	copy(file, "2a", &cli::parse(&["--strict", "other", one, two]));
+	assert!(fs::read(file).unwrap() == before);
+
+	// Without collisions --strict copies the files
+	copy(file, "2a", &cli::parse(&["--strict", "other", two]));
+	let reader = paks::FileReader::open(file, key).unwrap();
+	assert_eq!(reader.read(b"other/x.txt", key).unwrap(), b"two x");
+	assert_eq!(reader.read(b"other/y.txt", key).unwrap(), b"two y");
+
+	// Otherwise the collisions are reported and the last file copied to the path is kept
+	copy(file, "2a", &cli::parse(&["dest", one, two]));
+	let reader = paks::FileReader::open(file, key).unwrap();
+	assert_eq!(reader.read(b"dest/old.txt", key).unwrap(), b"one old");
+	assert_eq!(reader.read(b"dest/x.txt", key).unwrap(), b"two x");
+	assert_eq!(reader.read(b"dest/y.txt", key).unwrap(), b"two y");
+}
+
+#[test]
+fn test_copy_mtime() {
+	let dir = TestDir::new("copy_mtime");
+	let files = [("src/a.txt", "alpha"), ("src/sub/b.txt", "beta"), ("tiny/c.txt", "c")];
+	dir.write_files(&files);
+	let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+	for (path, _) in files {
+		fs::File::options().write(true).open(dir.join(path)).unwrap().set_modified(mtime).unwrap();
+	}
+	let (ref src, ref tiny, ref out) = (dir.path("src"), dir.path("tiny"), dir.path("out"));
+	let ref key = paks::parse_key("2a").unwrap();
+	let ref file = dir.archive("archive.paks", key, &[]);
+	let recorded = |path: &[u8]| paks::FileReader::open(file, key).unwrap().find_desc(path).unwrap().extension::<paks::Mtime>();
+
+	// Without --mtime nothing is recorded
+	copy(file, "2a", &cli::parse(&["--incremental", "dest", src]));
+	assert_eq!(recorded(b"dest/a.txt"), None);
+
+	// Unchanged files skipped by --incremental, copied files and bundled files are recorded
+	copy(file, "2a", &cli::parse(&["--incremental", "--mtime", "dest", src]));
+	assert_eq!(recorded(b"dest/a.txt"), Some(paks::Mtime(1_600_000_000)));
+	assert_eq!(recorded(b"dest/sub/b.txt"), Some(paks::Mtime(1_600_000_000)));
+	copy(file, "2a", &cli::parse(&["--mtime", "--bundle", "16", "bundled", tiny]));
+	assert_eq!(recorded(b"bundled/c.txt"), Some(paks::Mtime(1_600_000_000)));
+
+	// Extracting restores the recorded modification times
+	extract(file, "2a", &cli::parse(&[out]));
+	for path in ["dest/a.txt", "dest/sub/b.txt", "bundled/c.txt"] {
+		let path = dir.join("out").join(path);
+		assert_eq!(fs::metadata(path).unwrap().modified().unwrap(), mtime);
+	}
+}
+
+#[cfg(unix)]
+#[test]
+fn test_copy_symlinks() {
+	use std::os::unix::fs::symlink;
+
+	let dir = TestDir::new("copy_symlinks");
+	dir.write_files(&[("src/sub/file.txt", "contents")]);
+	let src = dir.join("src");
+	symlink(src.join("sub/file.txt"), src.join("alias.txt")).unwrap();
+	symlink(&src, src.join("sub/loop")).unwrap();
+	let ref src = dir.path("src");
+	let ref key = paks::parse_key("2a").unwrap();
+	let ref file = dir.archive("archive.paks", key, &[]);
+
+	// Following the symbolic links links the duplicate file and stops at the cycle
+	copy(file, "2a", &cli::parse(&["follow", src]));
+	let reader = paks::FileReader::open(file, key).unwrap();
+	let (file_desc, alias_desc) = (reader.find_file(b"follow/sub/file.txt").unwrap(), reader.find_file(b"follow/alias.txt").unwrap());
+	assert_eq!(file_desc.section, alias_desc.section);
+	assert_eq!(reader.read(b"follow/alias.txt", key).unwrap(), b"contents");
+	assert!(reader.find_desc(b"follow/sub/loop").is_none());
+
+	// Without following the symbolic links are skipped
+	copy(file, "2a", &cli::parse(&["--no-follow", "skip", src]));
+	let reader = paks::FileReader::open(file, key).unwrap();
+	assert_eq!(reader.read(b"skip/sub/file.txt", key).unwrap(), b"contents");
+	assert!(reader.find_desc(b"skip/alias.txt").is_none());
+	assert!(reader.find_desc(b"skip/sub/loop").is_none());
+}
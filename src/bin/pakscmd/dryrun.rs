@@ -0,0 +1,55 @@
+/*!
+Implements the `--dry-run` option of the commands which edit the PAKS archive.
+
+The edits are simulated on a read-only editor: the directory is edited and sections are allocated but no data is written.
+Finally the size of the PAKS archive is projected from the editor instead of finishing it.
+*/
+
+use paks::layout;
+use super::*;
+
+/// Opens the PAKS archive for editing, read-only when simulating the edits.
+pub fn open(file: &str, key: &paks::Key, dry_run: bool) -> Option<paks::FileEditor> {
+	let result = if dry_run { paks::FileEditor::read_only(file, key) } else { paks::FileEditor::open(file, key) };
+	match result {
+		Ok(edit) => Some(edit),
+		Err(err) => {
+			error!(Opening, file, err);
+			None
+		},
+	}
+}
+
+/// Size in bytes of the PAKS archive written with the high mark, descriptors and key slots.
+///
+/// The directory is followed by a copy of the header and the key slots.
+pub fn projected_len(high_mark: u32, descriptors: usize, key_slots: usize) -> u64 {
+	let high_mark = u32::max(high_mark, layout::HEADER_BLOCKS as u32) as u64;
+	let dir_len = (descriptors * layout::DESCRIPTOR_BLOCKS) as u64;
+	let slots_len = if key_slots == 0 { 0 } else { (key_slots * layout::KEY_SLOT_BLOCKS + 1) as u64 };
+	(high_mark + dir_len + layout::HEADER_BLOCKS as u64 + slots_len) * layout::BLOCK_SIZE as u64
+}
+
+/// Reports the size of the PAKS archive if the simulated edits were finished.
+pub fn report(file: &str, edit: &paks::FileEditor) {
+	report_len(file, projected_len(edit.high_mark(), edit.as_ref().len(), edit.key_slots().len()));
+}
+
+/// Reports the size of the PAKS archive after the simulated edits.
+pub fn report_len(file: &str, new_len: u64) {
+	let old_len = match fs::metadata(file) {
+		Ok(metadata) => metadata.len(),
+		Err(err) => return error!(Reading, file, err),
+	};
+	let growth = if new_len >= old_len { format!("+{}", new_len - old_len) } else { format!("-{}", old_len - new_len) };
+	info!(DryRunSize, file, old_len, new_len, growth);
+}
+
+#[test]
+fn test_projected_len() {
+	// An empty PAKS archive is its header followed by the copy of the header
+	assert_eq!(projected_len(0, 0, 0), 160);
+	assert_eq!(projected_len(5, 0, 0), 160);
+	assert_eq!(projected_len(7, 2, 0), (7 + 16 + 5) * 16);
+	assert_eq!(projected_len(7, 2, 1), (7 + 16 + 5 + 4) * 16);
+}
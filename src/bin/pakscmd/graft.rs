@@ -0,0 +1,203 @@
+/*!
+Implements the `graft` command.
+*/
+
+use std::collections::HashMap;
+use super::*;
+
+pub const HELP_GRAFT: &str = "\
+NAME
+    pakscmd-graft - Copies a subtree from another PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] graft <OTHER> <OTHER_KEY> <SRC_DIR> <DEST_DIR>
+
+DESCRIPTION
+    Copies the directory SRC_DIR of the other PAKS archive into this PAKS archive under DEST_DIR.
+    The files are decrypted with the key of the other PAKS archive and encrypted with the key of this PAKS archive.
+    The structure of the subtree is preserved, including empty directories, content types, plaintext files and links.
+    Existing files at the destination paths are replaced, existing directories are not replaced by files.
+
+ARGUMENTS
+    OTHER      Path to the other PAKS archive.
+    OTHER_KEY  The key of the other PAKS archive, or a user key with a key slot in it.
+    SRC_DIR    Path to the directory in the other PAKS archive, empty for the whole PAKS archive.
+    DEST_DIR   Path to the directory in this PAKS archive, empty for the root.
+";
+
+pub fn graft(file: &str, key: &str, args: &cli::Matches) {
+	let (other, other_key, src_dir, dest_dir) = match args.positional[..] {
+		[other, other_key, src_dir, dest_dir] => (other, other_key, src_dir.trim_end_matches('/'), dest_dir.trim_end_matches('/')),
+		_ => return error!(Expecting, "<OTHER> <OTHER_KEY> <SRC_DIR> <DEST_DIR>"),
+	};
+
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+	let ref other_key = match parse_key(&unlock_key(other, other_key)) {
+		Some(other_key) => other_key,
+		None => return,
+	};
+
+	let reader = match paks::FileReader::open(other, other_key) {
+		Ok(reader) => reader,
+		Err(err) => return error!(Opening, other, err),
+	};
+	let children = match reader.get_children(src_dir.as_bytes()) {
+		Some(children) => children,
+		None => return error!(DirNotFound, src_dir),
+	};
+
+	let mut edit = match paks::FileEditor::open(file, key) {
+		Ok(edit) => edit,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	if !dest_dir.is_empty() {
+		if let Err(err) = edit.create_dir(dest_dir.as_bytes()) {
+			return error!(Creating, dest_dir, err);
+		}
+	}
+
+	let mut state = Graft {
+		reader: &reader,
+		other_key,
+		edit: &mut edit,
+		key,
+		links: HashMap::new(),
+	};
+	state.dir(&mut dest_dir.as_bytes().to_vec(), children);
+
+	if let Err(err) = edit.finish(key) {
+		error!(Writing, file, err);
+	}
+}
+
+struct Graft<'a> {
+	reader: &'a paks::FileReader,
+	other_key: &'a paks::Key,
+	edit: &'a mut paks::FileEditor,
+	key: &'a paks::Key,
+	// Maps the sections of the other PAKS archive to the files copied from them to preserve links
+	links: HashMap<(u32, u32, u32), paks::Descriptor>,
+}
+
+impl<'a> Graft<'a> {
+	fn dir(&mut self, path: &mut Vec<u8>, children: &[paks::Descriptor]) {
+		let mut i = 0;
+		while i < children.len() {
+			let desc = &children[i];
+			i += 1;
+
+			// The archive metadata of the other PAKS archive is not grafted
+			if desc.is_file() && std::ptr::eq(children, self.reader.as_ref()) && paks::is_metadata_path(desc.name()) {
+				continue;
+			}
+
+			let path_len = path.len();
+			if path_len > 0 {
+				path.push(b'/');
+			}
+			path.extend_from_slice(desc.name());
+
+			if desc.is_dir() {
+				let end = usize::min(children.len(), i + desc.content_size as usize);
+				match self.edit.create_dir(path) {
+					Ok(()) => self.dir(path, &children[i..end]),
+					Err(err) => error!(Creating, String::from_utf8_lossy(path), err),
+				}
+				i = end;
+			}
+			else {
+				self.file(path, desc);
+			}
+
+			path.truncate(path_len);
+		}
+	}
+
+	fn file(&mut self, path: &[u8], desc: &paks::Descriptor) {
+		let path_str = String::from_utf8_lossy(path);
+
+		// Replacing a directory would orphan its children
+		if self.edit.find_desc(path).is_some_and(|desc| desc.is_dir()) {
+			return error!(GraftDirExists, path_str);
+		}
+
+		// Link to the file already copied from the same section, inline files have no section
+		let link_key = if desc.is_inline() { None } else { Some((desc.section.offset, desc.section.size, desc.data_offset())) };
+		if let Some(linked) = link_key.and_then(|link_key| self.links.get(&link_key)) {
+			if let Err(err) = self.edit.create_link(path, linked) {
+				error!(Linking, path_str, err);
+			}
+			return;
+		}
+
+		let data = match self.reader.read_data(desc, self.other_key) {
+			Ok(data) => data,
+			Err(err) => return error!(Reading, path_str, err),
+		};
+		let result = self.edit.edit_file(path).map_err(io::Error::from).and_then(|mut edit_file| {
+			edit_file.set_content(desc.content_type, data.len() as u32).set_plaintext(desc.is_plaintext());
+			edit_file.allocate_data().write_data(&data, self.key).map(|edit_file| *edit_file.descriptor())
+		});
+		match result {
+			Ok(copied) => {
+				if let Some(link_key) = link_key {
+					self.links.insert(link_key, copied);
+				}
+			},
+			Err(err) => error!(Creating, path_str, err),
+		}
+	}
+}
+
+#[test]
+fn test_graft() {
+	let dir = TestDir::new("graft");
+	let ref other = dir.path("other.paks");
+	let ref key = paks::parse_key("2a").unwrap();
+	let ref other_key = paks::parse_key("3b").unwrap();
+
+	let mut edit = paks::FileEditor::create_new(other, other_key).unwrap();
+	edit.create_file(b"src/a", b"alpha", other_key).unwrap();
+	edit.create_file(b"src/sub/b", b"beta", other_key).unwrap();
+	edit.create_file(b"src/clash/c", b"gamma", other_key).unwrap();
+	edit.create_dir(b"src/empty").unwrap();
+	edit.create_file(b"src/outside", b"outside", other_key).unwrap();
+	edit.create_file(b"outside", b"outside", other_key).unwrap();
+	let a = *edit.find_file(b"src/a").unwrap();
+	edit.create_link(b"src/link", &a).unwrap();
+	edit.edit_file(b"src/plain").unwrap().set_content(7, 5).set_plaintext(true).allocate_data().write_data(b"plain", other_key).unwrap();
+	edit.finish(other_key).unwrap();
+
+	// The destination has files colliding with a grafted file and a grafted directory, and a directory colliding with a grafted file
+	let ref file = dir.archive("archive.paks", key, &[("dest/a", "replaced"), ("dest/clash", "replaced"), ("dest/outside/kept", "kept"), ("keep", "keep")]);
+
+	graft(file, "2a", &cli::parse(&[other, "3b", "src/", "dest"]));
+
+	let reader = paks::FileReader::open(file, key).unwrap();
+	assert_eq!(reader.read(b"dest/a", key).unwrap(), b"alpha");
+	assert_eq!(reader.read(b"dest/sub/b", key).unwrap(), b"beta");
+	assert!(reader.get_children(b"dest/empty").unwrap().is_empty());
+	assert_eq!(reader.read(b"keep", key).unwrap(), b"keep");
+	assert!(reader.find_desc(b"outside").is_none());
+
+	// Colliding files are replaced, a colliding directory is kept
+	assert_eq!(reader.read(b"dest/clash/c", key).unwrap(), b"gamma");
+	assert_eq!(reader.read(b"dest/outside/kept", key).unwrap(), b"kept");
+
+	// Links, plaintext files and content types are preserved
+	let link = reader.find_file(b"dest/link").unwrap();
+	assert_eq!(link.section, reader.find_file(b"dest/a").unwrap().section);
+	let plain = reader.find_file(b"dest/plain").unwrap();
+	assert!(plain.is_plaintext());
+	assert_eq!(plain.content_type, 7);
+	assert_eq!(reader.read(b"dest/plain", key).unwrap(), b"plain");
+
+	// A missing source directory changes nothing
+	let before = fs::read(file).unwrap();
+	graft(file, "2a", &cli::parse(&[other, "3b", "missing", "dest"]));
+	assert!(fs::read(file).unwrap() == before);
+}
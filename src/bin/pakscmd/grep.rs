@@ -0,0 +1,318 @@
+/*!
+Implements the `grep` command.
+
+Supported regular expression syntax:
+
+* `.` matches any byte, `[abc]`, `[a-z]` and `[^abc]` match a single byte from the set.
+* `\d`, `\w` and `\s` match a digit, word or whitespace byte, `\xHH` matches the byte with hex value HH.
+* `\n`, `\r` and `\t` match the control characters, any other escaped byte matches itself.
+* `*`, `+` and `?` repeat the preceding item, as many times as possible.
+* `^` and `$` match at the start and end of a line.
+*/
+
+use super::*;
+
+pub const HELP_GREP: &str = "\
+NAME
+    pakscmd-grep - Searches files in the PAKS archive for a pattern.
+
+SYNOPSIS
+    pakscmd [..] grep [-E|--hex] [-i] <PATTERN> [PATH]..
+
+DESCRIPTION
+    Decrypts the files in the PAKS archive and searches them for the pattern.
+    Directories are searched recursively.
+    Without any PATH arguments the entire PAKS archive is searched.
+
+    Every match is printed as the path of the file in the PAKS archive followed by the byte offset of the match.
+
+    By default the PATTERN is searched for literally.
+    With -E the PATTERN is a regular expression supporting `.`, `[...]`, `*`, `+`, `?`, `^`, `$`
+    and the escapes `\\d`, `\\w`, `\\s` and `\\xHH`.
+
+ARGUMENTS
+    -E       The PATTERN is a regular expression.
+    --hex    The PATTERN is a sequence of bytes encoded in hex.
+    -i       Ignore ASCII case distinctions.
+    PATTERN  The pattern to search for.
+    PATH     Path to the file or directory in the PAKS archive to search.
+";
+
+pub fn grep(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let (pattern, paths) = match args.positional[..] {
+		[pattern, ref paths @ ..] => (pattern, paths),
+		_ => return error!(Expecting, "<PATTERN>"),
+	};
+
+	let result = if args.has("--hex") {
+		Regex::hex(pattern)
+	}
+	else if args.has("-E") {
+		Regex::parse(pattern.as_bytes())
+	}
+	else {
+		Ok(Regex::literal(pattern.as_bytes()))
+	};
+	let mut regex = match result {
+		Ok(regex) => regex,
+		Err(err) => return error!(InvalidPattern, pattern, err),
+	};
+	regex.nocase = args.has("-i");
+
+	let reader = match paks::FileReader::open(file, key) {
+		Ok(reader) => reader,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	let mut grep_file = |path: &[u8], desc: &paks::Descriptor| grep_file(&reader, &regex, path, desc, key);
+	if paths.is_empty() {
+		walk_files(&mut Vec::new(), reader.as_ref(), &mut grep_file);
+	}
+
+	for &path in paths {
+		walk_path(&reader, path, &mut grep_file);
+	}
+}
+
+fn grep_file(reader: &paks::FileReader, regex: &Regex, path: &[u8], desc: &paks::Descriptor, key: &paks::Key) {
+	let data = match reader.read_data(desc, key) {
+		Ok(data) => data,
+		Err(err) => return error!(Reading, String::from_utf8_lossy(path), err),
+	};
+
+	let path = String::from_utf8_lossy(path);
+	let mut start = 0;
+	while let Some((offset, end)) = regex.find(&data, start) {
+		info!(GrepMatch, path, offset);
+		start = end;
+	}
+}
+
+//----------------------------------------------------------------
+
+#[derive(Clone, Debug)]
+enum Atom {
+	Byte(u8),
+	Any,
+	Class(Box<[bool; 256]>),
+	LineStart,
+	LineEnd,
+}
+
+#[derive(Clone, Debug)]
+struct Piece {
+	atom: Atom,
+	min: usize,
+	max: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Regex {
+	pieces: Vec<Piece>,
+	pub nocase: bool,
+}
+
+impl Regex {
+	pub fn literal(pattern: &[u8]) -> Regex {
+		let pieces = pattern.iter().map(|&byte| Piece { atom: Atom::Byte(byte), min: 1, max: 1 }).collect();
+		Regex { pieces, nocase: false }
+	}
+
+	pub fn hex(pattern: &str) -> Result<Regex, &'static str> {
+		let digits: Vec<u8> = pattern.bytes().filter(|chr| !chr.is_ascii_whitespace()).collect();
+		let pairs = digits.chunks_exact(2);
+		if digits.is_empty() || !pairs.remainder().is_empty() {
+			return Err("expecting pairs of hex digits");
+		}
+		let mut bytes = Vec::new();
+		for pair in pairs {
+			match (hex_digit(pair[0]), hex_digit(pair[1])) {
+				(Some(hi), Some(lo)) => bytes.push(hi << 4 | lo),
+				_ => return Err("invalid hex digit"),
+			}
+		}
+		Ok(Regex::literal(&bytes))
+	}
+
+	pub fn parse(mut pattern: &[u8]) -> Result<Regex, &'static str> {
+		let mut pieces: Vec<Piece> = Vec::new();
+		while let Some(&chr) = pattern.first() {
+			pattern = &pattern[1..];
+			let atom = match chr {
+				b'*' | b'+' | b'?' => {
+					let piece = match pieces.last_mut() {
+						Some(piece) if piece.min == 1 && piece.max == 1 && !matches!(piece.atom, Atom::LineStart | Atom::LineEnd) => piece,
+						_ => return Err("nothing to repeat"),
+					};
+					match chr {
+						b'*' => { piece.min = 0; piece.max = usize::MAX; },
+						b'+' => { piece.max = usize::MAX; },
+						_ => { piece.min = 0; },
+					}
+					continue;
+				},
+				b'.' => Atom::Any,
+				b'^' => Atom::LineStart,
+				b'$' => Atom::LineEnd,
+				b'[' => {
+					let end = match pattern.iter().skip(1).position(|&chr| chr == b']') {
+						Some(end) => end + 1,
+						None => return Err("unclosed character class"),
+					};
+					let class = class_parse(&pattern[..end])?;
+					pattern = &pattern[end + 1..];
+					Atom::Class(class)
+				},
+				b'\\' => {
+					let (atom, rest) = escape_parse(pattern)?;
+					pattern = rest;
+					atom
+				},
+				chr => Atom::Byte(chr),
+			};
+			pieces.push(Piece { atom, min: 1, max: 1 });
+		}
+		if pieces.is_empty() {
+			return Err("empty pattern");
+		}
+		Ok(Regex { pieces, nocase: false })
+	}
+
+	/// Finds the first non-empty match at or after the start offset.
+	pub fn find(&self, text: &[u8], start: usize) -> Option<(usize, usize)> {
+		(start..text.len()).find_map(|offset| {
+			match self.match_at(&self.pieces, text, offset) {
+				Some(end) if end > offset => Some((offset, end)),
+				_ => None,
+			}
+		})
+	}
+
+	fn match_at(&self, pieces: &[Piece], text: &[u8], pos: usize) -> Option<usize> {
+		let (piece, rest) = match pieces {
+			[] => return Some(pos),
+			[piece, rest @ ..] => (piece, rest),
+		};
+		match piece.atom {
+			Atom::LineStart => {
+				if pos == 0 || text[pos - 1] == b'\n' { self.match_at(rest, text, pos) } else { None }
+			},
+			Atom::LineEnd => {
+				if pos == text.len() || text[pos] == b'\n' || text[pos] == b'\r' { self.match_at(rest, text, pos) } else { None }
+			},
+			_ => {
+				// Greedily match as many as possible then backtrack
+				let mut count = 0;
+				while count < piece.max && pos + count < text.len() && self.atom_match(&piece.atom, text[pos + count]) {
+					count += 1;
+				}
+				if count < piece.min {
+					return None;
+				}
+				(piece.min..=count).rev().find_map(|count| self.match_at(rest, text, pos + count))
+			},
+		}
+	}
+
+	fn atom_match(&self, atom: &Atom, byte: u8) -> bool {
+		match atom {
+			Atom::Byte(expected) if self.nocase => expected.eq_ignore_ascii_case(&byte),
+			Atom::Byte(expected) => *expected == byte,
+			Atom::Any => true,
+			Atom::Class(class) if self.nocase => class[byte.to_ascii_lowercase() as usize] || class[byte.to_ascii_uppercase() as usize],
+			Atom::Class(class) => class[byte as usize],
+			Atom::LineStart | Atom::LineEnd => false,
+		}
+	}
+}
+
+fn hex_digit(chr: u8) -> Option<u8> {
+	(chr as char).to_digit(16).map(|digit| digit as u8)
+}
+
+fn escape_parse(pattern: &[u8]) -> Result<(Atom, &[u8]), &'static str> {
+	let class = |f: fn(&u8) -> bool| {
+		let mut class = Box::new([false; 256]);
+		for byte in 0..=255u8 {
+			class[byte as usize] = f(&byte);
+		}
+		Atom::Class(class)
+	};
+	let atom = match pattern {
+		[] => return Err("trailing backslash"),
+		[b'x', hi, lo, rest @ ..] => match (hex_digit(*hi), hex_digit(*lo)) {
+			(Some(hi), Some(lo)) => return Ok((Atom::Byte(hi << 4 | lo), rest)),
+			_ => return Err("invalid hex escape"),
+		},
+		[b'x', ..] => return Err("invalid hex escape"),
+		[b'd', ..] => class(u8::is_ascii_digit),
+		[b'w', ..] => class(|&byte| byte.is_ascii_alphanumeric() || byte == b'_'),
+		[b's', ..] => class(u8::is_ascii_whitespace),
+		[b'n', ..] => Atom::Byte(b'\n'),
+		[b'r', ..] => Atom::Byte(b'\r'),
+		[b't', ..] => Atom::Byte(b'\t'),
+		[chr, ..] => Atom::Byte(*chr),
+	};
+	Ok((atom, &pattern[1..]))
+}
+
+fn class_parse(class: &[u8]) -> Result<Box<[bool; 256]>, &'static str> {
+	let (negate, mut class) = match class {
+		[b'^', class @ ..] => (true, class),
+		_ => (false, class),
+	};
+	let mut set = Box::new([false; 256]);
+	while let Some(&first) = class.first() {
+		match class {
+			[lo, b'-', hi, rest @ ..] => {
+				if lo > hi {
+					return Err("invalid character class range");
+				}
+				for byte in *lo..=*hi {
+					set[byte as usize] = true;
+				}
+				class = rest;
+			},
+			[_, rest @ ..] => {
+				set[first as usize] = true;
+				class = rest;
+			},
+			[] => unreachable!(),
+		}
+	}
+	if negate {
+		for found in set.iter_mut() {
+			*found = !*found;
+		}
+	}
+	Ok(set)
+}
+
+#[test]
+fn test_regex() {
+	let find = |pattern: &[u8], text: &[u8]| Regex::parse(pattern).unwrap().find(text, 0);
+	assert_eq!(find(b"b+", b"abbbc"), Some((1, 4)));
+	assert_eq!(find(b"a.c", b"xxabc"), Some((2, 5)));
+	assert_eq!(find(b"^id", b"x id\nid"), Some((5, 7)));
+	assert_eq!(find(b"id$", b"id x\nid"), Some((5, 7)));
+	assert_eq!(find(b"[0-9]+x?", b"ab123c"), Some((2, 5)));
+	assert_eq!(find(b"[^a-z]", b"ab1"), Some((2, 3)));
+	assert_eq!(find(b"\\d\\d", b"a1b22"), Some((3, 5)));
+	assert_eq!(find(b"\\x41\\.", b"xA.A"), Some((1, 3)));
+	assert_eq!(find(b"a*", b"bbb"), None);
+	assert!(Regex::parse(b"*a").is_err());
+	assert!(Regex::parse(b"[ab").is_err());
+
+	let mut regex = Regex::literal(b"Asset");
+	assert_eq!(regex.find(b"an asset", 0), None);
+	regex.nocase = true;
+	assert_eq!(regex.find(b"an asset", 0), Some((3, 8)));
+
+	assert_eq!(Regex::hex("00 ff").unwrap().find(b"a\x00\xff", 0), Some((1, 3)));
+	assert!(Regex::hex("0").is_err());
+}
@@ -0,0 +1,203 @@
+/*!
+Gitignore-style patterns used to exclude files when copying.
+
+Supported syntax:
+
+* Blank lines and lines starting with `#` are ignored.
+* A leading `!` negates the pattern, re-including a previously excluded path.
+* A trailing `/` only matches directories.
+* A pattern containing a `/` elsewhere is matched against the path relative to the ignore file, otherwise it matches the file name at any depth.
+* `*` matches anything except `/`, `?` matches a single character except `/`, `**` matches anything including `/`.
+* `[abc]`, `[a-z]` and `[!abc]` match a single character from the set.
+*/
+
+use std::{fs, io, path};
+
+pub const IGNORE_FILE: &str = ".paksignore";
+
+#[derive(Clone, Debug)]
+pub struct Pattern {
+	glob: Vec<u8>,
+	negate: bool,
+	dir_only: bool,
+	anchored: bool,
+}
+
+impl Pattern {
+	pub fn parse(line: &str) -> Option<Pattern> {
+		let mut line = line.trim_end();
+		if line.is_empty() || line.starts_with('#') {
+			return None;
+		}
+
+		let negate = line.starts_with('!');
+		if negate {
+			line = &line[1..];
+		}
+
+		let dir_only = line.ends_with('/');
+		let line = line.trim_end_matches('/');
+
+		let anchored = line.contains('/');
+		let line = line.trim_start_matches('/');
+		if line.is_empty() {
+			return None;
+		}
+
+		let glob = line.as_bytes().to_vec();
+		Some(Pattern { glob, negate, dir_only, anchored })
+	}
+
+	fn matches(&self, rel_path: &[u8], is_dir: bool) -> bool {
+		if self.dir_only && !is_dir {
+			return false;
+		}
+		if self.anchored {
+			glob_match(&self.glob, rel_path)
+		}
+		else {
+			let name_start = rel_path.iter().rposition(|&chr| chr == b'/').map(|i| i + 1).unwrap_or(0);
+			glob_match(&self.glob, &rel_path[name_start..])
+		}
+	}
+}
+
+/// A set of patterns relative to a base directory.
+#[derive(Clone, Debug)]
+pub struct Patterns {
+	base: path::PathBuf,
+	patterns: Vec<Pattern>,
+}
+
+impl Patterns {
+	pub fn new(base: &path::Path, patterns: Vec<Pattern>) -> Patterns {
+		Patterns { base: base.to_path_buf(), patterns }
+	}
+
+	/// Loads the ignore file in the given directory if it exists.
+	pub fn load(dir: &path::Path) -> io::Result<Option<Patterns>> {
+		let text = match fs::read_to_string(dir.join(IGNORE_FILE)) {
+			Ok(text) => text,
+			Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(err) => return Err(err),
+		};
+		let patterns = text.lines().filter_map(Pattern::parse).collect();
+		Ok(Some(Patterns::new(dir, patterns)))
+	}
+
+	/// Returns `Some(true)` if the path is excluded, `Some(false)` if it is explicitly included and `None` if no pattern matches.
+	pub fn check(&self, src_path: &path::Path, is_dir: bool) -> Option<bool> {
+		let rel_path = src_path.strip_prefix(&self.base).ok()?;
+		let rel_path = rel_path.to_str()?.replace('\\', "/");
+		self.patterns.iter().rev()
+			.find(|pat| pat.matches(rel_path.as_bytes(), is_dir))
+			.map(|pat| !pat.negate)
+	}
+}
+
+/// Returns true if the path is excluded by the given pattern sets.
+///
+/// Later pattern sets take precedence over earlier ones.
+pub fn is_excluded(sets: &[Patterns], src_path: &path::Path, is_dir: bool) -> bool {
+	sets.iter().rev()
+		.find_map(|set| set.check(src_path, is_dir))
+		.unwrap_or(false)
+}
+
+fn glob_match(pat: &[u8], text: &[u8]) -> bool {
+	match pat {
+		[] => text.is_empty(),
+		[b'*', b'*', rest @ ..] => {
+			// `**/` also matches zero directories
+			let rest_nosep = rest.strip_prefix(b"/").unwrap_or(rest);
+			if glob_match(rest_nosep, text) {
+				return true;
+			}
+			(0..text.len()).any(|i| glob_match(rest, &text[i + 1..]))
+		},
+		[b'*', rest @ ..] => {
+			for i in 0..=text.len() {
+				if glob_match(rest, &text[i..]) {
+					return true;
+				}
+				if i < text.len() && text[i] == b'/' {
+					break;
+				}
+			}
+			false
+		},
+		[b'?', rest @ ..] => {
+			match text {
+				[chr, text @ ..] if *chr != b'/' => glob_match(rest, text),
+				_ => false,
+			}
+		},
+		[b'[', class @ ..] => {
+			let end = match class.iter().skip(1).position(|&chr| chr == b']') {
+				Some(end) => end + 1,
+				None => return text.first() == Some(&b'[') && glob_match(class, &text[1..]),
+			};
+			match text {
+				[chr, text @ ..] if *chr != b'/' && class_match(&class[..end], *chr) => glob_match(&class[end + 1..], text),
+				_ => false,
+			}
+		},
+		[b'\\', chr, rest @ ..] | [chr, rest @ ..] => {
+			match text {
+				[first, text @ ..] if first == chr => glob_match(rest, text),
+				_ => false,
+			}
+		},
+	}
+}
+
+fn class_match(class: &[u8], chr: u8) -> bool {
+	let (negate, mut class) = match class {
+		[b'!' | b'^', class @ ..] => (true, class),
+		_ => (false, class),
+	};
+	let mut found = false;
+	while let Some(&first) = class.first() {
+		match class {
+			[lo, b'-', hi, rest @ ..] => {
+				found |= *lo <= chr && chr <= *hi;
+				class = rest;
+			},
+			[_, rest @ ..] => {
+				found |= first == chr;
+				class = rest;
+			},
+			[] => unreachable!(),
+		}
+	}
+	found != negate
+}
+
+#[test]
+fn test_glob_match() {
+	assert!(glob_match(b"*.txt", b"a.txt"));
+	assert!(!glob_match(b"*.txt", b"a/b.txt"));
+	assert!(glob_match(b"**/*.txt", b"a/b.txt"));
+	assert!(glob_match(b"**/*.txt", b"b.txt"));
+	assert!(glob_match(b"a/**", b"a/b/c"));
+	assert!(glob_match(b"a/**/c", b"a/c"));
+	assert!(glob_match(b"a?c", b"abc"));
+	assert!(!glob_match(b"a?c", b"a/c"));
+	assert!(glob_match(b"[a-c]x", b"bx"));
+	assert!(!glob_match(b"[!a-c]x", b"bx"));
+	assert!(glob_match(b"\\*", b"*"));
+}
+
+#[test]
+fn test_patterns() {
+	let base = path::Path::new("root");
+	let patterns = ["# comment", "*.psd", "!keep.psd", "build/", "/top.txt"];
+	let set = Patterns::new(base, patterns.iter().filter_map(|line| Pattern::parse(line)).collect());
+
+	assert_eq!(set.check(&base.join("art/a.psd"), false), Some(true));
+	assert_eq!(set.check(&base.join("art/keep.psd"), false), Some(false));
+	assert_eq!(set.check(&base.join("src/build"), true), Some(true));
+	assert_eq!(set.check(&base.join("src/build"), false), None);
+	assert_eq!(set.check(&base.join("top.txt"), false), Some(true));
+	assert_eq!(set.check(&base.join("sub/top.txt"), false), None);
+}
@@ -0,0 +1,169 @@
+/*!
+Implements the `inspect` command.
+*/
+
+use std::ops::Range;
+use paks::layout::{self, BLOCK_SIZE};
+use super::*;
+
+const HEADER_LEN: u32 = layout::HEADER_BLOCKS as u32;
+
+pub const HELP_INSPECT: &str = "\
+NAME
+    pakscmd-inspect - Shows the layout of the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] inspect [--raw RANGE]
+
+DESCRIPTION
+    Decrypts the header and the directory and shows how the blocks of the PAKS archive are used.
+    Block ranges are written as START..END where END is exclusive, a block is 16 bytes.
+
+    The header fields are followed by every region of the PAKS archive in order of their blocks:
+    the header, the sections of the files, the directory, the copy of the header and the key slots.
+    Blocks not referenced by any of these are garbage left behind by removed files and previous directories,
+    see `gc` to reclaim them. Gaps aligning the files to the block size of the PAKS archive are padding.
+    Files stored inline in the directory are listed last.
+
+    With --raw the encrypted bytes of the blocks in the RANGE are hexdumped instead.
+
+ARGUMENTS
+    --raw    Hexdump the encrypted bytes of the blocks START..END.
+";
+
+pub fn inspect(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	if let Some(range) = args.value("--raw") {
+		let range = match parse_range(range) {
+			Some(range) => range,
+			None => return error!(OptionInvalid, "--raw", "START..END", range),
+		};
+		return raw(file, range);
+	}
+
+	let reader = match paks::FileReader::open(file, key) {
+		Ok(reader) => reader,
+		Err(err) => return error!(Opening, file, err),
+	};
+	let file_len = match fs::metadata(file) {
+		Ok(metadata) => metadata.len(),
+		Err(err) => return error!(Reading, file, err),
+	};
+	let mut signature = Vec::new();
+	let _ = fs::File::open(file).and_then(|f| f.take(8).read_to_end(&mut signature));
+	let magic = paks::identify(&signature) != paks::Identify::Unknown;
+	let key_slots = paks::read_key_slots(file).unwrap_or_default();
+
+	let info = reader.info();
+	let dir = &info.directory;
+	let dir_end = layout::directory_blocks(info).map_or(u32::MAX, |range| range.end);
+	let file_blocks = (file_len / BLOCK_SIZE as u64) as u32;
+	let slots_len = if key_slots.is_empty() { 0 } else { (key_slots.len() * layout::KEY_SLOT_BLOCKS + 1) as u32 };
+	let paks_blocks = file_blocks.saturating_sub(slots_len);
+
+	println!("magic        {}", if magic { "yes" } else { "no (stealth mode)" });
+	println!("version      {}", String::from_utf8_lossy(&info.version.to_ne_bytes()));
+	println!("block size   {} bytes", BLOCK_SIZE << info.block_shift());
+	match (reader.archive_id(), reader.archive_generation()) {
+		(Some(id), Some(generation)) => println!("archive id   {} (generation {})", id, generation),
+		(Some(id), None) => println!("archive id   {}", id),
+		(None, _) => println!("archive id   none"),
+	}
+	println!("directory    {}..{} ({} descriptors)", dir.offset, dir_end, dir.size);
+	println!("dir nonce    {:016x}{:016x}", dir.nonce[1], dir.nonce[0]);
+	println!("dir mac      {:016x}{:016x}", dir.mac[1], dir.mac[0]);
+	println!("file size    {} bytes ({} blocks)", file_len, file_blocks);
+	println!("key slots    {}", key_slots.len());
+	println!();
+
+	// Collect the regions referenced by the header and the directory
+	let mut regions = vec![(0..HEADER_LEN, String::from("header"))];
+	let mut inline = Vec::new();
+	walk_files(&mut Vec::new(), reader.as_ref(), &mut |path, desc| {
+		let path = String::from_utf8_lossy(path);
+		if desc.is_inline() {
+			inline.push(format!("inline       {} ({} bytes)", path, desc.content_size));
+		}
+		else if desc.is_bundled() {
+			regions.push((section_range(&desc.section), format!("{} ({} bytes at offset {})", path, desc.content_size, desc.data_offset())));
+		}
+		else if let Some((offset, size)) = desc.delta_base() {
+			regions.push((section_range(&desc.section), format!("{} ({} bytes, delta)", path, desc.content_size)));
+			regions.push((offset..offset.saturating_add(size), format!("{} (delta base)", path)));
+		}
+		else {
+			regions.push((section_range(&desc.section), format!("{} ({} bytes{})", path, desc.content_size, if desc.is_plaintext() { ", plaintext" } else { "" })));
+		}
+	});
+	regions.push((dir.offset..dir_end, String::from("directory")));
+	let copy_end = dir_end.saturating_add(HEADER_LEN);
+	if paks_blocks >= copy_end {
+		regions.push((dir_end..copy_end, String::from("header copy")));
+	}
+	if slots_len > 0 {
+		regions.push((paks_blocks..file_blocks, String::from("key slots")));
+	}
+	regions.sort_by_key(|(range, _)| (range.start, range.end));
+
+	// Anything in between is garbage, unless it aligns the next region to the block size
+	let alignment = 1 << info.block_shift();
+	let mut covered = 0;
+	for (range, label) in &regions {
+		if range.start > covered {
+			let unused = if range.start - covered < alignment && range.start % alignment == 0 { "padding" } else { "garbage" };
+			println!("{:<12} {} ({} blocks)", format!("{}..{}", covered, range.start), unused, range.start - covered);
+		}
+		println!("{:<12} {}", format!("{}..{}", range.start, range.end), label);
+		covered = u32::max(covered, range.end);
+	}
+	if file_blocks > covered {
+		println!("{:<12} garbage ({} blocks)", format!("{}..{}", covered, file_blocks), file_blocks - covered);
+	}
+	for line in &inline {
+		println!("{}", line);
+	}
+}
+
+fn section_range(section: &paks::Section) -> Range<u32> {
+	layout::section_blocks(section).unwrap_or(section.offset..u32::MAX)
+}
+
+fn raw(file: &str, range: Range<u64>) {
+	let start = range.start.saturating_mul(BLOCK_SIZE as u64);
+	let len = (range.end - range.start).saturating_mul(BLOCK_SIZE as u64);
+	let mut data = Vec::new();
+	let result = fs::File::open(file).and_then(|mut f| {
+		f.seek(io::SeekFrom::Start(start))?;
+		f.take(len).read_to_end(&mut data)
+	});
+	if let Err(err) = result {
+		return error!(Reading, file, err);
+	}
+	if let Err(err) = io::stdout().write_all(cat::hexdump(&data, start).as_bytes()) {
+		error!(WritingStdout, file, err);
+	}
+}
+
+// Parses the block range `START..END`
+fn parse_range(s: &str) -> Option<Range<u64>> {
+	let (start, end) = s.split_once("..")?;
+	let start = start.parse().ok()?;
+	let end = end.parse().ok()?;
+	if start > end {
+		return None;
+	}
+	Some(start..end)
+}
+
+#[test]
+fn test_parse_range() {
+	assert_eq!(parse_range("5..12"), Some(5..12));
+	assert_eq!(parse_range("0..0"), Some(0..0));
+	assert_eq!(parse_range("12..5"), None);
+	assert_eq!(parse_range("5"), None);
+	assert_eq!(parse_range("a..b"), None);
+}
@@ -0,0 +1,68 @@
+/*!
+Long path support on Windows.
+
+Most Windows APIs fail with paths longer than 260 characters unless the path has the verbatim `\\?\` prefix.
+Windows does not normalize verbatim paths, the paths are made absolute and normalized before adding the prefix.
+UNC paths `\\server\share` become `\\?\UNC\server\share`.
+*/
+
+use std::path::{Path, PathBuf};
+
+/// Converts the path to a verbatim path on Windows, returns the path unchanged elsewhere.
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> PathBuf {
+	// Resolves the current directory and the `.` and `..` components and converts `/` to `\`
+	let path = match std::path::absolute(path) {
+		Ok(path) => path,
+		Err(_) => return path.to_path_buf(),
+	};
+	match path.to_str().and_then(verbatim) {
+		Some(verbatim) => PathBuf::from(verbatim),
+		None => path,
+	}
+}
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> PathBuf {
+	path.to_path_buf()
+}
+
+// Adds the verbatim prefix to an absolute normalized path
+// Returns None if the path already has a verbatim or device prefix
+#[cfg(windows)]
+fn verbatim(path: &str) -> Option<String> {
+	if path.starts_with(r"\\?\") || path.starts_with(r"\\.\") {
+		return None;
+	}
+	if let Some(unc) = path.strip_prefix(r"\\") {
+		return Some(format!(r"\\?\UNC\{}", unc));
+	}
+	match path.as_bytes() {
+		[drive, b':', b'\\', ..] if drive.is_ascii_alphabetic() => Some(format!(r"\\?\{}", path)),
+		_ => None,
+	}
+}
+
+#[cfg(windows)]
+#[test]
+fn test_long_path() {
+	assert_eq!(verbatim(r"C:\assets\a.txt").as_deref(), Some(r"\\?\C:\assets\a.txt"));
+	assert_eq!(verbatim(r"\\server\share\assets").as_deref(), Some(r"\\?\UNC\server\share\assets"));
+	assert_eq!(verbatim(r"\\?\C:\assets").as_deref(), None);
+	assert_eq!(verbatim(r"\\.\pipe\assets").as_deref(), None);
+
+	let path = long_path(Path::new("C:/assets/../textures/./wall.png"));
+	assert_eq!(path, Path::new(r"\\?\C:\textures\wall.png"));
+
+	// Create and read back a file nested deeper than 260 characters
+	let base = long_path(&std::env::temp_dir().join("pakscmd_long_path"));
+	let mut deep = base.clone();
+	for _ in 0..20 {
+		deep.push("a_fairly_long_directory_name");
+	}
+	std::fs::create_dir_all(&deep).unwrap();
+	deep.push("file.txt");
+	assert!(deep.as_os_str().len() > 260);
+	std::fs::write(&deep, b"deep").unwrap();
+	assert_eq!(std::fs::read(&deep).unwrap(), b"deep");
+	std::fs::remove_dir_all(&base).unwrap();
+}
@@ -0,0 +1,2116 @@
+/*!
+Implements pakscmd's command-line interface.
+*/
+
+#![allow(non_snake_case)]
+
+use std::{env, fs, io, io::prelude::*, path, str};
+use std::collections::HashMap;
+
+#[macro_use]
+mod msg;
+mod cli;
+mod cat;
+mod completions;
+mod copy;
+mod dryrun;
+mod graft;
+mod grep;
+mod ignore;
+mod inspect;
+mod longpath;
+mod patch;
+mod run;
+mod session;
+mod stats;
+
+fn main() {
+	let args: Vec<_> = env::args().collect();
+	let args: Vec<_> = args.iter().map(|s| &**s).collect();
+	let mut args = cli::parse(&args[1..]);
+
+	// Select the message language before anything is printed
+	let lang = args.value("--lang").map(String::from).or_else(|| env::var("PAKSCMD_LANG").ok());
+	if let Some(lang) = lang {
+		match msg::Lang::parse(&lang) {
+			Some(lang) => msg::set_lang(lang),
+			None => return error!(UnknownLang, lang),
+		}
+	}
+
+	if let Some(err) = args.error {
+		return err.emit();
+	}
+
+	// With a keystore the key may be omitted, `pakscmd <PAKFILE> <COMMAND> [..]`
+	let keystore = args.value("--keystore").map(String::from).or_else(|| env::var("PAKSCMD_KEYSTORE").ok());
+	let standalone = args.positional.first().and_then(|&name| cli::find(name)).filter(|cmd| cmd.is_standalone());
+	let keyless = keystore.as_ref().and(args.positional.get(1)).and_then(|&name| cli::find(name)).filter(|cmd| !cmd.is_standalone());
+	let (cmd, start) = match (standalone, keyless, &args.positional[..]) {
+		(Some(cmd), _, _) => (cmd, 1),
+		(None, Some(cmd), _) => (cmd, 2),
+		(None, None, [_, _, name, ..]) => match cli::find(name) {
+			Some(cmd) => (cmd, 3),
+			None => return error!(UnknownSubcommand, name),
+		},
+		(None, None, []) => return help(&args),
+		// `pakscmd <COMMAND> --help` is a shortcut for `pakscmd help <COMMAND>`
+		(None, None, [_]) if args.has("--help") => return help(&args),
+		(None, None, _) => return error!(InvalidSyntax),
+	};
+
+	if args.has("--help") {
+		return print!("{}", cmd.help);
+	}
+
+	for &(flag, _) in &args.flags {
+		if !cli::GLOBAL_FLAGS.iter().chain(cmd.flags).any(|other| other.name == flag.name) {
+			return error!(OptionNotAccepted, cmd.name, flag.name, cmd.name);
+		}
+	}
+
+	let prefix: Vec<_> = args.positional.drain(..start).collect();
+	match cmd.run {
+		cli::Run::Standalone(run) => run(&args),
+		cli::Run::Archive(run) => {
+			// Copy the PAKS archive aside before the command edits it, staged edits are backed up when committed
+			let staged = session::STAGED_COMMANDS.contains(&cmd.name) && session::is_open(prefix[0]);
+			if args.has("--backup") && !args.has("--dry-run") && !staged && !backup(prefix[0], args.value("--backup")) {
+				return;
+			}
+			let key = match (keyless, &keystore) {
+				(Some(_), Some(keystore)) => match keystore_key(prefix[0], keystore) {
+					Some(key) => key,
+					None => return,
+				},
+				_ => unlock_key(prefix[0], prefix[1]),
+			};
+			run(prefix[0], &key, &args)
+		},
+	}
+}
+
+/// Looks up the key of the PAKS archive in the keystore, see [`paks::Keystore::find`].
+///
+/// The key in the keystore may be a user key with a key slot in the PAKS archive.
+fn keystore_key(file: &str, keystore: &str) -> Option<String> {
+	let keys = match paks::Keystore::load(keystore) {
+		Ok(keys) => keys,
+		Err(err) => {
+			error!(Reading, keystore, err);
+			return None;
+		},
+	};
+	match keys.find(file) {
+		Some(key) => Some(unlock_key(file, &format!("{:016x}{:016x}", key[1], key[0]))),
+		None => {
+			error!(KeyNotInKeystore, file, keystore);
+			None
+		},
+	}
+}
+
+/// Unlocks the key of the PAKS archive if the key is a user key with a key slot.
+///
+/// Any other key is returned as-is, including when the PAKS archive doesn't exist yet.
+fn unlock_key(file: &str, key: &str) -> String {
+	let user_key = match paks::parse_key(key) {
+		Ok(user_key) => user_key,
+		Err(_) => return key.to_string(),
+	};
+	let key_slots = paks::read_key_slots(file).unwrap_or_default();
+	match paks::unlock_key(&key_slots, &user_key) {
+		Some(key) => format!("{:016x}{:016x}", key[1], key[0]),
+		None => key.to_string(),
+	}
+}
+
+fn parse_key(s: &str) -> Option<paks::Key> {
+	match paks::parse_key(s) {
+		Ok(key) => {
+			Some(key)
+		},
+		Err(err) => {
+			error!(ParsingKey, err);
+			None
+		},
+	}
+}
+
+/// Locks the PAKS archive against editors while it is rewritten as a whole.
+///
+/// The lock is released when the returned file is dropped.
+fn lock_archive(file: &str) -> Option<fs::File> {
+	let result = fs::OpenOptions::new().read(true).write(true).open(file).and_then(|f| match f.lock() {
+		Err(err) if err.kind() != io::ErrorKind::Unsupported => Err(err),
+		_ => Ok(f),
+	});
+	match result {
+		Ok(f) => Some(f),
+		Err(err) => {
+			error!(Opening, file, err);
+			None
+		},
+	}
+}
+
+/// Suffix appended to the file name of the PAKS archive to name its backup.
+const BACKUP_SUFFIX: &str = ".bak";
+
+/// Copies the PAKS archive to its backup, see `--backup`.
+///
+/// Nothing is copied if the PAKS archive does not exist yet.
+fn backup(file: &str, suffix: Option<&str>) -> bool {
+	let suffix = match suffix {
+		Some("") => {
+			error!(OptionInvalid, "--backup", "SUFFIX", "");
+			return false;
+		},
+		Some(suffix) => suffix,
+		None => BACKUP_SUFFIX,
+	};
+	if !path::Path::new(file).exists() {
+		return true;
+	}
+	let backup = format!("{}{}", file, suffix);
+	match fs::copy(file, &backup) {
+		Ok(_) => true,
+		Err(err) => {
+			error!(BackingUp, file, backup, err);
+			false
+		},
+	}
+}
+
+/// Calls the function for every file under the path in the PAKS archive.
+fn walk_path(reader: &paks::FileReader, path: &str, f: &mut dyn FnMut(&[u8], &paks::Descriptor)) {
+	let path = path.trim_end_matches('/');
+	match reader.find_desc(path.as_bytes()) {
+		Some(desc) if desc.is_file() => f(path.as_bytes(), desc),
+		Some(_) => {
+			let children = reader.get_children(path.as_bytes()).unwrap_or(&[]);
+			walk_files(&mut path.as_bytes().to_vec(), children, f);
+		},
+		None => error!(FileNotFound, path),
+	}
+}
+
+/// Calls the function for every file in the directory recursively.
+///
+/// The archive metadata is skipped, see [`paks::METADATA_PATHS`].
+fn walk_files(path: &mut Vec<u8>, children: &[paks::Descriptor], f: &mut dyn FnMut(&[u8], &paks::Descriptor)) {
+	let mut i = 0;
+	while i < children.len() {
+		let desc = &children[i];
+		i += 1;
+
+		let path_len = path.len();
+		if path_len > 0 {
+			path.push(b'/');
+		}
+		path.extend_from_slice(desc.name());
+
+		if desc.is_dir() {
+			let end = usize::min(children.len(), i + desc.content_size as usize);
+			walk_files(path, &children[i..end], f);
+			i = end;
+		}
+		else if !paks::is_metadata_path(path) {
+			f(path, desc);
+		}
+
+		path.truncate(path_len);
+	}
+}
+
+#[test]
+fn test_walk_files_metadata() {
+	let mut directory = paks::Directory::new();
+	directory.set_archive_id(paks::ArchiveId([1, 2]));
+	directory.create_empty_file(b"a/.archive-id").unwrap();
+	directory.create_empty_file(b"b").unwrap();
+
+	let mut paths = Vec::new();
+	walk_files(&mut Vec::new(), directory.as_ref(), &mut |path, _| paths.push(path.to_vec()));
+	assert_eq!(paths, [b"a/.archive-id".to_vec(), b"b".to_vec()]);
+}
+
+// Temporary directory of a test, named after the test and the process and removed when dropped
+#[cfg(test)]
+struct TestDir(path::PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+	fn new(name: &str) -> TestDir {
+		let dir = env::temp_dir().join(format!("pakscmd_{}_{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		TestDir(dir)
+	}
+
+	// Path in the directory as passed to the commands
+	fn path(&self, name: &str) -> String {
+		self.0.join(name).to_str().unwrap().to_string()
+	}
+
+	// Writes the files into the directory, creating their parent directories
+	fn write_files(&self, files: &[(&str, &str)]) {
+		for &(name, data) in files {
+			let path = self.0.join(name);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, data).unwrap();
+		}
+	}
+
+	// Creates a PAKS archive in the directory with the files
+	fn archive(&self, name: &str, key: &paks::Key, files: &[(&str, &str)]) -> String {
+		let file = self.path(name);
+		let mut edit = paks::FileEditor::create_new(&file, key).unwrap();
+		for &(path, data) in files {
+			edit.create_file(path.as_bytes(), data.as_bytes(), key).unwrap();
+		}
+		edit.finish(key).unwrap();
+		file
+	}
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDir {
+	type Target = path::Path;
+	#[inline]
+	fn deref(&self) -> &path::Path {
+		&self.0
+	}
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+	fn drop(&mut self) {
+		let _ = fs::remove_dir_all(&self.0);
+	}
+}
+
+#[test]
+fn test_backup_restore() {
+	let dir = TestDir::new("backup_restore");
+	let ref file = dir.path("archive.paks");
+	let ref key = paks::parse_key("2a").unwrap();
+	let edit = |path: &[u8], data: &[u8]| {
+		let mut edit = paks::FileEditor::open(file, key).unwrap();
+		edit.create_file(path, data, key).unwrap();
+		edit.finish(key).unwrap();
+	};
+
+	// Nothing to back up before the PAKS archive exists
+	assert!(backup(file, None));
+	assert!(!path::Path::new(&format!("{}.bak", file)).exists());
+	assert!(!backup(file, Some("")));
+
+	dir.archive("archive.paks", key, &[]);
+	edit(b"a", b"first");
+	assert!(backup(file, None));
+	assert_eq!(fs::read(format!("{}.bak", file)).unwrap(), fs::read(file).unwrap());
+
+	// An existing backup is replaced by the PAKS archive as it is before the next edit
+	edit(b"b", b"second");
+	let before = fs::read(file).unwrap();
+	assert!(backup(file, None));
+	assert!(backup(file, Some(".old")));
+	edit(b"c", b"third");
+	assert_eq!(fs::read(format!("{}.bak", file)).unwrap(), before);
+	assert_eq!(fs::read(format!("{}.old", file)).unwrap(), before);
+
+	// Restoring replaces the PAKS archive and keeps the backup
+	restore_backup(file, "2a", &cli::parse(&[]));
+	let reader = paks::FileReader::open(file, key).unwrap();
+	assert_eq!(reader.read(b"b", key).unwrap(), b"second");
+	assert!(reader.find_file(b"c").is_none());
+	assert!(path::Path::new(&format!("{}.bak", file)).exists());
+
+	// The PAKS archive is restored even if it was deleted
+	fs::remove_file(file).unwrap();
+	restore_backup(file, "2a", &cli::parse(&[".old"]));
+	assert_eq!(fs::read(file).unwrap(), before);
+
+	// A backup which does not open with the key does not replace the PAKS archive
+	edit(b"d", b"fourth");
+	let after = fs::read(file).unwrap();
+	restore_backup(file, "2b", &cli::parse(&[]));
+	fs::write(format!("{}.bak", file), b"not a PAKS archive").unwrap();
+	restore_backup(file, "2a", &cli::parse(&[]));
+	assert_eq!(fs::read(file).unwrap(), after);
+}
+
+#[test]
+fn test_undelete() {
+	let dir = TestDir::new("undelete");
+	let ref key = paks::parse_key("2a").unwrap();
+	let ref file = dir.archive("archive.paks", key, &[("a", "first"), ("b", "kept")]);
+
+	let mut edit = paks::FileEditor::open(file, key).unwrap();
+	edit.create_file(b"a", b"second", key).unwrap();
+	edit.finish(key).unwrap();
+	let mut edit = paks::FileEditor::open(file, key).unwrap();
+	edit.remove(b"a").unwrap();
+	edit.create_file(b"b", b"replaced", key).unwrap();
+	edit.finish(key).unwrap();
+
+	// The most recent contents are restored, existing files are not replaced
+	undelete(file, "2a", &cli::parse(&["a", "b", "missing"]));
+	let reader = paks::FileReader::open(file, key).unwrap();
+	assert_eq!(reader.read(b"a", key).unwrap(), b"second");
+	assert_eq!(reader.read(b"b", key).unwrap(), b"replaced");
+	assert!(reader.find_desc(b"missing").is_none());
+
+	// Garbage collecting discards the previous directories
+	let mut edit = paks::FileEditor::open(file, key).unwrap();
+	edit.remove(b"a").unwrap();
+	edit.finish(key).unwrap();
+	gc(file, "2a", &cli::parse(&[]));
+	undelete(file, "2a", &cli::parse(&["a"]));
+	let reader = paks::FileReader::open(file, key).unwrap();
+	assert!(reader.find_desc(b"a").is_none());
+	assert_eq!(reader.read(b"b", key).unwrap(), b"replaced");
+}
+
+#[test]
+fn test_digests() {
+	let dir = TestDir::new("digests");
+	let ref key = paks::parse_key("2a").unwrap();
+	let ref file = dir.archive("archive.paks", key, &[("a", "alpha"), ("dir/b", "beta"), ("dir/sub/c", "")]);
+	let reader = paks::FileReader::open(file, key).unwrap();
+
+	let digests = |all, paths: &[&str]| -> Vec<(String, Option<paks::hash::Digest>)> {
+		digests(&reader, key, all, paths).into_iter().map(|(path, digest)| (path, digest.ok())).collect()
+	};
+	let (a, b, c) = (paks::hash::sha256(b"alpha"), paks::hash::sha256(b"beta"), paks::hash::sha256(b""));
+
+	// The digest is the digest of the loose file, directories are hashed recursively
+	assert_eq!(digests(false, &["a", "dir/", "missing"]), [("a".to_string(), Some(a)), ("dir/b".to_string(), Some(b)), ("dir/sub/c".to_string(), Some(c))]);
+	assert_eq!(digests(true, &[]).len(), 3);
+	assert_eq!(digests(true, &["a"]).len(), 4);
+	assert_eq!(digests(false, &[]), []);
+}
+
+//----------------------------------------------------------------
+
+const HELP_GENERAL: &str = "\
+pakscmd - Copyright (c) 2020-2025 Casper <CasualX@users.noreply.github.com>
+
+USAGE
+    pakscmd help <COMMAND>
+    pakscmd [--lang LANG] identify [FILE]..
+    pakscmd completions <SHELL>
+    pakscmd [--lang LANG] <PAKFILE> <KEY> <COMMAND> [..]
+    pakscmd [--lang LANG] --keystore FILE <PAKFILE> <COMMAND> [..]
+
+OPTIONS
+    --lang LANG  Language of the messages, defaults to the PAKSCMD_LANG environment variable.
+                 en:      English (default).
+                 machine: Machine-readable tab-separated lines `<level> <id> <arg>..`.
+    --help       Displays help information, `pakscmd <COMMAND> --help` displays the help of the command.
+    --keystore FILE
+                 Looks up the KEY of the PAKFILE in the keystore, defaults to the PAKSCMD_KEYSTORE environment variable.
+                 The KEY argument is omitted, see the keystore format below.
+    --backup[=SUFFIX]
+                 Copies the PAKS archive to PAKFILE.bak, or PAKFILE followed by the SUFFIX, before editing it.
+                 Accepted by the commands which edit the PAKS archive, see `restore-backup`.
+                 Edits staged in a session are backed up by `commit --backup`, see `begin`.
+
+    Flags may be given anywhere on the command line, flags with a value accept `--flag=VALUE`.
+    Arguments after `--` are never treated as flags.
+
+ARGUMENTS
+    PAKFILE  Path to a PAKS archive to create or edit.
+    KEY      The 128-bit encryption key encoded in hex, or a user key with a key slot in the PAKS archive.
+    COMMAND  The subcommand to invoke.
+
+KEYSTORE
+    The keystore maps the PAKS archives to their keys, one `name = \"KEY\"` per line, `#` starts a comment.
+    The PAKFILE is looked up as given, by its file name and by its file name without the extension:
+
+        game = \"0123456789abcdef0123456789abcdef\"
+        \"dlc/expansion.paks\" = \"fedcba9876543210fedcba9876543210\"
+
+Commands are:
+";
+
+const HELP_EXAMPLES: &str = "
+    See `pakscmd help <COMMAND>` for more information on a specific command.
+
+EXAMPLES
+    pakscmd example.paks 0 new
+    pakscmd example.paks 0 add a/b/example < tests/data/example.txt
+    pakscmd example.paks 0 link a/b/example aa/bb/example
+    pakscmd example.paks 0 tree -u
+    pakscmd example.paks 0 rm a/b/example
+    pakscmd example.paks 0 cat aa/bb/example
+";
+
+const HELP_HELP: &str = "\
+NAME
+    pakscmd-help - Displays help information.
+
+SYNOPSIS
+    pakscmd help [COMMAND]
+
+DESCRIPTION
+    Displays the list of commands or the help of a specific command.
+";
+
+fn help(args: &cli::Matches) {
+	let name = match args.positional.first() {
+		Some(name) => name,
+		None => {
+			print!("{}", HELP_GENERAL);
+			for cmd in cli::visible().filter(|cmd| !cmd.is_standalone()) {
+				println!("    {:<7}  {}", cmd.name, cmd.about);
+			}
+			print!("{}", HELP_EXAMPLES);
+			return;
+		},
+	};
+	match cli::find(name) {
+		Some(cmd) if !cmd.hidden => print!("{}", cmd.help),
+		_ => error!(UnknownSubcommand, name),
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_NEW: &str = "\
+NAME
+    pakscmd-new - Creates a new empty PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] new [--stealth] [--block-size <BYTES>]
+
+DESCRIPTION
+    Creates a new empty PAKS archive with the given file name and encryption key.
+    If a file with this name already exists it will be overwritten.
+
+    The PAKS archive gets a random archive id stored in the .archive-id file, see `inspect`.
+
+OPTIONS
+    --stealth     Omit the plaintext magic signature.
+                  The PAKS archive is indistinguishable from random data without the key.
+    --block-size  Aligns the files to blocks of BYTES bytes, a power of two from 16 up to 65536, eg. 4096.
+                  Defaults to 16, larger blocks trade padding for throughput when reading large assets.
+                  The block size is recorded in the PAKS archive and kept by later edits, see `optimize`.
+";
+
+fn new(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	if !args.positional.is_empty() {
+		return error!(InvalidSyntaxCmd, "new");
+	}
+	let stealth = args.has("--stealth");
+	let block_shift = match args.value("--block-size") {
+		Some(value) => match parse_block_size(value) {
+			Some(block_shift) => block_shift,
+			None => return error!(OptionInvalid, "--block-size", "BYTES", value),
+		},
+		None => 0,
+	};
+
+	let result = if stealth {
+		let mut edit = paks::MemoryEditor::new();
+		edit.set_magic(false).set_block_shift(block_shift);
+		edit.finish(key)
+			.map_err(io::Error::from)
+			.and_then(|(data, _)| fs::write(file, dataview::bytes(data.as_slice())))
+	}
+	else {
+		paks::FileEditor::create_empty(file, key)
+			.and_then(|_| paks::FileEditor::open(file, key))
+			.and_then(|mut edit| {
+				edit.set_block_shift(block_shift);
+				edit.finish(key)
+			})
+	};
+	if let Err(err) = result {
+		error!(Writing, file, err);
+	}
+}
+
+// Parses the block size in bytes as the block shift, see paks::InfoHeader::block_shift
+fn parse_block_size(value: &str) -> Option<u32> {
+	let size = value.parse::<u32>().ok().filter(|size| size.is_power_of_two())?;
+	let block_shift = size.trailing_zeros().checked_sub(paks::layout::BLOCK_SIZE.trailing_zeros())?;
+	(block_shift <= paks::InfoHeader::MAX_BLOCK_SHIFT).then_some(block_shift)
+}
+
+#[test]
+fn test_parse_block_size() {
+	assert_eq!(parse_block_size("16"), Some(0));
+	assert_eq!(parse_block_size("4096"), Some(8));
+	assert_eq!(parse_block_size("65536"), Some(12));
+	assert_eq!(parse_block_size("131072"), None);
+	assert_eq!(parse_block_size("8"), None);
+	assert_eq!(parse_block_size("100"), None);
+	assert_eq!(parse_block_size("0"), None);
+	assert_eq!(parse_block_size("4k"), None);
+}
+
+//----------------------------------------------------------------
+
+const HELP_KEY: &str = "\
+NAME
+    pakscmd-key - Manages the key slots of the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] key add <USERKEY>
+    pakscmd [..] key remove <USERKEY>
+    pakscmd [..] key export --age-recipient <PUBKEY>..
+
+DESCRIPTION
+    A key slot wraps the encryption key of the PAKS archive with a user key.
+    Any user key with a key slot can be given as the KEY of the PAKS archive.
+    Key slots are added and removed without reencrypting the PAKS archive.
+
+    Removing a key slot does not revoke access from anyone who already unlocked
+    the encryption key, copy the files to a PAKS archive with a new key instead.
+
+    Exporting wraps the encryption key for existing key-management tooling instead.
+    The encryption key is written to stdout encrypted for the age recipients in the ASCII armored age format,
+    any of the recipients decrypts it with `age --decrypt -i <IDENTITY>`.
+    Requires pakscmd built with the `age` feature.
+
+ARGUMENTS
+    add      Adds a key slot for the user key, replacing any existing one.
+    remove   Removes the key slot of the user key.
+    export   Writes the encryption key wrapped for the age recipients.
+    USERKEY  The 128-bit user key encoded in hex.
+
+    --age-recipient PUBKEY
+             The age X25519 public key of a recipient, eg. `age1..`, may be given multiple times.
+";
+
+fn key(file: &str, key: &str, args: &cli::Matches) {
+	let (action, user_key) = match args.positional[..] {
+		[action @ ("add" | "remove"), user_key] => (action, user_key),
+		["export"] => return export_key(file, key, args),
+		_ => return error!(InvalidSyntaxCmd, "key"),
+	};
+
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+	let ref user_key = match parse_key(user_key) {
+		Some(user_key) => user_key,
+		None => return,
+	};
+
+	let mut edit = match paks::FileEditor::open(file, key) {
+		Ok(edit) => edit,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	if action == "add" {
+		if let Err(err) = edit.add_key_slot(user_key, key) {
+			return error!(Writing, file, err);
+		}
+	}
+	else if !edit.remove_key_slot(user_key) {
+		return error!(KeySlotNotFound, file);
+	}
+
+	if let Err(err) = edit.finish(key) {
+		error!(Writing, file, err);
+	}
+}
+
+#[cfg(feature = "age")]
+fn export_key(file: &str, key: &str, args: &cli::Matches) {
+	let recipients: Vec<&str> = args.values("--age-recipient").collect();
+	if recipients.is_empty() {
+		return error!(Expecting, "--age-recipient <PUBKEY>");
+	}
+
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	// Never export a key which does not open the PAKS archive
+	if let Err(err) = paks::FileReader::open(file, key) {
+		return error!(Opening, file, err);
+	}
+
+	match paks::export_key_age(key, &recipients) {
+		Ok(armored) => print!("{}", armored),
+		Err(err) => error!(ExportingKey, err),
+	}
+}
+
+#[cfg(not(feature = "age"))]
+fn export_key(_file: &str, _key: &str, _args: &cli::Matches) {
+	error!(ExportingKey, "pakscmd was built without the `age` feature");
+}
+
+//----------------------------------------------------------------
+
+const HELP_VERIFY_KEY: &str = "\
+NAME
+    pakscmd-verify-key - Checks the encryption key of the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] verify-key
+
+DESCRIPTION
+    Checks whether the encryption key opens the PAKS archive.
+    Nothing is listed or modified, use this to validate the key before a long running job.
+
+    Exits with status 0 if the key is correct and 1 otherwise.
+";
+
+fn verify_key(file: &str, key: &str, args: &cli::Matches) {
+	if !args.positional.is_empty() {
+		error!(InvalidSyntaxCmd, "verify-key");
+		std::process::exit(1);
+	}
+
+	if !check_key(file, key) {
+		std::process::exit(1);
+	}
+}
+
+fn check_key(file: &str, key: &str) -> bool {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return false,
+	};
+
+	match paks::FileReader::open(file, key) {
+		Ok(_) => {
+			info!(KeyCorrect, file);
+			true
+		},
+		Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+			error!(KeyIncorrect, file);
+			false
+		},
+		Err(err) => {
+			error!(Opening, file, err);
+			false
+		},
+	}
+}
+
+#[test]
+fn test_check_key() {
+	let dir = TestDir::new("check_key");
+	let ref key = paks::parse_key("2a").unwrap();
+	let ref file = dir.archive("archive.paks", key, &[("a", "alpha")]);
+
+	let mut edit = paks::FileEditor::open(file, key).unwrap();
+	edit.add_key_slot(&paks::parse_key("5b").unwrap(), key).unwrap();
+	edit.finish(key).unwrap();
+
+	// User keys are unlocked with their key slot before the check
+	assert!(check_key(file, &unlock_key(file, "2a")));
+	assert!(check_key(file, &unlock_key(file, "5b")));
+	assert!(!check_key(file, &unlock_key(file, "6c")));
+	assert!(!check_key(file, "not a key"));
+	assert!(!check_key(&dir.path("missing.paks"), "2a"));
+
+	// Checking the key does not modify the PAKS archive
+	let before = fs::read(file).unwrap();
+	assert!(check_key(file, "2a"));
+	assert!(fs::read(file).unwrap() == before);
+}
+
+//----------------------------------------------------------------
+
+const HELP_IDENTIFY: &str = "\
+NAME
+    pakscmd-identify - Identifies PAKS archives without the encryption key.
+
+SYNOPSIS
+    pakscmd identify [FILE]..
+
+DESCRIPTION
+    Identifies PAKS archives by their plaintext magic signature.
+    PAKS archives created with `new --stealth` cannot be identified.
+";
+
+fn identify(args: &cli::Matches) {
+	for &file in &args.positional {
+		let mut signature = Vec::new();
+		let result = fs::File::open(file).and_then(|f| f.take(8).read_to_end(&mut signature));
+		if let Err(err) = result {
+			error!(Reading, file, err);
+			continue;
+		}
+
+		match paks::identify(&signature) {
+			paks::Identify::Paks { version } => info!(IdentifyPaks, file, String::from_utf8_lossy(&version.to_ne_bytes())),
+			paks::Identify::Unsupported { version } => info!(IdentifyUnsupported, file, String::from_utf8_lossy(&version.to_ne_bytes())),
+			paks::Identify::Unknown => info!(IdentifyUnknown, file),
+		}
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_TREE: &str = "\
+NAME
+    pakscmd-tree - Displays the directory of the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] tree [-au] [--style <STYLE>] [--names <NAMEFILE>] [--types] [PATH]
+
+DESCRIPTION
+    Displays the directory of the PAKS archive.
+    The names of a stripped PAKS archive are restored from its name table, see `pakscmd help strip`.
+
+ARGUMENTS
+    -a       Display using ASCII art, same as --style ascii.
+    -u       Display using UNICODE art, same as --style unicode.
+    --style  The art style: ascii, unicode, compact (one line per entry) or markdown (nested list).
+    --names  The name table exported when the PAKS archive was stripped.
+    --types  Follow the files by the name of their content type, see `pakscmd help types`.
+    PATH     Optional subdirectory to start at.
+";
+
+fn tree(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let mut art = &paks::TreeArt::UNICODE;
+	for &(flag, value) in &args.flags {
+		match (flag.name, value) {
+			("-a", _) | ("--style", Some("ascii")) => art = &paks::TreeArt::ASCII,
+			("-u", _) | ("--style", Some("unicode")) => art = &paks::TreeArt::UNICODE,
+			("--style", Some("compact")) => art = &paks::TreeArt::COMPACT,
+			("--style", Some("markdown")) => art = &paks::TreeArt::MARKDOWN,
+			("--style", Some(style)) => return error!(OptionInvalid, "--style", "ascii, unicode, compact or markdown", style),
+			_ => (),
+		}
+	}
+
+	let path = match args.positional[..] {
+		[path] => Some(path),
+		_ => None,
+	};
+
+	let mut reader = match paks::FileReader::open(file, key) {
+		Ok(reader) => reader,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	if let Some(names) = args.value("--names") {
+		let table = match fs::read(names) {
+			Ok(table) => table,
+			Err(err) => return error!(Reading, names, err),
+		};
+		if let Err(err) = reader.attach_name_table(&table, key) {
+			return error!(Reading, names, err);
+		}
+	}
+
+	let types = match content_types(&reader, key, args) {
+		Some(types) => types,
+		None => return,
+	};
+	let display = match &types {
+		Some(types) => reader.display_children_with_types(path, art, types).map(|display| display.to_string()),
+		None => reader.display_children(path, art).map(|display| display.to_string()),
+	};
+	let display = match display {
+		Some(display) => display,
+		None => return error!(DirNotFound, path.unwrap_or("")),
+	};
+
+	println!("{}", display);
+}
+
+//----------------------------------------------------------------
+
+const HELP_LS: &str = "\
+NAME
+    pakscmd-ls - Lists the directory of the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] ls [-R] [--types] [PATH]
+
+DESCRIPTION
+    Lists the directory of the PAKS archive, one entry per line sorted by path.
+    Every line contains the path, the type (dir or file) and the size in bytes separated by tabs.
+    Unlike the tree this listing is easy to diff between builds of the PAKS archive.
+
+ARGUMENTS
+    -R       List subdirectories recursively.
+    --types  Add a fourth column to the files with the name of their content type, or its number if it has no name.
+    PATH     Optional subdirectory to list.
+";
+
+fn ls(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let path = match args.positional[..] {
+		[path] => Some(path),
+		_ => None,
+	};
+
+	let reader = match paks::FileReader::open(file, key) {
+		Ok(reader) => reader,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	let types = match content_types(&reader, key, args) {
+		Some(types) => types,
+		None => return,
+	};
+	let display = match &types {
+		Some(types) => reader.display_flat_children_with_types(path, args.has("-R"), types).map(|display| display.to_string()),
+		None => reader.display_flat_children(path, args.has("-R")).map(|display| display.to_string()),
+	};
+	let display = match display {
+		Some(display) => display,
+		None => return error!(DirNotFound, path.unwrap_or("")),
+	};
+
+	print!("{}", display);
+}
+
+/// Reads the names of the content types if requested with `--types`.
+fn content_types(reader: &paks::FileReader, key: &paks::Key, args: &cli::Matches) -> Option<Option<paks::ContentTypes>> {
+	if !args.has("--types") {
+		return Some(None);
+	}
+	match reader.content_types(key) {
+		Ok(types) => Some(Some(types)),
+		Err(err) => {
+			error!(Reading, String::from_utf8_lossy(paks::CONTENT_TYPES_PATH), err);
+			None
+		},
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_ADD: &str = "\
+NAME
+    pakscmd-add - Adds a file to the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] add [--plaintext] [--delta] [--sniff] [--dry-run] <PATH> < <CONTENT>
+
+DESCRIPTION
+    Adds a file to the PAKS archive.
+
+OPTIONS
+    --plaintext  Store the file unencrypted.
+                 Only use this for large, non-sensitive files which are read often.
+    --delta      Store only the differences against the file being replaced.
+                 Keeps PAKS archives small when large files are patched frequently.
+    --sniff      Assign the content type by inspecting the contents, eg. PNG, OGG, WASM or JSON.
+                 The name of the content type is added to the PAKS archive, see `pakscmd help types`.
+                 Files of unknown formats have content type 1.
+    --dry-run    Print the file which would be added and the resulting size of the PAKS archive without writing.
+                 Files added with --delta are projected as if stored whole.
+
+ARGUMENTS
+    PATH     The destination path in the PAKS archive to put the file.
+    CONTENT  The file data to write in the PAKS archive passed via stdin.
+";
+
+fn add(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let dry_run = args.has("--dry-run");
+
+	let path = match args.positional[..] {
+		[path] => path,
+		_ => return error!(Expecting, "<PATH>"),
+	};
+
+	let mut data = Vec::new();
+	match io::stdin().read_to_end(&mut data) {
+		Ok(_) => (),
+		Err(err) => return error!(ReadingStdin, err),
+	};
+
+	let flags: Vec<&str> = ["--plaintext", "--delta", "--sniff"].into_iter().filter(|&flag| args.has(flag)).collect();
+	if !dry_run && session::stage_add(file, &flags, path, &data) {
+		return;
+	}
+
+	let mut edit = match dryrun::open(file, key, dry_run) {
+		Some(edit) => edit,
+		None => return,
+	};
+
+	if let Err(err) = add_data(&mut edit, path, &data, args, dry_run, key) {
+		error!(Creating, path, err);
+	}
+
+	if dry_run {
+		return dryrun::report(file, &edit);
+	}
+	if let Err(err) = edit.finish(key) {
+		error!(Writing, file, err);
+	}
+}
+
+/// Adds the file to the PAKS archive with the flags of `add`, only allocating its section when simulating the edits.
+fn add_data(edit: &mut paks::FileEditor, path: &str, data: &[u8], flags: &cli::Matches, dry_run: bool, key: &paks::Key) -> io::Result<()> {
+	let plaintext = flags.has("--plaintext");
+	let delta = flags.has("--delta") && !plaintext;
+
+	let mut content_type = 1;
+	if flags.has("--sniff") {
+		let sniffer = paks::pack::Sniffer::builtin();
+		if let Some(rule) = sniffer.sniff(data) {
+			content_type = rule.content_type;
+			if !dry_run {
+				name_content_types(edit, &sniffer, &[content_type], key)?;
+			}
+		}
+	}
+
+	if delta && !dry_run {
+		edit.create_file_with(path.as_bytes(), data, paks::CreateMode::Delta, key)?;
+		if let Some(desc) = edit.find_id(path.as_bytes()).and_then(|id| edit.resolve_mut(id)) {
+			desc.content_type = content_type;
+		}
+		return Ok(());
+	}
+	let mut edit_file = edit.edit_file(path.as_bytes())?;
+	edit_file.set_content(content_type, data.len() as u32).set_plaintext(plaintext);
+	edit_file.allocate_data();
+	if dry_run {
+		info!(DryRunAdd, path, data.len());
+		return Ok(());
+	}
+	edit_file.write_data(data, key).map(|_| ())
+}
+
+/// Names the sniffed content types in the PAKS archive to keep it self-describing.
+fn name_content_types(edit: &mut paks::FileEditor, sniffer: &paks::pack::Sniffer, content_types: &[u32], key: &paks::Key) -> io::Result<()> {
+	let mut types = edit.content_types(key)?;
+	let mut named = false;
+	for &content_type in content_types {
+		named |= sniffer.name_content_type(content_type, &mut types);
+	}
+	if named {
+		edit.set_content_types(&types, key)?;
+	}
+	Ok(())
+}
+
+//----------------------------------------------------------------
+
+const HELP_PACK: &str = "\
+NAME
+    pakscmd-pack - Creates the PAKS archive from a manifest.
+
+SYNOPSIS
+    pakscmd [..] pack --manifest <MANIFEST> [--key-id ID=KEY].. [--transform EXT=NAME].. [--incremental]
+
+DESCRIPTION
+    Creates the PAKS archive from a manifest listing the files to pack.
+    If a file with this name already exists it will be overwritten,
+    the new PAKS archive keeps its archive id, see `inspect`.
+
+    The manifest is a TOML file with a `[[file]]` table for every file:
+
+        [[file]]
+        dest = \"textures/wall.png\"
+        src = \"art/wall.png\"
+        content_type = 2
+        key = \"art\"
+
+    The optional `[content_types]` table names the content types, see `pakscmd help types`:
+
+        [content_types]
+        2 = \"texture/png\"
+
+    The `dest` and `src` keys are required.
+    Source paths are relative to the manifest.
+    The optional `key` refers to a key id given with --key-id, by default files are encrypted with KEY.
+
+    Files with the extension EXT are passed through the builtin transform NAME before being packed,
+    see `pakscmd help copy` for the list of builtin transforms.
+
+    With --incremental a cache of the packed files is kept next to the PAKS archive in PAKFILE.cache.
+    Files which are unchanged since the previous pack are copied from the existing PAKS archive
+    without being read or encrypted again.
+
+ARGUMENTS
+    --manifest MANIFEST  Path to the manifest file.
+    --key-id ID=KEY      Defines the 128-bit encryption key encoded in hex for the key id, may be repeated.
+    --transform EXT=NAME Transform files with the extension EXT, may be repeated.
+    --incremental        Reuse unchanged files from the existing PAKS archive.
+";
+
+fn pack(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	if let Some(arg) = args.positional.first() {
+		return error!(UnknownArgument, arg);
+	}
+
+	let mut key_ids = HashMap::new();
+	for arg in args.values("--key-id") {
+		let (id, id_key) = match arg.split_once('=') {
+			Some(id_key) => id_key,
+			None => return error!(OptionInvalid, "--key-id", "ID=KEY", arg),
+		};
+		let id_key = match parse_key(id_key) {
+			Some(id_key) => id_key,
+			None => return,
+		};
+		key_ids.insert(id, id_key);
+	}
+
+	let mut transforms = paks::pack::Transforms::new();
+	for rule in args.values("--transform") {
+		if transforms.add_rule(rule).is_none() {
+			return error!(OptionInvalid, "--transform", "EXT=NAME", rule);
+		}
+	}
+
+	let incremental = args.has("--incremental");
+
+	let manifest_path = match args.value("--manifest") {
+		Some(manifest_path) => path::Path::new(manifest_path),
+		None => return error!(Expecting, "--manifest <PATH>"),
+	};
+
+	let manifest = match paks::pack::Manifest::load(manifest_path) {
+		Ok(manifest) => manifest,
+		Err(err) => return error!(Reading, manifest_path.display(), err),
+	};
+
+	// Load the previous PAKS archive and its cache
+	let cache_path = paks::pack::PackCache::sidecar_path(file);
+	let mut cache = paks::pack::PackCache::new();
+	if incremental {
+		cache = match paks::pack::PackCache::load(&cache_path) {
+			Ok(cache) => cache,
+			Err(err) => return error!(Reading, cache_path.display(), err),
+		};
+	}
+	// The archive id of the previous PAKS archive is kept, its contents are only reused with the cache
+	let prev = fs::read(file).ok().and_then(|data| paks::MemoryReader::from_bytes(&data, key).ok());
+
+	let base_dir = manifest_path.parent().unwrap_or(path::Path::new(""));
+	let edit = match paks::pack::from_manifest_incremental(&manifest, base_dir, key, &|id| key_ids.get(id).cloned(), &transforms, prev.as_ref(), &mut cache) {
+		Ok(edit) => edit,
+		Err(err) => return error!(Packing, manifest_path.display(), err),
+	};
+	drop(prev);
+
+	let result = edit.finish(key).map_err(io::Error::from).and_then(|(data, _)| fs::write(file, dataview::bytes(data.as_slice())));
+	if let Err(err) = result {
+		return error!(Writing, file, err);
+	}
+
+	if incremental {
+		if let Err(err) = cache.save(&cache_path) {
+			error!(Writing, cache_path.display(), err);
+		}
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_LINK: &str = "\
+NAME
+    pakscmd-link - Links the file from alternative paths.
+
+SYNOPSIS
+    pakscmd [..] link <SRC> [DEST]..
+
+DESCRIPTION
+    Links the source file to alternative destination paths.
+    Returns file not found error if the SRC path does not exist.
+
+ARGUMENTS
+    SRC      Path to the source file to link.
+    DEST     One or more destination paths where to link the SRC.
+";
+
+fn link(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let (src_path, dest_paths) = match args.positional[..] {
+		[src, ref dest @ ..] => (src, dest),
+		_ => return error!(Expecting, "<SRC> [DEST].."),
+	};
+
+	if session::stage(file, "link", &args.positional) {
+		return;
+	}
+
+	let mut edit = match paks::FileEditor::open(file, key) {
+		Ok(edit) => edit,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	let src_desc = match edit.find_desc(src_path.as_bytes()) {
+		Some(desc) if desc.is_dir() => return error!(FileNotFound, src_path),
+		Some(desc) => *desc,
+		None => return error!(FileNotFound, src_path),
+	};
+
+	for &dest_path in dest_paths {
+		if let Err(err) = edit.create_link(dest_path.as_bytes(), &src_desc) {
+			error!(Linking, dest_path, err);
+		}
+	}
+
+	if let Err(err) = edit.finish(key) {
+		error!(Writing, file, err);
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_HASH: &str = "\
+NAME
+    pakscmd-hash - Prints the SHA-256 digest of files in the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] hash [--all] [PATH]..
+
+DESCRIPTION
+    Decrypts the files in the PAKS archive and prints the SHA-256 digest of their contents.
+    Directories are hashed recursively.
+    The output has the same format as `sha256sum` for comparing against files on the file system.
+
+ARGUMENTS
+    --all    Hash every file in the PAKS archive.
+    PATH     Path to the file or directory in the PAKS archive to hash.
+";
+
+fn hash(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let all = args.has("--all");
+	if !all && args.positional.is_empty() {
+		return error!(Expecting, "[PATH].. or --all");
+	}
+
+	let reader = match paks::FileReader::open(file, key) {
+		Ok(reader) => reader,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	for (path, digest) in digests(&reader, key, all, &args.positional) {
+		match digest {
+			Ok(digest) => info!(HashDigest, digest, path),
+			Err(err) => error!(Reading, path, err),
+		}
+	}
+}
+
+// Digests the plaintext of every file with all, followed by the files under the paths
+fn digests(reader: &paks::FileReader, key: &paks::Key, all: bool, paths: &[&str]) -> Vec<(String, io::Result<paks::hash::Digest>)> {
+	let mut digests = Vec::new();
+	let mut hash_file = |path: &[u8], desc: &paks::Descriptor| {
+		let digest = reader.read_data(desc, key).map(|data| paks::hash::sha256(&data));
+		digests.push((String::from_utf8_lossy(path).into_owned(), digest));
+	};
+	if all {
+		walk_files(&mut Vec::new(), reader.as_ref(), &mut hash_file);
+	}
+
+	for &path in paths {
+		walk_path(reader, path, &mut hash_file);
+	}
+	digests
+}
+
+//----------------------------------------------------------------
+
+const HELP_PRELOAD: &str = "\
+NAME
+    pakscmd-preload - Manages the preload list of the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] preload get
+    pakscmd [..] preload set [PATH]..
+
+DESCRIPTION
+    Manages the ordered list of files an engine loads up front, eg. the load order of a level.
+    The preload list is stored in the PAKS archive as the file `.preload`.
+
+    get      Prints the preload list, one path per line.
+    set      Replaces the preload list with the given paths in order.
+             Without any PATH arguments the preload list is removed.
+
+ARGUMENTS
+    PATH     Path to the file in the PAKS archive to preload.
+";
+
+fn preload(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	match args.positional[..] {
+		["get"] => {
+			let reader = match paks::FileReader::open(file, key) {
+				Ok(reader) => reader,
+				Err(err) => return error!(Opening, file, err),
+			};
+			let paths = match reader.preload_list(key) {
+				Ok(paths) => paths,
+				Err(err) => return error!(Reading, String::from_utf8_lossy(paks::PRELOAD_PATH), err),
+			};
+			for path in &paths {
+				println!("{}", String::from_utf8_lossy(path));
+			}
+		},
+		["set", ref paths @ ..] => {
+			let mut edit = match paks::FileEditor::open(file, key) {
+				Ok(edit) => edit,
+				Err(err) => return error!(Opening, file, err),
+			};
+			let paths: Vec<&[u8]> = paths.iter().map(|path| path.as_bytes()).collect();
+			if let Err(err) = edit.set_preload_list(&paths, key) {
+				return error!(Writing, String::from_utf8_lossy(paks::PRELOAD_PATH), err);
+			}
+			if let Err(err) = edit.finish(key) {
+				error!(Writing, file, err);
+			}
+		},
+		_ => error!(InvalidSyntaxCmd, "preload"),
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_TYPES: &str = "\
+NAME
+    pakscmd-types - Manages the names of the content types.
+
+SYNOPSIS
+    pakscmd [..] types get
+    pakscmd [..] types set [ID=NAME]..
+    pakscmd [..] types unset [ID]..
+
+DESCRIPTION
+    Manages the names of the content types of the files, eg. `2=texture/bc7`.
+    The names are stored in the PAKS archive as the file `.content-types`,
+    shown by `pakscmd tree --types` and `pakscmd ls --types`.
+
+    get      Prints the named content types, one `ID = NAME` per line.
+    set      Names the content types, replacing their previous names.
+    unset    Removes the names of the content types.
+
+ARGUMENTS
+    ID       The non-zero content type.
+    NAME     The name of the content type.
+";
+
+fn types(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let (command, args) = match args.positional[..] {
+		[command @ ("get" | "set" | "unset"), ref args @ ..] => (command, args),
+		_ => return error!(InvalidSyntaxCmd, "types"),
+	};
+
+	if command == "get" {
+		let reader = match paks::FileReader::open(file, key) {
+			Ok(reader) => reader,
+			Err(err) => return error!(Opening, file, err),
+		};
+		let types = match reader.content_types(key) {
+			Ok(types) => types,
+			Err(err) => return error!(Reading, String::from_utf8_lossy(paks::CONTENT_TYPES_PATH), err),
+		};
+		for (id, name) in types.iter() {
+			println!("{} = {}", id, name);
+		}
+		return;
+	}
+
+	let mut edit = match paks::FileEditor::open(file, key) {
+		Ok(edit) => edit,
+		Err(err) => return error!(Opening, file, err),
+	};
+	let mut types = match edit.content_types(key) {
+		Ok(types) => types,
+		Err(err) => return error!(Reading, String::from_utf8_lossy(paks::CONTENT_TYPES_PATH), err),
+	};
+	for &arg in args {
+		let (id, name) = match (command, arg.split_once('=')) {
+			("set", Some((id, name))) => (id, Some(name)),
+			("unset", None) => (arg, None),
+			_ => return error!(InvalidSyntaxCmd, "types"),
+		};
+		let id = match id.parse::<u32>() {
+			Ok(id) if id != 0 => id,
+			_ => return error!(InvalidContentType, id),
+		};
+		match name {
+			Some(name) => types.insert(id, name),
+			None => types.remove(id),
+		};
+	}
+	if let Err(err) = edit.set_content_types(&types, key) {
+		return error!(Writing, String::from_utf8_lossy(paks::CONTENT_TYPES_PATH), err);
+	}
+	if let Err(err) = edit.finish(key) {
+		error!(Writing, file, err);
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_EXTRACT: &str = "\
+NAME
+    pakscmd-extract - Extracts files from the PAKS archive to a directory.
+
+SYNOPSIS
+    pakscmd [..] extract <DEST> [PATH]..
+
+DESCRIPTION
+    Extracts files from the PAKS archive to a directory.
+    Directories are extracted recursively, empty directories and empty files are recreated.
+    Without any PATH arguments the entire PAKS archive is extracted.
+    Paths which would escape the DEST directory are skipped with an error.
+    Modification times recorded with `copy --mtime` are restored.
+
+ARGUMENTS
+    DEST     The directory on the file system to extract to.
+    PATH     Path to the file or directory in the PAKS archive to extract.
+";
+
+fn extract(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let (dest, paths) = match args.positional[..] {
+		[dest, ref paths @ ..] => (longpath::long_path(path::Path::new(dest)), paths),
+		_ => return error!(Expecting, "<DIR>"),
+	};
+	let dest = dest.as_path();
+
+	let reader = match paks::FileReader::open(file, key) {
+		Ok(reader) => reader,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	if paths.is_empty() {
+		extract_rec(&reader, dest, &mut Vec::new(), reader.as_ref(), key);
+	}
+
+	for &path in paths {
+		let path = path.trim_end_matches(['/', '\\']);
+		match reader.find_desc(path.as_bytes()) {
+			Some(desc) if desc.is_file() => extract_file(&reader, dest, path.as_bytes(), desc, key),
+			Some(_) => {
+				let children = reader.get_children(path.as_bytes()).unwrap_or(&[]);
+				if extract_dir(dest, path.as_bytes()) {
+					extract_rec(&reader, dest, &mut path.as_bytes().to_vec(), children, key);
+				}
+			},
+			None => error!(FileNotFound, path),
+		}
+	}
+}
+
+fn extract_rec(reader: &paks::FileReader, dest: &path::Path, path: &mut Vec<u8>, children: &[paks::Descriptor], key: &paks::Key) {
+	let mut i = 0;
+	while i < children.len() {
+		let desc = &children[i];
+		i += 1;
+
+		let path_len = path.len();
+		if path_len > 0 {
+			path.push(b'/');
+		}
+		path.extend_from_slice(desc.name());
+
+		if desc.is_dir() {
+			let end = usize::min(children.len(), i + desc.content_size as usize);
+			if extract_dir(dest, path) {
+				extract_rec(reader, dest, path, &children[i..end], key);
+			}
+			i = end;
+		}
+		// The archive metadata is not extracted
+		else if !paks::is_metadata_path(path) {
+			extract_file(reader, dest, path, desc, key);
+		}
+
+		path.truncate(path_len);
+	}
+}
+
+fn extract_dir(dest: &path::Path, path: &[u8]) -> bool {
+	let dir_path = match paks::safe_join(dest, path) {
+		Ok(dir_path) => dir_path,
+		Err(err) => {
+			error!(Extracting, String::from_utf8_lossy(path), err);
+			return false;
+		},
+	};
+
+	if let Err(err) = fs::create_dir_all(&dir_path) {
+		error!(Creating, dir_path.display(), err);
+		return false;
+	}
+
+	true
+}
+
+fn extract_file(reader: &paks::FileReader, dest: &path::Path, path: &[u8], desc: &paks::Descriptor, key: &paks::Key) {
+	let file_path = match paks::safe_join(dest, path) {
+		Ok(file_path) => file_path,
+		Err(err) => return error!(Extracting, String::from_utf8_lossy(path), err),
+	};
+
+	let data = match reader.read_data(desc, key) {
+		Ok(data) => data,
+		Err(err) => return error!(Reading, String::from_utf8_lossy(path), err),
+	};
+
+	if let Some(parent) = file_path.parent() {
+		if let Err(err) = fs::create_dir_all(parent) {
+			return error!(Creating, parent.display(), err);
+		}
+	}
+
+	if let Err(err) = fs::write(&file_path, &data) {
+		return error!(Writing, file_path.display(), err);
+	}
+
+	if let Some(mtime) = desc.extension::<paks::Mtime>() {
+		let result = fs::File::options().write(true).open(&file_path).and_then(|file| file.set_modified(mtime.to_system_time()));
+		if let Err(err) = result {
+			error!(Writing, file_path.display(), err);
+		}
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_RM: &str = "\
+NAME
+    pakscmd-rm - Removes files from the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] rm [--dry-run] [PATH]..
+
+DESCRIPTION
+    Removes files from the PAKS archive.
+
+OPTIONS
+    --dry-run  Print the files which would be removed and the resulting size of the PAKS archive without writing.
+
+ARGUMENTS
+    PATH     Path to the file in the PAKS archive to remove.
+";
+
+fn rm(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let dry_run = args.has("--dry-run");
+	if !dry_run && !args.positional.is_empty() && session::stage(file, "rm", &args.positional) {
+		return;
+	}
+
+	let mut edit = match dryrun::open(file, key, dry_run) {
+		Some(edit) => edit,
+		None => return,
+	};
+
+	for &path in &args.positional {
+		if edit.remove(path.as_bytes()).is_none() {
+			error!(RemoveNotFound, path);
+		}
+		else if dry_run {
+			info!(DryRunRemove, path);
+		}
+	}
+
+	if dry_run {
+		return dryrun::report(file, &edit);
+	}
+	if let Err(err) = edit.finish(key) {
+		error!(Writing, file, err);
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_UNDELETE: &str = "\
+NAME
+    pakscmd-undelete - Restores removed files in the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] undelete [PATH]..
+
+DESCRIPTION
+    Searches the previous directories of the PAKS archive, most recent first,
+    for the files and restores them in the current directory.
+
+    Previous directories are left behind every time the PAKS archive is edited.
+    Files can only be restored if their contents are still intact,
+    garbage collecting the PAKS archive discards all previous directories.
+
+ARGUMENTS
+    PATH     Path to the removed file in the PAKS archive to restore.
+";
+
+fn undelete(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let generations = match paks::FileReader::generations(file, key) {
+		Ok(generations) => generations.len(),
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	// Find the most recent intact file descriptor for every path
+	let mut found: Vec<Option<(usize, paks::Descriptor)>> = vec![None; args.positional.len()];
+	for generation in 1..generations {
+		if found.iter().all(Option::is_some) {
+			break;
+		}
+		let reader = match paks::FileReader::open_generation(file, key, generation) {
+			Ok(reader) => reader,
+			Err(_) => continue,
+		};
+		for (&path, found) in args.positional.iter().zip(found.iter_mut()) {
+			if found.is_some() {
+				continue;
+			}
+			if let Some(desc) = reader.find_file(path.as_bytes()) {
+				if reader.read_data(desc, key).is_ok() {
+					*found = Some((generation, *desc));
+				}
+			}
+		}
+	}
+
+	let mut edit = match paks::FileEditor::open(file, key) {
+		Ok(edit) => edit,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	for (&path, found) in args.positional.iter().zip(&found) {
+		let (generation, desc) = match found {
+			Some(found) => found,
+			None => {
+				error!(UndeleteNotFound, path);
+				continue;
+			},
+		};
+		if edit.find_desc(path.as_bytes()).is_some() {
+			error!(UndeleteExists, path);
+			continue;
+		}
+		match edit.create_link(path.as_bytes(), desc) {
+			Ok(()) => info!(Undeleted, path, generation),
+			Err(err) => error!(Restoring, path, err),
+		}
+	}
+
+	if let Err(err) = edit.finish(key) {
+		error!(Writing, file, err);
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_RESTORE_BACKUP: &str = "\
+NAME
+    pakscmd-restore-backup - Restores the PAKS archive from its backup.
+
+SYNOPSIS
+    pakscmd [..] restore-backup [SUFFIX]
+
+DESCRIPTION
+    Replaces the PAKS archive with the backup copied aside by `--backup`, the backup is kept.
+    The backup must open with the key before the PAKS archive is replaced.
+
+ARGUMENTS
+    SUFFIX   Suffix of the backup appended to PAKFILE, defaults to `.bak`.
+";
+
+fn restore_backup(file: &str, key: &str, args: &cli::Matches) {
+	let suffix = match args.positional[..] {
+		[] => BACKUP_SUFFIX,
+		[suffix] if !suffix.is_empty() => suffix,
+		_ => return error!(InvalidSyntaxCmd, "restore-backup"),
+	};
+
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let backup = format!("{}{}", file, suffix);
+	if let Err(err) = paks::FileReader::open(&backup, key) {
+		return error!(Opening, backup, err);
+	}
+
+	// The PAKS archive may have been deleted by accident
+	let _lock = if path::Path::new(file).exists() {
+		match lock_archive(file) {
+			Some(lock) => Some(lock),
+			None => return,
+		}
+	}
+	else {
+		None
+	};
+
+	if let Err(err) = fs::copy(&backup, file) {
+		return error!(Writing, file, err);
+	}
+	info!(RestoredBackup, file, backup);
+}
+
+//----------------------------------------------------------------
+
+const HELP_MV: &str = "\
+NAME
+    pakscmd-mv - Moves files in the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] mv [--dry-run] <SRC> <DEST>
+
+DESCRIPTION
+    Moves files in the PAKS archive.
+
+OPTIONS
+    --dry-run  Print the move and the resulting size of the PAKS archive without writing.
+
+ARGUMENTS
+    SRC      Path to the source file.
+    DEST     Path to the destination file.
+";
+
+fn mv(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let (src_path, dest_path) = match args.positional[..] {
+		[src_path, dest_path] => (src_path, dest_path),
+		_ => return error!(Expecting, "<SRC> <DEST>"),
+	};
+
+	let dry_run = args.has("--dry-run");
+	if !dry_run && session::stage(file, "mv", &[src_path, dest_path]) {
+		return;
+	}
+
+	let mut edit = match dryrun::open(file, key, dry_run) {
+		Some(edit) => edit,
+		None => return,
+	};
+
+	let moved = edit.move_file(src_path.as_bytes(), dest_path.as_bytes());
+
+	if dry_run {
+		if moved {
+			info!(DryRunMove, src_path, dest_path);
+		}
+		return dryrun::report(file, &edit);
+	}
+	if let Err(err) = edit.finish(key) {
+		error!(Writing, file, err);
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_FSCK: &str = "\
+NAME
+    pakscmd-fsck - File system consistency check.
+
+SYNOPSIS
+    pakscmd [..] fsck [--portable]
+
+DESCRIPTION
+    Checks the PAKS file's directory for errors.
+
+OPTIONS
+    --portable  Also check the paths can be extracted on Windows, macOS and Linux alike.
+                Paths are limited to 16 components and 200 bytes, names to printable ASCII characters.
+                Names must not end with a dot or a space, be reserved device names such as CON or NUL,
+                contain any of <>:\"/\\|?* or differ from a sibling only in case.
+                Run it on freshly packed PAKS archives meant to be distributed to all operating systems.
+";
+
+fn fsck(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let reader = match paks::FileReader::open(file, key) {
+		Ok(reader) => reader,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	let mut log = String::new();
+	if !reader.fsck(reader.high_mark(), &mut log) {
+		info!(FsckErrors);
+	}
+	else {
+		info!(FsckOk);
+	}
+
+	for line in log.lines() {
+		info!(FsckLog, line);
+	}
+
+	if args.has("--portable") {
+		let mut log = String::new();
+		if !reader.check_portable(&paks::Portable::DEFAULT, &mut log) {
+			info!(FsckNotPortable);
+		}
+		for line in log.lines() {
+			info!(FsckLog, line);
+		}
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_GC: &str = "\
+NAME
+    pakscmd-gc - Collects garbage left behind by removed files.
+
+SYNOPSIS
+    pakscmd [..] gc [--dry-run]
+
+DESCRIPTION
+    Collects garbage left behind by removed files.
+    When files are removed their data is left behind.
+    These files are unreadable because their cryptographic nonce is forgotten.
+
+OPTIONS
+    --dry-run  Print the resulting size of the PAKS archive without writing.
+";
+
+fn gc(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let dry_run = args.has("--dry-run");
+
+	// Hold on to the lock while the PAKS file is replaced
+	let edit = match dryrun::open(file, key, dry_run) {
+		Some(edit) => edit,
+		None => return,
+	};
+
+	let mut mem = match edit.to_memory() {
+		Ok(mem) => mem,
+		Err(err) => return error!(Reading, file, err),
+	};
+
+	mem.gc();
+
+	if dry_run {
+		return dryrun::report_len(file, dryrun::projected_len(mem.high_mark(), mem.as_ref().len(), mem.key_slots().len()));
+	}
+
+	if let Err(err) = mem.save_to(file, key) {
+		error!(Writing, file, err);
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_OPTIMIZE: &str = "\
+NAME
+    pakscmd-optimize - Places the files in access order.
+
+SYNOPSIS
+    pakscmd [..] optimize [--order <ORDER>] [--block-size <BYTES>]
+
+DESCRIPTION
+    Rewrites the PAKS archive with the files placed in the order they are accessed.
+    Reading the files in access order then streams through the PAKS archive without seeking, eg. on HDD or optical media.
+    The remaining files are placed after the ordered files, links keep sharing their contents.
+    Garbage left behind by removed files is collected along the way.
+
+OPTIONS
+    --order       Path on the file system to the access order, one path in the PAKS archive per line.
+                  Defaults to the preload list of the PAKS archive, see `pakscmd help preload`.
+    --block-size  Changes the block size of the PAKS archive to BYTES bytes, a power of two from 16 up to 65536.
+                  Defaults to the block size of the PAKS archive, see `pakscmd help new`.
+";
+
+fn optimize(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	// Hold on to the lock while the PAKS file is replaced
+	let edit = match paks::FileEditor::open(file, key) {
+		Ok(edit) => edit,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	let mut mem = match edit.to_memory() {
+		Ok(mem) => mem,
+		Err(err) => return error!(Reading, file, err),
+	};
+
+	let order = match args.value("--order") {
+		Some(order) => match fs::read(order) {
+			Ok(data) => data.split(|&byte| byte == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line).to_vec()).filter(|path| !path.is_empty()).collect(),
+			Err(err) => return error!(Reading, order, err),
+		},
+		None => match mem.preload_list(key) {
+			Ok(paths) => paths,
+			Err(err) => return error!(Reading, String::from_utf8_lossy(paks::PRELOAD_PATH), err),
+		},
+	};
+
+	if let Some(value) = args.value("--block-size") {
+		match parse_block_size(value) {
+			Some(block_shift) => mem.set_block_shift(block_shift),
+			None => return error!(OptionInvalid, "--block-size", "BYTES", value),
+		};
+	}
+
+	let order: Vec<&[u8]> = order.iter().map(Vec::as_slice).collect();
+	mem.reorder(&order);
+
+	if let Err(err) = mem.save_to(file, key) {
+		error!(Writing, file, err);
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_STRIP: &str = "\
+NAME
+    pakscmd-strip - Replaces the names with the hash of their path.
+
+SYNOPSIS
+    pakscmd [..] strip [--names <NAMEFILE>] [OUTPUT]
+
+DESCRIPTION
+    Replaces the names with the hash of their path.
+    The stripped PAKS archive leaks no file naming, files are referenced by the hash of their path.
+    Garbage left behind by removed files is collected along the way.
+    Keep the PAKS archive with the names for development and ship the stripped PAKS archive.
+
+ARGUMENTS
+    --names  Exports the names to a name table, encrypted with the same key.
+             Use it with `pakscmd tree --names` to display the stripped PAKS archive.
+    OUTPUT   Path on the file system to write the stripped PAKS archive to.
+             Without an OUTPUT the PAKS archive is stripped in place.
+";
+
+fn strip(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let output = match args.positional[..] {
+		[] => file,
+		[output] => output,
+		_ => return error!(InvalidSyntaxCmd, "strip"),
+	};
+
+	// Hold on to the lock while the PAKS file is replaced
+	let edit = match paks::FileEditor::open(file, key) {
+		Ok(edit) => edit,
+		Err(err) => return error!(Opening, file, err),
+	};
+
+	let mut mem = match edit.to_memory() {
+		Ok(mem) => mem,
+		Err(err) => return error!(Reading, file, err),
+	};
+
+	if let Some(names) = args.value("--names") {
+		let table = paks::NameTable::from_directory(&mem);
+		let result = table.to_bytes(key).map_err(io::Error::from).and_then(|data| fs::write(names, data));
+		if let Err(err) = result {
+			return error!(Writing, names, err);
+		}
+	}
+
+	mem.strip_names();
+	mem.gc();
+
+	if let Err(err) = mem.save_to(output, key) {
+		error!(Writing, output, err);
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_SELFEXTRACT: &str = "\
+NAME
+    pakscmd-selfextract - Creates a self-extracting executable.
+
+SYNOPSIS
+    pakscmd [..] selfextract [--stub <STUB>] <OUTPUT>
+
+DESCRIPTION
+    Creates a self-extracting executable from the PAKS archive.
+    The PAKS archive is appended to a copy of the paksfx stub executable.
+    Run the executable as `<OUTPUT> <KEY> <DEST>` to extract the PAKS archive to the DEST directory.
+    The key is not stored in the executable.
+
+ARGUMENTS
+    --stub   Path to the stub executable.
+             Defaults to the paksfx executable next to pakscmd.
+    OUTPUT   Path on the file system to write the executable to.
+";
+
+fn selfextract(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let output = match args.positional[..] {
+		[output] => output,
+		_ => return error!(Expecting, "<OUTPUT>"),
+	};
+
+	let stub = match args.value("--stub") {
+		Some(stub) => path::PathBuf::from(stub),
+		None => match env::current_exe() {
+			Ok(exe) => exe.with_file_name(format!("paksfx{}", env::consts::EXE_SUFFIX)),
+			Err(err) => return error!(Resolving, "paksfx", err),
+		},
+	};
+
+	// Check the key before the PAKS archive is shipped
+	if let Err(err) = paks::FileReader::open(file, key) {
+		return error!(Opening, file, err);
+	}
+
+	let data = match fs::read(file) {
+		Ok(data) => data,
+		Err(err) => return error!(Reading, file, err),
+	};
+
+	let stub_data = match fs::read(&stub) {
+		Ok(stub_data) => stub_data,
+		Err(err) => return error!(Reading, stub.display(), err),
+	};
+
+	let result = fs::File::create(output).and_then(|mut dest| {
+		dest.write_all(&stub_data)?;
+		paks::write_embedded(&mut dest, &data)?;
+		// Keep the stub executable, eg. its permission bits on unix
+		dest.set_permissions(fs::metadata(&stub)?.permissions())
+	});
+	if let Err(err) = result {
+		error!(Writing, output, err);
+	}
+}
+
+//----------------------------------------------------------------
+
+const HELP_UPGRADE: &str = "\
+NAME
+    pakscmd-upgrade - Rewrites the PAKS archive in the newest file format.
+
+SYNOPSIS
+    pakscmd [..] upgrade
+
+DESCRIPTION
+    Rewrites the PAKS archive in the newest file format.
+    Every file is decrypted and encrypted again into a new PAKS archive.
+    Garbage left behind by removed files is collected along the way.
+    The upgraded PAKS archive is written with the plaintext magic signature.
+    Archives already in the newest file format are left unchanged.
+
+    The newest file format adds descriptor flags (plaintext, bundled and
+    delta files), the synthetic header nonce and the per-archive block size.
+    File sizes remain 32-bit and files are not split into chunks, files
+    larger than 4 GiB cannot be stored in any file format version.
+";
+
+fn upgrade(file: &str, key: &str, _args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let _lock = match lock_archive(file) {
+		Some(lock) => lock,
+		None => return,
+	};
+
+	let version = match paks::FileReader::open(file, key) {
+		Ok(reader) => reader.info().version,
+		Err(err) => return error!(Opening, file, err),
+	};
+	if version == paks::InfoHeader::VERSION {
+		return info!(UpToDate, file);
+	}
+
+	let data = match fs::read(file) {
+		Ok(data) => data,
+		Err(err) => return error!(Reading, file, err),
+	};
+
+	// Report which stage failed, the PAKS archive opened fine a moment ago
+	let reader = match paks::MemoryEditor::from_bytes_diagnose(&data, key) {
+		Ok(edit) => paks::MemoryReader::from(edit),
+		Err(report) => return error!(Reading, file, report),
+	};
+
+	let mut edit = paks::MemoryEditor::new();
+	if let Err(err) = paks::migrate(&reader, key, &mut edit, key) {
+		return error!(Upgrading, file, err);
+	}
+
+	let result = edit.finish(key).map_err(io::Error::from).and_then(|(data, _)| fs::write(file, dataview::bytes(data.as_slice())));
+	if let Err(err) = result {
+		error!(Writing, file, err);
+	}
+}
+
@@ -0,0 +1,258 @@
+/*!
+User-facing messages.
+
+Every message printed by pakscmd is looked up in a message table by its [`Msg`] id.
+The `--lang` option selects the message table, `machine` prints the messages in a stable machine-readable format instead:
+
+```text
+<level>\t<id>\t<arg>..
+```
+
+The level is one of `error`, `warning` or `info` and the id is the snake_case name of the message.
+Tabs and newlines in the arguments are escaped as `\t` and `\n`.
+*/
+
+use std::fmt;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Message language.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum Lang {
+	/// English messages.
+	En,
+	/// Machine-readable messages.
+	Machine,
+}
+
+impl Lang {
+	/// Parses the language name.
+	pub fn parse(name: &str) -> Option<Lang> {
+		match name {
+			"en" => Some(Lang::En),
+			"machine" => Some(Lang::Machine),
+			_ => None,
+		}
+	}
+}
+
+static LANG: AtomicU8 = AtomicU8::new(Lang::En as u8);
+
+/// Sets the message language.
+pub fn set_lang(lang: Lang) {
+	LANG.store(lang as u8, Ordering::Relaxed);
+}
+
+/// Gets the message language.
+pub fn lang() -> Lang {
+	match LANG.load(Ordering::Relaxed) {
+		1 => Lang::Machine,
+		_ => Lang::En,
+	}
+}
+
+/// Message level.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Level {
+	Error,
+	Warning,
+	Info,
+}
+
+macro_rules! messages {
+	($($name:ident $id:literal $en:literal,)*) => {
+		/// Message id.
+		#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+		pub enum Msg {
+			$($name,)*
+		}
+
+		impl Msg {
+			/// Stable machine-readable id.
+			pub fn id(self) -> &'static str {
+				match self {
+					$(Msg::$name => $id,)*
+				}
+			}
+
+			/// Message template, every `{}` is replaced by the next argument.
+			pub fn text(self, lang: Lang) -> &'static str {
+				match lang {
+					Lang::En | Lang::Machine => match self {
+						$(Msg::$name => $en,)*
+					},
+				}
+			}
+		}
+	};
+}
+
+messages! {
+	InvalidSyntax "invalid_syntax" "Error invalid syntax, see `pakscmd help`.",
+	InvalidSyntaxCmd "invalid_syntax_cmd" "Error invalid syntax, see `pakscmd help {}`.",
+	Expecting "expecting" "Error invalid syntax: expecting {}.",
+	OptionExpects "option_expects" "Error invalid syntax: {} expects {}.",
+	OptionInvalid "option_invalid" "Error invalid syntax: {} expects {}, got {}",
+	OptionNoValue "option_no_value" "Error invalid syntax: {} does not take a value.",
+	OptionNotAccepted "option_not_accepted" "Error invalid syntax: {} does not accept {}, see `pakscmd help {}`.",
+	UnknownSubcommand "unknown_subcommand" "Error unknown subcommand: {}",
+	UnknownArgument "unknown_argument" "Error unknown argument: {}",
+	UnknownLang "unknown_lang" "Error unknown language: {}",
+	UnknownShell "unknown_shell" "Error unknown shell: {}",
+	ParsingKey "parsing_key" "Error parsing key argument: {}",
+	KeyNotInKeystore "key_not_in_keystore" "Error {}: no key in the keystore {}",
+	Opening "opening" "Error opening {}: {}",
+	Reading "reading" "Error reading {}: {}",
+	ReadingStdin "reading_stdin" "Error reading stdin: {}",
+	Writing "writing" "Error writing {}: {}",
+	WritingStdout "writing_stdout" "Error writing {} to stdout: {}",
+	Creating "creating" "Error creating {}: {}",
+	Linking "linking" "Error linking {}: {}",
+	LinkNotCopied "link_not_copied" "Error linking {}: {} was not copied",
+	Resolving "resolving" "Error resolving {}: {}",
+	Transforming "transforming" "Error transforming {}: {}",
+	Extracting "extracting" "Error extracting {}: {}",
+	Packing "packing" "Error packing {}: {}",
+	Upgrading "upgrading" "Error upgrading {}: {}",
+	Restoring "restoring" "Error restoring {}: {}",
+	Patching "patching" "Error patching {}: {}",
+	ExportingKey "exporting_key" "Error exporting the key: {}",
+	InvalidPattern "invalid_pattern" "Error invalid pattern {}: {}",
+	InvalidFileName "invalid_file_name" "Error invalid file name: {}",
+	InvalidDirName "invalid_dir_name" "Error invalid directory name: {}",
+	InvalidContentType "invalid_content_type" "Error invalid content type: {}",
+	FileNotFound "file_not_found" "Error file not found: {}",
+	DirNotFound "dir_not_found" "Error directory not found or is a file: {}",
+	RemoveNotFound "remove_not_found" "Unable to remove {}: file not found?",
+	UndeleteNotFound "undelete_not_found" "Unable to restore {}: not found in previous directories or its contents are corrupted",
+	UndeleteExists "undelete_exists" "Unable to restore {}: path already exists",
+	Undeleted "undeleted" "Restored {} from generation {}",
+	GraftDirExists "graft_dir_exists" "Unable to graft {}: a directory already exists at the path",
+	BackingUp "backing_up" "Error backing up {} to {}: {}",
+	RestoredBackup "restored_backup" "Restored {} from {}",
+	SkipSymlink "skip_symlink" "Warning skipping {}: symbolic link",
+	SkipSymlinkCycle "skip_symlink_cycle" "Warning skipping {}: symbolic link cycle",
+	SkipNotFile "skip_not_file" "Warning skipping {}: not a file or directory",
+	CopyCollision "copy_collision" "Warning {} is copied from both {} and {}, keeping the last",
+	CopyCollisionStrict "copy_collision_strict" "Error {} is copied from both {} and {}",
+	CopyExists "copy_exists" "Warning {} already exists in the PAKS archive, replaced by {}",
+	CopyExistsStrict "copy_exists_strict" "Error {} already exists in the PAKS archive, not copying {}",
+	DryRunAdd "dry_run_add" "Would add {} ({} bytes)",
+	DryRunCopy "dry_run_copy" "Would copy {} to {} ({} bytes)",
+	DryRunLink "dry_run_link" "Would link {} to {}",
+	DryRunRemove "dry_run_remove" "Would remove {}",
+	DryRunMove "dry_run_move" "Would move {} to {}",
+	DryRunSize "dry_run_size" "{} is {} bytes, would be {} bytes ({} bytes)",
+	GrepMatch "grep_match" "{}:{}",
+	HashDigest "hash_digest" "{}  {}",
+	FsckOk "fsck_ok" "No errors found!",
+	FsckErrors "fsck_errors" "PAKS file contains errors:",
+	FsckLog "fsck_log" "{}",
+	FsckNotPortable "fsck_not_portable" "PAKS file cannot be extracted on all operating systems:",
+	PatchCreated "patch_created" "Created {} with {} operations ({} bytes)",
+	PatchApplied "patch_applied" "Applied {} to {}",
+	ScriptSyntax "script_syntax" "Error {} line {}: {}",
+	ScriptAborted "script_aborted" "Error {} line {}: the script was aborted, the PAKS archive is unchanged",
+	SessionOpen "session_open" "Error {}: a session is already open, see `pakscmd help commit`",
+	SessionNotOpen "session_not_open" "Error {}: no session is open, see `pakscmd help begin`",
+	SessionCommitted "session_committed" "Committed {} edits to {}",
+	PatchVerified "patch_verified" "Verified {} files, no errors found!",
+	UpToDate "up_to_date" "{} is already in the newest file format.",
+	IdentifyPaks "identify_paks" "{}: PAKS archive version {}",
+	IdentifyUnsupported "identify_unsupported" "{}: PAKS archive unsupported version {}",
+	IdentifyUnknown "identify_unknown" "{}: unknown (not a PAKS archive or stealth mode)",
+	KeyCorrect "key_correct" "{}: the key is correct",
+	KeyIncorrect "key_incorrect" "Error {}: the key is incorrect",
+	KeySlotNotFound "key_slot_not_found" "Error {}: the user key has no key slot",
+}
+
+/// Renders the message in the current language.
+pub fn render(level: Level, msg: Msg, args: &[&dyn fmt::Display]) -> String {
+	let mut s = String::new();
+	match lang() {
+		Lang::Machine => {
+			s.push_str(match level {
+				Level::Error => "error",
+				Level::Warning => "warning",
+				Level::Info => "info",
+			});
+			s.push('\t');
+			s.push_str(msg.id());
+			for arg in args {
+				s.push('\t');
+				for chr in arg.to_string().chars() {
+					match chr {
+						'\\' => s.push_str("\\\\"),
+						'\t' => s.push_str("\\t"),
+						'\n' => s.push_str("\\n"),
+						'\r' => s.push_str("\\r"),
+						chr => s.push(chr),
+					}
+				}
+			}
+		},
+		lang => {
+			let mut args = args.iter();
+			let mut parts = msg.text(lang).split("{}");
+			if let Some(part) = parts.next() {
+				s.push_str(part);
+			}
+			for part in parts {
+				if let Some(arg) = args.next() {
+					let _ = write!(s, "{}", arg);
+				}
+				s.push_str(part);
+			}
+		},
+	}
+	s
+}
+
+/// Prints the message in the current language.
+///
+/// Errors and warnings are printed to stderr, info messages to stdout.
+pub fn emit(level: Level, msg: Msg, args: &[&dyn fmt::Display]) {
+	let s = render(level, msg, args);
+	match level {
+		Level::Error | Level::Warning => eprintln!("{}", s),
+		Level::Info => println!("{}", s),
+	}
+}
+
+macro_rules! error {
+	($msg:ident $(, $arg:expr)* $(,)?) => {
+		$crate::msg::emit($crate::msg::Level::Error, $crate::msg::Msg::$msg, &[$(&$arg as &dyn std::fmt::Display),*])
+	};
+}
+
+macro_rules! warning {
+	($msg:ident $(, $arg:expr)* $(,)?) => {
+		$crate::msg::emit($crate::msg::Level::Warning, $crate::msg::Msg::$msg, &[$(&$arg as &dyn std::fmt::Display),*])
+	};
+}
+
+macro_rules! info {
+	($msg:ident $(, $arg:expr)* $(,)?) => {
+		$crate::msg::emit($crate::msg::Level::Info, $crate::msg::Msg::$msg, &[$(&$arg as &dyn std::fmt::Display),*])
+	};
+}
+
+#[test]
+fn test_render() {
+	assert_eq!(Lang::parse("en"), Some(Lang::En));
+	assert_eq!(Lang::parse("machine"), Some(Lang::Machine));
+	assert_eq!(Lang::parse("fr"), None);
+
+	let args: [&dyn fmt::Display; 3] = [&"a\tb", &"c\\d\r\ne", &7];
+	assert_eq!(render(Level::Error, Msg::Patching, &args[..2]), "Error patching a\tb: c\\d\r\ne");
+	assert_eq!(render(Level::Info, Msg::FsckOk, &[]), "No errors found!");
+
+	// The machine-readable format prints the id and escapes the arguments
+	set_lang(Lang::Machine);
+	let machine = render(Level::Error, Msg::Patching, &args[..2]);
+	let info = render(Level::Info, Msg::Undeleted, &[&"file", &args[2]]);
+	set_lang(Lang::En);
+	assert_eq!(machine, "error\tpatching\ta\\tb\tc\\\\d\\r\\ne");
+	assert_eq!(info, "info\tundeleted\tfile\t7");
+}
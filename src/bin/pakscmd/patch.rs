@@ -0,0 +1,156 @@
+/*!
+Implements the `patch` command.
+*/
+
+use super::*;
+
+pub const HELP_PATCH: &str = "\
+NAME
+    pakscmd-patch - Creates and applies patch files.
+
+SYNOPSIS
+    pakscmd [..] patch create <NEW> <PATCHFILE>
+    pakscmd [..] patch apply [--verify] <PATCHFILE>
+
+DESCRIPTION
+    Patch files update an installed PAKS archive to a newer version by shipping only the changed files.
+    Changed files are stored as a delta against their previous contents where that is smaller.
+    The patch file is encrypted with the key of the PAKS archive.
+
+    create   Creates the patch file updating this PAKS archive to the NEW PAKS archive.
+             Both PAKS archives must use the same key.
+    apply    Applies the patch file to this PAKS archive in place.
+             The patch only applies to the exact PAKS archive it was created from.
+             PAKS archives with an archive id report a patch for another PAKS archive or another generation of it.
+             The written files are checked against their SHA-256 digest,
+             if anything fails the PAKS archive is rolled back to before the patch.
+
+ARGUMENTS
+    --verify   After applying, check the directory for errors and authenticate every file in the PAKS archive.
+    NEW        Path to the new PAKS archive.
+    PATCHFILE  Path to the patch file, conventionally with the extension `.pakspatch`.
+";
+
+pub fn patch(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	match args.positional[..] {
+		["create", new, patch_file] => {
+			let patch = match paks::patch::create_file(file, new, patch_file, key) {
+				Ok(patch) => patch,
+				Err(err) => return error!(Patching, patch_file, err),
+			};
+			let size = fs::metadata(patch_file).map(|metadata| metadata.len()).unwrap_or(0);
+			info!(PatchCreated, patch_file, patch.ops.len(), size);
+		},
+		["apply", patch_file] => {
+			if let Err(err) = paks::patch::apply_file(file, patch_file, key) {
+				return error!(Patching, file, err);
+			}
+			info!(PatchApplied, patch_file, file);
+			if args.has("--verify") {
+				verify(file, key);
+			}
+		},
+		_ => error!(InvalidSyntaxCmd, "patch"),
+	}
+}
+
+// Checks the directory for errors and authenticates every file
+// Returns if no errors were found
+fn verify(file: &str, key: &paks::Key) -> bool {
+	let reader = match paks::FileReader::open(file, key) {
+		Ok(reader) => reader,
+		Err(err) => {
+			error!(Opening, file, err);
+			return false;
+		},
+	};
+
+	let mut log = String::new();
+	if !reader.fsck(reader.high_mark(), &mut log) {
+		info!(FsckErrors);
+		for line in log.lines() {
+			info!(FsckLog, line);
+		}
+		return false;
+	}
+
+	let mut files = 0;
+	let mut errors = 0;
+	walk_files(&mut Vec::new(), reader.as_ref(), &mut |path, desc| {
+		match reader.read_data(desc, key) {
+			Ok(data) => {
+				files += 1;
+				info!(HashDigest, paks::hash::sha256(&data), String::from_utf8_lossy(path));
+			},
+			Err(err) => {
+				errors += 1;
+				error!(Reading, String::from_utf8_lossy(path), err);
+			},
+		}
+	});
+	if errors == 0 {
+		info!(PatchVerified, files);
+	}
+	errors == 0
+}
+
+#[test]
+fn test_patch_command() {
+	let dir = TestDir::new("patch");
+	let ref key = paks::parse_key("2a").unwrap();
+	let ref old = dir.archive("old.paks", key, &[("a", "old contents"), ("gone", "gone")]);
+	let (ref new, ref patch_file) = (dir.path("new.paks"), dir.path("update.pakspatch"));
+
+	// The new PAKS archive changes the contents, content types and plaintext of the files
+	fs::copy(old, new).unwrap();
+	let mut edit = paks::FileEditor::open(new, key).unwrap();
+	edit.create_file(b"a", b"new contents", key).unwrap();
+	edit.remove(b"gone").unwrap();
+	edit.edit_file(b"b").unwrap().set_content(9, 5).set_plaintext(true).allocate_data().write_data(b"plain", key).unwrap();
+	let mut content_types = paks::ContentTypes::new();
+	content_types.insert(9, "text/plain");
+	edit.set_content_types(&content_types, key).unwrap();
+	edit.finish(key).unwrap();
+
+	// A wrong number of arguments does nothing
+	patch(old, "2a", &cli::parse(&["create", new]));
+	assert!(!path::Path::new(patch_file).exists());
+
+	patch(old, "2a", &cli::parse(&["create", new, patch_file]));
+	let base = fs::read(old).unwrap();
+	patch(old, "2a", &cli::parse(&["--verify", "apply", patch_file]));
+	assert!(verify(old, key));
+
+	let reader = paks::FileReader::open(old, key).unwrap();
+	assert_eq!(reader.read(b"a", key).unwrap(), b"new contents");
+	assert!(reader.find_desc(b"gone").is_none());
+	let b = reader.find_file(b"b").unwrap();
+	assert!(b.is_plaintext());
+	assert_eq!(b.content_type, 9);
+	assert_eq!(reader.content_types(key).unwrap().get(9), Some("text/plain"));
+
+	// The patch does not apply to the patched PAKS archive or to another base, neither is changed
+	let patched = fs::read(old).unwrap();
+	patch(old, "2a", &cli::parse(&["apply", patch_file]));
+	assert!(fs::read(old).unwrap() == patched);
+	let ref other = dir.path("other.paks");
+	fs::write(other, &base).unwrap();
+	let mut edit = paks::FileEditor::open(other, key).unwrap();
+	edit.create_file(b"a", b"other contents", key).unwrap();
+	edit.finish(key).unwrap();
+	let other_bytes = fs::read(other).unwrap();
+	patch(other, "2a", &cli::parse(&["apply", patch_file]));
+	assert!(fs::read(other).unwrap() == other_bytes);
+
+	// Verification finds corrupted files
+	let offset = reader.find_file(b"a").unwrap().section.offset as usize * paks::layout::BLOCK_SIZE;
+	let mut corrupt = patched;
+	corrupt[offset] ^= 1;
+	fs::write(old, &corrupt).unwrap();
+	assert!(!verify(old, key));
+}
@@ -0,0 +1,342 @@
+/*!
+Implements the `run` command.
+
+The whole script is parsed before the PAKS archive is opened.
+The edits share a single editor which is finished once, when every line of the script succeeded.
+*/
+
+use super::*;
+
+pub const HELP_RUN: &str = "\
+NAME
+    pakscmd-run - Runs a script of edits to the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] run [--dry-run] <SCRIPT>
+
+DESCRIPTION
+    Runs the edits listed in the script within a single open and finish of the PAKS archive.
+    The directory is written once instead of after every edit, which adds up for hundreds of edits.
+
+    The script has one command per line, `#` starts a comment line.
+    Arguments are separated by whitespace, double quotes group an argument containing whitespace
+    and a backslash escapes the next character within double quotes:
+
+        # Packing step
+        add textures/wall.png art/wall.png
+        add --plaintext \"music/main theme.ogg\" \"audio/main theme.ogg\"
+        link textures/wall.png textures/wall_old.png
+        mv config.json settings/config.json
+        rm obsolete.txt
+
+    The commands are:
+        add [--plaintext] [--delta] [--sniff] <PATH> <SRCFILE>
+                      Adds the file SRCFILE on the file system to the PAKS archive at PATH.
+        link <SRC> <DEST>..
+                      Links the file SRC from the paths DEST.
+        rm <PATH>..   Removes the paths.
+        mv <SRC> <DEST>
+                      Moves the file SRC to DEST.
+
+    The script stops at the first error and the PAKS archive is left unchanged.
+
+OPTIONS
+    --dry-run    Print the edits and the resulting size of the PAKS archive without writing.
+
+ARGUMENTS
+    SCRIPT   Path to the script, or `-` to read the script from stdin.
+";
+
+/// Command allowed in the script.
+struct ScriptCommand {
+	name: &'static str,
+	flags: &'static [&'static str],
+	min_args: usize,
+	max_args: usize,
+	syntax: &'static str,
+}
+
+static SCRIPT_COMMANDS: &[ScriptCommand] = &[
+	ScriptCommand { name: "add", flags: &["--plaintext", "--delta", "--sniff"], min_args: 2, max_args: 2, syntax: "<PATH> <SRCFILE>" },
+	ScriptCommand { name: "link", flags: &[], min_args: 1, max_args: usize::MAX, syntax: "<SRC> [DEST].." },
+	ScriptCommand { name: "rm", flags: &[], min_args: 1, max_args: usize::MAX, syntax: "<PATH>.." },
+	ScriptCommand { name: "mv", flags: &[], min_args: 2, max_args: 2, syntax: "<SRC> <DEST>" },
+];
+
+pub fn run(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let script_path = match args.positional[..] {
+		[script_path] => script_path,
+		_ => return error!(Expecting, "<SCRIPT>"),
+	};
+
+	let script = if script_path == "-" {
+		let mut script = String::new();
+		if let Err(err) = io::stdin().read_to_string(&mut script) {
+			return error!(ReadingStdin, err);
+		}
+		script
+	}
+	else {
+		match fs::read_to_string(script_path) {
+			Ok(script) => script,
+			Err(err) => return error!(Reading, script_path, err),
+		}
+	};
+
+	let script_name = if script_path == "-" { "stdin" } else { script_path };
+	execute(file, key, script_name, &script, args.has("--dry-run"));
+}
+
+/// Runs the script, returns the number of edits if every line of the script succeeded and the PAKS archive was written.
+///
+/// Simulates the edits and returns `None` when `dry_run` is set.
+pub fn execute(file: &str, key: &paks::Key, script_name: &str, script: &str, dry_run: bool) -> Option<usize> {
+	// Check the syntax of every line before editing
+	let mut lines = Vec::new();
+	for (line_nr, line) in script.lines().enumerate() {
+		let line_nr = line_nr + 1;
+		match split_args(line) {
+			Ok(words) if words.is_empty() => (),
+			Ok(words) => lines.push((line_nr, words)),
+			Err(message) => {
+				error!(ScriptSyntax, script_name, line_nr, message);
+				return None;
+			},
+		}
+	}
+	let mut commands = Vec::new();
+	for (line_nr, words) in &lines {
+		let words: Vec<&str> = words.iter().map(|word| &**word).collect();
+		let matches = cli::parse(&words[1..]);
+		if let Err(message) = check(words[0], &matches) {
+			error!(ScriptSyntax, script_name, line_nr, message);
+			return None;
+		}
+		commands.push((*line_nr, words[0], matches));
+	}
+
+	let mut edit = dryrun::open(file, key, dry_run)?;
+
+	for (line_nr, name, matches) in &commands {
+		if !exec(&mut edit, name, matches, dry_run, key) {
+			error!(ScriptAborted, script_name, line_nr);
+			return None;
+		}
+	}
+
+	if dry_run {
+		dryrun::report(file, &edit);
+		return None;
+	}
+	match edit.finish(key) {
+		Ok(_) => Some(commands.len()),
+		Err(err) => {
+			error!(Writing, file, err);
+			None
+		},
+	}
+}
+
+/// Checks the command exists and accepts the flags and the number of arguments.
+fn check(name: &str, matches: &cli::Matches) -> Result<(), String> {
+	let cmd = match SCRIPT_COMMANDS.iter().find(|cmd| cmd.name == name) {
+		Some(cmd) => cmd,
+		None => return Err(format!("unknown command {}", name)),
+	};
+	match matches.error {
+		Some(cli::ParseError::Unknown(arg)) => return Err(format!("unknown argument {}", arg)),
+		Some(cli::ParseError::MissingValue(flag)) | Some(cli::ParseError::UnexpectedValue(flag)) => return Err(format!("{} does not accept {}", name, flag.name)),
+		None => (),
+	}
+	if let Some((flag, _)) = matches.flags.iter().find(|(flag, _)| !cmd.flags.contains(&flag.name)) {
+		return Err(format!("{} does not accept {}", name, flag.name));
+	}
+	let len = matches.positional.len();
+	if len < cmd.min_args || len > cmd.max_args {
+		return Err(format!("expecting {} {}", name, cmd.syntax));
+	}
+	Ok(())
+}
+
+/// Applies the command to the editor, returns false after printing the error.
+fn exec(edit: &mut paks::FileEditor, name: &str, matches: &cli::Matches, dry_run: bool, key: &paks::Key) -> bool {
+	match (name, &matches.positional[..]) {
+		("add", &[path, src]) => {
+			let data = match fs::read(src) {
+				Ok(data) => data,
+				Err(err) => {
+					error!(Reading, src, err);
+					return false;
+				},
+			};
+			if let Err(err) = add_data(edit, path, &data, matches, dry_run, key) {
+				error!(Creating, path, err);
+				return false;
+			}
+		},
+		("link", &[src_path, ref dest_paths @ ..]) => {
+			let src_desc = match edit.find_desc(src_path.as_bytes()) {
+				Some(desc) if !desc.is_dir() => *desc,
+				_ => {
+					error!(FileNotFound, src_path);
+					return false;
+				},
+			};
+			for &dest_path in dest_paths {
+				if let Err(err) = edit.create_link(dest_path.as_bytes(), &src_desc) {
+					error!(Linking, dest_path, err);
+					return false;
+				}
+				if dry_run {
+					info!(DryRunLink, src_path, dest_path);
+				}
+			}
+		},
+		("rm", paths) => {
+			for &path in paths {
+				if edit.remove(path.as_bytes()).is_none() {
+					error!(RemoveNotFound, path);
+					return false;
+				}
+				if dry_run {
+					info!(DryRunRemove, path);
+				}
+			}
+		},
+		("mv", &[src_path, dest_path]) => {
+			if !edit.move_file(src_path.as_bytes(), dest_path.as_bytes()) {
+				error!(FileNotFound, src_path);
+				return false;
+			}
+			if dry_run {
+				info!(DryRunMove, src_path, dest_path);
+			}
+		},
+		_ => unreachable!("the script was checked"),
+	}
+	true
+}
+
+/// Quotes the argument for the script, see [`split_args`].
+pub fn quote(arg: &str) -> String {
+	let mut s = String::from("\"");
+	for chr in arg.chars() {
+		if chr == '"' || chr == '\\' {
+			s.push('\\');
+		}
+		s.push(chr);
+	}
+	s.push('"');
+	s
+}
+
+/// Splits the line of the script into its arguments.
+///
+/// Returns no arguments for empty and comment lines.
+fn split_args(line: &str) -> Result<Vec<String>, &'static str> {
+	let line = line.trim();
+	if line.starts_with('#') {
+		return Ok(Vec::new());
+	}
+
+	let mut words = Vec::new();
+	let mut chars = line.chars().peekable();
+	while let Some(&chr) = chars.peek() {
+		if chr.is_whitespace() {
+			chars.next();
+			continue;
+		}
+		let mut word = String::new();
+		while let Some(chr) = chars.next_if(|chr| !chr.is_whitespace()) {
+			if chr != '"' {
+				word.push(chr);
+				continue;
+			}
+			loop {
+				match chars.next() {
+					Some('"') => break,
+					Some('\\') => match chars.next() {
+						Some(chr) => word.push(chr),
+						None => return Err("unterminated quote"),
+					},
+					Some(chr) => word.push(chr),
+					None => return Err("unterminated quote"),
+				}
+			}
+		}
+		words.push(word);
+	}
+	Ok(words)
+}
+
+#[test]
+fn test_split_args() {
+	assert_eq!(split_args("  # comment"), Ok(Vec::<String>::new()));
+	assert_eq!(split_args(""), Ok(Vec::<String>::new()));
+	assert_eq!(split_args("add  a/b \t c.txt"), Ok(vec!["add".to_string(), "a/b".to_string(), "c.txt".to_string()]));
+	assert_eq!(split_args(r#"mv "a b" x"y z"w"#), Ok(vec!["mv".to_string(), "a b".to_string(), "xy zw".to_string()]));
+	assert_eq!(split_args(r#"rm "a\"b\\c" """#), Ok(vec!["rm".to_string(), "a\"b\\c".to_string(), String::new()]));
+	assert_eq!(split_args(r#"rm "abc"#), Err("unterminated quote"));
+	assert_eq!(split_args(&format!("rm {} {}", quote("a \"b\" \\c"), quote(""))), Ok(vec!["rm".to_string(), "a \"b\" \\c".to_string(), String::new()]));
+
+	let matches = cli::parse(&["--plaintext", "a", "b"]);
+	assert_eq!(check("add", &matches), Ok(()));
+	assert!(check("link", &matches).is_err());
+	assert!(check("frob", &matches).is_err());
+	assert!(check("mv", &cli::parse(&["a"])).is_err());
+	assert!(check("rm", &cli::parse(&["--bogus", "a"])).is_err());
+}
+
+#[test]
+fn test_session_commit() {
+	let dir = TestDir::new("session_commit");
+	let ref key = paks::parse_key("2a").unwrap();
+	let ref file = dir.archive("archive.paks", key, &[("old.txt", "old")]);
+	let no_args = cli::parse(&[]);
+
+	assert!(!session::stage(file, "rm", &["old.txt"]));
+	session::begin(file, "2a", &no_args);
+	assert!(session::is_open(file));
+	assert!(session::stage_add(file, &["--plaintext"], "-dash/new.txt", b"new"));
+	assert!(session::stage(file, "rm", &["old.txt"]));
+
+	// The staged edits are not visible until committed
+	let reader = paks::FileReader::open(file, key).unwrap();
+	assert!(reader.find_file(b"old.txt").is_some());
+	assert!(reader.find_file(b"-dash/new.txt").is_none());
+
+	session::commit(file, "2a", &no_args);
+	assert!(!session::is_open(file));
+	assert!(!std::path::Path::new(&format!("{}.journal.d", file)).exists());
+	let reader = paks::FileReader::open(file, key).unwrap();
+	assert!(reader.find_file(b"old.txt").is_none());
+	assert!(reader.find_file(b"-dash/new.txt").unwrap().is_plaintext());
+	assert_eq!(reader.read(b"-dash/new.txt", key).unwrap(), b"new");
+}
+
+#[test]
+fn test_session_abort() {
+	let dir = TestDir::new("session_abort");
+	let ref key = paks::parse_key("2a").unwrap();
+	let ref file = dir.archive("archive.paks", key, &[("old.txt", "old")]);
+	let no_args = cli::parse(&[]);
+
+	session::begin(file, "2a", &no_args);
+	assert!(session::stage_add(file, &[], "new.txt", b"new"));
+	assert!(session::stage(file, "mv", &["old.txt", "moved.txt"]));
+	session::abort(file, "2a", &no_args);
+	assert!(!session::is_open(file));
+	assert!(!std::path::Path::new(&format!("{}.journal.d", file)).exists());
+
+	// Committing without a session changes nothing
+	session::commit(file, "2a", &no_args);
+	let reader = paks::FileReader::open(file, key).unwrap();
+	assert_eq!(reader.read(b"old.txt", key).unwrap(), b"old");
+	assert!(reader.find_file(b"new.txt").is_none());
+	assert!(reader.find_file(b"moved.txt").is_none());
+}
@@ -0,0 +1,204 @@
+/*!
+Implements the `begin`, `commit` and `abort` commands.
+
+While a session is open the `add`, `link`, `rm` and `mv` commands append to a journal next to the PAKS archive instead of editing it.
+The journal is a script for the `run` command, the contents of the added files are staged in a directory next to the journal.
+*/
+
+use std::path::Path;
+use super::*;
+
+pub const HELP_BEGIN: &str = "\
+NAME
+    pakscmd-begin - Opens a session staging the edits to the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] begin
+
+DESCRIPTION
+    Opens a session, the edits of the following commands are staged in the journal PAKFILE.journal
+    instead of being written to the PAKS archive:
+
+        add, link, rm, mv
+
+    The contents of the added files are staged in the directory PAKFILE.journal.d.
+    Other commands read and edit the PAKS archive as-is, the staged edits are not visible until committed.
+
+    The staged edits are applied at once with `pakscmd [..] commit`, the directory of the PAKS archive is written only once.
+    Discard the staged edits with `pakscmd [..] abort`.
+";
+
+pub const HELP_COMMIT: &str = "\
+NAME
+    pakscmd-commit - Applies the edits staged in the session.
+
+SYNOPSIS
+    pakscmd [..] commit [--dry-run] [--backup[=SUFFIX]]
+
+DESCRIPTION
+    Applies the edits staged since `pakscmd [..] begin` to the PAKS archive and closes the session.
+    The edits are applied as a script, see `pakscmd help run`.
+    If any edit fails the PAKS archive is left unchanged and the session stays open.
+
+OPTIONS
+    --dry-run    Print the edits and the resulting size of the PAKS archive without writing.
+                 The session stays open.
+    --backup[=SUFFIX]
+                 Copies the PAKS archive aside before applying the edits, see `pakscmd help`.
+                 The staged commands themselves never back up the PAKS archive.
+";
+
+pub const HELP_ABORT: &str = "\
+NAME
+    pakscmd-abort - Discards the edits staged in the session.
+
+SYNOPSIS
+    pakscmd [..] abort
+
+DESCRIPTION
+    Discards the edits staged since `pakscmd [..] begin` and closes the session.
+    The PAKS archive is left unchanged.
+";
+
+/// Commands staged in the journal while a session is open.
+pub const STAGED_COMMANDS: &[&str] = &["add", "link", "rm", "mv"];
+
+fn journal_path(file: &str) -> String {
+	format!("{}.journal", file)
+}
+
+fn staging_path(file: &str) -> String {
+	format!("{}.journal.d", file)
+}
+
+/// Returns if a session is open for the PAKS archive.
+pub fn is_open(file: &str) -> bool {
+	Path::new(&journal_path(file)).exists()
+}
+
+pub fn begin(file: &str, key: &str, _args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	// Catch an incorrect key before any edits are staged
+	if let Err(err) = paks::FileReader::open(file, key) {
+		return error!(Opening, file, err);
+	}
+
+	let journal = journal_path(file);
+	match fs::OpenOptions::new().write(true).create_new(true).open(&journal) {
+		Ok(_) => (),
+		Err(err) if err.kind() == io::ErrorKind::AlreadyExists => error!(SessionOpen, file),
+		Err(err) => error!(Writing, journal, err),
+	}
+}
+
+pub fn commit(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let journal = journal_path(file);
+	let script = match fs::read_to_string(&journal) {
+		Ok(script) => script,
+		Err(err) if err.kind() == io::ErrorKind::NotFound => return error!(SessionNotOpen, file),
+		Err(err) => return error!(Reading, journal, err),
+	};
+
+	if let Some(edits) = run::execute(file, key, &journal, &script, args.has("--dry-run")) {
+		close(file);
+		info!(SessionCommitted, edits, file);
+	}
+}
+
+pub fn abort(file: &str, _key: &str, _args: &cli::Matches) {
+	if !is_open(file) {
+		return error!(SessionNotOpen, file);
+	}
+	close(file);
+}
+
+fn close(file: &str) {
+	let staging = staging_path(file);
+	if let Err(err) = fs::remove_dir_all(&staging) {
+		if err.kind() != io::ErrorKind::NotFound {
+			error!(Writing, staging, err);
+		}
+	}
+	let journal = journal_path(file);
+	if let Err(err) = fs::remove_file(&journal) {
+		error!(Writing, journal, err);
+	}
+}
+
+/// Stages the command in the journal if a session is open.
+///
+/// Returns false if no session is open and the command edits the PAKS archive itself.
+pub fn stage(file: &str, name: &str, args: &[&str]) -> bool {
+	if !is_open(file) {
+		return false;
+	}
+	append(file, name, &[], args);
+	true
+}
+
+/// Stages adding the file in the journal if a session is open, see [`stage`].
+pub fn stage_add(file: &str, flags: &[&str], path: &str, data: &[u8]) -> bool {
+	if !is_open(file) {
+		return false;
+	}
+
+	// Number the staged contents after the files staged before
+	let staging = staging_path(file);
+	if let Err(err) = fs::create_dir_all(&staging) {
+		error!(Writing, staging, err);
+		return true;
+	}
+	let count = match fs::read_dir(&staging) {
+		Ok(entries) => entries.count(),
+		Err(err) => {
+			error!(Reading, staging, err);
+			return true;
+		},
+	};
+	let src = Path::new(&staging).join(format!("{:06}", count));
+	if let Err(err) = fs::write(&src, data) {
+		error!(Writing, src.display(), err);
+		return true;
+	}
+
+	// The journal may be committed from another working directory
+	let src = match fs::canonicalize(&src) {
+		Ok(src) => src.to_string_lossy().into_owned(),
+		Err(err) => {
+			error!(Reading, src.display(), err);
+			return true;
+		},
+	};
+
+	append(file, "add", flags, &[path, &src]);
+	true
+}
+
+fn append(file: &str, name: &str, flags: &[&str], args: &[&str]) {
+	// Paths starting with a dash are not flags after `--`
+	let mut line = String::from(name);
+	for flag in flags.iter().chain(&["--"]) {
+		line.push(' ');
+		line.push_str(flag);
+	}
+	for arg in args {
+		line.push(' ');
+		line.push_str(&run::quote(arg));
+	}
+	line.push('\n');
+
+	let journal = journal_path(file);
+	let result = fs::OpenOptions::new().append(true).open(&journal).and_then(|mut journal| journal.write_all(line.as_bytes()));
+	if let Err(err) = result {
+		error!(Writing, journal, err);
+	}
+}
@@ -0,0 +1,202 @@
+/*!
+Implements the `stats` command.
+*/
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::ops::Range;
+use paks::layout;
+use super::*;
+
+pub const HELP_STATS: &str = "\
+NAME
+    pakscmd-stats - Shows size statistics of the PAKS archive.
+
+SYNOPSIS
+    pakscmd [..] stats [--json] [--top N]
+
+DESCRIPTION
+    Shows the size of the PAKS archive, the number and size of the files per top-level directory,
+    the largest files and the bytes of garbage left behind by removed files.
+    Files at the root of the PAKS archive are counted under the directory `.`.
+
+    The digest of the directory changes when the files in the PAKS archive change,
+    compare it between builds to skip work when the contents are the same.
+
+    Linked files are counted under every path linking them but only once among the largest files.
+
+    With --json the statistics are written as a JSON object for tracking size budgets in CI:
+
+        {\"size\":N,\"files\":N,\"bytes\":N,\"garbage\":N,\"digest\":\"HEX\",
+         \"directories\":{\"DIR\":{\"files\":N,\"bytes\":N},..},
+         \"largest\":[{\"path\":\"PATH\",\"bytes\":N},..]}
+
+ARGUMENTS
+    --json   Write the statistics as JSON.
+    --top N  Number of largest files to show, defaults to 10.
+";
+
+pub fn stats(file: &str, key: &str, args: &cli::Matches) {
+	let ref key = match parse_key(key) {
+		Some(key) => key,
+		None => return,
+	};
+
+	let top = match args.value("--top") {
+		Some(value) => match value.parse() {
+			Ok(top) => top,
+			Err(_) => return error!(OptionInvalid, "--top", "N", value),
+		},
+		None => 10,
+	};
+
+	let reader = match paks::FileReader::open(file, key) {
+		Ok(reader) => reader,
+		Err(err) => return error!(Opening, file, err),
+	};
+	let size = match fs::metadata(file) {
+		Ok(metadata) => metadata.len(),
+		Err(err) => return error!(Reading, file, err),
+	};
+	let key_slots = paks::read_key_slots(file).unwrap_or_default();
+
+	let stats = Stats::collect(&reader, size, key_slots.len(), top);
+	let output = if args.has("--json") { stats.json() } else { stats.text() };
+	print!("{}", output);
+}
+
+#[derive(Default)]
+struct DirStats {
+	files: u64,
+	bytes: u64,
+}
+
+#[derive(Default)]
+struct Stats {
+	size: u64,
+	files: u64,
+	bytes: u64,
+	garbage: u64,
+	digest: String,
+	directories: BTreeMap<String, DirStats>,
+	largest: Vec<(String, u64)>,
+}
+
+impl Stats {
+	fn collect(reader: &paks::FileReader, size: u64, key_slots: usize, top: usize) -> Stats {
+		let mut stats = Stats { size, digest: reader.digest().to_string(), ..Stats::default() };
+
+		// Blocks in use by the header, the sections of the files, the directory, the copy of the header and the key slots
+		let file_blocks = (size / layout::BLOCK_SIZE as u64) as u32;
+		let slots_len = if key_slots == 0 { 0 } else { (key_slots * layout::KEY_SLOT_BLOCKS + 1) as u32 };
+		let slots_start = file_blocks.saturating_sub(slots_len);
+		let mut used = vec![0..layout::HEADER_BLOCKS as u32, slots_start..file_blocks];
+		if let Some(dir) = layout::directory_blocks(reader.info()) {
+			used.push(dir.start..u32::min(dir.end.saturating_add(layout::HEADER_BLOCKS as u32), slots_start));
+		}
+
+		walk_files(&mut Vec::new(), reader.as_ref(), &mut |path, desc| {
+			let path = String::from_utf8_lossy(path).into_owned();
+			let top_dir = match path.split_once('/') {
+				Some((top_dir, _)) => top_dir,
+				None => ".",
+			};
+			let dir_stats = stats.directories.entry(top_dir.to_string()).or_default();
+			dir_stats.files += 1;
+			dir_stats.bytes += desc.content_size as u64;
+			stats.files += 1;
+			stats.bytes += desc.content_size as u64;
+
+			if let Some(section) = layout::section_blocks(&desc.section).filter(|_| !desc.is_inline()) {
+				used.push(section);
+			}
+			if let Some((offset, size)) = desc.delta_base() {
+				used.push(offset..offset.saturating_add(size));
+			}
+		});
+
+		stats.largest = reader.top_n_by_size(top).into_iter()
+			.map(|(path, size)| (String::from_utf8_lossy(&path).into_owned(), size as u64))
+			.collect();
+		stats.garbage = (file_blocks as u64).saturating_sub(union_len(&mut used)) * layout::BLOCK_SIZE as u64;
+		stats
+	}
+
+	fn text(&self) -> String {
+		let mut s = String::new();
+		let _ = writeln!(s, "size         {} bytes", self.size);
+		let _ = writeln!(s, "files        {} ({} bytes)", self.files, self.bytes);
+		let _ = writeln!(s, "garbage      {} bytes", self.garbage);
+		let _ = writeln!(s, "digest       {}", self.digest);
+		s.push_str("\ndirectories\n");
+		for (dir, dir_stats) in &self.directories {
+			let _ = writeln!(s, "  {:<12} {} files, {} bytes", dir, dir_stats.files, dir_stats.bytes);
+		}
+		s.push_str("\nlargest files\n");
+		for (path, bytes) in &self.largest {
+			let _ = writeln!(s, "  {:>12}  {}", bytes, path);
+		}
+		s
+	}
+
+	fn json(&self) -> String {
+		let mut s = String::new();
+		let _ = write!(s, "{{\"size\":{},\"files\":{},\"bytes\":{},\"garbage\":{},\"digest\":\"{}\",\"directories\":{{", self.size, self.files, self.bytes, self.garbage, self.digest);
+		for (i, (dir, dir_stats)) in self.directories.iter().enumerate() {
+			if i > 0 {
+				s.push(',');
+			}
+			let _ = write!(s, "{}:{{\"files\":{},\"bytes\":{}}}", json_string(dir), dir_stats.files, dir_stats.bytes);
+		}
+		s.push_str("},\"largest\":[");
+		for (i, (path, bytes)) in self.largest.iter().enumerate() {
+			if i > 0 {
+				s.push(',');
+			}
+			let _ = write!(s, "{{\"path\":{},\"bytes\":{}}}", json_string(path), bytes);
+		}
+		s.push_str("]}\n");
+		s
+	}
+}
+
+// Returns the number of blocks covered by the ranges
+fn union_len(ranges: &mut [Range<u32>]) -> u64 {
+	ranges.sort_by_key(|range| range.start);
+	let mut len = 0;
+	let mut covered = 0;
+	for range in ranges.iter() {
+		let start = u32::max(range.start, covered);
+		if range.end > start {
+			len += (range.end - start) as u64;
+			covered = range.end;
+		}
+	}
+	len
+}
+
+// Quotes and escapes the string as a JSON string
+fn json_string(text: &str) -> String {
+	let mut s = String::with_capacity(text.len() + 2);
+	s.push('"');
+	for chr in text.chars() {
+		match chr {
+			'"' => s.push_str("\\\""),
+			'\\' => s.push_str("\\\\"),
+			'\n' => s.push_str("\\n"),
+			'\r' => s.push_str("\\r"),
+			'\t' => s.push_str("\\t"),
+			chr if chr.is_control() => { let _ = write!(s, "\\u{:04x}", chr as u32); },
+			chr => s.push(chr),
+		}
+	}
+	s.push('"');
+	s
+}
+
+#[test]
+fn test_stats_helpers() {
+	assert_eq!(union_len(&mut [5..10, 0..5, 8..12, 20..21, 9..9]), 13);
+	assert_eq!(union_len(&mut []), 0);
+	assert_eq!(json_string("a\"b\\c\n\x01é"), "\"a\\\"b\\\\c\\n\\u0001é\"");
+}
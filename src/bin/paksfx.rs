@@ -0,0 +1,105 @@
+/*!
+Self-extracting PAKS archive stub.
+
+`pakscmd selfextract` appends a PAKS archive to a copy of this executable.
+When run, the embedded PAKS archive is extracted to the given directory.
+*/
+
+use std::{env, fs, path, process};
+
+const HELP: &str = "\
+Extracts the embedded PAKS archive.
+
+USAGE
+    {} <KEY> <DEST>
+
+ARGUMENTS
+    KEY      The 128-bit encryption key encoded in hex.
+    DEST     The directory on the file system to extract to.
+";
+
+fn main() {
+	let args: Vec<_> = env::args().collect();
+	let (key, dest) = match &args[..] {
+		[_, key, dest] => (key, path::Path::new(dest)),
+		_ => {
+			let exe = args.first().map(|exe| &**exe).unwrap_or("paksfx");
+			eprint!("{}", HELP.replace("{}", exe));
+			process::exit(2);
+		},
+	};
+
+	let ref key = match paks::parse_key(key) {
+		Ok(key) => key,
+		Err(err) => {
+			eprintln!("Error parsing key argument: {}", err);
+			process::exit(2);
+		},
+	};
+
+	let exe = match env::current_exe() {
+		Ok(exe) => exe,
+		Err(err) => {
+			eprintln!("Error locating the executable: {}", err);
+			process::exit(1);
+		},
+	};
+
+	let reader = match paks::FileReader::open_embedded(&exe, key) {
+		Ok(reader) => reader,
+		Err(err) => {
+			eprintln!("Error opening {}: {}", exe.display(), err);
+			process::exit(1);
+		},
+	};
+
+	let mut errors = 0;
+	extract_rec(&reader, dest, &mut Vec::new(), reader.as_ref(), key, &mut errors);
+	if errors > 0 {
+		process::exit(1);
+	}
+}
+
+fn extract_rec(reader: &paks::FileReader, dest: &path::Path, path: &mut Vec<u8>, children: &[paks::Descriptor], key: &paks::Key, errors: &mut u32) {
+	let mut i = 0;
+	while i < children.len() {
+		let desc = &children[i];
+		i += 1;
+
+		let path_len = path.len();
+		if path_len > 0 {
+			path.push(b'/');
+		}
+		path.extend_from_slice(desc.name());
+
+		if desc.is_dir() {
+			let end = usize::min(children.len(), i + desc.content_size as usize);
+			match paks::safe_join(dest, path).and_then(fs::create_dir_all) {
+				Ok(()) => extract_rec(reader, dest, path, &children[i..end], key, errors),
+				Err(err) => {
+					eprintln!("Error extracting {}: {}", String::from_utf8_lossy(path), err);
+					*errors += 1;
+				},
+			}
+			i = end;
+		}
+		// The archive metadata is not extracted
+		else if !paks::is_metadata_path(path) {
+			if let Err(err) = extract_file(reader, dest, path, desc, key) {
+				eprintln!("Error extracting {}: {}", String::from_utf8_lossy(path), err);
+				*errors += 1;
+			}
+		}
+
+		path.truncate(path_len);
+	}
+}
+
+fn extract_file(reader: &paks::FileReader, dest: &path::Path, path: &[u8], desc: &paks::Descriptor, key: &paks::Key) -> std::io::Result<()> {
+	let file_path = paks::safe_join(dest, path)?;
+	let data = reader.read_data(desc, key)?;
+	if let Some(parent) = file_path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	fs::write(&file_path, data)
+}
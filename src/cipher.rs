@@ -5,7 +5,7 @@ Speck128/128
 <https://nsacyber.github.io/simon-speck/implementations/ImplementationGuide1.1.pdf>
 */
 
-const ROUNDS: usize = 32;
+pub const ROUNDS: usize = 32;
 
 macro_rules! R {
 	($x:expr, $y:expr, $k:expr) => {
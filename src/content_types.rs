@@ -0,0 +1,148 @@
+use super::*;
+
+/// Path of the content type names in the PAKS file.
+///
+/// The content types of files are numbers left to the user, the names keep the PAKS archive self-describing across tools.
+/// They are stored as a regular file with one `id = name` per line, see [`MemoryReader::content_types`] and [`MemoryEditor::set_content_types`].
+pub const CONTENT_TYPES_PATH: &[u8] = b".content-types";
+
+/// Names of the content types, eg. `2 = texture/bc7`.
+///
+/// See [`CONTENT_TYPES_PATH`] for how the names are stored in the PAKS archive.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ContentTypes {
+	// Sorted by content type
+	names: Vec<(u32, String)>,
+}
+
+impl ContentTypes {
+	/// Creates a new empty table.
+	#[inline]
+	pub fn new() -> ContentTypes {
+		ContentTypes { names: Vec::new() }
+	}
+
+	/// Returns the name of the content type.
+	pub fn get(&self, content_type: u32) -> Option<&str> {
+		let index = self.names.binary_search_by_key(&content_type, |&(id, _)| id).ok()?;
+		Some(&self.names[index].1)
+	}
+
+	/// Returns the content type with the name.
+	pub fn find(&self, name: &str) -> Option<u32> {
+		self.names.iter().find(|(_, other)| other == name).map(|&(id, _)| id)
+	}
+
+	/// Names the content type, returns the previous name.
+	///
+	/// The name is checked when the table is stored, see [`MemoryEditor::set_content_types`].
+	pub fn insert(&mut self, content_type: u32, name: &str) -> Option<String> {
+		match self.names.binary_search_by_key(&content_type, |&(id, _)| id) {
+			Ok(index) => Some(mem::replace(&mut self.names[index].1, name.to_string())),
+			Err(index) => {
+				self.names.insert(index, (content_type, name.to_string()));
+				None
+			},
+		}
+	}
+
+	/// Removes the name of the content type.
+	pub fn remove(&mut self, content_type: u32) -> Option<String> {
+		let index = self.names.binary_search_by_key(&content_type, |&(id, _)| id).ok()?;
+		Some(self.names.remove(index).1)
+	}
+
+	/// Returns the content types and their names in order of the content type.
+	pub fn iter(&self) -> impl Iterator<Item = (u32, &str)> + '_ {
+		self.names.iter().map(|(id, name)| (*id, &**name))
+	}
+
+	/// Returns the number of named content types.
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.names.len()
+	}
+
+	/// Returns if no content types are named.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.names.is_empty()
+	}
+
+	/// Returns the name of the content type, or its number if it has no name.
+	pub fn describe(&self, content_type: u32) -> String {
+		match self.get(content_type) {
+			Some(name) => name.to_string(),
+			None => content_type.to_string(),
+		}
+	}
+}
+
+// Encodes the names one `id = name` per line
+// Directories have no content type and the names must survive the trimming when decoded
+pub(crate) fn encode(content_types: &ContentTypes) -> Result<Vec<u8>, Error> {
+	let mut data = Vec::new();
+	for (id, name) in content_types.iter() {
+		if id == 0 || name.is_empty() || name.contains('\n') || name.trim() != name {
+			return Err(Error::with_path(ErrorKind::InvalidInput, name.as_bytes()));
+		}
+		data.extend_from_slice(format!("{} = {}\n", id, name).as_bytes());
+	}
+	Ok(data)
+}
+
+// Decodes the names, lines which do not parse are ignored
+pub(crate) fn decode(data: &[u8]) -> ContentTypes {
+	let mut content_types = ContentTypes::new();
+	for line in String::from_utf8_lossy(data).lines() {
+		if let Some((id, name)) = line.split_once('=') {
+			if let Ok(id) = id.trim().parse() {
+				content_types.insert(id, name.trim());
+			}
+		}
+	}
+	content_types
+}
+
+#[test]
+fn test_content_types() {
+	let mut content_types = ContentTypes::new();
+	assert_eq!(content_types.insert(3, "audio/ogg"), None);
+	assert_eq!(content_types.insert(2, "texture/bc7"), None);
+	assert_eq!(content_types.insert(3, "audio/vorbis"), Some("audio/ogg".to_string()));
+	assert_eq!(content_types.iter().collect::<Vec<_>>(), [(2, "texture/bc7"), (3, "audio/vorbis")]);
+	assert_eq!(content_types.find("audio/vorbis"), Some(3));
+	assert_eq!(content_types.describe(2), "texture/bc7");
+	assert_eq!(content_types.describe(7), "7");
+
+	let data = encode(&content_types).unwrap();
+	assert_eq!(data, b"2 = texture/bc7\n3 = audio/vorbis\n");
+	assert_eq!(decode(&data), content_types);
+	assert_eq!(decode(b"garbage\n4=a = b\n"), decode(b"4 = a = b"));
+
+	let mut invalid = ContentTypes::new();
+	invalid.insert(0, "dir");
+	assert_eq!(encode(&invalid).unwrap_err().kind(), ErrorKind::InvalidInput);
+	invalid.remove(0);
+	invalid.insert(1, " padded");
+	assert!(encode(&invalid).is_err());
+
+	// Stored in the PAKS archive
+	let ref key = [13, 42];
+	let mut edit = MemoryEditor::new();
+	edit.set_content_types(&content_types, key).unwrap();
+	assert_eq!(edit.content_types(key).unwrap(), content_types);
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.content_types(key).unwrap(), content_types);
+
+	// Stripping the names keeps the content types
+	let mut edit = MemoryEditor::from(reader);
+	edit.strip_names();
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.content_types(key).unwrap(), content_types);
+	let mut edit = MemoryEditor::from(reader);
+	edit.set_content_types(&ContentTypes::new(), key).unwrap();
+	assert!(edit.find_file(CONTENT_TYPES_PATH).is_none());
+}
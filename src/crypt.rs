@@ -1,8 +1,8 @@
-/*
+/*!
 Authenticated Encryption
 ========================
 
-Bring Your Own Authenticated Encryption based on https://eprint.iacr.org/2019/712.pdf
+Bring Your Own Authenticated Encryption based on <https://eprint.iacr.org/2019/712.pdf>
 
 The chosen cipher is Speck128/128, the mode of operation is CTR.
 The authentication is CBC-MAC over the ciphertext.
@@ -25,6 +25,15 @@ The authentication is CBC-MAC over the ciphertext.
             ▼                              ▼              │
         NONCE(n+1)                      MAC(n+1)        MAC(n)
 ```
+
+Every section derives its own encryption and authentication keys from its random nonce, see [`Section`].
+
+Auxiliary files, eg. config sidecars, can be encrypted with the same scheme using [`seal`] and [`open`].
+
+# Stability
+
+The output of [`seal`] is the encryption of a section of the PAKS file format version 1.
+It will decrypt with [`open`] in every future release which reads this version of the PAKS file format.
 */
 
 use super::*;
@@ -35,23 +44,50 @@ fn xor(a: Block, b: Block) -> Block {
 fn counter(nonce: Block, i: usize) -> Block {
 	[nonce[0], nonce[1].wrapping_add(i as u64)]
 }
-fn random(blocks: &mut [Block]) {
-	if let Err(_) = getrandom::fill(dataview::bytes_mut(blocks)) {
-		random_error()
-	}
+fn random(blocks: &mut [Block]) -> Result<(), ErrorKind> {
+	getrandom::fill(dataview::bytes_mut(blocks)).map_err(random_error)
 }
 
+// Maps the failure of the random number generator to the closest error kind
 #[inline(never)]
 #[cold]
-fn random_error() -> ! {
-	panic!("random unavailable")
+fn random_error(err: getrandom::Error) -> ErrorKind {
+	if err == getrandom::Error::UNSUPPORTED {
+		return ErrorKind::Unsupported;
+	}
+	match err.raw_os_error() {
+		Some(code) => std::io::Error::from_raw_os_error(code).kind(),
+		None => ErrorKind::Other,
+	}
+}
+
+pub(crate) fn random_nonce() -> Result<Block, ErrorKind> {
+	let mut nonce = Block::default();
+	random(slice::from_mut(&mut nonce))?;
+	Ok(nonce)
 }
 
+// Takes the next nonce from the nonce source, or a random nonce without one
+#[inline]
+pub(crate) fn next_nonce(nonces: Option<&dyn NonceSource>) -> Result<Block, ErrorKind> {
+	match nonces {
+		Some(nonces) => nonces.nonce(),
+		None => random_nonce(),
+	}
+}
+
+// Fails without touching the blocks if no nonce is available
 #[inline(never)]
-pub fn encrypt_section(blocks: &mut [Block], section: &mut Section, &key: &Key) {
-	// Every encryption reinitialize with a random nonce
-	random(slice::from_mut(&mut section.nonce));
+pub(crate) fn encrypt_section_from(nonces: Option<&dyn NonceSource>, blocks: &mut [Block], section: &mut Section, key: &Key) -> Result<(), ErrorKind> {
+	// Every encryption reinitialize with a fresh nonce
+	section.nonce = next_nonce(nonces)?;
+	encrypt_section_with_nonce(blocks, section, key);
+	Ok(())
+}
 
+// Encrypts the section with the nonce already assigned to the section
+// The caller is responsible for never reusing the nonce with the same key
+pub(crate) fn encrypt_section_with_nonce(blocks: &mut [Block], section: &mut Section, &key: &Key) {
 	// Derive new keys and nonces and expand the round keys
 	let rk = cipher::expand(key);
 	let rke = cipher::expand(cipher::encrypt(counter(section.nonce, 0), &rk));
@@ -70,7 +106,7 @@ pub fn encrypt_section(blocks: &mut [Block], section: &mut Section, &key: &Key)
 }
 
 #[inline(never)]
-pub fn decrypt_section(blocks: &mut [Block], section: &Section, &key: &Key) -> bool {
+pub(crate) fn decrypt_section(blocks: &mut [Block], section: &Section, &key: &Key) -> bool {
 	// Derive new keys and nonces and expand the round keys
 	let rk = cipher::expand(key);
 	let rke = cipher::expand(cipher::encrypt(counter(section.nonce, 0), &rk));
@@ -90,6 +126,209 @@ pub fn decrypt_section(blocks: &mut [Block], section: &Section, &key: &Key) -> b
 	section.mac[0] ^ mac[0] | section.mac[1] ^ mac[1] == 0
 }
 
+// Authenticates the section without decrypting it
+#[inline(never)]
+pub(crate) fn verify_section(blocks: &[Block], section: &Section, &key: &Key) -> bool {
+	let rk = cipher::expand(key);
+	let rkm = cipher::expand(cipher::encrypt(counter(section.nonce, 1), &rk));
+	let nm = cipher::encrypt(counter(section.nonce, 3), &rk);
+
+	let mut mac = nm;
+	for &ct in blocks {
+		mac = cipher::encrypt(xor(mac, ct), &rkm);
+	}
+
+	section.mac[0] ^ mac[0] | section.mac[1] ^ mac[1] == 0
+}
+
+/// Encrypts and authenticates the data with the key.
+///
+/// Returns the random nonce, the MAC and the ciphertext, the ciphertext has the same length as the data.
+/// Store all three to decrypt the data with [`open`].
+///
+/// The data is encrypted as a section padded with zeroes to a multiple of the block size, the ciphertext of the padding is omitted.
+///
+/// # Errors
+///
+/// Fails with the error of the random number generator of the operating system.
+pub fn seal(data: &[u8], key: &Key) -> Result<(Block, Block, Vec<u8>), Error> {
+	let size = data.len().div_ceil(BLOCK_SIZE);
+	let mut blocks = vec![Block::default(); size];
+	dataview::bytes_mut(blocks.as_mut_slice())[..data.len()].copy_from_slice(data);
+
+	let mut section = Section { offset: 0, size: size as u32, nonce: Block::default(), mac: Block::default() };
+	encrypt_section_from(None, &mut blocks, &mut section, key)?;
+
+	let mut ciphertext = dataview::bytes(blocks.as_slice()).to_vec();
+	ciphertext.truncate(data.len());
+	Ok((section.nonce, section.mac, ciphertext))
+}
+
+/// Authenticates and decrypts the ciphertext returned by [`seal`].
+///
+/// # Errors
+///
+/// * [`ErrorKind::InvalidData`]: The key is incorrect or the nonce, the MAC or the ciphertext were modified.
+pub fn open(nonce: &Block, mac: &Block, ciphertext: &[u8], key: &Key) -> Result<Vec<u8>, Error> {
+	let size = ciphertext.len().div_ceil(BLOCK_SIZE);
+	let mut blocks = vec![Block::default(); size];
+	dataview::bytes_mut(blocks.as_mut_slice())[..ciphertext.len()].copy_from_slice(ciphertext);
+
+	let section = Section { offset: 0, size: size as u32, nonce: *nonce, mac: *mac };
+	let mut cipher = SectionCipher::new(&section, key);
+
+	// The ciphertext of the zero padding is the keystream
+	let tail = ciphertext.len() % BLOCK_SIZE;
+	if tail != 0 {
+		let mut keystream = [Block::default()];
+		cipher.decrypt(size - 1, &mut keystream);
+		dataview::bytes_mut(&mut blocks[size - 1..])[tail..].copy_from_slice(&dataview::bytes(&keystream)[tail..]);
+	}
+
+	cipher.authenticate(&blocks);
+	if !cipher.verify(&section) {
+		return Err(Error::new(ErrorKind::InvalidData));
+	}
+	cipher.decrypt(0, &mut blocks);
+
+	let mut data = dataview::bytes(blocks.as_slice()).to_vec();
+	data.truncate(ciphertext.len());
+	Ok(data)
+}
+
+// Incremental decryption of a section
+// Allows decrypting parts of a section while authenticating the whole section in chunks
+pub(crate) struct SectionCipher {
+	rke: [u64; cipher::ROUNDS],
+	rkm: [u64; cipher::ROUNDS],
+	ne: Block,
+	mac: Block,
+}
+
+impl SectionCipher {
+	pub fn new(section: &Section, &key: &Key) -> SectionCipher {
+		// Derive new keys and nonces and expand the round keys
+		let rk = cipher::expand(key);
+		let rke = cipher::expand(cipher::encrypt(counter(section.nonce, 0), &rk));
+		let rkm = cipher::expand(cipher::encrypt(counter(section.nonce, 1), &rk));
+		let ne = cipher::encrypt(counter(section.nonce, 2), &rk);
+		let mac = cipher::encrypt(counter(section.nonce, 3), &rk);
+		SectionCipher { rke, rkm, ne, mac }
+	}
+
+	// Authenticates the next chunk of ciphertext, must be called for every block of the section in order
+	pub fn authenticate(&mut self, ct: &[Block]) {
+		for &ct in ct {
+			self.mac = cipher::encrypt(xor(self.mac, ct), &self.rkm);
+		}
+	}
+
+	// Decrypts the blocks inplace, the index is the position of the first block in the section
+	pub fn decrypt(&self, index: usize, blocks: &mut [Block]) {
+		for (i, block) in blocks.iter_mut().enumerate() {
+			*block = xor(cipher::encrypt(counter(self.ne, index + i), &self.rke), *block);
+		}
+	}
+
+	// Checks the MAC after all the ciphertext is authenticated
+	pub fn verify(&self, section: &Section) -> bool {
+		section.mac[0] ^ self.mac[0] | section.mac[1] ^ self.mac[1] == 0
+	}
+}
+
+// Authenticates the section and decrypts the byte range starting at the byte offset into dest
+// The chunks are read in order and the ciphertext is never copied in its entirety
+// The dest is zeroed if the authentication or reading a chunk fails, unverified plaintext is never left behind
+pub(crate) fn decrypt_range<E>(
+	section: &Section, key: &Key, byte_offset: usize, dest: &mut [mem::MaybeUninit<u8>],
+	mut read_chunk: impl FnMut(usize, &mut [Block]) -> Result<(), E>,
+) -> Result<bool, E> {
+	let mut cipher = SectionCipher::new(section, key);
+	let mut chunk = [Block::default(); 4096];
+	let byte_end = byte_offset.saturating_add(dest.len());
+
+	let mut index = 0;
+	while index < section.size as usize {
+		let len = usize::min(chunk.len(), section.size as usize - index);
+		let chunk = &mut chunk[..len];
+		if let Err(err) = read_chunk(index, chunk) {
+			dest.fill(mem::MaybeUninit::new(0));
+			return Err(err);
+		}
+		cipher.authenticate(chunk);
+
+		// Decrypt the part of the chunk overlapping the requested range
+		let chunk_start = index * BLOCK_SIZE;
+		let chunk_end = chunk_start + len * BLOCK_SIZE;
+		let start = usize::max(chunk_start, byte_offset);
+		let end = usize::min(chunk_end, byte_end);
+		if start < end {
+			let first = (start - chunk_start) / BLOCK_SIZE;
+			let last = (end - chunk_start - 1) / BLOCK_SIZE + 1;
+			cipher.decrypt(index + first, &mut chunk[first..last]);
+			let bytes = dataview::bytes(&chunk[first..last]);
+			let skip = start - chunk_start - first * BLOCK_SIZE;
+			dest[start - byte_offset..end - byte_offset].write_copy_of_slice(&bytes[skip..skip + (end - start)]);
+		}
+
+		index += len;
+	}
+
+	if !cipher.verify(section) {
+		dest.fill(mem::MaybeUninit::new(0));
+		return Ok(false);
+	}
+	Ok(true)
+}
+
+// Deterministic encryption of descriptor names
+// The synthetic nonce is the CBC-MAC of the name buffer including its length, the name is encrypted in CTR mode with this nonce
+// Equal names encrypt to equal ciphertexts which allows finding descriptors by their encrypted name
+// The length of the name is kept in plaintext
+pub(crate) fn encrypt_name(name: &mut Name, &key: &Key) -> Block {
+	let rk = cipher::expand(key);
+	let rkm = cipher::expand(cipher::encrypt([0, 1], &rk));
+	let rke = cipher::expand(cipher::encrypt([0, 2], &rk));
+
+	let nonce = name_mac(name, &rkm);
+	name_xor(name, nonce, &rke);
+	nonce
+}
+
+// Decrypts the name and authenticates it against the synthetic nonce
+// Returns false if the key is incorrect, the name is left in an unspecified state
+pub(crate) fn decrypt_name(name: &mut Name, nonce: &Block, &key: &Key) -> bool {
+	let rk = cipher::expand(key);
+	let rkm = cipher::expand(cipher::encrypt([0, 1], &rk));
+	let rke = cipher::expand(cipher::encrypt([0, 2], &rk));
+
+	name_xor(name, *nonce, &rke);
+	let mac = name_mac(name, &rkm);
+
+	nonce[0] ^ mac[0] | nonce[1] ^ mac[1] == 0
+}
+
+fn name_mac(name: &Name, rkm: &[u64; cipher::ROUNDS]) -> Block {
+	let mut blocks = [Block::default(); NAME_BUF_LEN.div_ceil(BLOCK_SIZE)];
+	dataview::bytes_mut(&mut blocks)[..NAME_BUF_LEN].copy_from_slice(&name.buffer);
+	let mut mac = Block::default();
+	for &pt in &blocks {
+		mac = cipher::encrypt(xor(mac, pt), rkm);
+	}
+	mac
+}
+
+fn name_xor(name: &mut Name, nonce: Block, rke: &[u64; cipher::ROUNDS]) {
+	let mut keystream = [Block::default(); NAME_BUF_LEN.div_ceil(BLOCK_SIZE)];
+	for (i, block) in keystream.iter_mut().enumerate() {
+		*block = cipher::encrypt(counter(nonce, i), rke);
+	}
+	let len = name.get().len();
+	for (byte, &ks) in name.buffer[..len].iter_mut().zip(dataview::bytes(&keystream)) {
+		*byte ^= ks;
+	}
+}
+
 #[test]
 fn test_roundtrip() {
 	let data = [[1, 2], [3, 4], [5, !0]];
@@ -104,30 +343,211 @@ fn test_roundtrip() {
 		mac: Block::default(),
 	};
 
-	encrypt_section(&mut blocks, &mut section, key);
+	encrypt_section_from(None, &mut blocks, &mut section, key).unwrap();
 	eprintln!("{:#?}", section);
 
+	assert!(verify_section(&blocks, &section, key));
+
+	// Decrypt an unaligned byte range
+	let mut dest = [0u8; 20];
+	let result = decrypt_range::<()>(&section, key, 7, uninit_bytes(&mut dest), |index, chunk| {
+		chunk.copy_from_slice(&blocks[index..index + chunk.len()]);
+		Ok(())
+	});
+	assert_eq!(result, Ok(true));
+	assert_eq!(&dest[..], &dataview::bytes(&data)[7..27]);
+
 	assert!(decrypt_section(&mut blocks, &section, key));
 	assert_eq!(data, blocks);
 }
 
+#[test]
+fn test_decrypt_range_read_error() {
+	let ref key = [13, 43];
+	let mut blocks = vec![[7, 7]; 5000];
+	let mut section = Section { offset: 0, size: blocks.len() as u32, nonce: Block::default(), mac: Block::default() };
+	encrypt_section_from(None, &mut blocks, &mut section, key).unwrap();
+
+	// Failing to read the second chunk leaves no plaintext of the first chunk behind
+	let mut dest = vec![1u8; 5000 * BLOCK_SIZE];
+	let result = decrypt_range(&section, key, 0, uninit_bytes(&mut dest), |index, chunk| {
+		if index > 0 {
+			return Err("read error");
+		}
+		chunk.copy_from_slice(&blocks[index..index + chunk.len()]);
+		Ok(())
+	});
+	assert_eq!(result, Err("read error"));
+	assert!(dest.iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn test_seal_open() {
+	let ref key = [13, 42];
+	for len in [0, 1, 15, 16, 17, 100] {
+		let data: Vec<u8> = (0..len as u8).collect();
+		let (nonce, mac, ciphertext) = seal(&data, key).unwrap();
+		assert_eq!(ciphertext.len(), data.len());
+		assert_eq!(open(&nonce, &mac, &ciphertext, key), Ok(data.clone()));
+		assert_eq!(open(&nonce, &mac, &ciphertext, &[13, 43]), Err(Error::new(ErrorKind::InvalidData)));
+		if len > 0 {
+			let mut tampered = ciphertext.clone();
+			tampered[len - 1] ^= 1;
+			assert_eq!(open(&nonce, &mac, &tampered, key), Err(Error::new(ErrorKind::InvalidData)));
+			assert_eq!(open(&nonce, &mac, &ciphertext[..len - 1], key), Err(Error::new(ErrorKind::InvalidData)));
+		}
+	}
+}
+
 #[inline]
-pub fn encrypt_header(header: &mut Header, key: &Key) {
+pub(crate) fn encrypt_header(header: &mut Header, key: &Key, magic: bool) -> Result<(), ErrorKind> {
+	encrypt_header_from(None, header, key, magic, 0)
+}
+
+pub(crate) fn encrypt_header_from(nonces: Option<&dyn NonceSource>, header: &mut Header, key: &Key, magic: bool, block_shift: u32) -> Result<(), ErrorKind> {
+	let mut section = Header::SECTION;
+	section.nonce = next_nonce(nonces)?;
 	header.info.version = InfoHeader::VERSION;
-	header.info._unused = 0;
-	let mut section = Section::default();
-	crypt::encrypt_section(header.info.as_mut(), &mut section, key);
+	header.info.key_check = InfoHeader::key_check_with(block_shift);
+	// The magic signature replaces the first half of the random nonce leaving only 64 random bits
+	// The keystream is derived from the MAC of the plaintext instead, which covers the 128 random bits of the directory nonce
+	if magic {
+		section.nonce[0] = Header::signature(InfoHeader::VERSION);
+		encrypt_synthetic(header.info.as_mut(), &mut section, key);
+	}
+	else {
+		encrypt_section_with_nonce(header.info.as_mut(), &mut section, key);
+	}
 	header.nonce = section.nonce;
 	header.mac = section.mac;
+	Ok(())
 }
 
+// Decrypts and authenticates the header
+// Returns PermissionDenied if the key is incorrect, InvalidData if the header is corrupted and Unsupported for unknown versions
 #[inline]
-pub fn decrypt_header(header: &mut Header, key: &Key) -> bool {
+pub(crate) fn decrypt_header(header: &mut Header, key: &Key) -> Result<(), ErrorKind> {
 	let section = Section {
 		nonce: header.nonce,
 		mac: header.mac,
 		..Header::SECTION
 	};
-	crypt::decrypt_section(header.info.as_mut(), &section, key)
-		&& header.info.version == InfoHeader::VERSION
+	let is_synthetic = header.nonce[0] == Header::signature(InfoHeader::VERSION_3) || header.nonce[0] == Header::signature(InfoHeader::VERSION_4);
+	let is_valid = if is_synthetic {
+		decrypt_synthetic(header.info.as_mut(), &section, key)
+	}
+	else {
+		crypt::decrypt_section(header.info.as_mut(), &section, key)
+	};
+
+	// Decrypting with the correct key reveals the known plaintext even if other parts of the header are corrupted
+	// Except for the MAC of headers with the magic signature, their keystream is derived from the MAC
+	let is_known = header.info.is_key_check_known();
+	if is_valid && is_known && InfoHeader::is_supported(header.info.version) {
+		Ok(())
+	}
+	else if is_valid {
+		Err(ErrorKind::Unsupported)
+	}
+	else if is_known && InfoHeader::is_supported(header.info.version) {
+		Err(ErrorKind::InvalidData)
+	}
+	else {
+		Err(ErrorKind::PermissionDenied)
+	}
+}
+
+// Encrypts the header with a synthetic nonce, the MAC is computed over the plaintext and the keystream is derived from the MAC
+// Headers with the same nonce only share their keystream if their plaintext is the same as well
+fn encrypt_synthetic(blocks: &mut [Block], section: &mut Section, &key: &Key) {
+	let rk = cipher::expand(key);
+	let rke = cipher::expand(cipher::encrypt(counter(section.nonce, 0), &rk));
+	let rkm = cipher::expand(cipher::encrypt(counter(section.nonce, 1), &rk));
+	let nm = cipher::encrypt(counter(section.nonce, 3), &rk);
+
+	let mut mac = nm;
+	for &pt in blocks.iter() {
+		mac = cipher::encrypt(xor(mac, pt), &rkm);
+	}
+
+	let ne = cipher::encrypt(mac, &rke);
+	for (i, block) in blocks.iter_mut().enumerate() {
+		*block = xor(cipher::encrypt(counter(ne, i), &rke), *block);
+	}
+	section.mac = mac;
+}
+
+fn decrypt_synthetic(blocks: &mut [Block], section: &Section, &key: &Key) -> bool {
+	let rk = cipher::expand(key);
+	let rke = cipher::expand(cipher::encrypt(counter(section.nonce, 0), &rk));
+	let rkm = cipher::expand(cipher::encrypt(counter(section.nonce, 1), &rk));
+	let nm = cipher::encrypt(counter(section.nonce, 3), &rk);
+
+	let ne = cipher::encrypt(section.mac, &rke);
+	let mut mac = nm;
+	for (i, block) in blocks.iter_mut().enumerate() {
+		let pt = xor(cipher::encrypt(counter(ne, i), &rke), *block);
+		mac = cipher::encrypt(xor(mac, pt), &rkm);
+		*block = pt;
+	}
+
+	// Constant-time comparison of the mac
+	section.mac[0] ^ mac[0] | section.mac[1] ^ mac[1] == 0
+}
+
+#[test]
+fn test_synthetic_header() {
+	// Repeats the same nonce for every section
+	struct Repeat;
+	impl NonceSource for Repeat {
+		fn nonce(&self) -> Result<Block, ErrorKind> {
+			Ok([1, 2])
+		}
+	}
+
+	let ref key = [3, 4];
+	let mut headers = [Header::default(); 2];
+	for (i, header) in headers.iter_mut().enumerate() {
+		header.info.directory = Section { offset: 5, size: 0, nonce: [i as u64, 6], mac: [7, 8] };
+		encrypt_header_from(Some(&Repeat), header, key, true, 0).unwrap();
+	}
+
+	// The nonces are the same but the keystream differs with the directory nonce
+	// The first block of the info header has the same plaintext and would have the same ciphertext with the same keystream
+	assert_eq!(headers[0].nonce, headers[1].nonce);
+	assert_eq!(identify(dataview::bytes(&headers[0])), Identify::Paks { version: InfoHeader::VERSION });
+	assert_ne!(dataview::bytes(&headers[0].info)[..BLOCK_SIZE], dataview::bytes(&headers[1].info)[..BLOCK_SIZE]);
+
+	for (i, header) in headers.iter().enumerate() {
+		let mut header = *header;
+		assert_eq!(decrypt_header(&mut header, key), Ok(()));
+		assert_eq!(header.info.directory.nonce, [i as u64, 6]);
+	}
+
+	let mut tampered = headers[0];
+	tampered.info.directory.offset ^= 1;
+	assert_eq!(decrypt_header(&mut tampered, key), Err(ErrorKind::InvalidData));
+	let mut wrong_key = headers[0];
+	assert_eq!(decrypt_header(&mut wrong_key, &[4, 3]), Err(ErrorKind::PermissionDenied));
+}
+
+#[test]
+fn test_header_block_shift() {
+	let ref key = [5, 6];
+	for block_shift in [0, 8, InfoHeader::MAX_BLOCK_SHIFT] {
+		for magic in [true, false] {
+			let mut header = Header::default();
+			encrypt_header_from(None, &mut header, key, magic, block_shift).unwrap();
+			assert_eq!(decrypt_header(&mut header, key), Ok(()));
+			assert_eq!((header.info.version, header.info.block_shift()), (InfoHeader::VERSION_4, block_shift));
+		}
+	}
+
+	// Older versions have no block size, out of range block shifts are not a known key check
+	let info = InfoHeader { version: InfoHeader::VERSION_3, key_check: InfoHeader::key_check_with(8), ..InfoHeader::default() };
+	assert_eq!(info.block_shift(), 0);
+	assert!(!info.is_key_check_known());
+	let info = InfoHeader { version: InfoHeader::VERSION_4, key_check: InfoHeader::key_check_with(InfoHeader::MAX_BLOCK_SHIFT + 1), ..InfoHeader::default() };
+	assert!(!info.is_key_check_known());
+	assert_eq!(InfoHeader::key_check_with(0), InfoHeader::KEY_CHECK);
 }
@@ -0,0 +1,281 @@
+/*!
+Binary delta encoding.
+
+A delta describes new contents as instructions copying byte ranges from base contents and inserting literal bytes.
+Matching byte ranges are found with a rolling hash over windows of [`WINDOW`] bytes, similar to rsync.
+
+The instructions are encoded one after another:
+
+* Copy: the byte `1` followed by the byte offset in the base contents and the length as `u32` little endian.
+* Insert: the byte `2` followed by the length as `u32` little endian and the literal bytes.
+
+Delta files store a delta against the contents of an older section, see [`Descriptor::FLAG_DELTA`].
+Their section starts with the nonce and MAC of the base section and the byte range of the base contents, followed by the delta.
+*/
+
+use std::collections::HashMap;
+use super::*;
+
+/// Size in bytes of the windows matched against the base contents.
+pub const WINDOW: usize = 32;
+
+const OP_COPY: u8 = 1;
+const OP_INSERT: u8 = 2;
+
+/// Computes the delta turning the base contents into the data.
+pub fn diff(base: &[u8], data: &[u8]) -> Vec<u8> {
+	let mut delta = Vec::new();
+
+	// Index the windows of the base contents by their hash
+	let mut index = HashMap::new();
+	for offset in (0..base.len().saturating_sub(WINDOW - 1)).step_by(WINDOW) {
+		index.entry(Rolling::new(&base[offset..offset + WINDOW]).hash()).or_insert(offset);
+	}
+
+	let mut literal = 0;
+	let mut i = 0;
+	let mut rolling = Rolling::new(&data[..usize::min(WINDOW, data.len())]);
+	while i + WINDOW <= data.len() {
+		let offset = match index.get(&rolling.hash()) {
+			Some(&offset) if base[offset..offset + WINDOW] == data[i..i + WINDOW] => offset,
+			_ => {
+				if i + WINDOW < data.len() {
+					rolling.roll(data[i], data[i + WINDOW]);
+				}
+				i += 1;
+				continue;
+			},
+		};
+
+		// Extend the match backwards into the pending literal bytes and forwards as far as possible
+		let back = data[literal..i].iter().rev().zip(base[..offset].iter().rev()).take_while(|(a, b)| a == b).count();
+		let forward = data[i + WINDOW..].iter().zip(&base[offset + WINDOW..]).take_while(|(a, b)| a == b).count();
+		let start = i - back;
+		let end = i + WINDOW + forward;
+
+		insert(&mut delta, &data[literal..start]);
+		copy(&mut delta, offset - back, end - start);
+
+		i = end;
+		literal = end;
+		if i + WINDOW <= data.len() {
+			rolling = Rolling::new(&data[i..i + WINDOW]);
+		}
+	}
+	insert(&mut delta, &data[literal..]);
+	delta
+}
+
+/// Applies the delta to the base contents.
+///
+/// Returns `None` if the delta is malformed, copies bytes outside the base contents or the contents grow larger than the content size.
+/// The contents are never reconstructed past the content size.
+pub fn apply(base: &[u8], mut delta: &[u8], content_size: u32) -> Option<Vec<u8>> {
+	let mut data = Vec::new();
+	let fits = |data: &Vec<u8>, len: usize| data.len().checked_add(len).is_some_and(|end| end <= content_size as usize);
+	while let [op, rest @ ..] = delta {
+		match *op {
+			OP_COPY => {
+				let offset = read_u32(rest, 0)? as usize;
+				let len = read_u32(rest, 4)? as usize;
+				if !fits(&data, len) {
+					return None;
+				}
+				data.extend_from_slice(base.get(offset..offset.checked_add(len)?)?);
+				delta = &rest[8..];
+			},
+			OP_INSERT => {
+				let len = read_u32(rest, 0)? as usize;
+				if !fits(&data, len) {
+					return None;
+				}
+				data.extend_from_slice(rest.get(4..4usize.checked_add(len)?)?);
+				delta = &rest[4 + len..];
+			},
+			_ => return None,
+		}
+	}
+	Some(data)
+}
+
+fn copy(delta: &mut Vec<u8>, offset: usize, len: usize) {
+	delta.push(OP_COPY);
+	delta.extend_from_slice(&(offset as u32).to_le_bytes());
+	delta.extend_from_slice(&(len as u32).to_le_bytes());
+}
+
+fn insert(delta: &mut Vec<u8>, bytes: &[u8]) {
+	if !bytes.is_empty() {
+		delta.push(OP_INSERT);
+		delta.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+		delta.extend_from_slice(bytes);
+	}
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+	let bytes = bytes.get(offset..offset + 4)?;
+	Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// Adler-32 style rolling hash over a window of bytes
+struct Rolling {
+	a: u32,
+	b: u32,
+}
+impl Rolling {
+	fn new(window: &[u8]) -> Rolling {
+		let mut rolling = Rolling { a: 0, b: 0 };
+		for &byte in window {
+			rolling.a = rolling.a.wrapping_add(byte as u32);
+			rolling.b = rolling.b.wrapping_add(rolling.a);
+		}
+		rolling
+	}
+	fn roll(&mut self, out: u8, new: u8) {
+		self.a = self.a.wrapping_sub(out as u32).wrapping_add(new as u32);
+		self.b = self.b.wrapping_sub(WINDOW as u32 * out as u32).wrapping_add(self.a);
+	}
+	fn hash(&self) -> u32 {
+		self.b << 16 ^ self.a & 0xffff
+	}
+}
+
+//----------------------------------------------------------------
+
+// Start of the section of a delta file
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+struct DeltaHeader {
+	base_nonce: Block,
+	base_mac: Block,
+	base_data_offset: u32,
+	base_content_size: u32,
+	delta_len: u32,
+	reserved: u32,
+}
+
+unsafe impl Pod for DeltaHeader {}
+
+const HEADER_LEN: usize = mem::size_of::<DeltaHeader>();
+
+/// The base contents of a delta file.
+pub(crate) struct Base {
+	pub section: Section,
+	pub data_offset: u32,
+	pub content_size: u32,
+}
+
+impl Base {
+	// Gets the base contents from the decrypted blocks of its section
+	pub fn data<'a>(&self, blocks: &'a [Block]) -> Option<&'a [u8]> {
+		let start = self.data_offset as usize;
+		dataview::bytes(blocks).get(start..start.checked_add(self.content_size as usize)?)
+	}
+}
+
+/// Can the file be replaced by a delta file against its contents?
+///
+/// Inline, plaintext and bundled files and files with extension records are replaced as a whole.
+/// So are files sharing their section with other files, editing those files inplace would break the delta.
+pub(crate) fn is_base(dir: &[Descriptor], desc: &Descriptor) -> bool {
+	if !desc.is_file() || desc.is_inline() || desc.is_plaintext() || desc.is_bundled() || desc.extensions().next().is_some() || desc.section.size == 0 {
+		return false;
+	}
+	// The base of a delta file is already exclusive to the delta file
+	if desc.is_delta() {
+		return true;
+	}
+	let section = (desc.section.offset, desc.section.size);
+	let shared = dir.iter().filter(|other| other.is_file() && !other.is_inline() && ((other.section.offset, other.section.size) == section || other.delta_base() == Some(section)));
+	shared.count() == 1
+}
+
+// Decrypts the section of the delta file and parses its header
+fn read_header<E: From<ErrorKind>>(desc: &Descriptor, read_section: &mut impl FnMut(&Section) -> Result<Vec<Block>, E>) -> Result<(Base, Vec<u8>), E> {
+	let (offset, size) = desc.delta_base().ok_or(ErrorKind::InvalidInput)?;
+	let blocks = read_section(&desc.section)?;
+	let payload = dataview::bytes(blocks.as_slice());
+	let header: DeltaHeader = dataview::DataView::from(payload).try_read(0).ok_or(ErrorKind::InvalidData)?;
+	let delta = payload.get(HEADER_LEN..HEADER_LEN + header.delta_len as usize).ok_or(ErrorKind::InvalidData)?;
+	let base = Base {
+		section: Section { offset, size, nonce: header.base_nonce, mac: header.base_mac },
+		data_offset: header.base_data_offset,
+		content_size: header.base_content_size,
+	};
+	Ok((base, delta.to_vec()))
+}
+
+/// Finds the base contents for a new delta file replacing the file.
+///
+/// Delta files are replaced with a delta against their own base, the base of a delta file is never a delta file itself.
+pub(crate) fn base_of<E: From<ErrorKind>>(desc: &Descriptor, mut read_section: impl FnMut(&Section) -> Result<Vec<Block>, E>) -> Result<Base, E> {
+	if desc.is_delta() {
+		return read_header(desc, &mut read_section).map(|(base, _)| base);
+	}
+	Ok(Base { section: desc.section, data_offset: desc.data_offset(), content_size: desc.content_size })
+}
+
+/// Encodes the section contents of a delta file.
+///
+/// Returns `None` if the delta file would not take fewer blocks than the data.
+pub(crate) fn encode(base: &Base, base_data: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+	let delta = diff(base_data, data);
+	if bytes2blocks((HEADER_LEN + delta.len()) as u32) >= bytes2blocks(data.len() as u32) {
+		return None;
+	}
+	let header = DeltaHeader {
+		base_nonce: base.section.nonce,
+		base_mac: base.section.mac,
+		base_data_offset: base.data_offset,
+		base_content_size: base.content_size,
+		delta_len: delta.len() as u32,
+		reserved: 0,
+	};
+	let mut payload = dataview::bytes(&header).to_vec();
+	payload.extend_from_slice(&delta);
+	Some(payload)
+}
+
+/// Reconstructs the contents of the delta file from its section and the base section.
+pub(crate) fn read_data<E: From<ErrorKind>>(desc: &Descriptor, mut read_section: impl FnMut(&Section) -> Result<Vec<Block>, E>) -> Result<Vec<u8>, E> {
+	let (base, delta) = read_header(desc, &mut read_section)?;
+	let base_blocks = read_section(&base.section)?;
+	let base_data = base.data(&base_blocks).ok_or(ErrorKind::InvalidData)?;
+	match apply(base_data, &delta, desc.content_size) {
+		Some(data) if data.len() == desc.content_size as usize => Ok(data),
+		_ => Err(ErrorKind::InvalidData.into()),
+	}
+}
+
+#[test]
+fn test_diff_apply() {
+	let base: Vec<u8> = (0..4000u32).map(|i| (i * 7 % 251) as u8).collect();
+
+	// Edits in the middle, at the start and at the end are found
+	let mut data = base.clone();
+	data[1000..1010].copy_from_slice(b"0123456789");
+	data.splice(2000..2000, b"inserted bytes".iter().copied());
+	data.drain(3000..3100);
+	data.insert(0, b'!');
+	data.extend_from_slice(b"tail");
+	let delta = diff(&base, &data);
+	assert!(delta.len() < 100, "delta of {} bytes", delta.len());
+	assert_eq!(apply(&base, &delta, data.len() as u32).as_deref(), Some(&data[..]));
+	assert_eq!(apply(&base, &delta, data.len() as u32 - 1), None);
+
+	// Unrelated and tiny contents are inserted as-is
+	for data in [&b"unrelated"[..], b"", &base[..WINDOW - 1]] {
+		assert_eq!(apply(&base, &diff(&base, data), data.len() as u32).as_deref(), Some(data));
+		assert_eq!(apply(b"", &diff(b"", data), data.len() as u32).as_deref(), Some(data));
+	}
+
+	// Malformed deltas are rejected
+	assert_eq!(apply(&base, &[OP_COPY, 0, 0, 0, 0, 1, 0, 0], 100), None);
+	assert_eq!(apply(&base, &[OP_COPY, 0xa0, 0x0f, 0, 0, 1, 0, 0, 0], 100), None);
+	assert_eq!(apply(&base, &[OP_INSERT, 2, 0, 0, 0, 1], 100), None);
+	assert_eq!(apply(&base, &[3], 100), None);
+
+	// Copies larger than the content size are rejected before copying
+	let copies: Vec<u8> = (0..1000).flat_map(|_| [OP_COPY, 0, 0, 0, 0, 0xa0, 0x0f, 0, 0]).collect();
+	assert_eq!(apply(&base, &copies, 1_000_000), None);
+}
@@ -12,10 +12,36 @@ There are two types, directories and files, which share the same [`Descriptor`]
 
 use super::*;
 
+/// Path separator policy.
+///
+/// Determines which bytes separate the components of paths, see [`Directory::set_separators`].
+/// The paths produced by the directory, eg. [`Directory::path_of`], always use the canonical separator `/`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Separators {
+	/// Both `/` and `\` separate path components.
+	///
+	/// Paths are looked up as if every `\` is canonicalized to `/`, paths spelled with Windows separators find the same descriptors.
+	/// Names cannot contain either separator.
+	#[default]
+	Both,
+	/// Only `/` separates path components.
+	///
+	/// The `\` is an ordinary character in names, `a\b` is a single name instead of the name `b` in the directory `a`.
+	Strict,
+}
+
+impl Separators {
+	/// Returns if the byte separates path components.
+	#[inline]
+	pub const fn is_separator(self, byte: u8) -> bool {
+		byte == b'/' || (byte == b'\\' && matches!(self, Separators::Both))
+	}
+}
+
 /// Compares if the next component of the path matches the file descriptor.
 ///
 /// Returns None if the path does not match, otherwise returns the path with the descriptor's name removed.
-pub fn name_eq<'a>(desc: &Descriptor, path: &'a [u8]) -> Option<&'a [u8]> {
+pub fn name_eq<'a>(desc: &Descriptor, path: &'a [u8], sep: Separators) -> Option<&'a [u8]> {
 	let name = desc.name();
 	let mut i = 0;
 	loop {
@@ -26,7 +52,7 @@ pub fn name_eq<'a>(desc: &Descriptor, path: &'a [u8]) -> Option<&'a [u8]> {
 				break Some(&path[i..]);
 			}
 			// The path component matched
-			if path[i] == b'/' || path[i] == b'\\' {
+			if sep.is_separator(path[i]) {
 				break Some(&path[i + 1..]);
 			}
 			// The path did not match
@@ -67,16 +93,16 @@ pub fn next_sibling(desc: &Descriptor, i: usize, end: usize) -> usize {
 }
 
 #[inline]
-pub fn find_desc<'a>(dir: &'a [Descriptor], path: &[u8]) -> Option<&'a Descriptor> {
-	find(dir, path).get(0)
+pub fn find_desc<'a>(dir: &'a [Descriptor], path: &[u8], sep: Separators) -> Option<&'a Descriptor> {
+	find(dir, path, sep).get(0)
 }
 #[inline]
-pub fn find_dir<'a>(dir: &'a [Descriptor], path: &[u8]) -> Option<&'a [Descriptor]> {
+pub fn find_dir<'a>(dir: &'a [Descriptor], path: &[u8], sep: Separators) -> Option<&'a [Descriptor]> {
 	if path.len() == 0 {
 		Some(dir)
 	}
 	else {
-		find(dir, path).get(1..)
+		find(dir, path, sep).get(1..)
 	}
 }
 
@@ -89,7 +115,7 @@ pub fn find_dir<'a>(dir: &'a [Descriptor], path: &[u8]) -> Option<&'a [Descripto
 /// Returns a slice with length larger than or equal to one if a directory descriptor was found at the given path.
 /// The first entry in the slice is the directory descriptor, the tail are the child descriptors contained within the directory.
 /// These children also contain any subdirectories of the returned directory.
-pub fn find<'a>(dir: &'a [Descriptor], mut path: &[u8]) -> &'a [Descriptor] {
+pub fn find<'a>(dir: &'a [Descriptor], mut path: &[u8], sep: Separators) -> &'a [Descriptor] {
 	// Reject empty paths
 	if path.len() == 0 {
 		return &dir[..0];
@@ -99,7 +125,7 @@ pub fn find<'a>(dir: &'a [Descriptor], mut path: &[u8]) -> &'a [Descriptor] {
 	while i < end {
 		let desc = &dir[i];
 		let next_i = next_sibling(desc, i, end);
-		if let Some(tail) = name_eq(desc, path) {
+		if let Some(tail) = name_eq(desc, path, sep) {
 			// Exactly matching descriptor found
 			if tail.len() == 0 {
 				return &dir[i..next_i];
@@ -121,11 +147,256 @@ pub fn find<'a>(dir: &'a [Descriptor], mut path: &[u8]) -> &'a [Descriptor] {
 	return &dir[..0];
 }
 
+/// Traverse the directory with the given path, matching encrypted names with the name key.
+///
+/// The path components are encrypted with the name key and compared to the encrypted names, see [`Descriptor::FLAG_ENCRYPTED_NAME`].
+/// Descriptors whose names are not encrypted are compared to the plaintext path components.
+///
+/// See [`find`] for the returned slice.
+pub fn find_with_key<'a>(dir: &'a [Descriptor], mut path: &[u8], key: &NameKey, sep: Separators) -> &'a [Descriptor] {
+	// Reject empty paths
+	if path.is_empty() {
+		return &dir[..0];
+	}
+	let (mut name, mut tail) = split_name(path, sep);
+	let mut encrypted = encrypt_name(name, key);
+	let mut i = 0;
+	let mut end = dir.len();
+	while i < end {
+		let desc = &dir[i];
+		let next_i = next_sibling(desc, i, end);
+		let is_match = match &encrypted {
+			Some(encrypted) if desc.is_name_encrypted() => desc.name == *encrypted,
+			_ => !desc.is_name_encrypted() && desc.name() == name,
+		};
+		if is_match {
+			// Exactly matching descriptor found
+			if tail.is_empty() {
+				return &dir[i..next_i];
+			}
+			// Continue traversing directory descriptor
+			if desc.is_dir() {
+				path = tail;
+				(name, tail) = split_name(path, sep);
+				encrypted = encrypt_name(name, key);
+				i += 1;
+				end = next_i;
+				continue;
+			}
+		}
+		// Advance the iteration
+		i = next_i;
+	}
+	// No descriptor with this path found
+	&dir[..0]
+}
+
+// Splits the next component off the path
+fn split_name(path: &[u8], sep: Separators) -> (&[u8], &[u8]) {
+	match path.iter().position(|&chr| sep.is_separator(chr)) {
+		Some(i) => (&path[..i], &path[i + 1..]),
+		None => (path, &path[path.len()..]),
+	}
+}
+
+// Encrypts the path component, names which do not fit the name buffer cannot match
+fn encrypt_name(name: &[u8], key: &NameKey) -> Option<Name> {
+	if name.len() > MAX_NAME_LEN {
+		return None;
+	}
+	let mut encrypted = Name::from(name);
+	crypt::encrypt_name(&mut encrypted, key);
+	Some(encrypted)
+}
+
+/// Hashes the path with 64-bit FNV-1a.
+///
+/// The separator `\\` is hashed as `/`, the path must otherwise be spelled exactly as in the directory.
+/// Directories with the [`Separators::Strict`] policy must not have names containing `\\`, their hashes collide with the hashes of the paths using `/`.
+/// This is a const fn so assets can be referenced by hash without their paths appearing in the shipped binary.
+pub const fn path_hash(path: &[u8]) -> u64 {
+	path_hash_continue(FNV_OFFSET, path)
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+const fn path_hash_continue(mut hash: u64, path: &[u8]) -> u64 {
+	let mut i = 0;
+	while i < path.len() {
+		let byte = if path[i] == b'\\' { b'/' } else { path[i] };
+		hash = (hash ^ byte as u64).wrapping_mul(FNV_PRIME);
+		i += 1;
+	}
+	hash
+}
+
+/// Stores the hash of the path in every descriptor, see [`Descriptor::FLAG_PATH_HASH`].
+///
+/// Descriptors with an encrypted name and their children are skipped, hashes of guessable paths would reveal the hidden names.
+/// Descriptors with a stripped name keep the hash of their original path.
+pub fn hash_paths(dir: &mut [Descriptor]) {
+	hash_paths_rec(dir, None);
+}
+
+fn hash_paths_rec(dir: &mut [Descriptor], parent: Option<u64>) {
+	let mut i = 0;
+	while i < dir.len() {
+		let next_i = next_sibling(&dir[i], i, dir.len());
+		let desc = &mut dir[i];
+		if !desc.is_name_encrypted() {
+			let hash = match (desc.path_hash(), parent) {
+				(Some(hash), _) if desc.is_name_stripped() => hash,
+				(_, Some(parent)) => path_hash_continue(path_hash_continue(parent, b"/"), desc.name()),
+				(_, None) => path_hash(desc.name()),
+			};
+			desc.set_path_hash(hash);
+			if desc.is_dir() {
+				hash_paths_rec(&mut dir[i + 1..next_i], Some(hash));
+			}
+		}
+		i = next_i;
+	}
+}
+
+/// Paths of the archive metadata, the [archive id](ARCHIVE_ID_PATH), the [preload list](PRELOAD_PATH) and the [content types](CONTENT_TYPES_PATH).
+///
+/// The archive metadata is stored as files in the root directory which are looked up by name, their names are never stripped.
+/// The paths are reserved, the archive metadata is written by its own methods and cannot be created, removed or moved like other files, see [`PathError::Reserved`].
+/// It is hidden from the listings and the file iterators, extracting or copying the files of a PAKS archive leaves it behind.
+pub const METADATA_PATHS: &[&[u8]] = &[ARCHIVE_ID_PATH, PRELOAD_PATH, CONTENT_TYPES_PATH];
+
+/// Is the path reserved for the archive metadata?
+///
+/// See [`METADATA_PATHS`].
+#[inline]
+pub fn is_metadata_path(path: &[u8]) -> bool {
+	METADATA_PATHS.contains(&path)
+}
+
+// Is the descriptor in the root directory the archive metadata?
+fn is_metadata(desc: &Descriptor) -> bool {
+	desc.is_file() && !desc.is_name_encrypted() && !desc.is_name_stripped() && is_metadata_path(desc.name())
+}
+
+// Removes the archive metadata from the root directory
+fn remove_metadata(dir: &mut Vec<Descriptor>) {
+	let mut i = 0;
+	while i < dir.len() {
+		let next_i = next_sibling(&dir[i], i, dir.len());
+		if is_metadata(&dir[i]) {
+			dir.remove(i);
+		}
+		else {
+			i = next_i;
+		}
+	}
+}
+
+/// Replaces the names with the hexadecimal hash of their path, see [`Descriptor::FLAG_STRIPPED_NAME`].
+///
+/// Descriptors with an encrypted name and their children are left unchanged.
+/// The archive metadata, the [archive id](ARCHIVE_ID_PATH), the [preload list](PRELOAD_PATH) and the [content types](CONTENT_TYPES_PATH), keeps its name.
+pub fn strip_names(dir: &mut [Descriptor]) {
+	hash_paths(dir);
+	for desc in dir {
+		if let (false, Some(hash)) = (desc.is_name_stripped(), desc.path_hash()) {
+			if METADATA_PATHS.iter().any(|&path| path_hash(path) == hash) {
+				continue;
+			}
+			desc.name.set(format!("{:016x}", hash).as_bytes());
+			desc.flags |= Descriptor::FLAG_STRIPPED_NAME;
+		}
+	}
+}
+
+/// Reconstructs the path of the descriptor at the given index.
+///
+/// Path components are separated by `/`.
+/// Returns None if the index is out of range or the descriptor is not reachable from the root, which is the case for a corrupt directory.
+pub fn path_of(dir: &[Descriptor], index: usize) -> Option<Vec<u8>> {
+	let mut path = Vec::new();
+	let mut i = 0;
+	let mut end = dir.len();
+	while i < end {
+		let desc = &dir[i];
+		let next_i = next_sibling(desc, i, end);
+		if i == index {
+			path.extend_from_slice(desc.name());
+			return Some(path);
+		}
+		// Descend into the directory descriptor containing the index
+		if index < next_i {
+			path.extend_from_slice(desc.name());
+			path.push(b'/');
+			i += 1;
+			end = next_i;
+			continue;
+		}
+		i = next_i;
+	}
+	None
+}
+
+/// Returns the paths of all the descriptors in depth-first order, directories before their children.
+///
+/// The archive metadata is hidden, see [`METADATA_PATHS`].
+pub fn entries(dir: &[Descriptor]) -> Vec<(Vec<u8>, &Descriptor)> {
+	let mut entries = Vec::new();
+	flat_rec(&mut Vec::new(), dir, true, true, &mut entries);
+	entries
+}
+
+/// Returns the paths of all the descriptors including the archive metadata.
+pub fn entries_with_metadata(dir: &[Descriptor]) -> Vec<(Vec<u8>, &Descriptor)> {
+	let mut entries = Vec::new();
+	flat_rec(&mut Vec::new(), dir, true, false, &mut entries);
+	entries
+}
+
+/// Returns the paths of all the files ordered by the offset of their contents in the PAKS file.
+///
+/// Inline files have no section and come first, files sharing a section are ordered by their data offset.
+/// The archive metadata is hidden, see [`METADATA_PATHS`].
+pub fn disk_order(dir: &[Descriptor]) -> Vec<(Vec<u8>, &Descriptor)> {
+	sort_disk_order(entries(dir))
+}
+
+/// Returns the paths of all the files including the archive metadata ordered by the offset of their contents in the PAKS file.
+pub fn disk_order_with_metadata(dir: &[Descriptor]) -> Vec<(Vec<u8>, &Descriptor)> {
+	sort_disk_order(entries_with_metadata(dir))
+}
+
+fn sort_disk_order(mut files: Vec<(Vec<u8>, &Descriptor)>) -> Vec<(Vec<u8>, &Descriptor)> {
+	files.retain(|(_, desc)| desc.is_file());
+	files.sort_by_key(|(_, desc)| if desc.is_inline() { (0, 0) } else { (desc.section.offset, desc.data_offset()) });
+	files
+}
+
+/// Returns the paths and content sizes of the largest files, ordered by descending size.
+///
+/// Links share their contents and are listed once under their first path in sorted order.
+/// Files of equal size are ordered by path.
+pub fn top_n_by_size(dir: &[Descriptor], n: usize) -> Vec<(Vec<u8>, u32)> {
+	let mut entries = entries(dir);
+	entries.retain(|(_, desc)| desc.is_file());
+	entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+	// Inline files store their contents in the descriptor and cannot be linked
+	let mut sections = std::collections::HashSet::new();
+	entries.retain(|(_, desc)| desc.is_inline() || sections.insert((desc.section.offset, desc.section.size, desc.data_offset())));
+
+	let mut top: Vec<_> = entries.into_iter().map(|(path, desc)| (path, desc.content_size)).collect();
+	top.sort_by_key(|&(_, size)| cmp::Reverse(size));
+	top.truncate(n);
+	top
+}
+
 /*
 /// Finds a descriptor with the given name in an encrypted directory.
 ///
 /// The directory stays encrypted and only decrypts a single descriptor at the time.
-pub fn find_encrypted(encrypted_dir: &[Descriptor], mut path: &[u8], nonce: &Block, key: &Key) -> Option<Descriptor> {
+pub fn find_encrypted(encrypted_dir: &[Descriptor], mut path: &[u8], nonce: &Block, key: &Key, sep: Separators) -> Option<Descriptor> {
 	// Reject empty paths
 	if path.len() == 0 {
 		return None;
@@ -136,7 +407,7 @@ pub fn find_encrypted(encrypted_dir: &[Descriptor], mut path: &[u8], nonce: &Blo
 	while i < end {
 		let desc = crypt::decrypt_desc(&encrypted_dir[i], &nonce, key);
 		let next_i = next_sibling(&desc, i, end);
-		if let Some(tail) = name_eq(&desc, path) {
+		if let Some(tail) = name_eq(&desc, path, sep) {
 			// Exactly matching descriptor found
 			if tail.len() == 0 {
 				return Some(desc);
@@ -162,14 +433,25 @@ pub fn find_encrypted(encrypted_dir: &[Descriptor], mut path: &[u8], nonce: &Blo
 */
 
 /// Art used to render the directory structure.
+///
+/// Every entry is prefixed by the margins of its parent directories followed by the entry's own prefix.
+/// Construct a custom art by filling in the fields or modify one of the presets.
 #[derive(Copy, Clone, Debug)]
 pub struct TreeArt<'a> {
+	/// Margin for a parent directory with more entries following it.
 	pub margin_entry: &'a str,
+	/// Margin for a parent directory which is the last entry.
 	pub margin_last: &'a str,
+	/// Prefix for a directory with more entries following it.
 	pub dir_entry: &'a str,
+	/// Prefix for a directory which is the last entry.
 	pub dir_last: &'a str,
+	/// Prefix for a file with more entries following it.
 	pub file_entry: &'a str,
+	/// Prefix for a file which is the last entry.
 	pub file_last: &'a str,
+	/// Print an empty line between directories.
+	pub spacing: bool,
 }
 impl TreeArt<'static> {
 	pub const ASCII: TreeArt<'static> = TreeArt {
@@ -179,6 +461,7 @@ impl TreeArt<'static> {
 		dir_last: "`- ",
 		file_entry: "|  ",
 		file_last: "`  ",
+		spacing: true,
 	};
 	pub const UNICODE: TreeArt<'static> = TreeArt {
 		margin_entry: "│  ",
@@ -187,6 +470,27 @@ impl TreeArt<'static> {
 		dir_last: "└─ 📁 ",
 		file_entry: "│  ",
 		file_last: "└  ",
+		spacing: true,
+	};
+	/// Every entry on a single line without empty lines between directories.
+	pub const COMPACT: TreeArt<'static> = TreeArt {
+		margin_entry: "│ ",
+		margin_last: "  ",
+		dir_entry: "├ ",
+		dir_last: "└ ",
+		file_entry: "├ ",
+		file_last: "└ ",
+		spacing: false,
+	};
+	/// Nested Markdown list.
+	pub const MARKDOWN: TreeArt<'static> = TreeArt {
+		margin_entry: "  ",
+		margin_last: "  ",
+		dir_entry: "- ",
+		dir_last: "- ",
+		file_entry: "- ",
+		file_last: "- ",
+		spacing: false,
 	};
 }
 
@@ -194,12 +498,24 @@ impl TreeArt<'static> {
 pub struct DirFmt<'a> {
 	root: &'a str,
 	dir: &'a [Descriptor],
-	art: &'a TreeArt<'static>,
+	art: &'a TreeArt<'a>,
+	types: Option<&'a ContentTypes>,
+	hide_metadata: bool,
 }
 impl<'a> DirFmt<'a> {
 	#[inline]
-	pub const fn new(root: &'a str, dir: &'a [Descriptor], art: &'a TreeArt<'static>) -> DirFmt<'a> {
-		DirFmt { root, dir, art }
+	pub const fn new(root: &'a str, dir: &'a [Descriptor], art: &'a TreeArt<'a>) -> DirFmt<'a> {
+		DirFmt { root, dir, art, types: None, hide_metadata: false }
+	}
+	/// Hides the archive metadata when the descriptors are the root directory, see [`METADATA_PATHS`].
+	#[inline]
+	pub const fn hide_metadata(self) -> DirFmt<'a> {
+		DirFmt { hide_metadata: true, ..self }
+	}
+	/// Follows the files with the name of their content type in parentheses, files without a named content type are unchanged.
+	#[inline]
+	pub const fn with_types(self, types: &'a ContentTypes) -> DirFmt<'a> {
+		DirFmt { types: Some(types), ..self }
 	}
 }
 impl<'a> fmt::Display for DirFmt<'a> {
@@ -207,7 +523,11 @@ impl<'a> fmt::Display for DirFmt<'a> {
 		// Print the root directory
 		f.write_str(self.root)?;
 		f.write_str(if self.root.ends_with("/") { "\n" } else { "/\n" })?;
-		fmt_rec(f, 0, 0, self.dir, self.art)
+		let mut dir = self.dir.to_vec();
+		if self.hide_metadata {
+			remove_metadata(&mut dir);
+		}
+		fmt_rec(f, 0, 0, &dir, self.art, self.types)
 	}
 }
 
@@ -218,7 +538,7 @@ fn fmt_margin<W: fmt::Write>(f: &mut W, margin: u32, depth: u32, art: &TreeArt)
 	}
 	Ok(())
 }
-fn fmt_rec<W: fmt::Write>(f: &mut W, margin: u32, depth: u32, dir: &[Descriptor], art: &TreeArt) -> fmt::Result {
+fn fmt_rec<W: fmt::Write>(f: &mut W, margin: u32, depth: u32, dir: &[Descriptor], art: &TreeArt, types: Option<&ContentTypes>) -> fmt::Result {
 	// Max supported nested directories
 	if depth >= 31 {
 		return Ok(());
@@ -230,7 +550,7 @@ fn fmt_rec<W: fmt::Write>(f: &mut W, margin: u32, depth: u32, dir: &[Descriptor]
 		let desc = &dir[i];
 
 		// Print some space between directories
-		if i != 0 && (desc.is_dir() || was_dir) {
+		if art.spacing && i != 0 && (desc.is_dir() || was_dir) {
 			fmt_margin(f, margin, depth + 1, art)?;
 			f.write_str("\n")?;
 		}
@@ -262,9 +582,12 @@ fn fmt_rec<W: fmt::Write>(f: &mut W, margin: u32, depth: u32, dir: &[Descriptor]
 		if desc.is_dir() {
 			f.write_str("/\n")?;
 			let new_margin = margin | (is_last as u32) << depth;
-			fmt_rec(f, new_margin, depth + 1, &dir[i + 1..next_i], art)?;
+			fmt_rec(f, new_margin, depth + 1, &dir[i + 1..next_i], art, types)?;
 		}
 		else {
+			if let Some(name) = types.and_then(|types| types.get(desc.content_type)) {
+				write!(f, " ({})", name)?;
+			}
 			f.write_str("\n")?;
 		}
 
@@ -273,18 +596,113 @@ fn fmt_rec<W: fmt::Write>(f: &mut W, margin: u32, depth: u32, dir: &[Descriptor]
 	Ok(())
 }
 
+/// Formats the directory as a flat listing.
+///
+/// Every entry is printed on its own line as `path<TAB>type<TAB>size`, sorted by path.
+/// The type is `dir` or `file`, the size is the content size of files and zero for directories.
+/// Backslashes, tabs and newlines in the path are escaped as `\\`, `\t` and `\n`.
+pub struct FlatFmt<'a> {
+	root: &'a str,
+	dir: &'a [Descriptor],
+	recursive: bool,
+	types: Option<&'a ContentTypes>,
+	hide_metadata: bool,
+}
+impl<'a> FlatFmt<'a> {
+	/// The root is the path of the directory containing the descriptors, empty for the root directory.
+	#[inline]
+	pub const fn new(root: &'a str, dir: &'a [Descriptor], recursive: bool) -> FlatFmt<'a> {
+		FlatFmt { root, dir, recursive, types: None, hide_metadata: false }
+	}
+	/// Hides the archive metadata when the descriptors are the root directory, see [`METADATA_PATHS`].
+	#[inline]
+	pub const fn hide_metadata(self) -> FlatFmt<'a> {
+		FlatFmt { hide_metadata: true, ..self }
+	}
+	/// Adds a fourth column to the files with their content type, see [`ContentTypes::describe`].
+	#[inline]
+	pub const fn with_types(self, types: &'a ContentTypes) -> FlatFmt<'a> {
+		FlatFmt { types: Some(types), ..self }
+	}
+}
+impl<'a> fmt::Display for FlatFmt<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let mut entries = Vec::new();
+		let mut path = self.root.trim_end_matches('/').as_bytes().to_vec();
+		flat_rec(&mut path, self.dir, self.recursive, self.hide_metadata, &mut entries);
+		entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+		for (path, desc) in &entries {
+			fmt_escaped(f, &String::from_utf8_lossy(path))?;
+			if desc.is_dir() {
+				f.write_str("\tdir\t0\n")?;
+			}
+			else if let Some(types) = self.types {
+				write!(f, "\tfile\t{}\t", desc.content_size)?;
+				fmt_escaped(f, &types.describe(desc.content_type))?;
+				f.write_str("\n")?;
+			}
+			else {
+				writeln!(f, "\tfile\t{}", desc.content_size)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+fn fmt_escaped(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+	for chr in s.chars() {
+		match chr {
+			'\\' => f.write_str("\\\\")?,
+			'\t' => f.write_str("\\t")?,
+			'\n' => f.write_str("\\n")?,
+			'\r' => f.write_str("\\r")?,
+			chr => fmt::Write::write_char(f, chr)?,
+		}
+	}
+	Ok(())
+}
+
+// Hides the archive metadata if the descriptors are the root directory
+fn flat_rec<'a>(path: &mut Vec<u8>, dir: &'a [Descriptor], recursive: bool, hide_metadata: bool, entries: &mut Vec<(Vec<u8>, &'a Descriptor)>) {
+	let mut i = 0;
+	while i < dir.len() {
+		let desc = &dir[i];
+		let next_i = next_sibling(desc, i, dir.len());
+
+		if hide_metadata && is_metadata(desc) {
+			i = next_i;
+			continue;
+		}
+
+		let path_len = path.len();
+		if path_len > 0 {
+			path.push(b'/');
+		}
+		path.extend_from_slice(desc.name());
+		entries.push((path.clone(), desc));
+
+		if recursive && desc.is_dir() {
+			flat_rec(path, &dir[i + 1..next_i], recursive, false, entries);
+		}
+
+		path.truncate(path_len);
+		i = next_i;
+	}
+}
+
 /// Increments all directory descriptors' child count along the given path.
 /// Returns the index where `inc` number of descriptors must be inserted.
 ///
 /// Does not care if a descriptor already exists and will suggest to create one with the same name.
-fn dir_inc(dir: &mut Vec<Descriptor>, path: &mut &[u8], inc: i32) -> usize {
+fn dir_inc(dir: &mut Vec<Descriptor>, path: &mut &[u8], inc: i32, sep: Separators) -> usize {
 	let mut i = 0;
 	let mut end = dir.len();
 	while i < end {
 		let desc = &mut dir[i];
 		let next_i = next_sibling(desc, i, end);
 		// Compare the name of this descriptor with the given path
-		if let Some(tail) = name_eq(desc, *path) {
+		if let Some(tail) = name_eq(desc, *path, sep) {
 			// Found the descriptor matching this name
 			if tail.len() == 0 {
 				*path = tail;
@@ -292,7 +710,7 @@ fn dir_inc(dir: &mut Vec<Descriptor>, path: &mut &[u8], inc: i32) -> usize {
 			}
 			// Name matches a directory, descend
 			if desc.is_dir() {
-				desc.content_size = (desc.content_size as i32 + inc) as u32;
+				desc.content_size = desc.content_size.saturating_add_signed(inc);
 				*path = tail;
 				i = i + 1;
 				end = next_i;
@@ -309,10 +727,10 @@ fn dir_inc(dir: &mut Vec<Descriptor>, path: &mut &[u8], inc: i32) -> usize {
 	return i;
 }
 
-fn flenck(path: &[u8]) -> i32 {
+fn flenck(path: &[u8], sep: Separators) -> i32 {
 	let mut components = 0;
 	for i in 0..path.len() {
-		if path[i] == b'/' || path[i] == b'\\' {
+		if sep.is_separator(path[i]) {
 			components += 1;
 			if i + 1 == path.len() {
 				return components;
@@ -322,14 +740,92 @@ fn flenck(path: &[u8]) -> i32 {
 	return components + 1;
 }
 
+/// Path validation errors.
+///
+/// Returned when creating descriptors with a path which cannot be represented faithfully in the directory.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum PathError {
+	/// The path is empty.
+	Empty,
+	/// The path contains an empty component.
+	///
+	/// Eg. `a//b`, a leading separator `/a` or a trailing separator `a/`.
+	EmptyComponent,
+	/// The path contains a `.` or `..` component.
+	DotComponent,
+	/// A path component is longer than the maximum name length and would be cut off.
+	NameTooLong,
+	/// The path is reserved for the archive metadata, see [`METADATA_PATHS`].
+	Reserved,
+}
+
+impl fmt::Display for PathError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			PathError::Empty => "empty path",
+			PathError::EmptyComponent => "empty path component",
+			PathError::DotComponent => "invalid `.` or `..` path component",
+			PathError::NameTooLong => "path component too long",
+			PathError::Reserved => "path reserved for the archive metadata",
+		})
+	}
+}
+
+impl std::error::Error for PathError {}
+
+impl From<PathError> for ErrorKind {
+	#[inline]
+	fn from(_: PathError) -> ErrorKind {
+		ErrorKind::InvalidInput
+	}
+}
+impl From<PathError> for std::io::Error {
+	#[inline]
+	fn from(err: PathError) -> std::io::Error {
+		std::io::Error::new(ErrorKind::InvalidInput, err)
+	}
+}
+
+/// Maximum length in bytes of a single path component.
+pub const MAX_NAME_LEN: usize = NAME_BUF_LEN - 1;
+
+/// Validates the path for creating descriptors.
+///
+/// Both `/` and `\` are accepted as path separators, see [`validate_path_with`] for the other separator policies.
+/// Every component must be non-empty, not `.` or `..` and fit in the descriptor's name buffer.
+#[inline]
+pub fn validate_path(path: &[u8]) -> Result<(), PathError> {
+	validate_path_with(path, Separators::Both)
+}
+
+/// Validates the path for creating descriptors with the separator policy.
+///
+/// See [`validate_path`] for more information.
+pub fn validate_path_with(path: &[u8], sep: Separators) -> Result<(), PathError> {
+	if path.is_empty() {
+		return Err(PathError::Empty);
+	}
+	for name in path.split(|&chr| sep.is_separator(chr)) {
+		match name {
+			b"" => return Err(PathError::EmptyComponent),
+			b"." | b".." => return Err(PathError::DotComponent),
+			_ if name.len() > MAX_NAME_LEN => return Err(PathError::NameTooLong),
+			_ => (),
+		}
+	}
+	Ok(())
+}
+
 /// Creates a new descriptor at the appropriate place given the path.
 ///
 /// Non-existing sub directories are created as needed.
 /// If a file exists where a directory is expected, a directory with the same name is created as the file.
-pub fn create<'a>(dir: &'a mut Vec<Descriptor>, path: &[u8]) -> &'a mut Descriptor {
+///
+/// The path is not validated, see [`validate_path`].
+pub fn create<'a>(dir: &'a mut Vec<Descriptor>, path: &[u8], sep: Separators) -> &'a mut Descriptor {
 	// Dry run to find the index where to insert new descriptors
 	let mut tail = path;
-	let i = dir_inc(dir, &mut tail, 0);
+	let i = dir_inc(dir, &mut tail, 0, sep);
 
 	// Adding a descriptor which already exists
 	if tail.is_empty() {
@@ -337,18 +833,18 @@ pub fn create<'a>(dir: &'a mut Vec<Descriptor>, path: &[u8]) -> &'a mut Descript
 	}
 
 	// Number of descriptors to add
-	let inc = flenck(tail) as usize;
+	let inc = flenck(tail, sep) as usize;
 
 	// Update the parent directories
 	tail = path;
-	let _check = dir_inc(dir, &mut tail, inc as i32);
+	let _check = dir_inc(dir, &mut tail, inc as i32, sep);
 	debug_assert_eq!(i, _check);
 
 	// Splice new directory descriptors
 	let mut dir_len = inc as u32;
 	let _ = dir.splice(i..i, std::iter::repeat_with(|| {
 		let mut k = 0;
-		while k < tail.len() && tail[k] != b'/' && tail[k] != b'\\' {
+		while k < tail.len() && !sep.is_separator(tail[k]) {
 			k += 1;
 		}
 		dir_len -= 1;
@@ -372,10 +868,10 @@ pub fn create<'a>(dir: &'a mut Vec<Descriptor>, path: &[u8]) -> &'a mut Descript
 /// Returns `true` if a directory descriptor is found at the given path.
 /// The descriptor is removed and optionally copied to the deleted output argument.
 /// All the direct children of the removed directory are moved to its parent directory.
-pub fn remove(dir: &mut Vec<Descriptor>, path: &[u8]) -> Option<Descriptor> {
+pub fn remove(dir: &mut Vec<Descriptor>, path: &[u8], sep: Separators) -> Option<Descriptor> {
 	// Dry run to find the index of the descriptor to remove
 	let mut temp = path;
-	let i = dir_inc(dir, &mut temp, 0);
+	let i = dir_inc(dir, &mut temp, 0, sep);
 
 	// Early return if the descriptor wasn't found
 	if i >= dir.len() {
@@ -384,21 +880,98 @@ pub fn remove(dir: &mut Vec<Descriptor>, path: &[u8]) -> Option<Descriptor> {
 
 	// Update the parent directories
 	temp = path;
-	let _check = dir_inc(dir, &mut temp, -1);
+	let _check = dir_inc(dir, &mut temp, -1, sep);
 	debug_assert_eq!(i, _check);
 
 	// Finally remove the descriptor
 	Some(dir.remove(i))
 }
 
-pub fn fsck(dir: &[Descriptor], high_mark: u32, log: &mut dyn fmt::Write) -> bool {
-	fsck_rec(dir, high_mark, None, log)
+// The flags defined by the file format, the other flags are reserved
+const KNOWN_FLAGS: u32 = Descriptor::FLAG_PLAINTEXT | Descriptor::FLAG_ENCRYPTED_NAME | Descriptor::FLAG_PATH_HASH | Descriptor::FLAG_STRIPPED_NAME
+	| Descriptor::FLAG_INLINE | Descriptor::FLAG_BUNDLED | Descriptor::FLAG_EXTENSIONS | Descriptor::FLAG_DELTA;
+
+/// Validates the structural invariants of the directory.
+///
+/// Every directory's descendants must fit within its parent directory.
+/// Every file section must lie between the header and the high mark, be large enough for the file's content size and not overlap other file sections.
+/// Empty file sections are not checked and file descriptors sharing the exact same section (eg. links and bundled files) do not overlap.
+/// The base sections of delta files are checked like file sections.
+///
+/// Descriptors with unknown flags are rejected, they are written by a newer version of the file format.
+/// Delta files with extension records are rejected, the base section and the records share the extension area.
+///
+/// Unlike [`fsck`] this does not check the names of the descriptors.
+pub fn validate(dir: &[Descriptor], high_mark: u32) -> bool {
+	let mut sections = Vec::new();
+
+	// Stack of the end indices of the enclosing directories
+	let mut ends = vec![dir.len()];
+	for (i, desc) in dir.iter().enumerate() {
+		while ends.last() == Some(&i) {
+			ends.pop();
+		}
+		// The root directory ends after all the descriptors and is never popped
+		let end = ends[ends.len() - 1];
+
+		if desc.flags & !KNOWN_FLAGS != 0 {
+			return false;
+		}
+		// The extension area holds the base section of delta files
+		if desc.is_delta() && desc.flags & Descriptor::FLAG_EXTENSIONS != 0 {
+			return false;
+		}
+
+		if desc.is_file() {
+			if desc.is_inline() {
+				if desc.content_size as usize > Descriptor::INLINE_LEN {
+					return false;
+				}
+				continue;
+			}
+			// The content size of delta files is the size of the reconstructed contents
+			if !desc.is_delta() && desc.data_offset().checked_add(desc.content_size).is_none_or(|end| bytes2blocks(end) > desc.section.size) {
+				return false;
+			}
+			if let Some((offset, size)) = desc.delta_base() {
+				if size == 0 || offset < Header::BLOCKS_LEN as u32 || offset.checked_add(size).is_none_or(|base_end| base_end > high_mark) {
+					return false;
+				}
+				sections.push((offset, size));
+			}
+			if desc.section.size == 0 {
+				continue;
+			}
+			if desc.section.offset < Header::BLOCKS_LEN as u32 {
+				return false;
+			}
+			if desc.section.offset.checked_add(desc.section.size).is_none_or(|section_end| section_end > high_mark) {
+				return false;
+			}
+			sections.push((desc.section.offset, desc.section.size));
+		}
+		else {
+			if desc.content_size as usize > end - i - 1 {
+				return false;
+			}
+			ends.push(i + 1 + desc.content_size as usize);
+		}
+	}
+
+	// Sort the sections and check if any neighbours overlap
+	sections.sort_unstable();
+	sections.dedup();
+	sections.windows(2).all(|pair| pair[0].0 + pair[0].1 <= pair[1].0)
+}
+
+pub fn fsck(dir: &[Descriptor], high_mark: u32, sep: Separators, log: &mut dyn fmt::Write) -> bool {
+	fsck_rec(dir, high_mark, sep, None, log)
 }
 struct FsckParents<'a> {
 	desc: &'a Descriptor,
 	parents: Option<&'a FsckParents<'a>>,
 }
-fn fsck_rec(dir: &[Descriptor], high_mark: u32, parents: Option<&FsckParents>, log: &mut dyn fmt::Write) -> bool {
+fn fsck_rec(dir: &[Descriptor], high_mark: u32, sep: Separators, parents: Option<&FsckParents>, log: &mut dyn fmt::Write) -> bool {
 	let mut success = true;
 	let mut i = 0;
 	while i < dir.len() {
@@ -411,13 +984,26 @@ fn fsck_rec(dir: &[Descriptor], high_mark: u32, parents: Option<&FsckParents>, l
 			success = false;
 		}
 
-		// Invalid name
-		if let Err(err) = str::from_utf8(desc.name()) {
+		// Invalid name, encrypted names are binary
+		if let (false, Err(err)) = (desc.is_name_encrypted(), str::from_utf8(desc.name())) {
 			fsck_error(desc, parents, log, format_args!("invalid name ({})", err));
 			success = false;
 		}
 
-		if desc.is_file() {
+		// Names containing a separator cannot be found by their path
+		if !desc.is_name_encrypted() && desc.name().iter().any(|&chr| sep.is_separator(chr)) {
+			fsck_error(desc, parents, log, format_args!("invalid name: contains a path separator"));
+			success = false;
+		}
+
+		if desc.is_file() && desc.is_inline() {
+			// Inline file contents do not fit in the section object
+			if desc.content_size as usize > Descriptor::INLINE_LEN {
+				fsck_error(desc, parents, log, format_args!("invalid content size ({}): larger than inline contents", desc.content_size));
+				success = false;
+			}
+		}
+		else if desc.is_file() {
 			// File section overlaps the header
 			if desc.section.offset < Header::BLOCKS_LEN as u32 {
 				fsck_error(desc, parents, log, format_args!("invalid file section (offset={}, size={}): overlaps the header", desc.section.offset, desc.section.size));
@@ -431,13 +1017,21 @@ fn fsck_rec(dir: &[Descriptor], high_mark: u32, parents: Option<&FsckParents>, l
 			}
 
 			// File section overlaps the directory
-			if desc.section.offset > high_mark - desc.section.size {
+			else if desc.section.offset > high_mark - desc.section.size {
 				fsck_error(desc, parents, log, format_args!("invalid file section (offset={}, size={}): overlaps the directory", desc.section.offset, desc.section.size));
 				success = false;
 			}
 
+			// Delta base section out of bounds
+			if let Some((offset, size)) = desc.delta_base() {
+				if size == 0 || offset < Header::BLOCKS_LEN as u32 || offset.checked_add(size).is_none_or(|base_end| base_end > high_mark) {
+					fsck_error(desc, parents, log, format_args!("invalid delta base section (offset={}, size={}): out of bounds", offset, size));
+					success = false;
+				}
+			}
+
 			// File content size larger than its section size
-			if bytes2blocks(desc.content_size) > desc.section.size {
+			else if desc.data_offset().checked_add(desc.content_size).is_none_or(|end| bytes2blocks(end) > desc.section.size) {
 				fsck_error(desc, parents, log, format_args!("invalid content size ({}, offset={}, size={}): larger than its section", desc.content_size, desc.section.offset, desc.section.size));
 				success = false;
 			}
@@ -454,7 +1048,7 @@ fn fsck_rec(dir: &[Descriptor], high_mark: u32, parents: Option<&FsckParents>, l
 
 			// Recursively check the directory's children
 			let children = &dir[i..i + desc.content_size as usize];
-			fsck_rec(children, high_mark, Some(&FsckParents { desc, parents }), log);
+			success &= fsck_rec(children, high_mark, sep, Some(&FsckParents { desc, parents }), log);
 
 			i += desc.content_size as usize;
 		}
@@ -476,6 +1070,122 @@ fn fsck_error(desc: &Descriptor, parents: Option<&FsckParents>, log: &mut dyn fm
 	let _ = log.write_str("\n");
 }
 
+/// Portable profile.
+///
+/// Limits the paths of PAKS files meant to be extracted on all operating systems, see [`Directory::check_portable`].
+///
+/// Besides the configurable limits, names must not:
+///
+/// * End with a dot or a space, Windows silently removes them.
+/// * Be a reserved device name on Windows (`CON`, `PRN`, `AUX`, `NUL`, `COM1` to `COM9` and `LPT1` to `LPT9`), with or without an extension.
+/// * Contain control characters or any of `<>:"/\|?*`.
+/// * Collide with a sibling when case is ignored, case-insensitive file systems cannot extract both.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Portable {
+	/// Maximum number of components in a path.
+	pub max_depth: usize,
+	/// Maximum length in bytes of a single path component.
+	pub max_name_len: usize,
+	/// Maximum length in bytes of a path, leaves room for the destination directory under the Windows `MAX_PATH`.
+	pub max_path_len: usize,
+	/// Only allow printable ASCII characters in names.
+	pub ascii_only: bool,
+}
+
+impl Portable {
+	/// The default portable profile.
+	pub const DEFAULT: Portable = Portable {
+		max_depth: 16,
+		max_name_len: MAX_NAME_LEN,
+		max_path_len: 200,
+		ascii_only: true,
+	};
+}
+
+impl Default for Portable {
+	#[inline]
+	fn default() -> Portable {
+		Portable::DEFAULT
+	}
+}
+
+pub fn check_portable(dir: &[Descriptor], profile: &Portable, log: &mut dyn fmt::Write) -> bool {
+	portable_rec(dir, profile, 1, 0, None, log)
+}
+fn portable_rec(dir: &[Descriptor], profile: &Portable, depth: usize, parent_len: usize, parents: Option<&FsckParents>, log: &mut dyn fmt::Write) -> bool {
+	let mut success = true;
+	let mut siblings = std::collections::HashSet::new();
+	let mut i = 0;
+	while i < dir.len() {
+		let desc = &dir[i];
+		let next_i = next_sibling(desc, i, dir.len());
+
+		// Encrypted and stripped names are not known
+		let checked = !desc.is_name_encrypted() && !desc.is_name_stripped();
+		let name = desc.name();
+		let path_len = if parent_len == 0 { name.len() } else { parent_len + 1 + name.len() };
+
+		if depth > profile.max_depth {
+			fsck_error(desc, parents, log, format_args!("not portable: path too deep ({}, max={})", depth, profile.max_depth));
+			success = false;
+		}
+
+		if checked {
+			if name.len() > profile.max_name_len {
+				fsck_error(desc, parents, log, format_args!("not portable: name too long ({}, max={})", name.len(), profile.max_name_len));
+				success = false;
+			}
+
+			if path_len > profile.max_path_len {
+				fsck_error(desc, parents, log, format_args!("not portable: path too long ({}, max={})", path_len, profile.max_path_len));
+				success = false;
+			}
+
+			if profile.ascii_only && !name.is_ascii() {
+				fsck_error(desc, parents, log, format_args!("not portable: name is not ASCII"));
+				success = false;
+			}
+
+			if let Some(&chr) = name.iter().find(|&&chr| chr < 0x20 || chr == 0x7f || b"<>:\"/\\|?*".contains(&chr)) {
+				fsck_error(desc, parents, log, format_args!("not portable: invalid character {:?}", chr as char));
+				success = false;
+			}
+
+			if let Some(b'.' | b' ') = name.last() {
+				fsck_error(desc, parents, log, format_args!("not portable: name ends with a dot or a space"));
+				success = false;
+			}
+
+			if is_reserved_name(name) {
+				fsck_error(desc, parents, log, format_args!("not portable: reserved name on Windows"));
+				success = false;
+			}
+
+			if !siblings.insert(name.to_ascii_lowercase()) {
+				fsck_error(desc, parents, log, format_args!("not portable: name collides with a sibling when case is ignored"));
+				success = false;
+			}
+		}
+
+		if desc.is_dir() {
+			let children = &dir[i + 1..next_i];
+			success &= portable_rec(children, profile, depth + 1, path_len, Some(&FsckParents { desc, parents }), log);
+		}
+		i = next_i;
+	}
+	success
+}
+
+// Device names reserved by Windows, with or without an extension
+fn is_reserved_name(name: &[u8]) -> bool {
+	let stem = match name.iter().position(|&chr| chr == b'.') {
+		Some(i) => &name[..i],
+		None => name,
+	};
+	let stem = stem.to_ascii_uppercase();
+	matches!(&stem[..], b"CON" | b"PRN" | b"AUX" | b"NUL" | [b'C', b'O', b'M', b'1'..=b'9'] | [b'L', b'P', b'T', b'1'..=b'9'])
+}
+
 //----------------------------------------------------------------
 
 #[cfg(test)]
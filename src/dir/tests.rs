@@ -17,10 +17,10 @@ fn name_eq_example() {
 	let mut desc = Descriptor::default();
 	desc.name.set(b"test");
 
-	assert_eq!(name_eq(&desc, b"test"), Some(&b""[..]));
-	assert_eq!(name_eq(&desc, b"test/a/b"), Some(&b"a/b"[..]));
-	assert_eq!(name_eq(&desc, b"testing"), None);
-	assert_eq!(name_eq(&desc, b"te"), None);
+	assert_eq!(name_eq(&desc, b"test", Separators::Both), Some(&b""[..]));
+	assert_eq!(name_eq(&desc, b"test/a/b", Separators::Both), Some(&b"a/b"[..]));
+	assert_eq!(name_eq(&desc, b"testing", Separators::Both), None);
+	assert_eq!(name_eq(&desc, b"te", Separators::Both), None);
 }
 
 #[test]
@@ -102,18 +102,75 @@ fn test_to_string() {
 	assert_eq!(expected, result);
 }
 
+#[test]
+fn test_to_string_styles() {
+	let dir = [
+		Descriptor::dir(b"Foo", 2),
+		Descriptor::file(b"Bar"),
+		Descriptor::file(b"Baz"),
+		Descriptor::file(b"File"),
+	];
+
+	let result = DirFmt::new(".", &dir, &TreeArt::COMPACT).to_string();
+	assert_eq!(result, "./\n├ Foo/\n│ ├ Bar\n│ └ Baz\n└ File\n");
+
+	let result = DirFmt::new(".", &dir, &TreeArt::MARKDOWN).to_string();
+	assert_eq!(result, "./\n- Foo/\n  - Bar\n  - Baz\n- File\n");
+
+	// Custom art borrowed from a local string
+	let bullet = String::from("* ");
+	let art = TreeArt { dir_entry: &bullet, file_entry: &bullet, file_last: &bullet, ..TreeArt::MARKDOWN };
+	let result = DirFmt::new("root", &dir, &art).to_string();
+	assert_eq!(result, "root/\n* Foo/\n  * Bar\n  * Baz\n* File\n");
+
+	// Only files with a named content type are followed by the name
+	let mut types = ContentTypes::new();
+	types.insert(2, "texture/bc7");
+	let mut dir = dir;
+	dir[2].content_type = 2;
+	let result = DirFmt::new(".", &dir, &TreeArt::MARKDOWN).with_types(&types).to_string();
+	assert_eq!(result, "./\n- Foo/\n  - Bar\n  - Baz (texture/bc7)\n- File\n");
+}
+
+#[test]
+fn test_flat_fmt() {
+	let mut dir = Vec::new();
+	create(&mut dir, b"b/file", Separators::Both).content_type = 1;
+	create(&mut dir, b"a", Separators::Both).content_type = 1;
+	create(&mut dir, b"b/sub/tab\tname", Separators::Both).content_type = 1;
+	create(&mut dir, b"b/file", Separators::Both).content_size = 10;
+
+	let result = FlatFmt::new("", &dir, true).to_string();
+	assert_eq!(result, "a\tfile\t0\nb\tdir\t0\nb/file\tfile\t10\nb/sub\tdir\t0\nb/sub/tab\\tname\tfile\t0\n");
+
+	let result = FlatFmt::new("", &dir, false).to_string();
+	assert_eq!(result, "a\tfile\t0\nb\tdir\t0\n");
+
+	let result = FlatFmt::new("b/", find_dir(&dir, b"b", Separators::Both).unwrap(), false).to_string();
+	assert_eq!(result, "b/file\tfile\t10\nb/sub\tdir\t0\n");
+
+	// The content types are named or numbered
+	let mut types = ContentTypes::new();
+	types.insert(2, "texture\tbc7");
+	create(&mut dir, b"a", Separators::Both).content_type = 2;
+	let result = FlatFmt::new("", &dir, false).with_types(&types).to_string();
+	assert_eq!(result, "a\tfile\t0\ttexture\\tbc7\nb\tdir\t0\n");
+	let result = FlatFmt::new("b/", find_dir(&dir, b"b", Separators::Both).unwrap(), false).with_types(&types).to_string();
+	assert_eq!(result, "b/file\tfile\t10\t1\nb/sub\tdir\t0\n");
+}
+
 #[test]
 fn test_find_empty() {
-	assert_eq!(find(&[], b"path"), &[]);
+	assert_eq!(find(&[], b"path", Separators::Both), &[]);
 }
 
 #[test]
 fn test_find_desc01() {
 	let mut dir = Vec::new();
-	create(&mut dir, b"A/B/C");
+	create(&mut dir, b"A/B/C", Separators::Both);
 
-	let result1 = find_desc(&dir, b"A/B/C");
-	let result2 = find_desc(&dir, b"A/B/D");
+	let result1 = find_desc(&dir, b"A/B/C", Separators::Both);
+	let result2 = find_desc(&dir, b"A/B/D", Separators::Both);
 
 	assert_eq!(result1.unwrap().name(), b"C");
 	assert!(result2.is_none());
@@ -129,15 +186,43 @@ fn test_find() {
 		Descriptor::file(b"file"),
 	];
 
-	assert!(ptr::eq(find(&dir, b"before"), &dir[0..1]));
-	assert!(ptr::eq(find(&dir, b"a"), &dir[1..]));
+	assert!(ptr::eq(find(&dir, b"before", Separators::Both), &dir[0..1]));
+	assert!(ptr::eq(find(&dir, b"a", Separators::Both), &dir[1..]));
+
+	assert!(ptr::eq(find(&dir[2..], b"b", Separators::Both), &dir[2..]));
+
+	assert_eq!(find(&dir, "file".as_ref(), Separators::Both).len(), 0);
+	assert!(ptr::eq(find(&dir[4..], b"file", Separators::Both), &dir[4..]));
+
+	assert_eq!(find_desc(&dir, b"a\\b\\c\\file", Separators::Both).map(|x| x as *const _), Some(&dir[4] as *const _));
+}
 
-	assert!(ptr::eq(find(&dir[2..], b"b"), &dir[2..]));
+#[test]
+fn test_path_of() {
+	let dir = [
+		Descriptor::file(b"before"),
+		Descriptor::dir(b"a", 3),
+		Descriptor::dir(b"b", 2),
+		Descriptor::dir(b"c", 1),
+		Descriptor::file(b"file"),
+		Descriptor::file(b"after"),
+	];
 
-	assert_eq!(find(&dir, "file".as_ref()).len(), 0);
-	assert!(ptr::eq(find(&dir[4..], b"file"), &dir[4..]));
+	let paths: Vec<_> = (0..dir.len()).map(|i| path_of(&dir, i)).collect();
+	assert_eq!(paths, [
+		Some(b"before".to_vec()),
+		Some(b"a".to_vec()),
+		Some(b"a/b".to_vec()),
+		Some(b"a/b/c".to_vec()),
+		Some(b"a/b/c/file".to_vec()),
+		Some(b"after".to_vec()),
+	]);
+	assert_eq!(path_of(&dir, dir.len()), None);
 
-	assert_eq!(find_desc(&dir, b"a\\b\\c\\file").map(|x| x as *const _), Some(&dir[4] as *const _));
+	// Every path finds its descriptor again
+	for (i, path) in paths.iter().enumerate() {
+		assert!(ptr::eq(find_desc(&dir, path.as_ref().unwrap(), Separators::Both).unwrap(), &dir[i]));
+	}
 }
 
 #[test]
@@ -145,7 +230,7 @@ fn test_create_simple() {
 	let path = b"stuff.txt";
 
 	let mut dir = Vec::new();
-	create(&mut dir, path);
+	create(&mut dir, path, Separators::Both);
 
 	assert_eq!(dir.len(), 1);
 	let file = &dir[0];
@@ -162,8 +247,8 @@ fn test_create_simple_dirs() {
 	let path2 = b"A/BAR";
 
 	let mut dir = Vec::new();
-	create(&mut dir, path1);
-	create(&mut dir, path2);
+	create(&mut dir, path1, Separators::Both);
+	create(&mut dir, path2, Separators::Both);
 
 	let result = [
 		Descriptor::dir(b"A", 2),
@@ -184,6 +269,145 @@ fn test_create_simple_dirs() {
 // 		mac: Block::default(),
 // 	};
 // 	crypt2::encrypt_section(directory.as_blocks_mut(), &mut section, key);
-// 	let found = find_encrypted(directory.as_ref(), b"a/b/c/file", &section.nonce, key);
+// 	let found = find_encrypted(directory.as_ref(), b"a/b/c/file", &section.nonce, key, Separators::Both);
 // 	assert!(matches!(found, Some(_)));
 // }
+
+#[test]
+fn test_fsck_overflow() {
+	let high_mark = 100;
+	let mut log = String::new();
+
+	let mut file = Descriptor::file(b"file");
+	file.section.offset = 10;
+	file.section.size = 10;
+	file.content_size = 160;
+	assert!(fsck(&[file], high_mark, Separators::Both, &mut log));
+	assert_eq!(log, "");
+
+	// Section offset near the end of the address space
+	let mut bad = file;
+	bad.section.offset = u32::MAX - 5;
+	assert!(!fsck(&[bad], high_mark, Separators::Both, &mut log));
+
+	// Section size larger than the PAKS file
+	let mut bad = file;
+	bad.section.size = u32::MAX;
+	assert!(!fsck(&[bad], high_mark, Separators::Both, &mut log));
+
+	// Content size does not fit in the section
+	let mut bad = file;
+	bad.content_size = u32::MAX;
+	assert!(!fsck(&[bad], high_mark, Separators::Both, &mut log));
+
+	// Errors in nested directories are reported
+	let dir = [Descriptor::dir(b"dir", 1), bad];
+	log.clear();
+	assert!(!fsck(&dir, high_mark, Separators::Both, &mut log));
+	assert!(log.contains("dir/file"), "{}", log);
+}
+
+#[test]
+fn test_fsck_separators() {
+	let mut log = String::new();
+	let dir = [Descriptor::dir(b"a\\b", 0)];
+	assert!(fsck(&dir, 100, Separators::Strict, &mut log));
+	assert!(!fsck(&dir, 100, Separators::Both, &mut log));
+	assert!(!fsck(&[Descriptor::dir(b"a/b", 0)], 100, Separators::Strict, &mut log));
+
+	assert_eq!(validate_path_with(b"a\\b", Separators::Both), Ok(()));
+	assert_eq!(validate_path_with(b"a\\\\b", Separators::Both), Err(PathError::EmptyComponent));
+	assert_eq!(validate_path_with(b"a\\\\b", Separators::Strict), Ok(()));
+	assert_eq!(validate_path_with(b"..\\a", Separators::Strict), Ok(()));
+}
+
+#[test]
+fn test_validate() {
+	let high_mark = 100;
+	let file = |name: &[u8], offset, size, content_size| {
+		let mut desc = Descriptor::file(name);
+		desc.section.offset = offset;
+		desc.section.size = size;
+		desc.content_size = content_size;
+		desc
+	};
+
+	let dir = [
+		Descriptor::dir(b"a", 2),
+		file(b"x", 10, 2, 20),
+		file(b"y", 12, 3, 40),
+		file(b"z", 12, 3, 40),
+		file(b"empty", 0, 0, 0),
+	];
+	assert!(validate(&dir, high_mark));
+	assert!(validate(&[], high_mark));
+
+	// Children do not fit in the parent directory
+	assert!(!validate(&[Descriptor::dir(b"a", 1)], high_mark));
+	assert!(!validate(&[Descriptor::dir(b"a", 2), Descriptor::dir(b"b", 2), file(b"x", 10, 1, 1), file(b"y", 20, 1, 1)], high_mark));
+	assert!(!validate(&[Descriptor::dir(b"a", u32::MAX)], high_mark));
+
+	// Sections out of bounds
+	assert!(!validate(&[file(b"x", 1, 1, 1)], high_mark));
+	assert!(!validate(&[file(b"x", 99, 2, 1)], high_mark));
+	assert!(!validate(&[file(b"x", u32::MAX, 2, 1)], high_mark));
+
+	// Content size larger than its section
+	assert!(!validate(&[file(b"x", 10, 1, 17)], high_mark));
+	assert!(!validate(&[file(b"x", 0, 0, 1)], high_mark));
+
+	// Overlapping sections
+	assert!(!validate(&[file(b"x", 10, 3, 1), file(b"y", 12, 3, 1)], high_mark));
+	assert!(!validate(&[file(b"x", 10, 3, 1), file(b"y", 10, 2, 1)], high_mark));
+
+	// Unknown flags
+	let mut unknown = file(b"x", 10, 1, 1);
+	unknown.flags = 256;
+	assert!(!validate(&[unknown], high_mark));
+	let mut unknown = Descriptor::dir(b"a", 0);
+	unknown.flags = 1 << 31;
+	assert!(!validate(&[unknown], high_mark));
+}
+
+#[test]
+fn test_check_portable() {
+	let mut log = String::new();
+	let mut dir = Vec::new();
+	create(&mut dir, b"textures/wall.png", Separators::Both);
+	create(&mut dir, b"readme.txt", Separators::Both);
+	assert!(check_portable(&dir, &Portable::DEFAULT, &mut log));
+	assert_eq!(log, "");
+
+	let not_portable = |path: &[u8]| {
+		let mut dir = Vec::new();
+		create(&mut dir, path, Separators::Strict);
+		let mut log = String::new();
+		let portable = check_portable(&dir, &Portable::DEFAULT, &mut log);
+		assert_eq!(portable, log.is_empty());
+		portable
+	};
+	assert!(!not_portable(b"a/con"));
+	assert!(!not_portable(b"Aux.txt"));
+	assert!(!not_portable(b"LPT1"));
+	assert!(not_portable(b"COM10"));
+	assert!(not_portable(b"console"));
+	assert!(!not_portable(b"trailing."));
+	assert!(!not_portable(b"trailing "));
+	assert!(!not_portable(b"a\\b"));
+	assert!(!not_portable(b"what?"));
+	assert!(!not_portable("caf\u{e9}".as_bytes()));
+	assert!(!not_portable(&[[b'a'; 30]; 7].join(&b'/')));
+	assert!(not_portable(&[[b'a'; 30]; 6].join(&b'/')));
+	assert!(!not_portable(&b"a/".repeat(17)[..33]));
+	assert!(not_portable(&b"a/".repeat(16)[..31]));
+
+	// Names which only differ in case collide
+	create(&mut dir, b"textures/Wall.png", Separators::Both);
+	assert!(!check_portable(&dir, &Portable::DEFAULT, &mut log));
+	assert!(log.contains("/textures/Wall.png"), "{}", log);
+
+	// Stricter or looser profiles
+	let profile = Portable { max_name_len: 8, ascii_only: false, ..Portable::DEFAULT };
+	assert!(check_portable(&[Descriptor::file("caf\u{e9}".as_bytes())], &profile, &mut String::new()));
+	assert!(!check_portable(&[Descriptor::file(b"long_name")], &profile, &mut String::new()));
+}
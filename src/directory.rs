@@ -4,8 +4,20 @@ use super::*;
 ///
 /// The directory is a sequence of descriptors encoding a light-weight [TLV structure](https://en.wikipedia.org/wiki/Type-length-value).
 #[derive(Clone, Debug, Default)]
-#[repr(transparent)]
-pub struct Directory(Vec<Descriptor>);
+pub struct Directory(Vec<Descriptor>, /* generation */ u32, Separators);
+
+/// Descriptor handle.
+///
+/// Unlike borrowing a descriptor, a handle does not keep the directory borrowed.
+/// Resolve the handle with [`Directory::resolve`] to access the descriptor.
+///
+/// Adding, removing or moving descriptors shifts the descriptors around and invalidates all handles into the directory.
+/// Resolving an invalidated handle returns `None` instead of a different descriptor.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct DescId {
+	index: u32,
+	generation: u32,
+}
 
 impl AsRef<[Descriptor]> for Directory {
 	#[inline]
@@ -22,7 +34,7 @@ impl AsMut<[Descriptor]> for Directory {
 impl From<Vec<Descriptor>> for Directory {
 	#[inline]
 	fn from(dir: Vec<Descriptor>) -> Directory {
-		Directory(dir)
+		Directory(dir, 0, Separators::Both)
 	}
 }
 impl From<Directory> for Vec<Descriptor> {
@@ -58,38 +70,212 @@ impl Directory {
 	/// Finds a descriptor by its path.
 	#[inline]
 	pub fn find_desc(&self, path: &[u8]) -> Option<&Descriptor> {
-		dir::find_desc(&self.0, path)
+		dir::find_desc(&self.0, path, self.2)
 	}
 
 	/// Finds a file descriptor by its path.
 	#[inline]
 	pub fn find_file(&self, path: &[u8]) -> Option<&Descriptor> {
-		match dir::find_desc(&self.0, path) {
+		match dir::find_desc(&self.0, path, self.2) {
+			Some(desc) if desc.is_file() => Some(desc),
+			_ => None
+		}
+	}
+
+	/// Finds a descriptor by its path, decrypting the names with the name key.
+	///
+	/// See [`Descriptor::FLAG_ENCRYPTED_NAME`] for more information.
+	#[inline]
+	pub fn find_desc_with_key(&self, path: &[u8], key: &NameKey) -> Option<&Descriptor> {
+		dir::find_with_key(&self.0, path, key, self.2).first()
+	}
+
+	/// Finds a file descriptor by its path, decrypting the names with the name key.
+	#[inline]
+	pub fn find_file_with_key(&self, path: &[u8], key: &NameKey) -> Option<&Descriptor> {
+		match dir::find_with_key(&self.0, path, key, self.2).first() {
 			Some(desc) if desc.is_file() => Some(desc),
 			_ => None
 		}
 	}
 
+	/// Finds a descriptor by the hash of its path.
+	///
+	/// Only descriptors which store the hash of their path are found, see [`hash_paths`](Self::hash_paths).
+	#[inline]
+	pub fn find_by_hash(&self, hash: u64) -> Option<&Descriptor> {
+		self.0.iter().find(|desc| desc.path_hash() == Some(hash))
+	}
+
+	/// Finds the handle of a descriptor by its path.
+	#[inline]
+	pub fn find_id(&self, path: &[u8]) -> Option<DescId> {
+		let desc = dir::find_desc(&self.0, path, self.2)?;
+		self.id_of(desc)
+	}
+
+	/// Gets the handle of a descriptor borrowed from this directory.
+	///
+	/// Returns `None` if the descriptor is not part of this directory.
+	#[inline]
+	pub fn id_of(&self, desc: &Descriptor) -> Option<DescId> {
+		let index = index_of(&self.0, desc as *const Descriptor as usize)?;
+		Some(DescId { index: index as u32, generation: self.1 })
+	}
+
+	/// Resolves the handle to its descriptor.
+	///
+	/// Returns `None` if the handle is invalidated by adding, removing or moving descriptors.
+	#[inline]
+	pub fn resolve(&self, id: DescId) -> Option<&Descriptor> {
+		if id.generation != self.1 {
+			return None;
+		}
+		self.0.get(id.index as usize)
+	}
+
+	/// Resolves the handle to its descriptor for editing.
+	///
+	/// Returns `None` if the handle is invalidated by adding, removing or moving descriptors.
+	#[inline]
+	pub fn resolve_mut(&mut self, id: DescId) -> Option<&mut Descriptor> {
+		if id.generation != self.1 {
+			return None;
+		}
+		self.0.get_mut(id.index as usize)
+	}
+
+	/// Reconstructs the path of a descriptor borrowed from this directory.
+	///
+	/// Returns `None` if the descriptor is not part of this directory.
+	#[inline]
+	pub fn path_of(&self, desc: &Descriptor) -> Option<Vec<u8>> {
+		let index = index_of(&self.0, desc as *const Descriptor as usize)?;
+		dir::path_of(&self.0, index)
+	}
+
 	/// Gets the child descriptors of the directory at the given path.
 	#[inline]
 	pub fn get_children(&self, path: &[u8]) -> Option<&[Descriptor]> {
-		dir::find_dir(&self.0, path)
+		dir::find_dir(&self.0, path, self.2)
+	}
+
+	// Are the children the root directory? The archive metadata is hidden from its listings
+	fn is_root(&self, children: &[Descriptor]) -> bool {
+		ptr::eq(children, &self.0[..])
 	}
 
 	/// Returns a displayable directory.
 	#[inline]
 	pub fn display(&self) -> impl '_ + fmt::Display {
-		dir::DirFmt::new(".", &self.0, &dir::TreeArt::UNICODE)
+		dir::DirFmt::new(".", &self.0, &dir::TreeArt::UNICODE).hide_metadata()
 	}
 
 	/// Returns a displayable subdirectory.
 	#[inline]
-	pub fn display_children<'a>(&'a self, path: Option<&'a str>, art: &'a dir::TreeArt<'static>) -> Option<impl 'a + fmt::Display> {
+	pub fn display_children<'a>(&'a self, path: Option<&'a str>, art: &'a dir::TreeArt<'a>) -> Option<impl 'a + fmt::Display> {
+		let children = match path {
+			Some(path) => dir::find_dir(&self.0, path.as_bytes(), self.2)?,
+			None => &self.0,
+		};
+		let fmt = dir::DirFmt::new(path.unwrap_or("."), children, art);
+		Some(if self.is_root(children) { fmt.hide_metadata() } else { fmt })
+	}
+
+	/// Returns a displayable subdirectory showing the names of the content types.
+	///
+	/// Files with a named content type are followed by its name in parentheses, see [`ContentTypes`].
+	#[inline]
+	pub fn display_children_with_types<'a>(&'a self, path: Option<&'a str>, art: &'a dir::TreeArt<'a>, types: &'a ContentTypes) -> Option<impl 'a + fmt::Display> {
+		let children = match path {
+			Some(path) => dir::find_dir(&self.0, path.as_bytes(), self.2)?,
+			None => &self.0,
+		};
+		let fmt = dir::DirFmt::new(path.unwrap_or("."), children, art).with_types(types);
+		Some(if self.is_root(children) { fmt.hide_metadata() } else { fmt })
+	}
+
+	/// Returns a displayable flat listing of the directory.
+	///
+	/// Every entry is printed on its own line as `path<TAB>type<TAB>size`, sorted by path.
+	/// The type is `dir` or `file`, the size is the content size of files and zero for directories.
+	/// Backslashes, tabs and newlines in the path are escaped as `\\`, `\t` and `\n`.
+	///
+	/// Unlike the tree art this format is easy to parse and to diff between builds.
+	#[inline]
+	pub fn display_flat(&self) -> impl '_ + fmt::Display {
+		dir::FlatFmt::new("", &self.0, true).hide_metadata()
+	}
+
+	/// Returns a displayable flat listing of a subdirectory.
+	///
+	/// Without recursion only the direct children of the subdirectory are listed.
+	#[inline]
+	pub fn display_flat_children<'a>(&'a self, path: Option<&'a str>, recursive: bool) -> Option<impl 'a + fmt::Display> {
+		let children = match path {
+			Some(path) => dir::find_dir(&self.0, path.as_bytes(), self.2)?,
+			None => &self.0,
+		};
+		let fmt = dir::FlatFmt::new(path.unwrap_or(""), children, recursive);
+		Some(if self.is_root(children) { fmt.hide_metadata() } else { fmt })
+	}
+
+	/// Returns a displayable flat listing of a subdirectory with the content types.
+	///
+	/// Files have a fourth column with the name of their content type or its number if it has no name, see [`ContentTypes::describe`].
+	#[inline]
+	pub fn display_flat_children_with_types<'a>(&'a self, path: Option<&'a str>, recursive: bool, types: &'a ContentTypes) -> Option<impl 'a + fmt::Display> {
 		let children = match path {
-			Some(path) => dir::find_dir(&self.0, path.as_bytes())?,
+			Some(path) => dir::find_dir(&self.0, path.as_bytes(), self.2)?,
 			None => &self.0,
 		};
-		Some(dir::DirFmt::new(path.unwrap_or("."), children, art))
+		let fmt = dir::FlatFmt::new(path.unwrap_or(""), children, recursive).with_types(types);
+		Some(if self.is_root(children) { fmt.hide_metadata() } else { fmt })
+	}
+
+	/// Returns the paths and content sizes of the `n` largest files, ordered by descending size.
+	///
+	/// Links are listed once as they share their contents, files of equal size are ordered by path.
+	#[inline]
+	pub fn top_n_by_size(&self, n: usize) -> Vec<(Vec<u8>, u32)> {
+		dir::top_n_by_size(&self.0, n)
+	}
+
+	/// Returns a digest of the logical contents of the directory.
+	///
+	/// Compare the digests of two PAKS files to tell if their contents changed without comparing all their bytes.
+	/// The digest covers every descriptor in order, including the names, the content types and sizes and the nonce and MAC of the file contents.
+	/// The location of the file contents in the PAKS file is not covered, garbage collection does not change the digest.
+	///
+	/// Equal digests imply equal file contents as the MAC authenticates the contents.
+	/// Files are encrypted with a random nonce, creating a file again changes the digest even if its contents are the same.
+	/// Plaintext file contents are not authenticated, editing them inplace does not change the digest.
+	///
+	/// The digest is the SHA-256 of the descriptors with the offsets of their sections zeroed, it is stable across versions of this library.
+	/// Inline file contents are covered in full.
+	///
+	/// The archive id is covered but its generation is not, finishing an edit without changes does not change the digest, see [`archive_generation`](Self::archive_generation).
+	pub fn digest(&self) -> hash::Digest {
+		let archive_id = self.find_file(ARCHIVE_ID_PATH);
+		let mut hasher = hash::Sha256::new();
+		for desc in &self.0 {
+			let mut copy = *desc;
+			// Inline file contents are stored in place of the section object
+			if !copy.is_inline() {
+				copy.section.offset = 0;
+			}
+			if let Some((_, size)) = copy.delta_base() {
+				copy.set_delta_base(0, size);
+			}
+			// The generation is bumped by every finish
+			if archive_id.is_some_and(|archive_id| ptr::eq(archive_id, desc)) {
+				if let Some(id) = ArchiveId::from_desc(desc) {
+					archive_id::set_record(&mut copy, id, 0);
+				}
+			}
+			hasher.update(dataview::bytes(&copy));
+		}
+		hasher.finish()
 	}
 
 	/// File system consistency check.
@@ -100,20 +286,82 @@ impl Directory {
 	/// The high mark is the highest block index that a file section is allowed.
 	#[inline]
 	pub fn fsck(&self, high_mark: u32, log: &mut dyn fmt::Write) -> bool {
-		dir::fsck(&self.0, high_mark, log)
+		dir::fsck(&self.0, high_mark, self.2, log)
+	}
+
+	/// Checks if the PAKS file can be extracted on all operating systems.
+	///
+	/// Logs every path which breaks the portable profile and returns `false` if any were found.
+	/// Encrypted and stripped names are not checked, only their depth.
+	#[inline]
+	pub fn check_portable(&self, profile: &Portable, log: &mut dyn fmt::Write) -> bool {
+		dir::check_portable(&self.0, profile, log)
 	}
 }
 impl Directory {
 	/// Creates a new, empty `Directory` instance.
 	#[inline]
 	pub const fn new() -> Directory {
-		Directory(Vec::new())
+		Directory(Vec::new(), 0, Separators::Both)
+	}
+
+	/// Returns the path separator policy.
+	#[inline]
+	pub fn separators(&self) -> Separators {
+		self.2
+	}
+
+	/// Sets the path separator policy used to find, create and remove descriptors by their path.
+	///
+	/// Defaults to [`Separators::Both`].
+	/// The policy is not stored in the PAKS file, readers and editors of PAKS files created with [`Separators::Strict`] must set it again.
+	#[inline]
+	pub fn set_separators(&mut self, sep: Separators) -> &mut Directory {
+		self.2 = sep;
+		self
+	}
+
+	// Creates the descriptor and invalidates the handles if any descriptors were added
+	fn create_inner(&mut self, path: &[u8]) -> &mut Descriptor {
+		let len = self.0.len();
+		let addr = dir::create(&mut self.0, path, self.2) as *mut Descriptor as usize;
+		if self.0.len() != len {
+			self.1 = self.1.wrapping_add(1);
+		}
+		let index = index_of(&self.0, addr).unwrap();
+		&mut self.0[index]
+	}
+
+	/// Creates a descriptor at the given path.
+	///
+	/// Any missing parent directories are automatically created.
+	/// Returns the existing descriptor if one already exists at the given path.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the path is invalid, see [`validate_path`], or reserved for the archive metadata, see [`METADATA_PATHS`].
+	#[inline]
+	pub fn create(&mut self, path: &[u8]) -> Result<&mut Descriptor, PathError> {
+		dir::validate_path_with(path, self.2)?;
+		if dir::is_metadata_path(path) {
+			return Err(PathError::Reserved);
+		}
+		Ok(self.create_inner(path))
+	}
+
+	// Creates the descriptor of the archive metadata, its paths are reserved and cannot be created with create
+	pub(crate) fn create_metadata(&mut self, path: &[u8]) -> &mut Descriptor {
+		debug_assert!(dir::is_metadata_path(path));
+		self.create_inner(path)
 	}
 
-	// For internal use
+	/// Creates a descriptor at the given path without validating the path.
+	///
+	/// This is the legacy behavior of [`create`](Self::create):
+	/// empty components are ignored and names longer than the descriptor's name buffer are cut off.
 	#[inline]
-	pub(crate) fn create(&mut self, path: &[u8]) -> &mut Descriptor {
-		dir::create(&mut self.0, path)
+	pub fn create_lenient(&mut self, path: &[u8]) -> &mut Descriptor {
+		self.create_inner(path)
 	}
 
 	/// Creates a symbolic link from the path to the given file descriptor.
@@ -122,24 +370,95 @@ impl Directory {
 	///
 	/// Does nothing if the given descriptor is not a file descriptor.
 	#[inline]
-	pub fn create_link(&mut self, path: &[u8], file_desc: &Descriptor) {
+	pub fn create_link(&mut self, path: &[u8], file_desc: &Descriptor) -> Result<(), PathError> {
 		if file_desc.is_file() {
-			let desc = dir::create(&mut self.0, path);
+			let desc = self.create(path)?;
 			desc.content_size = file_desc.content_size;
 			desc.content_type = file_desc.content_type;
 			desc.section = file_desc.section;
+			desc.reserved[6..].copy_from_slice(&file_desc.reserved[6..]);
+			desc.flags = file_desc.flags & !(Descriptor::FLAG_ENCRYPTED_NAME | Descriptor::FLAG_PATH_HASH | Descriptor::FLAG_STRIPPED_NAME);
+		}
+		Ok(())
+	}
+
+	/// Creates an empty file at the given path.
+	///
+	/// Any missing parent directories are automatically created.
+	/// The file is stored inline, see [`Descriptor::FLAG_INLINE`], it has no section and does not need a key.
+	/// An existing file at the given path is replaced, a directory descriptor with children at the given path is left unchanged.
+	#[inline]
+	pub fn create_empty_file(&mut self, path: &[u8]) -> Result<(), PathError> {
+		let desc = self.create(path)?;
+		// Replacing a directory would orphan its children
+		if desc.is_file() || desc.is_empty_dir() {
+			desc.content_type = 1;
+			desc.set_inline_data(b"");
+		}
+		Ok(())
+	}
+
+	/// Returns the archive id, see [`ARCHIVE_ID_PATH`].
+	///
+	/// Returns `None` if the PAKS file was created without an archive id.
+	#[inline]
+	pub fn archive_id(&self) -> Option<ArchiveId> {
+		self.find_file(ARCHIVE_ID_PATH).and_then(ArchiveId::from_desc)
+	}
+
+	/// Returns the generation of the PAKS file.
+	///
+	/// The generation is stored next to the archive id and counts the finished edits, every finish increments it.
+	/// Together with the archive id it identifies the exact state of the PAKS file, see [`patch::Patch`].
+	/// Returns `None` if the PAKS file was created without an archive id.
+	#[inline]
+	pub fn archive_generation(&self) -> Option<u32> {
+		self.find_file(ARCHIVE_ID_PATH).and_then(archive_id::generation)
+	}
+
+	/// Stores the archive id, see [`ARCHIVE_ID_PATH`].
+	///
+	/// The archive id is stored inline and does not need a key, the generation restarts at zero.
+	/// Replace the archive id only to give a copy of a PAKS archive its own identity, see [`MemoryEditor::generate_archive_id`].
+	pub fn set_archive_id(&mut self, id: ArchiveId) {
+		let desc = self.create_inner(ARCHIVE_ID_PATH);
+		archive_id::set_record(desc, id, 0);
+	}
+
+	// Carries the archive id and its generation over from the previous directory of a rebuilt PAKS file
+	pub(crate) fn keep_archive_id(&mut self, prev: &Directory) {
+		if let Some(desc) = prev.find_file(ARCHIVE_ID_PATH).filter(|desc| ArchiveId::from_desc(desc).is_some()) {
+			*self.create_inner(ARCHIVE_ID_PATH) = *desc;
+		}
+	}
+
+	// Increments the generation when finishing an edit, PAKS files without an archive id have no generation
+	pub(crate) fn bump_archive_generation(&mut self) {
+		let desc = match self.find_id(ARCHIVE_ID_PATH).and_then(|id| self.resolve_mut(id)) {
+			Some(desc) => desc,
+			None => return,
+		};
+		if let Some(id) = ArchiveId::from_desc(desc) {
+			let generation = archive_id::generation(desc).unwrap_or(0).saturating_add(1);
+			archive_id::set_record(desc, id, generation);
 		}
 	}
 
 	/// Creates a directory descriptor at the given path.
 	///
 	/// Any missing parent directories are automatically created.
+	/// An existing directory descriptor at the given path is left unchanged.
 	#[inline]
-	pub fn create_dir(&mut self, path: &[u8]) {
-		let desc = dir::create(&mut self.0, path);
-		desc.content_type = 0;
-		desc.content_size = 0;
-		desc.section = Section::default();
+	pub fn create_dir(&mut self, path: &[u8]) -> Result<(), PathError> {
+		let desc = self.create(path)?;
+		// Resetting an existing directory would orphan its children
+		if desc.is_file() {
+			desc.content_type = 0;
+			desc.content_size = 0;
+			desc.section = Section::default();
+			desc.flags = 0;
+		}
+		Ok(())
 	}
 
 	/// Removes a descriptor at the given path.
@@ -153,9 +472,26 @@ impl Directory {
 	/// Returns `Some(desc)` if a directory descriptor is found at the given path.
 	/// The descriptor is removed and returned for inspection.
 	/// All the direct children of the removed directory are moved to its parent directory.
+	///
+	/// Returns `None` if the path is reserved for the archive metadata, see [`METADATA_PATHS`].
 	#[inline]
 	pub fn remove(&mut self, path: &[u8]) -> Option<Descriptor> {
-		dir::remove(&mut self.0, path)
+		if dir::is_metadata_path(path) {
+			return None;
+		}
+		self.remove_inner(path)
+	}
+
+	// Removes the descriptor of the archive metadata, its paths are reserved and cannot be removed with remove
+	pub(crate) fn remove_metadata(&mut self, path: &[u8]) -> Option<Descriptor> {
+		debug_assert!(dir::is_metadata_path(path));
+		self.remove_inner(path)
+	}
+
+	fn remove_inner(&mut self, path: &[u8]) -> Option<Descriptor> {
+		let deleted = dir::remove(&mut self.0, path, self.2)?;
+		self.1 = self.1.wrapping_add(1);
+		Some(deleted)
 	}
 
 	/// Moves a file descriptor from the src path to the given dest path.
@@ -163,27 +499,79 @@ impl Directory {
 	/// Returns `false` if the src path does not exist or is a directory descriptor.
 	/// This method cannot move directory descriptors.
 	///
+	/// Returns `false` if the dest path is invalid, see [`validate_path`].
+	///
+	/// Returns `false` if either path is reserved for the archive metadata, see [`METADATA_PATHS`].
+	///
 	/// Returns `true` if the move was successful.
 	pub fn move_file(&mut self, src_path: &[u8], dest_path: &[u8]) -> bool {
 		// Check to make sure it's a file descriptor
 		// Moving directory descriptors like this corrupts the directory
-		match dir::find_desc(&self.0, src_path) {
+		match dir::find_desc(&self.0, src_path, self.2) {
 			Some(src_desc) if src_desc.is_file() => (),
 			_ => return false,
 		}
 
+		// Check the destination before removing anything
+		if dir::validate_path_with(dest_path, self.2).is_err() || dir::is_metadata_path(src_path) || dir::is_metadata_path(dest_path) {
+			return false;
+		}
+
 		// Delete the descriptor
-		let deleted = match dir::remove(&mut self.0, src_path) {
+		let deleted = match self.remove(src_path) {
 			Some(deleted) => deleted,
 			None => return false,
 		};
 
-		let desc = dir::create(&mut self.0, dest_path);
+		let desc = self.create_inner(dest_path);
 		desc.content_type = deleted.content_type;
 		desc.content_size = deleted.content_size;
 		desc.section = deleted.section;
+		desc.reserved[6..].copy_from_slice(&deleted.reserved[6..]);
+		desc.flags = deleted.flags & !(Descriptor::FLAG_ENCRYPTED_NAME | Descriptor::FLAG_PATH_HASH | Descriptor::FLAG_STRIPPED_NAME);
 		return true;
 	}
+
+	/// Stores the hash of the path in every descriptor, see [`Descriptor::FLAG_PATH_HASH`].
+	///
+	/// The hashes are not updated when the directory is edited, hash the paths again before finishing the PAKS file.
+	/// Descriptors with an encrypted name and their children are skipped.
+	#[inline]
+	pub fn hash_paths(&mut self) {
+		dir::hash_paths(&mut self.0);
+	}
+
+	/// Encrypts all the names with the name key.
+	///
+	/// Names which are already encrypted are left unchanged.
+	/// Afterwards descriptors can only be found with [`find_desc_with_key`](Self::find_desc_with_key), the path based editing methods no longer see them.
+	/// Decrypt the names before editing, migrating or extracting the PAKS file.
+	pub fn encrypt_names(&mut self, key: &NameKey) {
+		for desc in &mut self.0 {
+			desc.encrypt_name(key);
+		}
+	}
+
+	/// Decrypts all the names with the name key.
+	///
+	/// Returns false if any of the names fails to decrypt, those names are left encrypted.
+	pub fn decrypt_names(&mut self, key: &NameKey) -> bool {
+		let mut success = true;
+		for desc in &mut self.0 {
+			success &= desc.decrypt_name(key);
+		}
+		success
+	}
+}
+
+// Finds the index of the descriptor at the given address
+fn index_of(dir: &[Descriptor], addr: usize) -> Option<usize> {
+	let offset = addr.checked_sub(dir.as_ptr() as usize)?;
+	let index = offset / mem::size_of::<Descriptor>();
+	if offset % mem::size_of::<Descriptor>() != 0 || index >= dir.len() {
+		return None;
+	}
+	Some(index)
 }
 
 #[cfg(test)]
@@ -9,9 +9,209 @@ fn test_create_remove_create_links() {
 	]);
 
 	let example1 = directory.as_ref()[2];
-	directory.create_link(b"aa/bb/example", &example1);
+	directory.create_link(b"aa/bb/example", &example1).unwrap();
 	let example2 = directory.remove(b"a/b/example").unwrap();
-	directory.create_link(b"a/b/example", &example2);
+	directory.create_link(b"a/b/example", &example2).unwrap();
 
 	dbg!(directory);
 }
+
+#[test]
+fn test_create_invalid_paths() {
+	let mut directory = Directory::new();
+
+	assert_eq!(directory.create(b"").err(), Some(PathError::Empty));
+	assert_eq!(directory.create(b"a//b").err(), Some(PathError::EmptyComponent));
+	assert_eq!(directory.create(b"/a").err(), Some(PathError::EmptyComponent));
+	assert_eq!(directory.create(b"a/").err(), Some(PathError::EmptyComponent));
+	assert_eq!(directory.create(b"a/../b").err(), Some(PathError::DotComponent));
+	assert_eq!(directory.create(&[b'x'; 40]).err(), Some(PathError::NameTooLong));
+	assert_eq!(directory.len(), 0);
+
+	assert!(directory.create(&[b'x'; 39]).is_ok());
+	assert!(directory.create(b"a\\b/c").is_ok());
+	assert_eq!(directory.len(), 4);
+
+	// The legacy mode truncates long names
+	let desc = directory.create_lenient(&[b'y'; 40]);
+	assert_eq!(desc.name(), &[b'y'; 39]);
+}
+
+#[test]
+fn test_separators() {
+	let mut directory = Directory::new();
+	directory.create(b"a\\b").unwrap();
+	assert!(directory.find_desc(b"a/b").is_some());
+
+	// Strict separators treat the `\` as part of the name
+	let mut directory = Directory::new();
+	directory.set_separators(Separators::Strict);
+	directory.create(b"a\\b/c").unwrap();
+	assert_eq!(directory.as_ref(), &[Descriptor::dir(b"a\\b", 1), Descriptor::dir(b"c", 0)]);
+	assert!(directory.find_desc(b"a\\b/c").is_some());
+	assert!(directory.find_desc(b"a/b/c").is_none());
+	assert!(directory.remove(b"a\\b/c").is_some());
+	assert!(directory.fsck(100, &mut String::new()));
+
+	// Names created with strict separators are rejected by the default policy
+	directory.set_separators(Separators::Both);
+	let mut log = String::new();
+	assert!(!directory.fsck(100, &mut log));
+	assert!(log.contains("path separator"), "{}", log);
+}
+
+#[test]
+fn test_create_empty_file() {
+	let mut directory = Directory::new();
+	directory.create_dir(b"a/b").unwrap();
+	directory.create_dir(b"c").unwrap();
+	assert!(!directory.find_desc(b"a").unwrap().is_empty_dir());
+	assert!(directory.find_desc(b"a/b").unwrap().is_empty_dir());
+
+	// Empty directories are replaced, directories with children are left unchanged
+	directory.create_empty_file(b"a").unwrap();
+	directory.create_empty_file(b"c").unwrap();
+	directory.create_empty_file(b"d/e").unwrap();
+	assert!(directory.find_desc(b"a").unwrap().is_dir());
+	let c = directory.find_file(b"c").unwrap();
+	assert_eq!((c.content_size, c.inline_data()), (0, Some(&b""[..])));
+	assert!(directory.find_file(b"d/e").unwrap().is_inline());
+	assert_eq!(directory.create_empty_file(b"d/../e"), Err(PathError::DotComponent));
+}
+
+#[test]
+fn test_create_dir_existing() {
+	let mut directory = Directory::new();
+	directory.create_link(b"a/b", &Descriptor::file(b"")).unwrap();
+	directory.create_dir(b"a").unwrap();
+
+	assert_eq!(directory.as_ref(), &[Descriptor::dir(b"a", 1), Descriptor::file(b"b")]);
+
+	// Files are still replaced by an empty directory
+	directory.create_dir(b"a/b").unwrap();
+	assert_eq!(directory.as_ref(), &[Descriptor::dir(b"a", 1), Descriptor::dir(b"b", 0)]);
+}
+
+#[test]
+fn test_desc_id() {
+	let mut directory = Directory::new();
+	directory.create(b"a/b").unwrap().content_type = 1;
+	directory.create(b"c").unwrap().content_type = 1;
+
+	// Handles do not keep the directory borrowed
+	let id = directory.find_id(b"a/b").unwrap();
+	directory.resolve_mut(id).unwrap().content_type = 42;
+	directory.create(b"c").unwrap().content_type = 13;
+	assert_eq!(directory.resolve(id).unwrap().content_type, 42);
+	assert_eq!(directory.id_of(directory.find_desc(b"a/b").unwrap()), Some(id));
+	assert_eq!(directory.id_of(&Descriptor::file(b"b")), None);
+	assert_eq!(directory.path_of(directory.resolve(id).unwrap()), Some(b"a/b".to_vec()));
+	assert_eq!(directory.path_of(&Descriptor::file(b"b")), None);
+
+	// Adding descriptors invalidates the handles
+	directory.create(b"a/a").unwrap();
+	assert_eq!(directory.resolve(id), None);
+	let id = directory.find_id(b"a/b").unwrap();
+	assert_eq!(directory.resolve(id).unwrap().content_type, 42);
+
+	// Removing or moving descriptors invalidates the handles
+	directory.remove(b"a/a");
+	assert_eq!(directory.resolve_mut(id), None);
+	let id = directory.find_id(b"c").unwrap();
+	assert!(directory.move_file(b"c", b"d"));
+	assert_eq!(directory.resolve(id), None);
+}
+
+#[test]
+fn test_encrypted_names() {
+	let ref name_key = [1, 2];
+	let mut directory = Directory::new();
+	directory.create(b"secret/level1.map").unwrap().content_type = 1;
+	directory.create(b"secret/level2.map").unwrap().content_type = 2;
+	directory.create(b"shared.txt").unwrap().content_type = 3;
+	let plain = directory.clone();
+
+	directory.encrypt_names(name_key);
+	assert!(directory.iter().all(|desc| desc.is_name_encrypted()));
+	assert_eq!(directory.find_desc(b"secret/level1.map"), None);
+	assert_eq!(directory.find_file_with_key(b"secret/level1.map", name_key).map(|desc| desc.content_type), Some(1));
+	assert_eq!(directory.find_file_with_key(b"secret\\level2.map", name_key).map(|desc| desc.content_type), Some(2));
+	assert_eq!(directory.find_desc_with_key(b"secret/", name_key).map(|desc| desc.is_dir()), Some(true));
+	assert_eq!(directory.find_desc_with_key(b"secret/level1.map", &[1, 3]), None);
+	assert_eq!(directory.find_file_with_key(b"secret", name_key), None);
+
+	// Incorrect name keys leave the names encrypted
+	assert!(!directory.clone().decrypt_names(&[1, 3]));
+	assert!(directory.decrypt_names(name_key));
+	assert_eq!(directory.as_ref(), plain.as_ref());
+}
+
+#[test]
+fn test_find_by_hash() {
+	let mut directory = Directory::new();
+	directory.create(b"textures/wall.png").unwrap().content_type = 1;
+	directory.create(b"textures/floor.png").unwrap().content_type = 2;
+	directory.create(b"music.ogg").unwrap().content_type = 3;
+	assert_eq!(directory.find_by_hash(path_hash(b"music.ogg")), None);
+
+	directory.hash_paths();
+	assert_eq!(path_hash(b"textures\\floor.png"), path_hash(b"textures/floor.png"));
+	assert_eq!(directory.find_by_hash(path_hash(b"textures/floor.png")).map(|desc| desc.content_type), Some(2));
+	assert_eq!(directory.find_by_hash(path_hash(b"music.ogg")).map(|desc| desc.content_type), Some(3));
+	assert_eq!(directory.find_by_hash(path_hash(b"textures")).map(|desc| desc.is_dir()), Some(true));
+	assert_eq!(directory.find_by_hash(path_hash(b"wall.png")), None);
+
+	// Links do not copy the hash of the linked path
+	let wall = *directory.find_file(b"textures/wall.png").unwrap();
+	directory.create_link(b"wall.png", &wall).unwrap();
+	assert_eq!(directory.find_file(b"wall.png").unwrap().path_hash(), None);
+}
+
+#[test]
+fn test_top_n_by_size() {
+	let mut directory = Directory::new();
+	for (path, offset, size) in [("a/small", 5, 10), ("a/big", 6, 300), ("b/c/mid", 30, 100), ("d", 40, 100)] {
+		let desc = directory.create(path.as_bytes()).unwrap();
+		desc.content_type = 1;
+		desc.content_size = size;
+		desc.section.offset = offset;
+		desc.section.size = size.div_ceil(16);
+	}
+	let big = *directory.find_file(b"a/big").unwrap();
+	directory.create_link(b"0/link", &big).unwrap();
+
+	// The link is listed once under its first path
+	assert_eq!(directory.top_n_by_size(3), vec![
+		(b"0/link".to_vec(), 300),
+		(b"b/c/mid".to_vec(), 100),
+		(b"d".to_vec(), 100),
+	]);
+	assert_eq!(directory.top_n_by_size(10).len(), 4);
+	assert_eq!(directory.top_n_by_size(0), vec![]);
+}
+
+#[test]
+fn test_reserved_metadata_paths() {
+	let mut directory = Directory::new();
+	directory.set_archive_id(ArchiveId([7, 8]));
+	directory.create_empty_file(b"a/.preload").unwrap();
+	directory.create_empty_file(b"b").unwrap();
+
+	// The archive metadata cannot be created, removed or moved like other files
+	assert_eq!(directory.create(PRELOAD_PATH).err(), Some(PathError::Reserved));
+	assert_eq!(directory.create_dir(CONTENT_TYPES_PATH).err(), Some(PathError::Reserved));
+	assert_eq!(directory.create_empty_file(ARCHIVE_ID_PATH).err(), Some(PathError::Reserved));
+	let b = *directory.find_file(b"b").unwrap();
+	assert_eq!(directory.create_link(ARCHIVE_ID_PATH, &b).err(), Some(PathError::Reserved));
+	assert_eq!(directory.remove(ARCHIVE_ID_PATH), None);
+	assert!(!directory.move_file(ARCHIVE_ID_PATH, b"c"));
+	assert!(!directory.move_file(b"b", PRELOAD_PATH));
+	assert_eq!(directory.archive_id(), Some(ArchiveId([7, 8])));
+	assert!(directory.find_file(b"b").is_some());
+
+	// The archive metadata is hidden from the listings, files with the same name in subdirectories are not
+	assert_eq!(directory.display_flat().to_string(), "a\tdir\t0\na/.preload\tfile\t0\nb\tfile\t0\n");
+	assert_eq!(directory.display_flat_children(None, false).unwrap().to_string(), "a\tdir\t0\nb\tfile\t0\n");
+	assert!(!directory.display().to_string().contains(".archive-id"));
+	assert!(directory.top_n_by_size(10).iter().all(|(path, _)| path != ARCHIVE_ID_PATH));
+}
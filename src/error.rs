@@ -0,0 +1,109 @@
+use super::*;
+
+/// Error type of the memory backend.
+///
+/// Carries the [`ErrorKind`] and, where applicable, the path of the file in the PAKS archive which caused the error.
+///
+/// Converts to and from [`std::io::Error`] so errors of the memory and file backends can be mixed with `?`.
+/// The path is preserved when converting back and forth.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Error {
+	kind: ErrorKind,
+	path: Option<Box<[u8]>>,
+	source: Option<PathError>,
+}
+
+impl Error {
+	/// Creates a new error without a path.
+	#[inline]
+	pub const fn new(kind: ErrorKind) -> Error {
+		Error { kind, path: None, source: None }
+	}
+
+	/// Creates a new error for the path in the PAKS archive.
+	#[inline]
+	pub fn with_path(kind: ErrorKind, path: &[u8]) -> Error {
+		Error { kind, path: Some(path.into()), source: None }
+	}
+
+	/// Returns the kind of error.
+	#[inline]
+	pub fn kind(&self) -> ErrorKind {
+		self.kind
+	}
+
+	/// Returns the path in the PAKS archive which caused the error, if any.
+	#[inline]
+	pub fn path(&self) -> Option<&[u8]> {
+		self.path.as_deref()
+	}
+
+	// Attaches the path if the error does not already have one
+	#[inline]
+	pub(crate) fn at(mut self, path: &[u8]) -> Error {
+		if self.path.is_none() {
+			self.path = Some(path.into());
+		}
+		self
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		fmt::Display::fmt(&self.kind, f)?;
+		if let Some(path) = &self.path {
+			write!(f, ": {}", String::from_utf8_lossy(path))?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		self.source.as_ref().map(|err| err as &(dyn std::error::Error + 'static))
+	}
+}
+
+impl PartialEq<ErrorKind> for Error {
+	#[inline]
+	fn eq(&self, kind: &ErrorKind) -> bool {
+		self.kind == *kind
+	}
+}
+
+impl From<ErrorKind> for Error {
+	#[inline]
+	fn from(kind: ErrorKind) -> Error {
+		Error::new(kind)
+	}
+}
+
+impl From<PathError> for Error {
+	#[inline]
+	fn from(err: PathError) -> Error {
+		Error { kind: ErrorKind::InvalidInput, path: None, source: Some(err) }
+	}
+}
+
+impl From<Error> for std::io::Error {
+	#[inline]
+	fn from(err: Error) -> std::io::Error {
+		std::io::Error::new(err.kind, err)
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(err: std::io::Error) -> Error {
+		let inner = err.get_ref();
+		if let Some(inner) = inner.and_then(|inner| inner.downcast_ref::<Error>()) {
+			return inner.clone();
+		}
+		if let Some(&inner) = inner.and_then(|inner| inner.downcast_ref::<PathError>()) {
+			return Error::from(inner);
+		}
+		Error::new(err.kind())
+	}
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,25 @@
+use super::*;
+
+#[test]
+fn test_error_conversions() {
+	let err = Error::with_path(ErrorKind::NotFound, b"a/b");
+	assert_eq!(err, ErrorKind::NotFound);
+	assert_eq!(err.to_string(), format!("{}: a/b", ErrorKind::NotFound));
+
+	// The path survives the round trip through io::Error
+	let io_err = std::io::Error::from(err.clone());
+	assert_eq!(io_err.kind(), ErrorKind::NotFound);
+	assert_eq!(io_err.to_string(), err.to_string());
+	assert_eq!(Error::from(io_err), err);
+
+	// Other io errors keep their kind
+	let err = Error::from(std::io::Error::from(ErrorKind::UnexpectedEof));
+	assert_eq!(err, Error::new(ErrorKind::UnexpectedEof));
+	assert_eq!(err.path(), None);
+
+	// Path errors are kept as the source
+	let err = Error::from(std::io::Error::from(PathError::Empty)).at(b"");
+	assert_eq!(err.kind(), ErrorKind::InvalidInput);
+	assert_eq!(err.path(), Some(&b""[..]));
+	assert_eq!(std::error::Error::source(&err).map(|err| err.to_string()), Some(PathError::Empty.to_string()));
+}
@@ -0,0 +1,189 @@
+/*!
+Descriptor extension area.
+
+The last two reserved fields of a descriptor hold a sequence of extension records.
+Each record starts with its tag byte and the length of its payload in bytes, followed by the payload.
+The sequence ends at a zero tag or the end of the extension area.
+
+Readers skip records with unknown tags, new versions of a record are introduced with a new tag.
+*/
+
+use std::time;
+use super::*;
+
+// Index of the first reserved field of the extension area
+const AREA_START: usize = 7;
+
+/// Extension record stored in the extension area of a descriptor.
+///
+/// The record is stored as the bytes of its plain old data, see [`Descriptor::extension`].
+pub trait Extension: Pod {
+	/// Tag identifying the record, must not be zero.
+	const TAG: u8;
+}
+
+/// Modification time of the source of the file contents, in seconds since the Unix epoch.
+///
+/// Recorded by `pakscmd copy --mtime` and restored by `pakscmd extract`, build tools comparing timestamps see the times of the source files.
+/// Times before the Unix epoch or after the year 2106 cannot be recorded.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[repr(transparent)]
+pub struct Mtime(pub u32);
+
+unsafe impl Pod for Mtime {}
+
+impl Extension for Mtime {
+	const TAG: u8 = 1;
+}
+
+impl Mtime {
+	/// Converts the system time, sub-second precision is truncated.
+	///
+	/// Returns `None` if the time cannot be recorded.
+	#[inline]
+	pub fn from_system_time(time: time::SystemTime) -> Option<Mtime> {
+		let secs = time.duration_since(time::UNIX_EPOCH).ok()?.as_secs();
+		u32::try_from(secs).ok().map(Mtime)
+	}
+
+	/// Converts to the system time.
+	#[inline]
+	pub fn to_system_time(self) -> time::SystemTime {
+		time::UNIX_EPOCH + time::Duration::from_secs(self.0 as u64)
+	}
+}
+
+/// Iterator over the extension records of a descriptor.
+///
+/// Yields the tag and payload of every record, see [`Descriptor::extensions`].
+#[derive(Clone, Debug)]
+pub struct Extensions<'a> {
+	area: &'a [u8],
+}
+
+impl<'a> Iterator for Extensions<'a> {
+	type Item = (u8, &'a [u8]);
+
+	fn next(&mut self) -> Option<(u8, &'a [u8])> {
+		let (&tag, &len) = match self.area {
+			[tag, len, ..] if *tag != 0 => (tag, len),
+			_ => return None,
+		};
+		let end = 2 + len as usize;
+		// Stop at a record which does not fit in the extension area
+		let payload = match self.area.get(2..end) {
+			Some(payload) => payload,
+			None => {
+				self.area = &[];
+				return None;
+			},
+		};
+		self.area = &self.area[end..];
+		Some((tag, payload))
+	}
+}
+
+impl Descriptor {
+	/// The last reserved fields hold extension records.
+	///
+	/// The extension area has room for [`EXTENSION_LEN`](Self::EXTENSION_LEN) bytes of records, each taking two bytes for its tag and length.
+	/// See [`extension`](Self::extension) and [`set_extension`](Self::set_extension).
+	pub const FLAG_EXTENSIONS: u32 = 64;
+
+	/// Size in bytes of the extension area, see [`FLAG_EXTENSIONS`](Self::FLAG_EXTENSIONS).
+	pub const EXTENSION_LEN: usize = (9 - AREA_START) * mem::size_of::<u32>();
+
+	fn extension_area(&self) -> &[u8] {
+		dataview::bytes(&self.reserved[AREA_START..])
+	}
+
+	/// Iterates over the extension records.
+	///
+	/// Yields nothing without the [`FLAG_EXTENSIONS`](Self::FLAG_EXTENSIONS) flag.
+	#[inline]
+	pub fn extensions(&self) -> Extensions<'_> {
+		let area = if self.flags & Descriptor::FLAG_EXTENSIONS != 0 { self.extension_area() } else { &[] };
+		Extensions { area }
+	}
+
+	/// Gets the payload of the extension record with the tag.
+	#[inline]
+	pub fn extension_data(&self, tag: u8) -> Option<&[u8]> {
+		self.extensions().find(|&(record, _)| record == tag).map(|(_, payload)| payload)
+	}
+
+	/// Gets the typed extension record.
+	///
+	/// Returns `None` if there is no record with its tag or the payload has the wrong size.
+	#[inline]
+	pub fn extension<T: Extension>(&self) -> Option<T> {
+		let payload = self.extension_data(T::TAG)?;
+		if payload.len() != mem::size_of::<T>() {
+			return None;
+		}
+		dataview::DataView::from(payload).try_read(0)
+	}
+
+	/// Stores the payload of the extension record with the tag, replacing any previous record with the tag.
+	///
+	/// Returns false and leaves the descriptor unchanged if the tag is zero, the records do not fit in the extension area
+	/// or the extension area holds the base section of a delta file, see [`FLAG_DELTA`](Self::FLAG_DELTA).
+	pub fn set_extension_data(&mut self, tag: u8, payload: &[u8]) -> bool {
+		if tag == 0 || self.is_delta() {
+			return false;
+		}
+		let area = match pack(self.extensions().filter(|&(record, _)| record != tag).chain([(tag, payload)])) {
+			Some(area) => area,
+			None => return false,
+		};
+		self.store_extensions(&area);
+		true
+	}
+
+	/// Stores the typed extension record, replacing any previous record with its tag.
+	///
+	/// Returns false and leaves the descriptor unchanged if the records do not fit in the extension area.
+	#[inline]
+	pub fn set_extension<T: Extension>(&mut self, value: &T) -> bool {
+		self.set_extension_data(T::TAG, dataview::bytes(value))
+	}
+
+	/// Removes the extension record with the tag.
+	///
+	/// Returns false if there is no record with the tag.
+	pub fn remove_extension(&mut self, tag: u8) -> bool {
+		if tag == 0 || self.extension_data(tag).is_none() {
+			return false;
+		}
+		// The remaining records always fit
+		let area = pack(self.extensions().filter(|&(record, _)| record != tag)).unwrap_or_default();
+		self.store_extensions(&area);
+		true
+	}
+
+	// Stores the extension area and sets the flag if there are any records
+	fn store_extensions(&mut self, area: &[u8; Descriptor::EXTENSION_LEN]) {
+		dataview::bytes_mut(&mut self.reserved[AREA_START..]).copy_from_slice(area);
+		if area[0] != 0 {
+			self.flags |= Descriptor::FLAG_EXTENSIONS;
+		}
+		else {
+			self.flags &= !Descriptor::FLAG_EXTENSIONS;
+		}
+	}
+}
+
+// Packs the records into an extension area, returns None if they do not fit
+fn pack<'a>(records: impl Iterator<Item = (u8, &'a [u8])>) -> Option<[u8; Descriptor::EXTENSION_LEN]> {
+	let mut area = [0u8; Descriptor::EXTENSION_LEN];
+	let mut len = 0;
+	for (tag, payload) in records {
+		let end = len + 2 + payload.len();
+		let record = area.get_mut(len..end)?;
+		record[0] = tag;
+		record[1] = payload.len() as u8;
+		record[2..].copy_from_slice(payload);
+		len = end;
+	}
+	Some(area)
+}
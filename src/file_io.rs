@@ -1,15 +1,16 @@
 /*!
 # FileIO based PAKS file format implementation
 
-Implements the PAKS file format using [`std::fs::File`].
+Implements the PAKS file format using [`std::fs::File`] or any other [`BlockSource`].
 */
 
-use std::{fs, path::Path, io, io::prelude::*};
+use std::{fs, path::{Path, PathBuf}, io, io::prelude::*};
 use super::*;
 
 /// Reads a PAKS file from a stream.
 ///
 /// This method reads and decrypts the PAKS file header.
+/// If the encryption key is incorrect, [`io::ErrorKind::PermissionDenied`] is returned.
 /// If the header is invalid or its MAC check fails, [`io::ErrorKind::InvalidData`] is returned.
 ///
 /// Then it reads all the blocks in the PAKS file as specified by the directory.
@@ -22,13 +23,20 @@ pub fn read<F: Read>(mut file: F, key: &Key) -> io::Result<Vec<Block>> {
 	let header2 = header;
 
 	// Decrypt and validate the header
-	if !crypt::decrypt_header(&mut header, key) {
-		return Err(io::Error::from(io::ErrorKind::InvalidData));
+	if let Err(kind) = crypt::decrypt_header(&mut header, key) {
+		if kind == io::ErrorKind::InvalidData {
+			tampered(Tampered::Header);
+		}
+		return Err(header_error(kind, header2.has_magic()));
 	}
 
 	// Use information from the header to calculate the total size of the PAKS file
 	// This code assumes the directory is the very last thing in the PAKS file
-	let blocks_len = usize::max(Header::BLOCKS_LEN, header.info.directory.offset as usize + header.info.directory.size as usize * Descriptor::BLOCKS_LEN);
+	let dir_end = match header.info.directory_end() {
+		Some(dir_end) => dir_end,
+		None => Err(io::ErrorKind::InvalidData)?,
+	};
+	let blocks_len = usize::max(Header::BLOCKS_LEN, dir_end as usize);
 	let mut blocks = vec![Block::default(); blocks_len];
 
 	// Copy the encrypted header into the output since it's already read from the file
@@ -40,82 +48,450 @@ pub fn read<F: Read>(mut file: F, key: &Key) -> io::Result<Vec<Block>> {
 	Ok(blocks)
 }
 
+/// Writes the PAKS file followed by a footer pointing back to its start.
+///
+/// Use this to append a PAKS file to the end of another file, eg. a self-extracting executable.
+/// The footer allows [`FileReader::open_embedded`] to locate the PAKS file regardless of what precedes it.
+pub fn write_embedded<W: Write>(mut dest: W, paks: &[u8]) -> io::Result<()> {
+	dest.write_all(paks)?;
+	let mut footer = [0u8; FOOTER_LEN];
+	footer[..8].copy_from_slice(&(paks.len() as u64).to_le_bytes());
+	footer[8..].copy_from_slice(FOOTER_SIGNATURE);
+	dest.write_all(&footer)
+}
+
+// The footer following an embedded PAKS file is the byte length of the PAKS file followed by a signature
+const FOOTER_SIGNATURE: &[u8; 8] = b"PAKSTAIL";
+const FOOTER_LEN: usize = 16;
+
+// Locates the PAKS file embedded at the end of the file from its footer
+// Returns the byte offset and the byte length of the PAKS file
+fn find_embedded(file: &fs::File) -> io::Result<(u64, u64)> {
+	let file_len = file.metadata()?.len();
+	let footer_offset = match file_len.checked_sub(FOOTER_LEN as u64) {
+		Some(footer_offset) => footer_offset,
+		None => return Err(io::Error::new(io::ErrorKind::InvalidData, "no embedded PAKS file")),
+	};
+	let mut footer = [0u8; FOOTER_LEN];
+	read_exact_at(file, &mut footer, footer_offset)?;
+	if &footer[8..] != FOOTER_SIGNATURE {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "no embedded PAKS file"));
+	}
+	let len = u64::from_le_bytes([footer[0], footer[1], footer[2], footer[3], footer[4], footer[5], footer[6], footer[7]]);
+	match footer_offset.checked_sub(len) {
+		Some(base) => Ok((base, len)),
+		None => Err(io::Error::new(io::ErrorKind::InvalidData, "corrupted footer")),
+	}
+}
+
+impl MemoryEditor {
+	/// Saves the PAKS file, overwrites any file if it already exists.
+	///
+	/// The encrypted directory, a copy of the header and the key slots are written after the blocks, like [`FileEditor::finish`].
+	/// The editor is left unchanged and can continue editing.
+	///
+	/// The PAKS file is not locked, this allows an editor of the PAKS file to hold on to its lock while the PAKS file is replaced.
+	/// Drop that editor afterwards without finishing it, finishing it would corrupt the saved PAKS file.
+	pub fn save_to<P: ?Sized + AsRef<Path>>(&self, path: &P, key: &Key) -> io::Result<()> {
+		save_to(self, path.as_ref(), key)
+	}
+}
+
+#[inline(never)]
+fn save_to(edit: &MemoryEditor, path: &Path, key: &Key) -> io::Result<()> {
+	span!(DEBUG, "save_to", path = %path.display(), blocks = edit.blocks.len(), descriptors = edit.directory.len());
+
+	let (header, dir_blocks) = edit.seal(key)?;
+
+	let mut file = fs::File::create(path)?;
+	file.write_all(dataview::bytes(&header))?;
+	file.write_all(dataview::bytes(edit.blocks.get(Header::BLOCKS_LEN..).unwrap_or(&[])))?;
+	file.write_all(dataview::bytes(dir_blocks.as_slice()))?;
+	file.write_all(dataview::bytes(&header))?;
+	file.write_all(dataview::bytes(keyslot::footer(&edit.key_slots).as_slice()))?;
+	file.sync_data()
+}
+
+// Attaches the path in the PAKS archive to the errors detected by this crate
+// Errors of the operating system are returned as-is
+fn at_path(err: io::Error, path: &[u8]) -> io::Error {
+	if err.raw_os_error().is_none() && err.get_ref().is_none() {
+		Error::with_path(err.kind(), path).into()
+	}
+	else {
+		err
+	}
+}
+
+// Describes why the header failed to decrypt
+// Without the magic signature an incorrect key is indistinguishable from a file which is not a PAKS file
+#[cold]
+fn header_error(kind: io::ErrorKind, magic: bool) -> io::Error {
+	let msg = match kind {
+		io::ErrorKind::PermissionDenied if magic => "incorrect key",
+		io::ErrorKind::PermissionDenied => "incorrect key or not a PAKS file",
+		io::ErrorKind::Unsupported => "unsupported file format version",
+		_ => "corrupted header",
+	};
+	io::Error::new(kind, msg)
+}
+
 #[inline(always)]
-fn read_header(file: &mut fs::File, key: &Key) -> io::Result<(InfoHeader, Directory)> {
+fn read_header(file: &fs::File, key: &Key) -> io::Result<(InfoHeader, Directory, bool)> {
+	// Readers do not take the lock, read the header before the length of the PAKS file
+	// A FileEditor finishing in between only grows the PAKS file and keeps the directory of the header intact
+	let mut header: Header = dataview::zeroed();
+	file.read_exact_at(dataview::bytes_mut(&mut header), 0)?;
+	let (len, _) = read_key_footer(file)?;
+	decrypt_header_at(file, key, header, 0, len)
+}
+
+// Reads the key slots at the end of the file
+// Returns the byte length of the PAKS file preceding the key slots and the key slots
+fn read_key_footer<S: ?Sized + BlockSource>(file: &S) -> io::Result<(u64, Vec<KeySlot>)> {
+	let file_len = file.size()?;
+	let blocks_len = file_len / BLOCK_SIZE as u64;
+	if blocks_len == 0 {
+		return Ok((file_len, Vec::new()));
+	}
+	let mut trailer = Block::default();
+	file.read_exact_at(dataview::bytes_mut(&mut trailer), (blocks_len - 1) * BLOCK_SIZE as u64)?;
+	let footer_len = match keyslot::footer_len(Some(trailer), blocks_len) {
+		Some(footer_len) => footer_len,
+		None => return Ok((file_len, Vec::new())),
+	};
+	let mut footer = vec![Block::default(); footer_len as usize];
+	let offset = (blocks_len - footer_len) * BLOCK_SIZE as u64;
+	file.read_exact_at(dataview::bytes_mut(footer.as_mut_slice()), offset)?;
+	Ok((offset, keyslot::parse_slots(&footer)))
+}
+
+/// Reads the key slots of the PAKS file, see [`KeySlot`].
+///
+/// If the PAKS file has no key slots the result is empty.
+/// Unlock the key of the PAKS file with [`unlock_key`].
+pub fn read_key_slots<P: ?Sized + AsRef<Path>>(path: &P) -> io::Result<Vec<KeySlot>> {
+	let file = fs::File::open(path)?;
+	read_key_footer(&file).map(|(_, key_slots)| key_slots)
+}
+
+// Reads the header and the directory of the PAKS file starting at the base byte offset with the given byte length
+fn read_header_at<S: ?Sized + BlockSource>(file: &S, key: &Key, base: u64, len: u64) -> io::Result<(InfoHeader, Directory, bool)> {
 	// Read the header
 	let mut header: Header = dataview::zeroed();
-	file.read_exact(dataview::bytes_mut(&mut header))?;
+	file.read_exact_at(dataview::bytes_mut(&mut header), base)?;
+	decrypt_header_at(file, key, header, base, len)
+}
+
+// Decrypts the header read at the base byte offset and reads the directory
+fn decrypt_header_at<S: ?Sized + BlockSource>(file: &S, key: &Key, mut header: Header, base: u64, len: u64) -> io::Result<(InfoHeader, Directory, bool)> {
+	let magic = header.has_magic();
 
 	// Decrypt the header and validate
-	if !crypt::decrypt_header(&mut header, key) {
+	// If the header is being written by FileEditor::finish, fall back to the copy of the new header at the end of the PAKS file
+	if let Err(kind) = crypt::decrypt_header(&mut header, key) {
+		header = match read_header_copy(file, key, base, len) {
+			Some(header) => header,
+			None => {
+				if kind == io::ErrorKind::InvalidData {
+					tampered(Tampered::Header);
+				}
+				return Err(header_error(kind, magic));
+			},
+		};
+	}
+
+	// The directory must fit in the PAKS file before allocating space for it
+	let dir_end = match header.info.directory_end() {
+		Some(dir_end) => dir_end as u64 * BLOCK_SIZE as u64,
+		None => Err(io::ErrorKind::InvalidData)?,
+	};
+	if dir_end > len {
 		Err(io::ErrorKind::InvalidData)?;
 	}
 
 	// Read the directory
-	file.seek(io::SeekFrom::Start(header.info.directory.offset as u64 * BLOCK_SIZE as u64))?;
 	let mut directory = Directory::from(vec![Descriptor::default(); header.info.directory.size as usize]);
-	file.read_exact(dataview::bytes_mut(directory.as_mut()))?;
+	file.read_exact_at(dataview::bytes_mut(directory.as_mut()), base + header.info.directory.offset as u64 * BLOCK_SIZE as u64)?;
 
 	// Decrypt the directory
 	if !crypt::decrypt_section(directory.as_blocks_mut(), &header.info.directory, key) {
+		tampered(Tampered::Directory(header.info.directory));
+		Err(io::ErrorKind::InvalidData)?;
+	}
+	upgrade_directory(header.info.version, directory.as_mut());
+
+	// Do not trust a directory which is authentic but logically invalid
+	if !dir::validate(directory.as_ref(), header.info.directory.offset) {
 		Err(io::ErrorKind::InvalidData)?;
 	}
 
-	Ok((header.info, directory))
+	Ok((header.info, directory, magic))
 }
 
-fn read_section(mut file: &fs::File, section: &Section, key: &Key) -> io::Result<Vec<Block>> {
-	// Read the data to memory buffer
-	let file_offset = section.offset as u64 * BLOCK_SIZE as u64;
+// Reads and decrypts the copy of the header at the end of the PAKS file
+// The copy must reference the directory right before it, see FileEditor::finish
+fn read_header_copy<S: ?Sized + BlockSource>(file: &S, key: &Key, base: u64, len: u64) -> Option<Header> {
+	let offset = (len / BLOCK_SIZE as u64).checked_sub(Header::BLOCKS_LEN as u64)?;
+	let mut header: Header = dataview::zeroed();
+	file.read_exact_at(dataview::bytes_mut(&mut header), base + offset * BLOCK_SIZE as u64).ok()?;
+	crypt::decrypt_header(&mut header, key).ok()?;
+	if header.info.directory_end()? as u64 != offset {
+		return None;
+	}
+	Some(header)
+}
+
+// Reads all the blocks of the PAKS file into memory, any trailing partial block is ignored
+fn read_blocks(file: &fs::File) -> io::Result<Vec<Block>> {
+	let blocks_len = file.metadata()?.len() / BLOCK_SIZE as u64;
+	let mut blocks = vec![Block::default(); blocks_len as usize];
+	read_exact_at(file, dataview::bytes_mut(blocks.as_mut_slice()), 0)?;
+	Ok(blocks)
+}
+
+// Decrypts and validates the directory referenced by the info header from the blocks of the PAKS file
+fn load_directory(blocks: &[Block], info: &InfoHeader, key: &Key) -> Result<Directory, ErrorKind> {
+	let dir_start = info.directory.offset as usize;
+	let dir_blocks = match info.directory_end().and_then(|dir_end| blocks.get(dir_start..dir_end as usize)) {
+		Some(dir_blocks) => dir_blocks,
+		None => return Err(ErrorKind::InvalidData),
+	};
+
+	let mut directory = Directory::from(vec![Descriptor::default(); info.directory.size as usize]);
+	directory.as_blocks_mut().copy_from_slice(dir_blocks);
+	if !crypt::decrypt_section(directory.as_blocks_mut(), &info.directory, key) {
+		return Err(ErrorKind::InvalidData);
+	}
+	upgrade_directory(info.version, directory.as_mut());
+
+	if !dir::validate(directory.as_ref(), info.directory.offset) {
+		return Err(ErrorKind::InvalidData);
+	}
+
+	Ok(directory)
+}
+
+// Scans the blocks backwards for the copies of the header left behind by FileEditor::finish
+// Every directory written by finish is immediately followed by a copy of the header referencing it
+// Yields the info header of every authentic copy and the result of loading the directory it references
+fn scan_directories<'a>(blocks: &'a [Block], key: &'a Key, magic: bool) -> impl 'a + Iterator<Item = (InfoHeader, Result<Directory, ErrorKind>)> {
+	let last = blocks.len().saturating_sub(Header::BLOCKS_LEN);
+	(Header::BLOCKS_LEN..=last).rev().filter_map(move |offset| {
+		let mut header: Header = dataview::DataView::from(&blocks[offset..offset + Header::BLOCKS_LEN]).read(0);
+		// Without the magic signature every block offset is a candidate
+		if magic && !header.has_magic() {
+			return None;
+		}
+		crypt::decrypt_header(&mut header, key).ok()?;
+		if header.info.directory_end() != Some(offset as u32) {
+			return None;
+		}
+		Some((header.info, load_directory(blocks, &header.info, key)))
+	})
+}
+
+// Reads at the file offset with a single positional read where supported
+// Positional reads do not use the file cursor and are safe to use from multiple threads
+#[cfg(unix)]
+fn read_exact_at(file: &fs::File, buf: &mut [u8], file_offset: u64) -> io::Result<()> {
+	std::os::unix::fs::FileExt::read_exact_at(file, buf, file_offset)
+}
+#[cfg(windows)]
+fn read_exact_at(file: &fs::File, mut buf: &mut [u8], mut file_offset: u64) -> io::Result<()> {
+	while !buf.is_empty() {
+		match std::os::windows::fs::FileExt::seek_read(file, buf, file_offset) {
+			Ok(0) => Err(io::ErrorKind::UnexpectedEof)?,
+			Ok(n) => {
+				buf = &mut buf[n..];
+				file_offset += n as u64;
+			},
+			Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+			Err(err) => return Err(err),
+		}
+	}
+	Ok(())
+}
+#[cfg(not(any(unix, windows)))]
+fn read_exact_at(mut file: &fs::File, buf: &mut [u8], file_offset: u64) -> io::Result<()> {
+	// Seeking and reading is not atomic, serialize the threads sharing a file handle
+	static SEEK_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+	let _guard = SEEK_LOCK.lock().unwrap_or_else(|err| err.into_inner());
 	file.seek(io::SeekFrom::Start(file_offset))?;
-	let mut blocks = vec![Block::default(); section.size as usize];
-	file.read_exact(dataview::bytes_mut(blocks.as_mut_slice()))?;
+	file.read_exact(buf)
+}
+
+// The base is the byte offset of the PAKS file, non-zero for embedded PAKS files
+fn read_section_into<S: ?Sized + BlockSource>(file: &S, base: u64, section: &Section, key: &Key, blocks: &mut [Block]) -> io::Result<()> {
+	span!(TRACE, "read_section", offset = section.offset, size = section.size);
+
+	if blocks.len() != section.size as usize {
+		Err(io::ErrorKind::InvalidInput)?;
+	}
+
+	// Read the data to the memory buffer
+	let file_offset = base + section.offset as u64 * BLOCK_SIZE as u64;
+	file.read_exact_at(dataview::bytes_mut(blocks), file_offset)?;
 
 	// Decrypt the data inplace
-	if !crypt::decrypt_section(&mut blocks, section, key) {
+	if !crypt::decrypt_section(blocks, section, key) {
+		tampered(Tampered::Section(*section));
 		Err(io::ErrorKind::InvalidData)?;
 	}
 
+	Ok(())
+}
+
+// Authenticates the section without decrypting it, the blocks buffer is reused between sections
+fn verify_section<S: ?Sized + BlockSource>(file: &S, base: u64, section: &Section, key: &Key, blocks: &mut Vec<Block>) -> io::Result<()> {
+	span!(TRACE, "verify_section", offset = section.offset, size = section.size);
+
+	blocks.clear();
+	blocks.resize(section.size as usize, Block::default());
+	let file_offset = base + section.offset as u64 * BLOCK_SIZE as u64;
+	file.read_exact_at(dataview::bytes_mut(blocks.as_mut_slice()), file_offset)?;
+
+	if !crypt::verify_section(blocks, section, key) {
+		tampered(Tampered::Section(*section));
+		Err(io::ErrorKind::InvalidData)?;
+	}
+
+	Ok(())
+}
+
+fn read_section<S: ?Sized + BlockSource>(file: &S, base: u64, section: &Section, key: &Key) -> io::Result<Vec<Block>> {
+	let mut blocks = vec![Block::default(); section.size as usize];
+	read_section_into(file, base, section, key, &mut blocks)?;
 	Ok(blocks)
 }
 
-fn read_data(file: &fs::File, desc: &Descriptor, key: &Key) -> io::Result<Vec<u8>> {
+fn read_data<S: ?Sized + BlockSource>(file: &S, base: u64, desc: &Descriptor, key: &Key) -> io::Result<Vec<u8>> {
+	span!(TRACE, "read_data", offset = desc.section.offset, size = desc.content_size);
+
 	if !desc.is_file() {
 		Err(io::ErrorKind::InvalidInput)?;
 	}
 
-	let blocks = read_section(file, &desc.section, key)?;
+	if desc.is_inline() {
+		return desc.inline_data().map(<[u8]>::to_vec).ok_or(io::ErrorKind::InvalidInput.into());
+	}
+
+	if desc.is_plaintext() {
+		if desc.data_offset() as u64 + desc.content_size as u64 > desc.section.size as u64 * BLOCK_SIZE as u64 {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+		let mut data = vec![0u8; desc.content_size as usize];
+		file.read_exact_at(&mut data, base + desc.section.offset as u64 * BLOCK_SIZE as u64 + desc.data_offset() as u64)?;
+		return Ok(data);
+	}
+
+	if desc.is_delta() {
+		return delta::read_data(desc, |section| read_section(file, base, section, key));
+	}
+
+	let blocks = read_section(file, base, &desc.section, key)?;
 
 	// Figure out which part of the blocks to copy
 	let data = dataview::bytes(blocks.as_slice());
-	let len = usize::min(data.len(), desc.content_size as usize);
-	Ok(data[..len].to_vec())
+	let start = usize::min(data.len(), desc.data_offset() as usize);
+	let len = usize::min(data.len() - start, desc.content_size as usize);
+	Ok(data[start..start + len].to_vec())
 }
 
-fn read_data_into(file: &fs::File, desc: &Descriptor, key: &Key, byte_offset: usize, dest: &mut [u8]) -> io::Result<()> {
+fn read_data_into<'a, S: ?Sized + BlockSource>(file: &S, base: u64, desc: &Descriptor, key: &Key, byte_offset: usize, dest: &'a mut [mem::MaybeUninit<u8>]) -> io::Result<&'a mut [u8]> {
+	span!(TRACE, "read_data_into", offset = desc.section.offset, byte_offset, len = dest.len());
+
 	if !desc.is_file() {
 		Err(io::ErrorKind::InvalidInput)?;
 	}
 
-	let blocks = read_section(file, &desc.section, key)?;
+	if desc.is_inline() {
+		return Ok(read_inline(desc, byte_offset, dest)?);
+	}
+
+	// Delta files are reconstructed as a whole, the dest is zeroed if the authentication fails like other files
+	if desc.is_delta() {
+		return match delta::read_data(desc, |section| read_section(file, base, section, key)) {
+			Ok(data) => Ok(read_slice(&data, byte_offset, dest)?),
+			Err(err) => {
+				dest.fill(mem::MaybeUninit::new(0));
+				Err(err)
+			},
+		};
+	}
 
-	// Figure out which part of the blocks to copy
-	let data = match dataview::bytes(blocks.as_slice()).get(byte_offset..byte_offset + dest.len()) {
-		Some(data) => data,
+	// The requested range must be within the file contents, bundled files must not read the contents of their neighbours
+	if byte_offset.checked_add(dest.len()).is_none_or(|end| end > desc.content_size as usize) {
+		Err(io::ErrorKind::InvalidInput)?;
+	}
+	let byte_offset = match byte_offset.checked_add(desc.data_offset() as usize) {
+		Some(byte_offset) => byte_offset,
 		None => Err(io::ErrorKind::InvalidInput)?,
 	};
+	if byte_offset.checked_add(dest.len()).is_none_or(|end| end as u64 > desc.section.size as u64 * BLOCK_SIZE as u64) {
+		Err(io::ErrorKind::InvalidInput)?;
+	}
 
-	// Copy the data to its destination
-	dest.copy_from_slice(data);
+	// Plaintext file contents are read as-is
+	let file_offset = base + desc.section.offset as u64 * BLOCK_SIZE as u64;
+	if desc.is_plaintext() {
+		dest.fill(mem::MaybeUninit::new(0));
+		// Every byte of dest is zeroed before reading into it
+		let dest = unsafe { dest.assume_init_mut() };
+		file.read_exact_at(dest, file_offset + byte_offset as u64)?;
+		return Ok(dest);
+	}
 
-	Ok(())
+	// Stream the section in chunks and decrypt directly into the destination
+	let is_valid = crypt::decrypt_range(&desc.section, key, byte_offset, dest, |index, chunk| {
+		file.read_exact_at(dataview::bytes_mut(chunk), file_offset + (index * BLOCK_SIZE) as u64)
+	})?;
+	if !is_valid {
+		tampered(Tampered::Section(desc.section));
+		Err(io::ErrorKind::InvalidData)?;
+	}
+
+	// Every byte of dest is written by the decryption
+	Ok(unsafe { dest.assume_init_mut() })
+}
+
+/// Joins an archive path onto a base directory on the file system.
+///
+/// Archive paths are untrusted input when extracting files from a PAKS archive.
+/// This function guarantees that the resulting path does not escape the base directory.
+///
+/// # Errors
+///
+/// Returns [`io::ErrorKind::InvalidInput`] if the archive path:
+///
+/// * Is invalid as per [`validate_path`], which rejects absolute paths, empty components and `.` or `..` components.
+/// * Is not valid UTF-8.
+/// * Contains a component with a drive or stream separator `:` or a nul byte.
+pub fn safe_join(base: &Path, path: &[u8]) -> io::Result<std::path::PathBuf> {
+	if let Err(err) = dir::validate_path(path) {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+	}
+	let path = match str::from_utf8(path) {
+		Ok(path) => path,
+		Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "path is not valid UTF-8")),
+	};
+	let mut result = base.to_path_buf();
+	for name in path.split(['/', '\\']) {
+		if name.contains([':', '\0']) {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "path component contains an invalid character"));
+		}
+		result.push(name);
+	}
+	Ok(result)
 }
 
 mod reader;
 mod editor;
 mod edit_file;
+mod lazy;
 
-pub use self::reader::FileReader;
+pub use self::reader::{FileReader, Salvage};
+pub use self::lazy::{BlockSource, LazyReader};
 pub use self::editor::FileEditor;
 pub use self::edit_file::FileEditFile;
 
@@ -5,6 +5,8 @@ pub struct FileEditFile<'a> {
 	pub(super) file: &'a fs::File,
 	pub(super) desc: &'a mut Descriptor,
 	pub(super) high_mark: &'a mut u32,
+	pub(super) alignment: u32,
+	pub(super) nonces: Option<&'a dyn NonceSource>,
 }
 
 impl<'a> FileEditFile<'a> {
@@ -30,27 +32,73 @@ impl<'a> FileEditFile<'a> {
 	#[inline]
 	pub fn set_section(&mut self, section: &Section) -> &mut FileEditFile<'a> {
 		self.desc.section = *section;
+		self.desc.clear_storage();
 		return self;
 	}
 
+	/// Sets whether the file contents are stored unencrypted.
+	///
+	/// Must be set before the file contents are written, see [`Descriptor::FLAG_PLAINTEXT`].
+	/// Only use this for non-sensitive files, plaintext file contents are not authenticated.
+	#[inline]
+	pub fn set_plaintext(&mut self, plaintext: bool) -> &mut FileEditFile<'a> {
+		if plaintext {
+			self.desc.flags |= Descriptor::FLAG_PLAINTEXT;
+		}
+		else {
+			self.desc.flags &= !Descriptor::FLAG_PLAINTEXT;
+		}
+		self
+	}
+
+	/// Stores the file contents inline in the file descriptor, see [`Descriptor::FLAG_INLINE`].
+	///
+	/// Replaces the section object and sets the content size, no space is allocated.
+	/// Returns false and leaves the file descriptor unchanged if the data is longer than [`Descriptor::INLINE_LEN`].
+	#[inline]
+	pub fn set_inline_data(&mut self, data: &[u8]) -> bool {
+		self.desc.set_inline_data(data)
+	}
+
+	/// Assigns a byte range of a section shared with other files to the file contents, see [`Descriptor::FLAG_BUNDLED`].
+	///
+	/// The content size is not changed and should be set with `set_content`.
+	/// The byte range must lie within the section, see [`FileEditor::create_bundle`] to write the shared section.
+	#[inline]
+	pub fn set_bundled(&mut self, section: &Section, data_offset: u32) -> &mut FileEditFile<'a> {
+		self.desc.set_bundled(section, data_offset);
+		self
+	}
+
 	/// Allocates and assigns space for the data.
 	///
 	/// The size allocated is defined by a previous call to [`set_content`](Self::set_content)'s `content_size` argument.
 	///
 	/// The space allocated is logically uninitialized and must be initialized with [`write_data`](Self::write_data) or [`zero_data`](Self::zero_data).
 	pub fn allocate_data(&mut self) -> &mut FileEditFile<'a> {
-		// Simple bump allocate from the file
-		self.desc.section.offset = *self.high_mark;
+		// Simple bump allocate from the file, padded to the alignment
+		self.desc.clear_storage();
+		self.desc.section.offset = self.high_mark.checked_next_multiple_of(self.alignment).expect("PAKS file too large");
 		self.desc.section.size = bytes2blocks(self.desc.content_size);
 
 		// Bump the allocation, panic on overflow
-		*self.high_mark = self.high_mark.checked_add(self.desc.section.size).expect("PAKS file too large");
+		*self.high_mark = self.desc.section.offset.checked_add(self.desc.section.size).expect("PAKS file too large");
 
 		return self;
 	}
 
 	/// Copies and encrypts the data with the given key into the address specified by this file descriptor.
+	#[inline]
 	pub fn write_data(&mut self, data: &[u8], key: &Key) -> io::Result<&mut FileEditFile<'a>> {
+		self.write_data_vectored(&[io::IoSlice::new(data)], key)
+	}
+
+	/// Copies and encrypts the data from multiple buffers with the given key into the address specified by this file descriptor.
+	///
+	/// The buffers are written one after another as if they were concatenated.
+	/// This allows composing a file from several parts (eg. header, body and footer) without concatenating them first.
+	/// The content size should be set to the total length of the buffers with [`set_content`](Self::set_content).
+	pub fn write_data_vectored(&mut self, bufs: &[io::IoSlice], key: &Key) -> io::Result<&mut FileEditFile<'a>> {
 		// Seek to this section's file offset
 		let file_offset = self.desc.section.offset as u64 * BLOCK_SIZE as u64;
 		self.file.seek(io::SeekFrom::Start(file_offset))?;
@@ -59,11 +107,10 @@ impl<'a> FileEditFile<'a> {
 		let mut blocks = vec![Block::default(); self.desc.section.size as usize];
 
 		// Copy the data in the temp allocation
-		let len = usize::min(dataview::bytes(blocks.as_slice()).len(), data.len());
-		dataview::bytes_mut(blocks.as_mut_slice())[..len].copy_from_slice(&data[..len]);
+		gather(dataview::bytes_mut(blocks.as_mut_slice()), bufs);
 
 		// Encrypt the data inplace
-		crypt::encrypt_section(&mut blocks, &mut self.desc.section, key);
+		seal_data(&mut blocks, self.desc, data_nonce(self.nonces, self.desc)?, key);
 
 		// Write the data to the file
 		let result = self.file.write_all(dataview::bytes(blocks.as_slice()));
@@ -72,8 +119,79 @@ impl<'a> FileEditFile<'a> {
 		result.map(|()| self)
 	}
 
+	/// Writes already encrypted data into the address specified by this file descriptor.
+	///
+	/// The nonce and MAC are taken from the given section, its offset is ignored.
+	/// See [`EncryptedData`](pack::EncryptedData) to encrypt the data ahead of time.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The number of blocks does not match the allocated section size.
+	pub fn write_encrypted(&mut self, blocks: &[Block], section: &Section) -> io::Result<&mut FileEditFile<'a>> {
+		if blocks.len() != self.desc.section.size as usize || section.size != self.desc.section.size {
+			Err(io::ErrorKind::InvalidInput)?;
+		}
+
+		// Seek to this section's file offset
+		let file_offset = self.desc.section.offset as u64 * BLOCK_SIZE as u64;
+		self.file.seek(io::SeekFrom::Start(file_offset))?;
+
+		// Write the data to the file
+		self.file.write_all(dataview::bytes(blocks))?;
+
+		self.desc.section.nonce = section.nonce;
+		self.desc.section.mac = section.mac;
+		Ok(self)
+	}
+
+	/// Truncates or extends the file contents to the new size.
+	///
+	/// Extending the file fills the new space with zeroes.
+	///
+	/// If the number of blocks does not change when shrinking only the content size is updated.
+	/// Otherwise the contents are decrypted and re-encrypted into a newly allocated section.
+	/// The old section is left behind as garbage, links to the file keep referring to the old contents.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The descriptor is not a file descriptor.
+	/// * [`io::ErrorKind::InvalidData`]: The file contents failed authentication.
+	pub fn truncate(&mut self, new_size: u32, key: &Key) -> io::Result<&mut FileEditFile<'a>> {
+		// Inline file contents are resized inplace
+		if let (Some(data), true) = (self.desc.inline_data(), new_size as usize <= Descriptor::INLINE_LEN) {
+			let mut data = data.to_vec();
+			data.resize(new_size as usize, 0);
+			self.desc.set_inline_data(&data);
+			return Ok(self);
+		}
+
+		// Shrinking within the last block does not require re-encrypting
+		if new_size <= self.desc.content_size && bytes2blocks(new_size) == self.desc.section.size && !self.desc.is_delta() {
+			self.desc.content_size = new_size;
+			return Ok(self);
+		}
+
+		let mut data = read_data(self.file, 0, self.desc, key)?;
+		data.resize(new_size as usize, 0);
+
+		let content_type = self.desc.content_type;
+		self.set_content(content_type, new_size);
+		self.allocate_data().write_data(&data, key)
+	}
+
 	/// Initialize the data with zeroes.
+	///
+	/// Bundled and delta file contents are moved to a newly allocated section first.
 	pub fn zero_data(&mut self, key: &Key) -> io::Result<&mut FileEditFile<'a>> {
+		if self.desc.is_bundled() || self.desc.is_delta() {
+			self.allocate_data();
+		}
+		if self.desc.is_inline() {
+			let len = usize::min(self.desc.content_size as usize, Descriptor::INLINE_LEN);
+			self.desc.set_inline_data(&[0u8; Descriptor::INLINE_LEN][..len]);
+			return Ok(self);
+		}
+
 		// Seek to this section's file offset
 		let file_offset = self.desc.section.offset as u64 * BLOCK_SIZE as u64;
 		self.file.seek(io::SeekFrom::Start(file_offset))?;
@@ -82,7 +200,7 @@ impl<'a> FileEditFile<'a> {
 		let mut blocks = vec![Block::default(); self.desc.section.size as usize];
 
 		// Encrypt the zeroes inplace
-		crypt::encrypt_section(&mut blocks, &mut self.desc.section, key);
+		seal_data(&mut blocks, self.desc, data_nonce(self.nonces, self.desc)?, key);
 
 		// Write the zeroes to the file
 		let result = self.file.write_all(dataview::bytes(blocks.as_slice()));
@@ -94,6 +212,8 @@ impl<'a> FileEditFile<'a> {
 	/// Reencrypts the data.
 	///
 	/// The file must be initialized (either through `init_data` or `zero_data`) before it can be updated.
+	/// Plaintext file contents are left unchanged.
+	/// Bundled files are moved to a newly allocated section, the other files sharing their section are left alone.
 	///
 	/// # Consistency guarantees
 	///
@@ -102,6 +222,19 @@ impl<'a> FileEditFile<'a> {
 	///
 	/// If consistency is important, consider removing & creating the file again instead.
 	pub fn reencrypt_data(&mut self, old_key: &Key, key: &Key) -> io::Result<()> {
+		// Plaintext file contents are not encrypted, inline file contents are encrypted with the directory
+		if self.desc.is_plaintext() || self.desc.is_inline() {
+			return Ok(());
+		}
+
+		// Delta files are reconstructed into a newly allocated section, their base section is left alone
+		// Bundled files are moved to a section of their own, the shared section still holds the other files encrypted with the old key
+		if self.desc.is_delta() || self.desc.is_bundled() {
+			let data = read_data(self.file, 0, self.desc, old_key)?;
+			self.allocate_data().write_data(&data, key)?;
+			return Ok(());
+		}
+
 		// Read the file to memory buffer
 		let file_offset = self.desc.section.offset as u64 * BLOCK_SIZE as u64;
 		self.file.seek(io::SeekFrom::Start(file_offset))?;
@@ -111,11 +244,12 @@ impl<'a> FileEditFile<'a> {
 		// Decrypt the data inplace
 		if !crypt::decrypt_section(&mut blocks, &self.desc.section, old_key) {
 			// Leave the data alone if the MAC is invalid
+			tampered(Tampered::Section(self.desc.section));
 			Err(io::ErrorKind::InvalidData)?;
 		}
 
 		// Encrypt the data inplace
-		crypt::encrypt_section(&mut blocks, &mut self.desc.section, key);
+		crypt::encrypt_section_from(self.nonces, &mut blocks, &mut self.desc.section, key)?;
 
 		// Write the data back to the file
 		self.file.seek(io::SeekFrom::Start(file_offset))?;
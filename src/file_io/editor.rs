@@ -1,3 +1,4 @@
+use std::sync::Arc;
 use super::*;
 
 /// File editor.
@@ -6,26 +7,65 @@ use super::*;
 ///
 /// The implementation makes a reasonable attempt to defend against data loss.
 /// If consistency is super important then consider [`MemoryEditor`] and save a fresh copy when needed.
+///
+/// Readers opened while the editor is finishing see either the previous or the new directory, never a mix of both:
+///
+/// * Data is only ever appended after the previous directory and its copy of the header, committed data is never overwritten.
+///   The directory offset acts as an epoch, every finish references a directory at a higher offset than before.
+///   The exception is [`reencrypt_data`](FileEditFile::reencrypt_data) which updates file contents inplace.
+/// * The new directory and a copy of the new header are appended and synced before the header at the start of the PAKS file is overwritten.
+/// * Readers which fail to authenticate the header, because it is being overwritten, use the copy of the header at the end of the PAKS file instead.
+///
+/// Rewriting the PAKS file as a whole, such as garbage collection, is not covered by these guarantees.
+///
+/// # Locking
+///
+/// The editor holds an exclusive advisory lock on the PAKS file until it is finished or dropped.
+/// This prevents two editors from clobbering each other's changes, other programs and [`FileReader`] are not affected by the lock.
+/// On platforms without file locking support the PAKS file is not locked.
 pub struct FileEditor {
 	file: fs::File,
+	path: PathBuf,
 	directory: Directory,
+	committed: (InfoHeader, Directory),
 	high_mark: u32,
+	magic: bool,
+	block_shift: u32,
+	inline_threshold: usize,
+	key_slots: Vec<KeySlot>,
+	nonces: Option<Arc<dyn NonceSource>>,
+	generate_id: bool,
 }
 
 impl FileEditor {
 	/// Creates a new PAKS file, failing if it already exists.
+	///
+	/// The new PAKS file gets a random archive id when it is finished, see [`ArchiveId`].
 	#[inline]
 	pub fn create_new<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<FileEditor> {
 		create_new(path.as_ref(), key)
 	}
 
 	/// Opens an existing PAKS file, error if it doesn't exist.
+	///
+	/// Waits until any other editor of the PAKS file is finished or dropped.
 	#[inline]
 	pub fn open<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<FileEditor> {
-		open(path.as_ref(), key)
+		open(path.as_ref(), key, true)
+	}
+
+	/// Opens an existing PAKS file without waiting for other editors, error if it doesn't exist.
+	///
+	/// If another editor holds the lock on the PAKS file, [`io::ErrorKind::ResourceBusy`] is returned.
+	#[inline]
+	pub fn try_open<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<FileEditor> {
+		open(path.as_ref(), key, false)
 	}
 
 	/// Creates an empty PAKS file, overwrites any file if it already exists.
+	///
+	/// The empty PAKS file gets a random archive id, see [`ArchiveId`].
+	/// Waits until any other editor of the PAKS file is finished or dropped.
 	#[inline]
 	pub fn create_empty<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<()> {
 		create_empty(path.as_ref(), key)
@@ -43,13 +83,18 @@ impl FileEditor {
 
 #[inline(never)]
 fn create_new(path: &Path, key: &Key) -> io::Result<FileEditor> {
-	let mut file = fs::OpenOptions::new().create_new(true).read(true).write(true).open(path)?;
+	span!(DEBUG, "create_new", path = %path.display());
 
+	// Encrypt the header before creating the file, no file is left behind if no nonce is available
 	let mut header = Header::default();
 	header.info.directory.offset = Header::BLOCKS_LEN as u32;
 	header.info.directory.size = 0;
-	crypt::encrypt_section(&mut [], &mut header.info.directory, key);
-	crypt::encrypt_header(&mut header, key);
+	crypt::encrypt_section_from(None, &mut [], &mut header.info.directory, key)?;
+	let info = header.info;
+	crypt::encrypt_header(&mut header, key, true)?;
+
+	let mut file = fs::OpenOptions::new().create_new(true).read(true).write(true).open(path)?;
+	lock(&file, true)?;
 
 	// Write an empty PAKS file placeholder
 	file.write_all(dataview::bytes(&header))?;
@@ -57,42 +102,90 @@ fn create_new(path: &Path, key: &Key) -> io::Result<FileEditor> {
 
 	// Create the empty FileEditor
 	let directory = Directory::new();
+	let committed = (info, directory.clone());
 	let high_mark = Header::BLOCKS_LEN as u32;
-	Ok(FileEditor { file, directory, high_mark })
+	Ok(FileEditor { file, path: path.to_path_buf(), directory, committed, high_mark, magic: true, block_shift: 0, inline_threshold: 0, key_slots: Vec::new(), nonces: None, generate_id: true })
 }
 
 #[inline(never)]
-fn open(path: &Path, key: &Key) -> io::Result<FileEditor> {
-	let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+fn open(path: &Path, key: &Key, wait: bool) -> io::Result<FileEditor> {
+	span!(DEBUG, "open", path = %path.display(), wait);
 
-	let (info, directory) = read_header(&mut file, key)?;
+	let file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+	// Acquire the lock before reading the header to see the changes of the previous editor
+	lock(&file, wait)?;
+
+	let (len, key_slots) = read_key_footer(&file)?;
+	let (info, directory, magic) = read_header_at(&file, key, 0, len)?;
+
+	let high_mark = initial_high_mark(&file, &info, len)?;
+	let committed = (info, directory.clone());
+	Ok(FileEditor { file, path: path.to_path_buf(), directory, committed, high_mark, magic, block_shift: info.block_shift(), inline_threshold: 0, key_slots, nonces: None, generate_id: false })
+}
 
-	// Initialize the high mark right after the end of the directory
-	// This ensures that in case of failure that the existing directory remains intact
-	let high_mark = info.directory.offset + info.directory.size * InfoHeader::BLOCKS_LEN as u32;
-	Ok(FileEditor { file, directory, high_mark })
+// Initialize the high mark right after the end of the directory and the copy of the header following it
+// This ensures that in case of failure that the existing directory remains intact
+// Keeping the copy of the header allows the directory to be recovered later, see FileReader::open_salvage
+// Any key slots following the PAKS file of the given byte length are kept intact as well until finish appends them again
+fn initial_high_mark(file: &fs::File, info: &InfoHeader, len: u64) -> io::Result<u32> {
+	let dir_end = match info.directory_end() {
+		Some(dir_end) => dir_end,
+		None => Err(io::ErrorKind::InvalidData)?,
+	};
+	let file_len = file.metadata()?.len() / BLOCK_SIZE as u64;
+	let high_mark = if len / BLOCK_SIZE as u64 != file_len {
+		u64::min(file_len, u32::MAX as u64) as u32
+	}
+	else if file_len >= dir_end as u64 + Header::BLOCKS_LEN as u64 {
+		dir_end.saturating_add(Header::BLOCKS_LEN as u32)
+	}
+	else {
+		dir_end
+	};
+	Ok(u32::max(Header::BLOCKS_LEN as u32, high_mark))
 }
 
 #[inline(never)]
 fn create_empty(path: &Path, key: &Key) -> io::Result<()> {
-	let mut header = Header::default();
-	header.info.directory.offset = Header::BLOCKS_LEN as u32;
-	header.info.directory.size = 0;
-	crypt::encrypt_section(&mut [], &mut header.info.directory, key);
-	crypt::encrypt_header(&mut header, key);
-	fs::write(path, dataview::bytes(&header))
+	let (blocks, _) = MemoryEditor::new().finish(key)?;
+
+	// Do not truncate the PAKS file before acquiring the lock
+	let mut file = fs::OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+	lock(&file, true)?;
+	file.set_len(0)?;
+	file.write_all(dataview::bytes(blocks.as_slice()))
+}
+
+// Acquires an exclusive advisory lock on the PAKS file, released when the file is closed
+fn lock(file: &fs::File, wait: bool) -> io::Result<()> {
+	let result = if wait {
+		file.lock()
+	}
+	else {
+		match file.try_lock() {
+			Ok(()) => Ok(()),
+			Err(fs::TryLockError::WouldBlock) => return Err(io::Error::new(io::ErrorKind::ResourceBusy, "PAKS file is locked by another editor")),
+			Err(fs::TryLockError::Error(err)) => Err(err),
+		}
+	};
+	match result {
+		Err(err) if err.kind() == io::ErrorKind::Unsupported => Ok(()),
+		result => result,
+	}
 }
 
 #[inline(never)]
 fn read_only(path: &Path, key: &Key) -> io::Result<FileEditor> {
-	let mut file = fs::File::open(path)?;
+	span!(DEBUG, "read_only", path = %path.display());
+
+	let file = fs::File::open(path)?;
 
-	let (info, directory) = read_header(&mut file, key)?;
+	let (len, key_slots) = read_key_footer(&file)?;
+	let (info, directory, magic) = read_header_at(&file, key, 0, len)?;
 
-	// Initialize the high mark right after the end of the directory
-	// This ensures that in case of failure that the existing directory remains intact
-	let high_mark = u32::max(Header::BLOCKS_LEN as u32, info.directory.offset + info.directory.size * InfoHeader::BLOCKS_LEN as u32);
-	Ok(FileEditor { file, directory, high_mark })
+	let high_mark = initial_high_mark(&file, &info, len)?;
+	let committed = (info, directory.clone());
+	Ok(FileEditor { file, path: path.to_path_buf(), directory, committed, high_mark, magic, block_shift: info.block_shift(), inline_threshold: 0, key_slots, nonces: None, generate_id: false })
 }
 
 impl ops::Deref for FileEditor {
@@ -116,51 +209,437 @@ impl FileEditor {
 		self.high_mark
 	}
 
+	/// Returns if the PAKS file is written with the plaintext magic signature.
+	#[inline]
+	pub fn has_magic(&self) -> bool {
+		self.magic
+	}
+
+	/// Sets whether the PAKS file is written with the plaintext magic signature.
+	///
+	/// New PAKS files are written with the magic signature, existing PAKS files keep their setting.
+	/// Without the magic signature (stealth mode) the PAKS file is indistinguishable from random data, see [`identify`].
+	#[inline]
+	pub fn set_magic(&mut self, magic: bool) -> &mut FileEditor {
+		self.magic = magic;
+		self
+	}
+
+	/// Returns the block size of the PAKS file as a shift of the cipher block size.
+	#[inline]
+	pub fn block_shift(&self) -> u32 {
+		self.block_shift
+	}
+
+	/// Sets the block size of the PAKS file to `BLOCK_SIZE << block_shift` bytes.
+	///
+	/// See [`MemoryEditor::set_block_shift`] for more information.
+	#[inline]
+	pub fn set_block_shift(&mut self, block_shift: u32) -> &mut FileEditor {
+		self.block_shift = u32::min(block_shift, InfoHeader::MAX_BLOCK_SHIFT);
+		self
+	}
+
+	/// Returns the size in bytes up to which new files are stored inline.
+	#[inline]
+	pub fn inline_threshold(&self) -> usize {
+		self.inline_threshold
+	}
+
+	/// Sets the size in bytes up to which new files are stored inline in their descriptor.
+	///
+	/// See [`MemoryEditor::set_inline_threshold`] for more information.
+	#[inline]
+	pub fn set_inline_threshold(&mut self, len: usize) -> &mut FileEditor {
+		self.inline_threshold = usize::min(len, Descriptor::INLINE_LEN);
+		self
+	}
+
+	/// Installs the source of the nonces of the sections encrypted by this editor.
+	///
+	/// See [`MemoryEditor::set_nonce_source`] for more information.
+	/// The header of a PAKS file created with [`create_new`](Self::create_new) is encrypted before the nonce source is installed, it is replaced when the editor is finished.
+	#[inline]
+	pub fn set_nonce_source(&mut self, nonces: Arc<dyn NonceSource>) -> &mut FileEditor {
+		self.nonces = Some(nonces);
+		self
+	}
+
+	/// Returns the nonce source, if any.
+	#[inline]
+	pub fn nonce_source(&self) -> Option<&dyn NonceSource> {
+		self.nonces.as_deref()
+	}
+
+	/// Generates a random archive id, see [`ArchiveId`].
+	///
+	/// See [`MemoryEditor::generate_archive_id`] for more information.
+	pub fn generate_archive_id(&mut self) -> io::Result<ArchiveId> {
+		let id = ArchiveId(crypt::next_nonce(self.nonces.as_deref())?);
+		self.directory.set_archive_id(id);
+		Ok(id)
+	}
+
+	/// Returns the key slots of the PAKS file.
+	#[inline]
+	pub fn key_slots(&self) -> &[KeySlot] {
+		&self.key_slots
+	}
+
+	/// Adds a key slot which wraps the key of the PAKS file with the user key.
+	///
+	/// See [`MemoryEditor::add_key_slot`] for more information.
+	pub fn add_key_slot(&mut self, user_key: &Key, key: &Key) -> io::Result<&mut FileEditor> {
+		let key_slot = KeySlot::wrap_from(self.nonces.as_deref(), key, user_key)?;
+		keyslot::remove(&mut self.key_slots, user_key);
+		self.key_slots.push(key_slot);
+		Ok(self)
+	}
+
+	/// Removes the key slot of the user key.
+	///
+	/// See [`MemoryEditor::remove_key_slot`] for more information.
+	pub fn remove_key_slot(&mut self, user_key: &Key) -> bool {
+		keyslot::remove(&mut self.key_slots, user_key)
+	}
+
+	/// Opens a reader of the last committed state of the PAKS file.
+	///
+	/// The reader sees the directory as it was when the editor was opened and is not affected by the changes made by the editor, even after it is finished.
+	/// This allows serving the files of a PAKS file while it is being updated, the reader uses its own handle to the PAKS file.
+	///
+	/// The contents of files updated inplace with [`reencrypt_data`](FileEditFile::reencrypt_data) fail authentication in the reader.
+	/// Garbage collecting the PAKS file invalidates the reader.
+	pub fn reader_snapshot(&self) -> io::Result<FileReader> {
+		let file = fs::File::open(&self.path)?;
+		let (info, ref directory) = self.committed;
+		Ok(FileReader { file, directory: directory.clone(), info, base: 0, metrics: None })
+	}
+
+	/// Copies the PAKS file into memory, including the changes which are not finished yet.
+	///
+	/// Any garbage in the PAKS file is copied as well, see [`MemoryEditor::gc`].
+	/// Save the result with [`MemoryEditor::save_to`].
+	pub fn to_memory(&self) -> io::Result<MemoryEditor> {
+		let mut blocks = vec![Block::default(); self.high_mark as usize];
+		read_exact_at(&self.file, dataview::bytes_mut(blocks.as_mut_slice()), 0)?;
+		let directory = self.directory.clone();
+		Ok(MemoryEditor { blocks, directory, magic: self.magic, block_shift: self.block_shift, inline_threshold: self.inline_threshold, key_slots: self.key_slots.clone(), nonces: self.nonces.clone(), generate_id: self.generate_id })
+	}
+
 	/// Creates a file descriptor at the given path.
 	///
 	/// Any missing parent directories are automatically created.
+	///
+	/// Returns an error if the path is invalid, see [`validate_path`].
 	#[inline]
-	pub fn edit_file(&mut self, path: &[u8]) -> FileEditFile<'_> {
-		let desc = self.directory.create(path);
+	pub fn edit_file(&mut self, path: &[u8]) -> Result<FileEditFile<'_>, PathError> {
+		let desc = self.directory.create(path)?;
+		let file = &self.file;
+		let high_mark = &mut self.high_mark;
+		let alignment = 1 << self.block_shift;
+		Ok(FileEditFile { file, desc, high_mark, alignment, nonces: self.nonces.as_deref() })
+	}
+
+	// Edits the archive metadata, its paths are reserved and cannot be edited with edit_file
+	pub(crate) fn edit_metadata(&mut self, path: &[u8]) -> FileEditFile<'_> {
+		let desc = self.directory.create_metadata(path);
 		let file = &self.file;
 		let high_mark = &mut self.high_mark;
-		FileEditFile { file, desc, high_mark }
+		let alignment = 1 << self.block_shift;
+		FileEditFile { file, desc, high_mark, alignment, nonces: self.nonces.as_deref() }
 	}
 
 	/// Creates a file at the given path.
 	///
 	/// The file is assigned a content_type of `1`.
 	/// A new section is allocated and the data is encrypted and written into the section.
+	/// Replacing a plaintext file stores the new contents encrypted.
 	///
 	/// Any missing parent directories are automatically created.
 	///
 	/// If the data's len is greater than 4 GiB it is truncated as its size is stored in a `u32`.
+	///
+	/// Returns an error if the path is invalid, see [`validate_path`].
 	pub fn create_file(&mut self, path: &[u8], data: &[u8], key: &Key) -> io::Result<&Descriptor> {
-		let mut edit_file = self.edit_file(path);
-		edit_file.set_content(1, data.len() as u32);
-		edit_file.allocate_data().write_data(data, key)?;
+		self.create_typed(path, 1, data, key)
+	}
+
+	/// Creates a file at the given path with the given behavior if the path already exists.
+	///
+	/// See [`MemoryEditor::create_file_with`] for more information.
+	pub fn create_file_with(&mut self, path: &[u8], data: &[u8], mode: CreateMode, key: &Key) -> io::Result<&Descriptor> {
+		match mode {
+			CreateMode::CreateNew if self.find_desc(path).is_some() => Err(Error::with_path(io::ErrorKind::AlreadyExists, path).into()),
+			CreateMode::CreateNew | CreateMode::Overwrite => self.create_typed(path, 1, data, key),
+			CreateMode::Append => self.append(path, data, key),
+			CreateMode::Delta => self.create_delta(path, data, key),
+		}
+	}
+
+	fn create_delta(&mut self, path: &[u8], data: &[u8], key: &Key) -> io::Result<&Descriptor> {
+		let old = match self.directory.find_file(path) {
+			Some(desc) if data.len() > self.inline_threshold && u32::try_from(data.len()).is_ok() && delta::is_base(&self.directory, desc) => *desc,
+			_ => return self.create_typed(path, 1, data, key),
+		};
+		let file = &self.file;
+		let base = delta::base_of(&old, |section| read_section(file, 0, section, key)).map_err(|err| at_path(err, path))?;
+		let base_blocks = read_section(file, 0, &base.section, key).map_err(|err| at_path(err, path))?;
+		let base_data = match base.data(&base_blocks) {
+			Some(base_data) => base_data,
+			None => Err(Error::with_path(io::ErrorKind::InvalidData, path))?,
+		};
+		let payload = match delta::encode(&base, base_data, data) {
+			Some(payload) => payload,
+			None => return self.create_typed(path, 1, data, key),
+		};
+
+		let mut edit_file = self.edit_file(path).map_err(|err| Error::from(err).at(path))?;
+		edit_file.set_content(1, payload.len() as u32);
+		edit_file.allocate_data().write_data(&payload, key)?;
+		edit_file.desc.content_size = data.len() as u32;
+		// Files with extension records are never the base of a delta
+		let is_delta = edit_file.desc.set_delta_base(base.section.offset, base.section.size);
+		debug_assert!(is_delta);
+		Ok(edit_file.desc)
+	}
+
+	/// Serializes the value to JSON and creates a file at the given path.
+	///
+	/// See [`MemoryEditor::create_json`] for more information.
+	#[cfg(feature = "json")]
+	pub fn create_json<T: ?Sized + serde::Serialize>(&mut self, path: &[u8], value: &T, key: &Key) -> io::Result<&Descriptor> {
+		let data = serde_json::to_vec(value).map_err(|_| Error::with_path(io::ErrorKind::InvalidInput, path))?;
+		self.create_typed(path, Descriptor::CONTENT_TYPE_JSON, &data, key)
+	}
+
+	/// Serializes the value to TOML and creates a file at the given path.
+	///
+	/// See [`MemoryEditor::create_toml`] for more information.
+	#[cfg(feature = "toml")]
+	pub fn create_toml<T: ?Sized + serde::Serialize>(&mut self, path: &[u8], value: &T, key: &Key) -> io::Result<&Descriptor> {
+		let data = toml::to_vec(value).map_err(|_| Error::with_path(io::ErrorKind::InvalidInput, path))?;
+		self.create_typed(path, Descriptor::CONTENT_TYPE_TOML, &data, key)
+	}
+
+	fn create_typed(&mut self, path: &[u8], content_type: u32, data: &[u8], key: &Key) -> io::Result<&Descriptor> {
+		let inline = data.len() <= self.inline_threshold;
+		let edit_file = self.edit_file(path).map_err(|err| Error::from(err).at(path))?;
+		write_typed(edit_file, content_type, data, inline, key)
+	}
+
+	// Stores the archive metadata like create_file
+	fn set_metadata(&mut self, path: &[u8], data: &[u8], key: &Key) -> io::Result<()> {
+		let inline = data.len() <= self.inline_threshold;
+		let edit_file = self.edit_metadata(path);
+		write_typed(edit_file, 1, data, inline, key)?;
+		Ok(())
+	}
+
+	/// Creates many small files sharing a single section, see [`Descriptor::FLAG_BUNDLED`].
+	///
+	/// The files are assigned a content_type of `1`.
+	/// The contents of the files are concatenated, encrypted and written into one newly allocated section.
+	/// Each file descriptor records the byte offset of its contents within the shared section.
+	/// Bundling folders of tiny files saves the nonce, MAC and padding of a section per file.
+	///
+	/// Any missing parent directories are automatically created.
+	///
+	/// Returns an error if any of the paths is invalid, see [`validate_path`].
+	/// No files are created unless all the paths are valid.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The files are larger than 4 GiB in total.
+	pub fn create_bundle(&mut self, files: &[(&[u8], &[u8])], key: &Key) -> io::Result<()> {
+		// Check the paths before writing anything
+		for &(path, _) in files {
+			validate_path_with(path, self.directory.separators()).map_err(|err| Error::from(err).at(path))?;
+		}
+		let content_size = match u32::try_from(files.iter().map(|(_, data)| data.len()).sum::<usize>()) {
+			Ok(content_size) => content_size,
+			Err(_) => Err(io::ErrorKind::InvalidInput)?,
+		};
+
+		// Encrypt the concatenated file contents into the shared section
+		let bufs: Vec<_> = files.iter().map(|&(_, data)| io::IoSlice::new(data)).collect();
+		let mut bundle = Descriptor::default();
+		FileEditFile { file: &self.file, desc: &mut bundle, high_mark: &mut self.high_mark, alignment: 1 << self.block_shift, nonces: self.nonces.as_deref() }
+			.set_content(1, content_size)
+			.allocate_data()
+			.write_data_vectored(&bufs, key)?;
+
+		let mut data_offset = 0;
+		for &(path, data) in files {
+			let desc = self.directory.create_lenient(path);
+			desc.content_type = 1;
+			desc.content_size = data.len() as u32;
+			desc.set_bundled(&bundle.section, data_offset);
+			data_offset += data.len() as u32;
+		}
+		Ok(())
+	}
+
+	/// Appends already encrypted blocks to the PAKS file.
+	///
+	/// Returns the section at its new location with the nonce and MAC of the given section, the offset of the given section is ignored.
+	/// The blocks are garbage until a file descriptor refers to the returned section, eg. with [`FileEditFile::set_section`].
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The number of blocks does not match the section size.
+	pub fn append_encrypted(&mut self, blocks: &[Block], section: &Section) -> io::Result<Section> {
+		let content_size = match u32::try_from(blocks.len() * BLOCK_SIZE) {
+			Ok(content_size) => content_size,
+			Err(_) => Err(io::ErrorKind::InvalidInput)?,
+		};
+		let mut desc = Descriptor::default();
+		FileEditFile { file: &self.file, desc: &mut desc, high_mark: &mut self.high_mark, alignment: 1 << self.block_shift, nonces: self.nonces.as_deref() }
+			.set_content(1, content_size)
+			.allocate_data()
+			.write_encrypted(blocks, section)?;
+		Ok(desc.section)
+	}
+
+
+	/// Appends data to the end of a file.
+	///
+	/// The file is created with a content_type of `1` if it does not exist.
+	/// The existing contents are decrypted and re-encrypted together with the data into a new larger section.
+	/// The old section is left behind as garbage, links to the file keep referring to the old contents.
+	///
+	/// Returns an error if the path is invalid, see [`validate_path`].
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The path refers to a directory or the file would grow larger than 4 GiB.
+	/// * [`io::ErrorKind::InvalidData`]: The existing file contents failed authentication.
+	pub fn append(&mut self, path: &[u8], data: &[u8], key: &Key) -> io::Result<&Descriptor> {
+		let (content_type, old_data) = match self.find_desc(path) {
+			Some(desc) if desc.is_file() => (desc.content_type, self.read(path, key)?),
+			Some(_) => Err(Error::with_path(io::ErrorKind::InvalidInput, path))?,
+			None => (1, Vec::new()),
+		};
+		let content_size = match u32::try_from(old_data.len() + data.len()) {
+			Ok(content_size) => content_size,
+			Err(_) => Err(Error::with_path(io::ErrorKind::InvalidInput, path))?,
+		};
+
+		let mut edit_file = self.edit_file(path).map_err(|err| Error::from(err).at(path))?;
+		edit_file.set_content(content_type, content_size);
+		edit_file.allocate_data().write_data_vectored(&[io::IoSlice::new(&old_data), io::IoSlice::new(data)], key)?;
 		Ok(edit_file.desc)
 	}
 
+	/// Overwrites part of a file's contents.
+	///
+	/// The bytes starting at the byte offset are replaced with the data and the patched contents are written to a newly allocated section.
+	/// Inline file contents are patched in the descriptor.
+	/// The file cannot grow this way, use [`truncate`](FileEditFile::truncate) to resize the file first.
+	///
+	/// Links to the file keep the previous contents, like [`append`](Self::append).
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::NotFound`]: No file exists at the path.
+	/// * [`io::ErrorKind::InvalidInput`]: The data does not fit within the file's contents.
+	/// * [`io::ErrorKind::InvalidData`]: The file contents failed authentication.
+	pub fn write_at(&mut self, path: &[u8], byte_offset: usize, data: &[u8], key: &Key) -> io::Result<()> {
+		let inline = match self.directory.find_file(path) {
+			Some(desc) if byte_offset.checked_add(data.len()).is_some_and(|end| end <= desc.content_size as usize) => desc.inline_data().map(<[u8]>::to_vec),
+			Some(_) => Err(Error::with_path(io::ErrorKind::InvalidInput, path))?,
+			None => Err(Error::with_path(io::ErrorKind::NotFound, path))?,
+		};
+
+		// Inline file contents are patched in the descriptor
+		if let Some(mut contents) = inline {
+			contents[byte_offset..byte_offset + data.len()].copy_from_slice(data);
+			if let Some(desc) = self.directory.find_id(path).and_then(|id| self.directory.resolve_mut(id)) {
+				desc.set_inline_data(&contents);
+			}
+			return Ok(());
+		}
+
+		// Committed and linked contents are never overwritten, the patched contents are copied to a new section
+		let mut contents = self.read(path, key)?;
+		contents[byte_offset..byte_offset + data.len()].copy_from_slice(data);
+		let mut edit_file = self.edit_file(path).map_err(|err| Error::from(err).at(path))?;
+		let desc = *edit_file.desc;
+		let result = edit_file.allocate_data().write_data(&contents, key).map(|_| ());
+
+		// Leave the existing file contents alone if no nonce is available
+		if result.is_err() {
+			*edit_file.desc = desc;
+		}
+		result
+	}
+
+	/// Stores the preload list, see [`PRELOAD_PATH`].
+	///
+	/// See [`MemoryEditor::set_preload_list`] for more information.
+	pub fn set_preload_list(&mut self, paths: &[&[u8]], key: &Key) -> io::Result<()> {
+		if paths.is_empty() {
+			self.directory.remove_metadata(PRELOAD_PATH);
+			return Ok(());
+		}
+		let data = preload::encode(&self.directory, paths)?;
+		self.set_metadata(PRELOAD_PATH, &data, key)
+	}
+
+	/// Reads the names of the content types, see [`CONTENT_TYPES_PATH`].
+	///
+	/// See [`MemoryReader::content_types`] for more information.
+	pub fn content_types(&self, key: &Key) -> io::Result<ContentTypes> {
+		match self.find_file(CONTENT_TYPES_PATH) {
+			Some(desc) => Ok(content_types::decode(&self.read_data(desc, key)?)),
+			None => Ok(ContentTypes::new()),
+		}
+	}
+
+	/// Stores the names of the content types, see [`CONTENT_TYPES_PATH`].
+	///
+	/// See [`MemoryEditor::set_content_types`] for more information.
+	pub fn set_content_types(&mut self, content_types: &ContentTypes, key: &Key) -> io::Result<()> {
+		if content_types.is_empty() {
+			self.directory.remove_metadata(CONTENT_TYPES_PATH);
+			return Ok(());
+		}
+		let data = content_types::encode(content_types)?;
+		self.set_metadata(CONTENT_TYPES_PATH, &data, key)
+	}
+
 	/// Reads the contents of a file from the PAKS archive.
 	pub fn read(&self, path: &[u8], key: &Key) -> io::Result<Vec<u8>> {
 		let desc = match self.find_file(path) {
 			Some(desc) => desc,
-			None => Err(io::ErrorKind::NotFound)?,
+			None => Err(Error::with_path(io::ErrorKind::NotFound, path))?,
 		};
 
-		self.read_data(desc, key)
+		self.read_data(desc, key).map_err(|err| at_path(err, path))
 	}
 
 	/// Reads the contents of a file from the PAKS archive into a string.
 	pub fn read_to_string(&self, path: &[u8], key: &Key) -> io::Result<String> {
-		let desc = match self.find_file(path) {
-			Some(desc) => desc,
-			None => Err(io::ErrorKind::NotFound)?,
-		};
+		let data = self.read(path, key)?;
+		String::from_utf8(data).map_err(|_| Error::with_path(io::ErrorKind::InvalidData, path).into())
+	}
 
-		let data = self.read_data(desc, key)?;
-		String::from_utf8(data).map_err(|_| io::ErrorKind::InvalidData.into())
+	/// Reads the contents of a file from the PAKS archive and deserializes it from JSON.
+	///
+	/// If the contents are not valid JSON for the type, [`io::ErrorKind::InvalidData`] is returned with the path attached.
+	#[cfg(feature = "json")]
+	pub fn read_json<T: serde::de::DeserializeOwned>(&self, path: &[u8], key: &Key) -> io::Result<T> {
+		let data = self.read(path, key)?;
+		serde_json::from_slice(&data).map_err(|_| Error::with_path(io::ErrorKind::InvalidData, path).into())
+	}
+
+	/// Reads the contents of a file from the PAKS archive and deserializes it from TOML.
+	///
+	/// If the contents are not valid TOML for the type, [`io::ErrorKind::InvalidData`] is returned with the path attached.
+	#[cfg(feature = "toml")]
+	pub fn read_toml<T: serde::de::DeserializeOwned>(&self, path: &[u8], key: &Key) -> io::Result<T> {
+		let data = self.read(path, key)?;
+		toml::from_slice(&data).map_err(|_| Error::with_path(io::ErrorKind::InvalidData, path).into())
 	}
 
 	/// Decrypts the section.
@@ -174,41 +653,90 @@ impl FileEditor {
 	/// * [`io::Error`]: An error encountered reading the underlying PAKS file.
 	#[inline]
 	pub fn read_section(&self, section: &Section, key: &Key) -> io::Result<Vec<Block>> {
-		read_section(&self.file, section, key)
+		read_section(&self.file, 0, section, key)
+	}
+
+	/// Decrypts the section into the given blocks.
+	///
+	/// Reuse the blocks buffer to avoid an allocation for every section when reading many sections.
+	/// The file contents are read with a single positional read where supported.
+	///
+	/// See [`read_section`](Self::read_section) for more information.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The length of the blocks is not equal to the section size.
+	#[inline]
+	pub fn read_section_into(&self, section: &Section, key: &Key, blocks: &mut [Block]) -> io::Result<()> {
+		read_section_into(&self.file, 0, section, key, blocks)
 	}
 
 	/// Decrypts the contents of the given file descriptor.
 	///
+	/// Plaintext file contents are returned as-is, see [`Descriptor::FLAG_PLAINTEXT`].
+	///
 	/// See [`read_section`](Self::read_section) for more information.
 	#[inline]
 	pub fn read_data(&self, desc: &Descriptor, key: &Key) -> io::Result<Vec<u8>> {
-		read_data(&self.file, desc, key)
+		read_data(&self.file, 0, desc, key)
 	}
 
 	/// Decrypts the contents of the given file descriptor into the dest buffer.
 	///
+	/// The bytes starting at the byte offset are decrypted directly into the dest buffer without any intermediate allocation.
+	/// The whole section is still authenticated, if the authentication fails the dest buffer is zeroed.
+	/// Plaintext file contents are copied as-is.
+	///
 	/// See [`read_section`](Self::read_section) for more information.
 	#[inline]
 	pub fn read_data_into(&self, desc: &Descriptor, key: &Key, byte_offset: usize, dest: &mut [u8]) -> io::Result<()> {
-		read_data_into(&self.file, desc, key, byte_offset, dest)
+		read_data_into(&self.file, 0, desc, key, byte_offset, uninit_bytes(dest)).map(|_| ())
 	}
 
 	/// Finish editing the PAKS file.
 	///
-	/// Encrypts and appends the directory to the PAKS file.
+	/// Encrypts and appends the directory to the PAKS file followed by a copy of the new header and the key slots.
+	/// Generates the archive id of a new PAKS file, see [`generate_archive_id`](Self::generate_archive_id).
+	/// Increments the generation of PAKS files with an archive id, see [`Directory::archive_generation`].
 	/// Before updating the new header the file is synced to attempt to preserve consistency.
 	/// Finally the header is updated to point to the new directory.
 	///
+	/// The previous directory and its copy of the header are left behind until the PAKS file is garbage collected,
+	/// see [`FileReader::open_salvage`] to recover a PAKS file from a previous directory.
+	///
 	/// Dropping the PAKS file without calling `finish` results in any changes being lost.
 	pub fn finish(self, key: &Key) -> io::Result<()> {
-		let FileEditor { mut file, mut directory, high_mark } = self;
+		self.finish_locked(key).1
+	}
+
+	// Finishes the editor and returns the PAKS file, the lock is held until the PAKS file is closed
+	pub(crate) fn finish_locked(mut self, key: &Key) -> (fs::File, io::Result<()>) {
+		let result = self.write_directory(key);
+		(self.file, result)
+	}
+
+	// Returns the PAKS file without finishing the editor, the lock is held until the PAKS file is closed
+	pub(crate) fn into_locked(self) -> fs::File {
+		self.file
+	}
+
+	fn write_directory(&mut self, key: &Key) -> io::Result<()> {
+		span!(DEBUG, "finish", high_mark = self.high_mark, descriptors = self.directory.len());
+
+		if self.generate_id && self.directory.archive_id().is_none() {
+			self.generate_archive_id()?;
+		}
+
+		let FileEditor { file, directory, high_mark, magic, block_shift, key_slots, nonces, .. } = self;
+		let (high_mark, magic, block_shift) = (*high_mark, *magic, *block_shift);
+		directory.bump_archive_generation();
 
 		let mut header = Header {
 			nonce: Block::default(),
 			mac: Block::default(),
 			info: InfoHeader {
 				version: InfoHeader::VERSION,
-				_unused: 0,
+				key_check: 0,
 				directory: Section {
 					offset: high_mark,
 					size: directory.len() as u32,
@@ -219,19 +747,23 @@ impl FileEditor {
 		};
 
 		// Encrypt the directory
-		crypt::encrypt_section(directory.as_blocks_mut(), &mut header.info.directory, key);
+		crypt::encrypt_section_from(nonces.as_deref(), directory.as_blocks_mut(), &mut header.info.directory, key)?;
 
 		// Encrypt the header
-		let mut section = Header::SECTION;
-		crypt::encrypt_section(header.info.as_mut(), &mut section, key);
-
-		header.nonce = section.nonce;
-		header.mac = section.mac;
+		crypt::encrypt_header_from(nonces.as_deref(), &mut header, key, magic, block_shift)?;
 
-		// Append the directory
+		// Append the directory followed by a copy of the header and the key slots
+		// The copy makes it possible to find and decrypt this directory after the header is overwritten
 		let dir_offset = high_mark as u64 * BLOCK_SIZE as u64;
+		let footer = keyslot::footer(key_slots);
 		file.seek(io::SeekFrom::Start(dir_offset))?;
 		file.write_all(dataview::bytes(directory.as_ref()))?;
+		file.write_all(dataview::bytes(&header))?;
+		file.write_all(dataview::bytes(footer.as_slice()))?;
+
+		// Discard anything left behind by editors which were never finished
+		// Readers expect the copy of the header at the end of the PAKS file, right before the key slots, while the header is overwritten
+		file.set_len(dir_offset + (dataview::bytes(directory.as_ref()).len() + mem::size_of::<Header>() + dataview::bytes(footer.as_slice()).len()) as u64)?;
 
 		// IMPORTANT! In order to prevent corruption:
 		// Ensure that the above write of the directory is synced
@@ -246,3 +778,12 @@ impl FileEditor {
 		Ok(())
 	}
 }
+
+fn write_typed<'a>(mut edit_file: FileEditFile<'a>, content_type: u32, data: &[u8], inline: bool, key: &Key) -> io::Result<&'a Descriptor> {
+	edit_file.set_content(content_type, data.len() as u32).set_plaintext(false);
+	if inline && edit_file.set_inline_data(data) {
+		return Ok(edit_file.desc);
+	}
+	edit_file.allocate_data().write_data(data, key)?;
+	Ok(edit_file.desc)
+}
@@ -0,0 +1,209 @@
+use std::sync::Arc;
+use super::*;
+
+/// Source of the bytes of a PAKS file, see [`LazyReader`].
+///
+/// Reads take `&self` and do not move any cursor, share the source between threads if it is `Sync`.
+///
+/// Implemented for [`fs::File`] with positioned reads and for byte slices, eg. a memory mapped PAKS file.
+/// Implement it to read the PAKS file from anywhere else, eg. ranged requests over the network.
+pub trait BlockSource {
+	/// Reads exactly enough bytes to fill the buffer starting at the byte offset.
+	///
+	/// Return [`io::ErrorKind::UnexpectedEof`] if the source ends before the buffer is filled.
+	fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+	/// Returns the size of the source in bytes.
+	fn size(&self) -> io::Result<u64>;
+}
+
+impl BlockSource for fs::File {
+	#[inline]
+	fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+		read_exact_at(self, buf, offset)
+	}
+	#[inline]
+	fn size(&self) -> io::Result<u64> {
+		Ok(self.metadata()?.len())
+	}
+}
+
+impl BlockSource for [u8] {
+	fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+		let start = usize::try_from(offset).unwrap_or(usize::MAX);
+		match start.checked_add(buf.len()).and_then(|end| self.get(start..end)) {
+			Some(bytes) => {
+				buf.copy_from_slice(bytes);
+				Ok(())
+			},
+			None => Err(io::ErrorKind::UnexpectedEof.into()),
+		}
+	}
+	#[inline]
+	fn size(&self) -> io::Result<u64> {
+		Ok(self.len() as u64)
+	}
+}
+
+impl<S: ?Sized + BlockSource> BlockSource for &S {
+	#[inline]
+	fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+		(**self).read_exact_at(buf, offset)
+	}
+	#[inline]
+	fn size(&self) -> io::Result<u64> {
+		(**self).size()
+	}
+}
+
+impl<S: ?Sized + BlockSource> BlockSource for Arc<S> {
+	#[inline]
+	fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+		(**self).read_exact_at(buf, offset)
+	}
+	#[inline]
+	fn size(&self) -> io::Result<u64> {
+		(**self).size()
+	}
+}
+
+/// Lazy reader.
+///
+/// Only the directory is kept in memory, the sections are read from the source on demand.
+/// Sits between [`MemoryReader`], which keeps the entire PAKS file in memory, and [`FileReader`], which reads from a file on disk.
+///
+/// The reader is safe to share between threads if the source is.
+pub struct LazyReader<S> {
+	source: S,
+	directory: Directory,
+	info: InfoHeader,
+	metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl<S: BlockSource> LazyReader<S> {
+	/// Reads the header and the directory of the PAKS file from the source.
+	///
+	/// If the encryption key is incorrect, [`io::ErrorKind::PermissionDenied`] is returned.
+	/// If the source is not a PAKS file or is corrupted, [`io::ErrorKind::InvalidData`] is returned.
+	pub fn new(source: S, key: &Key) -> io::Result<LazyReader<S>> {
+		span!(DEBUG, "LazyReader::new");
+
+		let (len, _) = read_key_footer(&source)?;
+		let (info, directory, _) = read_header_at(&source, key, 0, len)?;
+
+		Ok(LazyReader { source, directory, info, metrics: None })
+	}
+
+	/// Returns the source.
+	#[inline]
+	pub fn source(&self) -> &S {
+		&self.source
+	}
+
+	/// Returns the source, dropping the directory.
+	#[inline]
+	pub fn into_source(self) -> S {
+		self.source
+	}
+
+	/// Returns the info header.
+	#[inline]
+	pub fn info(&self) -> &InfoHeader {
+		&self.info
+	}
+
+	/// Highest block index containing file data.
+	#[inline]
+	pub fn high_mark(&self) -> u32 {
+		self.info.directory.offset
+	}
+
+	/// Sets the path separator policy, see [`Directory::set_separators`].
+	#[inline]
+	pub fn set_separators(&mut self, sep: Separators) -> &mut LazyReader<S> {
+		self.directory.set_separators(sep);
+		self
+	}
+
+	/// Installs the metrics hooks, see [`Metrics`].
+	#[inline]
+	pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) -> &mut LazyReader<S> {
+		self.metrics = Some(metrics);
+		self
+	}
+
+	/// Returns the metrics hooks, if any.
+	#[inline]
+	pub fn metrics(&self) -> Option<&dyn Metrics> {
+		self.metrics.as_deref()
+	}
+
+	// Reports the outcome of reading the section to the metrics
+	#[inline]
+	fn report<T>(&self, section: &Section, decrypted: usize, result: io::Result<T>) -> io::Result<T> {
+		metrics::report(self.metrics(), section, decrypted, result.as_ref().err().map(|err| err.kind()));
+		result
+	}
+
+	// Inline file contents are read from the directory, no section is read
+	#[inline]
+	fn report_data<T>(&self, desc: &Descriptor, decrypted: usize, result: io::Result<T>) -> io::Result<T> {
+		if desc.is_inline() {
+			return result;
+		}
+		self.report(&desc.section, decrypted, result)
+	}
+
+	/// Reads the contents of a file from the PAKS archive.
+	pub fn read(&self, path: &[u8], key: &Key) -> io::Result<Vec<u8>> {
+		let desc = match self.find_file(path) {
+			Some(desc) => desc,
+			None => Err(Error::with_path(io::ErrorKind::NotFound, path))?,
+		};
+
+		self.read_data(desc, key).map_err(|err| at_path(err, path))
+	}
+
+	/// Reads the contents of a file from the PAKS archive into a string.
+	pub fn read_to_string(&self, path: &[u8], key: &Key) -> io::Result<String> {
+		let data = self.read(path, key)?;
+		String::from_utf8(data).map_err(|_| Error::with_path(io::ErrorKind::InvalidData, path).into())
+	}
+
+	/// Decrypts the section.
+	///
+	/// See [`FileReader::read_section`] for more information.
+	#[inline]
+	pub fn read_section(&self, section: &Section, key: &Key) -> io::Result<Vec<Block>> {
+		let result = read_section(&self.source, 0, section, key);
+		self.report(section, section.size as usize * BLOCK_SIZE, result)
+	}
+
+	/// Decrypts the contents of the given file descriptor.
+	///
+	/// See [`FileReader::read_data`] for more information.
+	#[inline]
+	pub fn read_data(&self, desc: &Descriptor, key: &Key) -> io::Result<Vec<u8>> {
+		let result = read_data(&self.source, 0, desc, key);
+		let decrypted = if desc.is_plaintext() { 0 } else { desc.section.size as usize * BLOCK_SIZE };
+		self.report_data(desc, decrypted, result)
+	}
+
+	/// Decrypts the contents of the given file descriptor into the dest buffer.
+	///
+	/// See [`FileReader::read_data_into`] for more information.
+	#[inline]
+	pub fn read_data_into(&self, desc: &Descriptor, key: &Key, byte_offset: usize, dest: &mut [u8]) -> io::Result<()> {
+		let decrypted = if desc.is_plaintext() { 0 } else { dest.len() };
+		let result = read_data_into(&self.source, 0, desc, key, byte_offset, uninit_bytes(dest)).map(|_| ());
+		self.report_data(desc, decrypted, result)
+	}
+}
+
+impl<S> ops::Deref for LazyReader<S> {
+	type Target = Directory;
+	#[inline]
+	fn deref(&self) -> &Directory {
+		&self.directory
+	}
+}
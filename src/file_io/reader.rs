@@ -1,29 +1,251 @@
+use std::collections::HashMap;
+use std::sync::{atomic, Arc};
+use std::thread;
 use super::*;
 
 /// File reader.
+///
+/// The reader is safe to share between threads, reads do not move the cursor of the file handle.
+/// See the [thread safety](crate#thread-safety) notes.
 pub struct FileReader {
-	file: fs::File,
-	directory: Directory,
-	info: InfoHeader,
+	pub(super) file: fs::File,
+	pub(super) directory: Directory,
+	pub(super) info: InfoHeader,
+	// Byte offset of the PAKS file, non-zero for embedded PAKS files
+	pub(super) base: u64,
+	pub(super) metrics: Option<Arc<dyn Metrics>>,
 }
 
 impl FileReader {
 	/// Opens a PAKS file for reading.
 	///
-	/// If the file at the given path is not a PAKS file or the encryption key is incorrect, [`io::ErrorKind::InvalidData`] is returned.
+	/// If the encryption key is incorrect, [`io::ErrorKind::PermissionDenied`] is returned.
+	/// If the file at the given path is not a PAKS file or is corrupted, [`io::ErrorKind::InvalidData`] is returned.
 	#[inline]
 	pub fn open<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<FileReader> {
 		open(path.as_ref(), key)
 	}
+
+	/// Opens a PAKS file embedded at the end of another file for reading.
+	///
+	/// The PAKS file must be followed by the footer written by [`write_embedded`], eg. a self-extracting executable with its PAKS file appended.
+	/// If the file does not end with an embedded PAKS file, [`io::ErrorKind::InvalidData`] is returned.
+	///
+	/// See [`open`](Self::open) for more information.
+	#[inline]
+	pub fn open_embedded<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<FileReader> {
+		open_embedded(path.as_ref(), key)
+	}
+
+	/// Opens a corrupted PAKS file for reading, recovering as much as possible.
+	///
+	/// The entire PAKS file is read into memory.
+	/// If the current directory fails its authentication or validation, the PAKS file is scanned for the most recent directory left behind by [`FileEditor::finish`] which can be recovered.
+	/// Every file in the recovered directory is authenticated, files which fail authentication are removed from the directory and listed in [`Salvage::lost`].
+	///
+	/// If no directory can be recovered, the error of opening the current directory is returned.
+	/// If the encryption key is incorrect, [`io::ErrorKind::PermissionDenied`] is returned.
+	pub fn open_salvage<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<Salvage> {
+		open_salvage(path.as_ref(), key)
+	}
+
+	/// Lists the directories of the PAKS file, most recent first.
+	///
+	/// The entire PAKS file is read into memory.
+	/// The first info header references the current directory, followed by the directories left behind by [`FileEditor::finish`].
+	/// Previous directories are only available until the PAKS file is garbage collected.
+	///
+	/// The index of an info header is its generation, see [`open_generation`](Self::open_generation).
+	pub fn generations<P: ?Sized + AsRef<Path>>(path: &P, key: &Key) -> io::Result<Vec<InfoHeader>> {
+		generations(path.as_ref(), key)
+	}
+
+	/// Opens a previous directory of the PAKS file for reading.
+	///
+	/// Generation zero is the current directory, generation `n` is the directory written `n` edits before the current directory.
+	/// Use this to recover a file which was accidentally removed from the PAKS file.
+	///
+	/// If the generation does not exist, [`io::ErrorKind::NotFound`] is returned.
+	/// If the directory of the generation is corrupted, [`io::ErrorKind::InvalidData`] is returned.
+	/// See [`generations`](Self::generations) for more information.
+	pub fn open_generation<P: ?Sized + AsRef<Path>>(path: &P, key: &Key, generation: usize) -> io::Result<FileReader> {
+		open_generation(path.as_ref(), key, generation)
+	}
+}
+
+/// Recovered PAKS file, see [`FileReader::open_salvage`].
+pub struct Salvage {
+	/// Reader for the recovered directory.
+	pub reader: FileReader,
+	/// The number of more recent directories which could not be recovered.
+	///
+	/// Zero if the current directory was recovered.
+	pub generation: usize,
+	/// Paths of the files which failed authentication.
+	///
+	/// These files are removed from the recovered directory.
+	pub lost: Vec<Vec<u8>>,
 }
 
 #[inline(never)]
 fn open(path: &Path, key: &Key) -> io::Result<FileReader> {
-	let mut file = fs::File::open(path)?;
+	span!(DEBUG, "open", path = %path.display());
+
+	let file = fs::File::open(path)?;
+
+	let (info, directory, _) = read_header(&file, key)?;
+
+	Ok(FileReader { file, directory, info, base: 0, metrics: None })
+}
+
+#[inline(never)]
+fn open_embedded(path: &Path, key: &Key) -> io::Result<FileReader> {
+	span!(DEBUG, "open_embedded", path = %path.display());
+
+	let file = fs::File::open(path)?;
 
-	let (info, directory) = read_header(&mut file, key)?;
+	let (base, len) = find_embedded(&file)?;
+	let (info, directory, _) = read_header_at(&file, key, base, len)?;
 
-	Ok(FileReader { file, directory, info })
+	Ok(FileReader { file, directory, info, base, metrics: None })
+}
+
+#[inline(never)]
+fn open_salvage(path: &Path, key: &Key) -> io::Result<Salvage> {
+	span!(DEBUG, "open_salvage", path = %path.display());
+
+	let file = fs::File::open(path)?;
+	let blocks = read_blocks(&file)?;
+	if blocks.len() < Header::BLOCKS_LEN {
+		Err(io::ErrorKind::UnexpectedEof)?;
+	}
+
+	let mut header: Header = dataview::DataView::from(&blocks[..Header::BLOCKS_LEN]).read(0);
+	let magic = header.has_magic();
+	let is_authentic = crypt::decrypt_header(&mut header, key);
+	let result = match is_authentic {
+		Ok(()) => load_directory(&blocks, &header.info, key).map_err(io::Error::from),
+		Err(kind) => Err(header_error(kind, magic)),
+	};
+
+	let (generation, info, mut directory) = match result {
+		Ok(directory) => (0, header.info, directory),
+		Err(err) => {
+			// If the header is not authentic the copy following the current directory takes its place
+			let skip = is_authentic.is_ok() as usize;
+			let copies = scan_directories(&blocks, key, magic)
+				.filter(|(info, _)| skip == 0 || info.directory != header.info.directory);
+			let mut found = None;
+			for (generation, (info, result)) in copies.enumerate() {
+				if let Ok(directory) = result {
+					found = Some((generation + skip, info, directory));
+					break;
+				}
+			}
+			match found {
+				Some(found) => found,
+				None => return Err(err),
+			}
+		},
+	};
+
+	// Authenticate every file and remove the ones which fail
+	let mut lost = Vec::new();
+	salvage_rec(&mut Vec::new(), directory.as_ref(), &mut |path, desc| {
+		let is_valid = if desc.is_delta() {
+			// Delta files are authenticated together with their base section
+			let read_section = |section: &Section| {
+				let mut data = blocks.get(section.range_usize()).ok_or(ErrorKind::InvalidInput)?.to_vec();
+				if crypt::decrypt_section(&mut data, section, key) { Ok(data) } else { Err(ErrorKind::InvalidData) }
+			};
+			delta::read_data(desc, read_section).is_ok()
+		}
+		else {
+			desc.is_plaintext() || desc.is_inline() || blocks.get(desc.section.range_usize()).is_some_and(|blocks| crypt::verify_section(blocks, &desc.section, key))
+		};
+		if !is_valid {
+			lost.push(path.to_vec());
+		}
+	});
+	for path in &lost {
+		match dir::is_metadata_path(path) {
+			true => directory.remove_metadata(path),
+			false => directory.remove(path),
+		};
+	}
+
+	let reader = FileReader { file, directory, info, base: 0, metrics: None };
+	Ok(Salvage { reader, generation, lost })
+}
+
+// Decrypts the header of the PAKS file, used to find the previous directories
+fn current_header(blocks: &[Block], key: &Key) -> io::Result<(InfoHeader, bool)> {
+	if blocks.len() < Header::BLOCKS_LEN {
+		Err(io::ErrorKind::UnexpectedEof)?;
+	}
+	let mut header: Header = dataview::DataView::from(&blocks[..Header::BLOCKS_LEN]).read(0);
+	let magic = header.has_magic();
+	crypt::decrypt_header(&mut header, key).map_err(|kind| header_error(kind, magic))?;
+	Ok((header.info, magic))
+}
+
+#[inline(never)]
+fn generations(path: &Path, key: &Key) -> io::Result<Vec<InfoHeader>> {
+	span!(DEBUG, "generations", path = %path.display());
+
+	let file = fs::File::open(path)?;
+	let blocks = read_blocks(&file)?;
+	let (current, magic) = current_header(&blocks, key)?;
+
+	// The copy of the header following the current directory is skipped
+	let previous = scan_directories(&blocks, key, magic).map(|(info, _)| info).filter(|info| info.directory != current.directory);
+	Ok(std::iter::once(current).chain(previous).collect())
+}
+
+#[inline(never)]
+fn open_generation(path: &Path, key: &Key, generation: usize) -> io::Result<FileReader> {
+	span!(DEBUG, "open_generation", path = %path.display(), generation);
+
+	if generation == 0 {
+		return open(path, key);
+	}
+
+	let file = fs::File::open(path)?;
+	let blocks = read_blocks(&file)?;
+	let (current, magic) = current_header(&blocks, key)?;
+
+	// The copy of the header following the current directory is skipped
+	let mut previous = scan_directories(&blocks, key, magic).filter(|(info, _)| info.directory != current.directory);
+	let (info, result) = match previous.nth(generation - 1) {
+		Some(found) => found,
+		None => Err(io::ErrorKind::NotFound)?,
+	};
+	let directory = result?;
+	Ok(FileReader { file, directory, info, base: 0, metrics: None })
+}
+
+fn salvage_rec(path: &mut Vec<u8>, dir: &[Descriptor], f: &mut dyn FnMut(&[u8], &Descriptor)) {
+	let mut i = 0;
+	while i < dir.len() {
+		let desc = &dir[i];
+		let next_i = dir::next_sibling(desc, i, dir.len());
+
+		let path_len = path.len();
+		if path_len > 0 {
+			path.push(b'/');
+		}
+		path.extend_from_slice(desc.name());
+
+		if desc.is_dir() {
+			salvage_rec(path, &dir[i + 1..next_i], f);
+		}
+		else {
+			f(path, desc);
+		}
+
+		path.truncate(path_len);
+		i = next_i;
+	}
 }
 
 impl ops::Deref for FileReader {
@@ -47,25 +269,205 @@ impl FileReader {
 		self.info.directory.offset
 	}
 
+	/// Restores the names of a stripped PAKS file from its name table.
+	///
+	/// The table is the PAKS file exported by [`NameTable::to_bytes`] and the key is the key of the table.
+	/// Only the names of the reader are restored, the PAKS file is left unchanged.
+	/// Returns the number of names restored.
+	///
+	/// See [`MemoryEditor::strip_names`] for more information.
+	pub fn attach_name_table(&mut self, table: &[u8], key: &Key) -> io::Result<usize> {
+		let table = NameTable::from_bytes(table, key)?;
+		Ok(table.restore(&mut self.directory))
+	}
+
+	/// Sets the path separator policy, see [`Directory::set_separators`].
+	#[inline]
+	pub fn set_separators(&mut self, sep: Separators) -> &mut FileReader {
+		self.directory.set_separators(sep);
+		self
+	}
+
+	/// Installs the metrics hooks, see [`Metrics`].
+	#[inline]
+	pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) -> &mut FileReader {
+		self.metrics = Some(metrics);
+		self
+	}
+
+	/// Returns the metrics hooks, if any.
+	#[inline]
+	pub fn metrics(&self) -> Option<&dyn Metrics> {
+		self.metrics.as_deref()
+	}
+
+	// Reports the outcome of reading the section to the metrics
+	#[inline]
+	fn report<T>(&self, section: &Section, decrypted: usize, result: io::Result<T>) -> io::Result<T> {
+		metrics::report(self.metrics(), section, decrypted, result.as_ref().err().map(|err| err.kind()));
+		result
+	}
+
+	// Inline file contents are read from the directory, no section is read
+	#[inline]
+	fn report_data<T>(&self, desc: &Descriptor, decrypted: usize, result: io::Result<T>) -> io::Result<T> {
+		if desc.is_inline() {
+			return result;
+		}
+		self.report(&desc.section, decrypted, result)
+	}
+
 	/// Reads the contents of a file from the PAKS archive.
 	pub fn read(&self, path: &[u8], key: &Key) -> io::Result<Vec<u8>> {
 		let desc = match self.find_file(path) {
 			Some(desc) => desc,
-			None => Err(io::ErrorKind::NotFound)?,
+			None => Err(Error::with_path(io::ErrorKind::NotFound, path))?,
 		};
 
-		self.read_data(desc, key)
+		self.read_data(desc, key).map_err(|err| at_path(err, path))
 	}
 
 	/// Reads the contents of a file from the PAKS archive into a string.
 	pub fn read_to_string(&self, path: &[u8], key: &Key) -> io::Result<String> {
-		let desc = match self.find_file(path) {
-			Some(desc) => desc,
-			None => Err(io::ErrorKind::NotFound)?,
-		};
+		let data = self.read(path, key)?;
+		String::from_utf8(data).map_err(|_| Error::with_path(io::ErrorKind::InvalidData, path).into())
+	}
+
+	/// Reads the contents of a file from the PAKS archive and deserializes it from JSON.
+	///
+	/// If the contents are not valid JSON for the type, [`io::ErrorKind::InvalidData`] is returned with the path attached.
+	#[cfg(feature = "json")]
+	pub fn read_json<T: serde::de::DeserializeOwned>(&self, path: &[u8], key: &Key) -> io::Result<T> {
+		let data = self.read(path, key)?;
+		serde_json::from_slice(&data).map_err(|_| Error::with_path(io::ErrorKind::InvalidData, path).into())
+	}
 
-		let data = self.read_data(desc, key)?;
-		String::from_utf8(data).map_err(|_| io::ErrorKind::InvalidData.into())
+	/// Reads the contents of a file from the PAKS archive and deserializes it from TOML.
+	///
+	/// If the contents are not valid TOML for the type, [`io::ErrorKind::InvalidData`] is returned with the path attached.
+	#[cfg(feature = "toml")]
+	pub fn read_toml<T: serde::de::DeserializeOwned>(&self, path: &[u8], key: &Key) -> io::Result<T> {
+		let data = self.read(path, key)?;
+		toml::from_slice(&data).map_err(|_| Error::with_path(io::ErrorKind::InvalidData, path).into())
+	}
+
+	/// Reads the preload list, see [`PRELOAD_PATH`].
+	///
+	/// See [`MemoryReader::preload_list`] for more information.
+	pub fn preload_list(&self, key: &Key) -> io::Result<Vec<Vec<u8>>> {
+		match self.find_file(PRELOAD_PATH) {
+			Some(desc) => Ok(preload::decode(&self.read_data(desc, key)?)),
+			None => Ok(Vec::new()),
+		}
+	}
+
+	/// Reads the names of the content types, see [`CONTENT_TYPES_PATH`].
+	///
+	/// See [`MemoryReader::content_types`] for more information.
+	pub fn content_types(&self, key: &Key) -> io::Result<ContentTypes> {
+		match self.find_file(CONTENT_TYPES_PATH) {
+			Some(desc) => Ok(content_types::decode(&self.read_data(desc, key)?)),
+			None => Ok(ContentTypes::new()),
+		}
+	}
+
+	/// Reads every file in the order of their contents in the PAKS file.
+	///
+	/// Tools which process every file, eg. to scan or reindex the PAKS file, read it front to back instead of seeking for every file.
+	/// Files bundled together are decrypted once per bundle.
+	/// Links are yielded under every path linking them.
+	///
+	/// Errors have the path attached and do not stop the iteration.
+	pub fn read_all<'a>(&'a self, key: &Key) -> impl 'a + Iterator<Item = (Vec<u8>, io::Result<Vec<u8>>)> {
+		let key = *key;
+		let mut bundle = None;
+		dir::disk_order(self.directory.as_ref()).into_iter().map(move |(path, desc)| {
+			let result = if desc.is_bundled() {
+				read_bundled(&mut bundle, desc, |section| self.read_section(section, &key))
+			}
+			else {
+				self.read_data(desc, &key)
+			};
+			let result = result.map_err(|err| at_path(err, &path));
+			(path, result)
+		})
+	}
+
+	/// Authenticates the contents of every file on a pool of threads.
+	///
+	/// Integrity checks of large PAKS files, eg. in CI, are limited by the disk bandwidth instead of a single core.
+	/// Every section is authenticated once without decrypting it, even if it is shared by links or bundled files.
+	/// Delta files are reconstructed to also authenticate their base.
+	/// Plaintext file contents have no MAC and are only checked to be within the PAKS file, inline file contents are authenticated with the directory.
+	///
+	/// With zero jobs the number of threads is the available parallelism.
+	///
+	/// Returns the path of every file including the archive metadata with the result of its authentication, in the order of [`read_all`](Self::read_all).
+	/// Errors have the path attached, files which fail authentication have [`io::ErrorKind::InvalidData`].
+	pub fn verify_all_parallel(&self, key: &Key, jobs: usize) -> Vec<(Vec<u8>, io::Result<()>)> {
+		span!(DEBUG, "verify_all_parallel", jobs);
+
+		let files = dir::disk_order_with_metadata(self.directory.as_ref());
+
+		// Files sharing their encrypted section are authenticated once
+		let mut units = Vec::new();
+		let mut sections = HashMap::new();
+		let unit_of: Vec<Option<usize>> = files.iter().map(|&(_, desc)| {
+			if desc.is_inline() {
+				return None;
+			}
+			if desc.is_plaintext() || desc.is_delta() {
+				units.push(desc);
+				return Some(units.len() - 1);
+			}
+			Some(*sections.entry(desc.section).or_insert_with(|| {
+				units.push(desc);
+				units.len() - 1
+			}))
+		}).collect();
+
+		let jobs = if jobs == 0 { thread::available_parallelism().map_or(1, |n| n.get()) } else { jobs };
+		let jobs = usize::max(1, usize::min(jobs, units.len()));
+
+		// The threads take the next unit in the order of the PAKS file to keep the disk access mostly sequential
+		let next = atomic::AtomicUsize::new(0);
+		let mut results = vec![Ok(()); units.len()];
+		thread::scope(|scope| {
+			let workers: Vec<_> = (0..jobs).map(|_| scope.spawn(|| {
+				let mut blocks = Vec::new();
+				let mut done = Vec::new();
+				loop {
+					let index = next.fetch_add(1, atomic::Ordering::Relaxed);
+					let desc = match units.get(index) {
+						Some(desc) => desc,
+						None => break,
+					};
+					done.push((index, self.verify_data(desc, key, &mut blocks).map_err(|err| err.kind())));
+				}
+				done
+			})).collect();
+			for worker in workers {
+				for (index, result) in worker.join().unwrap() {
+					results[index] = result;
+				}
+			}
+		});
+
+		files.into_iter().zip(unit_of).map(|((path, _), unit)| {
+			let result = match unit.map(|index| results[index]) {
+				Some(Err(kind)) => Err(Error::with_path(kind, &path).into()),
+				_ => Ok(()),
+			};
+			(path, result)
+		}).collect()
+	}
+
+	fn verify_data(&self, desc: &Descriptor, key: &Key, blocks: &mut Vec<Block>) -> io::Result<()> {
+		if desc.is_plaintext() || desc.is_delta() {
+			return self.read_data(desc, key).map(|_| ());
+		}
+		let result = verify_section(&self.file, self.base, &desc.section, key, blocks);
+		self.report(&desc.section, 0, result)
 	}
 
 	/// Decrypts the section.
@@ -79,22 +481,61 @@ impl FileReader {
 	/// * [`io::Error`]: An error encountered reading the underlying PAKS file.
 	#[inline]
 	pub fn read_section(&self, section: &Section, key: &Key) -> io::Result<Vec<Block>> {
-		read_section(&self.file, section, key)
+		let result = read_section(&self.file, self.base, section, key);
+		self.report(section, section.size as usize * BLOCK_SIZE, result)
+	}
+
+	/// Decrypts the section into the given blocks.
+	///
+	/// Reuse the blocks buffer to avoid an allocation for every section when reading many sections.
+	/// The file contents are read with a single positional read where supported.
+	///
+	/// See [`read_section`](Self::read_section) for more information.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The length of the blocks is not equal to the section size.
+	#[inline]
+	pub fn read_section_into(&self, section: &Section, key: &Key, blocks: &mut [Block]) -> io::Result<()> {
+		let result = read_section_into(&self.file, self.base, section, key, blocks);
+		self.report(section, section.size as usize * BLOCK_SIZE, result)
 	}
 
 	/// Decrypts the contents of the given file descriptor.
 	///
+	/// Plaintext file contents are returned as-is, see [`Descriptor::FLAG_PLAINTEXT`].
+	///
 	/// See [`read_section`](Self::read_section) for more information.
 	#[inline]
 	pub fn read_data(&self, desc: &Descriptor, key: &Key) -> io::Result<Vec<u8>> {
-		read_data(&self.file, desc, key)
+		let result = read_data(&self.file, self.base, desc, key);
+		let decrypted = if desc.is_plaintext() { 0 } else { desc.section.size as usize * BLOCK_SIZE };
+		self.report_data(desc, decrypted, result)
 	}
 
 	/// Decrypts the contents of the given file descriptor into the dest buffer.
 	///
+	/// The bytes starting at the byte offset are decrypted directly into the dest buffer without any intermediate allocation.
+	/// The whole section is still authenticated, if the authentication fails the dest buffer is zeroed.
+	/// Plaintext file contents are copied as-is.
+	///
 	/// See [`read_section`](Self::read_section) for more information.
 	#[inline]
 	pub fn read_data_into(&self, desc: &Descriptor, key: &Key, byte_offset: usize, dest: &mut [u8]) -> io::Result<()> {
-		read_data_into(&self.file, desc, key, byte_offset, dest)
+		self.read_data_uninit(desc, key, byte_offset, uninit_bytes(dest)).map(|_| ())
+	}
+
+	/// Decrypts the contents of the given file descriptor into the uninitialized dest buffer.
+	///
+	/// Engines can decrypt straight into persistently mapped GPU buffers or other uninitialized memory without zeroing it or an intermediate allocation.
+	/// The dest buffer has no alignment requirement, the bytes are written starting at its first byte and the initialized dest buffer is returned.
+	/// File contents start at a multiple of 16 bytes from the start of the PAKS file.
+	///
+	/// See [`read_data_into`](Self::read_data_into) for more information.
+	#[inline]
+	pub fn read_data_uninit<'a>(&self, desc: &Descriptor, key: &Key, byte_offset: usize, dest: &'a mut [mem::MaybeUninit<u8>]) -> io::Result<&'a mut [u8]> {
+		let decrypted = if desc.is_plaintext() { 0 } else { dest.len() };
+		let result = read_data_into(&self.file, self.base, desc, key, byte_offset, dest);
+		self.report_data(desc, decrypted, result)
 	}
 }
@@ -51,3 +51,1300 @@ fn test_corrupt1() {
 	// Corruption!
 	assert_eq!(example_text, ALPHABET);
 }
+
+#[test]
+fn test_safe_join() {
+	let base = Path::new("base");
+
+	assert_eq!(safe_join(base, b"a/b\\c").unwrap(), base.join("a").join("b").join("c"));
+
+	for path in [&b"../etc/passwd"[..], b"/etc/passwd", b"\\etc", b"a/./b", b"a/../../b", b"C:/Windows", b"a/b:stream", b"", b"a\0b"] {
+		let err = safe_join(base, path).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?}", path);
+	}
+}
+
+#[test]
+fn test_read_section_into() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [3, 4];
+
+	temp_file!("read_section_into");
+
+	let mut edit = FileEditor::create_new("read_section_into", key).unwrap();
+	edit.create_file(b"a", &ALPHABET[..20], key).unwrap();
+	edit.create_file(b"b", &ALPHABET[..40], key).unwrap();
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("read_section_into", key).unwrap();
+	let mut blocks = Vec::new();
+	for (path, len) in [(&b"a"[..], 20), (&b"b"[..], 40)] {
+		let desc = reader.find_file(path).unwrap();
+		blocks.resize(desc.section.size as usize, Block::default());
+		reader.read_section_into(&desc.section, key, &mut blocks).unwrap();
+		assert_eq!(&dataview::bytes(blocks.as_slice())[..len], &ALPHABET[..len]);
+	}
+
+	// The buffer must match the section size
+	let desc = reader.find_file(b"b").unwrap();
+	let err = reader.read_section_into(&desc.section, key, &mut [Block::default(); 1]).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_read_data_into() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [5, 6];
+
+	temp_file!("read_data_into");
+
+	// Large enough to span multiple chunks
+	let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+	let mut edit = FileEditor::create_new("read_data_into", key).unwrap();
+	edit.create_file(b"large", &data, key).unwrap();
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("read_data_into", key).unwrap();
+	let desc = reader.find_file(b"large").unwrap();
+	for (offset, len) in [(0, 100_000), (3, 10), (65_530, 20), (99_999, 1)] {
+		let mut dest = vec![0u8; len];
+		reader.read_data_into(desc, key, offset, &mut dest).unwrap();
+		assert_eq!(dest, &data[offset..offset + len]);
+	}
+
+	// Decrypt into uninitialized memory
+	let mut dest = vec![mem::MaybeUninit::uninit(); 100_000];
+	assert_eq!(reader.read_data_uninit(desc, key, 0, &mut dest).unwrap(), &data[..]);
+	let mut dest = [mem::MaybeUninit::uninit(); 20];
+	assert_eq!(reader.read_data_uninit(desc, key, 65_530, &mut dest).unwrap(), &data[65_530..65_550]);
+
+	// Out of bounds
+	let err = reader.read_data_into(desc, key, 100_000, &mut [0u8; 16]).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+	// Wrong key zeroes the destination
+	let mut dest = [0xffu8; 16];
+	let err = reader.read_data_into(desc, &[7, 8], 0, &mut dest).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+	assert_eq!(dest, [0u8; 16]);
+}
+
+#[test]
+fn test_read_data_uninit() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [55, 56];
+	let base: Vec<u8> = ALPHABET.iter().cycle().take(2000).copied().collect();
+	let mut changed = base.clone();
+	changed[1000..1007].copy_from_slice(b"CHANGED");
+
+	temp_file!("read_data_uninit");
+
+	let mut edit = FileEditor::create_new("read_data_uninit", key).unwrap();
+	edit.set_inline_threshold(Descriptor::INLINE_LEN);
+	edit.create_file(b"inline", &ALPHABET[..20], key).unwrap();
+	edit.create_file(b"encrypted", &base[..100], key).unwrap();
+	let mut edit_file = edit.edit_file(b"plaintext").unwrap();
+	edit_file.set_content(1, 40).set_plaintext(true);
+	edit_file.allocate_data().write_data(&ALPHABET[..40], key).unwrap();
+	edit.create_file(b"delta", &base, key).unwrap();
+	edit.create_file_with(b"delta", &changed, CreateMode::Delta, key).unwrap();
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("read_data_uninit", key).unwrap();
+	let read = |path: &[u8], byte_offset: usize, len: usize| {
+		let mut dest = vec![mem::MaybeUninit::new(0xaa); len];
+		let data = reader.read_data_uninit(reader.find_file(path).unwrap(), key, byte_offset, &mut dest).unwrap();
+		assert_eq!(data.len(), len);
+		data.to_vec()
+	};
+	assert!(reader.find_file(b"inline").unwrap().is_inline());
+	assert_eq!(read(b"inline", 3, 10), &ALPHABET[3..13]);
+	assert!(reader.find_file(b"plaintext").unwrap().is_plaintext());
+	assert_eq!(read(b"plaintext", 5, 30), &ALPHABET[5..35]);
+	assert_eq!(read(b"encrypted", 17, 60), &base[17..77]);
+	assert!(reader.find_file(b"delta").unwrap().is_delta());
+	assert_eq!(read(b"delta", 990, 20), &changed[990..1010]);
+	assert_eq!(read(b"encrypted", 100, 0), b"");
+
+	// Failing the authentication zeroes the dest buffer
+	let bytes = fs::read("read_data_uninit").unwrap();
+	for path in [&b"encrypted"[..], b"delta"] {
+		let desc = *reader.find_file(path).unwrap();
+		let mut tampered = bytes.clone();
+		tampered[(desc.section.offset as usize + 1) * BLOCK_SIZE] ^= 1;
+		fs::write("read_data_uninit", &tampered).unwrap();
+		let reader = FileReader::open("read_data_uninit", key).unwrap();
+		let mut dest = [mem::MaybeUninit::new(0xaa); 20];
+		assert_eq!(reader.read_data_uninit(&desc, key, 10, &mut dest).unwrap_err().kind(), io::ErrorKind::InvalidData);
+		// Every byte of dest was initialized before reading
+		assert!(dest.iter().all(|byte| unsafe { byte.assume_init() } == 0));
+	}
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [57, 58];
+
+	temp_file!("json");
+
+	#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+	struct Config {
+		title: String,
+		size: [u32; 2],
+	}
+	let config = Config { title: "PAKS".to_string(), size: [640, 480] };
+
+	let mut edit = FileEditor::create_new("json", key).unwrap();
+	assert_eq!(edit.create_json(b"config.json", &config, key).unwrap().content_type, Descriptor::CONTENT_TYPE_JSON);
+	assert_eq!(edit.read_json::<Config>(b"config.json", key).unwrap(), config);
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("json", key).unwrap();
+	assert_eq!(reader.read_json::<Config>(b"config.json", key).unwrap(), config);
+	assert_eq!(reader.read_json::<Config>(b"missing.json", key).unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn test_toml() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [59, 60];
+
+	temp_file!("toml");
+
+	#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+	struct Config {
+		title: String,
+		size: [u32; 2],
+	}
+	let config = Config { title: "PAKS".to_string(), size: [640, 480] };
+
+	let mut edit = FileEditor::create_new("toml", key).unwrap();
+	edit.create_file(b"config.toml", b"title = \"PAKS\"\nsize = [640, 480]\n", key).unwrap();
+	edit.create_file(b"invalid.toml", b"title = ", key).unwrap();
+	assert_eq!(edit.read_toml::<Config>(b"config.toml", key).unwrap(), config);
+	assert_eq!(edit.create_toml(b"created.toml", &config, key).unwrap().content_type, Descriptor::CONTENT_TYPE_TOML);
+	assert_eq!(edit.read_toml::<Config>(b"created.toml", key).unwrap(), config);
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("toml", key).unwrap();
+	assert_eq!(reader.read_toml::<Config>(b"config.toml", key).unwrap(), config);
+	assert_eq!(reader.read_toml::<Config>(b"created.toml", key).unwrap(), config);
+	assert_eq!(reader.read_toml::<Config>(b"invalid.toml", key).unwrap_err().kind(), io::ErrorKind::InvalidData);
+	assert_eq!(reader.read_toml::<Config>(b"missing.toml", key).unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn test_corrupt_ranges() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [9, 10];
+
+	temp_file!("corrupt_ranges");
+
+	let mut edit = FileEditor::create_new("corrupt_ranges", key).unwrap();
+	edit.create_file(b"a", &ALPHABET[..20], key).unwrap();
+	edit.create_file(b"b", &ALPHABET[..40], key).unwrap();
+	edit.finish(key).unwrap();
+
+	// The high mark starts right after the directory at the end of the PAKS file
+	let file_len = fs::metadata("corrupt_ranges").unwrap().len();
+	let edit = FileEditor::open("corrupt_ranges", key).unwrap();
+	assert_eq!(edit.high_mark() as u64 * BLOCK_SIZE as u64, file_len);
+	drop(edit);
+
+	// Plaintext content size larger than its section is rejected before allocating
+	let reader = FileReader::open("corrupt_ranges", key).unwrap();
+	let mut desc = *reader.find_file(b"a").unwrap();
+	desc.flags |= Descriptor::FLAG_PLAINTEXT;
+	desc.content_size = u32::MAX;
+	assert_eq!(reader.read_data(&desc, key).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+	// Byte ranges which overflow
+	let desc = reader.find_file(b"b").unwrap();
+	let err = reader.read_data_into(desc, key, usize::MAX, &mut [0u8; 4]).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+	// Rewrite the header with a directory which does not fit in the PAKS file
+	let bytes = fs::read("corrupt_ranges").unwrap();
+	for (offset, size) in [(Header::BLOCKS_LEN as u32, u32::MAX), (u32::MAX, 1), (Header::BLOCKS_LEN as u32, 1000)] {
+		let mut header: Header = dataview::DataView::from(&bytes[..]).read(0);
+		assert_eq!(crypt::decrypt_header(&mut header, key), Ok(()));
+		header.info.directory.offset = offset;
+		header.info.directory.size = size;
+		crypt::encrypt_header(&mut header, key, true).unwrap();
+		let mut corrupt = bytes.clone();
+		corrupt[..mem::size_of::<Header>()].copy_from_slice(dataview::bytes(&header));
+		fs::write("corrupt_ranges", &corrupt).unwrap();
+		assert_eq!(FileReader::open("corrupt_ranges", key).err().map(|err| err.kind()), Some(io::ErrorKind::InvalidData));
+		assert_eq!(FileEditor::open("corrupt_ranges", key).err().map(|err| err.kind()), Some(io::ErrorKind::InvalidData));
+	}
+}
+
+#[test]
+fn test_open_salvage() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [11, 12];
+
+	temp_file!("open_salvage");
+
+	let mut edit = FileEditor::create_new("open_salvage", key).unwrap();
+	edit.create_file(b"a", &ALPHABET[..20], key).unwrap();
+	edit.finish(key).unwrap();
+	let mut edit = FileEditor::open("open_salvage", key).unwrap();
+	edit.create_file(b"dir/b", &ALPHABET[..40], key).unwrap();
+	edit.finish(key).unwrap();
+	let mut edit = FileEditor::open("open_salvage", key).unwrap();
+	edit.create_file(b"c", &ALPHABET[..10], key).unwrap();
+	edit.finish(key).unwrap();
+
+	// An intact PAKS file opens the current directory
+	let salvage = FileReader::open_salvage("open_salvage", key).unwrap();
+	assert_eq!(salvage.generation, 0);
+	assert!(salvage.lost.is_empty());
+	assert!(salvage.reader.find_file(b"c").is_some());
+	assert_eq!(FileReader::open_salvage("open_salvage", &[11, 13]).err().map(|err| err.kind()), Some(io::ErrorKind::PermissionDenied));
+
+	// Corrupt the current directory which comes right before the copy of the header at the end
+	let bytes = fs::read("open_salvage").unwrap();
+	let mut corrupt = bytes.clone();
+	corrupt[bytes.len() - Header::BLOCKS_LEN * BLOCK_SIZE - 1] ^= 1;
+	fs::write("open_salvage", &corrupt).unwrap();
+	assert_eq!(FileReader::open("open_salvage", key).err().map(|err| err.kind()), Some(io::ErrorKind::InvalidData));
+
+	let salvage = FileReader::open_salvage("open_salvage", key).unwrap();
+	assert_eq!(salvage.generation, 1);
+	assert!(salvage.lost.is_empty());
+	assert!(salvage.reader.find_file(b"c").is_none());
+	assert_eq!(salvage.reader.read(b"dir/b", key).unwrap(), &ALPHABET[..40]);
+
+	// Corrupt the header and the contents of a file
+	let desc = *salvage.reader.find_file(b"dir/b").unwrap();
+	corrupt[..BLOCK_SIZE].fill(0);
+	corrupt[desc.section.offset as usize * BLOCK_SIZE] ^= 1;
+	fs::write("open_salvage", &corrupt).unwrap();
+
+	let salvage = FileReader::open_salvage("open_salvage", key).unwrap();
+	assert_eq!(salvage.generation, 1);
+	assert_eq!(salvage.lost, [b"dir/b".to_vec()]);
+	assert!(salvage.reader.find_file(b"dir/b").is_none());
+	assert_eq!(salvage.reader.read(b"a", key).unwrap(), &ALPHABET[..20]);
+}
+
+#[test]
+fn test_finish_digest() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [15, 16];
+
+	temp_file!("finish_digest");
+
+	let mut edit = FileEditor::create_new("finish_digest", key).unwrap();
+	edit.generate_archive_id().unwrap();
+	edit.create_file(b"a", &ALPHABET[..20], key).unwrap();
+	edit.finish(key).unwrap();
+	let digest = FileReader::open("finish_digest", key).unwrap().digest();
+
+	// Finishing without changes bumps the generation but keeps the digest
+	FileEditor::open("finish_digest", key).unwrap().finish(key).unwrap();
+	let reader = FileReader::open("finish_digest", key).unwrap();
+	assert_eq!(reader.archive_generation(), Some(2));
+	assert_eq!(reader.digest(), digest);
+
+	// Changing the file contents does change the digest
+	let mut edit = FileEditor::open("finish_digest", key).unwrap();
+	edit.create_file(b"b", &ALPHABET[..10], key).unwrap();
+	edit.finish(key).unwrap();
+	assert_ne!(FileReader::open("finish_digest", key).unwrap().digest(), digest);
+}
+
+#[test]
+fn test_open_generation() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [13, 14];
+
+	temp_file!("open_generation");
+
+	let mut edit = FileEditor::create_new("open_generation", key).unwrap();
+	edit.create_file(b"a", &ALPHABET[..20], key).unwrap();
+	edit.finish(key).unwrap();
+	let mut edit = FileEditor::open("open_generation", key).unwrap();
+	edit.create_file(b"b", &ALPHABET[..40], key).unwrap();
+	edit.finish(key).unwrap();
+	let mut edit = FileEditor::open("open_generation", key).unwrap();
+	edit.remove(b"a");
+	edit.finish(key).unwrap();
+
+	let generations = FileReader::generations("open_generation", key).unwrap();
+	assert_eq!(generations.len(), 3);
+	assert_eq!(generations[0], *FileReader::open("open_generation", key).unwrap().info());
+
+	// The removed file is still available in previous generations
+	let reader = FileReader::open_generation("open_generation", key, 0).unwrap();
+	assert!(reader.find_file(b"a").is_none());
+	let reader = FileReader::open_generation("open_generation", key, 1).unwrap();
+	assert_eq!(reader.read(b"a", key).unwrap(), &ALPHABET[..20]);
+	assert_eq!(reader.read(b"b", key).unwrap(), &ALPHABET[..40]);
+	let reader = FileReader::open_generation("open_generation", key, 2).unwrap();
+	assert!(reader.find_file(b"b").is_none());
+
+	assert_eq!(FileReader::open_generation("open_generation", key, 3).err().map(|err| err.kind()), Some(io::ErrorKind::NotFound));
+	assert_eq!(FileReader::open_generation("open_generation", &[13, 15], 1).err().map(|err| err.kind()), Some(io::ErrorKind::PermissionDenied));
+}
+
+#[test]
+fn test_lock() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [15, 16];
+
+	temp_file!("lock");
+
+	let edit = FileEditor::create_new("lock", key).unwrap();
+	assert_eq!(FileEditor::try_open("lock", key).err().map(|err| err.kind()), Some(io::ErrorKind::ResourceBusy));
+	edit.finish(key).unwrap();
+
+	// Readers are not affected by the lock
+	let mut edit = FileEditor::try_open("lock", key).unwrap();
+	assert!(FileReader::open("lock", key).is_ok());
+	assert_eq!(FileEditor::try_open("lock", key).err().map(|err| err.kind()), Some(io::ErrorKind::ResourceBusy));
+
+	// A waiting editor sees the changes of the previous editor
+	let waiting = std::thread::spawn(move || {
+		let edit = FileEditor::open("lock", &[15, 16]).unwrap();
+		edit.find_file(b"a").is_some()
+	});
+	std::thread::sleep(std::time::Duration::from_millis(50));
+	edit.create_file(b"a", &ALPHABET[..20], key).unwrap();
+	edit.finish(key).unwrap();
+	assert!(waiting.join().unwrap());
+}
+
+#[test]
+fn test_torn_header() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [17, 18];
+
+	temp_file!("torn_header");
+
+	let mut edit = FileEditor::create_new("torn_header", key).unwrap();
+	edit.create_file(b"a", &ALPHABET[..20], key).unwrap();
+	edit.finish(key).unwrap();
+	let old = fs::read("torn_header").unwrap();
+
+	let mut edit = FileEditor::open("torn_header", key).unwrap();
+	edit.create_file(b"b", &ALPHABET[..40], key).unwrap();
+	edit.finish(key).unwrap();
+	let new = fs::read("torn_header").unwrap();
+
+	// Header partially overwritten, the copy of the new header at the end is used
+	let header_len = mem::size_of::<Header>();
+	let mut torn = new.clone();
+	torn[..header_len / 2].copy_from_slice(&old[..header_len / 2]);
+	fs::write("torn_header", &torn).unwrap();
+	let reader = FileReader::open("torn_header", key).unwrap();
+	assert_eq!(reader.read(b"b", key).unwrap(), &ALPHABET[..40]);
+	assert_eq!(FileReader::open("torn_header", &[17, 19]).err().map(|err| err.kind()), Some(io::ErrorKind::PermissionDenied));
+
+	// Header not yet overwritten, the previous directory is still intact
+	let mut stale = new.clone();
+	stale[..header_len].copy_from_slice(&old[..header_len]);
+	fs::write("torn_header", &stale).unwrap();
+	let reader = FileReader::open("torn_header", key).unwrap();
+	assert!(reader.find_file(b"b").is_none());
+	assert_eq!(reader.read(b"a", key).unwrap(), &ALPHABET[..20]);
+}
+
+#[test]
+fn test_read_while_write() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [19, 20];
+
+	temp_file!("read_while_write");
+
+	FileEditor::create_new("read_while_write", key).unwrap().finish(key).unwrap();
+
+	let writer = std::thread::spawn(|| {
+		let ref key = [19, 20];
+		for i in 0..20 {
+			let mut edit = FileEditor::open("read_while_write", key).unwrap();
+			edit.create_file(format!("file{}", i).as_bytes(), &ALPHABET[..i + 1], key).unwrap();
+			edit.finish(key).unwrap();
+		}
+	});
+
+	// Every reader sees a consistent state of the PAKS file
+	while !writer.is_finished() {
+		let reader = FileReader::open("read_while_write", key).unwrap();
+		for i in 0..20 {
+			if let Some(desc) = reader.find_file(format!("file{}", i).as_bytes()) {
+				assert_eq!(reader.read_data(desc, key).unwrap(), &ALPHABET[..i + 1]);
+			}
+		}
+	}
+	writer.join().unwrap();
+}
+
+#[test]
+fn test_reader_snapshot() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [21, 22];
+
+	temp_file!("reader_snapshot");
+
+	let mut edit = FileEditor::create_new("reader_snapshot", key).unwrap();
+	assert!(edit.reader_snapshot().unwrap().is_empty());
+	edit.create_file(b"old", &ALPHABET[..10], key).unwrap();
+	edit.finish(key).unwrap();
+
+	let mut edit = FileEditor::open("reader_snapshot", key).unwrap();
+	let snapshot = edit.reader_snapshot().unwrap();
+
+	// Changes made by the editor are not visible in the snapshot
+	edit.create_file(b"new", &ALPHABET[..20], key).unwrap();
+	edit.create_file(b"old", &ALPHABET[..5], key).unwrap();
+	assert!(snapshot.find_file(b"new").is_none());
+	assert_eq!(snapshot.read(b"old", key).unwrap(), &ALPHABET[..10]);
+
+	// Not even after the editor is finished
+	edit.finish(key).unwrap();
+	assert!(snapshot.find_file(b"new").is_none());
+	assert_eq!(snapshot.read(b"old", key).unwrap(), &ALPHABET[..10]);
+
+	let reader = FileReader::open("reader_snapshot", key).unwrap();
+	assert_eq!(reader.read(b"new", key).unwrap(), &ALPHABET[..20]);
+	assert_eq!(reader.read(b"old", key).unwrap(), &ALPHABET[..5]);
+}
+
+#[test]
+fn test_write_at() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [21, 23];
+
+	temp_file!("write_at");
+
+	let mut edit = FileEditor::create_new("write_at", key).unwrap();
+	let desc = *edit.create_file(b"a", &ALPHABET[..40], key).unwrap();
+	edit.create_link(b"b", &desc).unwrap();
+	let mut edit_file = edit.edit_file(b"c").unwrap();
+	edit_file.set_content(1, 20).set_plaintext(true);
+	edit_file.allocate_data().write_data(&ALPHABET[..20], key).unwrap();
+	edit.finish(key).unwrap();
+
+	let mut edit = FileEditor::open("write_at", key).unwrap();
+	let snapshot = edit.reader_snapshot().unwrap();
+	let high_mark = edit.high_mark();
+	edit.write_at(b"a", 2, b"XYZ", key).unwrap();
+	edit.write_at(b"c", 0, b"XYZ", key).unwrap();
+
+	// The committed contents are left alone, the patched contents are copied past the high mark
+	assert!(edit.find_file(b"a").unwrap().section.offset >= high_mark);
+	assert!(edit.find_file(b"c").unwrap().section.offset >= high_mark);
+	assert!(edit.find_file(b"c").unwrap().is_plaintext());
+	assert_eq!(snapshot.read(b"a", key).unwrap(), &ALPHABET[..40]);
+	assert_eq!(snapshot.read(b"c", key).unwrap(), &ALPHABET[..20]);
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("write_at", key).unwrap();
+	let mut expected = ALPHABET[..40].to_vec();
+	expected[2..5].copy_from_slice(b"XYZ");
+	assert_eq!(reader.read(b"a", key).unwrap(), expected);
+	assert_eq!(reader.read(b"b", key).unwrap(), &ALPHABET[..40]);
+	assert_eq!(&reader.read(b"c", key).unwrap()[..5], b"XYZde");
+	assert!(reader.fsck(u32::MAX, &mut String::new()));
+}
+
+#[test]
+fn test_to_memory() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [23, 24];
+
+	temp_file!("to_memory");
+
+	let mut edit = FileEditor::create_new("to_memory", key).unwrap();
+	edit.create_file(b"removed", &ALPHABET[..20], key).unwrap();
+	edit.create_file(b"kept", &ALPHABET[..10], key).unwrap();
+	edit.finish(key).unwrap();
+
+	// Copy the PAKS file into memory including changes which are not finished yet
+	let mut edit = FileEditor::open("to_memory", key).unwrap();
+	edit.remove(b"removed");
+	edit.create_file(b"pending", &ALPHABET[..5], key).unwrap();
+	let mut mem = edit.to_memory().unwrap();
+	assert_eq!(mem.read(b"pending", key).unwrap(), &ALPHABET[..5]);
+
+	// Replace the PAKS file while holding on to the lock
+	mem.gc();
+	mem.save_to("to_memory", key).unwrap();
+	drop(edit);
+
+	let reader = FileReader::open("to_memory", key).unwrap();
+	assert!(reader.find_file(b"removed").is_none());
+	assert_eq!(reader.read(b"kept", key).unwrap(), &ALPHABET[..10]);
+	assert_eq!(reader.read(b"pending", key).unwrap(), &ALPHABET[..5]);
+
+	// Errors carry the path in the PAKS archive
+	let err = reader.read(b"removed", key).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::NotFound);
+	assert_eq!(Error::from(err).path(), Some(&b"removed"[..]));
+	assert_eq!(FileReader::generations("to_memory", key).unwrap().len(), 1);
+
+	// The saved PAKS file can be edited again
+	let mut edit = FileEditor::open("to_memory", key).unwrap();
+	edit.create_file(b"more", &ALPHABET[..3], key).unwrap();
+	edit.finish(key).unwrap();
+	assert_eq!(FileReader::generations("to_memory", key).unwrap().len(), 2);
+}
+
+#[test]
+fn test_open_embedded() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [7, 8];
+
+	temp_file!("open_embedded");
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &ALPHABET[..20], key).unwrap();
+	let mut edit_file = edit.edit_file(b"b").unwrap();
+	edit_file.set_content(1, 30).set_plaintext(true);
+	edit_file.allocate_data().write_data(&ALPHABET[..30], key).unwrap();
+	let (blocks, _) = edit.finish(key).unwrap();
+
+	// Append the PAKS file to a stub which is not a multiple of the block size
+	let mut file = b"#!stub executable".to_vec();
+	write_embedded(&mut file, dataview::bytes(blocks.as_slice())).unwrap();
+	fs::write("open_embedded", &file).unwrap();
+
+	assert!(FileReader::open("open_embedded", key).is_err());
+	let reader = FileReader::open_embedded("open_embedded", key).unwrap();
+	assert_eq!(reader.read(b"a", key).unwrap(), &ALPHABET[..20]);
+	assert_eq!(reader.read(b"b", key).unwrap(), &ALPHABET[..30]);
+	let mut dest = [0u8; 5];
+	reader.read_data_into(reader.find_file(b"a").unwrap(), key, 3, &mut dest).unwrap();
+	assert_eq!(dest, ALPHABET[3..8]);
+	let mut dest = [mem::MaybeUninit::uninit(); 5];
+	assert_eq!(reader.read_data_uninit(reader.find_file(b"b").unwrap(), key, 3, &mut dest).unwrap(), &ALPHABET[3..8]);
+
+	// Files without the footer are rejected
+	fs::write("open_embedded", dataview::bytes(blocks.as_slice())).unwrap();
+	assert_eq!(FileReader::open_embedded("open_embedded", key).err().map(|err| err.kind()), Some(io::ErrorKind::InvalidData));
+}
+
+#[test]
+fn test_block_shift() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [9, 10];
+
+	temp_file!("block_shift");
+
+	let mut edit = FileEditor::create_new("block_shift", key).unwrap();
+	edit.set_block_shift(3);
+	edit.create_file(b"a", &ALPHABET[..20], key).unwrap();
+	edit.create_file(b"b", &ALPHABET[..40], key).unwrap();
+	assert_eq!(edit.find_file(b"a").unwrap().section.offset, 8);
+	assert_eq!(edit.find_file(b"b").unwrap().section.offset, 16);
+	assert_eq!(edit.to_memory().unwrap().block_shift(), 3);
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("block_shift", key).unwrap();
+	assert_eq!((reader.info().version, reader.info().block_shift()), (InfoHeader::VERSION_4, 3));
+	assert_eq!(reader.read(b"a", key).unwrap(), &ALPHABET[..20]);
+	assert_eq!(reader.read(b"b", key).unwrap(), &ALPHABET[..40]);
+
+	// Editing the PAKS file again keeps its block size
+	let mut edit = FileEditor::open("block_shift", key).unwrap();
+	assert_eq!(edit.block_shift(), 3);
+	edit.create_file(b"c", &ALPHABET[..20], key).unwrap();
+	assert_eq!(edit.find_file(b"c").unwrap().section.offset % 8, 0);
+	edit.finish(key).unwrap();
+	assert_eq!(FileReader::open("block_shift", key).unwrap().read(b"c", key).unwrap(), &ALPHABET[..20]);
+}
+
+#[test]
+fn test_bundle() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [11, 12];
+
+	temp_file!("bundle");
+
+	let mut edit = FileEditor::create_new("bundle", key).unwrap();
+	edit.create_bundle(&[(b"a", &ALPHABET[..5]), (b"b/c", &ALPHABET[5..20]), (b"d", b"")], key).unwrap();
+	edit.write_at(b"d", 0, b"", key).unwrap();
+	let a = *edit.find_file(b"a").unwrap();
+	let c = *edit.find_file(b"b/c").unwrap();
+	assert!(a.is_bundled() && c.is_bundled());
+	assert_eq!(a.section, c.section);
+	assert_eq!(c.data_offset(), 5);
+	edit.write_at(b"a", 1, b"z", key).unwrap();
+	assert!(!edit.find_file(b"a").unwrap().is_bundled());
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("bundle", key).unwrap();
+	assert_eq!(reader.read(b"a", key).unwrap(), b"azcde");
+	assert_eq!(reader.read(b"b/c", key).unwrap(), &ALPHABET[5..20]);
+	assert_eq!(reader.read(b"d", key).unwrap(), b"");
+	let mut dest = [0; 4];
+	reader.read_data_into(&c, key, 2, &mut dest).unwrap();
+	assert_eq!(dest, ALPHABET[7..11]);
+}
+
+#[test]
+fn test_bundle_reencrypt() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref old_key = [33, 34];
+	let ref key = [35, 36];
+
+	temp_file!("bundle_reencrypt");
+
+	let mut edit = FileEditor::create_new("bundle_reencrypt", old_key).unwrap();
+	edit.create_bundle(&[(b"x", b"AAAA"), (b"y", b"SECRETSECRET")], old_key).unwrap();
+	edit.edit_file(b"x").unwrap().reencrypt_data(old_key, key).unwrap();
+	edit.edit_file(b"y").unwrap().reencrypt_data(old_key, key).unwrap();
+	assert!(!edit.find_file(b"x").unwrap().is_bundled());
+	assert!(!edit.find_file(b"y").unwrap().is_bundled());
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("bundle_reencrypt", key).unwrap();
+	assert_eq!(reader.read(b"x", key).unwrap(), b"AAAA");
+	assert_eq!(reader.read(b"y", key).unwrap(), b"SECRETSECRET");
+}
+
+#[test]
+fn test_bundle_read_range() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [37, 38];
+
+	temp_file!("bundle_read_range");
+
+	let mut edit = FileEditor::create_new("bundle_read_range", key).unwrap();
+	edit.create_bundle(&[(b"x", b"AAAA"), (b"y", b"SECRETSECRET")], key).unwrap();
+	edit.finish(key).unwrap();
+
+	// Reads are bounded by the file contents, not the shared section
+	let reader = FileReader::open("bundle_read_range", key).unwrap();
+	let x = reader.find_file(b"x").unwrap();
+	let mut dest = [0; 16];
+	assert_eq!(reader.read_data_into(x, key, 0, &mut dest).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+	assert_eq!(dest, [0; 16]);
+	let mut dest = [mem::MaybeUninit::uninit(); 4];
+	assert_eq!(reader.read_data_uninit(x, key, 0, &mut dest).unwrap(), b"AAAA");
+	assert!(reader.read_data_uninit(x, key, 2, &mut dest).is_err());
+}
+
+#[test]
+fn test_read_all() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [31, 32];
+
+	temp_file!("read_all");
+
+	let mut edit = FileEditor::create_new("read_all", key).unwrap();
+	edit.set_inline_threshold(8);
+	edit.create_file(b"z/late", &ALPHABET[..30], key).unwrap();
+	edit.create_bundle(&[(b"a", &ALPHABET[..5]), (b"b/c", &ALPHABET[5..20])], key).unwrap();
+	edit.create_file(b"tiny", b"xy", key).unwrap();
+	edit.create_file(b"broken", &ALPHABET[20..40], key).unwrap();
+	let late = *edit.find_file(b"z/late").unwrap();
+	edit.create_link(b"link", &late).unwrap();
+	let broken = *edit.find_file(b"broken").unwrap();
+	edit.finish(key).unwrap();
+
+	// Corrupt the contents of one file
+	let mut bytes = fs::read("read_all").unwrap();
+	bytes[broken.section.offset as usize * BLOCK_SIZE] ^= 1;
+	fs::write("read_all", &bytes).unwrap();
+
+	let reader = FileReader::open("read_all", key).unwrap();
+	let files: Vec<_> = reader.read_all(key).collect();
+	let paths: Vec<&[u8]> = files.iter().map(|(path, _)| path.as_slice()).collect();
+	assert_eq!(paths, [&b"tiny"[..], b"z/late", b"link", b"a", b"b/c", b"broken"]);
+	assert_eq!(files[0].1.as_ref().unwrap(), b"xy");
+	assert_eq!(files[1].1.as_ref().unwrap(), &ALPHABET[..30]);
+	assert_eq!(files[2].1.as_ref().unwrap(), &ALPHABET[..30]);
+	assert_eq!(files[3].1.as_ref().unwrap(), &ALPHABET[..5]);
+	assert_eq!(files[4].1.as_ref().unwrap(), &ALPHABET[5..20]);
+	assert_eq!(files[5].1.as_ref().unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_verify_all_parallel() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [33, 34];
+
+	temp_file!("verify_all_parallel");
+
+	let mut edit = FileEditor::create_new("verify_all_parallel", key).unwrap();
+	for i in 0..20u8 {
+		edit.create_file(format!("files/{}", i).as_bytes(), &ALPHABET[i as usize..], key).unwrap();
+	}
+	edit.create_bundle(&[(b"a", &ALPHABET[..5]), (b"b", &ALPHABET[5..20])], key).unwrap();
+	let mut edit_file = edit.edit_file(b"plain").unwrap();
+	edit_file.set_content(1, 9).set_plaintext(true);
+	edit_file.allocate_data().write_data(b"plaintext", key).unwrap();
+	let bundle = *edit.find_file(b"b").unwrap();
+	edit.create_link(b"link", &bundle).unwrap();
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("verify_all_parallel", key).unwrap();
+	assert_eq!(reader.read(b"plain", key).unwrap(), b"plaintext");
+	for jobs in [0, 1, 3] {
+		let results = reader.verify_all_parallel(key, jobs);
+		assert_eq!(results.len(), 25);
+		assert!(results.iter().all(|(_, result)| result.is_ok()));
+	}
+
+	// Corrupt the bundle shared by three paths
+	let mut bytes = fs::read("verify_all_parallel").unwrap();
+	bytes[bundle.section.offset as usize * BLOCK_SIZE] ^= 1;
+	fs::write("verify_all_parallel", &bytes).unwrap();
+
+	let reader = FileReader::open("verify_all_parallel", key).unwrap();
+	let results = reader.verify_all_parallel(key, 4);
+	let paths: Vec<_> = reader.read_all(key).map(|(path, _)| path).collect();
+	assert_eq!(results.iter().map(|(path, _)| path.clone()).filter(|path| path != ARCHIVE_ID_PATH).collect::<Vec<_>>(), paths);
+	let mut failed: Vec<&[u8]> = results.iter()
+		.filter(|(_, result)| result.as_ref().is_err_and(|err| err.kind() == io::ErrorKind::InvalidData))
+		.map(|(path, _)| path.as_slice())
+		.collect();
+	failed.sort();
+	assert_eq!(failed, [&b"a"[..], b"b", b"link"]);
+}
+
+#[test]
+fn test_shared_reader() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [13, 14];
+
+	temp_file!("shared_reader");
+
+	let mut edit = FileEditor::create_new("shared_reader", key).unwrap();
+	for i in 0..8 {
+		edit.create_file(format!("file{}", i).as_bytes(), &ALPHABET[i..i + 30], key).unwrap();
+	}
+	edit.finish(key).unwrap();
+
+	// Threads sharing the reader never observe each other's reads
+	let reader = FileReader::open("shared_reader", key).unwrap();
+	std::thread::scope(|scope| {
+		for i in 0..8 {
+			let reader = &reader;
+			scope.spawn(move || {
+				let desc = reader.find_file(format!("file{}", i).as_bytes()).unwrap();
+				for _ in 0..100 {
+					assert_eq!(reader.read_data(desc, key).unwrap(), &ALPHABET[i..i + 30]);
+					let mut dest = [0; 10];
+					reader.read_data_into(desc, key, 20, &mut dest).unwrap();
+					assert_eq!(dest, ALPHABET[i + 20..i + 30]);
+				}
+			});
+		}
+	});
+}
+
+#[test]
+fn test_key_slots() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [15, 16];
+	let ref alice = [17, 18];
+	let ref bob = [19, 20];
+
+	temp_file!("key_slots");
+
+	let mut edit = FileEditor::create_new("key_slots", key).unwrap();
+	edit.create_file(b"a", &ALPHABET[..10], key).unwrap();
+	edit.add_key_slot(alice, key).unwrap().add_key_slot(bob, key).unwrap();
+	edit.finish(key).unwrap();
+
+	let key_slots = read_key_slots("key_slots").unwrap();
+	assert_eq!(key_slots.len(), 2);
+	assert_eq!(unlock_key(&key_slots, alice), Some(*key));
+	assert_eq!(unlock_key(&key_slots, bob), Some(*key));
+	assert_eq!(unlock_key(&key_slots, &[21, 22]), None);
+
+	// Editing keeps the key slots intact
+	let mut edit = FileEditor::open("key_slots", key).unwrap();
+	assert_eq!(edit.key_slots().len(), 2);
+	edit.create_file(b"b", &ALPHABET[10..20], key).unwrap();
+	assert!(edit.remove_key_slot(bob));
+	assert!(!edit.remove_key_slot(bob));
+	edit.finish(key).unwrap();
+
+	let key_slots = read_key_slots("key_slots").unwrap();
+	assert_eq!(unlock_key(&key_slots, alice), Some(*key));
+	assert_eq!(unlock_key(&key_slots, bob), None);
+
+	let reader = FileReader::open("key_slots", key).unwrap();
+	assert_eq!(reader.read(b"a", key).unwrap(), &ALPHABET[..10]);
+	assert_eq!(reader.read(b"b", key).unwrap(), &ALPHABET[10..20]);
+
+	// Saving a copy in memory keeps the key slots
+	let mem = FileEditor::open("key_slots", key).unwrap().to_memory().unwrap();
+	mem.save_to("key_slots", key).unwrap();
+	assert_eq!(unlock_key(&read_key_slots("key_slots").unwrap(), alice), Some(*key));
+	assert!(FileReader::open("key_slots", key).is_ok());
+}
+
+#[test]
+fn test_delta_files() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [41, 42];
+	let v1: Vec<u8> = ALPHABET.iter().cycle().take(2000).copied().collect();
+	let mut v2 = v1.clone();
+	v2.splice(1000..1000, *b"inserted");
+
+	temp_file!("delta_files");
+
+	let mut edit = FileEditor::create_new("delta_files", key).unwrap();
+	edit.create_file(b"a", &v1, key).unwrap();
+	edit.finish(key).unwrap();
+	let len = fs::metadata("delta_files").unwrap().len();
+
+	let mut edit = FileEditor::open("delta_files", key).unwrap();
+	assert!(edit.create_file_with(b"a", &v2, CreateMode::Delta, key).unwrap().is_delta());
+	edit.finish(key).unwrap();
+	assert!(fs::metadata("delta_files").unwrap().len() - len < 1000);
+
+	let reader = FileReader::open("delta_files", key).unwrap();
+	assert_eq!(reader.read(b"a", key).unwrap(), v2);
+	let mut dest = [0u8; 8];
+	reader.read_data_into(reader.find_file(b"a").unwrap(), key, 1000, &mut dest).unwrap();
+	assert_eq!(&dest, b"inserted");
+
+	// Salvaging authenticates the base section
+	let salvage = FileReader::open_salvage("delta_files", key).unwrap();
+	assert!(salvage.lost.is_empty());
+}
+
+#[test]
+fn test_patch_files() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [43, 44];
+	let v1: Vec<u8> = ALPHABET.iter().cycle().take(3000).copied().collect();
+	let mut v2 = v1.clone();
+	v2.splice(1500..1500, *b"patched");
+
+	temp_file!("patch_old");
+	temp_file!("patch_new");
+	temp_file!("patch_old.pakspatch");
+
+	let mut edit = FileEditor::create_new("patch_old", key).unwrap();
+	edit.create_file(b"a/big", &v1, key).unwrap();
+	edit.create_file(b"a/same", b"same", key).unwrap();
+	edit.create_file(b"gone/file", b"gone", key).unwrap();
+	edit.create_file(b"kind", b"file", key).unwrap();
+	edit.finish(key).unwrap();
+
+	let mut edit = FileEditor::create_new("patch_new", key).unwrap();
+	edit.create_file(b"a/big", &v2, key).unwrap();
+	edit.create_file(b"a/same", b"same", key).unwrap();
+	edit.create_file(b"kind/file", b"dir", key).unwrap();
+	edit.create_dir(b"empty").unwrap();
+	edit.finish(key).unwrap();
+
+	let patch = patch::create_file("patch_old", "patch_new", "patch_old.pakspatch", key).unwrap();
+	assert_eq!(patch.ops.len(), 6);
+	assert!(matches!(&patch.ops[2], patch::PatchOp::Write(write) if write.delta && write.data.len() < 100));
+	let bytes = fs::read("patch_old.pakspatch").unwrap();
+	assert_eq!(patch::Patch::from_bytes(&bytes, key).unwrap(), patch);
+	assert!(patch::Patch::from_bytes(&bytes, &[1, 2]).is_err());
+
+	// Deltas are not reconstructed past the content size
+	let mut tampered = patch.clone();
+	match &mut tampered.ops[2] {
+		patch::PatchOp::Write(write) => {
+			assert_eq!(write.content_size, v2.len() as u32);
+			write.content_size = 100;
+		},
+		_ => unreachable!(),
+	}
+	let mut edit = FileEditor::open("patch_old", key).unwrap();
+	assert_eq!(tampered.apply(&mut edit, key).unwrap_err().kind(), io::ErrorKind::InvalidData);
+	drop(edit);
+
+	// Applying the patch reproduces the new PAKS archive
+	patch::apply_file("patch_old", "patch_old.pakspatch", key).unwrap();
+	let reader = FileReader::open("patch_old", key).unwrap();
+	assert_eq!(reader.read(b"a/big", key).unwrap(), v2);
+	assert_eq!(reader.read(b"kind/file", key).unwrap(), b"dir");
+	assert!(reader.find_desc(b"empty").unwrap().is_dir());
+	assert!(reader.find_desc(b"gone").is_none());
+
+	// The patch no longer applies, the PAKS archive is left unchanged
+	let before = fs::read("patch_old").unwrap();
+	let err = patch::apply_file("patch_old", "patch_old.pakspatch", key).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+	assert_eq!(fs::read("patch_old").unwrap(), before);
+
+	// Failing to apply the patch rolls back to the previous directory
+	let mut patch = patch::Patch::diff(&reader, &FileReader::open("patch_new", key).unwrap(), key).unwrap();
+	patch.ops.push(patch::PatchOp::Remove(b"missing".to_vec()));
+	fs::write("patch_old.pakspatch", patch.to_bytes(key).unwrap()).unwrap();
+	assert!(patch::apply_file("patch_old", "patch_old.pakspatch", key).is_err());
+	assert_eq!(fs::read("patch_old").unwrap(), before);
+
+	// Patches of PAKS archives with an archive id check the generation and keep the archive id
+	let mut edit = FileEditor::open("patch_old", key).unwrap();
+	let id = edit.generate_archive_id().unwrap();
+	edit.finish(key).unwrap();
+	fs::copy("patch_old", "patch_new").unwrap();
+	let mut edit = FileEditor::open("patch_new", key).unwrap();
+	edit.create_file(b"added", b"added", key).unwrap();
+	edit.finish(key).unwrap();
+	let patch = patch::create_file("patch_old", "patch_new", "patch_old.pakspatch", key).unwrap();
+	assert_eq!((patch.archive_id, patch.generation), (Some(id), 1));
+	assert_eq!(patch.ops.len(), 1);
+	assert_eq!(patch::Patch::from_bytes(&fs::read("patch_old.pakspatch").unwrap(), key).unwrap(), patch);
+
+	patch::apply_file("patch_old", "patch_old.pakspatch", key).unwrap();
+	let reader = FileReader::open("patch_old", key).unwrap();
+	assert_eq!((reader.archive_id(), reader.archive_generation()), (Some(id), Some(2)));
+	let err = patch::apply_file("patch_old", "patch_old.pakspatch", key).unwrap_err();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+	assert_eq!(err.to_string(), "patch is for generation 1 of the PAKS archive, found generation 2");
+}
+
+#[test]
+fn test_patch_flags() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [47, 48];
+
+	temp_file!("patch_flags_old");
+	temp_file!("patch_flags_new");
+	temp_file!("patch_flags_old.pakspatch");
+
+	let mut edit = FileEditor::create_new("patch_flags_old", key).unwrap();
+	edit.create_file(b"a", b"old contents", key).unwrap();
+	edit.create_file(b"b", b"same", key).unwrap();
+	edit.finish(key).unwrap();
+
+	// The new PAKS archive stores the files inline, bundled and with extension records
+	fs::copy("patch_flags_old", "patch_flags_new").unwrap();
+	let mut edit = FileEditor::open("patch_flags_new", key).unwrap();
+	edit.set_inline_threshold(Descriptor::INLINE_LEN);
+	edit.create_file(b"a", b"new contents", key).unwrap();
+	let id = edit.find_id(b"b").unwrap();
+	assert!(edit.resolve_mut(id).unwrap().set_extension(&Mtime(1234)));
+	edit.create_bundle(&[(b"c/x", b"xxx"), (b"c/y", b"yyy")], key).unwrap();
+	edit.finish(key).unwrap();
+
+	// Files which differ only in their extension records are unchanged
+	let patch = patch::create_file("patch_flags_old", "patch_flags_new", "patch_flags_old.pakspatch", key).unwrap();
+	assert!(patch.ops.iter().all(|op| !matches!(op, patch::PatchOp::Write(write) if write.path == b"b")));
+
+	// The storage flags and extension records are not carried
+	patch::apply_file("patch_flags_old", "patch_flags_old.pakspatch", key).unwrap();
+	let reader = FileReader::open("patch_flags_old", key).unwrap();
+	let a = reader.find_file(b"a").unwrap();
+	assert!(!a.is_inline());
+	assert_eq!(reader.read(b"a", key).unwrap(), b"new contents");
+	assert_eq!(reader.find_file(b"b").unwrap().extension::<Mtime>(), None);
+	let (x, y) = (reader.find_file(b"c/x").unwrap(), reader.find_file(b"c/y").unwrap());
+	assert!(!x.is_bundled() && !y.is_bundled());
+	assert_ne!(x.section, y.section);
+	assert_eq!(reader.read(b"c/y", key).unwrap(), b"yyy");
+
+	// The lock is released after applying the patch
+	FileEditor::try_open("patch_flags_old", key).unwrap();
+}
+
+#[test]
+fn test_sync_files() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [45, 46];
+	let big: Vec<u8> = ALPHABET.iter().cycle().take(5000).copied().collect();
+
+	temp_file!("sync_local");
+	temp_file!("sync_remote");
+
+	let mut edit = FileEditor::create_new("sync_remote", key).unwrap();
+	edit.create_file(b"a/big", &big, key).unwrap();
+	edit.create_file(b"a/small", b"small", key).unwrap();
+	edit.create_file(b"b/changed", b"version 1", key).unwrap();
+	edit.finish(key).unwrap();
+	fs::copy("sync_remote", "sync_local").unwrap();
+
+	// The remote PAKS archive is updated, the unchanged sections keep their nonce and MAC
+	let mut edit = FileEditor::open("sync_remote", key).unwrap();
+	edit.create_file(b"b/changed", b"version 2", key).unwrap();
+	edit.create_file(b"c/new", &big[..3000], key).unwrap();
+	edit.create_bundle(&[(b"d/x", b"xxx"), (b"d/y", b"yyy")], key).unwrap();
+	edit.create_file_with(b"a/big", &[&big[..], b"tail"].concat(), CreateMode::Delta, key).unwrap();
+	edit.remove(b"a/small");
+	edit.finish(key).unwrap();
+
+	let remote = FileReader::open("sync_remote", key).unwrap();
+	let manifest = sync::Manifest::create(&remote, key).unwrap();
+	let bytes = manifest.to_bytes(key).unwrap();
+	let manifest = sync::Manifest::from_bytes(&bytes, key).unwrap();
+	assert!(sync::Manifest::from_bytes(&bytes, &[1, 2]).is_err());
+
+	// The base of the delta file is reused, everything else is downloaded
+	let plan = sync::plan(&FileReader::open("sync_local", key).unwrap(), &manifest, key).unwrap();
+	assert_eq!(plan.reuse.len(), 1);
+	assert_eq!(plan.download.len(), 4);
+
+	let remote_bytes = fs::read("sync_remote").unwrap();
+	let mut downloaded = 0;
+	let mut fetch = |offset: u64, buf: &mut [u8]| {
+		buf.copy_from_slice(&remote_bytes[offset as usize..offset as usize + buf.len()]);
+		downloaded += buf.len();
+		Ok(())
+	};
+	sync::sync_file("sync_local", &manifest, key, &mut fetch).unwrap();
+	assert_eq!(downloaded as u64, plan.download_len());
+	assert!(downloaded < big.len());
+
+	let local = FileReader::open("sync_local", key).unwrap();
+	for path in [&b"a/big"[..], b"b/changed", b"c/new", b"d/x", b"d/y"] {
+		assert_eq!(local.read(path, key).unwrap(), remote.read(path, key).unwrap());
+	}
+	assert!(local.find_desc(b"a/small").is_none());
+
+	// A rebuilt PAKS archive with the same contents reuses every section by digest
+	fs::remove_file("sync_local").unwrap();
+	let mut edit = FileEditor::create_new("sync_local", key).unwrap();
+	edit.create_file(b"c/new", &big[..3000], key).unwrap();
+	edit.create_file(b"other", b"version 2", key).unwrap();
+	edit.finish(key).unwrap();
+	let plan = sync::plan(&FileReader::open("sync_local", key).unwrap(), &manifest, key).unwrap();
+	assert_eq!(plan.reuse.len(), 2);
+}
+
+#[test]
+fn test_sync_checks() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [49, 50];
+
+	temp_file!("sync_checks_local");
+	temp_file!("sync_checks_remote");
+
+	let mut edit = FileEditor::create_new("sync_checks_remote", key).unwrap();
+	edit.generate_archive_id().unwrap();
+	edit.create_file(b"a", &ALPHABET[..40], key).unwrap();
+	edit.finish(key).unwrap();
+	let mut edit = FileEditor::create_new("sync_checks_local", key).unwrap();
+	edit.generate_archive_id().unwrap();
+	edit.finish(key).unwrap();
+	let before = fs::read("sync_checks_local").unwrap();
+
+	let remote_bytes = fs::read("sync_checks_remote").unwrap();
+	let mut fetch = |offset: u64, buf: &mut [u8]| {
+		buf.copy_from_slice(&remote_bytes[offset as usize..offset as usize + buf.len()]);
+		Ok(())
+	};
+
+	// The manifest of another PAKS archive is refused
+	let manifest = sync::Manifest::create(&FileReader::open("sync_checks_remote", key).unwrap(), key).unwrap();
+	let err = sync::sync_file("sync_checks_local", &manifest, key, &mut fetch).err().unwrap();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+	assert_eq!(fs::read("sync_checks_local").unwrap(), before);
+
+	// Sections past the end of the address space are malformed instead of overflowing
+	let mut manifest = manifest;
+	manifest.directory = Directory::from(FileReader::open("sync_checks_local", key).unwrap().as_ref().to_vec());
+	manifest.sections[0].section.offset = u32::MAX - 1;
+	let err = sync::sync_file("sync_checks_local", &manifest, key, &mut fetch).err().unwrap();
+	assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+	assert_eq!(fs::read("sync_checks_local").unwrap(), before);
+}
+
+#[test]
+fn test_lazy_reader() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [37, 38];
+
+	temp_file!("lazy_reader");
+
+	let mut edit = FileEditor::create_new("lazy_reader", key).unwrap();
+	edit.create_file(b"a", &ALPHABET[..20], key).unwrap();
+	edit.create_file(b"b/c", &ALPHABET[10..50], key).unwrap();
+	edit.finish(key).unwrap();
+
+	// Counts the bytes read from the source
+	struct Counting<'a>(&'a [u8], std::sync::atomic::AtomicU64);
+	impl BlockSource for Counting<'_> {
+		fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+			self.1.fetch_add(buf.len() as u64, std::sync::atomic::Ordering::Relaxed);
+			self.0.read_exact_at(buf, offset)
+		}
+		fn size(&self) -> io::Result<u64> {
+			self.0.size()
+		}
+	}
+
+	let bytes = fs::read("lazy_reader").unwrap();
+	let reader = LazyReader::new(Counting(&bytes, Default::default()), key).unwrap();
+	let c = reader.find_file(b"b/c").unwrap().section;
+	let loaded = reader.source().1.load(std::sync::atomic::Ordering::Relaxed);
+	assert!(loaded < bytes.len() as u64);
+
+	// Only the section of the file is read
+	assert_eq!(reader.read(b"b/c", key).unwrap(), &ALPHABET[10..50]);
+	assert_eq!(reader.source().1.load(std::sync::atomic::Ordering::Relaxed), loaded + c.size as u64 * BLOCK_SIZE as u64);
+	let mut dest = [0; 5];
+	reader.read_data_into(reader.find_file(b"a").unwrap(), key, 3, &mut dest).unwrap();
+	assert_eq!(dest, ALPHABET[3..8]);
+
+	let reader = LazyReader::new(fs::File::open("lazy_reader").unwrap(), key).unwrap();
+	assert_eq!(reader.read(b"a", key).unwrap(), &ALPHABET[..20]);
+	assert_eq!(reader.read(b"d", key).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+	// Truncated sources fail to read
+	assert!(LazyReader::new(&bytes[..bytes.len() / 2], key).is_err());
+	assert_eq!(LazyReader::new(&bytes[..], &[1, 2]).err().map(|err| err.kind()), Some(io::ErrorKind::PermissionDenied));
+}
+
+#[test]
+fn test_empty_files_and_dirs() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [39, 40];
+
+	temp_file!("empty_files");
+
+	let mut edit = FileEditor::create_new("empty_files", key).unwrap();
+	edit.create_file(b"zero", b"", key).unwrap();
+	edit.create_empty_file(b"a/inline").unwrap();
+	edit.create_dir(b"a/empty").unwrap();
+	edit.create_dir(b"b/c/d").unwrap();
+	edit.finish(key).unwrap();
+
+	// Reopening and editing the PAKS file keeps them
+	let mut edit = FileEditor::open("empty_files", key).unwrap();
+	edit.create_file(b"after", &ALPHABET[..10], key).unwrap();
+	edit.finish(key).unwrap();
+
+	let reader = FileReader::open("empty_files", key).unwrap();
+	assert!(reader.fsck(reader.high_mark(), &mut String::new()));
+	assert_eq!(reader.read(b"zero", key).unwrap(), b"");
+	assert_eq!(reader.read(b"a/inline", key).unwrap(), b"");
+	assert!(reader.find_desc(b"a/empty").unwrap().is_empty_dir());
+	assert!(reader.find_desc(b"b/c/d").unwrap().is_empty_dir());
+	assert!(!reader.find_desc(b"b/c").unwrap().is_empty_dir());
+	assert_eq!(reader.read(b"after", key).unwrap(), &ALPHABET[..10]);
+	assert_eq!(reader.verify_all_parallel(key, 2).iter().filter(|(_, result)| result.is_err()).count(), 0);
+}
+
+#[test]
+fn test_nonce_failure() {
+	if cfg!(miri) {
+		return;
+	}
+
+	struct Unavailable;
+	impl NonceSource for Unavailable {
+		fn nonce(&self) -> Result<Block, ErrorKind> {
+			Err(ErrorKind::Unsupported)
+		}
+	}
+
+	let ref key = [41, 42];
+
+	temp_file!("nonce_failure");
+
+	let mut edit = FileEditor::create_new("nonce_failure", key).unwrap();
+	edit.create_file(b"a", &ALPHABET[..10], key).unwrap();
+	edit.finish(key).unwrap();
+
+	// The PAKS file is left at the previous version
+	let mut edit = FileEditor::open("nonce_failure", key).unwrap();
+	edit.set_nonce_source(std::sync::Arc::new(Unavailable));
+	assert_eq!(edit.create_file(b"b", &ALPHABET[..20], key).unwrap_err().kind(), io::ErrorKind::Unsupported);
+	assert_eq!(edit.write_at(b"a", 0, b"xyz", key).unwrap_err().kind(), io::ErrorKind::Unsupported);
+	assert_eq!(edit.finish(key).unwrap_err().kind(), io::ErrorKind::Unsupported);
+
+	let reader = FileReader::open("nonce_failure", key).unwrap();
+	assert_eq!(reader.read(b"a", key).unwrap(), &ALPHABET[..10]);
+	assert!(reader.find_desc(b"b").is_none());
+}
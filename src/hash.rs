@@ -0,0 +1,191 @@
+/*!
+SHA-256
+=======
+
+<https://nvlpubs.nist.gov/nistpubs/FIPS/NIST.FIPS.180-4.pdf>
+
+Used to fingerprint file contents, it is not part of the PAKS file format.
+*/
+
+use std::fmt;
+
+const K: [u32; 64] = [
+	0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+	0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+	0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+	0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+	0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+	0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+	0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+	0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const H0: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+/// SHA-256 digest.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct Digest(pub [u8; 32]);
+
+impl Digest {
+	/// Parses a digest from its hexadecimal representation.
+	pub fn parse(s: &str) -> Option<Digest> {
+		if s.len() != 64 || !s.is_ascii() {
+			return None;
+		}
+		let mut digest = [0u8; 32];
+		for (i, byte) in digest.iter_mut().enumerate() {
+			*byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+		}
+		Some(Digest(digest))
+	}
+}
+
+impl fmt::Display for Digest {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for byte in &self.0 {
+			write!(f, "{:02x}", byte)?;
+		}
+		Ok(())
+	}
+}
+
+impl fmt::Debug for Digest {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Digest({})", self)
+	}
+}
+
+/// Streaming SHA-256 hasher.
+#[derive(Clone, Debug)]
+pub struct Sha256 {
+	state: [u32; 8],
+	buffer: [u8; 64],
+	buffer_len: usize,
+	total_len: u64,
+}
+
+impl Default for Sha256 {
+	#[inline]
+	fn default() -> Sha256 {
+		Sha256::new()
+	}
+}
+
+impl Sha256 {
+	/// Creates a new hasher.
+	#[inline]
+	pub const fn new() -> Sha256 {
+		Sha256 { state: H0, buffer: [0; 64], buffer_len: 0, total_len: 0 }
+	}
+
+	/// Hashes more data.
+	pub fn update(&mut self, mut data: &[u8]) {
+		self.total_len = self.total_len.wrapping_add(data.len() as u64);
+
+		// Complete a partially filled buffer
+		if self.buffer_len > 0 {
+			let n = usize::min(64 - self.buffer_len, data.len());
+			self.buffer[self.buffer_len..self.buffer_len + n].copy_from_slice(&data[..n]);
+			self.buffer_len += n;
+			data = &data[n..];
+			if self.buffer_len < 64 {
+				return;
+			}
+			let block = self.buffer;
+			compress(&mut self.state, &block);
+			self.buffer_len = 0;
+		}
+
+		// Process whole blocks directly from the input
+		let mut chunks = data.chunks_exact(64);
+		for block in &mut chunks {
+			compress(&mut self.state, block.try_into().unwrap());
+		}
+
+		// Keep the remainder for later
+		let rest = chunks.remainder();
+		self.buffer[..rest.len()].copy_from_slice(rest);
+		self.buffer_len = rest.len();
+	}
+
+	/// Finishes the hash and returns the digest.
+	pub fn finish(mut self) -> Digest {
+		let bit_len = self.total_len.wrapping_mul(8);
+
+		// Append the padding and the message length
+		let mut padding = [0u8; 72];
+		padding[0] = 0x80;
+		let pad_len = if self.buffer_len < 56 { 56 - self.buffer_len } else { 120 - self.buffer_len };
+		padding[pad_len..pad_len + 8].copy_from_slice(&bit_len.to_be_bytes());
+		let total_len = self.total_len;
+		self.update(&padding[..pad_len + 8]);
+		self.total_len = total_len;
+		debug_assert_eq!(self.buffer_len, 0);
+
+		let mut digest = [0u8; 32];
+		for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state.iter()) {
+			chunk.copy_from_slice(&word.to_be_bytes());
+		}
+		Digest(digest)
+	}
+}
+
+/// Computes the SHA-256 digest of the data.
+#[inline]
+pub fn sha256(data: &[u8]) -> Digest {
+	let mut hasher = Sha256::new();
+	hasher.update(data);
+	hasher.finish()
+}
+
+fn compress(state: &mut [u32; 8], block: &[u8; 64]) {
+	let mut w = [0u32; 64];
+	for (i, chunk) in block.chunks_exact(4).enumerate() {
+		w[i] = u32::from_be_bytes(chunk.try_into().unwrap());
+	}
+	for i in 16..64 {
+		let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+		let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+		w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+	}
+
+	let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+	for i in 0..64 {
+		let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+		let ch = (e & f) ^ (!e & g);
+		let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+		let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+		let maj = (a & b) ^ (a & c) ^ (b & c);
+		let t2 = s0.wrapping_add(maj);
+		h = g;
+		g = f;
+		f = e;
+		e = d.wrapping_add(t1);
+		d = c;
+		c = b;
+		b = a;
+		a = t1.wrapping_add(t2);
+	}
+
+	for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+		*s = s.wrapping_add(v);
+	}
+}
+
+#[test]
+fn test_vectors() {
+	assert_eq!(sha256(b"").to_string(), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+	assert_eq!(sha256(b"abc").to_string(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+	assert_eq!(
+		sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq").to_string(),
+		"248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+
+	// Streaming in odd sized chunks must match the one-shot digest
+	let data = [0x5au8; 1000];
+	let mut hasher = Sha256::new();
+	for chunk in data.chunks(7) {
+		hasher.update(chunk);
+	}
+	assert_eq!(hasher.finish(), sha256(&data));
+	assert_eq!(Digest::parse(&sha256(&data).to_string()), Some(sha256(&data)));
+}
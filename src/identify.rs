@@ -0,0 +1,62 @@
+use super::*;
+
+/// Result of [`identify`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Identify {
+	/// The bytes start with the PAKS magic signature and a supported file format version.
+	///
+	/// Failing to open the PAKS file means the encryption key is incorrect or the PAKS file is corrupted.
+	Paks {
+		/// The file format version, see [`InfoHeader::is_supported`].
+		version: u32,
+	},
+	/// The bytes start with the PAKS magic signature but the file format version is not supported by this library.
+	Unsupported {
+		/// The file format version.
+		version: u32,
+	},
+	/// The bytes do not start with the PAKS magic signature.
+	///
+	/// The bytes are either not a PAKS file or a PAKS file written in stealth mode.
+	/// A stealth PAKS file is indistinguishable from random data without the encryption key.
+	Unknown,
+}
+
+/// Identifies the PAKS file format without the encryption key.
+///
+/// Only the first 8 bytes are inspected, see [`Header::MAGIC`].
+pub fn identify(bytes: &[u8]) -> Identify {
+	let signature = match bytes.get(..8) {
+		Some(signature) => signature,
+		None => return Identify::Unknown,
+	};
+	if signature[..4] != Header::MAGIC {
+		return Identify::Unknown;
+	}
+	let version = u32::from_ne_bytes([signature[4], signature[5], signature[6], signature[7]]);
+	if InfoHeader::is_supported(version) {
+		Identify::Paks { version }
+	}
+	else {
+		Identify::Unsupported { version }
+	}
+}
+
+#[test]
+fn test_identify() {
+	let ref key = [1, 2];
+	let (blocks, _) = MemoryEditor::new().finish(key).unwrap();
+	assert_eq!(identify(dataview::bytes(blocks.as_slice())), Identify::Paks { version: InfoHeader::VERSION });
+	assert!(MemoryReader::from_blocks(blocks, key).is_ok());
+
+	let mut edit = MemoryEditor::new();
+	edit.set_magic(false);
+	let (blocks, _) = edit.finish(key).unwrap();
+	assert_eq!(identify(dataview::bytes(blocks.as_slice())), Identify::Unknown);
+	assert!(MemoryReader::from_blocks(blocks.clone(), key).is_ok());
+	assert!(!MemoryEditor::from_blocks(blocks, key).unwrap().has_magic());
+
+	assert_eq!(identify(b"PAKSPAK1"), Identify::Paks { version: InfoHeader::VERSION_1 });
+	assert_eq!(identify(b"PAKSPAK9"), Identify::Unsupported { version: u32::from_ne_bytes(*b"PAK9") });
+	assert_eq!(identify(b"PAKS"), Identify::Unknown);
+}
@@ -0,0 +1,203 @@
+/*!
+Key slots.
+
+The key slots are appended after the PAKS file, followed by a trailer block with the number of key slots and a signature.
+PAKS files without key slots have no trailer.
+*/
+
+use super::*;
+
+/// Key slot object.
+///
+/// Wraps the key of the PAKS file with a user key.
+/// Any user key with a key slot unlocks the key of the PAKS file, see [`unlock_key`].
+/// Key slots are added and removed without reencrypting the PAKS file, see [`MemoryEditor::add_key_slot`].
+#[derive(Copy, Clone, Default, Eq, PartialEq)]
+#[repr(C)]
+pub struct KeySlot {
+	/// Cryptographic nonce used to wrap the key.
+	pub nonce: Block,
+	/// Cryptographic MAC used to authenticate the wrapped key.
+	pub mac: Block,
+	/// The wrapped key.
+	pub wrapped: Block,
+}
+
+unsafe impl Pod for KeySlot {}
+
+impl fmt::Debug for KeySlot {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// Never print the wrapped key
+		f.debug_struct("KeySlot").finish_non_exhaustive()
+	}
+}
+
+impl KeySlot {
+	/// Size of the key slot in blocks.
+	pub const BLOCKS_LEN: usize = mem::size_of::<KeySlot>() / BLOCK_SIZE;
+
+	/// Wraps the key with the user key.
+	///
+	/// # Errors
+	///
+	/// Fails with the error of the random number generator of the operating system.
+	pub fn wrap(key: &Key, user_key: &Key) -> Result<KeySlot, Error> {
+		Ok(KeySlot::wrap_from(None, key, user_key)?)
+	}
+
+	pub(crate) fn wrap_from(nonces: Option<&dyn NonceSource>, key: &Key, user_key: &Key) -> Result<KeySlot, ErrorKind> {
+		let mut section = Section::default();
+		let mut blocks = [*key];
+		crypt::encrypt_section_from(nonces, &mut blocks, &mut section, user_key)?;
+		Ok(KeySlot { nonce: section.nonce, mac: section.mac, wrapped: blocks[0] })
+	}
+
+	/// Unwraps the key with the user key.
+	///
+	/// Returns `None` if the key slot does not belong to the user key.
+	pub fn unwrap(&self, user_key: &Key) -> Option<Key> {
+		let section = Section { offset: 0, size: 1, nonce: self.nonce, mac: self.mac };
+		let mut blocks = [self.wrapped];
+		if !crypt::decrypt_section(&mut blocks, &section, user_key) {
+			return None;
+		}
+		Some(blocks[0])
+	}
+}
+
+/// Unlocks the key of the PAKS file with the user key.
+///
+/// Returns `None` if none of the key slots belong to the user key.
+pub fn unlock_key(key_slots: &[KeySlot], user_key: &Key) -> Option<Key> {
+	key_slots.iter().find_map(|slot| slot.unwrap(user_key))
+}
+
+/// Returns the key slots appended after the PAKS file.
+///
+/// The blocks are the entire PAKS file, if there are no key slots the result is empty.
+pub fn key_slots(blocks: &[Block]) -> Vec<KeySlot> {
+	match footer_len(blocks.last().copied(), blocks.len() as u64) {
+		Some(len) => parse_slots(&blocks[blocks.len() - len as usize..]),
+		None => Vec::new(),
+	}
+}
+
+/// Wraps the key of the PAKS file for the [age](https://age-encryption.org) recipients.
+///
+/// Distributes the key with existing key-management tooling instead of a key slot.
+/// The key is encrypted as its hex encoding followed by a newline, the format accepted by [`parse_key`], in the ASCII armored age format.
+/// Any of the recipients decrypts it with `age --decrypt -i <IDENTITY>` or [`import_key_age`].
+///
+/// # Errors
+///
+/// * [`std::io::ErrorKind::InvalidInput`]: There are no recipients or a recipient is not an age X25519 public key, eg. `age1..`.
+#[cfg(feature = "age")]
+pub fn export_key_age(key: &Key, recipients: &[&str]) -> std::io::Result<String> {
+	use std::io::{self, Write};
+
+	let recipients = recipients.iter()
+		.map(|recipient| recipient.trim().parse::<age::x25519::Recipient>())
+		.collect::<Result<Vec<_>, _>>()
+		.map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+	let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn age::Recipient))
+		.map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+	let mut armored = Vec::new();
+	let mut writer = encryptor.wrap_output(age::armor::ArmoredWriter::wrap_output(&mut armored, age::armor::Format::AsciiArmor)?)?;
+	writeln!(writer, "{:016x}{:016x}", key[1], key[0])?;
+	writer.finish()?.finish()?;
+	String::from_utf8(armored).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Unwraps the key exported with [`export_key_age`] with the age X25519 identity, eg. `AGE-SECRET-KEY-1..`.
+///
+/// # Errors
+///
+/// * [`std::io::ErrorKind::InvalidInput`]: The identity is not an age X25519 identity.
+/// * [`std::io::ErrorKind::InvalidData`]: The identity is not a recipient or the contents are not a key.
+#[cfg(feature = "age")]
+pub fn import_key_age(armored: &str, identity: &str) -> std::io::Result<Key> {
+	use std::io;
+
+	let identity = identity.trim().parse::<age::x25519::Identity>().map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+	let plaintext = age::decrypt(&identity, armored.as_bytes()).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+	let text = str::from_utf8(&plaintext).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+	parse_key(text.trim()).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+// The trailer block is the number of key slots followed by this signature
+const SIGNATURE: u64 = u64::from_le_bytes(*b"PAKSLOTS");
+
+// Returns the blocks of the key slots followed by the trailer, empty without key slots
+pub(crate) fn footer(key_slots: &[KeySlot]) -> Vec<Block> {
+	if key_slots.is_empty() {
+		return Vec::new();
+	}
+	let mut blocks = Vec::with_capacity(key_slots.len() * KeySlot::BLOCKS_LEN + 1);
+	for slot in key_slots {
+		blocks.extend_from_slice(&[slot.nonce, slot.mac, slot.wrapped]);
+	}
+	blocks.push([key_slots.len() as u64, SIGNATURE]);
+	blocks
+}
+
+// Returns the length in blocks of the footer given its last block and the length in blocks of the file
+// Returns None if there is no footer or it does not fit in the file
+pub(crate) fn footer_len(trailer: Option<Block>, blocks_len: u64) -> Option<u64> {
+	let [count, signature] = trailer?;
+	if signature != SIGNATURE {
+		return None;
+	}
+	let len = count.checked_mul(KeySlot::BLOCKS_LEN as u64)?.checked_add(1)?;
+	if len > blocks_len {
+		return None;
+	}
+	Some(len)
+}
+
+// Parses the key slots from the blocks of the footer including its trailer
+pub(crate) fn parse_slots(footer: &[Block]) -> Vec<KeySlot> {
+	let slots = &footer[..footer.len().saturating_sub(1)];
+	slots.chunks_exact(KeySlot::BLOCKS_LEN).map(|slot| KeySlot { nonce: slot[0], mac: slot[1], wrapped: slot[2] }).collect()
+}
+
+// Finds the footer at the start of the blocks, it may be followed by trailing unused blocks
+// Returns the length in blocks of the footer including its trailer
+pub(crate) fn find_footer(blocks: &[Block]) -> Option<usize> {
+	let mut count = 0;
+	let mut index = 0;
+	while let Some(&block) = blocks.get(index) {
+		if block == [count, SIGNATURE] {
+			return Some(index + 1);
+		}
+		count += 1;
+		index += KeySlot::BLOCKS_LEN;
+	}
+	None
+}
+
+// Removes the key slots which belong to the user key, returns true if any were removed
+pub(crate) fn remove(key_slots: &mut Vec<KeySlot>, user_key: &Key) -> bool {
+	let len = key_slots.len();
+	key_slots.retain(|slot| slot.unwrap(user_key).is_none());
+	key_slots.len() != len
+}
+
+#[cfg(feature = "age")]
+#[test]
+fn test_export_key_age() {
+	let identity = age::x25519::Identity::generate();
+	let recipient = identity.to_public().to_string();
+	let identity = age::secrecy::ExposeSecret::expose_secret(&identity.to_string()).to_string();
+	let key = [0x0123456789abcdef, 0xfedcba9876543210];
+
+	let armored = export_key_age(&key, &[&recipient]).unwrap();
+	assert!(armored.starts_with("-----BEGIN AGE ENCRYPTED FILE-----"));
+	assert_eq!(import_key_age(&armored, &identity).unwrap(), key);
+
+	let other = age::x25519::Identity::generate();
+	let other = age::secrecy::ExposeSecret::expose_secret(&other.to_string()).to_string();
+	assert_eq!(import_key_age(&armored, &other).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+	assert_eq!(export_key_age(&key, &["age1invalid"]).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+	assert_eq!(export_key_age(&key, &[]).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+}
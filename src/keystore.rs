@@ -0,0 +1,167 @@
+/*!
+Keystore.
+
+Maps the names of PAKS archives to their keys, eg. so tools don't need the key on the command line.
+*/
+
+use std::{fs, io, path::Path};
+use super::*;
+
+/// Keys of PAKS archives by name.
+///
+/// The keystore file is a small subset of TOML, one key per line:
+///
+/// ```toml
+/// # Names may be bare or quoted, the keys are encoded in hex like parse_key
+/// game = "0123456789abcdef0123456789abcdef"
+/// "dlc/expansion.paks" = "fedcba9876543210fedcba9876543210"
+/// ```
+///
+/// See [`find`](Self::find) to look up the key of a PAKS archive by its path.
+#[derive(Clone, Default)]
+pub struct Keystore {
+	keys: Vec<(String, Key)>,
+}
+
+impl fmt::Debug for Keystore {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		// Never print the keys
+		f.debug_list().entries(self.names()).finish()
+	}
+}
+
+impl Keystore {
+	/// Creates a new empty keystore.
+	#[inline]
+	pub fn new() -> Keystore {
+		Keystore { keys: Vec::new() }
+	}
+
+	/// Loads the keystore from a file.
+	pub fn load<P: ?Sized + AsRef<Path>>(path: &P) -> io::Result<Keystore> {
+		let text = fs::read_to_string(path)?;
+		Keystore::parse(&text)
+	}
+
+	/// Parses the keystore.
+	///
+	/// # Errors
+	///
+	/// Returns [`io::ErrorKind::InvalidData`] with a message and line number describing the syntax error, duplicate name or invalid key.
+	pub fn parse(text: &str) -> io::Result<Keystore> {
+		let mut keystore = Keystore::new();
+
+		for (line_nr, line) in text.lines().enumerate() {
+			let line_nr = line_nr + 1;
+			let line = pack::strip_comment(line).trim();
+			if line.is_empty() {
+				continue;
+			}
+
+			// The key never contains `=` but a quoted name might
+			let (name, value) = match line.rsplit_once('=') {
+				Some((name, value)) => (name.trim(), value.trim()),
+				None => return Err(syntax_error(line_nr, "expected `name = \"key\"`")),
+			};
+			let name = if name.starts_with('"') {
+				pack::unquote(name).map_err(|message| syntax_error(line_nr, message))?
+			}
+			else {
+				name.to_string()
+			};
+			if name.is_empty() {
+				return Err(syntax_error(line_nr, "expected a name"));
+			}
+			let key = pack::unquote(value).map_err(|message| syntax_error(line_nr, message))?;
+			let key = match parse_key(&key) {
+				Ok(key) => key,
+				Err(err) => return Err(syntax_error(line_nr, &format!("invalid key: {}", err))),
+			};
+
+			if keystore.get(&name).is_some() {
+				return Err(syntax_error(line_nr, "duplicate name"));
+			}
+			keystore.keys.push((name, key));
+		}
+
+		Ok(keystore)
+	}
+
+	/// Inserts the key under the name, returns the key previously stored under the name.
+	pub fn insert(&mut self, name: &str, key: Key) -> Option<Key> {
+		match self.keys.iter_mut().find(|(other, _)| other == name) {
+			Some((_, old)) => Some(mem::replace(old, key)),
+			None => {
+				self.keys.push((name.to_string(), key));
+				None
+			},
+		}
+	}
+
+	/// Returns the key stored under the name.
+	pub fn get(&self, name: &str) -> Option<Key> {
+		self.keys.iter().find(|(other, _)| other == name).map(|&(_, key)| key)
+	}
+
+	/// Returns the key of the PAKS archive at the path.
+	///
+	/// Looks up the path as given, then its file name and finally its file name without the extension.
+	/// A keystore shared by several directories names the PAKS archives by their file name, eg. `game.paks` or `game`.
+	pub fn find<P: ?Sized + AsRef<Path>>(&self, path: &P) -> Option<Key> {
+		let path = path.as_ref();
+		let file_name = path.file_name().and_then(|name| name.to_str());
+		let file_stem = path.file_stem().and_then(|name| name.to_str());
+		[path.to_str(), file_name, file_stem].into_iter().flatten().find_map(|name| self.get(name))
+	}
+
+	/// Returns the names in the keystore.
+	pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+		self.keys.iter().map(|(name, _)| &**name)
+	}
+
+	/// Returns the number of keys.
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.keys.len()
+	}
+
+	/// Returns if the keystore is empty.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.keys.is_empty()
+	}
+}
+
+fn syntax_error(line_nr: usize, message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, format!("keystore line {}: {}", line_nr, message))
+}
+
+#[test]
+fn test_keystore() {
+	let keystore = Keystore::parse(r#"
+# Keys of the PAKS archives
+game = "0123456789abcdef0123456789abcdef"
+"dlc/expansion.paks" = "2a" # The key of the expansion
+"#).unwrap();
+	assert_eq!(keystore.len(), 2);
+	assert_eq!(keystore.get("game"), Some([0x0123456789abcdef, 0x0123456789abcdef]));
+	assert_eq!(keystore.get("dlc/expansion.paks"), Some([42, 0]));
+	assert_eq!(keystore.get("dlc"), None);
+
+	// Found by the path, file name or file stem
+	assert_eq!(keystore.find("data/game.paks"), keystore.get("game"));
+	assert_eq!(keystore.find("dlc/expansion.paks"), Some([42, 0]));
+	assert_eq!(keystore.find("expansion.paks"), None);
+	assert!(!format!("{:?}", keystore).contains("0123"));
+
+	let mut keystore = keystore;
+	assert_eq!(keystore.insert("game", [1, 2]), Some([0x0123456789abcdef, 0x0123456789abcdef]));
+	assert_eq!(keystore.insert("other", [3, 4]), None);
+	assert_eq!(keystore.names().collect::<Vec<_>>(), ["game", "dlc/expansion.paks", "other"]);
+
+	assert_eq!(Keystore::parse("game").unwrap_err().to_string(), "keystore line 1: expected `name = \"key\"`");
+	assert!(Keystore::parse("game = 42").is_err());
+	assert!(Keystore::parse("game = \"xyz\"").is_err());
+	assert!(Keystore::parse("game = \"1\"\ngame = \"2\"").is_err());
+	assert!(Keystore::parse("\"\" = \"1\"").is_err());
+}
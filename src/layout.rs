@@ -0,0 +1,83 @@
+/*!
+Layout
+======
+
+The on-disk structures of the PAKS file format.
+
+Forensic and recovery tools may use these definitions to walk the raw blocks of a PAKS file without the readers and editors of this crate.
+All structures are plain old data in native endianness.
+
+A PAKS file is an array of [`Block`]s, all offsets and sizes are counted in blocks unless stated otherwise:
+
+* The [`Header`] takes the first [`HEADER_BLOCKS`] blocks, the [`InfoHeader`] in its last blocks is encrypted.
+* The [sections](Section) of the files follow in no particular order, interleaved with garbage left behind by removed files and previous directories.
+  The sections may be aligned to the block size recorded in the header, see [`InfoHeader::block_shift`].
+* The directory is an array of [`Descriptor`]s at the [directory blocks](directory_blocks) referenced by the info header.
+* A copy of the header may follow the directory.
+* The [`KeySlot`]s may be appended at the end, followed by a trailer block with their count and the signature `PAKSLOTS`.
+*/
+
+use std::ops::Range;
+
+pub use super::{Block, Header, InfoHeader, Section, Descriptor, Name, KeySlot};
+
+/// Size of a block in bytes.
+pub const BLOCK_SIZE: usize = super::BLOCK_SIZE;
+
+/// Size of the [`Header`] in blocks.
+pub const HEADER_BLOCKS: usize = Header::BLOCKS_LEN;
+
+/// Size of the [`InfoHeader`] in blocks, the last blocks of the header.
+pub const INFO_HEADER_BLOCKS: usize = InfoHeader::BLOCKS_LEN;
+
+/// Size of a [`Descriptor`] in blocks.
+pub const DESCRIPTOR_BLOCKS: usize = Descriptor::BLOCKS_LEN;
+
+/// Size of a [`KeySlot`] in blocks.
+pub const KEY_SLOT_BLOCKS: usize = KeySlot::BLOCKS_LEN;
+
+/// Returns the byte offset of the block index.
+#[inline]
+pub const fn block_offset(index: u32) -> u64 {
+	index as u64 * BLOCK_SIZE as u64
+}
+
+/// Returns the number of blocks needed to store the bytes.
+#[inline]
+pub const fn blocks_for(byte_size: u32) -> u32 {
+	byte_size.div_ceil(BLOCK_SIZE as u32)
+}
+
+/// Returns the block range of the section.
+///
+/// Returns `None` if the end of the section overflows.
+#[inline]
+pub fn section_blocks(section: &Section) -> Option<Range<u32>> {
+	Some(section.offset..section.offset.checked_add(section.size)?)
+}
+
+/// Returns the block range of the directory referenced by the info header.
+///
+/// The size of the directory section is the number of descriptors, not the number of blocks.
+/// Returns `None` if the end of the directory overflows.
+#[inline]
+pub fn directory_blocks(info: &InfoHeader) -> Option<Range<u32>> {
+	Some(info.directory.offset..info.directory_end()?)
+}
+
+#[test]
+fn test_layout() {
+	assert_eq!(BLOCK_SIZE, 16);
+	assert_eq!((HEADER_BLOCKS, INFO_HEADER_BLOCKS, DESCRIPTOR_BLOCKS, KEY_SLOT_BLOCKS), (5, 3, 8, 3));
+	assert_eq!(block_offset(5), 80);
+	assert_eq!((blocks_for(0), blocks_for(1), blocks_for(16), blocks_for(17)), (0, 1, 1, 2));
+
+	let mut info = InfoHeader::default();
+	info.directory.offset = 10;
+	info.directory.size = 2;
+	assert_eq!(section_blocks(&info.directory), Some(10..12));
+	assert_eq!(directory_blocks(&info), Some(10..26));
+	info.directory.size = u32::MAX;
+	assert_eq!(section_blocks(&info.directory), None);
+	assert_eq!(directory_blocks(&info), None);
+}
@@ -8,6 +8,7 @@ Getting started
 ---------------
 
 PAKS files can be inspected with the standard file IO with [`FileReader`] and [`FileEditor`], or from memory with [`MemoryReader`] and [`MemoryEditor`].
+A [`LazyReader`] keeps only the directory in memory and reads the files on demand from any [`BlockSource`], eg. a memory mapped file or the network.
 
 ### Creating PAKS files
 
@@ -19,10 +20,10 @@ let ref key = paks::Key::default();
 let mut editor = paks::MemoryEditor::new();
 
 // Add content to the PAKS file
-editor.create_file(b"foo/example", include_bytes!("../tests/data/example.txt"), key);
+editor.create_file(b"foo/example", include_bytes!("../tests/data/example.txt"), key).unwrap();
 
 // Finish the PAKS file and write to disk
-let (blocks, _) = editor.finish(key);
+let (blocks, _) = editor.finish(key).unwrap();
 # /* Don't actually write the file while running tests...
 std::fs::write("myfile.paks", paks::as_bytes(&blocks)).unwrap();
 # */
@@ -37,7 +38,7 @@ let ref key = paks::Key::default();
 let mut editor = paks::FileEditor::create_new("myfile.paks", key).unwrap();
 
 // Add content to the PAKS file
-editor.create_file(b"foo/example", include_bytes!("../tests/data/example.txt"), key);
+editor.create_file(b"foo/example", include_bytes!("../tests/data/example.txt"), key).unwrap();
 
 // Finish writing the PAKS file
 editor.finish(key).unwrap();
@@ -65,6 +66,30 @@ let data = reader.read(b"foo/example", key).unwrap();
 // reading the file will fail with an error
 ```
 
+Enable the `json` feature to deserialize and serialize JSON files in one step with `read_json` and `create_json`.
+Enable the `toml` feature to do the same for TOML files with `read_toml` and `create_toml`.
+
+Enable the `test-util` feature in your dev-dependencies to build PAKS files and check their contents in unit tests with the `testing` module.
+
+### Diagnostics
+
+Enable the `tracing` feature to instrument the crate with [tracing](https://docs.rs/tracing) spans.
+Opening, finishing and garbage collecting PAKS files are recorded at the debug level, reading and decrypting sections at the trace level.
+The spans carry the sizes involved, use a subscriber which records span durations to diagnose slow asset loads.
+
+### Thread safety
+
+Every public type is [`Send`] and [`Sync`], this is checked at compile time.
+
+* [`MemoryReader`] and [`FileReader`] only need `&self` to read, share them between threads by reference or with [`Arc`](std::sync::Arc).
+  Cloning a [`MemoryReader`] is cheap, the clones share the PAKS file in memory.
+* [`FileReader`] reads with positioned IO and never moves the cursor of its file handle.
+  A [`LazyReader`] is as thread safe as its [`BlockSource`].
+  On platforms without positioned IO the reads seek and are serialized with a global lock.
+* [`MemoryEditor`] and [`FileEditor`] need `&mut self` to edit, wrap them in a [`Mutex`](std::sync::Mutex) to edit from multiple threads.
+  Multiple [`FileReader`] instances may read a PAKS file while a [`FileEditor`] is editing it, see its consistency guarantees.
+* The [tamper hook](set_tamper_hook) and [metrics hooks](Metrics) may be called from any thread reading a PAKS file.
+
 File Format
 -----------
 
@@ -79,6 +104,12 @@ The [`InfoHeader`] contains a section object referencing the [`Directory`].
 
 The directory encodes a file hierarchy in a light-weight [TLV structure](https://en.wikipedia.org/wiki/Type-length-value).
 The file format expects the directory to come at the end of the PAKS file.
+[`FileEditor`] follows the directory with a copy of the header, previous directories and their copy are left behind when the PAKS file is edited.
+This allows [`FileReader::open_salvage`] to recover a corrupted PAKS file from a previous directory.
+
+The [`layout`] module gathers the on-disk structures and block range helpers for tools which read the raw blocks.
+
+The PAKS file may be followed by [key slots](KeySlot) which wrap its key with user keys, any of these user keys unlocks the key with [`unlock_key`].
 
 The individual files' data are stored in between the header and the directory in no particular order.
 When files are removed their data isn't immediately reclaimed leaving behind gaps.
@@ -89,7 +120,7 @@ These operations are performed on a per-file basis, the whole PAKS file does not
 
 */
 
-use std::{cmp, fmt, mem, num, ops, slice, str};
+use std::{cmp, fmt, mem, num, ops, ptr, slice, str};
 use std::io::ErrorKind;
 
 use dataview::Pod;
@@ -110,25 +141,120 @@ use dataview::Pod;
 // 	};
 // }
 
+// Enters a tracing span until the end of the enclosing block
+#[cfg(feature = "tracing")]
+macro_rules! span {
+	($level:ident, $($args:tt)*) => {
+		let _span = ::tracing::span!(::tracing::Level::$level, $($args)*).entered();
+	};
+}
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+	($level:ident, $($args:tt)*) => {};
+}
+
 mod cipher;
-mod crypt;
+pub mod crypt;
 
 mod dir;
-pub use self::dir::TreeArt;
+pub use self::dir::{TreeArt, PathError, Separators, Portable, validate_path, validate_path_with, path_hash, is_metadata_path, METADATA_PATHS};
 
 mod directory;
 pub use self::directory::*;
 
+mod error;
+pub use self::error::Error;
+
 mod file_io;
 pub use self::file_io::*;
 
 mod memory;
 pub use self::memory::*;
 
+mod extension;
+pub use self::extension::{Extension, Extensions, Mtime};
+
+mod keyslot;
+pub use self::keyslot::{KeySlot, unlock_key, key_slots};
+#[cfg(feature = "age")]
+pub use self::keyslot::{export_key_age, import_key_age};
+
+mod keystore;
+pub use self::keystore::Keystore;
+
+mod metrics;
+pub use self::metrics::{Metrics, Counters};
+
+mod nonce;
+pub use self::nonce::{NonceSource, SystemNonces, SeededNonces};
+
+mod names;
+pub use self::names::NameTable;
+
+mod preload;
+pub use self::preload::PRELOAD_PATH;
+
+mod content_types;
+pub use self::content_types::{ContentTypes, CONTENT_TYPES_PATH};
+
+mod archive_id;
+pub use self::archive_id::{ArchiveId, ARCHIVE_ID_PATH};
+
+mod tamper;
+pub use self::tamper::{Tampered, TamperHook, set_tamper_hook, take_tamper_hook};
+use self::tamper::tampered;
+
+mod migrate;
+pub use self::migrate::migrate;
+
+mod identify;
+pub use self::identify::*;
+
+pub mod delta;
+
+pub mod hash;
+
+pub mod layout;
+
+pub mod pack;
+
+pub mod patch;
+
+pub mod sync;
+
+#[cfg(feature = "test-util")]
+pub mod testing;
+
+// Public types must remain safe to share between threads, see the thread safety notes above
+const _: fn() = || {
+	fn assert_send_sync<T: Send + Sync>() {}
+	assert_send_sync::<MemoryReader>();
+	assert_send_sync::<MemoryEditor>();
+	assert_send_sync::<MemoryEditFile>();
+	assert_send_sync::<FileReader>();
+	assert_send_sync::<FileEditor>();
+	assert_send_sync::<FileEditFile>();
+	assert_send_sync::<LazyReader<std::fs::File>>();
+	assert_send_sync::<Salvage>();
+	assert_send_sync::<Directory>();
+	assert_send_sync::<Descriptor>();
+	assert_send_sync::<Error>();
+	assert_send_sync::<NameTable>();
+	assert_send_sync::<Counters>();
+	assert_send_sync::<SeededNonces>();
+	assert_send_sync::<KeySlot>();
+	assert_send_sync::<TamperHook>();
+	assert_send_sync::<pack::Transforms>();
+	assert_send_sync::<pack::PackCache>();
+	assert_send_sync::<pack::Manifest>();
+	assert_send_sync::<hash::Sha256>();
+};
+
 /// Block primitive.
 ///
 /// A block is the smallest addressable unit of which the PAKS file is made.
 /// It defines the size and alignment of the underlying storage.
+/// The block is the block of the cipher, the sections of a PAKS file may be aligned to a larger block size, see [`InfoHeader::block_shift`].
 pub type Block = [u64; 2];
 
 /// Key type.
@@ -136,11 +262,68 @@ pub type Block = [u64; 2];
 /// All PAKS files are encrypted with the Speck128/128 cipher.
 pub type Key = [u64; 2];
 
+/// Name key type.
+///
+/// Hides the names of the descriptors from anyone holding only the key of the PAKS file, see [`Descriptor::FLAG_ENCRYPTED_NAME`].
+pub type NameKey = [u64; 2];
+
+/// How to create a file at a path which already exists.
+///
+/// See [`MemoryEditor::create_file_with`] and [`FileEditor::create_file_with`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum CreateMode {
+	/// Fails with [`ErrorKind::AlreadyExists`] if anything exists at the path.
+	///
+	/// Catches accidental path collisions, eg. when packing multiple source folders into one PAKS file.
+	CreateNew,
+	/// Replaces any existing file, like [`MemoryEditor::create_file`].
+	#[default]
+	Overwrite,
+	/// Appends to any existing file, like [`MemoryEditor::append`].
+	Append,
+	/// Replaces any existing file with a delta against its previous contents, see [`Descriptor::FLAG_DELTA`].
+	///
+	/// The file is replaced like [`Overwrite`](Self::Overwrite) if the delta does not save any blocks.
+	Delta,
+}
+
 /// Parses a hexadecimal string into a Key.
 pub fn parse_key(s: &str) -> Result<Key, num::ParseIntError> {
 	u128::from_str_radix(s, 16).map(|val| [(val & 0xffffffffffffffff) as u64, (val >> 64) as u64])
 }
 
+/// Includes a PAKS file in the executable as `&'static [Block]`.
+///
+/// The path is resolved like [`include_bytes!`], read the PAKS file without copying it with [`MemoryReader::from_static`].
+/// The expression may initialize a `static` or `const` item.
+///
+/// ```
+/// let ref key = paks::Key::default();
+/// # /* The PAKS file doesn't exist...
+/// static ASSETS: &[paks::Block] = paks::include_paks!("assets.paks");
+/// let reader = paks::MemoryReader::from_static(ASSETS, key).unwrap();
+/// # */
+/// ```
+#[macro_export]
+macro_rules! include_paks {
+	($path:expr) => {{
+		#[repr(C)]
+		struct Aligned<T: ?Sized> {
+			_align: [$crate::Block; 0],
+			bytes: T,
+		}
+		const ALIGNED: &Aligned<[u8]> = &Aligned { _align: [], bytes: *include_bytes!($path) };
+		unsafe { $crate::__static_blocks(&ALIGNED.bytes) }
+	}};
+}
+
+// The bytes must be aligned to the block, the include_paks macro takes care of that
+#[doc(hidden)]
+pub const unsafe fn __static_blocks(bytes: &'static [u8]) -> &'static [Block] {
+	assert!(bytes.len().is_multiple_of(BLOCK_SIZE), "PAKS file length is not a multiple of the block size");
+	slice::from_raw_parts(bytes.as_ptr() as *const Block, bytes.len() / BLOCK_SIZE)
+}
+
 const BLOCK_SIZE: usize = mem::size_of::<Block>();
 // const KEY_SIZE: usize = mem::size_of::<Key>();
 
@@ -161,6 +344,7 @@ pub struct Section {
 }
 
 impl Section {
+	// A section whose end overflows results in an inverted range which is rejected by `slice::get`
 	#[inline]
 	fn range_usize(&self) -> ops::Range<usize> {
 		self.offset as usize..(self.offset.wrapping_add(self.size)) as usize
@@ -184,6 +368,74 @@ fn bytes2blocks(byte_size: u32) -> u32 {
 	if byte_size == 0 { 0 } else { (byte_size - 1) / BLOCK_SIZE as u32 + 1 }
 }
 
+// Copies the buffers one after another into dest, excess data is cut off
+fn gather(dest: &mut [u8], bufs: &[std::io::IoSlice]) {
+	let mut offset = 0;
+	for buf in bufs {
+		let len = usize::min(dest.len() - offset, buf.len());
+		dest[offset..offset + len].copy_from_slice(&buf[..len]);
+		offset += len;
+	}
+}
+
+// Views the bytes as possibly uninitialized bytes
+// Only initialized bytes may be written through the view
+fn uninit_bytes(bytes: &mut [u8]) -> &mut [mem::MaybeUninit<u8>] {
+	unsafe { &mut *(bytes as *mut [u8] as *mut [mem::MaybeUninit<u8>]) }
+}
+
+// Copies the bytes of the inline file contents starting at the byte offset into dest
+fn read_inline<'a>(desc: &Descriptor, byte_offset: usize, dest: &'a mut [mem::MaybeUninit<u8>]) -> Result<&'a mut [u8], ErrorKind> {
+	match desc.inline_data() {
+		Some(data) => read_slice(data, byte_offset, dest),
+		None => Err(ErrorKind::InvalidInput),
+	}
+}
+
+// Copies the bytes of the file contents starting at the byte offset into dest
+fn read_slice<'a>(data: &[u8], byte_offset: usize, dest: &'a mut [mem::MaybeUninit<u8>]) -> Result<&'a mut [u8], ErrorKind> {
+	let src = byte_offset.checked_add(dest.len()).and_then(|end| data.get(byte_offset..end));
+	match src {
+		Some(src) => Ok(dest.write_copy_of_slice(src)),
+		None => Err(ErrorKind::InvalidInput),
+	}
+}
+
+// Reads the bundled file contents, the section shared with the previously read bundled file is decrypted only once
+fn read_bundled<E: From<ErrorKind>>(bundle: &mut Option<(Section, Vec<Block>)>, desc: &Descriptor, read_section: impl FnOnce(&Section) -> Result<Vec<Block>, E>) -> Result<Vec<u8>, E> {
+	let blocks = match bundle {
+		Some((section, blocks)) if *section == desc.section => blocks,
+		_ => &mut bundle.insert((desc.section, read_section(&desc.section)?)).1,
+	};
+	let start = desc.data_offset() as usize;
+	match dataview::bytes(blocks.as_slice()).get(start..start + desc.content_size as usize) {
+		Some(data) => Ok(data.to_vec()),
+		None => Err(ErrorKind::InvalidInput.into()),
+	}
+}
+
+// Takes the nonce to encrypt the file contents with, plaintext file contents have no nonce.
+// Take it before writing the file contents so a failure leaves no plaintext behind.
+fn data_nonce(nonces: Option<&dyn NonceSource>, desc: &Descriptor) -> Result<Block, ErrorKind> {
+	if desc.is_plaintext() {
+		Ok(Block::default())
+	}
+	else {
+		crypt::next_nonce(nonces)
+	}
+}
+
+// Encrypts the file contents inplace unless they are stored as plaintext.
+fn seal_data(blocks: &mut [Block], desc: &mut Descriptor, nonce: Block, key: &Key) {
+	desc.section.nonce = nonce;
+	if desc.is_plaintext() {
+		desc.section.mac = Block::default();
+	}
+	else {
+		crypt::encrypt_section_with_nonce(blocks, &mut desc.section, key);
+	}
+}
+
 //----------------------------------------------------------------
 
 /// The info header.
@@ -192,7 +444,12 @@ fn bytes2blocks(byte_size: u32) -> u32 {
 pub struct InfoHeader {
 	/// Version info value, should be equal to [`VERSION`](Self::VERSION).
 	pub version: u32,
-	pub _unused: u32,
+	/// Key check value, should be equal to [`KEY_CHECK`](Self::KEY_CHECK).
+	///
+	/// Together with the version it is a known plaintext used to distinguish an incorrect key from a corrupted header.
+	/// PAKS files written by older versions of this library set it to zero.
+	/// Since [`VERSION_4`](Self::VERSION_4) it records the block shift, see [`key_check_with`](Self::key_check_with).
+	pub key_check: u32,
 	/// The section object describing the location of the directory.
 	///
 	/// Special note: the section size specifies the number of `Descriptors` not the number of blocks.
@@ -202,9 +459,96 @@ pub struct InfoHeader {
 impl InfoHeader {
 	/// File format version number.
 	///
+	/// This is the version written by this library.
+	/// Older versions are still supported for reading, see [`is_supported`](Self::is_supported).
+	///
 	/// This library is endian-sensitive; reading a PAKS file on a machine
 	/// with the wrong endianness will cause the version check to fail.
-	pub const VERSION: u32 = u32::from_ne_bytes(*b"PAK1");
+	pub const VERSION: u32 = InfoHeader::VERSION_4;
+
+	/// The original file format version.
+	///
+	/// The trailing bytes of the descriptors were unused, they are ignored when reading.
+	pub const VERSION_1: u32 = u32::from_ne_bytes(*b"PAK1");
+
+	/// File format version adding the [descriptor flags](Descriptor::flags).
+	///
+	/// Readers of the original version do not know the flags and would misread the descriptors using them.
+	pub const VERSION_2: u32 = u32::from_ne_bytes(*b"PAK2");
+
+	/// File format version deriving the keystream of the header from its MAC.
+	///
+	/// The magic signature leaves only 64 random bits in the header nonce, the header is encrypted with a synthetic nonce instead.
+	/// The descriptors are the same as in the previous version.
+	pub const VERSION_3: u32 = u32::from_ne_bytes(*b"PAK3");
+
+	/// File format version recording the block size of the PAKS file, see [`block_shift`](Self::block_shift).
+	///
+	/// Editors of older versions do not know the block size and would allocate sections which are not aligned to it.
+	/// The header and the descriptors are the same as in the previous version.
+	pub const VERSION_4: u32 = u32::from_ne_bytes(*b"PAK4");
+
+	/// Key check value.
+	pub const KEY_CHECK: u32 = u32::from_ne_bytes(*b"KEYS");
+
+	/// The largest block shift, a block size of 64 KiB.
+	pub const MAX_BLOCK_SHIFT: u32 = 12;
+
+	/// Returns if the file format version can be read by this library.
+	#[inline]
+	pub const fn is_supported(version: u32) -> bool {
+		version == InfoHeader::VERSION_1 || version == InfoHeader::VERSION_2 || version == InfoHeader::VERSION_3 || version == InfoHeader::VERSION_4
+	}
+
+	/// Returns the key check value recording the block shift.
+	///
+	/// The last byte of [`KEY_CHECK`](Self::KEY_CHECK) is incremented by the block shift, a block shift of zero is the key check value itself.
+	/// The block shift must not exceed [`MAX_BLOCK_SHIFT`](Self::MAX_BLOCK_SHIFT).
+	#[inline]
+	pub const fn key_check_with(block_shift: u32) -> u32 {
+		let [k, e, y, s] = InfoHeader::KEY_CHECK.to_ne_bytes();
+		u32::from_ne_bytes([k, e, y, s + block_shift as u8])
+	}
+
+	/// Returns the block size of the PAKS file as a shift of the cipher block size.
+	///
+	/// The sections of the files are allocated at multiples of `BLOCK_SIZE << block_shift` bytes, aligning the file contents for higher throughput.
+	/// Offsets and sizes of the sections are still counted in cipher blocks, the block size does not change how the PAKS file is read.
+	///
+	/// PAKS files before [`VERSION_4`](Self::VERSION_4) have a block shift of zero.
+	#[inline]
+	pub const fn block_shift(&self) -> u32 {
+		if self.version != InfoHeader::VERSION_4 {
+			return 0;
+		}
+		let [_, _, _, s] = self.key_check.to_ne_bytes();
+		s.wrapping_sub(InfoHeader::KEY_CHECK.to_ne_bytes()[3]) as u32
+	}
+
+	// Returns if the key check is the known plaintext of the version
+	fn is_key_check_known(&self) -> bool {
+		if self.version != InfoHeader::VERSION_4 {
+			return matches!(self.key_check, 0 | InfoHeader::KEY_CHECK);
+		}
+		self.block_shift() <= InfoHeader::MAX_BLOCK_SHIFT && self.key_check == InfoHeader::key_check_with(self.block_shift())
+	}
+
+	// Block index right after the directory, the directory section's size is the number of descriptors
+	// Returns None if the directory extends beyond the maximum PAKS file size
+	fn directory_end(&self) -> Option<u32> {
+		let dir_len = self.directory.size.checked_mul(Descriptor::BLOCKS_LEN as u32)?;
+		self.directory.offset.checked_add(dir_len)
+	}
+}
+
+// Upgrades the descriptors read from an older file format version.
+fn upgrade_directory(version: u32, dir: &mut [Descriptor]) {
+	if version == InfoHeader::VERSION_1 {
+		for desc in dir {
+			desc.flags = 0;
+			desc.reserved = [0; 9];
+		}
+	}
 }
 
 impl fmt::Debug for InfoHeader {
@@ -221,6 +565,9 @@ impl fmt::Debug for InfoHeader {
 #[repr(C)]
 pub struct Header {
 	/// Cryptographic nonce used for the info header.
+	///
+	/// Unless written in stealth mode, the first 8 bytes of the nonce are the plaintext magic signature [`MAGIC`](Self::MAGIC) followed by the file format version.
+	/// See [`identify`] to detect PAKS files without the encryption key.
 	pub nonce: Block,
 	/// Cryptographic MAC used to authenticate the info header.
 	pub mac: Block,
@@ -231,6 +578,23 @@ pub struct Header {
 }
 
 impl Header {
+	/// The plaintext magic signature at the start of PAKS files.
+	pub const MAGIC: [u8; 4] = *b"PAKS";
+
+	// The first half of the header nonce containing the magic signature and the file format version
+	const fn signature(version: u32) -> u64 {
+		let version = version.to_ne_bytes();
+		u64::from_ne_bytes([
+			Header::MAGIC[0], Header::MAGIC[1], Header::MAGIC[2], Header::MAGIC[3],
+			version[0], version[1], version[2], version[3],
+		])
+	}
+
+	// Returns if the encrypted header was written with the magic signature
+	fn has_magic(&self) -> bool {
+		identify(dataview::bytes(self)) != Identify::Unknown
+	}
+
 	const SECTION: Section = Section {
 		offset: Header::BLOCKS_LEN as u32 - InfoHeader::BLOCKS_LEN as u32,
 		size: InfoHeader::BLOCKS_LEN as u32,
@@ -262,11 +626,78 @@ pub struct Descriptor {
 	pub section: Section,
 	/// The name of the descriptor, see [`name`](Self::name).
 	pub name: Name,
-	/// Extra meta section object, unused for now.
-	pub meta: Section,
+	/// The descriptor flags, see [`FLAG_PLAINTEXT`](Self::FLAG_PLAINTEXT).
+	///
+	/// Unknown flags are reserved and must be zero.
+	pub flags: u32,
+	/// Reserved for future use, must be zero unless used by one of the flags.
+	///
+	/// See [`FLAG_ENCRYPTED_NAME`](Self::FLAG_ENCRYPTED_NAME), [`FLAG_PATH_HASH`](Self::FLAG_PATH_HASH), [`FLAG_BUNDLED`](Self::FLAG_BUNDLED), [`FLAG_EXTENSIONS`](Self::FLAG_EXTENSIONS) and [`FLAG_DELTA`](Self::FLAG_DELTA).
+	/// New features which need space in the descriptor should define an [`Extension`] record instead of a flag.
+	pub reserved: [u32; 9],
 }
 
 impl Descriptor {
+	/// The file contents are stored unencrypted.
+	///
+	/// Plaintext file contents are neither encrypted nor authenticated, the nonce and MAC of their section are zero.
+	/// This allows hot, non-sensitive files (eg. streaming music) to be memory-mapped and read without any decryption cost.
+	/// The contents are located at byte offset `section.offset * BLOCK_SIZE` in the PAKS file.
+	pub const FLAG_PLAINTEXT: u32 = 1;
+
+	/// The name is encrypted with a [`NameKey`].
+	///
+	/// Encrypted names hide the entries of the PAKS file even from someone holding the key to decrypt the directory or a shared file.
+	/// Names are encrypted deterministically, the same name always encrypts to the same bytes which allows finding descriptors by their encrypted path.
+	/// The length of the name is not hidden.
+	///
+	/// The synthetic nonce of the encrypted name is stored in the first four reserved fields.
+	/// See [`encrypt_name`](Self::encrypt_name) and [`Directory::find_desc_with_key`].
+	pub const FLAG_ENCRYPTED_NAME: u32 = 2;
+
+	/// The descriptor stores the hash of its path.
+	///
+	/// The hash is stored in the fifth and sixth reserved fields, see [`path_hash()`] for the hash function.
+	/// Use [`Directory::hash_paths`] to store the hashes and [`Directory::find_by_hash`] to find descriptors by the hash of their path.
+	pub const FLAG_PATH_HASH: u32 = 4;
+
+	/// The name is replaced by the hexadecimal hash of its path.
+	///
+	/// Stripped names leak no file naming, the descriptors are found by the hash of their original path.
+	/// See [`MemoryEditor::strip_names`] and [`Directory::find_by_hash`].
+	pub const FLAG_STRIPPED_NAME: u32 = 8;
+
+	/// The file contents are stored inline in place of the section object.
+	///
+	/// Files up to [`INLINE_LEN`](Self::INLINE_LEN) bytes occupy no blocks, shrinking PAKS files with many tiny files.
+	/// Inline contents are encrypted and authenticated together with the directory and are read without any further decryption.
+	/// See [`inline_data`](Self::inline_data) and [`MemoryEditor::set_inline_threshold`].
+	pub const FLAG_INLINE: u32 = 16;
+
+	/// Maximum size in bytes of inline file contents, see [`FLAG_INLINE`](Self::FLAG_INLINE).
+	pub const INLINE_LEN: usize = mem::size_of::<Section>();
+
+	/// The file contents are a byte range within a section shared with other files.
+	///
+	/// Bundling many tiny files into one section saves the padding of their last blocks and speeds up reading them in sequence.
+	/// The byte offset of the file contents within the section is stored in the seventh reserved field.
+	/// See [`data_offset`](Self::data_offset) and [`MemoryEditor::create_bundle`].
+	pub const FLAG_BUNDLED: u32 = 32;
+
+	/// The file contents are stored as a binary delta against the contents of an older section.
+	///
+	/// Replacing a file with a slightly different version only appends the differences, keeping frequently patched PAKS files small.
+	/// The section holds the encrypted delta, the offset and size of the base section are stored in the last two reserved fields.
+	/// The base section is stored in the extension area, delta files have no extension records, see [`FLAG_EXTENSIONS`](Self::FLAG_EXTENSIONS).
+	/// The contents are reconstructed transparently when read, see [`delta`] and [`CreateMode::Delta`].
+	pub const FLAG_DELTA: u32 = 128;
+
+	/// Content type of JSON files created with `create_json`, the bytes `JSON` in little endian.
+	pub const CONTENT_TYPE_JSON: u32 = u32::from_le_bytes(*b"JSON");
+
+	/// Content type of TOML files created with `create_toml`, the bytes `TOML` in little endian.
+	pub const CONTENT_TYPE_TOML: u32 = u32::from_le_bytes(*b"TOML");
+
 	/// Creates a new empty descriptor with the given name, content type and size.
 	///
 	/// The descriptor is a directory descriptor if its `content_type` is zero.
@@ -309,11 +740,205 @@ impl Descriptor {
 		self.content_type == 0
 	}
 
+	/// Is this a directory descriptor without any children?
+	///
+	/// Empty directories are stored as a directory descriptor with a content size of zero, extracting the PAKS file recreates them.
+	#[inline]
+	pub fn is_empty_dir(&self) -> bool {
+		self.content_type == 0 && self.content_size == 0
+	}
+
 	/// Is this a file descriptor?
 	#[inline]
 	pub fn is_file(&self) -> bool {
 		self.content_type != 0
 	}
+
+	/// Are the file contents stored unencrypted?
+	///
+	/// See [`FLAG_PLAINTEXT`](Self::FLAG_PLAINTEXT).
+	#[inline]
+	pub fn is_plaintext(&self) -> bool {
+		self.flags & Descriptor::FLAG_PLAINTEXT != 0
+	}
+
+	/// Are the file contents stored inline?
+	///
+	/// See [`FLAG_INLINE`](Self::FLAG_INLINE).
+	#[inline]
+	pub fn is_inline(&self) -> bool {
+		self.flags & Descriptor::FLAG_INLINE != 0
+	}
+
+	/// Gets the inline file contents.
+	///
+	/// Returns `None` if the file contents are not stored inline or the content size is too large.
+	#[inline]
+	pub fn inline_data(&self) -> Option<&[u8]> {
+		if !self.is_inline() {
+			return None;
+		}
+		dataview::bytes(&self.section).get(..self.content_size as usize)
+	}
+
+	/// Stores the file contents inline, replacing the section object.
+	///
+	/// Sets the content size to the length of the data, inline file contents are never plaintext.
+	/// Returns false and leaves the descriptor unchanged if the data is longer than [`INLINE_LEN`](Self::INLINE_LEN).
+	pub fn set_inline_data(&mut self, data: &[u8]) -> bool {
+		if data.len() > Descriptor::INLINE_LEN {
+			return false;
+		}
+		let mut section = Section::default();
+		dataview::bytes_mut(&mut section)[..data.len()].copy_from_slice(data);
+		self.section = section;
+		self.content_size = data.len() as u32;
+		self.clear_storage();
+		self.flags = self.flags & !Descriptor::FLAG_PLAINTEXT | Descriptor::FLAG_INLINE;
+		true
+	}
+
+	/// Are the file contents bundled with other files?
+	///
+	/// See [`FLAG_BUNDLED`](Self::FLAG_BUNDLED).
+	#[inline]
+	pub fn is_bundled(&self) -> bool {
+		self.flags & Descriptor::FLAG_BUNDLED != 0
+	}
+
+	/// Gets the byte offset of the file contents within its section.
+	///
+	/// Returns zero unless the file contents are bundled, see [`FLAG_BUNDLED`](Self::FLAG_BUNDLED).
+	#[inline]
+	pub fn data_offset(&self) -> u32 {
+		if !self.is_bundled() {
+			return 0;
+		}
+		self.reserved[6]
+	}
+
+	/// Assigns a byte range of a section shared with other files to the file contents.
+	///
+	/// The content size is not changed, bundled file contents are never plaintext.
+	#[inline]
+	pub fn set_bundled(&mut self, section: &Section, data_offset: u32) {
+		self.clear_storage();
+		self.section = *section;
+		self.reserved[6] = data_offset;
+		self.flags = self.flags & !Descriptor::FLAG_PLAINTEXT | Descriptor::FLAG_BUNDLED;
+	}
+
+	/// Are the file contents stored as a delta?
+	///
+	/// See [`FLAG_DELTA`](Self::FLAG_DELTA).
+	#[inline]
+	pub fn is_delta(&self) -> bool {
+		self.flags & Descriptor::FLAG_DELTA != 0
+	}
+
+	/// Gets the offset and size in blocks of the base section of a delta file.
+	///
+	/// See [`FLAG_DELTA`](Self::FLAG_DELTA).
+	#[inline]
+	pub fn delta_base(&self) -> Option<(u32, u32)> {
+		if !self.is_delta() {
+			return None;
+		}
+		Some((self.reserved[7], self.reserved[8]))
+	}
+
+	/// Stores the offset and size in blocks of the base section, turning the file into a delta file.
+	///
+	/// The section must hold the encrypted delta and the content size is the size of the reconstructed contents.
+	///
+	/// Returns false and leaves the descriptor unchanged if it holds extension records, the base section is stored in the extension area.
+	/// Remove the extension records first, see [`FLAG_EXTENSIONS`](Self::FLAG_EXTENSIONS).
+	#[inline]
+	pub fn set_delta_base(&mut self, offset: u32, size: u32) -> bool {
+		if self.flags & Descriptor::FLAG_EXTENSIONS != 0 {
+			return false;
+		}
+		self.reserved[7] = offset;
+		self.reserved[8] = size;
+		self.flags |= Descriptor::FLAG_DELTA;
+		true
+	}
+
+	// Clears the inline, bundled and delta storage of the file contents before assigning a section
+	fn clear_storage(&mut self) {
+		self.reserved[6] = 0;
+		if self.is_delta() {
+			self.reserved[7..9].fill(0);
+		}
+		self.flags &= !(Descriptor::FLAG_INLINE | Descriptor::FLAG_BUNDLED | Descriptor::FLAG_DELTA);
+	}
+
+	/// Is the name encrypted?
+	///
+	/// See [`FLAG_ENCRYPTED_NAME`](Self::FLAG_ENCRYPTED_NAME).
+	#[inline]
+	pub fn is_name_encrypted(&self) -> bool {
+		self.flags & Descriptor::FLAG_ENCRYPTED_NAME != 0
+	}
+
+	/// Is the name replaced by the hash of its path?
+	///
+	/// See [`FLAG_STRIPPED_NAME`](Self::FLAG_STRIPPED_NAME).
+	#[inline]
+	pub fn is_name_stripped(&self) -> bool {
+		self.flags & Descriptor::FLAG_STRIPPED_NAME != 0
+	}
+
+	/// Gets the stored hash of the descriptor's path.
+	///
+	/// See [`FLAG_PATH_HASH`](Self::FLAG_PATH_HASH).
+	#[inline]
+	pub fn path_hash(&self) -> Option<u64> {
+		if self.flags & Descriptor::FLAG_PATH_HASH == 0 {
+			return None;
+		}
+		Some(self.reserved[4] as u64 | (self.reserved[5] as u64) << 32)
+	}
+
+	/// Stores the hash of the descriptor's path.
+	#[inline]
+	pub fn set_path_hash(&mut self, hash: u64) {
+		self.reserved[4] = hash as u32;
+		self.reserved[5] = (hash >> 32) as u32;
+		self.flags |= Descriptor::FLAG_PATH_HASH;
+	}
+
+	/// Encrypts the name with the name key.
+	///
+	/// Does nothing if the name is already encrypted.
+	pub fn encrypt_name(&mut self, key: &NameKey) {
+		if self.is_name_encrypted() {
+			return;
+		}
+		let nonce = crypt::encrypt_name(&mut self.name, key);
+		self.reserved[..4].copy_from_slice(&[nonce[0] as u32, (nonce[0] >> 32) as u32, nonce[1] as u32, (nonce[1] >> 32) as u32]);
+		self.flags |= Descriptor::FLAG_ENCRYPTED_NAME;
+	}
+
+	/// Decrypts the name with the name key.
+	///
+	/// Returns false and leaves the descriptor unchanged if the name key is incorrect.
+	/// Does nothing if the name is not encrypted.
+	pub fn decrypt_name(&mut self, key: &NameKey) -> bool {
+		if !self.is_name_encrypted() {
+			return true;
+		}
+		let [a, b, c, d] = [self.reserved[0] as u64, self.reserved[1] as u64, self.reserved[2] as u64, self.reserved[3] as u64];
+		let nonce = [a | b << 32, c | d << 32];
+		let mut name = self.name;
+		if !crypt::decrypt_name(&mut name, &nonce, key) {
+			return false;
+		}
+		self.name = name;
+		self.reserved[..4].fill(0);
+		self.flags &= !Descriptor::FLAG_ENCRYPTED_NAME;
+		true
+	}
 }
 
 impl fmt::Debug for Descriptor {
@@ -323,6 +948,7 @@ impl fmt::Debug for Descriptor {
 			.field("content_type", &self.content_type)
 			.field("content_size", &self.content_size)
 			.field("section", &self.section)
+			.field("flags", &self.flags)
 			.finish()
 	}
 }
@@ -1,5 +1,27 @@
 use super::*;
 
+// Decrypts and authenticates a section into the destination blocks.
+// Returns an error if the section range, destination length or MAC is incorrect.
+fn read_section_into(blocks: &[Block], section: &Section, key: &Key, dest: &mut [Block]) -> Result<(), ErrorKind> {
+	span!(TRACE, "read_section", offset = section.offset, size = section.size);
+
+	let blocks = match blocks.get(section.range_usize()) {
+		Some(blocks) => blocks,
+		None => return Err(ErrorKind::InvalidInput),
+	};
+	if dest.len() != blocks.len() {
+		return Err(ErrorKind::InvalidInput);
+	}
+
+	dest.copy_from_slice(blocks);
+	if !crypt::decrypt_section(dest, section, key) {
+		tampered(Tampered::Section(*section));
+		return Err(ErrorKind::InvalidData);
+	}
+
+	Ok(())
+}
+
 // Decrypts and authenticates a section.
 // Returns an error if the section range or MAC is incorrect.
 fn read_section(blocks: &[Block], section: &Section, key: &Key) -> Result<Vec<Block>, ErrorKind> {
@@ -10,91 +32,264 @@ fn read_section(blocks: &[Block], section: &Section, key: &Key) -> Result<Vec<Bl
 
 	let mut blocks = blocks.to_vec();
 	if !crypt::decrypt_section(&mut blocks, section, key) {
+		tampered(Tampered::Section(*section));
 		return Err(ErrorKind::InvalidData);
 	}
 
 	Ok(blocks)
 }
 
+// The blocks, the directory, the key slots and the block shift of the PAKS file
+type Parsed = (Vec<Block>, Directory, Vec<KeySlot>, u32);
+
 // Decrypts and authenticates the header and the directory.
 // Returns an the original blocks on any bounds errors or MAC checks fail.
-fn from_blocks(mut blocks: Vec<Block>, key: &Key) -> Result<(Vec<Block>, Directory), Vec<Block>> {
+fn from_blocks(mut blocks: Vec<Block>, key: &Key, strict: bool) -> Result<Parsed, (Vec<Block>, ParseReport)> {
+	span!(DEBUG, "from_blocks", blocks = blocks.len());
+
 	// The blocks must contain at least space for the header ref$1
 	if blocks.len() < Header::BLOCKS_LEN {
-		return Err(blocks);
+		let len = blocks.len();
+		return Err((blocks, ParseReport::TooSmall { len }));
 	}
 
 	// Decrypt the header
 	let mut header: Header = dataview::DataView::from_mut(blocks.as_mut_slice()).read(0);
-	if !crypt::decrypt_header(&mut header, key) {
-		// MAC is incorrect!
-		return Err(blocks);
+	if let Err(kind) = crypt::decrypt_header(&mut header, key) {
+		// Incorrect key or MAC is incorrect!
+		let report = match kind {
+			ErrorKind::Unsupported => ParseReport::UnsupportedVersion { version: header.info.version },
+			ErrorKind::InvalidData => {
+				tampered(Tampered::Header);
+				ParseReport::HeaderMac
+			},
+			_ => ParseReport::IncorrectKey,
+		};
+		return Err((blocks, report));
 	}
 
 	// Extract the directory
 	let dir_start = header.info.directory.offset as usize;
-	let dir_end = dir_start + header.info.directory.size as usize * Descriptor::BLOCKS_LEN;
-	let dir_blocks = match blocks.get_mut(dir_start..dir_end) {
-		Some(dir_blocks) => dir_blocks,
-		None => return Err(blocks),
+	let dir_end = match header.info.directory_end().filter(|&dir_end| dir_end as usize <= blocks.len()) {
+		Some(dir_end) => dir_end as usize,
+		None => {
+			let len = blocks.len();
+			return Err((blocks, ParseReport::DirectoryBounds { directory: header.info.directory, len }));
+		},
 	};
+	let dir_blocks = &mut blocks[dir_start..dir_end];
 
 	// Decrypt the directory
-	crypt::decrypt_section(dir_blocks, &header.info.directory, key);
+	// The directory is decrypted inplace, on failure the blocks are left in an unspecified state
+	if !crypt::decrypt_section(dir_blocks, &header.info.directory, key) {
+		tampered(Tampered::Directory(header.info.directory));
+		return Err((blocks, ParseReport::DirectoryMac { directory: header.info.directory }));
+	}
 
 	// Reinterpret the directory
 	let dir = unsafe {
 		slice::from_raw_parts(dir_blocks.as_ptr() as *const Descriptor, header.info.directory.size as usize)
 	};
-	let directory = Directory::from(dir.to_vec());
+	let mut directory = Directory::from(dir.to_vec());
+	upgrade_directory(header.info.version, directory.as_mut());
+
+	// Do not trust a directory which is authentic but logically invalid
+	if !dir::validate(directory.as_ref(), header.info.directory.offset) {
+		return Err((blocks, ParseReport::InvalidDirectory { directory: header.info.directory }));
+	}
+
+	// The directory may be followed by a copy of the header and the key slots, see MemoryEditor::save_to
+	let mut tail_start = dir_end;
+	if blocks.get(dir_end..dir_end + Header::BLOCKS_LEN) == Some(&blocks[..Header::BLOCKS_LEN]) {
+		tail_start += Header::BLOCKS_LEN;
+	}
+	let footer_len = keyslot::find_footer(&blocks[tail_start..]).unwrap_or(0);
+	let key_slots = keyslot::parse_slots(&blocks[tail_start..tail_start + footer_len]);
+
+	// Anything after that is unused, eg. padding added by a download server
+	let trailing = blocks.len() - tail_start - footer_len;
+	if strict && trailing > 0 {
+		return Err((blocks, ParseReport::TrailingData { len: trailing }));
+	}
 
 	// Truncate the blocks to trim the directory
-	if blocks.len() == dir_end {
-		blocks.truncate(dir_start);
+	// Every section is located before the directory, see dir::validate
+	blocks.truncate(dir_start);
+
+	Ok((blocks, directory, key_slots, header.info.block_shift()))
+}
+
+// Copies the bytes to blocks before parsing them, also returns if the PAKS file has the plaintext magic signature
+fn from_bytes(bytes: &[u8], key: &Key, strict: bool) -> Result<(Parsed, bool), ParseReport> {
+	// The input bytes must be a multiple of the BLOCK_SIZE or this is nonsense
+	if !bytes.len().is_multiple_of(BLOCK_SIZE) {
+		return Err(ParseReport::SizeMultiple { len: bytes.len() });
 	}
 
-	Ok((blocks, directory))
+	// Allocate enough space to hold the blocks equivalent
+	// This is necessary as internal operations have alignment requirements
+	// Copy the input into these blocks
+	let mut blocks = vec![Block::default(); bytes.len() / BLOCK_SIZE];
+	dataview::bytes_mut(blocks.as_mut_slice())[..bytes.len()].copy_from_slice(bytes);
+
+	let magic = identify(bytes) != Identify::Unknown;
+	match from_blocks(blocks, key, strict) {
+		Ok(parsed) => Ok((parsed, magic)),
+		Err((_, report)) => Err(report),
+	}
+}
+
+// Decrypts and authenticates the header and the directory without modifying the blocks.
+fn load_directory(blocks: &[Block], key: &Key) -> Result<(Directory, u32), ErrorKind> {
+	span!(DEBUG, "load_directory", blocks = blocks.len());
+
+	// The blocks must contain at least space for the header
+	if blocks.len() < Header::BLOCKS_LEN {
+		return Err(ErrorKind::InvalidData);
+	}
+
+	// Decrypt a copy of the header
+	let mut header: Header = dataview::DataView::from(blocks).read(0);
+	if let Err(kind) = crypt::decrypt_header(&mut header, key) {
+		if kind == ErrorKind::InvalidData {
+			tampered(Tampered::Header);
+		}
+		return Err(kind);
+	}
+
+	// Copy the directory out of the blocks
+	let dir_start = header.info.directory.offset as usize;
+	let dir_blocks = match header.info.directory_end().and_then(|dir_end| blocks.get(dir_start..dir_end as usize)) {
+		Some(dir_blocks) => dir_blocks,
+		None => return Err(ErrorKind::InvalidData),
+	};
+	let mut directory = Directory::from(vec![Descriptor::default(); header.info.directory.size as usize]);
+	directory.as_blocks_mut().copy_from_slice(dir_blocks);
+
+	// Decrypt the copy of the directory
+	if !crypt::decrypt_section(directory.as_blocks_mut(), &header.info.directory, key) {
+		tampered(Tampered::Directory(header.info.directory));
+		return Err(ErrorKind::InvalidData);
+	}
+	upgrade_directory(header.info.version, directory.as_mut());
+
+	// Do not trust a directory which is authentic but logically invalid
+	if !dir::validate(directory.as_ref(), header.info.directory.offset) {
+		return Err(ErrorKind::InvalidData);
+	}
+
+	Ok((directory, header.info.block_shift()))
 }
 
 fn read_data(blocks: &[Block], desc: &Descriptor, key: &Key) -> Result<Vec<u8>, ErrorKind> {
+	span!(TRACE, "read_data", offset = desc.section.offset, size = desc.content_size);
+
 	if !desc.is_file() {
 		return Err(ErrorKind::InvalidInput);
 	}
 
+	if desc.is_inline() {
+		return desc.inline_data().map(<[u8]>::to_vec).ok_or(ErrorKind::InvalidInput);
+	}
+
+	if desc.is_plaintext() {
+		let data = match plaintext_data(blocks, desc) {
+			Some(data) => data,
+			None => return Err(ErrorKind::InvalidInput),
+		};
+		return Ok(data.to_vec());
+	}
+
+	if desc.is_delta() {
+		return delta::read_data(desc, |section| read_section(blocks, section, key));
+	}
+
 	let blocks = read_section(blocks, &desc.section, key)?;
 
 	// Figure out which part of the blocks to copy
 	let data = dataview::bytes(blocks.as_slice());
-	let len = usize::min(data.len(), desc.content_size as usize);
-	Ok(data[..len].to_vec())
+	let start = usize::min(data.len(), desc.data_offset() as usize);
+	let len = usize::min(data.len() - start, desc.content_size as usize);
+	Ok(data[start..start + len].to_vec())
 }
 
-fn read_data_into(blocks: &[Block], desc: &Descriptor, key: &Key, byte_offset: usize, dest: &mut [u8]) -> Result<(), ErrorKind> {
+fn read_data_into<'a>(blocks: &[Block], desc: &Descriptor, key: &Key, byte_offset: usize, dest: &'a mut [mem::MaybeUninit<u8>]) -> Result<&'a mut [u8], ErrorKind> {
+	span!(TRACE, "read_data_into", offset = desc.section.offset, byte_offset, len = dest.len());
+
 	if !desc.is_file() {
 		return Err(ErrorKind::InvalidInput);
 	}
 
-	let blocks = read_section(blocks, &desc.section, key)?;
+	if desc.is_inline() {
+		return read_inline(desc, byte_offset, dest);
+	}
 
-	// Figure out which part of the blocks to copy
-	let data = match dataview::bytes(blocks.as_slice()).get(byte_offset..byte_offset + dest.len()) {
-		Some(data) => data,
+	// Delta files are reconstructed as a whole, the dest is zeroed if the authentication fails like other files
+	if desc.is_delta() {
+		return match delta::read_data(desc, |section| read_section(blocks, section, key)) {
+			Ok(data) => read_slice(&data, byte_offset, dest),
+			Err(err) => {
+				dest.fill(mem::MaybeUninit::new(0));
+				Err(err)
+			},
+		};
+	}
+
+	let blocks = match blocks.get(desc.section.range_usize()) {
+		Some(blocks) => blocks,
 		None => return Err(ErrorKind::InvalidInput),
 	};
 
-	// Copy the data to its destination
-	dest.copy_from_slice(data);
+	// The requested range must be within the file contents, bundled files must not read the contents of their neighbours
+	if byte_offset.checked_add(dest.len()).is_none_or(|end| end > desc.content_size as usize) {
+		return Err(ErrorKind::InvalidInput);
+	}
+	let byte_offset = match byte_offset.checked_add(desc.data_offset() as usize) {
+		Some(byte_offset) => byte_offset,
+		None => return Err(ErrorKind::InvalidInput),
+	};
+	if byte_offset.checked_add(dest.len()).is_none_or(|end| end > blocks.len() * BLOCK_SIZE) {
+		return Err(ErrorKind::InvalidInput);
+	}
 
-	Ok(())
+	// Plaintext file contents are copied as-is
+	if desc.is_plaintext() {
+		let len = dest.len();
+		return Ok(dest.write_copy_of_slice(&dataview::bytes(blocks)[byte_offset..byte_offset + len]));
+	}
+
+	// Decrypt directly into the destination
+	let result = crypt::decrypt_range::<()>(&desc.section, key, byte_offset, dest, |index, chunk| {
+		chunk.copy_from_slice(&blocks[index..index + chunk.len()]);
+		Ok(())
+	});
+	if result != Ok(true) {
+		tampered(Tampered::Section(desc.section));
+		return Err(ErrorKind::InvalidData);
+	}
+
+	// Every byte of dest is written by the decryption
+	Ok(unsafe { dest.assume_init_mut() })
+}
+
+fn plaintext_data<'a>(blocks: &'a [Block], desc: &Descriptor) -> Option<&'a [u8]> {
+	if !desc.is_file() || !desc.is_plaintext() || desc.is_inline() {
+		return None;
+	}
+	let data = dataview::bytes(blocks.get(desc.section.range_usize())?);
+	let start = desc.data_offset() as usize;
+	data.get(start..start.checked_add(desc.content_size as usize)?)
 }
 
 mod reader;
 mod editor;
 mod edit_file;
+mod report;
 
 pub use self::reader::*;
 pub use self::editor::*;
 pub use self::edit_file::*;
+pub use self::report::ParseReport;
 
 #[cfg(test)]
 mod tests;
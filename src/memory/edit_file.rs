@@ -7,6 +7,8 @@ use super::*;
 pub struct MemoryEditFile<'a> {
 	pub(super) desc: &'a mut Descriptor,
 	pub(super) blocks: &'a mut Vec<Block>,
+	pub(super) alignment: u32,
+	pub(super) nonces: Option<&'a dyn NonceSource>,
 }
 
 impl<'a> MemoryEditFile<'a> {
@@ -32,9 +34,44 @@ impl<'a> MemoryEditFile<'a> {
 	#[inline]
 	pub fn set_section(&mut self, section: &Section) -> &mut MemoryEditFile<'a> {
 		self.desc.section = *section;
+		self.desc.clear_storage();
 		return self;
 	}
 
+	/// Sets whether the file contents are stored unencrypted.
+	///
+	/// Must be set before the file contents are written, see [`Descriptor::FLAG_PLAINTEXT`].
+	/// Only use this for non-sensitive files, plaintext file contents are not authenticated.
+	#[inline]
+	pub fn set_plaintext(&mut self, plaintext: bool) -> &mut MemoryEditFile<'a> {
+		if plaintext {
+			self.desc.flags |= Descriptor::FLAG_PLAINTEXT;
+		}
+		else {
+			self.desc.flags &= !Descriptor::FLAG_PLAINTEXT;
+		}
+		self
+	}
+
+	/// Stores the file contents inline in the file descriptor, see [`Descriptor::FLAG_INLINE`].
+	///
+	/// Replaces the section object and sets the content size, no space is allocated.
+	/// Returns false and leaves the file descriptor unchanged if the data is longer than [`Descriptor::INLINE_LEN`].
+	#[inline]
+	pub fn set_inline_data(&mut self, data: &[u8]) -> bool {
+		self.desc.set_inline_data(data)
+	}
+
+	/// Assigns a byte range of a section shared with other files to the file contents, see [`Descriptor::FLAG_BUNDLED`].
+	///
+	/// The content size is not changed and should be set with `set_content`.
+	/// The byte range must lie within the section, see [`MemoryEditor::create_bundle`] to write the shared section.
+	#[inline]
+	pub fn set_bundled(&mut self, section: &Section, data_offset: u32) -> &mut MemoryEditFile<'a> {
+		self.desc.set_bundled(section, data_offset);
+		self
+	}
+
 	/// Allocates and assigns space for the file contents.
 	///
 	/// The size allocated is defined by a previous call to `set_content`'s content_size argument.
@@ -43,13 +80,15 @@ impl<'a> MemoryEditFile<'a> {
 	pub fn allocate_data(&mut self) -> &mut MemoryEditFile<'a> {
 		let size = bytes2blocks(self.desc.content_size);
 
-		// Simple bump allocate from the blocks Vec
-		self.desc.section.offset = self.blocks.len() as u32;
+		// Simple bump allocate from the blocks Vec, padded to the alignment
+		self.desc.clear_storage();
+		let offset = self.blocks.len().next_multiple_of(self.alignment as usize);
+		self.desc.section.offset = offset as u32;
 		self.desc.section.size = size;
 
 		// In the case of overflow... Do nothing?
 		// Writing data into the allocation will fail
-		if let Some(new_len) = self.blocks.len().checked_add(size as usize) {
+		if let Some(new_len) = offset.checked_add(size as usize) {
 			// Should be overwritten by `write_data` or `zero_data`
 			self.blocks.resize(new_len, Block::default());
 		}
@@ -57,56 +96,177 @@ impl<'a> MemoryEditFile<'a> {
 		return self;
 	}
 
+	/// Copies the encrypted contents of a file from another PAKS archive without decrypting them.
+	///
+	/// The blocks are the other PAKS archive and the section locates the file contents in those blocks.
+	/// The contents are authenticated with the given key before being copied and remain encrypted with this key.
+	/// The content type and size are not changed and should be set with `set_content`.
+	///
+	/// Returns false and leaves the file descriptor unchanged if the section is out of bounds or fails authentication.
+	pub fn copy_encrypted(&mut self, blocks: &[Block], section: &Section, key: &Key) -> bool {
+		let src = match blocks.get(section.range_usize()) {
+			Some(src) => src,
+			None => return false,
+		};
+		if !crypt::verify_section(src, section, key) {
+			tampered(Tampered::Section(*section));
+			return false;
+		}
+
+		let offset = self.blocks.len().next_multiple_of(self.alignment as usize);
+		self.blocks.resize(offset, Block::default());
+		self.blocks.extend_from_slice(src);
+		self.desc.section = Section { offset: offset as u32, ..*section };
+		self.desc.clear_storage();
+		true
+	}
+
 	/// Copies and encrypts the data with the given key into the address specified by this file descriptor.
 	///
+	/// # Errors
+	///
+	/// * Any error of the nonce source, see [`NonceSource`].
+	///
 	/// # Panics
 	///
 	/// This method assumes the section is correctly initialized (either through `set_section` or `allocate`).
-	pub fn write_data(&mut self, data: &[u8], key: &Key) -> &mut MemoryEditFile<'a> {
+	#[inline]
+	pub fn write_data(&mut self, data: &[u8], key: &Key) -> Result<&mut MemoryEditFile<'a>, Error> {
+		self.write_data_vectored(&[std::io::IoSlice::new(data)], key)
+	}
+
+	/// Copies and encrypts the data from multiple buffers with the given key into the address specified by this file descriptor.
+	///
+	/// The buffers are written one after another as if they were concatenated.
+	/// This allows composing a file from several parts (eg. header, body and footer) without concatenating them first.
+	/// The content size should be set to the total length of the buffers with [`set_content`](Self::set_content).
+	///
+	/// See [`write_data`](Self::write_data) for more information.
+	pub fn write_data_vectored(&mut self, bufs: &[std::io::IoSlice], key: &Key) -> Result<&mut MemoryEditFile<'a>, Error> {
+		let nonce = data_nonce(self.nonces, self.desc)?;
 		let blocks = &mut self.blocks[self.desc.section.range_usize()];
 
 		// Copy the data into the allocation
-		let len = usize::min(dataview::bytes(blocks).len(), data.len());
-		dataview::bytes_mut(blocks)[..len].copy_from_slice(&data[..len]);
+		// Any remaining space in the last block is left as zeroes
+		let bytes = dataview::bytes_mut(blocks);
+		bytes.fill(0);
+		gather(bytes, bufs);
 
 		// Encrypt the data inplace
-		crypt::encrypt_section(blocks, &mut self.desc.section, key);
+		seal_data(blocks, self.desc, nonce, key);
 
-		return self;
+		Ok(self)
+	}
+
+	/// Truncates or extends the file contents to the new size.
+	///
+	/// Extending the file fills the new space with zeroes.
+	///
+	/// If the number of blocks does not change when shrinking only the content size is updated.
+	/// Otherwise the contents are decrypted and re-encrypted into a newly allocated section.
+	/// The old section is left behind as garbage, links to the file keep referring to the old contents.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::InvalidInput`]: The descriptor is not a file descriptor.
+	/// * [`ErrorKind::InvalidData`]: The file contents failed authentication.
+	/// * Any error of the nonce source, see [`NonceSource`].
+	pub fn truncate(&mut self, new_size: u32, key: &Key) -> Result<&mut MemoryEditFile<'a>, Error> {
+		// Inline file contents are resized inplace
+		if let (Some(data), true) = (self.desc.inline_data(), new_size as usize <= Descriptor::INLINE_LEN) {
+			let mut data = data.to_vec();
+			data.resize(new_size as usize, 0);
+			self.desc.set_inline_data(&data);
+			return Ok(self);
+		}
+
+		// Shrinking within the last block does not require re-encrypting
+		if new_size <= self.desc.content_size && bytes2blocks(new_size) == self.desc.section.size && !self.desc.is_delta() {
+			self.desc.content_size = new_size;
+			return Ok(self);
+		}
+
+		let mut data = read_data(self.blocks, self.desc, key)?;
+		data.resize(new_size as usize, 0);
+
+		let content_type = self.desc.content_type;
+		self.set_content(content_type, new_size);
+		self.allocate_data().write_data(&data, key)
 	}
 
 	/// Initialize the data with zeroes.
-	pub fn zero_data(&mut self, key: &Key) -> &mut MemoryEditFile<'a> {
+	///
+	/// Bundled and delta file contents are moved to a newly allocated section first.
+	///
+	/// Fails with any error of the nonce source, see [`NonceSource`].
+	pub fn zero_data(&mut self, key: &Key) -> Result<&mut MemoryEditFile<'a>, Error> {
+		if self.desc.is_bundled() || self.desc.is_delta() {
+			self.allocate_data();
+		}
+		if self.desc.is_inline() {
+			let len = usize::min(self.desc.content_size as usize, Descriptor::INLINE_LEN);
+			self.desc.set_inline_data(&[0u8; Descriptor::INLINE_LEN][..len]);
+			return Ok(self);
+		}
+
+		let nonce = data_nonce(self.nonces, self.desc)?;
 		let blocks = &mut self.blocks[self.desc.section.range_usize()];
 
 		// Zero the data
 		blocks.fill(Block::default());
 
 		// Encrypt the data inplace
-		crypt::encrypt_section(blocks, &mut self.desc.section, key);
+		seal_data(blocks, self.desc, nonce, key);
 
-		return self;
+		Ok(self)
 	}
 
 	/// Reencrypts the data.
 	///
 	/// The file must be initialized (either through `write_data` or `zero_data`) before it can be updated.
+	/// Plaintext file contents are left unchanged.
+	///
+	/// Bundled files are moved to a newly allocated section, the other files sharing their section are left alone.
+	///
+	/// Fails with any error of the nonce source and leaves the file contents unchanged, see [`NonceSource`].
+	/// Fails with [`ErrorKind::InvalidData`] if the contents of a bundled or delta file failed authentication, the file is left unchanged.
 	///
 	/// # Panics
 	///
 	/// This method assumes the section is correctly initialized (either through `set_section` or `allocate`).
-	pub fn reencrypt_data(&mut self, old_key: &Key, key: &Key) {
+	pub fn reencrypt_data(&mut self, old_key: &Key, key: &Key) -> Result<(), Error> {
+		// Plaintext file contents are not encrypted, inline file contents are encrypted with the directory
+		if self.desc.is_plaintext() || self.desc.is_inline() {
+			return Ok(());
+		}
+
+		// Delta files are reconstructed into a newly allocated section, their base section is left alone
+		// Bundled files are moved to a section of their own, the shared section still holds the other files encrypted with the old key
+		if self.desc.is_delta() || self.desc.is_bundled() {
+			let data = read_data(self.blocks, self.desc, old_key)?;
+			self.allocate_data().write_data(&data, key)?;
+			return Ok(());
+		}
+
+		// Take the nonce before decrypting, a failure leaves the file contents alone
+		let nonce = crypt::next_nonce(self.nonces)?;
+
 		let blocks = &mut self.blocks[self.desc.section.range_usize()];
 
 		let old_mac = self.desc.section.mac;
 
 		// Simply decrypt and encrypt again
 		let is_valid = crypt::decrypt_section(blocks, &self.desc.section, old_key);
-		crypt::encrypt_section(blocks, &mut self.desc.section, key);
+		if !is_valid {
+			tampered(Tampered::Section(self.desc.section));
+		}
+		self.desc.section.nonce = nonce;
+		crypt::encrypt_section_with_nonce(blocks, &mut self.desc.section, key);
 
 		// If the MAC wasn't valid to begin with, keep it invalid
 		if !is_valid {
 			self.desc.section.mac = old_mac;
 		}
+		Ok(())
 	}
 }
@@ -1,3 +1,5 @@
+use std::collections::{hash_map, HashMap};
+use std::sync::Arc;
 use super::*;
 
 /// Memory editor.
@@ -5,17 +7,25 @@ use super::*;
 /// This implementation keeps the entire PAKS file in memory.
 #[derive(Clone, Debug)]
 pub struct MemoryEditor {
-	blocks: Vec<Block>,
-	directory: Directory,
+	pub(crate) blocks: Vec<Block>,
+	pub(crate) directory: Directory,
+	pub(crate) magic: bool,
+	pub(crate) block_shift: u32,
+	pub(crate) inline_threshold: usize,
+	pub(crate) key_slots: Vec<KeySlot>,
+	pub(crate) nonces: Option<Arc<dyn NonceSource>>,
+	pub(crate) generate_id: bool,
 }
 
 impl MemoryEditor {
 	/// Creates a new `MemoryEditor` instance.
+	///
+	/// The new PAKS file gets a random archive id when it is finished, see [`ArchiveId`].
 	pub fn new() -> MemoryEditor {
 		// The blocks must contain at least space for the header ref$1
 		let blocks = vec![Block::default(); Header::BLOCKS_LEN];
 		let directory = Directory::from(Vec::new());
-		MemoryEditor { blocks, directory }
+		MemoryEditor { blocks, directory, magic: true, block_shift: 0, inline_threshold: 0, key_slots: Vec::new(), nonces: None, generate_id: true }
 	}
 
 	/// Parses the bytes as the PAKS file format for editing.
@@ -28,28 +38,53 @@ impl MemoryEditor {
 	/// # Errors
 	///
 	/// * [`ErrorKind::InvalidInput`]: Bytes length is not a multiple of the block size.
-	/// * [`ErrorKind::InvalidData`]: Incorrect version info or authentication checks failed.
-	pub fn from_bytes(bytes: &[u8], key: &Key) -> Result<MemoryEditor, ErrorKind> {
-		// The input bytes must be a multiple of the BLOCK_SIZE or this is nonsense
-		if bytes.len() % BLOCK_SIZE != 0 {
-			return Err(ErrorKind::InvalidInput);
-		}
+	/// * [`ErrorKind::PermissionDenied`]: The encryption key is incorrect.
+	/// * [`ErrorKind::InvalidData`]: The PAKS file is corrupted, authentication checks failed or the directory is invalid.
+	/// * [`ErrorKind::Unsupported`]: The file format version is not supported.
+	///
+	/// Unused blocks trailing the PAKS file are ignored, eg. padding added by a download server.
+	/// See [`from_bytes_strict`](Self::from_bytes_strict) to reject them.
+	///
+	/// See [`from_bytes_diagnose`](Self::from_bytes_diagnose) to find out which stage failed.
+	#[inline]
+	pub fn from_bytes(bytes: &[u8], key: &Key) -> Result<MemoryEditor, Error> {
+		MemoryEditor::from_bytes_diagnose(bytes, key).map_err(Error::from)
+	}
 
-		// Allocate enough space to hold the blocks equivalent
-		// This is necessary as internal operations have alignment requirements
-		// Copy the input into these blocks
-		let mut blocks = vec![Block::default(); bytes.len() / BLOCK_SIZE];
-		dataview::bytes_mut(blocks.as_mut_slice())[..bytes.len()].copy_from_slice(bytes);
+	/// Parses the bytes as the PAKS file format for editing.
+	///
+	/// Like [`from_bytes`](Self::from_bytes) but reports exactly which stage failed, eg. to show to users.
+	#[inline]
+	pub fn from_bytes_diagnose(bytes: &[u8], key: &Key) -> Result<MemoryEditor, ParseReport> {
+		from_bytes(bytes, key, false).map(|((blocks, directory, key_slots, block_shift), magic)| MemoryEditor { blocks, directory, magic, block_shift, inline_threshold: 0, key_slots, nonces: None, generate_id: false })
+	}
 
-		match from_blocks(blocks, key) {
-			Ok((blocks, directory)) => Ok(MemoryEditor { blocks, directory }),
-			Err(_) => unimplemented!(),
-		}
+	/// Parses the bytes as the PAKS file format for editing.
+	///
+	/// Like [`from_bytes_diagnose`](Self::from_bytes_diagnose) but rejects unused blocks trailing the PAKS file with [`ParseReport::TrailingData`].
+	#[inline]
+	pub fn from_bytes_strict(bytes: &[u8], key: &Key) -> Result<MemoryEditor, ParseReport> {
+		from_bytes(bytes, key, true).map(|((blocks, directory, key_slots, block_shift), magic)| MemoryEditor { blocks, directory, magic, block_shift, inline_threshold: 0, key_slots, nonces: None, generate_id: false })
 	}
 
 	/// Parses the blocks as the PAKS file format for editing.
+	///
+	/// Unused blocks trailing the PAKS file are ignored.
 	pub fn from_blocks(blocks: Vec<Block>, key: &Key) -> Result<MemoryEditor, Vec<Block>> {
-		from_blocks(blocks, key).map(|(blocks, directory)| MemoryEditor { blocks, directory })
+		let magic = identify(dataview::bytes(blocks.as_slice())) != Identify::Unknown;
+		from_blocks(blocks, key, false).map(|(blocks, directory, key_slots, block_shift)| MemoryEditor { blocks, directory, magic, block_shift, inline_threshold: 0, key_slots, nonces: None, generate_id: false }).map_err(|(blocks, _)| blocks)
+	}
+}
+
+impl From<MemoryReader> for MemoryEditor {
+	/// Continues editing the PAKS file of the reader.
+	///
+	/// The blocks are copied as they may be shared with clones of the reader.
+	/// The reader does not keep the key slots of the PAKS file, the editor starts without key slots.
+	#[inline]
+	fn from(reader: MemoryReader) -> MemoryEditor {
+		let MemoryReader { blocks, directory, magic, block_shift, .. } = reader;
+		MemoryEditor { blocks: blocks.to_vec(), directory: Arc::unwrap_or_clone(directory), magic, block_shift, inline_threshold: 0, key_slots: Vec::new(), nonces: None, generate_id: false }
 	}
 }
 
@@ -73,49 +108,430 @@ impl MemoryEditor {
 		self.blocks.len() as u32
 	}
 
+	/// Returns if the PAKS file is written with the plaintext magic signature.
+	#[inline]
+	pub fn has_magic(&self) -> bool {
+		self.magic
+	}
+
+	/// Sets whether the PAKS file is written with the plaintext magic signature.
+	///
+	/// New PAKS files are written with the magic signature, existing PAKS files keep their setting.
+	/// Without the magic signature (stealth mode) the PAKS file is indistinguishable from random data, see [`identify`].
+	#[inline]
+	pub fn set_magic(&mut self, magic: bool) -> &mut MemoryEditor {
+		self.magic = magic;
+		self
+	}
+
+	/// Returns the block size of the PAKS file as a shift of the cipher block size.
+	#[inline]
+	pub fn block_shift(&self) -> u32 {
+		self.block_shift
+	}
+
+	/// Sets the block size of the PAKS file to `BLOCK_SIZE << block_shift` bytes.
+	///
+	/// The cipher block of 16 bytes is the smallest block size, small files never waste more than 15 bytes of padding and are better stored inline or bundled.
+	/// Archives of large assets can use a larger block size, eg. a block shift of `8` for 4 KiB aligned reads with higher throughput.
+	/// The block shift is limited to [`InfoHeader::MAX_BLOCK_SHIFT`].
+	///
+	/// New sections are allocated at multiples of the block size, existing sections keep their place until collected with [`gc`](Self::gc) or reordered with [`reorder`](Self::reorder).
+	/// The block size is recorded in the header, see [`InfoHeader::block_shift`], existing PAKS files keep their block size.
+	#[inline]
+	pub fn set_block_shift(&mut self, block_shift: u32) -> &mut MemoryEditor {
+		self.block_shift = u32::min(block_shift, InfoHeader::MAX_BLOCK_SHIFT);
+		self
+	}
+
+	/// Returns the size in bytes up to which new files are stored inline.
+	#[inline]
+	pub fn inline_threshold(&self) -> usize {
+		self.inline_threshold
+	}
+
+	/// Sets the size in bytes up to which new files are stored inline in their descriptor, see [`Descriptor::FLAG_INLINE`].
+	///
+	/// Files created with [`create_file`](Self::create_file) no larger than the threshold occupy no blocks, eg. thousands of tiny metadata files.
+	/// The threshold is limited to [`Descriptor::INLINE_LEN`] bytes, a threshold of zero (the default) disables inlining.
+	/// Reading inline files is transparent.
+	///
+	/// The threshold is not stored in the PAKS file.
+	#[inline]
+	pub fn set_inline_threshold(&mut self, len: usize) -> &mut MemoryEditor {
+		self.inline_threshold = usize::min(len, Descriptor::INLINE_LEN);
+		self
+	}
+
+	/// Installs the source of the nonces of the sections encrypted by this editor, see [`NonceSource`].
+	///
+	/// Without a nonce source the nonces are random, see [`SystemNonces`].
+	/// Install a [`SeededNonces`] to produce reproducible PAKS files in tests.
+	#[inline]
+	pub fn set_nonce_source(&mut self, nonces: Arc<dyn NonceSource>) -> &mut MemoryEditor {
+		self.nonces = Some(nonces);
+		self
+	}
+
+	/// Returns the nonce source, if any.
+	#[inline]
+	pub fn nonce_source(&self) -> Option<&dyn NonceSource> {
+		self.nonces.as_deref()
+	}
+
+	/// Generates a random archive id, see [`ArchiveId`].
+	///
+	/// New PAKS files get an archive id when they are finished, the archive id is kept by every edit afterwards.
+	/// Call this to give a copy of a PAKS archive its own identity.
+	/// The archive id is taken from the nonce source, PAKS files created with [`SeededNonces`] get reproducible archive ids.
+	///
+	/// Fails with any error of the nonce source and leaves the archive id unchanged, see [`NonceSource`].
+	pub fn generate_archive_id(&mut self) -> Result<ArchiveId, Error> {
+		let id = ArchiveId(crypt::next_nonce(self.nonces.as_deref())?);
+		self.directory.set_archive_id(id);
+		Ok(id)
+	}
+
+	/// Returns the key slots of the PAKS file.
+	#[inline]
+	pub fn key_slots(&self) -> &[KeySlot] {
+		&self.key_slots
+	}
+
+	/// Adds a key slot which wraps the key of the PAKS file with the user key.
+	///
+	/// The user key then unlocks the key of the PAKS file with [`unlock_key`], the PAKS file is not reencrypted.
+	/// The key must be the key the PAKS file is finished with, any existing key slot of the user key is replaced.
+	///
+	/// Fails with any error of the nonce source and leaves the key slots unchanged, see [`NonceSource`].
+	pub fn add_key_slot(&mut self, user_key: &Key, key: &Key) -> Result<&mut MemoryEditor, Error> {
+		let key_slot = KeySlot::wrap_from(self.nonces.as_deref(), key, user_key)?;
+		keyslot::remove(&mut self.key_slots, user_key);
+		self.key_slots.push(key_slot);
+		Ok(self)
+	}
+
+	/// Removes the key slot of the user key.
+	///
+	/// Returns false if the user key has no key slot.
+	///
+	/// The user key can no longer unlock the key of the PAKS file, but anyone who unlocked it before keeps access to the PAKS file.
+	/// Reencrypt the PAKS file with a new key to revoke that access, see [`migrate`].
+	pub fn remove_key_slot(&mut self, user_key: &Key) -> bool {
+		keyslot::remove(&mut self.key_slots, user_key)
+	}
+
 	/// Creates a file descriptor at the given path.
 	///
 	/// Any missing parent directories are automatically created.
-	pub fn edit_file(&mut self, path: &[u8]) -> MemoryEditFile<'_> {
-		let desc = self.directory.create(path);
+	///
+	/// Returns an error if the path is invalid, see [`validate_path`].
+	pub fn edit_file(&mut self, path: &[u8]) -> Result<MemoryEditFile<'_>, PathError> {
+		let alignment = 1 << self.block_shift;
+		let desc = self.directory.create(path)?;
 		let blocks = &mut self.blocks;
-		MemoryEditFile { blocks, desc }
+		Ok(MemoryEditFile { blocks, desc, alignment, nonces: self.nonces.as_deref() })
+	}
+
+	// Edits the archive metadata, its paths are reserved and cannot be edited with edit_file
+	pub(crate) fn edit_metadata(&mut self, path: &[u8]) -> MemoryEditFile<'_> {
+		let alignment = 1 << self.block_shift;
+		let desc = self.directory.create_metadata(path);
+		let blocks = &mut self.blocks;
+		MemoryEditFile { blocks, desc, alignment, nonces: self.nonces.as_deref() }
 	}
 
 	/// Creates a file at the given path.
 	///
 	/// The file is assigned a content_type of `1`.
 	/// A new section is allocated and the data is encrypted and written into the section.
+	/// Replacing a plaintext file stores the new contents encrypted.
 	///
 	/// Any missing parent directories are automatically created.
 	///
 	/// If the data's len is greater than 4 GiB it is truncated as its size is stored in a `u32`.
-	pub fn create_file(&mut self, path: &[u8], data: &[u8], key: &Key) -> &Descriptor {
-		let mut edit_file = self.edit_file(path);
-		edit_file.set_content(1, data.len() as u32);
-		edit_file.allocate_data().write_data(data, key);
-		edit_file.desc
+	///
+	/// Returns an error if the path is invalid, see [`validate_path`].
+	pub fn create_file(&mut self, path: &[u8], data: &[u8], key: &Key) -> Result<&Descriptor, Error> {
+		self.create_typed(path, 1, data, key)
+	}
+
+	/// Creates a file at the given path with the given behavior if the path already exists.
+	///
+	/// See [`create_file`](Self::create_file) and [`append`](Self::append).
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::AlreadyExists`]: The mode is [`CreateMode::CreateNew`] and a file or directory exists at the path.
+	/// * [`ErrorKind::InvalidData`]: The mode is [`CreateMode::Delta`] and the existing file contents failed authentication.
+	pub fn create_file_with(&mut self, path: &[u8], data: &[u8], mode: CreateMode, key: &Key) -> Result<&Descriptor, Error> {
+		match mode {
+			CreateMode::CreateNew if self.find_desc(path).is_some() => Err(Error::with_path(ErrorKind::AlreadyExists, path)),
+			CreateMode::CreateNew | CreateMode::Overwrite => self.create_typed(path, 1, data, key),
+			CreateMode::Append => self.append(path, data, key),
+			CreateMode::Delta => self.create_delta(path, data, key),
+		}
+	}
+
+	fn create_delta(&mut self, path: &[u8], data: &[u8], key: &Key) -> Result<&Descriptor, Error> {
+		let old = match self.directory.find_file(path) {
+			Some(desc) if data.len() > self.inline_threshold && u32::try_from(data.len()).is_ok() && delta::is_base(&self.directory, desc) => *desc,
+			_ => return self.create_typed(path, 1, data, key),
+		};
+		let blocks = &self.blocks;
+		let base = delta::base_of(&old, |section| read_section(blocks, section, key)).map_err(|kind| Error::with_path(kind, path))?;
+		let base_blocks = read_section(blocks, &base.section, key).map_err(|kind| Error::with_path(kind, path))?;
+		let base_data = base.data(&base_blocks).ok_or(Error::with_path(ErrorKind::InvalidData, path))?;
+		let payload = match delta::encode(&base, base_data, data) {
+			Some(payload) => payload,
+			None => return self.create_typed(path, 1, data, key),
+		};
+
+		let mut edit_file = self.edit_file(path).map_err(|err| Error::from(err).at(path))?;
+		edit_file.set_content(1, payload.len() as u32);
+		edit_file.allocate_data().write_data(&payload, key).map_err(|err| err.at(path))?;
+		edit_file.desc.content_size = data.len() as u32;
+		// Files with extension records are never the base of a delta
+		let is_delta = edit_file.desc.set_delta_base(base.section.offset, base.section.size);
+		debug_assert!(is_delta);
+		Ok(edit_file.desc)
+	}
+
+	/// Serializes the value to JSON and creates a file at the given path.
+	///
+	/// The file is assigned a content_type of [`Descriptor::CONTENT_TYPE_JSON`], otherwise the file is created like [`create_file`](Self::create_file).
+	/// If the value cannot be serialized, [`ErrorKind::InvalidInput`] is returned with the path attached.
+	#[cfg(feature = "json")]
+	pub fn create_json<T: ?Sized + serde::Serialize>(&mut self, path: &[u8], value: &T, key: &Key) -> Result<&Descriptor, Error> {
+		let data = serde_json::to_vec(value).map_err(|_| Error::with_path(ErrorKind::InvalidInput, path))?;
+		self.create_typed(path, Descriptor::CONTENT_TYPE_JSON, &data, key)
+	}
+
+	/// Serializes the value to TOML and creates a file at the given path.
+	///
+	/// The file is assigned a content_type of [`Descriptor::CONTENT_TYPE_TOML`], otherwise the file is created like [`create_file`](Self::create_file).
+	/// If the value cannot be serialized, [`ErrorKind::InvalidInput`] is returned with the path attached.
+	#[cfg(feature = "toml")]
+	pub fn create_toml<T: ?Sized + serde::Serialize>(&mut self, path: &[u8], value: &T, key: &Key) -> Result<&Descriptor, Error> {
+		let data = toml::to_vec(value).map_err(|_| Error::with_path(ErrorKind::InvalidInput, path))?;
+		self.create_typed(path, Descriptor::CONTENT_TYPE_TOML, &data, key)
+	}
+
+	fn create_typed(&mut self, path: &[u8], content_type: u32, data: &[u8], key: &Key) -> Result<&Descriptor, Error> {
+		let inline = data.len() <= self.inline_threshold;
+		let edit_file = self.edit_file(path).map_err(|err| Error::from(err).at(path))?;
+		write_typed(edit_file, content_type, data, inline, key).map_err(|err| err.at(path))
+	}
+
+	// Stores the archive metadata like create_file
+	fn set_metadata(&mut self, path: &[u8], data: &[u8], key: &Key) -> Result<(), Error> {
+		let inline = data.len() <= self.inline_threshold;
+		let edit_file = self.edit_metadata(path);
+		write_typed(edit_file, 1, data, inline, key).map_err(|err| err.at(path))?;
+		Ok(())
+	}
+
+	/// Creates many small files sharing a single section, see [`Descriptor::FLAG_BUNDLED`].
+	///
+	/// The files are assigned a content_type of `1`.
+	/// The contents of the files are concatenated, encrypted and written into one newly allocated section.
+	/// Each file descriptor records the byte offset of its contents within the shared section.
+	/// Bundling folders of tiny files saves the nonce, MAC and padding of a section per file.
+	///
+	/// Any missing parent directories are automatically created.
+	///
+	/// Returns an error if any of the paths is invalid, see [`validate_path`].
+	/// No files are created unless all the paths are valid.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::InvalidInput`]: The files are larger than 4 GiB in total.
+	pub fn create_bundle(&mut self, files: &[(&[u8], &[u8])], key: &Key) -> Result<(), Error> {
+		// Check the paths before writing anything
+		for &(path, _) in files {
+			validate_path_with(path, self.directory.separators()).map_err(|err| Error::from(err).at(path))?;
+		}
+		let content_size = match u32::try_from(files.iter().map(|(_, data)| data.len()).sum::<usize>()) {
+			Ok(content_size) => content_size,
+			Err(_) => return Err(Error::from(ErrorKind::InvalidInput)),
+		};
+
+		// Encrypt the concatenated file contents into the shared section
+		let bufs: Vec<_> = files.iter().map(|&(_, data)| std::io::IoSlice::new(data)).collect();
+		let mut bundle = Descriptor::default();
+		MemoryEditFile { desc: &mut bundle, blocks: &mut self.blocks, alignment: 1 << self.block_shift, nonces: self.nonces.as_deref() }
+			.set_content(1, content_size)
+			.allocate_data()
+			.write_data_vectored(&bufs, key)?;
+
+		let mut data_offset = 0;
+		for &(path, data) in files {
+			let desc = self.directory.create_lenient(path);
+			desc.content_type = 1;
+			desc.content_size = data.len() as u32;
+			desc.set_bundled(&bundle.section, data_offset);
+			data_offset += data.len() as u32;
+		}
+		Ok(())
+	}
+
+	/// Appends data to the end of a file.
+	///
+	/// The file is created with a content_type of `1` if it does not exist.
+	/// The existing contents are decrypted and re-encrypted together with the data into a new larger section.
+	/// The old section is left behind as garbage, links to the file keep referring to the old contents.
+	///
+	/// Returns an error if the path is invalid, see [`validate_path`].
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::InvalidInput`]: The path refers to a directory or the file would grow larger than 4 GiB.
+	/// * [`ErrorKind::InvalidData`]: The existing file contents failed authentication.
+	pub fn append(&mut self, path: &[u8], data: &[u8], key: &Key) -> Result<&Descriptor, Error> {
+		let (content_type, old_data) = match self.find_desc(path) {
+			Some(desc) if desc.is_file() => (desc.content_type, self.read(path, key)?),
+			Some(_) => return Err(Error::with_path(ErrorKind::InvalidInput, path)),
+			None => (1, Vec::new()),
+		};
+		let content_size = match u32::try_from(old_data.len() + data.len()) {
+			Ok(content_size) => content_size,
+			Err(_) => return Err(Error::with_path(ErrorKind::InvalidInput, path)),
+		};
+
+		let mut edit_file = self.edit_file(path).map_err(|err| Error::from(err).at(path))?;
+		edit_file.set_content(content_type, content_size);
+		edit_file.allocate_data().write_data_vectored(&[std::io::IoSlice::new(&old_data), std::io::IoSlice::new(data)], key).map_err(|err| err.at(path))?;
+		Ok(edit_file.desc)
+	}
+
+	/// Overwrites part of a file's contents.
+	///
+	/// The bytes starting at the byte offset are replaced with the data and the patched contents are written to a newly allocated section.
+	/// Inline file contents are patched in the descriptor.
+	/// The file cannot grow this way, use [`truncate`](MemoryEditFile::truncate) to resize the file first.
+	///
+	/// Links to the file keep the previous contents, like [`append`](Self::append).
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::NotFound`]: No file exists at the path.
+	/// * [`ErrorKind::InvalidInput`]: The data does not fit within the file's contents.
+	/// * [`ErrorKind::InvalidData`]: The file contents failed authentication.
+	pub fn write_at(&mut self, path: &[u8], byte_offset: usize, data: &[u8], key: &Key) -> Result<(), Error> {
+		let inline = match self.directory.find_file(path) {
+			Some(desc) if byte_offset.checked_add(data.len()).is_some_and(|end| end <= desc.content_size as usize) => desc.inline_data().map(<[u8]>::to_vec),
+			Some(_) => return Err(Error::with_path(ErrorKind::InvalidInput, path)),
+			None => return Err(Error::with_path(ErrorKind::NotFound, path)),
+		};
+
+		// Inline file contents are patched in the descriptor
+		if let Some(mut contents) = inline {
+			contents[byte_offset..byte_offset + data.len()].copy_from_slice(data);
+			if let Some(desc) = self.directory.find_id(path).and_then(|id| self.directory.resolve_mut(id)) {
+				desc.set_inline_data(&contents);
+			}
+			return Ok(());
+		}
+
+		// Linked contents are never overwritten, the patched contents are copied to a new section
+		let mut contents = self.read(path, key)?;
+		contents[byte_offset..byte_offset + data.len()].copy_from_slice(data);
+		let mut edit_file = self.edit_file(path).map_err(|err| Error::from(err).at(path))?;
+		let desc = *edit_file.desc;
+		let result = edit_file.allocate_data().write_data(&contents, key).map(|_| ());
+
+		// Leave the existing file contents alone if no nonce is available
+		if result.is_err() {
+			*edit_file.desc = desc;
+		}
+		result.map_err(|err| err.at(path))
+	}
+
+	/// Reads the preload list, see [`PRELOAD_PATH`].
+	///
+	/// See [`MemoryReader::preload_list`] for more information.
+	pub fn preload_list(&self, key: &Key) -> Result<Vec<Vec<u8>>, Error> {
+		match self.find_file(PRELOAD_PATH) {
+			Some(desc) => Ok(preload::decode(&self.read_data(desc, key)?)),
+			None => Ok(Vec::new()),
+		}
+	}
+
+	/// Stores the preload list, see [`PRELOAD_PATH`].
+	///
+	/// The paths are stored in the given order, an empty list removes the preload list.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::NotFound`]: A path does not refer to a file.
+	/// * [`ErrorKind::InvalidInput`]: A path contains a newline.
+	pub fn set_preload_list(&mut self, paths: &[&[u8]], key: &Key) -> Result<(), Error> {
+		if paths.is_empty() {
+			self.directory.remove_metadata(PRELOAD_PATH);
+			return Ok(());
+		}
+		let data = preload::encode(&self.directory, paths)?;
+		self.set_metadata(PRELOAD_PATH, &data, key)
+	}
+
+	/// Reads the names of the content types, see [`CONTENT_TYPES_PATH`].
+	///
+	/// See [`MemoryReader::content_types`] for more information.
+	pub fn content_types(&self, key: &Key) -> Result<ContentTypes, Error> {
+		match self.find_file(CONTENT_TYPES_PATH) {
+			Some(desc) => Ok(content_types::decode(&self.read_data(desc, key)?)),
+			None => Ok(ContentTypes::new()),
+		}
+	}
+
+	/// Stores the names of the content types, see [`CONTENT_TYPES_PATH`].
+	///
+	/// An empty table removes the names.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::InvalidInput`]: The content type zero is named or a name is empty, contains a newline or starts or ends with whitespace.
+	pub fn set_content_types(&mut self, content_types: &ContentTypes, key: &Key) -> Result<(), Error> {
+		if content_types.is_empty() {
+			self.directory.remove_metadata(CONTENT_TYPES_PATH);
+			return Ok(());
+		}
+		let data = content_types::encode(content_types)?;
+		self.set_metadata(CONTENT_TYPES_PATH, &data, key)
 	}
 
 	/// Reads the contents of a file from the PAKS archive.
-	pub fn read(&self, path: &[u8], key: &Key) -> Result<Vec<u8>, ErrorKind> {
+	pub fn read(&self, path: &[u8], key: &Key) -> Result<Vec<u8>, Error> {
 		let desc = match self.find_file(path) {
 			Some(desc) => desc,
-			None => return Err(ErrorKind::NotFound),
+			None => return Err(Error::with_path(ErrorKind::NotFound, path)),
 		};
 
-		self.read_data(desc, key)
+		read_data(&self.blocks, desc, key).map_err(|kind| Error::with_path(kind, path))
 	}
 
 	/// Reads the contents of a file from the PAKS archive into a string.
-	pub fn read_to_string(&self, path: &[u8], key: &Key) -> Result<String, ErrorKind> {
-		let desc = match self.find_file(path) {
-			Some(desc) => desc,
-			None => return Err(ErrorKind::NotFound),
-		};
+	pub fn read_to_string(&self, path: &[u8], key: &Key) -> Result<String, Error> {
+		let data = self.read(path, key)?;
+		String::from_utf8(data).map_err(|_| Error::with_path(ErrorKind::InvalidData, path))
+	}
 
-		let data = self.read_data(desc, key)?;
-		String::from_utf8(data).map_err(|_| ErrorKind::InvalidData)
+	/// Reads the contents of a file from the PAKS archive and deserializes it from JSON.
+	///
+	/// If the contents are not valid JSON for the type, [`ErrorKind::InvalidData`] is returned with the path attached.
+	#[cfg(feature = "json")]
+	pub fn read_json<T: serde::de::DeserializeOwned>(&self, path: &[u8], key: &Key) -> Result<T, Error> {
+		let data = self.read(path, key)?;
+		serde_json::from_slice(&data).map_err(|_| Error::with_path(ErrorKind::InvalidData, path))
+	}
+
+	/// Reads the contents of a file from the PAKS archive and deserializes it from TOML.
+	///
+	/// If the contents are not valid TOML for the type, [`ErrorKind::InvalidData`] is returned with the path attached.
+	#[cfg(feature = "toml")]
+	pub fn read_toml<T: serde::de::DeserializeOwned>(&self, path: &[u8], key: &Key) -> Result<T, Error> {
+		let data = self.read(path, key)?;
+		toml::from_slice(&data).map_err(|_| Error::with_path(ErrorKind::InvalidData, path))
 	}
 
 	/// Decrypts the section.
@@ -127,24 +543,52 @@ impl MemoryEditor {
 	/// * [`ErrorKind::InvalidInput`]: The the descriptor is not a file descriptor.
 	/// * [`ErrorKind::InvalidData`]: The file's MAC is incorrect, the file is corrupted.
 	#[inline]
-	pub fn read_section(&self, section: &Section, key: &Key) -> Result<Vec<Block>, ErrorKind> {
-		read_section(&self.blocks, section, key)
+	pub fn read_section(&self, section: &Section, key: &Key) -> Result<Vec<Block>, Error> {
+		read_section(&self.blocks, section, key).map_err(Error::new)
+	}
+
+	/// Decrypts the section into the given blocks.
+	///
+	/// Reuse the blocks buffer to avoid an allocation for every section when reading many sections.
+	///
+	/// See [`read_section`](Self::read_section) for more information.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::InvalidInput`]: The length of the blocks is not equal to the section size.
+	#[inline]
+	pub fn read_section_into(&self, section: &Section, key: &Key, blocks: &mut [Block]) -> Result<(), Error> {
+		read_section_into(&self.blocks, section, key, blocks).map_err(Error::new)
 	}
 
 	/// Decrypts the contents of the given file descriptor.
 	///
+	/// Plaintext file contents are returned as-is, see [`Descriptor::FLAG_PLAINTEXT`].
+	///
 	/// See [`read_section`](Self::read_section) for more information.
 	#[inline]
-	pub fn read_data(&self, desc: &Descriptor, key: &Key) -> Result<Vec<u8>, ErrorKind> {
-		read_data(&self.blocks, desc, key)
+	pub fn read_data(&self, desc: &Descriptor, key: &Key) -> Result<Vec<u8>, Error> {
+		read_data(&self.blocks, desc, key).map_err(Error::new)
 	}
 
 	/// Decrypts the contents of the given file descriptor into the dest buffer.
 	///
+	/// The bytes starting at the byte offset are decrypted directly into the dest buffer without any intermediate allocation.
+	/// The whole section is still authenticated, if the authentication fails the dest buffer is zeroed.
+	/// Plaintext file contents are copied as-is.
+	///
 	/// See [`read_section`](Self::read_section) for more information.
 	#[inline]
-	pub fn read_data_into(&self, desc: &Descriptor, key: &Key, byte_offset: usize, dest: &mut [u8]) -> Result<(), ErrorKind> {
-		read_data_into(&self.blocks, desc, key, byte_offset, dest)
+	pub fn read_data_into(&self, desc: &Descriptor, key: &Key, byte_offset: usize, dest: &mut [u8]) -> Result<(), Error> {
+		read_data_into(&self.blocks, desc, key, byte_offset, uninit_bytes(dest)).map(|_| ()).map_err(Error::new)
+	}
+
+	/// Borrows the contents of the given plaintext file descriptor without copying.
+	///
+	/// Returns `None` if the descriptor is not a plaintext file descriptor or its section is out of bounds.
+	#[inline]
+	pub fn plaintext_data(&self, desc: &Descriptor) -> Option<&[u8]> {
+		plaintext_data(&self.blocks, desc)
 	}
 
 	/// Compacts the referenced data blocks from file descriptors.
@@ -152,17 +596,92 @@ impl MemoryEditor {
 	/// Removing files only removes their descriptors, leaving unreadable garbage around.
 	/// The cryptographic nonce has been erased making it no longer possible to recover the file data.
 	/// This method reclaims the space left behind by deleted files.
+	/// Linked and bundled files keep sharing their section, the base sections of delta files are kept.
 	///
 	/// Any file descriptors with an invalid section object has their section object zeroed.
+	///
+	/// The blocks are compacted in place, peak memory stays at the size of the PAKS file.
+	/// The sections end up in the order of their offsets, see [`reorder`](Self::reorder) to place them in another order.
 	pub fn gc(&mut self) {
-		let mut blocks = vec![Block::default(); Header::BLOCKS_LEN];
+		span!(DEBUG, "gc", blocks = self.blocks.len(), descriptors = self.directory.len());
+
+		let len = self.blocks.len();
+		let valid = |offset: u32, size: u32| {
+			let end = offset as usize + size as usize;
+			(size == 0 || offset as usize >= Header::BLOCKS_LEN) && end <= len
+		};
+
+		// Every valid section referenced by the files, the base sections of delta files may be shared with other files
+		let mut sections = Vec::new();
+		for desc in self.directory.iter() {
+			if let Some((offset, size)) = desc.delta_base() {
+				sections.push((offset, size));
+			}
+			if desc.is_file() && !desc.is_inline() {
+				sections.push((desc.section.offset, desc.section.size));
+			}
+		}
+		sections.retain(|&(offset, size)| valid(offset, size));
+		sections.sort_unstable();
+		sections.dedup();
+
+		// Overlapping sections are moved together as one span
+		let mut spans: Vec<GcSpan> = Vec::new();
+		for &(offset, size) in &sections {
+			let (start, end) = (offset as usize, offset as usize + size as usize);
+			match spans.last_mut() {
+				Some(span) if start < span.end => span.end = cmp::max(span.end, end),
+				_ => spans.push(GcSpan { start, end, dest: 0 }),
+			}
+		}
+
+		// Slide the spans down over the garbage, the spans are sorted so no data is overwritten before it is moved
+		let mut pos = Header::BLOCKS_LEN;
+		for span in &mut spans {
+			self.blocks.copy_within(span.start..span.end, pos);
+			span.dest = pos;
+			pos += span.end - span.start;
+		}
 
+		// Then move the spans up to their aligned offsets, starting at the end so no data is overwritten before it is moved
+		let mut aligned = Vec::with_capacity(spans.len());
+		let mut end = Header::BLOCKS_LEN;
+		for span in &spans {
+			let offset = end.next_multiple_of(1 << self.block_shift);
+			aligned.push(offset);
+			end = offset + (span.end - span.start);
+		}
+		self.blocks.resize(cmp::max(end, pos), Block::default());
+		for (span, &offset) in spans.iter().zip(&aligned).rev() {
+			self.blocks.copy_within(span.dest..span.dest + (span.end - span.start), offset);
+		}
+
+		// Zero the padding, it holds stale data of the moved sections
+		let mut padding = Header::BLOCKS_LEN;
+		for (span, &offset) in spans.iter().zip(&aligned) {
+			self.blocks[padding..offset].fill(Block::default());
+			padding = offset + (span.end - span.start);
+		}
+		self.blocks.truncate(end);
+		self.blocks.shrink_to_fit();
+
+		// Relocate the sections, sections inside a span keep their place relative to the span
+		let relocate = |offset: u32| {
+			let index = spans.partition_point(|span| span.start <= offset as usize) - 1;
+			(aligned[index] + (offset as usize - spans[index].start)) as u32
+		};
 		for desc in self.directory.as_mut() {
-			if desc.is_file() {
-				let offset = blocks.len();
-				if let Some(data) = self.blocks.get(desc.section.range_usize()) {
-					blocks.extend_from_slice(data);
-					desc.section.offset = offset as u32;
+			if let Some((offset, size)) = desc.delta_base() {
+				if !valid(offset, size) {
+					desc.clear_storage();
+					desc.section = Section::default();
+					continue;
+				}
+				desc.set_delta_base(relocate(offset), size);
+			}
+			if desc.is_file() && !desc.is_inline() {
+				if valid(desc.section.offset, desc.section.size) {
+					desc.section.offset = relocate(desc.section.offset);
 				}
 				else {
 					// Not much to do when we find an invalid descriptor...
@@ -170,64 +689,154 @@ impl MemoryEditor {
 				}
 			}
 		}
+	}
+
+	/// Rewrites the data blocks with the sections placed in the given access order.
+	///
+	/// The files are laid out in the order of the paths, followed by the remaining files in directory order.
+	/// Reading the files in access order then streams through the PAKS file without seeking, eg. on HDD or optical media.
+	/// Paths which do not refer to a file are ignored, the order may be stale, see [`preload_list`](MemoryReader::preload_list).
+	///
+	/// Links keep sharing their section, all descriptors are preserved.
+	/// Garbage left behind by removed files is collected along the way, see [`gc`](Self::gc).
+	pub fn reorder(&mut self, order: &[&[u8]]) {
+		span!(DEBUG, "reorder", blocks = self.blocks.len(), paths = order.len());
+
+		let mut blocks = vec![Block::default(); Header::BLOCKS_LEN];
+
+		// Place every section once, linked files share their section
+		let mut offsets = HashMap::new();
+		let ordered = order.iter().filter_map(|path| self.directory.find_file(path));
+		let sections = ordered.chain(self.directory.iter()).filter(|desc| desc.is_file() && !desc.is_inline()).flat_map(|desc| {
+			// The base section of a delta file is placed right before the delta
+			let base = desc.delta_base().map(|(offset, size)| Section { offset, size, ..Section::default() });
+			base.into_iter().chain([desc.section])
+		});
+		for section in sections {
+			if let hash_map::Entry::Vacant(entry) = offsets.entry((section.offset, section.size)) {
+				if let Some(data) = self.blocks.get(section.range_usize()) {
+					let offset = blocks.len().next_multiple_of(1 << self.block_shift);
+					blocks.resize(offset, Block::default());
+					entry.insert(offset as u32);
+					blocks.extend_from_slice(data);
+				}
+			}
+		}
+
+		for desc in self.directory.as_mut() {
+			if let Some((offset, size)) = desc.delta_base() {
+				match offsets.get(&(offset, size)) {
+					Some(&offset) => {
+						desc.set_delta_base(offset, size);
+					},
+					None => {
+						desc.clear_storage();
+						desc.section = Section::default();
+						continue;
+					},
+				}
+			}
+			if desc.is_file() && !desc.is_inline() {
+				match offsets.get(&(desc.section.offset, desc.section.size)) {
+					Some(&offset) => desc.section.offset = offset,
+					// Not much to do when we find an invalid descriptor...
+					None => desc.section = Section::default(),
+				}
+			}
+		}
 
 		self.blocks = blocks;
 	}
 
+	/// Replaces the names with the hash of their path to produce a release PAKS file which leaks no file naming.
+	///
+	/// Files are found afterwards with [`Directory::find_by_hash`], see [`Descriptor::FLAG_STRIPPED_NAME`].
+	/// Stripping the names again is harmless, the hashes of the original paths are kept.
+	/// The archive metadata keeps its name, see [`ARCHIVE_ID_PATH`], [`PRELOAD_PATH`] and [`CONTENT_TYPES_PATH`].
+	/// Collect the garbage with [`gc`](Self::gc) before finishing, the blocks may contain previous directories with the original names.
+	pub fn strip_names(&mut self) {
+		dir::strip_names(self.directory.as_mut());
+	}
+
 	/// Finish editing the PAKS file.
 	///
-	/// Initializes the header, encrypts the directory and appends it to the blocks.
+	/// Initializes the header, encrypts the directory and appends it to the blocks followed by the key slots.
 	/// Returns the encrypted PAKS file and the unencrypted directory for inspection.
-	pub fn finish(self, key: &Key) -> (Vec<Block>, Directory) {
-		let MemoryEditor { mut blocks, directory } = self;
-
-		{
-			// Ensure enough room for the header ref$1
-			if blocks.len() < Header::BLOCKS_LEN {
-				let padding = &[[0, 0]; Header::BLOCKS_LEN];
-				blocks.extend_from_slice(&padding[..Header::BLOCKS_LEN - blocks.len()]);
-			}
+	/// Generates the archive id of a new PAKS file, see [`generate_archive_id`](Self::generate_archive_id).
+	/// Increments the generation of PAKS files with an archive id, see [`Directory::archive_generation`].
+	///
+	/// Fails with any error of the nonce source, see [`NonceSource`].
+	pub fn finish(mut self, key: &Key) -> Result<(Vec<Block>, Directory), Error> {
+		span!(DEBUG, "finish", blocks = self.blocks.len(), descriptors = self.directory.len());
 
-			// Keep track if the highest block index before the directory starts
-			let high_mark = blocks.len();
-			let dir_size = directory.len();
-
-			// Append the directory (unencrypted)
-			blocks.extend_from_slice(directory.as_blocks());
-
-			// Satisfy the borrow checker
-			let (blocks, directory) = blocks.split_at_mut(high_mark);
-
-			// Safety: We've ensured there's at least enough blocks for the header before the high_mark
-			let header: &mut Header = dataview::DataView::from_mut(blocks).get_mut(0);
-
-			// Write a template header
-			*header = Header {
-				nonce: Block::default(),
-				mac: Block::default(),
-				info: InfoHeader {
-					version: InfoHeader::VERSION,
-					_unused: 0,
-					directory: Section {
-						offset: high_mark as u32,
-						size: dir_size as u32,
-						nonce: Block::default(),
-						mac: Block::default(),
-					},
+		if self.generate_id && self.directory.archive_id().is_none() {
+			self.generate_archive_id()?;
+		}
+
+		let (header, dir_blocks) = self.seal(key)?;
+		let MemoryEditor { mut blocks, mut directory, key_slots, .. } = self;
+		directory.bump_archive_generation();
+
+		// Ensure enough room for the header ref$1
+		if blocks.len() < Header::BLOCKS_LEN {
+			blocks.resize(Header::BLOCKS_LEN, Block::default());
+		}
+
+		// Write the header and append the encrypted directory followed by the key slots
+		blocks[..Header::BLOCKS_LEN].copy_from_slice(header.as_ref());
+		blocks.extend_from_slice(&dir_blocks);
+		blocks.extend_from_slice(&keyslot::footer(&key_slots));
+
+		Ok((blocks, directory))
+	}
+
+	// Encrypts the header and the directory without consuming the editor.
+	// The directory is located right after the blocks, or the header if there are fewer blocks than the header.
+	pub(crate) fn seal(&self, key: &Key) -> Result<(Header, Vec<Block>), ErrorKind> {
+		// Keep track if the highest block index before the directory starts
+		let high_mark = usize::max(Header::BLOCKS_LEN, self.blocks.len());
+		let mut directory = self.directory.clone();
+		directory.bump_archive_generation();
+		let mut dir_blocks = directory.as_blocks().to_vec();
+
+		let mut header = Header {
+			nonce: Block::default(),
+			mac: Block::default(),
+			info: InfoHeader {
+				version: InfoHeader::VERSION,
+				key_check: 0,
+				directory: Section {
+					offset: high_mark as u32,
+					size: self.directory.len() as u32,
+					nonce: Block::default(),
+					mac: Block::default(),
 				},
-			};
+			},
+		};
 
-			// Encrypt the directory
-			crypt::encrypt_section(directory, &mut header.info.directory, key);
+		// Encrypt the directory
+		crypt::encrypt_section_from(self.nonces.as_deref(), &mut dir_blocks, &mut header.info.directory, key)?;
 
-			// Encrypt the header
-			let mut section = Header::SECTION;
-			crypt::encrypt_section(header.info.as_mut(), &mut section, key);
+		// Encrypt the header
+		crypt::encrypt_header_from(self.nonces.as_deref(), &mut header, key, self.magic, self.block_shift)?;
 
-			header.nonce = section.nonce;
-			header.mac = section.mac;
-		}
+		Ok((header, dir_blocks))
+	}
+}
+
+// Range of blocks moved as a whole by the garbage collector
+struct GcSpan {
+	start: usize,
+	end: usize,
+	// Offset after sliding the span down
+	dest: usize,
+}
 
-		(blocks, directory)
+fn write_typed<'a>(mut edit_file: MemoryEditFile<'a>, content_type: u32, data: &[u8], inline: bool, key: &Key) -> Result<&'a Descriptor, Error> {
+	edit_file.set_content(content_type, data.len() as u32).set_plaintext(false);
+	if inline && edit_file.set_inline_data(data) {
+		return Ok(edit_file.desc);
 	}
+	edit_file.allocate_data().write_data(data, key)?;
+	Ok(edit_file.desc)
 }
@@ -1,11 +1,20 @@
+use std::sync::Arc;
 use super::*;
 
 /// Memory reader.
 ///
 /// This implementation keeps the entire PAKS file in memory.
+///
+/// The blocks and the directory are reference counted, cloning the reader is cheap.
+/// Clones share one loaded PAKS file between threads and systems without copying it.
+/// Readers of a PAKS file embedded in the executable borrow its blocks instead, see [`from_static`](Self::from_static).
+#[derive(Clone)]
 pub struct MemoryReader {
-	blocks: Vec<Block>,
-	directory: Directory,
+	pub(super) blocks: Blocks,
+	pub(super) directory: Arc<Directory>,
+	pub(super) magic: bool,
+	pub(super) block_shift: u32,
+	pub(super) metrics: Option<Arc<dyn Metrics>>,
 }
 
 impl MemoryReader {
@@ -19,28 +28,131 @@ impl MemoryReader {
 	/// # Errors
 	///
 	/// * [`ErrorKind::InvalidInput`]: Bytes length is not a multiple of the block size.
-	/// * [`ErrorKind::InvalidData`]: Incorrect version info or authentication checks failed.
-	pub fn from_bytes(bytes: &[u8], key: &Key) -> Result<MemoryReader, ErrorKind> {
-		// The input bytes must be a multiple of the BLOCK_SIZE or this is nonsense
-		if bytes.len() % BLOCK_SIZE != 0 {
-			return Err(ErrorKind::InvalidInput);
+	/// * [`ErrorKind::PermissionDenied`]: The encryption key is incorrect.
+	/// * [`ErrorKind::InvalidData`]: The PAKS file is corrupted, authentication checks failed or the directory is invalid.
+	/// * [`ErrorKind::Unsupported`]: The file format version is not supported.
+	///
+	/// Unused blocks trailing the PAKS file are ignored, eg. padding added by a download server.
+	/// See [`from_bytes_strict`](Self::from_bytes_strict) to reject them.
+	#[inline]
+	pub fn from_bytes(bytes: &[u8], key: &Key) -> Result<MemoryReader, Error> {
+		from_bytes(bytes, key, false).map(|((blocks, directory, _, block_shift), magic)| MemoryReader { blocks: Blocks::Shared(blocks.into()), directory: Arc::new(directory), magic, block_shift, metrics: None }).map_err(Error::from)
+	}
+
+	/// Parses the bytes as the PAKS file format for reading.
+	///
+	/// Like [`from_bytes`](Self::from_bytes) but rejects unused blocks trailing the PAKS file with [`ErrorKind::InvalidData`].
+	#[inline]
+	pub fn from_bytes_strict(bytes: &[u8], key: &Key) -> Result<MemoryReader, Error> {
+		from_bytes(bytes, key, true).map(|((blocks, directory, _, block_shift), magic)| MemoryReader { blocks: Blocks::Shared(blocks.into()), directory: Arc::new(directory), magic, block_shift, metrics: None }).map_err(Error::from)
+	}
+
+	/// Parses the blocks as the PAKS file format for reading.
+	///
+	/// Unused blocks trailing the PAKS file are ignored.
+	pub fn from_blocks(blocks: Vec<Block>, key: &Key) -> Result<MemoryReader, Vec<Block>> {
+		let magic = identify(dataview::bytes(blocks.as_slice())) != Identify::Unknown;
+		from_blocks(blocks, key, false).map(|(blocks, directory, _, block_shift)| MemoryReader { blocks: Blocks::Shared(blocks.into()), directory: Arc::new(directory), magic, block_shift, metrics: None }).map_err(|(blocks, _)| blocks)
+	}
+
+	/// Parses the blocks in static memory as the PAKS file format for reading.
+	///
+	/// The blocks are borrowed rather than copied, eg. a PAKS file embedded in the executable with [`include_paks!`](crate::include_paks).
+	/// Only the directory is decrypted to the heap, the blocks are never modified.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::PermissionDenied`]: The encryption key is incorrect.
+	/// * [`ErrorKind::InvalidData`]: The PAKS file is corrupted, authentication checks failed or the directory is invalid.
+	/// * [`ErrorKind::Unsupported`]: The file format version is not supported.
+	pub fn from_static(blocks: &'static [Block], key: &Key) -> Result<MemoryReader, Error> {
+		let magic = identify(dataview::bytes(blocks)) != Identify::Unknown;
+		match load_directory(blocks, key) {
+			Ok((directory, block_shift)) => Ok(MemoryReader { blocks: Blocks::Static(blocks), directory: Arc::new(directory), magic, block_shift, metrics: None }),
+			Err(kind) => Err(Error::new(kind)),
 		}
+	}
 
-		// Allocate enough space to hold the blocks equivalent
-		// This is necessary as internal operations have alignment requirements
-		// Copy the input into these blocks
-		let mut blocks = vec![Block::default(); bytes.len() / BLOCK_SIZE];
-		dataview::bytes_mut(blocks.as_mut_slice())[..bytes.len()].copy_from_slice(bytes);
+	/// Moves the reader behind an [`Arc`] for APIs expecting shared ownership.
+	///
+	/// Cloning the reader is cheap as well, the clones share the blocks and the directory.
+	#[inline]
+	pub fn into_shared(self) -> Arc<MemoryReader> {
+		Arc::new(self)
+	}
+}
 
-		match from_blocks(blocks, key) {
-			Ok((blocks, directory)) => Ok(MemoryReader { blocks, directory }),
-			Err(_) => return Err(ErrorKind::InvalidData),
+impl From<MemoryEditor> for MemoryReader {
+	/// Commits the changes of the editor for reading.
+	///
+	/// The directory is not encrypted, see [`MemoryEditor::finish`] to get the encrypted PAKS file.
+	#[inline]
+	fn from(edit: MemoryEditor) -> MemoryReader {
+		let MemoryEditor { blocks, directory, magic, block_shift, .. } = edit;
+		MemoryReader { blocks: Blocks::Shared(blocks.into()), directory: Arc::new(directory), magic, block_shift, metrics: None }
+	}
+}
+
+// The blocks of the reader are either shared with its clones or borrowed from static memory
+#[derive(Clone)]
+pub(super) enum Blocks {
+	Shared(Arc<[Block]>),
+	Static(&'static [Block]),
+}
+
+impl ops::Deref for Blocks {
+	type Target = [Block];
+	#[inline]
+	fn deref(&self) -> &[Block] {
+		match self {
+			Blocks::Shared(blocks) => blocks,
+			Blocks::Static(blocks) => blocks,
 		}
 	}
+}
 
-	/// Parses the blocks as the PAKS file format for reading.
-	pub fn from_blocks(blocks: Vec<Block>, key: &Key) -> Result<MemoryReader, Vec<Block>> {
-		from_blocks(blocks, key).map(|(blocks, directory)| MemoryReader { blocks, directory })
+impl MemoryReader {
+	#[inline]
+	pub(crate) fn blocks(&self) -> &[Block] {
+		&self.blocks
+	}
+
+	/// Sets the path separator policy, see [`Directory::set_separators`].
+	///
+	/// Clones the directory if it is shared with other readers.
+	#[inline]
+	pub fn set_separators(&mut self, sep: Separators) -> &mut MemoryReader {
+		Arc::make_mut(&mut self.directory).set_separators(sep);
+		self
+	}
+
+	/// Installs the metrics hooks, see [`Metrics`].
+	#[inline]
+	pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) -> &mut MemoryReader {
+		self.metrics = Some(metrics);
+		self
+	}
+
+	/// Returns the metrics hooks, if any.
+	#[inline]
+	pub fn metrics(&self) -> Option<&dyn Metrics> {
+		self.metrics.as_deref()
+	}
+
+	// Reports the outcome of reading the section to the metrics
+	#[inline]
+	fn report<T>(&self, section: &Section, decrypted: usize, result: Result<T, ErrorKind>) -> Result<T, ErrorKind> {
+		metrics::report(self.metrics(), section, decrypted, result.as_ref().err().copied());
+		result
+	}
+
+	// Inline file contents are read from the directory, no section is read
+	#[inline]
+	fn report_data<T>(&self, desc: &Descriptor, decrypted: usize, result: Result<T, ErrorKind>) -> Result<T, ErrorKind> {
+		if desc.is_inline() {
+			return result;
+		}
+		self.report(&desc.section, decrypted, result)
 	}
 }
 
@@ -54,24 +166,79 @@ impl ops::Deref for MemoryReader {
 
 impl MemoryReader {
 	/// Reads the contents of a file from the PAKS archive.
-	pub fn read(&self, path: &[u8], key: &Key) -> Result<Vec<u8>, ErrorKind> {
+	pub fn read(&self, path: &[u8], key: &Key) -> Result<Vec<u8>, Error> {
 		let desc = match self.find_file(path) {
 			Some(desc) => desc,
-			None => return Err(ErrorKind::NotFound),
+			None => return Err(Error::with_path(ErrorKind::NotFound, path)),
 		};
 
-		self.read_data(desc, key)
+		self.read_data(desc, key).map_err(|err| err.at(path))
 	}
 
 	/// Reads the contents of a file from the PAKS archive into a string.
-	pub fn read_to_string(&self, path: &[u8], key: &Key) -> Result<String, ErrorKind> {
-		let desc = match self.find_file(path) {
-			Some(desc) => desc,
-			None => return Err(ErrorKind::NotFound),
-		};
+	pub fn read_to_string(&self, path: &[u8], key: &Key) -> Result<String, Error> {
+		let data = self.read(path, key)?;
+		String::from_utf8(data).map_err(|_| Error::with_path(ErrorKind::InvalidData, path))
+	}
+
+	/// Reads the contents of a file from the PAKS archive and deserializes it from JSON.
+	///
+	/// If the contents are not valid JSON for the type, [`ErrorKind::InvalidData`] is returned with the path attached.
+	#[cfg(feature = "json")]
+	pub fn read_json<T: serde::de::DeserializeOwned>(&self, path: &[u8], key: &Key) -> Result<T, Error> {
+		let data = self.read(path, key)?;
+		serde_json::from_slice(&data).map_err(|_| Error::with_path(ErrorKind::InvalidData, path))
+	}
+
+	/// Reads the contents of a file from the PAKS archive and deserializes it from TOML.
+	///
+	/// If the contents are not valid TOML for the type, [`ErrorKind::InvalidData`] is returned with the path attached.
+	#[cfg(feature = "toml")]
+	pub fn read_toml<T: serde::de::DeserializeOwned>(&self, path: &[u8], key: &Key) -> Result<T, Error> {
+		let data = self.read(path, key)?;
+		toml::from_slice(&data).map_err(|_| Error::with_path(ErrorKind::InvalidData, path))
+	}
+
+	/// Reads the preload list, see [`PRELOAD_PATH`].
+	///
+	/// Warm caches by reading the files in the given order, returns an empty list if the PAKS file has no preload list.
+	/// The order is the load order, reading in the order of [`Section::offset`] avoids seeking if the files are not laid out in load order.
+	pub fn preload_list(&self, key: &Key) -> Result<Vec<Vec<u8>>, Error> {
+		match self.find_file(PRELOAD_PATH) {
+			Some(desc) => Ok(preload::decode(&self.read_data(desc, key)?)),
+			None => Ok(Vec::new()),
+		}
+	}
 
-		let data = self.read_data(desc, key)?;
-		String::from_utf8(data).map_err(|_| ErrorKind::InvalidData)
+	/// Reads the names of the content types, see [`CONTENT_TYPES_PATH`].
+	///
+	/// Returns an empty table if the PAKS file names no content types.
+	pub fn content_types(&self, key: &Key) -> Result<ContentTypes, Error> {
+		match self.find_file(CONTENT_TYPES_PATH) {
+			Some(desc) => Ok(content_types::decode(&self.read_data(desc, key)?)),
+			None => Ok(ContentTypes::new()),
+		}
+	}
+
+	/// Reads every file in the order of their contents in the PAKS file.
+	///
+	/// Files bundled together are decrypted once per bundle.
+	/// Links are yielded under every path linking them.
+	///
+	/// Errors have the path attached and do not stop the iteration.
+	pub fn read_all<'a>(&'a self, key: &Key) -> impl 'a + Iterator<Item = (Vec<u8>, Result<Vec<u8>, Error>)> {
+		let key = *key;
+		let mut bundle = None;
+		dir::disk_order(self.directory.as_ref()).into_iter().map(move |(path, desc)| {
+			let result = if desc.is_bundled() {
+				read_bundled(&mut bundle, desc, |section| self.read_section(section, &key))
+			}
+			else {
+				self.read_data(desc, &key)
+			};
+			let result = result.map_err(|err| err.at(&path));
+			(path, result)
+		})
 	}
 
 	/// Decrypts and authenticates the section.
@@ -83,23 +250,69 @@ impl MemoryReader {
 	/// * [`ErrorKind::InvalidInput`]: The the descriptor is not a file descriptor.
 	/// * [`ErrorKind::InvalidData`]: The file's MAC is incorrect, the file is corrupted.
 	#[inline]
-	pub fn read_section(&self, section: &Section, key: &Key) -> Result<Vec<Block>, ErrorKind> {
-		read_section(&self.blocks, section, key)
+	pub fn read_section(&self, section: &Section, key: &Key) -> Result<Vec<Block>, Error> {
+		let result = read_section(&self.blocks, section, key);
+		self.report(section, section.size as usize * BLOCK_SIZE, result).map_err(Error::new)
+	}
+
+	/// Decrypts the section into the given blocks.
+	///
+	/// Reuse the blocks buffer to avoid an allocation for every section when reading many sections.
+	///
+	/// See [`read_section`](Self::read_section) for more information.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::InvalidInput`]: The length of the blocks is not equal to the section size.
+	#[inline]
+	pub fn read_section_into(&self, section: &Section, key: &Key, blocks: &mut [Block]) -> Result<(), Error> {
+		let result = read_section_into(&self.blocks, section, key, blocks);
+		self.report(section, section.size as usize * BLOCK_SIZE, result).map_err(Error::new)
 	}
 
 	/// Decrypts the contents of the given file descriptor.
 	///
+	/// Plaintext file contents are returned as-is, see [`Descriptor::FLAG_PLAINTEXT`].
+	///
 	/// See [`read_section`](Self::read_section) for more information.
 	#[inline]
-	pub fn read_data(&self, desc: &Descriptor, key: &Key) -> Result<Vec<u8>, ErrorKind> {
-		read_data(&self.blocks, desc, key)
+	pub fn read_data(&self, desc: &Descriptor, key: &Key) -> Result<Vec<u8>, Error> {
+		let result = read_data(&self.blocks, desc, key);
+		let decrypted = if desc.is_plaintext() { 0 } else { desc.section.size as usize * BLOCK_SIZE };
+		self.report_data(desc, decrypted, result).map_err(Error::new)
 	}
 
 	/// Decrypts the contents of the given file descriptor into the dest buffer.
 	///
+	/// The bytes starting at the byte offset are decrypted directly into the dest buffer without any intermediate allocation.
+	/// The whole section is still authenticated, if the authentication fails the dest buffer is zeroed.
+	/// Plaintext file contents are copied as-is.
+	///
 	/// See [`read_section`](Self::read_section) for more information.
 	#[inline]
-	pub fn read_data_into(&self, desc: &Descriptor, key: &Key, byte_offset: usize, dest: &mut [u8]) -> Result<(), ErrorKind> {
-		read_data_into(&self.blocks, desc, key, byte_offset, dest)
+	pub fn read_data_into(&self, desc: &Descriptor, key: &Key, byte_offset: usize, dest: &mut [u8]) -> Result<(), Error> {
+		self.read_data_uninit(desc, key, byte_offset, uninit_bytes(dest)).map(|_| ())
+	}
+
+	/// Decrypts the contents of the given file descriptor into the uninitialized dest buffer.
+	///
+	/// Engines can decrypt straight into persistently mapped GPU buffers or other uninitialized memory without zeroing it or an intermediate allocation.
+	/// The dest buffer has no alignment requirement, the bytes are written starting at its first byte and the initialized dest buffer is returned.
+	/// File contents start at a multiple of 16 bytes from the start of the PAKS file.
+	///
+	/// See [`read_data_into`](Self::read_data_into) for more information.
+	#[inline]
+	pub fn read_data_uninit<'a>(&self, desc: &Descriptor, key: &Key, byte_offset: usize, dest: &'a mut [mem::MaybeUninit<u8>]) -> Result<&'a mut [u8], Error> {
+		let decrypted = if desc.is_plaintext() { 0 } else { dest.len() };
+		let result = read_data_into(&self.blocks, desc, key, byte_offset, dest);
+		self.report_data(desc, decrypted, result).map_err(Error::new)
+	}
+
+	/// Borrows the contents of the given plaintext file descriptor without copying.
+	///
+	/// Returns `None` if the descriptor is not a plaintext file descriptor or its section is out of bounds.
+	#[inline]
+	pub fn plaintext_data(&self, desc: &Descriptor) -> Option<&[u8]> {
+		plaintext_data(&self.blocks, desc)
 	}
 }
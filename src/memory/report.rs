@@ -0,0 +1,68 @@
+use super::*;
+
+/// Stage at which parsing a PAKS file failed, see [`MemoryEditor::from_bytes_diagnose`].
+///
+/// Converts to [`Error`] with the matching [`ErrorKind`], see [`kind`](Self::kind).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ParseReport {
+	/// The length in bytes is not a multiple of the block size.
+	SizeMultiple { len: usize },
+	/// The PAKS file is too small to contain the header, the length is in blocks.
+	TooSmall { len: usize },
+	/// The encryption key is incorrect.
+	IncorrectKey,
+	/// The header failed authentication although the key is correct.
+	HeaderMac,
+	/// The file format version is not supported.
+	UnsupportedVersion { version: u32 },
+	/// The directory is not within the PAKS file, the length is in blocks.
+	DirectoryBounds { directory: Section, len: usize },
+	/// The directory failed authentication.
+	DirectoryMac { directory: Section },
+	/// The directory is authentic but is logically invalid, eg. a file's contents are out of bounds.
+	InvalidDirectory { directory: Section },
+	/// Unused blocks trail the PAKS file, only reported when parsing strictly, see [`MemoryEditor::from_bytes_strict`].
+	TrailingData { len: usize },
+}
+
+impl ParseReport {
+	/// Returns the kind of error.
+	///
+	/// * [`ErrorKind::InvalidInput`]: The length is not a multiple of the block size.
+	/// * [`ErrorKind::PermissionDenied`]: The encryption key is incorrect.
+	/// * [`ErrorKind::Unsupported`]: The file format version is not supported.
+	/// * [`ErrorKind::InvalidData`]: Any other stage.
+	pub fn kind(&self) -> ErrorKind {
+		match self {
+			ParseReport::SizeMultiple { .. } => ErrorKind::InvalidInput,
+			ParseReport::IncorrectKey => ErrorKind::PermissionDenied,
+			ParseReport::UnsupportedVersion { .. } => ErrorKind::Unsupported,
+			ParseReport::TooSmall { .. } | ParseReport::HeaderMac | ParseReport::DirectoryBounds { .. } | ParseReport::DirectoryMac { .. } | ParseReport::InvalidDirectory { .. } | ParseReport::TrailingData { .. } => ErrorKind::InvalidData,
+		}
+	}
+}
+
+impl fmt::Display for ParseReport {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ParseReport::SizeMultiple { len } => write!(f, "size of {} bytes is not a multiple of {} bytes", len, BLOCK_SIZE),
+			ParseReport::TooSmall { len } => write!(f, "too small for the header: {} blocks", len),
+			ParseReport::IncorrectKey => f.write_str("incorrect key"),
+			ParseReport::HeaderMac => f.write_str("header failed authentication"),
+			ParseReport::UnsupportedVersion { version } => write!(f, "unsupported version {}", version),
+			ParseReport::DirectoryBounds { directory, len } => write!(f, "directory at block {} of {} descriptors is out of bounds: {} blocks", directory.offset, directory.size, len),
+			ParseReport::DirectoryMac { directory } => write!(f, "directory at block {} failed authentication", directory.offset),
+			ParseReport::InvalidDirectory { directory } => write!(f, "directory at block {} is invalid", directory.offset),
+			ParseReport::TrailingData { len } => write!(f, "{} unused blocks trail the PAKS file", len),
+		}
+	}
+}
+
+impl std::error::Error for ParseReport {}
+
+impl From<ParseReport> for Error {
+	#[inline]
+	fn from(report: ParseReport) -> Error {
+		Error::new(report.kind())
+	}
+}
@@ -7,16 +7,16 @@ fn test_simple() {
 	let ref key = [1, 2];
 
 	// Create a new PAKS file and finish it
-	let (blocks, _) = MemoryEditor::new().finish(key);
+	let (blocks, _) = MemoryEditor::new().finish(key).unwrap();
 
 	// Re-open the PAKS file for editing
 	let mut edit = MemoryEditor::from_blocks(blocks, key).expect("failed to edit");
 
 	// Add the test file
-	edit.create_file(b"example", EXAMPLE, key);
+	edit.create_file(b"example", EXAMPLE, key).unwrap();
 
 	// Finish the test PAKS file
-	let (blocks, _) = edit.finish(key);
+	let (blocks, _) = edit.finish(key).unwrap();
 
 	// Re-open the PAKS file for reading
 	let reader = MemoryReader::from_blocks(blocks, key).expect("failed to read");
@@ -24,10 +24,1164 @@ fn test_simple() {
 	// Check the directory listing
 	let dir = &*reader;
 	let listing = dir::DirFmt::new(".", dir.as_ref(), &dir::TreeArt::ASCII).to_string();
-	assert_eq!(dbg!(listing), "./\n`  example\n");
+	assert_eq!(dbg!(listing), "./\n|  .archive-id\n`  example\n");
 
 	// Check the test file
 	let desc = reader.find_file(b"example").expect("example file not found");
 	let example = reader.read_data(desc, key).expect("failed to read example");
 	assert_eq!(example, EXAMPLE);
 }
+
+#[test]
+fn test_write_data_vectored() {
+	let ref key = [3, 4];
+	let mut edit = MemoryEditor::new();
+
+	let header = b"HEADER:";
+	let body = b"some body text";
+	let footer = b":FOOTER";
+	let bufs = [std::io::IoSlice::new(header), std::io::IoSlice::new(body), std::io::IoSlice::new(footer)];
+	let content_size = bufs.iter().map(|buf| buf.len()).sum::<usize>() as u32;
+
+	let mut edit_file = edit.edit_file(b"composed").unwrap();
+	edit_file.set_content(1, content_size).allocate_data().write_data_vectored(&bufs, key).unwrap();
+
+	assert_eq!(edit.read(b"composed", key).unwrap(), b"HEADER:some body text:FOOTER");
+}
+
+#[test]
+fn test_append() {
+	let ref key = [5, 6];
+	let mut edit = MemoryEditor::new();
+
+	edit.append(b"log/events", b"first\n", key).unwrap();
+	edit.append(b"log/events", b"second\n", key).unwrap();
+	assert_eq!(edit.read(b"log/events", key).unwrap(), b"first\nsecond\n");
+
+	// Cannot append to directories or with the wrong key
+	assert_eq!(edit.append(b"log", b"x", key).unwrap_err(), ErrorKind::InvalidInput);
+	assert_eq!(edit.append(b"log/events", b"x", &[7, 8]).unwrap_err(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_truncate() {
+	let ref key = [7, 8];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"file", &EXAMPLE[..40], key).unwrap();
+
+	// Shrink within the last block keeps the section
+	let section = edit.find_file(b"file").unwrap().section;
+	edit.edit_file(b"file").unwrap().truncate(35, key).unwrap();
+	assert_eq!(edit.find_file(b"file").unwrap().section, section);
+	assert_eq!(edit.read(b"file", key).unwrap(), &EXAMPLE[..35]);
+
+	// Shrink across block boundaries
+	edit.edit_file(b"file").unwrap().truncate(10, key).unwrap();
+	assert_eq!(edit.find_file(b"file").unwrap().section.size, 1);
+	assert_eq!(edit.read(b"file", key).unwrap(), &EXAMPLE[..10]);
+
+	// Extend with zeroes
+	edit.edit_file(b"file").unwrap().truncate(20, key).unwrap();
+	let mut expected = EXAMPLE[..10].to_vec();
+	expected.resize(20, 0);
+	assert_eq!(edit.read(b"file", key).unwrap(), expected);
+}
+
+#[test]
+fn test_write_at() {
+	let ref key = [9, 10];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"file", &EXAMPLE[..40], key).unwrap();
+	let nonce = edit.find_file(b"file").unwrap().section.nonce;
+
+	edit.write_at(b"file", 13, b"PATCHED", key).unwrap();
+	let mut expected = EXAMPLE[..40].to_vec();
+	expected[13..20].copy_from_slice(b"PATCHED");
+	assert_eq!(edit.read(b"file", key).unwrap(), expected);
+	assert_ne!(edit.find_file(b"file").unwrap().section.nonce, nonce);
+
+	assert_eq!(edit.write_at(b"file", 35, b"TOO LONG", key).unwrap_err(), ErrorKind::InvalidInput);
+	assert_eq!(edit.write_at(b"missing", 0, b"", key).unwrap_err(), ErrorKind::NotFound);
+	assert_eq!(edit.write_at(b"missing", 0, b"", key).unwrap_err().path(), Some(&b"missing"[..]));
+	assert_eq!(edit.write_at(b"file", 0, b"x", &[1, 1]).unwrap_err(), ErrorKind::InvalidData);
+	assert_eq!(edit.read(b"file", key).unwrap(), expected);
+
+	// Links keep the previous contents
+	let desc = *edit.find_file(b"file").unwrap();
+	edit.create_link(b"link", &desc).unwrap();
+	edit.write_at(b"file", 0, b"LINKED", key).unwrap();
+	assert_eq!(edit.read(b"link", key).unwrap(), expected);
+	expected[..6].copy_from_slice(b"LINKED");
+	assert_eq!(edit.read(b"file", key).unwrap(), expected);
+}
+
+#[test]
+fn test_plaintext() {
+	let ref key = [11, 12];
+	let mut edit = MemoryEditor::new();
+	let mut edit_file = edit.edit_file(b"music").unwrap();
+	edit_file.set_content(1, 40).set_plaintext(true);
+	edit_file.allocate_data().write_data(&EXAMPLE[..40], key).unwrap();
+	edit.write_at(b"music", 0, b"PATCHED", key).unwrap();
+
+	let mut expected = EXAMPLE[..40].to_vec();
+	expected[..7].copy_from_slice(b"PATCHED");
+
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks.clone(), key).unwrap();
+	let desc = reader.find_file(b"music").unwrap();
+	assert!(desc.is_plaintext());
+	assert_eq!(desc.section.mac, Block::default());
+
+	// The contents are stored as-is and readable without decryption
+	let offset = desc.section.offset as usize * BLOCK_SIZE;
+	assert_eq!(&dataview::bytes(reader.blocks())[offset..offset + 40], &expected[..]);
+	assert_eq!(reader.plaintext_data(desc), Some(&expected[..]));
+	assert_eq!(reader.read_data(desc, key).unwrap(), expected);
+	let mut dest = [0u8; 10];
+	reader.read_data_into(desc, key, 5, &mut dest).unwrap();
+	assert_eq!(dest, expected[5..15]);
+	let mut dest = [mem::MaybeUninit::uninit(); 10];
+	assert_eq!(reader.read_data_uninit(desc, key, 5, &mut dest).unwrap(), &expected[5..15]);
+
+	// Replacing the file encrypts it again
+	let mut edit = MemoryEditor::from_blocks(blocks, key).unwrap();
+	edit.create_file(b"music", &EXAMPLE[..40], key).unwrap();
+	let desc = edit.find_file(b"music").unwrap();
+	assert!(!desc.is_plaintext());
+	assert_eq!(edit.plaintext_data(desc), None);
+}
+
+#[test]
+fn test_read_data_uninit() {
+	let ref key = [53, 54];
+	let mut changed = EXAMPLE[..2000].to_vec();
+	changed[1000..1007].copy_from_slice(b"CHANGED");
+
+	let mut edit = MemoryEditor::new();
+	edit.set_inline_threshold(Descriptor::INLINE_LEN);
+	edit.create_file(b"inline", &EXAMPLE[..20], key).unwrap();
+	edit.create_file(b"encrypted", &EXAMPLE[..100], key).unwrap();
+	let mut edit_file = edit.edit_file(b"plaintext").unwrap();
+	edit_file.set_content(1, 40).set_plaintext(true);
+	edit_file.allocate_data().write_data(&EXAMPLE[..40], key).unwrap();
+	edit.create_file(b"delta", &EXAMPLE[..2000], key).unwrap();
+	edit.create_file_with(b"delta", &changed, CreateMode::Delta, key).unwrap();
+	let (blocks, _) = edit.finish(key).unwrap();
+
+	let reader = MemoryReader::from_blocks(blocks.clone(), key).unwrap();
+	let read = |path: &[u8], byte_offset: usize, len: usize| {
+		let mut dest = vec![mem::MaybeUninit::new(0xaa); len];
+		let data = reader.read_data_uninit(reader.find_file(path).unwrap(), key, byte_offset, &mut dest).unwrap();
+		assert_eq!(data.len(), len);
+		data.to_vec()
+	};
+	assert!(reader.find_file(b"inline").unwrap().is_inline());
+	assert_eq!(read(b"inline", 3, 10), &EXAMPLE[3..13]);
+	assert!(reader.find_file(b"plaintext").unwrap().is_plaintext());
+	assert_eq!(read(b"plaintext", 5, 30), &EXAMPLE[5..35]);
+	assert_eq!(read(b"encrypted", 17, 60), &EXAMPLE[17..77]);
+	assert!(reader.find_file(b"delta").unwrap().is_delta());
+	assert_eq!(read(b"delta", 990, 20), &changed[990..1010]);
+	assert_eq!(read(b"encrypted", 100, 0), b"");
+
+	// Failing the authentication zeroes the dest buffer
+	for path in [&b"encrypted"[..], b"delta"] {
+		let mut tampered = blocks.clone();
+		let desc = reader.find_file(path).unwrap();
+		tampered[desc.section.offset as usize + 1][0] ^= 1;
+		let reader = MemoryReader::from_blocks(tampered, key).unwrap();
+		let mut dest = [mem::MaybeUninit::new(0xaa); 20];
+		assert_eq!(reader.read_data_uninit(desc, key, 10, &mut dest).unwrap_err(), ErrorKind::InvalidData);
+		// Every byte of dest was initialized before reading
+		assert!(dest.iter().all(|byte| unsafe { byte.assume_init() } == 0));
+	}
+}
+
+#[test]
+fn test_wrong_key() {
+	let ref key = [13, 14];
+	let (blocks, _) = MemoryEditor::new().finish(key).unwrap();
+	let bytes = dataview::bytes(blocks.as_slice());
+	assert!(MemoryReader::from_bytes(bytes, key).is_ok());
+	assert_eq!(MemoryReader::from_bytes(bytes, &[13, 15]).err().map(|err| err.kind()), Some(ErrorKind::PermissionDenied));
+
+	// Corrupt the directory section in the header
+	let mut corrupt = bytes.to_vec();
+	corrupt[Header::BLOCKS_LEN * BLOCK_SIZE - 1] ^= 1;
+	assert_eq!(MemoryReader::from_bytes(&corrupt, key).err().map(|err| err.kind()), Some(ErrorKind::InvalidData));
+	assert_eq!(MemoryEditor::from_bytes(&corrupt, key).err().map(|err| err.kind()), Some(ErrorKind::InvalidData));
+}
+
+#[test]
+fn test_corrupt_ranges() {
+	let ref key = [15, 16];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"file", b"hello", key).unwrap();
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks.clone(), key).unwrap();
+
+	// Sections extending beyond the end of the address space
+	let mut desc = *reader.find_file(b"file").unwrap();
+	desc.section.offset = u32::MAX;
+	desc.section.size = 2;
+	assert_eq!(reader.read_section(&desc.section, key).unwrap_err(), ErrorKind::InvalidInput);
+	assert_eq!(reader.read_data(&desc, key).unwrap_err(), ErrorKind::InvalidInput);
+	assert_eq!(reader.read_data_into(&desc, key, 0, &mut [0u8; 4]).unwrap_err(), ErrorKind::InvalidInput);
+	desc.flags |= Descriptor::FLAG_PLAINTEXT;
+	assert_eq!(reader.plaintext_data(&desc), None);
+	assert_eq!(reader.read_data(&desc, key).unwrap_err(), ErrorKind::InvalidInput);
+
+	// Byte ranges which overflow
+	let desc = reader.find_file(b"file").unwrap();
+	assert_eq!(reader.read_data_into(desc, key, usize::MAX, &mut [0u8; 4]).unwrap_err(), ErrorKind::InvalidInput);
+
+	// Rewrite the header with a directory extending beyond the end of the address space
+	for (offset, size) in [(Header::BLOCKS_LEN as u32, u32::MAX), (u32::MAX, 1), (u32::MAX - 7, 1)] {
+		let mut blocks = blocks.clone();
+		let mut header: Header = dataview::DataView::from(blocks.as_slice()).read(0);
+		assert_eq!(crypt::decrypt_header(&mut header, key), Ok(()));
+		header.info.directory.offset = offset;
+		header.info.directory.size = size;
+		crypt::encrypt_header(&mut header, key, true).unwrap();
+		dataview::DataView::from_mut(blocks.as_mut_slice()).write(0, &header);
+		assert_eq!(MemoryReader::from_bytes(dataview::bytes(blocks.as_slice()), key).err().map(|err| err.kind()), Some(ErrorKind::InvalidData));
+	}
+}
+
+#[test]
+fn test_invalid_directory() {
+	let ref key = [17, 18];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"dir/a", b"hello", key).unwrap();
+	edit.create_file(b"dir/b", b"world", key).unwrap();
+	let (blocks, _) = edit.finish(key).unwrap();
+	assert!(MemoryReader::from_bytes(dataview::bytes(blocks.as_slice()), key).is_ok());
+
+	// Reencrypt the modified directory so it passes the authentication
+	let rewrite = |f: &dyn Fn(&mut [Descriptor])| {
+		let mut blocks = blocks.clone();
+		let mut header: Header = dataview::DataView::from(blocks.as_slice()).read(0);
+		assert_eq!(crypt::decrypt_header(&mut header, key), Ok(()));
+		let dir_start = header.info.directory.offset as usize;
+		let dir_blocks = &mut blocks[dir_start..dir_start + header.info.directory.size as usize * Descriptor::BLOCKS_LEN];
+		assert!(crypt::decrypt_section(dir_blocks, &header.info.directory, key));
+		f(dataview::DataView::from_mut(&mut *dir_blocks).slice_mut(0, header.info.directory.size as usize));
+		crypt::encrypt_section_from(None, dir_blocks, &mut header.info.directory, key).unwrap();
+		crypt::encrypt_header(&mut header, key, true).unwrap();
+		dataview::DataView::from_mut(blocks.as_mut_slice()).write(0, &header);
+		MemoryReader::from_bytes(dataview::bytes(blocks.as_slice()), key).err().map(|err| err.kind())
+	};
+	assert_eq!(rewrite(&|_| ()), None);
+	assert_eq!(rewrite(&|dir| dir[0].content_size = dir.len() as u32), Some(ErrorKind::InvalidData));
+	assert_eq!(rewrite(&|dir| dir[2].section = dir[1].section), None);
+	assert_eq!(rewrite(&|dir| dir[1].section.size = 2), Some(ErrorKind::InvalidData));
+	assert_eq!(rewrite(&|dir| dir[1].section.offset = u32::MAX), Some(ErrorKind::InvalidData));
+	assert_eq!(rewrite(&|dir| dir[1].content_size = 1000), Some(ErrorKind::InvalidData));
+
+	// Directory which fails authentication
+	let mut corrupt = blocks.clone();
+	let last = corrupt.len() - 1;
+	corrupt[last][0] ^= 1;
+	assert_eq!(MemoryReader::from_bytes(dataview::bytes(corrupt.as_slice()), key).err().map(|err| err.kind()), Some(ErrorKind::InvalidData));
+	assert_eq!(MemoryEditor::from_bytes(dataview::bytes(corrupt.as_slice()), key).err().map(|err| err.kind()), Some(ErrorKind::InvalidData));
+}
+
+#[test]
+fn test_conversions() {
+	let ref key = [19, 20];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"file", EXAMPLE, key).unwrap();
+
+	// Commit the editor for reading without encrypting it
+	let reader = MemoryReader::from(edit);
+	assert_eq!(reader.read(b"file", key).unwrap(), EXAMPLE);
+
+	// Continue editing and finish the PAKS file
+	let mut edit = MemoryEditor::from(reader);
+	edit.create_file(b"other", b"hello", key).unwrap();
+	assert!(edit.has_magic());
+	let (blocks, _) = edit.finish(key).unwrap();
+
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.read(b"file", key).unwrap(), EXAMPLE);
+	assert_eq!(reader.read(b"other", key).unwrap(), b"hello");
+}
+
+#[test]
+fn test_metrics() {
+	let ref key = [21, 22];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"file", &EXAMPLE[..40], key).unwrap();
+	let mut reader = MemoryReader::from(edit);
+	let counters = std::sync::Arc::new(Counters::new());
+	reader.set_metrics(counters.clone());
+
+	assert_eq!(reader.read(b"file", key).unwrap(), &EXAMPLE[..40]);
+	let desc = reader.find_file(b"file").unwrap();
+	let mut dest = [0u8; 10];
+	reader.read_data_into(desc, key, 5, &mut dest).unwrap();
+	assert_eq!(counters.sections_read(), 2);
+	assert_eq!(counters.bytes_decrypted(), 3 * BLOCK_SIZE as u64 + 10);
+
+	// Reading with the wrong key fails authentication
+	assert!(reader.read(b"file", &[21, 23]).is_err());
+	assert_eq!(counters.mac_failures(), 1);
+	assert_eq!(counters.sections_read(), 2);
+	assert_eq!(counters.cache_hits(), 0);
+}
+
+#[test]
+fn test_tamper_hook() {
+	static ALARMS: std::sync::Mutex<Vec<Tampered>> = std::sync::Mutex::new(Vec::new());
+	set_tamper_hook(Box::new(|what| ALARMS.lock().unwrap().push(*what)));
+
+	let ref key = [23, 24];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"file", &EXAMPLE[..40], key).unwrap();
+	let (mut blocks, _) = edit.finish(key).unwrap();
+
+	// Modify the contents of the file
+	let reader = MemoryReader::from_blocks(blocks.clone(), key).unwrap();
+	let section = reader.find_file(b"file").unwrap().section;
+	blocks[section.offset as usize][0] ^= 1;
+	let reader = MemoryReader::from_blocks(blocks.clone(), key).unwrap();
+	assert_eq!(reader.read(b"file", key).unwrap_err(), ErrorKind::InvalidData);
+
+	// Modify the directory
+	let mut header: Header = dataview::DataView::from(blocks.as_slice()).read(0);
+	assert_eq!(crypt::decrypt_header(&mut header, key), Ok(()));
+	let directory = header.info.directory;
+	blocks[directory.offset as usize][0] ^= 1;
+	assert!(MemoryReader::from_blocks(blocks, key).is_err());
+
+	take_tamper_hook();
+	let alarms = ALARMS.lock().unwrap();
+	assert!(alarms.contains(&Tampered::Section(section)));
+	assert!(alarms.contains(&Tampered::Directory(directory)));
+}
+
+#[test]
+fn test_strip_names() {
+	let ref key = [25, 26];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"levels/secret_ending.map", b"ending", key).unwrap();
+	edit.create_file(b"readme.txt", b"hello", key).unwrap();
+
+	edit.strip_names();
+	let hash = path_hash(b"levels/secret_ending.map");
+	assert_eq!(edit.find_by_hash(hash).unwrap().name(), format!("{:016x}", hash).as_bytes());
+
+	// Stripping again keeps the hashes of the original paths
+	edit.strip_names();
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert!(reader.iter().filter(|desc| desc.name() != ARCHIVE_ID_PATH).all(|desc| desc.is_name_stripped() && !desc.name().ends_with(b".map")));
+	let desc = reader.find_by_hash(hash).unwrap();
+	assert_eq!(reader.read_data(desc, key).unwrap(), b"ending");
+	let desc = reader.find_by_hash(path_hash(b"readme.txt")).unwrap();
+	assert_eq!(reader.read_data(desc, key).unwrap(), b"hello");
+}
+
+#[test]
+fn test_name_table() {
+	let ref key = [27, 28];
+	let ref names_key = [29, 30];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"levels/secret_ending.map", b"ending", key).unwrap();
+	edit.create_file(b"readme.txt", b"hello", key).unwrap();
+	let plain = edit.display().to_string();
+
+	let table = NameTable::from_directory(&edit).to_bytes(names_key).unwrap();
+	edit.strip_names();
+	let (blocks, mut directory) = edit.finish(key).unwrap();
+	assert_ne!(directory.display().to_string(), plain);
+
+	assert_eq!(NameTable::from_bytes(&table, key).unwrap_err(), ErrorKind::PermissionDenied);
+	assert_eq!(NameTable::from_bytes(dataview::bytes(blocks.as_slice()), key).unwrap_err(), ErrorKind::InvalidData);
+	let table = NameTable::from_bytes(&table, names_key).unwrap();
+	assert_eq!(table.len(), 3);
+	assert_eq!(table.restore(&mut directory), 3);
+	assert_eq!(directory.display().to_string(), plain);
+	assert_eq!(directory.find_file(b"levels/secret_ending.map"), directory.find_by_hash(path_hash(b"levels/secret_ending.map")));
+}
+
+#[test]
+fn test_preload_list() {
+	let ref key = [31, 32];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"levels/e1m1.map", b"map", key).unwrap();
+	edit.create_file(b"textures/wall.tex", b"wall", key).unwrap();
+	edit.create_file(b"sounds/door.wav", b"door", key).unwrap();
+
+	assert_eq!(edit.set_preload_list(&[b"levels/e1m2.map"], key).unwrap_err(), ErrorKind::NotFound);
+	assert_eq!(edit.set_preload_list(&[b"levels"], key).unwrap_err(), ErrorKind::NotFound);
+	edit.set_preload_list(&[b"textures/wall.tex", b"levels/e1m1.map"], key).unwrap();
+
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks.clone(), key).unwrap();
+	assert_eq!(reader.preload_list(key).unwrap(), [b"textures/wall.tex".to_vec(), b"levels/e1m1.map".to_vec()]);
+
+	// Stripping the names keeps the preload list, the listed files are found by hash
+	let mut edit = MemoryEditor::from_blocks(blocks.clone(), key).unwrap();
+	edit.strip_names();
+	let (stripped, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(stripped, key).unwrap();
+	let list = reader.preload_list(key).unwrap();
+	assert_eq!(list, [b"textures/wall.tex".to_vec(), b"levels/e1m1.map".to_vec()]);
+	assert!(list.iter().all(|path| reader.find_by_hash(path_hash(path)).unwrap().is_name_stripped()));
+
+	// An empty list removes the preload list
+	let mut edit = MemoryEditor::from_blocks(blocks, key).unwrap();
+	edit.set_preload_list(&[], key).unwrap();
+	assert_eq!(edit.find_desc(PRELOAD_PATH), None);
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.preload_list(key).unwrap(), Vec::<Vec<u8>>::new());
+}
+
+#[test]
+fn test_reorder() {
+	let ref key = [33, 34];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &EXAMPLE[..20], key).unwrap();
+	edit.create_file(b"garbage", &EXAMPLE[..100], key).unwrap();
+	edit.create_file(b"b", &EXAMPLE[..40], key).unwrap();
+	edit.create_file(b"c", &EXAMPLE[..60], key).unwrap();
+	let desc = *edit.find_file(b"a").unwrap();
+	edit.create_link(b"link/a", &desc).unwrap();
+	edit.remove(b"garbage");
+
+	edit.reorder(&[b"c", b"missing", b"link/a"]);
+	let offset = |path: &[u8]| edit.find_file(path).unwrap().section.offset;
+	assert!(offset(b"c") < offset(b"a") && offset(b"a") < offset(b"b"));
+	assert_eq!(edit.find_file(b"link/a").unwrap().section, edit.find_file(b"a").unwrap().section);
+	assert_eq!(edit.high_mark() as usize, Header::BLOCKS_LEN + 2 + 3 + 4);
+
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.read(b"a", key).unwrap(), &EXAMPLE[..20]);
+	assert_eq!(reader.read(b"b", key).unwrap(), &EXAMPLE[..40]);
+	assert_eq!(reader.read(b"c", key).unwrap(), &EXAMPLE[..60]);
+	assert_eq!(reader.read(b"link/a", key).unwrap(), &EXAMPLE[..20]);
+}
+
+#[test]
+fn test_block_shift() {
+	let ref key = [35, 36];
+	let mut edit = MemoryEditor::new();
+	edit.set_block_shift(2);
+	edit.create_file(b"a", &EXAMPLE[..20], key).unwrap();
+	edit.create_file(b"b", &EXAMPLE[..100], key).unwrap();
+	edit.create_file(b"c", &EXAMPLE[..40], key).unwrap();
+	assert!(edit.iter().filter(|desc| desc.is_file()).all(|desc| desc.section.offset % 4 == 0));
+
+	edit.remove(b"a");
+	edit.set_block_shift(3).gc();
+	assert!(edit.iter().filter(|desc| desc.is_file()).all(|desc| desc.section.offset % 8 == 0));
+	edit.set_block_shift(4).reorder(&[b"c"]);
+	assert!(edit.iter().filter(|desc| desc.is_file()).all(|desc| desc.section.offset % 16 == 0));
+
+	// The block size is recorded in the header and kept by the next edit
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.read(b"b", key).unwrap(), &EXAMPLE[..100]);
+	assert_eq!(reader.read(b"c", key).unwrap(), &EXAMPLE[..40]);
+	let mut edit = MemoryEditor::from(reader);
+	assert_eq!(edit.block_shift(), 4);
+	edit.create_file(b"d", &EXAMPLE[..10], key).unwrap();
+	assert_eq!(edit.find_file(b"d").unwrap().section.offset % 16, 0);
+
+	// The block shift is limited to 64 KiB blocks
+	assert_eq!(edit.set_block_shift(20).block_shift(), InfoHeader::MAX_BLOCK_SHIFT);
+	let (blocks, _) = edit.finish(key).unwrap();
+	assert_eq!(MemoryEditor::from_blocks(blocks, key).unwrap().block_shift(), InfoHeader::MAX_BLOCK_SHIFT);
+}
+
+#[test]
+fn test_gc_in_place() {
+	let ref key = [47, 48];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &EXAMPLE[..20], key).unwrap();
+	edit.create_file(b"b", &EXAMPLE[..100], key).unwrap();
+	edit.create_file(b"c", &EXAMPLE[..40], key).unwrap();
+	let b = edit.find_file(b"b").unwrap().section;
+	let c = edit.find_file(b"c").unwrap().section;
+
+	// Sections overlapping other sections move along with them, invalid sections are zeroed
+	let mut part = *edit.find_file(b"b").unwrap();
+	part.section = Section { offset: b.offset + 2, size: 3, ..b };
+	edit.create_link(b"part", &part).unwrap();
+	part.section.offset = edit.high_mark();
+	edit.create_link(b"invalid", &part).unwrap();
+	edit.remove(b"a");
+
+	edit.gc();
+	assert_eq!(edit.high_mark(), Header::BLOCKS_LEN as u32 + b.size + c.size);
+	let b = edit.find_file(b"b").unwrap().section;
+	assert_eq!(b.offset, Header::BLOCKS_LEN as u32);
+	assert_eq!(edit.find_file(b"part").unwrap().section.offset, b.offset + 2);
+	assert_eq!(edit.find_file(b"invalid").unwrap().section, Section::default());
+	assert_eq!(edit.read(b"b", key).unwrap(), &EXAMPLE[..100]);
+	assert_eq!(edit.read(b"c", key).unwrap(), &EXAMPLE[..40]);
+
+	// Realigning moves the sections up and zeroes the padding
+	edit.set_block_shift(3).gc();
+	let c = edit.find_file(b"c").unwrap().section;
+	assert!(c.offset.is_multiple_of(8));
+	let b = edit.find_file(b"b").unwrap().section;
+	assert!(edit.blocks[(b.offset + b.size) as usize..c.offset as usize].iter().all(|block| *block == Block::default()));
+	assert_eq!(edit.find_file(b"part").unwrap().section.offset, b.offset + 2);
+
+	// Overlapping sections do not pass validation when opening the PAKS file
+	edit.remove(b"part");
+	edit.remove(b"invalid");
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.read(b"b", key).unwrap(), &EXAMPLE[..100]);
+	assert_eq!(reader.read(b"c", key).unwrap(), &EXAMPLE[..40]);
+}
+
+#[test]
+fn test_inline() {
+	let ref key = [37, 38];
+	let mut edit = MemoryEditor::new();
+	edit.set_inline_threshold(100);
+	assert_eq!(edit.inline_threshold(), Descriptor::INLINE_LEN);
+	edit.create_file(b"a", &EXAMPLE[..20], key).unwrap();
+	edit.create_file(b"b", &EXAMPLE[..100], key).unwrap();
+	let high_mark = edit.high_mark();
+	edit.create_file(b"c/d", b"", key).unwrap();
+	edit.create_file(b"c/e", &EXAMPLE[..Descriptor::INLINE_LEN], key).unwrap();
+	assert_eq!(edit.high_mark(), high_mark);
+	assert!(edit.find_file(b"a").unwrap().is_inline());
+	assert!(!edit.find_file(b"b").unwrap().is_inline());
+
+	edit.write_at(b"a", 2, b"xyz", key).unwrap();
+	assert!(edit.write_at(b"a", 18, b"xyz", key).is_err());
+	edit.edit_file(b"c/e").unwrap().truncate(8, key).unwrap();
+	edit.edit_file(b"c/d").unwrap().truncate(100, key).unwrap();
+	assert!(!edit.find_file(b"c/d").unwrap().is_inline());
+	assert!(edit.move_file(b"a", b"f"));
+	edit.gc();
+	edit.reorder(&[b"f", b"b"]);
+
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	let mut expected = EXAMPLE[..20].to_vec();
+	expected[2..5].copy_from_slice(b"xyz");
+	assert_eq!(reader.read(b"f", key).unwrap(), expected);
+	assert_eq!(reader.read(b"b", key).unwrap(), &EXAMPLE[..100]);
+	assert_eq!(reader.read(b"c/d", key).unwrap(), [0; 100]);
+	assert_eq!(reader.read(b"c/e", key).unwrap(), &EXAMPLE[..8]);
+
+	let desc = reader.find_file(b"f").unwrap();
+	let mut dest = [0; 4];
+	reader.read_data_into(desc, key, 2, &mut dest).unwrap();
+	assert_eq!(dest, expected[2..6]);
+	assert!(reader.read_data_into(desc, key, 18, &mut dest).is_err());
+}
+
+#[test]
+fn test_bundle() {
+	let ref key = [39, 40];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"x", &EXAMPLE[..50], key).unwrap();
+	edit.create_bundle(&[(b"a", &EXAMPLE[..10]), (b"b", &EXAMPLE[10..30]), (b"c/d", &EXAMPLE[30..33])], key).unwrap();
+	assert!(edit.create_bundle(&[(b"e", b"e"), (b"../f", b"f")], key).is_err());
+	assert!(edit.find_file(b"e").is_none());
+	let b = *edit.find_file(b"b").unwrap();
+	assert!(b.is_bundled());
+	assert_eq!(b.data_offset(), 10);
+	assert_eq!(b.section, edit.find_file(b"c/d").unwrap().section);
+	assert_eq!(edit.read(b"b", key).unwrap(), &EXAMPLE[10..30]);
+
+	assert!(edit.move_file(b"b", b"g"));
+	edit.create_link(b"h", &b).unwrap();
+	edit.edit_file(b"c/d").unwrap().truncate(40, key).unwrap();
+	assert!(!edit.find_file(b"c/d").unwrap().is_bundled());
+	edit.remove(b"x");
+	edit.gc();
+	assert_eq!(edit.find_file(b"a").unwrap().section, edit.find_file(b"g").unwrap().section);
+	edit.reorder(&[b"c/d", b"g"]);
+	assert_eq!(edit.find_file(b"a").unwrap().section, edit.find_file(b"g").unwrap().section);
+
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.read(b"a", key).unwrap(), &EXAMPLE[..10]);
+	assert_eq!(reader.read(b"g", key).unwrap(), &EXAMPLE[10..30]);
+	assert_eq!(reader.read(b"h", key).unwrap(), &EXAMPLE[10..30]);
+	let mut expected = EXAMPLE[30..33].to_vec();
+	expected.resize(40, 0);
+	assert_eq!(reader.read(b"c/d", key).unwrap(), expected);
+
+	// The bundled file contents must lie within the shared section
+	let mut desc = *reader.find_file(b"g").unwrap();
+	let section = desc.section;
+	assert!(dir::validate(&[desc], u32::MAX));
+	desc.set_bundled(&section, 30);
+	assert!(!dir::validate(&[desc], u32::MAX));
+}
+
+#[test]
+fn test_bundle_reencrypt() {
+	let ref old_key = [43, 44];
+	let ref key = [45, 46];
+	let mut edit = MemoryEditor::new();
+	edit.create_bundle(&[(b"x", b"AAAA"), (b"y", b"SECRETSECRET")], old_key).unwrap();
+
+	// Reencrypting moves each bundled file to a section of its own, the other files remain readable
+	edit.edit_file(b"x").unwrap().reencrypt_data(old_key, key).unwrap();
+	assert!(!edit.find_file(b"x").unwrap().is_bundled());
+	assert_eq!(edit.read(b"y", old_key).unwrap(), b"SECRETSECRET");
+	edit.edit_file(b"y").unwrap().reencrypt_data(old_key, key).unwrap();
+	assert_eq!(edit.read(b"x", key).unwrap(), b"AAAA");
+	assert_eq!(edit.read(b"y", key).unwrap(), b"SECRETSECRET");
+
+	// Bundled file contents which fail authentication are not moved
+	edit.create_bundle(&[(b"z", b"ZZZZ")], old_key).unwrap();
+	assert_eq!(edit.edit_file(b"z").unwrap().reencrypt_data(key, key).unwrap_err().kind(), ErrorKind::InvalidData);
+	assert!(edit.find_file(b"z").unwrap().is_bundled());
+}
+
+#[test]
+fn test_bundle_read_range() {
+	let ref key = [47, 48];
+	let mut edit = MemoryEditor::new();
+	edit.create_bundle(&[(b"x", b"AAAA"), (b"y", b"SECRETSECRET")], key).unwrap();
+	let reader = MemoryReader::from(edit);
+
+	// Reads are bounded by the file contents, not the shared section
+	let x = reader.find_file(b"x").unwrap();
+	let mut dest = [0; 16];
+	assert_eq!(reader.read_data_into(x, key, 0, &mut dest).unwrap_err().kind(), ErrorKind::InvalidInput);
+	assert_eq!(dest, [0; 16]);
+	let mut dest = [0; 4];
+	reader.read_data_into(x, key, 0, &mut dest).unwrap();
+	assert_eq!(&dest, b"AAAA");
+	assert!(reader.read_data_into(x, key, 1, &mut dest).is_err());
+	let y = reader.find_file(b"y").unwrap();
+	reader.read_data_into(y, key, 8, &mut dest).unwrap();
+	assert_eq!(&dest, b"CRET");
+}
+
+#[test]
+fn test_shared_reader() {
+	let ref key = [41, 42];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &EXAMPLE[..100], key).unwrap();
+	let reader = MemoryReader::from(edit);
+
+	// Clones share the blocks and the directory
+	let clone = reader.clone();
+	assert_eq!(clone.blocks().as_ptr(), reader.blocks().as_ptr());
+	let shared = clone.into_shared();
+	let thread = std::thread::spawn(move || shared.read(b"a", &[41, 42]).unwrap());
+	assert_eq!(thread.join().unwrap(), &EXAMPLE[..100]);
+
+	// Editing the reader leaves its clones alone
+	let clone = reader.clone();
+	let mut edit = MemoryEditor::from(reader);
+	edit.create_file(b"a", b"changed", key).unwrap();
+	assert_eq!(clone.read(b"a", key).unwrap(), &EXAMPLE[..100]);
+}
+
+#[test]
+fn test_key_slots() {
+	let ref key = [25, 26];
+	let ref user_key = [27, 28];
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &EXAMPLE[..100], key).unwrap();
+	edit.add_key_slot(user_key, key).unwrap();
+	let (blocks, _) = edit.finish(key).unwrap();
+
+	let slots = key_slots(&blocks);
+	assert_eq!(slots.len(), 1);
+	let ref unlocked = unlock_key(&slots, user_key).unwrap();
+	assert_eq!(unlocked, key);
+	assert_eq!(unlock_key(&slots, key), None);
+
+	// The key slots are split off and appended again after the directory
+	let mut edit = MemoryEditor::from_blocks(blocks, unlocked).unwrap();
+	assert_eq!(edit.key_slots().len(), 1);
+	edit.create_file(b"b", &EXAMPLE[100..200], key).unwrap();
+	let (blocks, _) = edit.finish(key).unwrap();
+	assert_eq!(unlock_key(&key_slots(&blocks), user_key), Some(*key));
+
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.read(b"b", key).unwrap(), &EXAMPLE[100..200]);
+
+	// Without key slots there is no footer
+	let mut edit = MemoryEditor::from(reader);
+	assert!(edit.key_slots().is_empty());
+	assert!(!edit.remove_key_slot(user_key));
+	let (blocks, _) = edit.finish(key).unwrap();
+	assert!(key_slots(&blocks).is_empty());
+}
+
+#[test]
+fn test_from_static() {
+	let ref key = [29, 30];
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &EXAMPLE[..100], key).unwrap();
+	let (blocks, _) = edit.finish(key).unwrap();
+
+	// The blocks are borrowed and left encrypted
+	let blocks: &'static [Block] = Vec::leak(blocks);
+	let reader = MemoryReader::from_static(blocks, key).unwrap();
+	assert_eq!(reader.read(b"a", key).unwrap(), &EXAMPLE[..100]);
+	assert_eq!(MemoryReader::from_bytes(dataview::bytes(blocks), key).unwrap().read(b"a", key).unwrap(), &EXAMPLE[..100]);
+
+	assert_eq!(MemoryReader::from_static(blocks, &[31, 32]).err().map(|err| err.kind()), Some(ErrorKind::PermissionDenied));
+	assert_eq!(MemoryReader::from_static(&blocks[..3], key).err().map(|err| err.kind()), Some(ErrorKind::InvalidData));
+}
+
+#[test]
+fn test_extensions() {
+	let ref key = [35, 36];
+
+	#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+	#[repr(C)]
+	struct Stamp(u32);
+	unsafe impl Pod for Stamp {}
+	impl Extension for Stamp {
+		const TAG: u8 = 0xf0;
+	}
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", b"hello", key).unwrap();
+	let desc = edit.create_lenient(b"a");
+	assert_eq!(desc.extension::<Stamp>(), None);
+	assert!(desc.set_extension(&Stamp(12345)));
+	assert!(desc.set_extension_data(0xf1, &[]));
+	// The records no longer fit, the descriptor is left unchanged
+	assert!(!desc.set_extension_data(0xf2, &[]));
+	assert!(desc.set_extension(&Stamp(54321)));
+	assert_eq!(desc.extensions().collect::<Vec<_>>(), [(0xf1, &[][..]), (0xf0, &54321u32.to_ne_bytes()[..])]);
+	let desc = *desc;
+	assert!(edit.create_link(b"b", &desc).is_ok());
+	assert!(edit.move_file(b"b", b"c"));
+	let reader = MemoryReader::from(edit);
+
+	// Extension records are kept when reading, linking and moving files
+	let desc = reader.find_file(b"a").unwrap();
+	assert_eq!(desc.extension::<Stamp>(), Some(Stamp(54321)));
+	assert_eq!(desc.extension_data(0xf1), Some(&[][..]));
+	assert_eq!(reader.find_file(b"c").unwrap().extension::<Stamp>(), Some(Stamp(54321)));
+	assert_eq!(reader.read(b"a", key).unwrap(), b"hello");
+
+	let mut desc = *desc;
+	assert!(desc.remove_extension(0xf1));
+	assert!(!desc.remove_extension(0xf1));
+	assert_eq!(desc.extension::<Stamp>(), Some(Stamp(54321)));
+	assert!(desc.remove_extension(Stamp::TAG));
+	assert_eq!(desc.flags & Descriptor::FLAG_EXTENSIONS, 0);
+	assert_eq!(desc.reserved[7..], [0, 0]);
+
+	let time = std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_700_000_000_123);
+	let mtime = Mtime::from_system_time(time).unwrap();
+	assert!(desc.set_extension(&mtime));
+	assert_eq!(desc.extension::<Mtime>().map(Mtime::to_system_time), Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000)));
+	assert_eq!(Mtime::from_system_time(std::time::UNIX_EPOCH - std::time::Duration::from_secs(1)), None);
+}
+
+#[test]
+fn test_create_mode() {
+	let ref key = [37, 38];
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file_with(b"a/b", b"one", CreateMode::CreateNew, key).unwrap();
+	let err = edit.create_file_with(b"a/b", b"two", CreateMode::CreateNew, key).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+	assert_eq!(err.path(), Some(&b"a/b"[..]));
+	assert_eq!(edit.create_file_with(b"a", b"two", CreateMode::CreateNew, key).unwrap_err().kind(), ErrorKind::AlreadyExists);
+	assert_eq!(edit.read(b"a/b", key).unwrap(), b"one");
+
+	edit.create_file_with(b"a/b", b"two", CreateMode::Append, key).unwrap();
+	assert_eq!(edit.read(b"a/b", key).unwrap(), b"onetwo");
+	edit.create_file_with(b"a/b", b"three", CreateMode::Overwrite, key).unwrap();
+	assert_eq!(edit.read(b"a/b", key).unwrap(), b"three");
+	edit.create_file_with(b"a/c", b"four", CreateMode::Append, key).unwrap();
+	assert_eq!(edit.read(b"a/c", key).unwrap(), b"four");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_json() {
+	let ref key = [33, 34];
+
+	let mut edit = MemoryEditor::new();
+	let config = std::collections::HashMap::from([("width", 640)]);
+	assert_eq!(edit.create_json(b"config.json", &config, key).unwrap().content_type, Descriptor::CONTENT_TYPE_JSON);
+	edit.create_file(b"broken.json", b"{", key).unwrap();
+	let reader = MemoryReader::from(edit);
+
+	let config: std::collections::HashMap<String, serde_json::Value> = reader.read_json(b"config.json", key).unwrap();
+	assert_eq!(config["width"], 640);
+	assert_eq!(reader.read_to_string(b"config.json", key).unwrap(), r#"{"width":640}"#);
+
+	let err = reader.read_json::<serde_json::Value>(b"broken.json", key).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::InvalidData);
+	assert_eq!(err.path(), Some(&b"broken.json"[..]));
+	assert_eq!(reader.read_json::<serde_json::Value>(b"missing.json", key).unwrap_err().kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn test_delta_files() {
+	let ref key = [39, 40];
+	let v1: Vec<u8> = (0..3000u32).map(|i| (i * 13 % 253) as u8).collect();
+	let mut v2 = v1.clone();
+	v2[1500..1505].copy_from_slice(b"patch");
+	let mut v3 = v1.clone();
+	v3.extend_from_slice(b"appended");
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &v1, key).unwrap();
+	edit.create_file(b"pad", &[1; 100], key).unwrap();
+	let base = edit.find_file(b"a").unwrap().section;
+	let desc = *edit.create_file_with(b"a", &v2, CreateMode::Delta, key).unwrap();
+	assert!(desc.is_delta() && desc.section.size < 10);
+	assert_eq!(desc.delta_base(), Some((base.offset, base.size)));
+	assert_eq!(desc.content_size, 3000);
+	assert_eq!(edit.read(b"a", key).unwrap(), v2);
+
+	// Replacing a delta file reuses its base and files sharing their section are replaced as a whole
+	let desc = *edit.create_file_with(b"a", &v3, CreateMode::Delta, key).unwrap();
+	assert_eq!(desc.delta_base(), Some((base.offset, base.size)));
+	let pad = *edit.find_file(b"pad").unwrap();
+	edit.create_link(b"b", &pad).unwrap();
+	assert!(!edit.create_file_with(b"b", &[2; 100], CreateMode::Delta, key).unwrap().is_delta());
+	assert!(!edit.create_file_with(b"c", &v1, CreateMode::Delta, key).unwrap().is_delta());
+
+	// The base section survives garbage collection and the contents are reconstructed on read
+	edit.gc();
+	let (blocks, _) = edit.finish(key).unwrap();
+	let reader = MemoryReader::from_blocks(blocks.clone(), key).unwrap();
+	let desc = reader.find_file(b"a").unwrap();
+	assert!(desc.is_delta());
+	assert_eq!(reader.read(b"a", key).unwrap(), v3);
+	let mut dest = [0u8; 10];
+	reader.read_data_into(desc, key, 2998, &mut dest).unwrap();
+	assert_eq!(&dest[..], &v3[2998..]);
+	assert!(reader.fsck(u32::MAX, &mut String::new()));
+
+	// Editing a delta file inplace stores its contents as a whole
+	let mut edit = MemoryEditor::from_blocks(blocks, key).unwrap();
+	edit.write_at(b"a", 0, b"xy", key).unwrap();
+	assert!(!edit.find_file(b"a").unwrap().is_delta());
+	assert_eq!(&edit.read(b"a", key).unwrap()[..4], &[b'x', b'y', v3[2], v3[3]]);
+}
+
+#[test]
+fn test_delta_extensions() {
+	let ref key = [49, 50];
+	let v1: Vec<u8> = (0..3000u32).map(|i| (i * 7 % 251) as u8).collect();
+	let mut v2 = v1.clone();
+	v2[100..105].copy_from_slice(b"patch");
+
+	// The base section of a delta file and the extension records share the extension area
+	let mut desc = Descriptor::file(b"a");
+	assert!(desc.set_extension(&Mtime(1234)));
+	let before = desc;
+	assert!(!desc.set_delta_base(10, 20));
+	assert_eq!(desc, before);
+	assert!(desc.remove_extension(Mtime::TAG));
+	assert!(desc.set_delta_base(10, 20));
+	assert!(!desc.set_extension(&Mtime(1234)));
+	assert_eq!(desc.delta_base(), Some((10, 20)));
+
+	// Delta files with extension records are corrupt
+	desc.flags |= Descriptor::FLAG_EXTENSIONS;
+	assert!(!dir::validate(&[desc], u32::MAX));
+
+	// Files with extension records are replaced as a whole and keep their records
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &v1, key).unwrap();
+	let id = edit.find_id(b"a").unwrap();
+	assert!(edit.resolve_mut(id).unwrap().set_extension(&Mtime(1234)));
+	let desc = *edit.create_file_with(b"a", &v2, CreateMode::Delta, key).unwrap();
+	assert!(!desc.is_delta());
+	assert_eq!(desc.extension::<Mtime>(), Some(Mtime(1234)));
+	assert_eq!(edit.read(b"a", key).unwrap(), v2);
+}
+
+#[test]
+fn test_reencrypt_delta_error() {
+	let ref key = [51, 52];
+	let ref new_key = [53, 54];
+	let v1: Vec<u8> = (0..3000u32).map(|i| (i * 11 % 247) as u8).collect();
+	let mut v2 = v1.clone();
+	v2[100..105].copy_from_slice(b"patch");
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &v1, key).unwrap();
+	let desc = *edit.create_file_with(b"a", &v2, CreateMode::Delta, key).unwrap();
+	assert!(desc.is_delta());
+
+	// Reencrypting a delta file whose base failed authentication reports the error and leaves the file unchanged
+	let (offset, _) = desc.delta_base().unwrap();
+	edit.blocks[offset as usize][0] ^= 1;
+	let mut edit_file = edit.edit_file(b"a").unwrap();
+	assert_eq!(edit_file.reencrypt_data(key, new_key).unwrap_err().kind(), ErrorKind::InvalidData);
+	assert_eq!(*edit.find_file(b"a").unwrap(), desc);
+}
+
+#[test]
+fn test_directory_digest() {
+	let ref key = [41, 42];
+	let mut edit = MemoryEditor::new();
+	// The digest covers the archive id, generate it before finishing
+	edit.generate_archive_id().unwrap();
+	edit.create_file(b"a", &EXAMPLE[..100], key).unwrap();
+	edit.create_file(b"garbage", &EXAMPLE[..200], key).unwrap();
+	edit.create_file(b"b/c", &EXAMPLE[100..300], key).unwrap();
+	let desc = *edit.create_file_with(b"b/c", &EXAMPLE[100..310], CreateMode::Delta, key).unwrap();
+	assert!(desc.is_delta());
+	edit.remove(b"garbage");
+	let digest = edit.digest();
+
+	// Moving the file contents around does not change the digest
+	edit.gc();
+	assert_ne!(edit.find_file(b"b/c").unwrap().section.offset, desc.section.offset);
+	assert_eq!(edit.digest(), digest);
+	let (blocks, dir) = edit.finish(key).unwrap();
+	assert_eq!(dir.digest(), digest);
+	assert_eq!(MemoryReader::from_blocks(blocks.clone(), key).unwrap().digest(), digest);
+
+	// Changing the file contents or the names does change the digest
+	let mut edit = MemoryEditor::from_blocks(blocks, key).unwrap();
+	edit.write_at(b"a", 0, b"x", key).unwrap();
+	assert_ne!(edit.digest(), digest);
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &EXAMPLE[..100], key).unwrap();
+	let digest = edit.digest();
+	assert!(edit.move_file(b"a", b"b"));
+	assert_ne!(edit.digest(), digest);
+
+	// Every byte of inline file contents is covered, including those in place of the section offset
+	let inline_digest = |data: &[u8]| {
+		let mut edit = MemoryEditor::new();
+		edit.set_inline_threshold(Descriptor::INLINE_LEN);
+		assert!(edit.create_file(b"a", data, key).unwrap().is_inline());
+		edit.digest()
+	};
+	assert_ne!(inline_digest(b"abcdXYZ"), inline_digest(b"wxyzXYZ"));
+	assert_eq!(inline_digest(b"abcdXYZ"), inline_digest(b"abcdXYZ"));
+}
+
+#[test]
+fn test_seeded_nonces() {
+	let ref key = [43, 44];
+	let build = |seed| {
+		let mut edit = MemoryEditor::new();
+		edit.set_nonce_source(std::sync::Arc::new(SeededNonces::new(seed)));
+		edit.create_file(b"a", &EXAMPLE[..100], key).unwrap();
+		edit.create_file(b"b/c", &EXAMPLE[100..300], key).unwrap();
+		edit.write_at(b"a", 10, b"xyz", key).unwrap();
+		edit.add_key_slot(&[45, 46], key).unwrap();
+		edit.finish(key).unwrap().0
+	};
+
+	// The same seed reproduces the PAKS file byte for byte
+	let blocks = build(1);
+	assert_eq!(build(1), blocks);
+	assert_ne!(build(2), blocks);
+
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(&reader.read(b"b/c", key).unwrap()[..], &EXAMPLE[100..300]);
+	assert!(reader.fsck(u32::MAX, &mut String::new()));
+
+	// Every section is encrypted with a different nonce
+	let nonces = SeededNonces::new(1);
+	let first: Vec<_> = (0..100).map(|_| nonces.nonce()).collect();
+	let mut sorted = first.clone();
+	sorted.sort();
+	sorted.dedup();
+	assert_eq!(sorted.len(), first.len());
+	assert_ne!(SystemNonces.nonce(), SystemNonces.nonce());
+}
+
+#[test]
+fn test_nonce_failure() {
+	struct Unavailable;
+	impl NonceSource for Unavailable {
+		fn nonce(&self) -> Result<Block, ErrorKind> {
+			Err(ErrorKind::Unsupported)
+		}
+	}
+
+	let ref key = [47, 48];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &EXAMPLE[..20], key).unwrap();
+	edit.set_nonce_source(std::sync::Arc::new(Unavailable));
+
+	// Encryption fails with the error of the nonce source instead of panicking
+	let err = edit.create_file(b"b", &EXAMPLE[100..200], key).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::Unsupported);
+	assert_eq!(err.path(), Some(&b"b"[..]));
+	assert!(!dataview::bytes(edit.blocks.as_slice()).windows(100).any(|window| window == &EXAMPLE[100..200]));
+
+	// Failed edits leave the existing file contents and key slots alone
+	assert_eq!(edit.write_at(b"a", 0, b"xyz", key).unwrap_err().kind(), ErrorKind::Unsupported);
+	assert_eq!(&edit.read(b"a", key).unwrap()[..], &EXAMPLE[..20]);
+	assert_eq!(edit.add_key_slot(&[49, 50], key).unwrap_err().kind(), ErrorKind::Unsupported);
+	assert!(edit.key_slots().is_empty());
+	assert_eq!(edit.finish(key).unwrap_err().kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn test_from_bytes_diagnose() {
+	let ref key = [51, 52];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &EXAMPLE[..100], key).unwrap();
+	let (blocks, _) = edit.finish(key).unwrap();
+	let bytes = dataview::bytes(blocks.as_slice());
+	let diagnose = |bytes: &[u8], key: &Key| MemoryEditor::from_bytes_diagnose(bytes, key).err();
+	assert_eq!(diagnose(bytes, key), None);
+
+	assert_eq!(diagnose(&bytes[..bytes.len() - 1], key), Some(ParseReport::SizeMultiple { len: bytes.len() - 1 }));
+	assert_eq!(diagnose(&bytes[..BLOCK_SIZE], key), Some(ParseReport::TooSmall { len: 1 }));
+	assert_eq!(diagnose(bytes, &[51, 53]), Some(ParseReport::IncorrectKey));
+
+	// Corrupt the header, corrupting its MAC changes the whole keystream of the header and looks like an incorrect key
+	let mut corrupt = bytes.to_vec();
+	corrupt[Header::BLOCKS_LEN * BLOCK_SIZE - 1] ^= 1;
+	assert_eq!(diagnose(&corrupt, key), Some(ParseReport::HeaderMac));
+
+	let mut header: Header = dataview::DataView::from(blocks.as_slice()).read(0);
+	assert_eq!(crypt::decrypt_header(&mut header, key), Ok(()));
+	let directory = header.info.directory;
+
+	// Truncate the directory
+	let len = blocks.len() - 1;
+	assert_eq!(diagnose(&bytes[..len * BLOCK_SIZE], key), Some(ParseReport::DirectoryBounds { directory, len }));
+
+	// Corrupt the directory
+	let mut corrupt = bytes.to_vec();
+	corrupt[directory.offset as usize * BLOCK_SIZE] ^= 1;
+	assert_eq!(diagnose(&corrupt, key), Some(ParseReport::DirectoryMac { directory }));
+
+	// Errors keep their kind when converted
+	assert_eq!(MemoryEditor::from_bytes(&corrupt, key).unwrap_err(), ErrorKind::InvalidData);
+	assert_eq!(ParseReport::IncorrectKey.kind(), ErrorKind::PermissionDenied);
+	assert_eq!(ParseReport::DirectoryMac { directory }.to_string(), format!("directory at block {} failed authentication", directory.offset));
+}
+
+#[test]
+fn test_trailing_data() {
+	let ref key = [53, 54];
+	let ref user_key = [55, 56];
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a", &EXAMPLE[..100], key).unwrap();
+	edit.add_key_slot(user_key, key).unwrap();
+	let (blocks, _) = edit.finish(key).unwrap();
+	let bytes = dataview::bytes(blocks.as_slice());
+	assert!(MemoryEditor::from_bytes_strict(bytes, key).is_ok());
+
+	// Pad the PAKS file to a multiple of 4 KiB
+	let mut padded = bytes.to_vec();
+	padded.resize(bytes.len().next_multiple_of(4096), 0);
+	let edit = MemoryEditor::from_bytes(&padded, key).unwrap();
+	assert_eq!(edit.key_slots().len(), 1);
+	assert_eq!(edit.read(b"a", key).unwrap(), &EXAMPLE[..100]);
+	assert_eq!(MemoryReader::from_bytes(&padded, key).unwrap().read(b"a", key).unwrap(), &EXAMPLE[..100]);
+
+	// Strict parsing rejects the padding
+	let len = (padded.len() - bytes.len()) / BLOCK_SIZE;
+	assert_eq!(MemoryEditor::from_bytes_strict(&padded, key).err(), Some(ParseReport::TrailingData { len }));
+	assert_eq!(MemoryReader::from_bytes_strict(&padded, key).err().unwrap(), ErrorKind::InvalidData);
+
+	// The copy of the header before the key slots is not trailing data, see save_to
+	let mut copy = blocks.clone();
+	let footer_start = blocks.len() - KeySlot::BLOCKS_LEN - 1;
+	copy.splice(footer_start..footer_start, blocks[..Header::BLOCKS_LEN].iter().copied());
+	let edit = MemoryEditor::from_bytes_strict(dataview::bytes(copy.as_slice()), key).unwrap();
+	assert_eq!(edit.key_slots().len(), 1);
+	assert_eq!(edit.read(b"a", key).unwrap(), &EXAMPLE[..100]);
+}
+
+#[test]
+fn test_hidden_metadata() {
+	let ref key = [45, 46];
+
+	let mut edit = MemoryEditor::new();
+	edit.generate_archive_id().unwrap();
+	edit.create_file(b"a/file", b"contents", key).unwrap();
+	edit.set_preload_list(&[b"a/file"], key).unwrap();
+	let mut types = ContentTypes::new();
+	types.insert(1, "text");
+	edit.set_content_types(&types, key).unwrap();
+
+	// The metadata paths are reserved for the metadata setters
+	assert_eq!(edit.create_file(PRELOAD_PATH, b"a/file", key).unwrap_err().kind(), ErrorKind::InvalidInput);
+	assert_eq!(edit.remove(CONTENT_TYPES_PATH), None);
+	let (blocks, _) = edit.finish(key).unwrap();
+
+	// The metadata is hidden from the listings and the file iterators
+	let reader = MemoryReader::from_blocks(blocks.clone(), key).unwrap();
+	assert_eq!(reader.display_flat().to_string(), "a\tdir\t0\na/file\tfile\t8\n");
+	let files: Vec<_> = reader.read_all(key).map(|(path, _)| path).collect();
+	assert_eq!(files, [b"a/file".to_vec()]);
+	crate::testing::assert_files(&reader, [(b"a/file", b"contents")], key);
+	assert_eq!(reader.preload_list(key).unwrap(), [b"a/file".to_vec()]);
+	assert_eq!(reader.content_types(key).unwrap(), types);
+
+	// Migrating keeps the metadata
+	let ref new_key = [47, 48];
+	let mut edit = MemoryEditor::new();
+	migrate(&reader, key, &mut edit, new_key).unwrap();
+	let (migrated, _) = edit.finish(new_key).unwrap();
+	let migrated = MemoryReader::from_blocks(migrated, new_key).unwrap();
+	assert_eq!(migrated.archive_id(), reader.archive_id());
+	assert_eq!(migrated.preload_list(new_key).unwrap(), [b"a/file".to_vec()]);
+	assert_eq!(migrated.content_types(new_key).unwrap(), types);
+
+	// An empty preload list still removes it
+	let mut edit = MemoryEditor::from_blocks(blocks, key).unwrap();
+	edit.set_preload_list(&[], key).unwrap();
+	assert_eq!(edit.find_desc(PRELOAD_PATH), None);
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn test_tracing_spans() {
+	use std::sync::{Arc, Mutex};
+	use tracing::{span, Event, Metadata, Subscriber};
+
+	// Records the name and the field names of every new span
+	struct Spans(Arc<Mutex<Vec<(&'static str, String)>>>);
+	impl Subscriber for Spans {
+		fn enabled(&self, _: &Metadata) -> bool {
+			true
+		}
+		fn new_span(&self, span: &span::Attributes) -> span::Id {
+			let mut spans = self.0.lock().unwrap();
+			spans.push((span.metadata().name(), span.metadata().fields().to_string()));
+			span::Id::from_u64(spans.len() as u64)
+		}
+		fn record(&self, _: &span::Id, _: &span::Record) {}
+		fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+		fn event(&self, _: &Event) {}
+		fn enter(&self, _: &span::Id) {}
+		fn exit(&self, _: &span::Id) {}
+	}
+
+	let ref key = [1, 2];
+	let spans = Arc::new(Mutex::new(Vec::new()));
+	tracing::subscriber::with_default(Spans(spans.clone()), || {
+		let mut edit = MemoryEditor::new();
+		edit.create_file(b"a", b"contents", key).unwrap();
+		edit.gc();
+		let (blocks, _) = edit.finish(key).unwrap();
+		let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+		reader.read(b"a", key).unwrap();
+	});
+
+	let spans = spans.lock().unwrap();
+	let names: Vec<_> = spans.iter().map(|&(name, _)| name).collect();
+	assert_eq!(names, ["gc", "finish", "from_blocks", "read_data"]);
+	assert_eq!(spans[1].1, "{blocks, descriptors}");
+	assert_eq!(spans[3].1, "{offset, size}");
+}
@@ -0,0 +1,127 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use super::*;
+
+/// Metrics hooks of the readers.
+///
+/// Install with [`MemoryReader::set_metrics`] or [`FileReader::set_metrics`] to surface asset streaming health, eg. on a debug HUD.
+/// Every method has an empty default implementation, implement only the hooks of interest.
+/// Hooks may be called from multiple threads and should be cheap, see [`Counters`] for a ready-made implementation.
+pub trait Metrics: Send + Sync {
+	/// Called after a section of the given size in blocks is read.
+	///
+	/// Sections which fail authentication are not reported here, see [`mac_failure`](Self::mac_failure).
+	#[inline]
+	fn section_read(&self, size: u32) {
+		let _ = size;
+	}
+
+	/// Called with the number of bytes decrypted after a section is read.
+	///
+	/// Plaintext file contents are not decrypted and are not reported here.
+	#[inline]
+	fn bytes_decrypted(&self, bytes: u64) {
+		let _ = bytes;
+	}
+
+	/// Called by caching layers built on top of a reader when a read is served from its cache.
+	///
+	/// The readers themselves do not cache and never call this hook.
+	#[inline]
+	fn cache_hit(&self) {}
+
+	/// Called when a section fails authentication, the data is corrupted or the key is incorrect.
+	#[inline]
+	fn mac_failure(&self) {}
+}
+
+/// Atomic counters implementing [`Metrics`].
+#[derive(Debug, Default)]
+pub struct Counters {
+	bytes_decrypted: AtomicU64,
+	sections_read: AtomicU64,
+	cache_hits: AtomicU64,
+	mac_failures: AtomicU64,
+}
+
+impl Counters {
+	/// Creates new counters starting at zero.
+	#[inline]
+	pub const fn new() -> Counters {
+		Counters {
+			bytes_decrypted: AtomicU64::new(0),
+			sections_read: AtomicU64::new(0),
+			cache_hits: AtomicU64::new(0),
+			mac_failures: AtomicU64::new(0),
+		}
+	}
+
+	/// Returns the number of bytes decrypted.
+	#[inline]
+	pub fn bytes_decrypted(&self) -> u64 {
+		self.bytes_decrypted.load(Ordering::Relaxed)
+	}
+
+	/// Returns the number of sections read.
+	#[inline]
+	pub fn sections_read(&self) -> u64 {
+		self.sections_read.load(Ordering::Relaxed)
+	}
+
+	/// Returns the number of cache hits.
+	#[inline]
+	pub fn cache_hits(&self) -> u64 {
+		self.cache_hits.load(Ordering::Relaxed)
+	}
+
+	/// Returns the number of sections which failed authentication.
+	#[inline]
+	pub fn mac_failures(&self) -> u64 {
+		self.mac_failures.load(Ordering::Relaxed)
+	}
+
+	/// Resets all counters to zero.
+	pub fn reset(&self) {
+		self.bytes_decrypted.store(0, Ordering::Relaxed);
+		self.sections_read.store(0, Ordering::Relaxed);
+		self.cache_hits.store(0, Ordering::Relaxed);
+		self.mac_failures.store(0, Ordering::Relaxed);
+	}
+}
+
+impl Metrics for Counters {
+	#[inline]
+	fn section_read(&self, _size: u32) {
+		self.sections_read.fetch_add(1, Ordering::Relaxed);
+	}
+	#[inline]
+	fn bytes_decrypted(&self, bytes: u64) {
+		self.bytes_decrypted.fetch_add(bytes, Ordering::Relaxed);
+	}
+	#[inline]
+	fn cache_hit(&self) {
+		self.cache_hits.fetch_add(1, Ordering::Relaxed);
+	}
+	#[inline]
+	fn mac_failure(&self) {
+		self.mac_failures.fetch_add(1, Ordering::Relaxed);
+	}
+}
+
+// Reports the outcome of reading a section to the metrics
+// The number of bytes decrypted is zero for plaintext file contents
+pub(crate) fn report(metrics: Option<&dyn Metrics>, section: &Section, decrypted: usize, error: Option<ErrorKind>) {
+	let metrics = match metrics {
+		Some(metrics) => metrics,
+		None => return,
+	};
+	match error {
+		None => {
+			metrics.section_read(section.size);
+			if decrypted > 0 {
+				metrics.bytes_decrypted(decrypted as u64);
+			}
+		},
+		Some(ErrorKind::InvalidData) => metrics.mac_failure(),
+		Some(_) => {},
+	}
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use super::*;
+
+/// Migrates the contents of a PAKS archive into the newest file format.
+///
+/// Every file is decrypted with the old key and written into the editor encrypted with the new key.
+/// The directory structure, content types and [plaintext](Descriptor::FLAG_PLAINTEXT) files are preserved.
+/// Links to the same file contents remain linked, bundled files remain bundled, empty directories are kept and garbage left behind by removed files is not copied.
+///
+/// The archive is written in the newest file format when the editor is finished.
+/// Readers of older file format versions are upgraded on the fly, see [`InfoHeader::is_supported`].
+///
+/// The newest file format adds the [descriptor flags](Descriptor::flags), the synthetic header nonce and the per-archive block size.
+/// File sizes remain 32-bit and files are not split into chunks, so files larger than 4 GiB cannot be stored.
+/// Widening [`Descriptor::content_size`] and [`Section`] would break every user of these public fields, this is left for a future format version.
+///
+/// # Errors
+///
+/// * [`ErrorKind::InvalidInput`]: A path in the archive is invalid, see [`validate_path`].
+/// * [`ErrorKind::InvalidData`]: A file failed authentication with the old key.
+pub fn migrate(reader: &MemoryReader, old_key: &Key, edit: &mut MemoryEditor, key: &Key) -> Result<(), Error> {
+	let mut state = Migrate {
+		reader,
+		old_key,
+		edit,
+		key,
+		path: Vec::new(),
+		links: HashMap::new(),
+	};
+	state.dir(reader)
+}
+
+struct Migrate<'a> {
+	reader: &'a MemoryReader,
+	old_key: &'a Key,
+	edit: &'a mut MemoryEditor,
+	key: &'a Key,
+	path: Vec<u8>,
+	// Maps the old sections to the new sections to preserve links
+	links: HashMap<(u32, u32), Section>,
+}
+
+impl<'a> Migrate<'a> {
+	fn dir(&mut self, dir: &[Descriptor]) -> Result<(), Error> {
+		let mut i = 0;
+		while i < dir.len() {
+			let desc = &dir[i];
+			let next_i = dir::next_sibling(desc, i, dir.len());
+
+			let path_len = self.path.len();
+			if path_len != 0 {
+				self.path.push(b'/');
+			}
+			self.path.extend_from_slice(desc.name());
+
+			if desc.is_dir() {
+				self.edit.create_dir(&self.path).map_err(|err| Error::from(err).at(&self.path))?;
+				self.dir(&dir[i + 1..next_i])?;
+			}
+			else {
+				self.file(desc)?;
+			}
+
+			self.path.truncate(path_len);
+			i = next_i;
+		}
+		Ok(())
+	}
+
+	fn file(&mut self, desc: &Descriptor) -> Result<(), Error> {
+		let link_key = (desc.section.offset, desc.section.size);
+		let mut edit_file = match dir::is_metadata_path(&self.path) {
+			true => self.edit.edit_metadata(&self.path),
+			false => self.edit.edit_file(&self.path).map_err(|err| Error::from(err).at(&self.path))?,
+		};
+		edit_file.set_content(desc.content_type, desc.content_size).set_plaintext(desc.is_plaintext());
+
+		// Inline file contents are part of the directory
+		if let Some(data) = desc.inline_data() {
+			edit_file.set_inline_data(data);
+			return Ok(());
+		}
+
+		if let Some(section) = self.links.get(&link_key) {
+			match desc.is_bundled() {
+				true => edit_file.set_bundled(section, desc.data_offset()),
+				false => edit_file.set_section(section),
+			};
+			return Ok(());
+		}
+
+		// Bundled files share a copy of the whole section
+		if desc.is_bundled() {
+			let blocks = self.reader.read_section(&desc.section, self.old_key).map_err(|err| err.at(&self.path))?;
+			let data = dataview::bytes(blocks.as_slice());
+			edit_file.set_content(desc.content_type, data.len() as u32).allocate_data().write_data(data, self.key).map_err(|err| err.at(&self.path))?;
+			let section = edit_file.descriptor().section;
+			edit_file.set_content(desc.content_type, desc.content_size).set_bundled(&section, desc.data_offset());
+			self.links.insert(link_key, section);
+			return Ok(());
+		}
+
+		let data = self.reader.read_data(desc, self.old_key).map_err(|err| err.at(&self.path))?;
+		edit_file.allocate_data().write_data(&data, self.key).map_err(|err| err.at(&self.path))?;
+		self.links.insert(link_key, edit_file.descriptor().section);
+		Ok(())
+	}
+}
+
+#[test]
+fn test_migrate() {
+	let ref old_key = [1, 2];
+	let ref key = [3, 4];
+
+	let mut edit = MemoryEditor::new();
+	edit.create_file(b"a/x", b"hello", old_key).unwrap();
+	let desc = *edit.find_file(b"a/x").unwrap();
+	edit.create_link(b"b/y", &desc).unwrap();
+	edit.create_dir(b"d").unwrap();
+	let mut edit_file = edit.edit_file(b"c").unwrap();
+	edit_file.set_content(3, 5).set_plaintext(true);
+	edit_file.allocate_data().write_data(b"plain", old_key).unwrap();
+	edit.create_bundle(&[(b"e/1", b"one"), (b"e/2", b"two")], old_key).unwrap();
+	let (blocks, _) = edit.finish(old_key).unwrap();
+
+	let reader = MemoryReader::from_blocks(blocks, old_key).unwrap();
+	let mut edit = MemoryEditor::new();
+	migrate(&reader, old_key, &mut edit, key).unwrap();
+	let (blocks, _) = edit.finish(key).unwrap();
+
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	assert_eq!(reader.read(b"a/x", key).unwrap(), b"hello");
+	assert_eq!(reader.find_file(b"a/x").unwrap().section, reader.find_file(b"b/y").unwrap().section);
+	assert_eq!(reader.find_file(b"c").unwrap().content_type, 3);
+	assert_eq!(reader.plaintext_data(reader.find_file(b"c").unwrap()), Some(&b"plain"[..]));
+	assert!(reader.find_desc(b"d").unwrap().is_dir());
+	assert_eq!(reader.read(b"e/2", key).unwrap(), b"two");
+	assert_eq!(reader.find_file(b"e/1").unwrap().section, reader.find_file(b"e/2").unwrap().section);
+}
+
+#[test]
+fn test_read_version_1() {
+	let ref key = [5, 6];
+
+	// Version 1 did not define the trailing bytes of the descriptors nor the archive id
+	let mut edit = MemoryEditor::new();
+	edit.generate_id = false;
+	edit.create_file(b"file", b"hello", key).unwrap();
+	let desc = edit.create_lenient(b"file");
+	desc.flags = !0;
+	desc.reserved = [!0; 9];
+	let (mut blocks, _) = edit.finish(key).unwrap();
+
+	// Rewrite the header as version 1
+	let mut header: Header = dataview::DataView::from(blocks.as_slice()).read(0);
+	assert_eq!(crypt::decrypt_header(&mut header, key), Ok(()));
+	header.info.version = InfoHeader::VERSION_1;
+	let mut section = Header::SECTION;
+	crypt::encrypt_section_from(None, header.info.as_mut(), &mut section, key).unwrap();
+	header.nonce = section.nonce;
+	header.mac = section.mac;
+	dataview::DataView::from_mut(blocks.as_mut_slice()).write(0, &header);
+
+	let reader = MemoryReader::from_blocks(blocks, key).unwrap();
+	let desc = reader.find_file(b"file").unwrap();
+	assert_eq!(desc.flags, 0);
+	assert_eq!(reader.read_data(desc, key).unwrap(), b"hello");
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use super::*;
+
+/// Table of the original names of stripped descriptors.
+///
+/// Maps the hash of the path of every descriptor to its name, see [`MemoryEditor::strip_names`].
+/// Export the table before stripping the names and keep it next to the release PAKS file for internal tooling.
+/// The table is stored as a separate PAKS file encrypted with its own key.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NameTable {
+	names: HashMap<u64, Name>,
+}
+
+impl NameTable {
+	// Path of the file holding the names in the PAKS file of the table
+	const PATH: &'static [u8] = b"names";
+
+	/// Creates a new empty name table.
+	#[inline]
+	pub fn new() -> NameTable {
+		NameTable { names: HashMap::new() }
+	}
+
+	/// Collects the names of the directory.
+	///
+	/// Descriptors whose name is already stripped or encrypted are skipped.
+	pub fn from_directory(directory: &Directory) -> NameTable {
+		let mut dir = directory.as_ref().to_vec();
+		dir::hash_paths(&mut dir);
+		let names = dir.iter()
+			.filter(|desc| !desc.is_name_stripped() && !desc.is_name_encrypted())
+			.filter_map(|desc| Some((desc.path_hash()?, desc.name)))
+			.collect();
+		NameTable { names }
+	}
+
+	/// Returns the number of names in the table.
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.names.len()
+	}
+
+	/// Returns true if the table has no names.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.names.is_empty()
+	}
+
+	/// Gets the name of the descriptor by the hash of its path.
+	#[inline]
+	pub fn get(&self, hash: u64) -> Option<&[u8]> {
+		self.names.get(&hash).map(|name| name.get())
+	}
+
+	/// Restores the names of the stripped descriptors.
+	///
+	/// The hashes of the paths are kept, the descriptors are found by both their path and hash afterwards.
+	/// Returns the number of names restored.
+	pub fn restore(&self, directory: &mut Directory) -> usize {
+		let mut count = 0;
+		for desc in directory.as_mut() {
+			if let (true, Some(hash)) = (desc.is_name_stripped(), desc.path_hash()) {
+				if let Some(name) = self.names.get(&hash) {
+					desc.name = *name;
+					desc.flags &= !Descriptor::FLAG_STRIPPED_NAME;
+					count += 1;
+				}
+			}
+		}
+		count
+	}
+
+	/// Encrypts the table as a PAKS file.
+	///
+	/// # Errors
+	///
+	/// Fails with the error of the random number generator of the operating system.
+	pub fn to_bytes(&self, key: &Key) -> Result<Vec<u8>, Error> {
+		let mut data = Vec::new();
+		for (&hash, name) in &self.names {
+			data.extend_from_slice(&hash.to_le_bytes());
+			data.push(name.get().len() as u8);
+			data.extend_from_slice(name.get());
+		}
+
+		let mut edit = MemoryEditor::new();
+		let _ = edit.create_file(NameTable::PATH, &data, key);
+		let (blocks, _) = edit.finish(key)?;
+		Ok(dataview::bytes(blocks.as_slice()).to_vec())
+	}
+
+	/// Decrypts the table from a PAKS file.
+	///
+	/// # Errors
+	///
+	/// * [`ErrorKind::PermissionDenied`]: The encryption key is incorrect.
+	/// * [`ErrorKind::InvalidData`]: The bytes are not a name table or are corrupted.
+	pub fn from_bytes(bytes: &[u8], key: &Key) -> Result<NameTable, Error> {
+		let reader = MemoryReader::from_bytes(bytes, key)?;
+		let data = match reader.find_file(NameTable::PATH) {
+			Some(desc) => reader.read_data(desc, key)?,
+			None => return Err(Error::new(ErrorKind::InvalidData)),
+		};
+
+		let mut names = HashMap::new();
+		let mut data = &data[..];
+		while !data.is_empty() {
+			let (hash, len) = match data {
+				[a, b, c, d, e, f, g, h, len, ..] => (u64::from_le_bytes([*a, *b, *c, *d, *e, *f, *g, *h]), *len as usize),
+				_ => return Err(Error::new(ErrorKind::InvalidData)),
+			};
+			let name = match data.get(9..9 + len) {
+				Some(name) if len <= dir::MAX_NAME_LEN => name,
+				_ => return Err(Error::new(ErrorKind::InvalidData)),
+			};
+			names.insert(hash, Name::from(name));
+			data = &data[9 + len..];
+		}
+		Ok(NameTable { names })
+	}
+}
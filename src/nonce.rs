@@ -0,0 +1,72 @@
+use std::fmt;
+use std::sync::Mutex;
+use super::*;
+
+/// Source of the nonces of the encrypted sections.
+///
+/// Install with [`MemoryEditor::set_nonce_source`] or [`FileEditor::set_nonce_source`], editors without a nonce source use [`SystemNonces`].
+/// Platforms with their own entropy source, eg. consoles, implement this trait instead of registering a custom `getrandom` backend.
+///
+/// Every section derives its encryption keys from its nonce, a nonce must never be returned twice for the same key.
+/// Nonces are not secret but must be unpredictable, see [`SeededNonces`] for the only exception.
+///
+/// If no nonce is available the edit fails with the returned error instead of encrypting, eg. [`MemoryEditor::finish`].
+/// A file being written when the nonce source fails is left without valid contents, no plaintext is written.
+pub trait NonceSource: Send + Sync {
+	/// Returns a fresh nonce.
+	fn nonce(&self) -> Result<Block, ErrorKind>;
+}
+
+impl fmt::Debug for dyn NonceSource {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("NonceSource")
+	}
+}
+
+/// Nonces from the operating system's random number generator.
+///
+/// This is the default nonce source.
+/// If the random number generator fails the error is mapped to the closest [`ErrorKind`], [`ErrorKind::Unsupported`] if the platform has no random number generator.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemNonces;
+
+impl NonceSource for SystemNonces {
+	#[inline]
+	fn nonce(&self) -> Result<Block, ErrorKind> {
+		crypt::random_nonce()
+	}
+}
+
+/// Reproducible nonces for tests.
+///
+/// Editors with the same seed making the same edits produce byte for byte identical PAKS files.
+///
+/// <div class="warning">The nonces are predictable, never use this nonce source outside of tests.</div>
+#[derive(Debug)]
+pub struct SeededNonces {
+	state: Mutex<u64>,
+}
+
+impl SeededNonces {
+	/// Creates a new nonce source from the seed.
+	#[inline]
+	pub const fn new(seed: u64) -> SeededNonces {
+		SeededNonces { state: Mutex::new(seed) }
+	}
+}
+
+impl NonceSource for SeededNonces {
+	fn nonce(&self) -> Result<Block, ErrorKind> {
+		let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+		Ok([splitmix64(&mut state), splitmix64(&mut state)])
+	}
+}
+
+// Scrambled counter, consecutive nonces are unrelated to each other like random nonces
+fn splitmix64(state: &mut u64) -> u64 {
+	*state = state.wrapping_add(0x9E3779B97F4A7C15);
+	let mut z = *state;
+	z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+	z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+	z ^ (z >> 31)
+}
@@ -0,0 +1,137 @@
+/*!
+Packing files into PAKS archives.
+*/
+
+use std::io;
+use super::*;
+
+/// Maps source paths to destination paths in the PAKS archive while packing.
+///
+/// The source path is the path relative to the root being packed, using `/` as the separator.
+pub trait PathMapper {
+	/// Returns the destination path for the given source path.
+	///
+	/// Returns `None` to skip the source path.
+	fn map_path(&self, src_path: &[u8]) -> Option<Vec<u8>>;
+}
+
+impl<F: Fn(&[u8]) -> Option<Vec<u8>>> PathMapper for F {
+	#[inline]
+	fn map_path(&self, src_path: &[u8]) -> Option<Vec<u8>> {
+		self(src_path)
+	}
+}
+
+/// Path mapper replacing path prefixes.
+///
+/// The rule with the longest matching source prefix is applied.
+/// Prefixes are matched by whole path components, the prefix `art` matches `art/a.png` but not `artwork/a.png`.
+/// Source paths not matching any rule are left unchanged.
+#[derive(Clone, Debug, Default)]
+pub struct PrefixMapper {
+	rules: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl PrefixMapper {
+	/// Creates a new `PrefixMapper` instance without any rules.
+	#[inline]
+	pub const fn new() -> PrefixMapper {
+		PrefixMapper { rules: Vec::new() }
+	}
+
+	/// Adds a rule replacing the source prefix with the destination prefix.
+	pub fn add(&mut self, src_prefix: &[u8], dest_prefix: &[u8]) -> &mut PrefixMapper {
+		let trim = |s: &[u8]| -> Vec<u8> {
+			let start = s.iter().position(|&chr| chr != b'/').unwrap_or(s.len());
+			let end = s.iter().rposition(|&chr| chr != b'/').map_or(start, |i| i + 1);
+			s[start..end].to_vec()
+		};
+		self.rules.push((trim(src_prefix), trim(dest_prefix)));
+		self
+	}
+
+	/// Parses and adds a rule formatted as `SRC_PREFIX=DEST_PREFIX`.
+	///
+	/// Returns `None` if the rule does not contain a `=`.
+	pub fn add_rule(&mut self, rule: &str) -> Option<&mut PrefixMapper> {
+		let (src_prefix, dest_prefix) = rule.split_once('=')?;
+		Some(self.add(src_prefix.as_bytes(), dest_prefix.as_bytes()))
+	}
+}
+
+impl PathMapper for PrefixMapper {
+	fn map_path(&self, src_path: &[u8]) -> Option<Vec<u8>> {
+		let mut best: Option<(&[u8], &[u8])> = None;
+		for (src_prefix, dest_prefix) in &self.rules {
+			let tail = match src_path.strip_prefix(&src_prefix[..]) {
+				Some(tail) => tail,
+				None => continue,
+			};
+			let is_component = src_prefix.is_empty() || tail.is_empty() || tail[0] == b'/';
+			if is_component && best.is_none_or(|(prefix, _)| src_prefix.len() > prefix.len()) {
+				best = Some((src_prefix, dest_prefix));
+			}
+		}
+
+		let (src_prefix, dest_prefix) = match best {
+			Some(best) => best,
+			None => return Some(src_path.to_vec()),
+		};
+
+		let tail = &src_path[src_prefix.len()..];
+		let tail = tail.strip_prefix(b"/").unwrap_or(tail);
+		let mut dest_path = dest_prefix.to_vec();
+		if !dest_path.is_empty() && !tail.is_empty() {
+			dest_path.push(b'/');
+		}
+		dest_path.extend_from_slice(tail);
+		Some(dest_path)
+	}
+}
+
+/// File contents encrypted ahead of time.
+///
+/// The encryption does not depend on where the contents are stored in the PAKS archive.
+/// Files can be encrypted in parallel and written to the PAKS archive afterwards,
+/// see [`FileEditFile::write_encrypted`].
+#[derive(Clone, Debug, Default)]
+pub struct EncryptedData {
+	/// The encrypted blocks.
+	pub blocks: Vec<Block>,
+	/// The section with the nonce and MAC of the encrypted blocks, its offset is zero.
+	pub section: Section,
+	/// The size of the contents in bytes.
+	pub content_size: u32,
+}
+
+impl EncryptedData {
+	/// Encrypts the data with the given key.
+	///
+	/// If the data's len is greater than 4 GiB it is truncated as its size is stored in a `u32`.
+	///
+	/// Fails with the error of the random number generator of the operating system.
+	pub fn encrypt(data: &[u8], key: &Key) -> io::Result<EncryptedData> {
+		let content_size = usize::min(data.len(), u32::MAX as usize) as u32;
+		let size = bytes2blocks(content_size);
+		let mut blocks = vec![Block::default(); size as usize];
+		dataview::bytes_mut(blocks.as_mut_slice())[..content_size as usize].copy_from_slice(&data[..content_size as usize]);
+		let mut section = Section { offset: 0, size, ..Section::default() };
+		crypt::encrypt_section_from(None, &mut blocks, &mut section, key)?;
+		Ok(EncryptedData { blocks, section, content_size })
+	}
+}
+
+mod manifest;
+pub use self::manifest::*;
+
+mod cache;
+pub use self::cache::*;
+
+mod transform;
+pub use self::transform::*;
+
+mod sniff;
+pub use self::sniff::*;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,196 @@
+use std::{fs, io, time};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use super::*;
+
+/// Timestamp used to detect changes to a source file without reading it.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct SourceStamp {
+	/// Size of the file in bytes.
+	pub size: u64,
+	/// Modification time in nanoseconds since the unix epoch.
+	pub mtime: u64,
+}
+
+impl SourceStamp {
+	/// Queries the source stamp of a file.
+	pub fn of<P: ?Sized + AsRef<Path>>(path: &P) -> io::Result<SourceStamp> {
+		let meta = fs::metadata(path)?;
+		let mtime = meta.modified()?
+			.duration_since(time::UNIX_EPOCH)
+			.map(|dur| dur.as_nanos() as u64)
+			.unwrap_or(0);
+		Ok(SourceStamp { size: meta.len(), mtime })
+	}
+}
+
+/// Cached information about a packed file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CacheEntry {
+	/// Source file path the file was packed from.
+	pub src: PathBuf,
+	/// Source stamp of the source file when it was packed.
+	pub stamp: SourceStamp,
+	/// Digest of the source file contents.
+	pub digest: hash::Digest,
+	/// Names of the transforms applied to the file contents, see [`Transforms::names`].
+	pub transforms: String,
+	/// Content type of the file descriptor.
+	pub content_type: u32,
+	/// Id of the key used to encrypt the file or `None` for the archive's key.
+	pub key_id: Option<String>,
+	/// Cryptographic nonce of the file's section.
+	pub nonce: Block,
+	/// Cryptographic MAC of the file's section.
+	pub mac: Block,
+}
+
+impl CacheEntry {
+	/// Returns if the descriptor still refers to the cached file contents.
+	#[inline]
+	pub fn matches(&self, desc: &Descriptor) -> bool {
+		desc.is_file() && desc.content_type == self.content_type && desc.section.nonce == self.nonce && desc.section.mac == self.mac
+	}
+}
+
+/// Incremental packing cache.
+///
+/// The cache is stored in a sidecar file next to the PAKS archive, see [`sidecar_path`](Self::sidecar_path).
+/// For every packed file it records the source file's stamp and digest and the section where its encrypted contents were stored.
+/// When repacking, files whose source is unchanged are carried over from the previous PAKS archive without being read or encrypted again.
+///
+/// The cache contains the paths and digests of the packed files in plaintext, it does not contain any keys.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PackCache {
+	entries: BTreeMap<String, CacheEntry>,
+}
+
+const CACHE_HEADER: &str = "# paks cache v2";
+
+impl PackCache {
+	/// Creates a new empty cache.
+	#[inline]
+	pub const fn new() -> PackCache {
+		PackCache { entries: BTreeMap::new() }
+	}
+
+	/// Returns the path of the sidecar cache file for the given PAKS archive.
+	pub fn sidecar_path<P: ?Sized + AsRef<Path>>(archive: &P) -> PathBuf {
+		let mut path = archive.as_ref().as_os_str().to_owned();
+		path.push(".cache");
+		PathBuf::from(path)
+	}
+
+	/// Loads the cache from a file.
+	///
+	/// Returns an empty cache if the file does not exist or was written by an incompatible version.
+	pub fn load<P: ?Sized + AsRef<Path>>(path: &P) -> io::Result<PackCache> {
+		match fs::read_to_string(path) {
+			Ok(text) => Ok(PackCache::parse(&text)),
+			Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(PackCache::new()),
+			Err(err) => Err(err),
+		}
+	}
+
+	/// Saves the cache to a file.
+	pub fn save<P: ?Sized + AsRef<Path>>(&self, path: &P) -> io::Result<()> {
+		fs::write(path, self.to_string())
+	}
+
+	/// Parses the cache.
+	///
+	/// The cache is only an optimization, malformed lines are ignored.
+	pub fn parse(text: &str) -> PackCache {
+		let mut cache = PackCache::new();
+		let mut lines = text.lines();
+		if lines.next() != Some(CACHE_HEADER) {
+			return cache;
+		}
+		for line in lines {
+			if let Some((dest, entry)) = parse_entry(line) {
+				cache.entries.insert(dest, entry);
+			}
+		}
+		cache
+	}
+
+	/// Gets the cached information for the destination path.
+	#[inline]
+	pub fn get(&self, dest: &str) -> Option<&CacheEntry> {
+		self.entries.get(dest)
+	}
+
+	/// Inserts or replaces the cached information for the destination path.
+	#[inline]
+	pub fn insert(&mut self, dest: String, entry: CacheEntry) {
+		self.entries.insert(dest, entry);
+	}
+
+	/// Removes the cached information for the destination path.
+	#[inline]
+	pub fn remove(&mut self, dest: &str) -> Option<CacheEntry> {
+		self.entries.remove(dest)
+	}
+
+	/// Returns the number of cached files.
+	#[inline]
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Returns if the cache is empty.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+}
+
+impl fmt::Display for PackCache {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "{}", CACHE_HEADER)?;
+		for (dest, entry) in &self.entries {
+			let src = match entry.src.to_str() {
+				Some(src) => src,
+				None => continue,
+			};
+			let key_id = entry.key_id.as_deref().unwrap_or("");
+			// Paths containing the field separator cannot be cached
+			if [dest, src, key_id, &entry.transforms].iter().any(|field| field.contains(['\t', '\n', '\r'])) {
+				continue;
+			}
+			writeln!(f, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:016x}{:016x}\t{:016x}{:016x}",
+				dest, src, entry.stamp.size, entry.stamp.mtime, entry.digest, entry.transforms, entry.content_type, key_id,
+				entry.nonce[0], entry.nonce[1], entry.mac[0], entry.mac[1])?;
+		}
+		Ok(())
+	}
+}
+
+fn parse_entry(line: &str) -> Option<(String, CacheEntry)> {
+	let mut fields = line.split('\t');
+	let dest = fields.next()?.to_string();
+	let src = PathBuf::from(fields.next()?);
+	let size = fields.next()?.parse().ok()?;
+	let mtime = fields.next()?.parse().ok()?;
+	let digest = hash::Digest::parse(fields.next()?)?;
+	let transforms = fields.next()?.to_string();
+	let content_type = fields.next()?.parse().ok()?;
+	let key_id = Some(fields.next()?).filter(|key_id| !key_id.is_empty()).map(String::from);
+	let nonce = parse_block(fields.next()?)?;
+	let mac = parse_block(fields.next()?)?;
+	if fields.next().is_some() {
+		return None;
+	}
+	let stamp = SourceStamp { size, mtime };
+	Some((dest, CacheEntry { src, stamp, digest, transforms, content_type, key_id, nonce, mac }))
+}
+
+fn parse_block(s: &str) -> Option<Block> {
+	if s.len() != 32 || !s.is_ascii() {
+		return None;
+	}
+	let lo = u64::from_str_radix(&s[..16], 16).ok()?;
+	let hi = u64::from_str_radix(&s[16..], 16).ok()?;
+	Some([lo, hi])
+}
+
@@ -0,0 +1,366 @@
+use std::{fs, io, path::{Path, PathBuf}};
+use super::*;
+
+/// Packing manifest.
+///
+/// The manifest is a small subset of TOML listing the files to pack:
+///
+/// ```toml
+/// # Each file is described by a `[[file]]` table
+/// [[file]]
+/// dest = "textures/wall.png"
+/// src = "art/wall.png"
+/// content_type = 2
+///
+/// [[file]]
+/// dest = "config/game.json"
+/// src = "config/game.json"
+/// key = "config"
+///
+/// [[file]]
+/// dest = "music/theme.ogg"
+/// src = "music/theme.ogg"
+/// plaintext = true
+///
+/// # Names of the content types stored in the PAKS archive
+/// [content_types]
+/// 2 = "texture/png"
+/// ```
+///
+/// Supported fields:
+///
+/// * `dest`: Required, the destination path in the PAKS archive.
+/// * `src`: Required, the source file path relative to the manifest.
+/// * `content_type`: Optional, the non-zero content type. Defaults to `1`.
+/// * `key`: Optional, the id of the key used to encrypt the file. Defaults to the archive's key.
+/// * `plaintext`: Optional, store the file unencrypted, see [`Descriptor::FLAG_PLAINTEXT`]. Defaults to `false`.
+///
+/// The optional `[content_types]` table names the content types, see [`CONTENT_TYPES_PATH`].
+///
+/// PAKS archives do not compress the files, a `compression` key is rejected instead of being ignored.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Manifest {
+	pub entries: Vec<ManifestEntry>,
+	/// Names of the content types.
+	pub content_types: ContentTypes,
+}
+
+/// Manifest file entry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ManifestEntry {
+	/// Destination path in the PAKS archive.
+	pub dest: String,
+	/// Source file path, relative paths are relative to the manifest.
+	pub src: PathBuf,
+	/// Content type of the file descriptor.
+	pub content_type: u32,
+	/// Id of the key used to encrypt the file or `None` for the archive's key.
+	pub key_id: Option<String>,
+	/// Store the file contents unencrypted.
+	pub plaintext: bool,
+}
+
+impl Manifest {
+	/// Loads the manifest from a file.
+	pub fn load<P: ?Sized + AsRef<Path>>(path: &P) -> io::Result<Manifest> {
+		let text = fs::read_to_string(path)?;
+		Manifest::parse(&text)
+	}
+
+	/// Parses the manifest.
+	///
+	/// # Errors
+	///
+	/// Returns [`io::ErrorKind::InvalidData`] with a message and line number describing the syntax error.
+	pub fn parse(text: &str) -> io::Result<Manifest> {
+		let mut entries = Vec::new();
+		let mut content_types = ContentTypes::new();
+		let mut current: Option<(usize, PartialEntry)> = None;
+		let mut in_content_types = false;
+
+		for (line_nr, line) in text.lines().enumerate() {
+			let line_nr = line_nr + 1;
+			let line = strip_comment(line).trim();
+			if line.is_empty() {
+				continue;
+			}
+
+			if line == "[[file]]" {
+				if let Some((start, entry)) = current.take() {
+					entries.push(entry.finish(start)?);
+				}
+				current = Some((line_nr, PartialEntry::default()));
+				in_content_types = false;
+				continue;
+			}
+			if line == "[content_types]" {
+				if let Some((start, entry)) = current.take() {
+					entries.push(entry.finish(start)?);
+				}
+				in_content_types = true;
+				continue;
+			}
+
+			let (name, value) = match line.split_once('=') {
+				Some((name, value)) => (name.trim(), value.trim()),
+				None => return Err(syntax_error(line_nr, "expected `[[file]]` or `key = value`")),
+			};
+
+			if in_content_types {
+				let content_type = parse_int(line_nr, name)?;
+				if content_type == 0 {
+					return Err(syntax_error(line_nr, "content type must be non-zero"));
+				}
+				if content_types.insert(content_type, &parse_string(line_nr, value)?).is_some() {
+					return Err(syntax_error(line_nr, "duplicate content type"));
+				}
+				continue;
+			}
+
+			let entry = match &mut current {
+				Some((_, entry)) => entry,
+				None => return Err(syntax_error(line_nr, "expected `[[file]]` or `[content_types]` before any keys")),
+			};
+
+			match name {
+				"dest" => entry.dest = Some(parse_string(line_nr, value)?),
+				"src" => entry.src = Some(parse_string(line_nr, value)?),
+				"content_type" => entry.content_type = Some(parse_int(line_nr, value)?),
+				"compression" => return Err(syntax_error(line_nr, "compression is not supported, compress the source file instead")),
+				"key" => entry.key_id = Some(parse_string(line_nr, value)?),
+				"plaintext" => entry.plaintext = Some(parse_bool(line_nr, value)?),
+				_ => return Err(syntax_error(line_nr, "unknown key")),
+			}
+		}
+
+		if let Some((start, entry)) = current.take() {
+			entries.push(entry.finish(start)?);
+		}
+
+		Ok(Manifest { entries, content_types })
+	}
+}
+
+#[derive(Default)]
+struct PartialEntry {
+	dest: Option<String>,
+	src: Option<String>,
+	content_type: Option<u32>,
+	key_id: Option<String>,
+	plaintext: Option<bool>,
+}
+
+impl PartialEntry {
+	fn finish(self, line_nr: usize) -> io::Result<ManifestEntry> {
+		let dest = match self.dest {
+			Some(dest) => dest,
+			None => return Err(syntax_error(line_nr, "missing `dest` key")),
+		};
+		if let Err(err) = dir::validate_path(dest.as_bytes()) {
+			return Err(syntax_error(line_nr, &format!("invalid `dest` path: {}", err)));
+		}
+		let src = match self.src {
+			Some(src) => PathBuf::from(src),
+			None => return Err(syntax_error(line_nr, "missing `src` key")),
+		};
+		let content_type = self.content_type.unwrap_or(1);
+		if content_type == 0 {
+			return Err(syntax_error(line_nr, "`content_type` must be non-zero"));
+		}
+		let key_id = self.key_id;
+		let plaintext = self.plaintext.unwrap_or(false);
+		Ok(ManifestEntry { dest, src, content_type, key_id, plaintext })
+	}
+}
+
+pub(crate) fn strip_comment(line: &str) -> &str {
+	// Find the first `#` outside of a string
+	let mut in_string = false;
+	let mut escaped = false;
+	for (i, chr) in line.char_indices() {
+		if escaped {
+			escaped = false;
+			continue;
+		}
+		match chr {
+			'\\' if in_string => escaped = true,
+			'"' => in_string = !in_string,
+			'#' if !in_string => return &line[..i],
+			_ => (),
+		}
+	}
+	line
+}
+
+fn parse_string(line_nr: usize, value: &str) -> io::Result<String> {
+	unquote(value).map_err(|message| syntax_error(line_nr, message))
+}
+
+// Parses a TOML basic string with the common escape sequences
+pub(crate) fn unquote(value: &str) -> Result<String, &'static str> {
+	let inner = match value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+		Some(inner) if value.len() >= 2 => inner,
+		_ => return Err("expected a string"),
+	};
+	let mut result = String::with_capacity(inner.len());
+	let mut chars = inner.chars();
+	while let Some(chr) = chars.next() {
+		if chr != '\\' {
+			result.push(chr);
+			continue;
+		}
+		result.push(match chars.next() {
+			Some('\\') => '\\',
+			Some('"') => '"',
+			Some('n') => '\n',
+			Some('t') => '\t',
+			_ => return Err("invalid escape sequence"),
+		});
+	}
+	Ok(result)
+}
+
+fn parse_int(line_nr: usize, value: &str) -> io::Result<u32> {
+	let value = value.replace('_', "");
+	let result = match value.strip_prefix("0x") {
+		Some(hex) => u32::from_str_radix(hex, 16),
+		None => value.parse(),
+	};
+	result.map_err(|_| syntax_error(line_nr, "expected an integer"))
+}
+
+fn parse_bool(line_nr: usize, value: &str) -> io::Result<bool> {
+	match value {
+		"true" => Ok(true),
+		"false" => Ok(false),
+		_ => Err(syntax_error(line_nr, "expected a boolean")),
+	}
+}
+
+fn syntax_error(line_nr: usize, message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, format!("manifest line {}: {}", line_nr, message))
+}
+
+/// Packs the files listed in the manifest into a new PAKS archive.
+///
+/// Relative source paths are resolved against the base directory, typically the directory containing the manifest.
+///
+/// Files with a key id are encrypted with the key returned by the key lookup, otherwise the archive's key is used.
+/// The returned editor must be finished with the archive's key.
+///
+/// # Errors
+///
+/// * [`io::ErrorKind::NotFound`]: The key lookup does not know the entry's key id.
+/// * [`io::Error`]: An error reading a source file or creating its descriptor.
+pub fn from_manifest(manifest: &Manifest, base_dir: &Path, key: &Key, key_lookup: &dyn Fn(&str) -> Option<Key>) -> io::Result<MemoryEditor> {
+	from_manifest_incremental(manifest, base_dir, key, key_lookup, &Transforms::new(), None, &mut PackCache::new())
+}
+
+/// Packs the files listed in the manifest into a new PAKS archive reusing the previous PAKS archive.
+///
+/// The file contents are passed through the matching transforms before being encrypted.
+///
+/// Files are carried over from the previous PAKS archive without being encrypted again if the cache shows their contents are unchanged.
+/// If the source file's size and modification time match the cache the source file is not read at all.
+/// Carried over contents are authenticated with the file's key, files whose key has changed are encrypted again.
+///
+/// Plaintext files are always packed again.
+/// The archive id of the previous PAKS archive is kept, see [`ArchiveId`].
+///
+/// On success the cache is replaced with the information about the newly packed files.
+///
+/// See [`from_manifest`] for more information.
+pub fn from_manifest_incremental(
+	manifest: &Manifest, base_dir: &Path, key: &Key, key_lookup: &dyn Fn(&str) -> Option<Key>,
+	transforms: &Transforms, prev: Option<&MemoryReader>, cache: &mut PackCache,
+) -> io::Result<MemoryEditor> {
+	let mut edit = MemoryEditor::new();
+	if let Some(prev) = prev {
+		edit.directory.keep_archive_id(prev);
+	}
+	let mut new_cache = PackCache::new();
+
+	for entry in &manifest.entries {
+		let file_key = match &entry.key_id {
+			Some(key_id) => match key_lookup(key_id) {
+				Some(file_key) => file_key,
+				None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("unknown key id: {}", key_id))),
+			},
+			None => *key,
+		};
+
+		let src_path = base_dir.join(&entry.src);
+		let stamp = match SourceStamp::of(&src_path) {
+			Ok(stamp) => stamp,
+			Err(err) => return Err(io::Error::new(err.kind(), format!("{}: {}", entry.src.display(), err))),
+		};
+
+		// Find the file contents in the previous PAKS archive
+		let transform_names = transforms.names(&entry.dest, entry.content_type);
+		let cached = cache.get(&entry.dest)
+			.filter(|_| !entry.plaintext)
+			.filter(|cached| cached.src == entry.src && cached.content_type == entry.content_type && cached.key_id == entry.key_id)
+			.filter(|cached| cached.transforms == transform_names);
+		let prev_file = match (prev, cached) {
+			(Some(prev), Some(cached)) => prev.find_file(entry.dest.as_bytes())
+				.filter(|desc| cached.matches(desc) && !desc.is_plaintext() && !desc.is_inline() && !desc.is_bundled() && !desc.is_delta())
+				.map(|desc| (prev.blocks(), desc)),
+			_ => None,
+		};
+
+		let carry_over = |edit: &mut MemoryEditor| -> io::Result<bool> {
+			let (blocks, desc) = match prev_file {
+				Some(prev_file) => prev_file,
+				None => return Ok(false),
+			};
+			let mut edit_file = edit.edit_file(entry.dest.as_bytes())?;
+			edit_file.set_content(entry.content_type, desc.content_size);
+			Ok(edit_file.copy_encrypted(blocks, &desc.section, &file_key))
+		};
+
+		// The source file is unchanged, carry over without reading it
+		if let Some(cached) = cached {
+			if cached.stamp == stamp && carry_over(&mut edit)? {
+				new_cache.insert(entry.dest.clone(), cached.clone());
+				continue;
+			}
+		}
+
+		let data = match fs::read(&src_path) {
+			Ok(data) => data,
+			Err(err) => return Err(io::Error::new(err.kind(), format!("{}: {}", entry.src.display(), err))),
+		};
+		let digest = hash::sha256(&data);
+
+		// The source file was touched but its contents are unchanged
+		if let Some(cached) = cached {
+			if cached.digest == digest && carry_over(&mut edit)? {
+				new_cache.insert(entry.dest.clone(), CacheEntry { stamp, ..cached.clone() });
+				continue;
+			}
+		}
+
+		let data = transforms.apply(&entry.dest, entry.content_type, data)?;
+
+		let mut edit_file = edit.edit_file(entry.dest.as_bytes())?;
+		edit_file.set_content(entry.content_type, data.len() as u32).set_plaintext(entry.plaintext);
+		edit_file.allocate_data().write_data(&data, &file_key)?;
+
+		let section = edit_file.descriptor().section;
+		new_cache.insert(entry.dest.clone(), CacheEntry {
+			src: entry.src.clone(),
+			stamp,
+			digest,
+			transforms: transform_names,
+			content_type: entry.content_type,
+			key_id: entry.key_id.clone(),
+			nonce: section.nonce,
+			mac: section.mac,
+		});
+	}
+
+	edit.set_content_types(&manifest.content_types, key)?;
+
+	*cache = new_cache;
+	Ok(edit)
+}
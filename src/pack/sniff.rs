@@ -0,0 +1,140 @@
+use super::*;
+
+/// Pattern matched against the file contents.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SniffPattern {
+	/// The contents contain the bytes at the offset.
+	Magic {
+		offset: usize,
+		bytes: Vec<u8>,
+	},
+	/// The contents are UTF-8 text enclosed in `{}` or `[]`, ignoring surrounding whitespace.
+	///
+	/// The contents are not parsed, only their first and last characters are checked.
+	Json,
+}
+
+impl SniffPattern {
+	/// Creates a pattern matching the bytes at the offset.
+	pub fn magic(offset: usize, bytes: &[u8]) -> SniffPattern {
+		SniffPattern::Magic { offset, bytes: bytes.to_vec() }
+	}
+
+	/// Returns if the file contents match the pattern.
+	pub fn matches(&self, data: &[u8]) -> bool {
+		match self {
+			SniffPattern::Magic { offset, bytes } => data.get(*offset..).is_some_and(|data| data.starts_with(bytes)),
+			SniffPattern::Json => match str::from_utf8(data) {
+				Ok(text) => {
+					let text = text.trim();
+					text.starts_with('{') && text.ends_with('}') || text.starts_with('[') && text.ends_with(']')
+				},
+				Err(_) => false,
+			},
+		}
+	}
+}
+
+/// Assigns the content type to the files matching all its patterns.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SniffRule {
+	/// The content type of the matching files.
+	pub content_type: u32,
+	/// Name of the content type, eg. `image/png`, see [`ContentTypes`].
+	pub name: String,
+	/// Every pattern must match the file contents.
+	pub patterns: Vec<SniffPattern>,
+}
+
+impl SniffRule {
+	/// Creates a rule.
+	pub fn new(content_type: u32, name: &str, patterns: Vec<SniffPattern>) -> SniffRule {
+		SniffRule { content_type, name: name.to_string(), patterns }
+	}
+
+	/// Returns if the file contents match every pattern.
+	pub fn matches(&self, data: &[u8]) -> bool {
+		self.patterns.iter().all(|pattern| pattern.matches(data))
+	}
+}
+
+/// Ordered table of rules assigning content types by inspecting the file contents.
+///
+/// The first matching rule assigns the content type, see [`sniff`](Self::sniff).
+/// Start from the [builtin rules](Self::builtin) or an empty table and add custom rules for the formats of the project.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Sniffer {
+	rules: Vec<SniffRule>,
+}
+
+impl Sniffer {
+	/// Creates a new empty table.
+	#[inline]
+	pub fn new() -> Sniffer {
+		Sniffer { rules: Vec::new() }
+	}
+
+	/// Creates the table of the builtin rules for common file formats.
+	///
+	/// The content types are four characters in little endian, like [`Descriptor::CONTENT_TYPE_JSON`]:
+	///
+	/// | Content type | Name               | Detected by                     |
+	/// |--------------|--------------------|---------------------------------|
+	/// | `PNG `       | `image/png`        | The PNG signature               |
+	/// | `JPEG`       | `image/jpeg`       | The JPEG start of image marker  |
+	/// | `GIF `       | `image/gif`        | `GIF8`                          |
+	/// | `WEBP`       | `image/webp`       | `RIFF` with `WEBP` at offset 8  |
+	/// | `WAV `       | `audio/wav`        | `RIFF` with `WAVE` at offset 8  |
+	/// | `OGG `       | `audio/ogg`        | `OggS`                          |
+	/// | `WASM`       | `application/wasm` | `\0asm`                         |
+	/// | `ZIP `       | `application/zip`  | `PK\x03\x04`                    |
+	/// | `PDF `       | `application/pdf`  | `%PDF-`                         |
+	/// | `JSON`       | `application/json` | See [`SniffPattern::Json`]      |
+	pub fn builtin() -> Sniffer {
+		let mut sniffer = Sniffer::new();
+		sniffer
+			.add(SniffRule::new(fourcc(b"PNG "), "image/png", vec![SniffPattern::magic(0, b"\x89PNG\r\n\x1a\n")]))
+			.add(SniffRule::new(fourcc(b"JPEG"), "image/jpeg", vec![SniffPattern::magic(0, b"\xff\xd8\xff")]))
+			.add(SniffRule::new(fourcc(b"GIF "), "image/gif", vec![SniffPattern::magic(0, b"GIF8")]))
+			.add(SniffRule::new(fourcc(b"WEBP"), "image/webp", vec![SniffPattern::magic(0, b"RIFF"), SniffPattern::magic(8, b"WEBP")]))
+			.add(SniffRule::new(fourcc(b"WAV "), "audio/wav", vec![SniffPattern::magic(0, b"RIFF"), SniffPattern::magic(8, b"WAVE")]))
+			.add(SniffRule::new(fourcc(b"OGG "), "audio/ogg", vec![SniffPattern::magic(0, b"OggS")]))
+			.add(SniffRule::new(fourcc(b"WASM"), "application/wasm", vec![SniffPattern::magic(0, b"\0asm")]))
+			.add(SniffRule::new(fourcc(b"ZIP "), "application/zip", vec![SniffPattern::magic(0, b"PK\x03\x04")]))
+			.add(SniffRule::new(fourcc(b"PDF "), "application/pdf", vec![SniffPattern::magic(0, b"%PDF-")]))
+			.add(SniffRule::new(Descriptor::CONTENT_TYPE_JSON, "application/json", vec![SniffPattern::Json]));
+		sniffer
+	}
+
+	/// Adds the rule after the existing rules.
+	pub fn add(&mut self, rule: SniffRule) -> &mut Sniffer {
+		self.rules.push(rule);
+		self
+	}
+
+	/// Returns the rules in order.
+	#[inline]
+	pub fn rules(&self) -> &[SniffRule] {
+		&self.rules
+	}
+
+	/// Returns the first rule matching the file contents.
+	pub fn sniff(&self, data: &[u8]) -> Option<&SniffRule> {
+		self.rules.iter().find(|rule| rule.matches(data))
+	}
+
+	/// Names the content type after its rule unless it is already named, returns if the name was added.
+	pub fn name_content_type(&self, content_type: u32, content_types: &mut ContentTypes) -> bool {
+		if content_types.get(content_type).is_some() {
+			return false;
+		}
+		match self.rules.iter().find(|rule| rule.content_type == content_type) {
+			Some(rule) => content_types.insert(content_type, &rule.name).is_none(),
+			None => false,
+		}
+	}
+}
+
+const fn fourcc(chars: &[u8; 4]) -> u32 {
+	u32::from_le_bytes(*chars)
+}
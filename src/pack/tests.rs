@@ -0,0 +1,173 @@
+use super::*;
+
+#[test]
+fn test_prefix_mapper() {
+	let mut mapper = PrefixMapper::new();
+	mapper.add_rule("art=textures").unwrap();
+	mapper.add_rule("art/ui=ui/").unwrap();
+	mapper.add_rule("docs=").unwrap();
+
+	assert_eq!(mapper.map_path(b"art/a.png").as_deref(), Some(&b"textures/a.png"[..]));
+	assert_eq!(mapper.map_path(b"art/ui/button.png").as_deref(), Some(&b"ui/button.png"[..]));
+	assert_eq!(mapper.map_path(b"artwork/a.png").as_deref(), Some(&b"artwork/a.png"[..]));
+	assert_eq!(mapper.map_path(b"docs/readme.md").as_deref(), Some(&b"readme.md"[..]));
+	assert!(mapper.add_rule("invalid").is_none());
+}
+
+#[test]
+fn test_manifest_parse() {
+	let manifest = Manifest::parse(r#"
+# Example manifest
+[[file]]
+dest = "textures/wall.png" # trailing comment
+src = "art/wall #1.png"
+content_type = 0x10
+
+[[file]]
+dest = "config/game.json"
+src = "config/game.json"
+key = "config"
+
+[content_types]
+0x10 = "texture/png"
+2 = "config/json"
+"#).unwrap();
+
+	assert_eq!(manifest.entries.len(), 2);
+	assert_eq!(manifest.entries[0].dest, "textures/wall.png");
+	assert_eq!(manifest.entries[0].src, std::path::Path::new("art/wall #1.png"));
+	assert_eq!(manifest.entries[0].content_type, 0x10);
+	assert_eq!(manifest.entries[0].key_id, None);
+	assert_eq!(manifest.entries[1].content_type, 1);
+	assert_eq!(manifest.entries[1].key_id.as_deref(), Some("config"));
+	assert_eq!(manifest.content_types.get(0x10), Some("texture/png"));
+	assert_eq!(manifest.content_types.len(), 2);
+
+	assert!(Manifest::parse("dest = \"a\"").is_err());
+	assert!(Manifest::parse("[[file]]\nsrc = \"a\"").is_err());
+	let err = Manifest::parse("[[file]]\ndest = \"a\"\nsrc = \"a\"\ncompression = \"none\"").unwrap_err();
+	assert!(err.to_string().contains("compression is not supported"), "{}", err);
+	assert!(Manifest::parse("[content_types]\n0 = \"dir\"").is_err());
+	assert!(Manifest::parse("[content_types]\n2 = \"a\"\n2 = \"b\"").is_err());
+}
+
+#[test]
+fn test_incremental() {
+	if cfg!(miri) {
+		return;
+	}
+
+	let ref key = [1, 2];
+	let dir = std::env::temp_dir().join(format!("paks_incremental_{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let _cleanup = Cleanup(&dir);
+	std::fs::write(dir.join("a.txt"), b"hello").unwrap();
+	std::fs::write(dir.join("b.txt"), b"world").unwrap();
+
+	let manifest = Manifest::parse("[[file]]\ndest = \"a\"\nsrc = \"a.txt\"\n[[file]]\ndest = \"b\"\nsrc = \"b.txt\"\n[content_types]\n1 = \"text/plain\"").unwrap();
+	let mut cache = PackCache::new();
+	let edit = from_manifest_incremental(&manifest, &dir, key, &|_| None, &Transforms::new(), None, &mut cache).unwrap();
+	assert_eq!(edit.content_types(key).unwrap(), manifest.content_types);
+	let (blocks, directory) = edit.finish(key).unwrap();
+	assert_eq!(cache.len(), 2);
+
+	// The packed PAKS archive gets an archive id
+	let id = directory.archive_id().unwrap();
+	assert_eq!(directory.archive_generation(), Some(1));
+
+	// The cache survives a roundtrip through its text format
+	let cache = PackCache::parse(&cache.to_string());
+	assert_eq!(cache.len(), 2);
+
+	// Change only one of the files
+	std::fs::write(dir.join("b.txt"), b"changed").unwrap();
+	let prev = MemoryReader::from_blocks(blocks, key).unwrap();
+	let mut new_cache = cache.clone();
+	let edit = from_manifest_incremental(&manifest, &dir, key, &|_| None, &Transforms::new(), Some(&prev), &mut new_cache).unwrap();
+
+	let a = edit.find_file(b"a").unwrap();
+	assert_eq!(a.section.mac, prev.find_file(b"a").unwrap().section.mac);
+	assert_eq!(edit.read(b"a", key).unwrap(), b"hello");
+	assert_ne!(edit.find_file(b"b").unwrap().section.mac, prev.find_file(b"b").unwrap().section.mac);
+	assert_eq!(edit.read(b"b", key).unwrap(), b"changed");
+
+	// Rebuilding keeps the archive id, also without the cache
+	let (_, directory) = edit.finish(key).unwrap();
+	assert_eq!((directory.archive_id(), directory.archive_generation()), (Some(id), Some(2)));
+	let edit = from_manifest_incremental(&manifest, &dir, key, &|_| None, &Transforms::new(), Some(&prev), &mut PackCache::new()).unwrap();
+	assert_eq!(edit.archive_id(), Some(id));
+
+	// Carried over files are encrypted again with a different key
+	let ref other_key = [3, 4];
+	let edit = from_manifest_incremental(&manifest, &dir, other_key, &|_| None, &Transforms::new(), Some(&prev), &mut cache.clone()).unwrap();
+	assert_eq!(edit.read(b"a", other_key).unwrap(), b"hello");
+}
+
+struct Cleanup<'a>(&'a std::path::Path);
+impl Drop for Cleanup<'_> {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(self.0);
+	}
+}
+
+#[test]
+fn test_sniffer() {
+	let sniffer = Sniffer::builtin();
+	let sniff = |data: &[u8]| sniffer.sniff(data).map(|rule| &rule.name[..]);
+	assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("image/png"));
+	assert_eq!(sniff(b"RIFF\x24\0\0\0WAVEfmt "), Some("audio/wav"));
+	assert_eq!(sniff(b"RIFF\x24\0\0\0WEBPVP8 "), Some("image/webp"));
+	assert_eq!(sniff(b"OggS\0\x02"), Some("audio/ogg"));
+	assert_eq!(sniff(b"\0asm\x01\0\0\0"), Some("application/wasm"));
+	assert_eq!(sniff(b" \n{\"a\": [1, 2]}\n"), Some("application/json"));
+	assert_eq!(sniffer.sniff(b"[]").map(|rule| rule.content_type), Some(Descriptor::CONTENT_TYPE_JSON));
+	assert_eq!(sniff(b"{ unterminated"), None);
+	assert_eq!(sniff(b"RIFF"), None);
+	assert_eq!(sniff(b""), None);
+
+	// Custom rules are matched in order after the builtin rules
+	let mut sniffer = Sniffer::builtin();
+	sniffer.add(SniffRule::new(0x4b544c31, "texture/ktx", vec![SniffPattern::magic(1, b"KTX ")]));
+	assert_eq!(sniffer.sniff(b"\xabKTX 11").map(|rule| rule.content_type), Some(0x4b544c31));
+
+	// Names the sniffed content types without renaming
+	let mut content_types = ContentTypes::new();
+	content_types.insert(Descriptor::CONTENT_TYPE_JSON, "config");
+	assert!(sniffer.name_content_type(0x4b544c31, &mut content_types));
+	assert!(!sniffer.name_content_type(0x4b544c31, &mut content_types));
+	assert!(!sniffer.name_content_type(Descriptor::CONTENT_TYPE_JSON, &mut content_types));
+	assert!(!sniffer.name_content_type(7, &mut content_types));
+	assert_eq!(content_types.get(0x4b544c31), Some("texture/ktx"));
+	assert_eq!(content_types.get(Descriptor::CONTENT_TYPE_JSON), Some("config"));
+}
+
+#[test]
+fn test_transforms() {
+	let mut transforms = Transforms::new();
+	transforms.add_rule("json=minify-json").unwrap();
+	assert!(transforms.add_rule("json=unknown").is_none());
+
+	assert_eq!(transforms.names("config/game.JSON", 1), "minify-json");
+	assert_eq!(transforms.names("config/.json", 1), "");
+	assert_eq!(transforms.names("config/game.txt", 1), "");
+
+	let data = transforms.apply("game.json", 1, b"{ \"a b\": [1, 2],\n \"c\": \"\\\" \" }".to_vec()).unwrap();
+	assert_eq!(data, b"{\"a b\":[1,2],\"c\":\"\\\" \"}");
+	let data = transforms.apply("game.txt", 1, b"{ }".to_vec()).unwrap();
+	assert_eq!(data, b"{ }");
+	assert!(transforms.apply("game.json", 1, b"\"abc".to_vec()).is_err());
+}
+
+#[test]
+fn test_encrypted_data() {
+	let ref key = [5, 6];
+	let data = EncryptedData::encrypt(b"Hello, world! Encrypted ahead of time.", key).unwrap();
+	assert_eq!(data.content_size, 38);
+	assert_eq!(data.blocks.len(), 3);
+
+	let mut edit = MemoryEditor::new();
+	let mut edit_file = edit.edit_file(b"hello").unwrap();
+	edit_file.set_content(1, data.content_size);
+	assert!(edit_file.copy_encrypted(&data.blocks, &data.section, key));
+	assert_eq!(edit.read(b"hello", key).unwrap(), b"Hello, world! Encrypted ahead of time.");
+}
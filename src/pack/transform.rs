@@ -0,0 +1,170 @@
+use std::io;
+use super::*;
+
+/// Transforms file contents while packing.
+///
+/// Transforms turn the packer into a lightweight asset pipeline stage, eg. minifying JSON or recompressing textures.
+/// Files may be transformed in parallel on multiple threads.
+pub trait FileTransform: Send + Sync {
+	/// Name of the transform.
+	///
+	/// The name is recorded in the [`PackCache`], changing the transforms applied to a file invalidates its cache entry.
+	fn name(&self) -> &str;
+
+	/// Transforms the contents of the file packed to the destination path.
+	fn transform(&self, dest: &str, data: Vec<u8>) -> io::Result<Vec<u8>>;
+}
+
+/// Selects the files a transform is applied to.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum TransformFilter {
+	/// Files whose destination path has the extension, compared case-insensitively.
+	Extension(String),
+	/// Files with the content type.
+	ContentType(u32),
+	/// All files.
+	All,
+}
+
+impl TransformFilter {
+	/// Returns if the filter selects the file.
+	pub fn matches(&self, dest: &str, content_type: u32) -> bool {
+		match self {
+			TransformFilter::Extension(ext) => {
+				let file_name = dest.rsplit('/').next().unwrap_or(dest);
+				match file_name.rsplit_once('.') {
+					Some((stem, file_ext)) => !stem.is_empty() && file_ext.eq_ignore_ascii_case(ext),
+					None => false,
+				}
+			},
+			TransformFilter::ContentType(ct) => *ct == content_type,
+			TransformFilter::All => true,
+		}
+	}
+}
+
+/// Ordered list of file transforms.
+///
+/// Every matching transform is applied to the file in the order they were added.
+#[derive(Default)]
+pub struct Transforms {
+	rules: Vec<(TransformFilter, Box<dyn FileTransform>)>,
+}
+
+impl Transforms {
+	/// Creates a new empty list of transforms.
+	#[inline]
+	pub const fn new() -> Transforms {
+		Transforms { rules: Vec::new() }
+	}
+
+	/// Adds a transform applied to the files selected by the filter.
+	pub fn add(&mut self, filter: TransformFilter, transform: Box<dyn FileTransform>) -> &mut Transforms {
+		self.rules.push((filter, transform));
+		self
+	}
+
+	/// Parses and adds a rule formatted as `EXT=TRANSFORM` using a [builtin transform](builtin_transform).
+	///
+	/// Returns `None` if the rule does not contain a `=` or the transform is unknown.
+	pub fn add_rule(&mut self, rule: &str) -> Option<&mut Transforms> {
+		let (ext, name) = rule.split_once('=')?;
+		let transform = builtin_transform(name)?;
+		let filter = match ext.trim_start_matches('.') {
+			"*" => TransformFilter::All,
+			ext => TransformFilter::Extension(ext.to_string()),
+		};
+		Some(self.add(filter, transform))
+	}
+
+	/// Returns if there are no transforms.
+	#[inline]
+	pub fn is_empty(&self) -> bool {
+		self.rules.is_empty()
+	}
+
+	/// Returns the names of the transforms applied to the file separated by `,`.
+	pub fn names(&self, dest: &str, content_type: u32) -> String {
+		let mut names = String::new();
+		for (filter, transform) in &self.rules {
+			if filter.matches(dest, content_type) {
+				if !names.is_empty() {
+					names.push(',');
+				}
+				names.push_str(transform.name());
+			}
+		}
+		names
+	}
+
+	/// Applies the matching transforms to the file contents.
+	///
+	/// # Errors
+	///
+	/// Errors returned by a transform are annotated with its name and the destination path.
+	pub fn apply(&self, dest: &str, content_type: u32, mut data: Vec<u8>) -> io::Result<Vec<u8>> {
+		for (filter, transform) in &self.rules {
+			if filter.matches(dest, content_type) {
+				data = match transform.transform(dest, data) {
+					Ok(data) => data,
+					Err(err) => return Err(io::Error::new(err.kind(), format!("{}: {}: {}", transform.name(), dest, err))),
+				};
+			}
+		}
+		Ok(data)
+	}
+}
+
+/// Returns the builtin transform with the given name.
+///
+/// Builtin transforms:
+///
+/// * `minify-json`: Removes insignificant whitespace from JSON, see [`MinifyJson`].
+pub fn builtin_transform(name: &str) -> Option<Box<dyn FileTransform>> {
+	match name {
+		"minify-json" => Some(Box::new(MinifyJson)),
+		_ => None,
+	}
+}
+
+/// Removes insignificant whitespace from JSON files.
+///
+/// The JSON is not otherwise validated, unterminated strings are reported as [`io::ErrorKind::InvalidData`].
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MinifyJson;
+
+impl FileTransform for MinifyJson {
+	fn name(&self) -> &str {
+		"minify-json"
+	}
+
+	fn transform(&self, _dest: &str, data: Vec<u8>) -> io::Result<Vec<u8>> {
+		let mut result = Vec::with_capacity(data.len());
+		let mut in_string = false;
+		let mut escaped = false;
+		for &byte in &data {
+			if in_string {
+				if escaped {
+					escaped = false;
+				}
+				else if byte == b'\\' {
+					escaped = true;
+				}
+				else if byte == b'"' {
+					in_string = false;
+				}
+			}
+			else if byte == b'"' {
+				in_string = true;
+			}
+			else if matches!(byte, b' ' | b'\t' | b'\n' | b'\r') {
+				continue;
+			}
+			result.push(byte);
+		}
+		if in_string {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "unterminated string"));
+		}
+		Ok(result)
+	}
+}
@@ -0,0 +1,508 @@
+/*!
+Patch files updating installed PAKS archives.
+
+A patch file describes the changes between two versions of a PAKS archive, see [`Patch::diff`].
+Shipping a patch file instead of the new PAKS archive only ships the changed files, changed files are stored as a [`delta`] against their previous contents where that is smaller.
+
+The `.pakspatch` file starts with a plaintext header of 4 blocks:
+
+* The magic bytes `PAKSPTCH` and the format version as `u32` little endian.
+* The byte length of the body as `u32` little endian.
+* The section of the body, see [`Section`].
+
+The body is encrypted and authenticated with the key of the PAKS archive, all its integers are `u32` little endian:
+
+* The MAC of the directory of the PAKS archive the patch applies to.
+* The archive id of the PAKS archive the patch applies to, zero if it has none, followed by its generation.
+* The number of directory operations followed by the directory operations, each is the operation byte and the length and bytes of its path:
+  * Remove: the byte `1`.
+  * Create directory: the byte `2`.
+  * Write: the byte `3` followed by the content type, the flags, the SHA-256 digest of the contents, the byte length of the contents and the byte length of its data.
+* The data of the write operations one after another, this is either the contents or a delta against the previous contents of the file.
+
+Patches are applied in place with [`apply_file`].
+Patches of PAKS archives with an [`ArchiveId`] refuse to apply to a different PAKS archive or a different generation of it, see [`Patch::check_base`].
+*/
+
+use std::{fs, io, io::prelude::*, path::Path};
+use std::collections::HashMap;
+use super::*;
+
+const MAGIC: [u8; 8] = *b"PAKSPTCH";
+const VERSION: u32 = 1;
+
+const OP_REMOVE: u8 = 1;
+const OP_CREATE_DIR: u8 = 2;
+const OP_WRITE: u8 = 3;
+
+const WRITE_PLAINTEXT: u32 = 1;
+const WRITE_DELTA: u32 = 2;
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+struct PatchHeader {
+	magic: [u8; 8],
+	version: u32,
+	body_len: u32,
+	body: Section,
+	reserved: [u32; 2],
+}
+
+unsafe impl Pod for PatchHeader {}
+
+const HEADER_LEN: usize = mem::size_of::<PatchHeader>();
+
+/// Directory operation of a patch.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PatchOp {
+	/// Removes the file or directory at the path.
+	Remove(Vec<u8>),
+	/// Creates the directory at the path.
+	CreateDir(Vec<u8>),
+	/// Writes the file at the path.
+	Write(PatchWrite),
+}
+
+/// Write operation of a patch.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PatchWrite {
+	/// Path of the file.
+	pub path: Vec<u8>,
+	/// Content type of the file.
+	pub content_type: u32,
+	/// Store the file contents as plaintext.
+	pub plaintext: bool,
+	/// The data is a delta against the previous contents of the file.
+	pub delta: bool,
+	/// SHA-256 digest of the file contents.
+	pub digest: hash::Digest,
+	/// Size in bytes of the file contents.
+	///
+	/// Deltas are never reconstructed past this size.
+	pub content_size: u32,
+	/// The file contents or the delta.
+	pub data: Vec<u8>,
+}
+
+/// Patch updating a PAKS archive.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Patch {
+	/// MAC of the directory of the PAKS archive the patch applies to.
+	///
+	/// Every finished edit encrypts the directory with a new nonce, the patch applies only to the exact PAKS archive it was created from.
+	pub base: Block,
+	/// Archive id of the PAKS archive the patch applies to, if it has one.
+	pub archive_id: Option<ArchiveId>,
+	/// Generation of the PAKS archive the patch applies to, see [`Directory::archive_generation`].
+	pub generation: u32,
+	/// Directory operations in the order they are applied.
+	pub ops: Vec<PatchOp>,
+}
+
+impl Patch {
+	/// Creates the patch updating the old PAKS archive to the new PAKS archive.
+	///
+	/// Both PAKS archives are decrypted with the same key.
+	/// Removed paths and paths changing between file and directory are removed, new directories are created.
+	/// New and changed files are written, using a delta against the old contents where that is smaller.
+	/// Linked files are written once for every path.
+	/// The archive id is never patched, the patched PAKS archive keeps its own archive id.
+	///
+	/// Only the contents, the content type and the [plaintext](Descriptor::FLAG_PLAINTEXT) flag of the files are patched.
+	/// The other descriptor flags are not carried: written files are stored in a section of their own, even if they are inline, bundled or delta files in the new PAKS archive.
+	/// Extension records, eg. the [`Mtime`], are not patched, files which differ only in their extension records are left unchanged and written files keep the records they had.
+	pub fn diff(old: &FileReader, new: &FileReader, key: &Key) -> io::Result<Patch> {
+		let mut old_entries = dir::entries_with_metadata(old.as_ref());
+		let mut new_entries = dir::entries_with_metadata(new.as_ref());
+		old_entries.retain(|(path, _)| path != ARCHIVE_ID_PATH);
+		new_entries.retain(|(path, _)| path != ARCHIVE_ID_PATH);
+		let old_map: HashMap<&[u8], &Descriptor> = old_entries.iter().map(|(path, desc)| (&path[..], *desc)).collect();
+		let new_map: HashMap<&[u8], &Descriptor> = new_entries.iter().map(|(path, desc)| (&path[..], *desc)).collect();
+
+		let mut ops = Vec::new();
+
+		// Removing a directory removes its children, they are not removed separately
+		let mut removed: Vec<&[u8]> = Vec::new();
+		for (path, desc) in &old_entries {
+			let kept = matches!(new_map.get(&path[..]), Some(new_desc) if new_desc.is_dir() == desc.is_dir());
+			if !kept && !removed.iter().any(|dir| is_within(path, dir)) {
+				ops.push(PatchOp::Remove(path.clone()));
+				removed.push(path);
+			}
+		}
+
+		for (path, desc) in &new_entries {
+			let old_desc = old_map.get(&path[..]).filter(|old_desc| old_desc.is_dir() == desc.is_dir());
+			if desc.is_dir() {
+				if old_desc.is_none() {
+					ops.push(PatchOp::CreateDir(path.clone()));
+				}
+				continue;
+			}
+
+			let data = new.read_data(desc, key).map_err(|err| at_path(err, path))?;
+			let mut write = PatchWrite {
+				path: path.clone(),
+				content_type: desc.content_type,
+				plaintext: desc.is_plaintext(),
+				delta: false,
+				digest: hash::sha256(&data),
+				content_size: data.len() as u32,
+				data,
+			};
+			if let Some(old_desc) = old_desc {
+				let old_data = old.read_data(old_desc, key).map_err(|err| at_path(err, path))?;
+				if old_data == write.data && old_desc.content_type == write.content_type && old_desc.is_plaintext() == write.plaintext {
+					continue;
+				}
+				let delta = delta::diff(&old_data, &write.data);
+				if delta.len() < write.data.len() {
+					write.delta = true;
+					write.data = delta;
+				}
+			}
+			ops.push(PatchOp::Write(write));
+		}
+
+		let archive_id = old.archive_id();
+		let generation = old.archive_generation().unwrap_or(0);
+		Ok(Patch { base: old.info().directory.mac, archive_id, generation, ops })
+	}
+
+	/// Encodes and encrypts the patch file.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The encoded patch is larger than 4 GiB, its length is stored in a `u32`.
+	/// * Fails with the error of the random number generator of the operating system.
+	pub fn to_bytes(&self, key: &Key) -> io::Result<Vec<u8>> {
+		let mut body = Vec::new();
+		body.extend_from_slice(dataview::bytes(&self.base));
+		body.extend_from_slice(dataview::bytes(&self.archive_id.unwrap_or_default().0));
+		put_u32(&mut body, self.generation);
+		put_u32(&mut body, self.ops.len() as u32);
+		for op in &self.ops {
+			let (op_byte, path) = match op {
+				PatchOp::Remove(path) => (OP_REMOVE, path),
+				PatchOp::CreateDir(path) => (OP_CREATE_DIR, path),
+				PatchOp::Write(write) => (OP_WRITE, &write.path),
+			};
+			body.push(op_byte);
+			put_u32(&mut body, path.len() as u32);
+			body.extend_from_slice(path);
+			if let PatchOp::Write(write) = op {
+				let flags = if write.plaintext { WRITE_PLAINTEXT } else { 0 } | if write.delta { WRITE_DELTA } else { 0 };
+				put_u32(&mut body, write.content_type);
+				put_u32(&mut body, flags);
+				body.extend_from_slice(&write.digest.0);
+				put_u32(&mut body, write.content_size);
+				put_u32(&mut body, write.data.len() as u32);
+			}
+		}
+		for op in &self.ops {
+			if let PatchOp::Write(write) = op {
+				body.extend_from_slice(&write.data);
+			}
+		}
+
+		let body_len = u32::try_from(body.len()).map_err(|_| invalid_input("patch too large"))?;
+		let size = bytes2blocks(body_len);
+		let mut blocks = vec![Block::default(); size as usize];
+		dataview::bytes_mut(blocks.as_mut_slice())[..body.len()].copy_from_slice(&body);
+
+		let mut header = PatchHeader {
+			magic: MAGIC,
+			version: VERSION,
+			body_len,
+			body: Section { offset: (HEADER_LEN / BLOCK_SIZE) as u32, size, ..Section::default() },
+			reserved: [0; 2],
+		};
+		crypt::encrypt_section_from(None, &mut blocks, &mut header.body, key)?;
+
+		let mut bytes = dataview::bytes(&header).to_vec();
+		bytes.extend_from_slice(dataview::bytes(blocks.as_slice()));
+		Ok(bytes)
+	}
+
+	/// Decrypts and decodes the patch file.
+	///
+	/// If the patch file is malformed or its MAC check fails, [`io::ErrorKind::InvalidData`] is returned.
+	pub fn from_bytes(bytes: &[u8], key: &Key) -> io::Result<Patch> {
+		let header = match dataview::DataView::from(bytes).try_read::<PatchHeader>(0) {
+			Some(header) if header.magic == MAGIC => header,
+			_ => return Err(invalid_data("not a patch file")),
+		};
+		if header.version != VERSION {
+			return Err(invalid_data("unsupported patch version"));
+		}
+		if header.body.offset as usize != HEADER_LEN / BLOCK_SIZE
+			|| bytes.len() != HEADER_LEN + header.body.size as usize * BLOCK_SIZE
+			|| header.body_len as usize > header.body.size as usize * BLOCK_SIZE
+		{
+			return Err(invalid_data("malformed patch file"));
+		}
+
+		let mut blocks = vec![Block::default(); header.body.size as usize];
+		dataview::bytes_mut(blocks.as_mut_slice()).copy_from_slice(&bytes[HEADER_LEN..]);
+		if !crypt::decrypt_section(&mut blocks, &header.body, key) {
+			return Err(invalid_data("patch authentication failed"));
+		}
+
+		let mut body = Parser(&dataview::bytes(blocks.as_slice())[..header.body_len as usize]);
+		let base = body.block()?;
+		let archive_id = Some(ArchiveId(body.block()?)).filter(|id| id.0 != Block::default());
+		let generation = body.u32()?;
+		let count = body.u32()?;
+		let mut ops = Vec::new();
+		let mut data_lens = Vec::new();
+		for _ in 0..count {
+			let op_byte = body.take(1)?[0];
+			let len = body.u32()? as usize;
+			let path = body.take(len)?.to_vec();
+			let op = match op_byte {
+				OP_REMOVE => PatchOp::Remove(path),
+				OP_CREATE_DIR => PatchOp::CreateDir(path),
+				OP_WRITE => {
+					let content_type = body.u32()?;
+					let flags = body.u32()?;
+					let mut digest = hash::Digest::default();
+					digest.0.copy_from_slice(body.take(32)?);
+					let content_size = body.u32()?;
+					data_lens.push(body.u32()? as usize);
+					let plaintext = flags & WRITE_PLAINTEXT != 0;
+					let delta = flags & WRITE_DELTA != 0;
+					PatchOp::Write(PatchWrite { path, content_type, plaintext, delta, digest, content_size, data: Vec::new() })
+				},
+				_ => return Err(invalid_data("malformed patch file")),
+			};
+			ops.push(op);
+		}
+		let writes = ops.iter_mut().filter_map(|op| match op { PatchOp::Write(write) => Some(write), _ => None });
+		for (write, data_len) in writes.zip(data_lens) {
+			write.data = body.take(data_len)?.to_vec();
+		}
+		if !body.0.is_empty() {
+			return Err(invalid_data("malformed patch file"));
+		}
+
+		Ok(Patch { base, archive_id, generation, ops })
+	}
+
+	/// Checks that the patch applies to the PAKS archive.
+	///
+	/// Patches of PAKS archives with an archive id report a different archive id or a different generation of the PAKS archive.
+	/// The directory must be the exact directory the patch was created from.
+	///
+	/// # Errors
+	///
+	/// Returns [`io::ErrorKind::InvalidInput`] with a message describing the mismatch.
+	pub fn check_base(&self, reader: &FileReader) -> io::Result<()> {
+		if let Some(id) = self.archive_id {
+			if reader.archive_id() != Some(id) {
+				return Err(invalid_input(format!("patch is for the PAKS archive {}", id)));
+			}
+			let generation = reader.archive_generation().unwrap_or(0);
+			if generation != self.generation {
+				return Err(invalid_input(format!("patch is for generation {} of the PAKS archive, found generation {}", self.generation, generation)));
+			}
+		}
+		if reader.info().directory.mac != self.base {
+			return Err(invalid_input("patch does not apply to this PAKS archive"));
+		}
+		Ok(())
+	}
+
+	/// Applies the directory operations to the PAKS archive without finishing the editor.
+	///
+	/// The contents of every written file are checked against their digest before they are written.
+	/// Deltas are applied against the current contents of the file in the editor.
+	pub fn apply(&self, edit: &mut FileEditor, key: &Key) -> io::Result<()> {
+		for op in &self.ops {
+			match op {
+				PatchOp::Remove(path) => {
+					let removed = match dir::is_metadata_path(path) {
+						true => edit.remove_metadata(path),
+						false => edit.remove(path),
+					};
+					if removed.is_none() {
+						return Err(at_path(io::ErrorKind::NotFound.into(), path));
+					}
+				},
+				PatchOp::CreateDir(path) => edit.create_dir(path)?,
+				PatchOp::Write(write) => {
+					let delta_data;
+					let data = if write.delta {
+						let base = edit.read(&write.path, key).map_err(|err| at_path(err, &write.path))?;
+						delta_data = delta::apply(&base, &write.data, write.content_size).ok_or_else(|| at_path(invalid_data("malformed delta"), &write.path))?;
+						&delta_data
+					}
+					else {
+						&write.data
+					};
+					if hash::sha256(data) != write.digest {
+						return Err(at_path(invalid_data("digest mismatch"), &write.path));
+					}
+					let mut edit_file = match dir::is_metadata_path(&write.path) {
+						true => edit.edit_metadata(&write.path),
+						false => edit.edit_file(&write.path)?,
+					};
+					edit_file.set_content(write.content_type, data.len() as u32).set_plaintext(write.plaintext);
+					edit_file.allocate_data().write_data(data, key)?;
+				},
+			}
+		}
+		Ok(())
+	}
+
+	/// Verifies the patched PAKS archive.
+	///
+	/// Checks that removed paths are gone, unless recreated by a later operation, directories exist and files match their digest.
+	pub fn verify(&self, reader: &FileReader, key: &Key) -> io::Result<()> {
+		for (i, op) in self.ops.iter().enumerate() {
+			match op {
+				PatchOp::Remove(path) => {
+					let recreated = self.ops[i + 1..].iter().any(|op| match op {
+						PatchOp::Remove(_) => false,
+						PatchOp::CreateDir(other) => is_within(other, path),
+						PatchOp::Write(write) => is_within(&write.path, path),
+					});
+					if !recreated && reader.find_desc(path).is_some() {
+						return Err(at_path(invalid_data("not removed"), path));
+					}
+				},
+				PatchOp::CreateDir(path) => {
+					if !reader.find_desc(path).is_some_and(Descriptor::is_dir) {
+						return Err(at_path(io::ErrorKind::NotFound.into(), path));
+					}
+				},
+				PatchOp::Write(write) => {
+					let desc = match reader.find_file(&write.path) {
+						Some(desc) => desc,
+						None => return Err(at_path(io::ErrorKind::NotFound.into(), &write.path)),
+					};
+					let data = reader.read_data(desc, key).map_err(|err| at_path(err, &write.path))?;
+					if hash::sha256(&data) != write.digest || desc.content_type != write.content_type || desc.is_plaintext() != write.plaintext {
+						return Err(at_path(invalid_data("digest mismatch"), &write.path));
+					}
+				},
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Creates the patch file updating the old PAKS archive to the new PAKS archive.
+///
+/// See [`Patch::diff`] for more information.
+pub fn create_file<P: ?Sized + AsRef<Path>, Q: ?Sized + AsRef<Path>, R: ?Sized + AsRef<Path>>(old_path: &P, new_path: &Q, patch_path: &R, key: &Key) -> io::Result<Patch> {
+	let old = FileReader::open(old_path, key)?;
+	let new = FileReader::open(new_path, key)?;
+	let patch = Patch::diff(&old, &new, key)?;
+	fs::write(patch_path, patch.to_bytes(key)?)?;
+	Ok(patch)
+}
+
+/// Applies the patch file to the PAKS archive in place.
+///
+/// If the patch was not created from this PAKS archive, [`io::ErrorKind::InvalidInput`] is returned, see [`Patch::check_base`].
+///
+/// The patch is applied with a [`FileEditor`], holding its lock while patching, verifying and rolling back.
+/// After finishing the editor the PAKS archive is reopened, checked with [`fsck`](Directory::fsck) and verified with [`Patch::verify`].
+/// If anything fails the header and the length of the PAKS archive are restored, rolling back to the directory before the patch.
+pub fn apply_file<P: ?Sized + AsRef<Path>, Q: ?Sized + AsRef<Path>>(archive_path: &P, patch_path: &Q, key: &Key) -> io::Result<()> {
+	let patch = Patch::from_bytes(&fs::read(patch_path)?, key)?;
+	apply_patch(archive_path.as_ref(), &patch, key)
+}
+
+#[inline(never)]
+fn apply_patch(path: &Path, patch: &Patch, key: &Key) -> io::Result<()> {
+	let mut edit = FileEditor::open(path, key)?;
+	patch.check_base(&FileReader::open(path, key)?)?;
+
+	// The editor only appends after its high mark until it overwrites the header when finished
+	// Remember the header and everything after the high mark to roll back
+	let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+	let len = file.metadata()?.len();
+	let mut header = [0u8; mem::size_of::<Header>()];
+	file.read_exact(&mut header)?;
+	let tail_offset = u64::min(edit.high_mark() as u64 * BLOCK_SIZE as u64, len);
+	let mut tail = Vec::new();
+	file.seek(io::SeekFrom::Start(tail_offset))?;
+	file.read_to_end(&mut tail)?;
+
+	// Hold the lock until the patched PAKS archive is verified or rolled back
+	let (_locked, result) = match patch.apply(&mut edit, key) {
+		Ok(()) => {
+			let (locked, result) = edit.finish_locked(key);
+			(locked, result.and_then(|()| verify_file(path, patch, key)))
+		},
+		Err(err) => (edit.into_locked(), Err(err)),
+	};
+
+	if let Err(err) = result {
+		file.seek(io::SeekFrom::Start(tail_offset))?;
+		file.write_all(&tail)?;
+		file.set_len(len)?;
+		file.sync_data()?;
+		file.seek(io::SeekFrom::Start(0))?;
+		file.write_all(&header)?;
+		file.sync_data()?;
+		return Err(err);
+	}
+	Ok(())
+}
+
+fn verify_file(path: &Path, patch: &Patch, key: &Key) -> io::Result<()> {
+	let reader = FileReader::open(path, key)?;
+	let mut log = String::new();
+	if !reader.fsck(reader.high_mark(), &mut log) {
+		return Err(invalid_data(log));
+	}
+	patch.verify(&reader, key)
+}
+
+// Is the path the directory itself or inside it?
+fn is_within(path: &[u8], dir: &[u8]) -> bool {
+	match path.strip_prefix(dir) {
+		Some(rest) => rest.is_empty() || rest[0] == b'/',
+		None => false,
+	}
+}
+
+fn put_u32(bytes: &mut Vec<u8>, value: u32) {
+	bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn invalid_input<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidInput, error)
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn at_path(err: io::Error, path: &[u8]) -> io::Error {
+	io::Error::new(err.kind(), format!("{}: {}", err, String::from_utf8_lossy(path)))
+}
+
+// Reads the body of the patch file front to back
+struct Parser<'a>(&'a [u8]);
+
+impl<'a> Parser<'a> {
+	fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+		if len > self.0.len() {
+			return Err(invalid_data("malformed patch file"));
+		}
+		let (bytes, rest) = self.0.split_at(len);
+		self.0 = rest;
+		Ok(bytes)
+	}
+	fn u32(&mut self) -> io::Result<u32> {
+		let bytes = self.take(4)?;
+		Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+	}
+	fn block(&mut self) -> io::Result<Block> {
+		let bytes = self.take(BLOCK_SIZE)?;
+		Ok(dataview::DataView::from(bytes).read(0))
+	}
+}
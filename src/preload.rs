@@ -0,0 +1,30 @@
+use super::*;
+
+/// Path of the preload list in the PAKS file.
+///
+/// The preload list is the ordered list of paths an engine loads up front, eg. the load order of a level.
+/// It is stored as a regular file with one path per line, see [`MemoryReader::preload_list`] and [`MemoryEditor::set_preload_list`].
+/// The preload list keeps its name when the names are stripped, find the listed files with [`Directory::find_by_hash`] afterwards.
+pub const PRELOAD_PATH: &[u8] = b".preload";
+
+// Encodes the paths one per line
+// Every path must refer to a file in the directory
+pub(crate) fn encode(directory: &Directory, paths: &[&[u8]]) -> Result<Vec<u8>, Error> {
+	let mut data = Vec::new();
+	for &path in paths {
+		if path.contains(&b'\n') {
+			return Err(Error::with_path(ErrorKind::InvalidInput, path));
+		}
+		if directory.find_file(path).is_none() {
+			return Err(Error::with_path(ErrorKind::NotFound, path));
+		}
+		data.extend_from_slice(path);
+		data.push(b'\n');
+	}
+	Ok(data)
+}
+
+// Decodes the paths, empty lines are ignored
+pub(crate) fn decode(data: &[u8]) -> Vec<Vec<u8>> {
+	data.split(|&byte| byte == b'\n').filter(|path| !path.is_empty()).map(|path| path.to_vec()).collect()
+}
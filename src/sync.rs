@@ -0,0 +1,356 @@
+/*!
+Incremental updates of PAKS archives from a remote PAKS archive.
+
+The publisher creates a [`Manifest`] of the remote PAKS archive: its directory and the SHA-256 digest of every section.
+The client downloads the manifest, computes which sections it already has with [`plan`] and downloads only the missing sections with [`sync_file`].
+
+A remote section is reused from the local PAKS archive if:
+
+* A local section has the same nonce and MAC, eg. the file was not changed since the local PAKS archive was downloaded.
+* A local section has the same decrypted contents, eg. the remote PAKS archive was rebuilt from scratch.
+  The sections referenced by delta files are only reused with the same nonce and MAC, their nonce and MAC is stored in the delta file.
+
+Neighbouring sections are downloaded with a single read, see [`Remote`].
+
+The manifest file starts with a plaintext header of 4 blocks, the magic bytes `PAKSSYNC` and the format version followed by the byte length and the section of the body.
+The body is encrypted and authenticated with the key of the PAKS archive, all its integers are `u32` little endian:
+
+* The number of descriptors followed by the descriptors of the directory.
+* The number of sections followed by the sections, each is the [`Section`], the flags and the SHA-256 digest.
+*/
+
+use std::{io, path::Path};
+use std::collections::HashMap;
+use super::*;
+
+const MAGIC: [u8; 8] = *b"PAKSSYNC";
+const VERSION: u32 = 1;
+
+const SECTION_PLAINTEXT: u32 = 1;
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+struct ManifestHeader {
+	magic: [u8; 8],
+	version: u32,
+	body_len: u32,
+	body: Section,
+	reserved: [u32; 2],
+}
+
+unsafe impl Pod for ManifestHeader {}
+
+const HEADER_LEN: usize = mem::size_of::<ManifestHeader>();
+
+/// Source of the remote PAKS archive.
+///
+/// Implemented for closures, eg. making HTTP range requests.
+pub trait Remote {
+	/// Reads the bytes at the byte offset in the remote PAKS archive.
+	fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+}
+
+impl<F: FnMut(u64, &mut [u8]) -> io::Result<()>> Remote for F {
+	#[inline]
+	fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+		self(offset, buf)
+	}
+}
+
+/// Digest of a section.
+#[derive(Copy, Clone, Default, Eq, PartialEq)]
+pub struct SectionDigest {
+	/// The section in the remote PAKS archive.
+	pub section: Section,
+	/// The section is stored unencrypted, see [`Descriptor::FLAG_PLAINTEXT`].
+	pub plaintext: bool,
+	/// SHA-256 digest of the decrypted blocks of the section.
+	pub digest: hash::Digest,
+}
+
+/// Manifest of the remote PAKS archive.
+#[derive(Clone, Default)]
+pub struct Manifest {
+	/// The directory of the remote PAKS archive.
+	pub directory: Directory,
+	/// Digests of the sections of the remote PAKS archive, ordered by offset.
+	pub sections: Vec<SectionDigest>,
+}
+
+impl Manifest {
+	/// Creates the manifest of the PAKS archive.
+	///
+	/// Decrypts every section of the PAKS archive to compute its digest.
+	pub fn create(reader: &FileReader, key: &Key) -> io::Result<Manifest> {
+		let sections = sections(reader.as_ref(), |section| reader.read_section(section, key))?;
+		let sections = sections.into_iter()
+			.map(|(section, plaintext)| {
+				let digest = section_digest(&section, plaintext, |desc| reader.read_data(desc, key))?;
+				Ok(SectionDigest { section, plaintext, digest })
+			})
+			.collect::<io::Result<_>>()?;
+		Ok(Manifest { directory: Directory::from(reader.as_ref().to_vec()), sections })
+	}
+
+	/// Encodes and encrypts the manifest file.
+	///
+	/// # Errors
+	///
+	/// * [`io::ErrorKind::InvalidInput`]: The encoded manifest is larger than 4 GiB, its length is stored in a `u32`.
+	/// * Fails with the error of the random number generator of the operating system.
+	pub fn to_bytes(&self, key: &Key) -> io::Result<Vec<u8>> {
+		let mut body = Vec::new();
+		body.extend_from_slice(&(self.directory.len() as u32).to_le_bytes());
+		body.extend_from_slice(dataview::bytes(self.directory.as_ref()));
+		body.extend_from_slice(&(self.sections.len() as u32).to_le_bytes());
+		for entry in &self.sections {
+			let flags = if entry.plaintext { SECTION_PLAINTEXT } else { 0 };
+			body.extend_from_slice(dataview::bytes(&entry.section));
+			body.extend_from_slice(&flags.to_le_bytes());
+			body.extend_from_slice(&entry.digest.0);
+		}
+
+		let body_len = u32::try_from(body.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "manifest too large"))?;
+		let size = bytes2blocks(body_len);
+		let mut blocks = vec![Block::default(); size as usize];
+		dataview::bytes_mut(blocks.as_mut_slice())[..body.len()].copy_from_slice(&body);
+
+		let mut header = ManifestHeader {
+			magic: MAGIC,
+			version: VERSION,
+			body_len,
+			body: Section { offset: (HEADER_LEN / BLOCK_SIZE) as u32, size, ..Section::default() },
+			reserved: [0; 2],
+		};
+		crypt::encrypt_section_from(None, &mut blocks, &mut header.body, key)?;
+
+		let mut bytes = dataview::bytes(&header).to_vec();
+		bytes.extend_from_slice(dataview::bytes(blocks.as_slice()));
+		Ok(bytes)
+	}
+
+	/// Decrypts and decodes the manifest file.
+	///
+	/// If the manifest file is malformed or its MAC check fails, [`io::ErrorKind::InvalidData`] is returned.
+	pub fn from_bytes(bytes: &[u8], key: &Key) -> io::Result<Manifest> {
+		let header = match dataview::DataView::from(bytes).try_read::<ManifestHeader>(0) {
+			Some(header) if header.magic == MAGIC && header.version == VERSION => header,
+			_ => return Err(invalid_data("not a manifest file")),
+		};
+		if header.body.offset as usize != HEADER_LEN / BLOCK_SIZE
+			|| bytes.len() != HEADER_LEN + header.body.size as usize * BLOCK_SIZE
+			|| header.body_len as usize > header.body.size as usize * BLOCK_SIZE
+		{
+			return Err(invalid_data("malformed manifest file"));
+		}
+
+		let mut blocks = vec![Block::default(); header.body.size as usize];
+		dataview::bytes_mut(blocks.as_mut_slice()).copy_from_slice(&bytes[HEADER_LEN..]);
+		if !crypt::decrypt_section(&mut blocks, &header.body, key) {
+			return Err(invalid_data("manifest authentication failed"));
+		}
+		let body = &dataview::bytes(blocks.as_slice())[..header.body_len as usize];
+		let view = dataview::DataView::from(body);
+
+		let mut offset = 0;
+		let read_u32 = |offset: &mut usize| -> io::Result<u32> {
+			let value = view.try_read::<[u8; 4]>(*offset).ok_or_else(|| invalid_data("malformed manifest file"))?;
+			*offset += 4;
+			Ok(u32::from_le_bytes(value))
+		};
+
+		let len = read_u32(&mut offset)? as usize;
+		let dir_end = len.checked_mul(mem::size_of::<Descriptor>()).and_then(|dir_len| dir_len.checked_add(offset));
+		let dir_bytes = dir_end.and_then(|dir_end| body.get(offset..dir_end)).ok_or_else(|| invalid_data("malformed manifest file"))?;
+		let mut directory = vec![Descriptor::default(); len];
+		dataview::bytes_mut(directory.as_mut_slice()).copy_from_slice(dir_bytes);
+		offset += dir_bytes.len();
+
+		let len = read_u32(&mut offset)? as usize;
+		let mut sections = Vec::new();
+		for _ in 0..len {
+			let section = view.try_read::<Section>(offset).ok_or_else(|| invalid_data("malformed manifest file"))?;
+			offset += mem::size_of::<Section>();
+			let flags = read_u32(&mut offset)?;
+			let digest = view.try_read::<[u8; 32]>(offset).ok_or_else(|| invalid_data("malformed manifest file"))?;
+			offset += 32;
+			sections.push(SectionDigest { section, plaintext: flags & SECTION_PLAINTEXT != 0, digest: hash::Digest(digest) });
+		}
+		if offset != body.len() {
+			return Err(invalid_data("malformed manifest file"));
+		}
+
+		Ok(Manifest { directory: Directory::from(directory), sections })
+	}
+}
+
+/// Sections to download from the remote PAKS archive.
+#[derive(Clone, Default)]
+pub struct Plan {
+	/// Sections of the remote PAKS archive to download, ordered by offset.
+	pub download: Vec<SectionDigest>,
+	/// Sections of the remote PAKS archive and the local sections with the same contents.
+	pub reuse: Vec<(Section, Section)>,
+}
+
+impl Plan {
+	/// Number of bytes to download.
+	pub fn download_len(&self) -> u64 {
+		self.download.iter().map(|entry| entry.section.size as u64 * BLOCK_SIZE as u64).sum()
+	}
+}
+
+/// Computes the sections to download to update the local PAKS archive to the remote PAKS archive.
+///
+/// Decrypts every section of the local PAKS archive to compute its digest, sections failing authentication are not reused.
+pub fn plan(local: &FileReader, manifest: &Manifest, key: &Key) -> io::Result<Plan> {
+	make_plan(local.as_ref(), |section| local.read_section(section, key), |desc| local.read_data(desc, key), manifest)
+}
+
+/// Updates the local PAKS archive to the remote PAKS archive in place.
+///
+/// Downloads the sections from the remote PAKS archive according to the [`plan`] and replaces the directory with the directory of the manifest.
+/// The downloaded sections are authenticated before they are written, plaintext sections are checked against their digest.
+/// Nothing is committed unless all the sections are downloaded, see [`FileEditor::finish`].
+///
+/// # Errors
+///
+/// * [`io::ErrorKind::InvalidInput`]: The local PAKS archive has a different archive id than the remote PAKS archive, see [`ArchiveId`].
+/// * [`io::ErrorKind::InvalidData`]: The manifest is malformed or a downloaded section failed authentication.
+pub fn sync_file<P: ?Sized + AsRef<Path>>(path: &P, manifest: &Manifest, key: &Key, remote: &mut dyn Remote) -> io::Result<Plan> {
+	let mut edit = FileEditor::open(path, key)?;
+	if edit.archive_id() != manifest.directory.archive_id() {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, "manifest is for a different PAKS archive"));
+	}
+	let plan = make_plan(edit.as_ref(), |section| edit.read_section(section, key), |desc| edit.read_data(desc, key), manifest)?;
+
+	// Maps the remote sections to their location in the local PAKS archive
+	let mut locations: HashMap<(u32, u32), Section> = plan.reuse.iter().map(|(remote, local)| ((remote.offset, remote.size), *local)).collect();
+
+	// Download neighbouring sections with a single read
+	let mut i = 0;
+	while i < plan.download.len() {
+		let start = plan.download[i].section.offset;
+		let mut end = i + 1;
+		while end < plan.download.len() && plan.download[end - 1].section.offset.checked_add(plan.download[end - 1].section.size) == Some(plan.download[end].section.offset) {
+			end += 1;
+		}
+		let run = &plan.download[i..end];
+		i = end;
+
+		let last = &run[run.len() - 1].section;
+		let run_len = last.offset.checked_add(last.size).and_then(|run_end| run_end.checked_sub(start)).ok_or_else(|| invalid_data("malformed manifest file"))?;
+		let mut blocks = vec![Block::default(); run_len as usize];
+		remote.read_at(start as u64 * BLOCK_SIZE as u64, dataview::bytes_mut(blocks.as_mut_slice()))?;
+
+		for entry in run {
+			let begin = (entry.section.offset - start) as usize;
+			let blocks = &blocks[begin..begin + entry.section.size as usize];
+			let valid = if entry.plaintext {
+				hash::sha256(dataview::bytes(blocks)) == entry.digest
+			}
+			else {
+				crypt::verify_section(blocks, &entry.section, key)
+			};
+			if !valid {
+				return Err(invalid_data("downloaded section failed authentication"));
+			}
+			let location = edit.append_encrypted(blocks, &entry.section)?;
+			locations.insert((entry.section.offset, entry.section.size), location);
+		}
+	}
+
+	// Splice the sections into the directory of the remote PAKS archive
+	let mut directory: Vec<Descriptor> = manifest.directory.to_vec();
+	for desc in &mut directory {
+		if !desc.is_file() || desc.is_inline() {
+			continue;
+		}
+		if let Some(base) = desc.delta_base() {
+			let location = locations.get(&base).ok_or_else(|| invalid_data("section missing from the manifest"))?;
+			desc.set_delta_base(location.offset, location.size);
+		}
+		desc.section = *locations.get(&(desc.section.offset, desc.section.size)).ok_or_else(|| invalid_data("section missing from the manifest"))?;
+	}
+	let dir: &mut Directory = &mut edit;
+	*dir = Directory::from(directory);
+
+	let mut log = String::new();
+	if !edit.fsck(edit.high_mark(), &mut log) {
+		return Err(invalid_data(log));
+	}
+	edit.finish(key)?;
+	Ok(plan)
+}
+
+fn make_plan(
+	dir: &[Descriptor],
+	read_section: impl Fn(&Section) -> io::Result<Vec<Block>>,
+	read_data: impl Fn(&Descriptor) -> io::Result<Vec<u8>>,
+	manifest: &Manifest,
+) -> io::Result<Plan> {
+	// Index the local sections by their nonce and MAC and by their digest
+	let mut exact = HashMap::new();
+	let mut digests = HashMap::new();
+	for (section, plaintext) in sections(dir, &read_section)? {
+		if let Ok(digest) = section_digest(&section, plaintext, &read_data) {
+			if !plaintext {
+				exact.insert((section.nonce, section.mac, section.size), section);
+			}
+			digests.insert((plaintext, section.size, digest), section);
+		}
+	}
+
+	// The nonce and MAC of the base of delta files are stored in the delta file
+	let bases: Vec<(u32, u32)> = manifest.directory.iter().filter_map(Descriptor::delta_base).collect();
+
+	let mut plan = Plan::default();
+	for entry in &manifest.sections {
+		let remote = &entry.section;
+		let local = match exact.get(&(remote.nonce, remote.mac, remote.size)) {
+			Some(local) if !entry.plaintext => Some(local),
+			_ if bases.contains(&(remote.offset, remote.size)) => None,
+			_ => digests.get(&(entry.plaintext, remote.size, entry.digest)),
+		};
+		match local {
+			Some(local) => plan.reuse.push((*remote, *local)),
+			None => plan.download.push(*entry),
+		}
+	}
+	plan.download.sort_by_key(|entry| entry.section.offset);
+	Ok(plan)
+}
+
+// Lists the distinct sections of the files and the bases of the delta files
+fn sections(dir: &[Descriptor], read_section: impl Fn(&Section) -> io::Result<Vec<Block>>) -> io::Result<Vec<(Section, bool)>> {
+	let mut sections = HashMap::new();
+	for desc in dir {
+		if !desc.is_file() || desc.is_inline() {
+			continue;
+		}
+		sections.entry((desc.section.offset, desc.section.size)).or_insert((desc.section, desc.is_plaintext()));
+		if desc.is_delta() {
+			let base = delta::base_of(desc, &read_section)?;
+			sections.entry((base.section.offset, base.section.size)).or_insert((base.section, false));
+		}
+	}
+	let mut sections: Vec<_> = sections.into_values().collect();
+	sections.sort_by_key(|(section, _)| section.offset);
+	Ok(sections)
+}
+
+// Computes the digest of the decrypted blocks of the section
+fn section_digest(section: &Section, plaintext: bool, read_data: impl Fn(&Descriptor) -> io::Result<Vec<u8>>) -> io::Result<hash::Digest> {
+	let desc = Descriptor {
+		content_type: 1,
+		content_size: section.size.checked_mul(BLOCK_SIZE as u32).ok_or(io::ErrorKind::InvalidData)?,
+		section: *section,
+		flags: if plaintext { Descriptor::FLAG_PLAINTEXT } else { 0 },
+		..Descriptor::default()
+	};
+	read_data(&desc).map(|data| hash::sha256(&data))
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, error)
+}
@@ -0,0 +1,45 @@
+use std::sync::RwLock;
+use super::*;
+
+/// Part of the PAKS file which failed authentication, see [`set_tamper_hook`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Tampered {
+	/// The header failed authentication although the key is correct.
+	Header,
+	/// The directory failed authentication.
+	Directory(Section),
+	/// The contents of a file failed authentication.
+	///
+	/// Sections have no key check, an incorrect key is indistinguishable from modified contents.
+	Section(Section),
+}
+
+/// Tamper alarm, see [`set_tamper_hook`].
+pub type TamperHook = Box<dyn Fn(&Tampered) + Send + Sync>;
+
+static HOOK: RwLock<Option<TamperHook>> = RwLock::new(None);
+
+/// Registers a tamper alarm, replacing any previously registered one.
+///
+/// The hook is called whenever a MAC verification fails while opening a PAKS file or reading, patching or re-encrypting its contents, before the error is returned.
+/// This allows anti-tamper systems to log or react to modified PAKS files instead of only observing an [`ErrorKind::InvalidData`] error.
+///
+/// The hook is global and may be called from multiple threads.
+/// Scanning for previous directories with [`FileReader::open_salvage`], [`FileReader::generations`] or [`FileReader::open_generation`] expects authentication failures and does not call the hook.
+pub fn set_tamper_hook(hook: TamperHook) {
+	*HOOK.write().unwrap_or_else(|err| err.into_inner()) = Some(hook);
+}
+
+/// Unregisters the tamper alarm, returning it.
+pub fn take_tamper_hook() -> Option<TamperHook> {
+	HOOK.write().unwrap_or_else(|err| err.into_inner()).take()
+}
+
+// Sounds the tamper alarm
+#[cold]
+#[inline(never)]
+pub(crate) fn tampered(what: Tampered) {
+	if let Some(hook) = &*HOOK.read().unwrap_or_else(|err| err.into_inner()) {
+		hook(&what);
+	}
+}
@@ -0,0 +1,155 @@
+/*!
+Test fixtures
+=============
+
+Builds PAKS files in memory and checks their contents in one call, for unit testing code which loads assets from PAKS files.
+
+```
+let ref key = [1, 2];
+let reader = paks::testing::reader([
+    ("config.txt", "fullscreen=1"),
+    ("textures/wall.png", "PNG"),
+    ("textures/floor.png", "PNG"),
+    ("saves/", ""),
+], key);
+
+paks::testing::assert_tree(&reader, "\
+    config.txt\tfile\t12
+    saves\tdir\t0
+    textures\tdir\t0
+    textures/floor.png\tfile\t3
+    textures/wall.png\tfile\t3
+");
+paks::testing::assert_files(&reader, [
+    ("config.txt", "fullscreen=1"),
+    ("textures/wall.png", "PNG"),
+    ("textures/floor.png", "PNG"),
+], key);
+```
+
+Paths ending with a `/` create empty directories, their contents are ignored.
+
+The helpers panic with a descriptive message when something is wrong, they are not meant to be used outside of tests.
+*/
+
+use super::*;
+
+/// Creates a PAKS file with the files and returns its blocks.
+///
+/// The files are created in order, a later file at the same path replaces the earlier one.
+///
+/// # Panics
+///
+/// Panics if a path is invalid.
+pub fn build<I, P, D>(files: I, key: &Key) -> Vec<Block>
+	where I: IntoIterator<Item = (P, D)>, P: AsRef<[u8]>, D: AsRef<[u8]>
+{
+	let mut edit = MemoryEditor::new();
+	for (path, data) in files {
+		let path = path.as_ref();
+		let result = match path.strip_suffix(b"/") {
+			Some(dir_path) => edit.create_dir(dir_path).map_err(Error::from),
+			None => edit.create_file(path, data.as_ref(), key).map(|_| ()),
+		};
+		if let Err(err) = result {
+			panic!("failed to create {:?}: {}", String::from_utf8_lossy(path), err);
+		}
+	}
+	match edit.finish(key) {
+		Ok((blocks, _)) => blocks,
+		Err(err) => panic!("failed to finish the PAKS file: {}", err),
+	}
+}
+
+/// Creates a PAKS file with the files and opens it for reading.
+///
+/// See [`build`].
+pub fn reader<I, P, D>(files: I, key: &Key) -> MemoryReader
+	where I: IntoIterator<Item = (P, D)>, P: AsRef<[u8]>, D: AsRef<[u8]>
+{
+	match MemoryReader::from_blocks(build(files, key), key) {
+		Ok(reader) => reader,
+		Err(_) => panic!("failed to open the PAKS file"),
+	}
+}
+
+/// Asserts the directory matches the expected tree.
+///
+/// The tree is the flat listing of [`Directory::display_flat`], one `path<TAB>type<TAB>size` line per entry sorted by path.
+/// Leading whitespace on every line of the expected tree is ignored to allow indenting it.
+#[track_caller]
+pub fn assert_tree(dir: &Directory, expected: &str) {
+	let tree = dir.display_flat().to_string();
+	let expected: String = expected.lines().map(|line| format!("{}\n", line.trim_start())).collect();
+	assert!(tree == expected, "directory tree mismatch\n--- actual\n{}--- expected\n{}", tree, expected);
+}
+
+/// Asserts the PAKS file contains exactly the files with the expected contents.
+///
+/// Directories are not compared, use [`assert_tree`] to check empty directories.
+#[track_caller]
+pub fn assert_files<I, P, D>(reader: &MemoryReader, files: I, key: &Key)
+	where I: IntoIterator<Item = (P, D)>, P: AsRef<[u8]>, D: AsRef<[u8]>
+{
+	let mut expected: Vec<(Vec<u8>, Vec<u8>)> = files.into_iter()
+		.map(|(path, data)| (path.as_ref().to_vec(), data.as_ref().to_vec()))
+		.collect();
+	expected.sort();
+
+	let mut paths: Vec<Vec<u8>> = dir::entries(reader.as_ref()).into_iter()
+		.filter(|(_, desc)| desc.is_file())
+		.map(|(path, _)| path)
+		.collect();
+	paths.sort();
+
+	let expected_paths: Vec<&[u8]> = expected.iter().map(|(path, _)| path.as_slice()).collect();
+	assert!(paths == expected_paths, "file paths mismatch\n--- actual\n{}\n--- expected\n{}", lines(&paths), lines(&expected_paths));
+
+	for (path, data) in &expected {
+		match reader.read(path, key) {
+			Ok(content) => assert!(&content == data, "contents mismatch of {:?}", String::from_utf8_lossy(path)),
+			Err(err) => panic!("failed to read {:?}: {}", String::from_utf8_lossy(path), err),
+		}
+	}
+}
+
+fn lines<P: AsRef<[u8]>>(paths: &[P]) -> String {
+	let paths: Vec<_> = paths.iter().map(|path| String::from_utf8_lossy(path.as_ref())).collect();
+	paths.join("\n")
+}
+
+#[test]
+fn test_fixtures() {
+	let ref key = [3, 4];
+	let reader = reader([
+		("a.txt", "old"),
+		("b/c.txt", "c"),
+		("a.txt", "new"),
+		("d/", "ignored"),
+	], key);
+
+	assert_tree(&reader, "\
+		a.txt\tfile\t3
+		b\tdir\t0
+		b/c.txt\tfile\t1
+		d\tdir\t0
+");
+	assert_files(&reader, [("b/c.txt", "c"), ("a.txt", "new")], key);
+
+}
+
+#[test]
+#[should_panic(expected = "contents mismatch")]
+fn test_fixtures_contents_mismatch() {
+	let ref key = [3, 4];
+	let reader = reader([("a.txt", "new")], key);
+	assert_files(&reader, [("a.txt", "old")], key);
+}
+
+#[test]
+#[should_panic(expected = "file paths mismatch")]
+fn test_fixtures_paths_mismatch() {
+	let ref key = [3, 4];
+	let reader = reader([("a.txt", "new"), ("b.txt", "b")], key);
+	assert_files(&reader, [("a.txt", "new")], key);
+}
@@ -59,13 +59,17 @@ pub fn key_free(key_ptr: *mut paks::Key) {
 pub fn paks_open(data_ptr: *const u8, data_len: usize, key: *const paks::Key) -> *mut paks::MemoryEditor {
 	let data = unsafe { slice::from_raw_parts(data_ptr, data_len) };
 	let key = unsafe { &*key };
-	match paks::MemoryEditor::from_bytes(data, key) {
+	match paks::MemoryEditor::from_bytes_diagnose(data, key) {
 		Ok(paks) => {
 			let paks = Box::new(paks);
 			Box::into_raw(paks)
 		},
-		Err(err) => {
-			let err = serde_json::json!({ "error": err.to_string() }).to_string();
+		Err(report) => {
+			let msg = match report.kind() {
+				std::io::ErrorKind::InvalidData => format!("corrupted PAKS file: {}", report),
+				_ => report.to_string(),
+			};
+			let err = serde_json::json!({ "error": msg }).to_string();
 			unsafe { result_error(err.as_ptr(), err.to_string().len()) };
 			std::ptr::null_mut()
 		},